@@ -0,0 +1,76 @@
+//! Benchmarks for transforming a record-oriented document with several threads.
+//!
+//! Run with `cargo bench --bench bench_parallel`. The throughput of each thread count shows how the transformation scales.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use xrust::batch::{transform_all, BatchOptions};
+use xrust::xslt::transform_parallel;
+use xrust::Error;
+
+const STYLE: &str = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::record'><r><xsl:apply-templates/></r></xsl:template>
+  <xsl:template match='child::field'><f><xsl:sequence select='upper-case(string(.))'/></f></xsl:template>
+</xsl:stylesheet>";
+
+// A document with the given number of records, of about 170 bytes each
+fn records(n: usize) -> String {
+    let mut s = String::from("<records>");
+    for i in 0..n {
+        s.push_str(format!("<record id='{}'>", i).as_str());
+        for j in 0..4 {
+            s.push_str(format!("<field>value {} of record {}</field>", j, i).as_str())
+        }
+        s.push_str("</record>")
+    }
+    s.push_str("</records>");
+    s
+}
+
+fn parallel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transform_parallel");
+    group.sample_size(10);
+    for n in [10_000, 40_000] {
+        let src = records(n);
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        for threads in [1, 2, 4, 8] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}KB", src.len() / 1024), threads),
+                &threads,
+                |b, &t| {
+                    b.iter(|| transform_parallel(STYLE, src.as_str(), t).expect("transform failed"))
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transform_all");
+    group.sample_size(10);
+    let docs: Vec<String> = (0..200).map(|_| records(100)).collect();
+    group.throughput(Throughput::Bytes(docs.iter().map(|d| d.len() as u64).sum()));
+    for threads in [1, 2, 4, 8] {
+        let options = BatchOptions {
+            threads,
+            ..BatchOptions::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &options, |b, o| {
+            b.iter(|| {
+                transform_all(
+                    docs.iter(),
+                    STYLE,
+                    o,
+                    &mut |_: usize, r: Result<String, Error>| {
+                        r.expect("transform failed");
+                    },
+                )
+                .expect("unable to compile stylesheet")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel, batch);
+criterion_main!(benches);
@@ -0,0 +1,633 @@
+/*! ## The syntax tree of compiled expressions
+
+An XPath expression, or the body of an XSLT template, is compiled to a vector of [Constructor]s. This module, which is enabled by the "ast" feature, helps external tools to analyse, lint, or rewrite those trees.
+
+A [Constructor] is displayed in XPath syntax. XSLT instructions that have no XPath equivalent are displayed as function-like calls, such as `apply-templates(child::node())`. [Expr] displays a sequence of constructors.
+
+A [Visitor] is called for every constructor in a tree, before and after its operands are visited. [walk] visits a tree, and [walk_mut] visits a tree with a [VisitorMut] that may change it.
+
+```rust
+use xrust::ast::{walk, Expr, Visitor};
+use xrust::evaluate::Constructor;
+use xrust::xpath::parse;
+
+// Collect the names of the variables that are referenced
+#[derive(Default)]
+struct Variables(Vec<String>);
+impl Visitor for Variables {
+    fn enter(&mut self, c: &Constructor) -> bool {
+        if let Constructor::VariableReference(v) = c {
+            self.0.push(v.clone())
+        }
+        true
+    }
+}
+
+let e = parse("for $x in 1 to 3 return $x * $y").expect("unable to parse expression");
+assert_eq!(Expr(&e).to_string(), "for $x in 1 to 3 return $x * $y");
+let mut v = Variables::default();
+walk(&e, &mut v);
+assert_eq!(v.0, vec!["x", "y"]);
+```
+*/
+
+use crate::evaluate::{ArithmeticOperator, Constructor, Grouping, Quantifier};
+use crate::value::{Operator, Value};
+use std::fmt;
+
+/// Called for each [Constructor] in a tree, in depth-first order.
+pub trait Visitor {
+    /// Called before the operands of the constructor are visited. If this returns false then the operands are not visited.
+    fn enter(&mut self, _c: &Constructor) -> bool {
+        true
+    }
+    /// Called after the operands of the constructor have been visited, or skipped.
+    fn leave(&mut self, _c: &Constructor) {}
+}
+
+/// Called for each [Constructor] in a tree, in depth-first order. The constructors may be changed.
+pub trait VisitorMut {
+    /// Called before the operands of the constructor are visited. If this returns false then the operands are not visited. If the constructor is replaced, then the operands of the replacement are visited.
+    fn enter(&mut self, _c: &mut Constructor) -> bool {
+        true
+    }
+    /// Called after the operands of the constructor have been visited, or skipped.
+    fn leave(&mut self, _c: &mut Constructor) {}
+}
+
+/// Visit each constructor in the sequence, and their operands.
+pub fn walk<V: Visitor + ?Sized>(s: &[Constructor], v: &mut V) {
+    for c in s {
+        if v.enter(c) {
+            for o in operands(c) {
+                walk(o, v)
+            }
+        }
+        v.leave(c)
+    }
+}
+
+/// Visit each constructor in the sequence, and their operands, allowing them to be changed.
+pub fn walk_mut<V: VisitorMut + ?Sized>(s: &mut [Constructor], v: &mut V) {
+    for c in s {
+        if v.enter(c) {
+            for o in operands_mut(c) {
+                walk_mut(o, v)
+            }
+        }
+        v.leave(c)
+    }
+}
+
+/// The operands of a constructor, i.e. the sequences of constructors that it contains, in the order in which they appear in the expression.
+pub fn operands(c: &Constructor) -> Vec<&Vec<Constructor>> {
+    match c {
+        Constructor::LiteralElement(_, v)
+        | Constructor::LiteralAttribute(_, v)
+        | Constructor::DeepCopy(v)
+        | Constructor::VariableDeclaration(_, v)
+        | Constructor::ApplyTemplates(v)
+        | Constructor::NextIteration(v)
+        | Constructor::Break(v)
+        | Constructor::SetAttribute(_, v)
+        | Constructor::InstanceOf(v, _)
+        | Constructor::Treat(v, _)
+        | Constructor::Convert(v, _)
+        | Constructor::Cast(v, _, _)
+        | Constructor::Castable(v, _, _) => vec![v],
+        Constructor::Copy(s, v)
+        | Constructor::Loop(s, v)
+        | Constructor::Let(s, v)
+        | Constructor::Quantified(_, s, v) => vec![s, v],
+        Constructor::Iterate(s, p, b, c) => vec![s, p, b, c],
+        Constructor::Merge(m, a) => {
+            let mut r = vec![];
+            for s in m {
+                r.push(&s.select);
+                r.extend(s.keys.iter().map(|k| &k.select))
+            }
+            r.push(a);
+            r
+        }
+        Constructor::Or(v)
+        | Constructor::And(v)
+        | Constructor::Path(v)
+        | Constructor::Step(_, v)
+        | Constructor::GeneralComparison(_, v)
+        | Constructor::ValueComparison(_, v)
+        | Constructor::Concat(v)
+        | Constructor::ValueTemplate(v)
+        | Constructor::Range(v)
+        | Constructor::SetOperation(_, v)
+        | Constructor::SimpleMap(v)
+        | Constructor::FunctionCall(_, v) => v.iter().collect(),
+        Constructor::Arithmetic(v) => v.iter().map(|a| &a.operand).collect(),
+        Constructor::Switch(v, o) => v.iter().chain(std::iter::once(o)).collect(),
+        Constructor::ForEach(s, t, g) => {
+            let mut r = vec![s];
+            if let Some(h) = g {
+                r.push(grouping(h))
+            }
+            r.push(t);
+            r
+        }
+        Constructor::Literal(_)
+        | Constructor::UnescapedText(_)
+        | Constructor::ContextItem
+        | Constructor::Root
+        | Constructor::VariableReference(_)
+        | Constructor::ApplyImports
+        | Constructor::Empty
+        | Constructor::NotImplemented(_) => vec![],
+    }
+}
+
+/// The operands of a constructor, as for [operands], that may be changed.
+pub fn operands_mut(c: &mut Constructor) -> Vec<&mut Vec<Constructor>> {
+    match c {
+        Constructor::LiteralElement(_, v)
+        | Constructor::LiteralAttribute(_, v)
+        | Constructor::DeepCopy(v)
+        | Constructor::VariableDeclaration(_, v)
+        | Constructor::ApplyTemplates(v)
+        | Constructor::NextIteration(v)
+        | Constructor::Break(v)
+        | Constructor::SetAttribute(_, v)
+        | Constructor::InstanceOf(v, _)
+        | Constructor::Treat(v, _)
+        | Constructor::Convert(v, _)
+        | Constructor::Cast(v, _, _)
+        | Constructor::Castable(v, _, _) => vec![v],
+        Constructor::Copy(s, v)
+        | Constructor::Loop(s, v)
+        | Constructor::Let(s, v)
+        | Constructor::Quantified(_, s, v) => vec![s, v],
+        Constructor::Iterate(s, p, b, c) => vec![s, p, b, c],
+        Constructor::Merge(m, a) => {
+            let mut r = vec![];
+            for s in m {
+                r.push(&mut s.select);
+                r.extend(s.keys.iter_mut().map(|k| &mut k.select))
+            }
+            r.push(a);
+            r
+        }
+        Constructor::Or(v)
+        | Constructor::And(v)
+        | Constructor::Path(v)
+        | Constructor::Step(_, v)
+        | Constructor::GeneralComparison(_, v)
+        | Constructor::ValueComparison(_, v)
+        | Constructor::Concat(v)
+        | Constructor::ValueTemplate(v)
+        | Constructor::Range(v)
+        | Constructor::SetOperation(_, v)
+        | Constructor::SimpleMap(v)
+        | Constructor::FunctionCall(_, v) => v.iter_mut().collect(),
+        Constructor::Arithmetic(v) => v.iter_mut().map(|a| &mut a.operand).collect(),
+        Constructor::Switch(v, o) => v.iter_mut().chain(std::iter::once(o)).collect(),
+        Constructor::ForEach(s, t, g) => {
+            let mut r = vec![s];
+            if let Some(h) = g {
+                r.push(match h {
+                    Grouping::By(v)
+                    | Grouping::StartingWith(v)
+                    | Grouping::EndingWith(v)
+                    | Grouping::Adjacent(v) => v,
+                })
+            }
+            r.push(t);
+            r
+        }
+        Constructor::Literal(_)
+        | Constructor::UnescapedText(_)
+        | Constructor::ContextItem
+        | Constructor::Root
+        | Constructor::VariableReference(_)
+        | Constructor::ApplyImports
+        | Constructor::Empty
+        | Constructor::NotImplemented(_) => vec![],
+    }
+}
+
+fn grouping(g: &Grouping) -> &Vec<Constructor> {
+    match g {
+        Grouping::By(v)
+        | Grouping::StartingWith(v)
+        | Grouping::EndingWith(v)
+        | Grouping::Adjacent(v) => v,
+    }
+}
+
+/// Displays a sequence of constructors, such as a compiled expression, in XPath syntax.
+pub struct Expr<'a>(pub &'a [Constructor]);
+
+impl<'a> fmt::Display for Expr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut comma = false;
+        for c in self.0 {
+            if comma {
+                f.write_str(", ")?
+            }
+            match c {
+                // A variable declaration is in scope for the rest of the sequence
+                Constructor::VariableDeclaration(v, e) => {
+                    write!(f, "let ${} := {} return ", v, Single(e))?;
+                    comma = false
+                }
+                _ => {
+                    write!(f, "{}", c)?;
+                    comma = true
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// An operand of an operator. It is parenthesised unless it is a single, simple constructor.
+struct Operand<'a>(&'a [Constructor]);
+
+impl<'a> fmt::Display for Operand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            [c] if is_simple(c) => write!(f, "{}", c),
+            _ => write!(f, "({})", Expr(self.0)),
+        }
+    }
+}
+
+// A single expression, such as the body of a loop. It is parenthesised only if it is a sequence.
+struct Single<'a>(&'a [Constructor]);
+
+impl<'a> fmt::Display for Single<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            [c] => write!(f, "{}", c),
+            _ => write!(f, "({})", Expr(self.0)),
+        }
+    }
+}
+
+fn is_simple(c: &Constructor) -> bool {
+    matches!(
+        c,
+        Constructor::Literal(_)
+            | Constructor::Empty
+            | Constructor::ContextItem
+            | Constructor::Root
+            | Constructor::Path(_)
+            | Constructor::Step(_, _)
+            | Constructor::FunctionCall(_, _)
+            | Constructor::VariableReference(_)
+            | Constructor::LiteralElement(_, _)
+            | Constructor::LiteralAttribute(_, _)
+            | Constructor::UnescapedText(_)
+            | Constructor::Copy(_, _)
+            | Constructor::DeepCopy(_)
+            | Constructor::ApplyTemplates(_)
+            | Constructor::ApplyImports
+            | Constructor::ForEach(_, _, _)
+            | Constructor::Iterate(_, _, _, _)
+            | Constructor::Merge(_, _)
+            | Constructor::NextIteration(_)
+            | Constructor::Break(_)
+            | Constructor::SetAttribute(_, _)
+            | Constructor::NotImplemented(_)
+    )
+}
+
+// Write operands separated by an operator
+fn infix(f: &mut fmt::Formatter<'_>, op: &str, v: &[Vec<Constructor>]) -> fmt::Result {
+    for (i, o) in v.iter().enumerate() {
+        if i > 0 {
+            write!(f, " {} ", op)?
+        }
+        write!(f, "{}", Operand(o))?
+    }
+    Ok(())
+}
+
+// Write the variable bindings of a for, let or quantified expression
+fn bindings(f: &mut fmt::Formatter<'_>, d: &[Constructor], op: &str) -> fmt::Result {
+    for (i, c) in d.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?
+        }
+        match c {
+            Constructor::VariableDeclaration(v, e) => write!(f, "${} {} {}", v, op, Single(e))?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+// Write parameters and their values, as in xsl:iterate and xsl:next-iteration
+fn params(f: &mut fmt::Formatter<'_>, d: &[Constructor]) -> fmt::Result {
+    f.write_str("(")?;
+    for (i, c) in d.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?
+        }
+        match c {
+            Constructor::VariableDeclaration(v, e) => write!(f, "${} := {}", v, Single(e))?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str(")")
+}
+
+impl fmt::Display for Constructor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constructor::Literal(v) => match v {
+                Value::String(s) => write!(f, "\"{}\"", s.replace('"', "\"\"")),
+                _ => write!(f, "{}", v),
+            },
+            Constructor::LiteralElement(qn, c) => {
+                write!(f, "element {} {{{}}}", qn.to_string(), Expr(c))
+            }
+            Constructor::LiteralAttribute(qn, c) => {
+                write!(f, "attribute {} {{{}}}", qn.to_string(), Expr(c))
+            }
+            Constructor::UnescapedText(v) => {
+                write!(f, "text {{\"{}\"}}", v.to_string().replace('"', "\"\""))
+            }
+            Constructor::Copy(s, c) => write!(f, "copy({}) {{{}}}", Expr(s), Expr(c)),
+            Constructor::InstanceOf(s, t) => write!(f, "{} instance of {}", Operand(s), t),
+            Constructor::Treat(s, t) => write!(f, "{} treat as {}", Operand(s), t),
+            Constructor::Convert(s, t) => write!(f, "convert({}) as {}", Expr(s), t),
+            Constructor::Cast(s, t, e) => {
+                write!(
+                    f,
+                    "{} cast as {}{}",
+                    Operand(s),
+                    t,
+                    if *e { "?" } else { "" }
+                )
+            }
+            Constructor::Castable(s, t, e) => {
+                write!(
+                    f,
+                    "{} castable as {}{}",
+                    Operand(s),
+                    t,
+                    if *e { "?" } else { "" }
+                )
+            }
+            Constructor::DeepCopy(s) => write!(f, "copy-of({})", Expr(s)),
+            Constructor::ContextItem => f.write_str("."),
+            Constructor::Or(v) => infix(f, "or", v),
+            Constructor::And(v) => infix(f, "and", v),
+            Constructor::Root => f.write_str("/"),
+            Constructor::Path(v) => {
+                for (i, s) in v.iter().enumerate() {
+                    match (i, s.as_slice()) {
+                        // The root is the start of an absolute path
+                        (0, [Constructor::Root]) if v.len() > 1 => {}
+                        (0, _) => write!(f, "{}", Operand(s))?,
+                        _ => write!(f, "/{}", Operand(s))?,
+                    }
+                }
+                Ok(())
+            }
+            Constructor::Step(nm, p) => {
+                write!(f, "{}::{}", nm.axis.to_string(), nm.nodetest.to_xpath())?;
+                for q in p {
+                    write!(f, "[{}]", Expr(q))?
+                }
+                Ok(())
+            }
+            Constructor::GeneralComparison(o, v) => infix(f, o.to_string(), v),
+            Constructor::ValueComparison(o, v) => infix(f, value_operator(o), v),
+            Constructor::Concat(v) => infix(f, "||", v),
+            // Written as a string constructor
+            Constructor::ValueTemplate(v) => {
+                f.write_str("``[")?;
+                for o in v {
+                    match o.as_slice() {
+                        [Constructor::Literal(Value::String(s))] => f.write_str(s)?,
+                        _ => write!(f, "`{{{}}}`", Expr(o))?,
+                    }
+                }
+                f.write_str("]``")
+            }
+            Constructor::Range(v) => infix(f, "to", v),
+            Constructor::SetOperation(o, v) => infix(f, o.to_string().as_str(), v),
+            Constructor::SimpleMap(v) => infix(f, "!", v),
+            Constructor::Arithmetic(v) => {
+                for a in v {
+                    match a.op {
+                        ArithmeticOperator::Noop => {}
+                        ArithmeticOperator::Add => f.write_str(" + ")?,
+                        ArithmeticOperator::Subtract => f.write_str(" - ")?,
+                        ArithmeticOperator::Multiply => f.write_str(" * ")?,
+                        ArithmeticOperator::Divide => f.write_str(" div ")?,
+                        ArithmeticOperator::IntegerDivide => f.write_str(" idiv ")?,
+                        ArithmeticOperator::Modulo => f.write_str(" mod ")?,
+                    }
+                    write!(f, "{}", Operand(&a.operand))?
+                }
+                Ok(())
+            }
+            Constructor::FunctionCall(func, a) => {
+                if let Some(p) = func.get_prefix() {
+                    write!(f, "{}:", p)?
+                }
+                write!(f, "{}(", func.get_name())?;
+                for (i, b) in a.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?
+                    }
+                    write!(f, "{}", Expr(b))?
+                }
+                f.write_str(")")
+            }
+            Constructor::VariableDeclaration(v, e) => write!(f, "let ${} := {}", v, Operand(e)),
+            Constructor::VariableReference(v) => write!(f, "${}", v),
+            Constructor::Loop(d, b) => {
+                f.write_str("for ")?;
+                bindings(f, d, "in")?;
+                write!(f, " return {}", Single(b))
+            }
+            Constructor::Let(d, b) => {
+                f.write_str("let ")?;
+                bindings(f, d, ":=")?;
+                write!(f, " return {}", Single(b))
+            }
+            Constructor::Quantified(q, d, t) => {
+                f.write_str(match q {
+                    Quantifier::Some => "some ",
+                    Quantifier::Every => "every ",
+                })?;
+                bindings(f, d, "in")?;
+                write!(f, " satisfies {}", Single(t))
+            }
+            Constructor::Switch(v, o) => {
+                for c in v.chunks(2) {
+                    write!(f, "if ({}) then ", Expr(&c[0]))?;
+                    if let Some(b) = c.get(1) {
+                        write!(f, "{} else ", Single(b))?
+                    }
+                }
+                write!(f, "{}", Single(o))
+            }
+            Constructor::ApplyTemplates(s) => write!(f, "apply-templates({})", Expr(s)),
+            Constructor::ApplyImports => f.write_str("apply-imports()"),
+            Constructor::Empty => f.write_str("()"),
+            Constructor::ForEach(s, t, g) => {
+                write!(f, "for-each({})", Expr(s))?;
+                match g {
+                    Some(Grouping::By(v)) => write!(f, " group-by({})", Expr(v))?,
+                    Some(Grouping::StartingWith(v)) => {
+                        write!(f, " group-starting-with({})", Expr(v))?
+                    }
+                    Some(Grouping::EndingWith(v)) => write!(f, " group-ending-with({})", Expr(v))?,
+                    Some(Grouping::Adjacent(v)) => write!(f, " group-adjacent({})", Expr(v))?,
+                    None => {}
+                }
+                write!(f, " {{{}}}", Expr(t))
+            }
+            Constructor::Iterate(s, p, b, c) => {
+                write!(f, "iterate({}) ", Expr(s))?;
+                params(f, p)?;
+                write!(f, " {{{}}}", Expr(b))?;
+                if !c.is_empty() {
+                    write!(f, " on-completion {{{}}}", Expr(c))?
+                }
+                Ok(())
+            }
+            Constructor::Merge(m, a) => {
+                f.write_str("merge(")?;
+                for (i, s) in m.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?
+                    }
+                    if let Some(n) = &s.name {
+                        write!(f, "{} ", n)?
+                    }
+                    write!(f, "({}) by (", Expr(&s.select))?;
+                    for (j, k) in s.keys.iter().enumerate() {
+                        if j > 0 {
+                            f.write_str(", ")?
+                        }
+                        write!(f, "{}", Single(&k.select))?;
+                        if k.descending {
+                            f.write_str(" descending")?
+                        }
+                    }
+                    f.write_str(")")?
+                }
+                write!(f, ") {{{}}}", Expr(a))
+            }
+            Constructor::NextIteration(p) => {
+                f.write_str("next-iteration")?;
+                params(f, p)
+            }
+            Constructor::Break(b) => write!(f, "break({})", Expr(b)),
+            Constructor::SetAttribute(qn, v) => {
+                write!(f, "set-attribute({}, {})", qn.to_string(), Expr(v))
+            }
+            Constructor::NotImplemented(m) => write!(f, "not-implemented(\"{}\")", m),
+        }
+    }
+}
+
+fn value_operator(o: &Operator) -> &'static str {
+    match o {
+        Operator::Equal => "eq",
+        Operator::NotEqual => "ne",
+        Operator::LessThan => "lt",
+        Operator::LessThanEqual => "le",
+        Operator::GreaterThan => "gt",
+        Operator::GreaterThanEqual => "ge",
+        Operator::Is => "is",
+        Operator::Before => "<<",
+        Operator::After => ">>",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xpath::parse;
+
+    fn display(e: &str) -> String {
+        Expr(&parse(e).expect("unable to parse expression")).to_string()
+    }
+
+    #[test]
+    fn display_xpath() {
+        for e in [
+            "/",
+            "/child::a/descendant::b[attribute::id = \"x\"][2]",
+            "child::p:a/attribute::*:b/child::text()",
+            "((1 + 2) * 3) - (4 idiv 5)",
+            "1 to 10",
+            "child::a union child::b union (child::c except child::d)",
+            "(child::a intersect child::b) except child::c",
+            "child::a ! string(.) ! upper-case(.)",
+            "(($x eq 1) and ($y ne 2)) or not($z)",
+            "\"a\" || \"b\"\"c\"",
+            "for $x in (1, 2), $y in child::a return $x + $y",
+            "let $x := 1 return $x",
+            "let $x := 1, $y := $x + 1 return ($x, $y)",
+            "some $x in (1, 2) satisfies $x > 1",
+            "every $x in child::a, $y in $x/child::b satisfies $y = 1",
+            "if (true()) then 1 else 2",
+            "count(child::node()) > 1",
+            "child::processing-instruction(go)",
+            "ancestor-or-self::node()/self::element()",
+        ] {
+            let d = display(e);
+            assert_eq!(d, e);
+            // The output can be parsed again
+            assert_eq!(display(d.as_str()), d)
+        }
+    }
+
+    #[test]
+    fn visitors() {
+        // Count the steps, but not those inside predicates
+        #[derive(Default)]
+        struct Steps(usize);
+        impl Visitor for Steps {
+            fn enter(&mut self, c: &Constructor) -> bool {
+                match c {
+                    Constructor::Step(_, _) => {
+                        self.0 += 1;
+                        false
+                    }
+                    _ => true,
+                }
+            }
+        }
+        let e = parse("child::a[child::b]/child::c").expect("unable to parse expression");
+        let mut s = Steps::default();
+        walk(&e, &mut s);
+        assert_eq!(s.0, 2);
+
+        // Rename a variable
+        struct Rename;
+        impl VisitorMut for Rename {
+            fn enter(&mut self, c: &mut Constructor) -> bool {
+                match c {
+                    Constructor::VariableReference(v) | Constructor::VariableDeclaration(v, _)
+                        if v == "x" =>
+                    {
+                        *v = String::from("item")
+                    }
+                    _ => {}
+                }
+                true
+            }
+        }
+        let mut e =
+            parse("for $x in child::a return $x/child::b").expect("unable to parse expression");
+        walk_mut(&mut e, &mut Rename);
+        assert_eq!(
+            Expr(&e).to_string(),
+            "for $item in child::a return $item/child::b"
+        )
+    }
+}
@@ -0,0 +1,335 @@
+/*! ## Navigating the XPath axes
+
+An [AxisIter] visits the nodes on one of the thirteen [XPath axes](https://www.w3.org/TR/xpath-31/#axes), starting from a [Node].
+
+Nodes are visited in the order of the axis: document order for forward axes, and reverse document order for the reverse axes (ancestor, ancestor-or-self, preceding and preceding-sibling). Unlike [Node::ancestor_iter](../forest/struct.Node.html#method.ancestor_iter), the ancestor axes include the Document-type node. The attribute axis visits attributes in an undefined order.
+
+A [Tree](../forest/struct.Tree.html) does not have namespace nodes, so the namespace axis is always empty.
+
+```rust
+use xrust::axes::{Axis, AxisIter};
+use xrust::forest::Forest;
+
+let mut f = Forest::new();
+let t = f.grow_tree("<a><b/><c><d/></c><e/></a>")
+    .expect("unable to parse XML");
+let a = f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap();
+let d = AxisIter::new(&f, a, Axis::Descendant).collect(&f)[2];
+
+let mut it = AxisIter::new(&f, d, Axis::Preceding);
+assert_eq!(it.next(&f).unwrap().to_name(&f).get_localname(), "b");
+assert!(it.next(&f).is_none());
+let names: Vec<String> = AxisIter::new(&f, d, Axis::Following)
+    .collect(&f)
+    .iter()
+    .map(|n| n.to_name(&f).get_localname())
+    .collect();
+assert_eq!(names, vec!["e"]);
+```
+*/
+
+use crate::forest::{Children, Descendants, Forest, Node, NodeType, ReverseDescendants, Siblings};
+use crate::xdmerror::*;
+use std::convert::TryFrom;
+
+/// The XPath axes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    SelfAxis,
+}
+
+impl Axis {
+    /// Whether the axis visits nodes in reverse document order.
+    pub fn is_reverse(&self) -> bool {
+        matches!(
+            self,
+            Axis::Ancestor | Axis::AncestorOrSelf | Axis::Preceding | Axis::PrecedingSibling
+        )
+    }
+    /// The name of the axis, as used in XPath.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Axis::Ancestor => "ancestor",
+            Axis::AncestorOrSelf => "ancestor-or-self",
+            Axis::Attribute => "attribute",
+            Axis::Child => "child",
+            Axis::Descendant => "descendant",
+            Axis::DescendantOrSelf => "descendant-or-self",
+            Axis::Following => "following",
+            Axis::FollowingSibling => "following-sibling",
+            Axis::Namespace => "namespace",
+            Axis::Parent => "parent",
+            Axis::Preceding => "preceding",
+            Axis::PrecedingSibling => "preceding-sibling",
+            Axis::SelfAxis => "self",
+        }
+    }
+}
+
+impl TryFrom<&str> for Axis {
+    type Error = Error;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "ancestor" => Ok(Axis::Ancestor),
+            "ancestor-or-self" => Ok(Axis::AncestorOrSelf),
+            "attribute" => Ok(Axis::Attribute),
+            "child" => Ok(Axis::Child),
+            "descendant" => Ok(Axis::Descendant),
+            "descendant-or-self" => Ok(Axis::DescendantOrSelf),
+            "following" => Ok(Axis::Following),
+            "following-sibling" => Ok(Axis::FollowingSibling),
+            "namespace" => Ok(Axis::Namespace),
+            "parent" => Ok(Axis::Parent),
+            "preceding" => Ok(Axis::Preceding),
+            "preceding-sibling" => Ok(Axis::PrecedingSibling),
+            "self" => Ok(Axis::SelfAxis),
+            _ => Result::Err(Error::new(
+                ErrorKind::StaticSyntax,
+                format!("\"{}\" is not an axis", s),
+            )),
+        }
+    }
+}
+
+enum State {
+    Done,
+    // Yields the node, then stops
+    Once(Node),
+    // Yields the node, then its ancestors
+    Up(Node),
+    Children(Children),
+    Siblings(Siblings),
+    // Optionally yields a node before its descendants
+    Descendants(Option<Node>, Descendants),
+    // The attributes are collected, since the forest cannot be borrowed between calls
+    Attributes(std::vec::IntoIter<Node>),
+    // The last node whose subtree is being visited, and its remaining descendants
+    Following(Node, Option<Descendants>),
+    // The root of the subtree that is being visited, and its remaining descendants.
+    // The root itself is visited after its descendants.
+    Preceding(Node, Option<ReverseDescendants>),
+}
+
+/// Navigate the nodes on an [Axis].
+pub struct AxisIter {
+    state: State,
+}
+
+impl AxisIter {
+    /// Create an iterator over the nodes on the axis, starting from the given node.
+    pub fn new(f: &Forest, n: Node, axis: Axis) -> AxisIter {
+        let attr = n.node_type(f) == NodeType::Attribute;
+        let parent = n.parent_node(f);
+        let state = match axis {
+            Axis::SelfAxis => State::Once(n),
+            Axis::Parent => parent.map_or(State::Done, State::Once),
+            Axis::Ancestor => parent.map_or(State::Done, State::Up),
+            Axis::AncestorOrSelf => State::Up(n),
+            Axis::Attribute => {
+                let mut v = vec![];
+                if n.node_type(f) == NodeType::Element {
+                    let mut ait = n.attribute_iter(f);
+                    while let Some(a) = ait.next() {
+                        v.push(a)
+                    }
+                }
+                State::Attributes(v.into_iter())
+            }
+            Axis::Child => State::Children(n.child_iter()),
+            Axis::Descendant => State::Descendants(None, n.descend_iter(f)),
+            Axis::DescendantOrSelf => State::Descendants(Some(n), n.descend_iter(f)),
+            Axis::FollowingSibling if !attr && parent.is_some() => State::Siblings(n.next_iter(f)),
+            Axis::PrecedingSibling if !attr && parent.is_some() => State::Siblings(n.prev_iter(f)),
+            Axis::FollowingSibling | Axis::PrecedingSibling => State::Done,
+            Axis::Following => match (attr, parent) {
+                // The descendants of the attribute's element follow the attribute
+                (true, Some(p)) => State::Following(p, Some(p.descend_iter(f))),
+                _ => State::Following(n, None),
+            },
+            Axis::Preceding => match (attr, parent) {
+                (true, Some(p)) => State::Preceding(p, None),
+                _ => State::Preceding(n, None),
+            },
+            Axis::Namespace => State::Done,
+        };
+        AxisIter { state }
+    }
+    /// The next node on the axis.
+    pub fn next(&mut self, f: &Forest) -> Option<Node> {
+        match &mut self.state {
+            State::Done => None,
+            State::Once(n) => {
+                let n = *n;
+                self.state = State::Done;
+                Some(n)
+            }
+            State::Up(n) => {
+                let n = *n;
+                self.state = n.parent_node(f).map_or(State::Done, State::Up);
+                Some(n)
+            }
+            State::Children(c) => c.next(f),
+            State::Siblings(s) => s.next(f),
+            State::Descendants(first, d) => first.take().or_else(|| d.next(f)),
+            State::Attributes(a) => a.next(),
+            State::Following(cur, desc) => {
+                if let Some(n) = desc.as_mut().and_then(|d| d.next(f)) {
+                    return Some(n);
+                }
+                // The next node in document order that is not a descendant of the current node
+                let mut m = *cur;
+                loop {
+                    if m.parent_node(f).is_some() {
+                        if let Some(s) = m.next_iter(f).next(f) {
+                            *cur = s;
+                            *desc = Some(s.descend_iter(f));
+                            return Some(s);
+                        }
+                    }
+                    match m.parent_node(f) {
+                        Some(p) => m = p,
+                        None => {
+                            self.state = State::Done;
+                            return None;
+                        }
+                    }
+                }
+            }
+            State::Preceding(cur, pending) => {
+                if let Some(rd) = pending {
+                    if let Some(n) = rd.next(f) {
+                        return Some(n);
+                    }
+                    *pending = None;
+                    return Some(*cur);
+                }
+                // The next subtree is that of the preceding sibling of the current node, or of its nearest ancestor that has one.
+                // Ancestors themselves are not on the axis.
+                let mut m = *cur;
+                loop {
+                    if m.parent_node(f).is_some() {
+                        if let Some(s) = m.prev_iter(f).next(f) {
+                            let mut rd = s.descend_iter_rev(f);
+                            *cur = s;
+                            return match rd.next(f) {
+                                Some(n) => {
+                                    *pending = Some(rd);
+                                    Some(n)
+                                }
+                                None => Some(s),
+                            };
+                        }
+                    }
+                    match m.parent_node(f) {
+                        Some(p) => m = p,
+                        None => {
+                            self.state = State::Done;
+                            return None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Collect the remaining nodes on the axis.
+    pub fn collect(mut self, f: &Forest) -> Vec<Node> {
+        let mut result = vec![];
+        while let Some(n) = self.next(f) {
+            result.push(n)
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qname::QualifiedName;
+
+    // Names of nodes; text nodes are given by their content
+    fn names(f: &Forest, n: Node, axis: Axis) -> Vec<String> {
+        AxisIter::new(f, n, axis)
+            .collect(f)
+            .iter()
+            .map(|m| match m.node_type(f) {
+                NodeType::Document => String::from("/"),
+                NodeType::Text => m.to_string(f),
+                _ => m.to_name(f).get_localname(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn all_axes() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a><b><c/>t</b><d x='1'><e/><g><h/></g><i/></d><j><k/></j></a>")
+            .expect("unable to parse XML");
+        let doc = f.get_ref(t).unwrap().get_doc_node();
+        let a = doc.get_first_element(&f).unwrap();
+        let d = AxisIter::new(&f, a, Axis::Child).collect(&f)[1];
+        let g = AxisIter::new(&f, d, Axis::Child).collect(&f)[1];
+        let x = d
+            .get_attribute(&f, &QualifiedName::new(None, None, String::from("x")))
+            .unwrap();
+
+        assert_eq!(names(&f, g, Axis::SelfAxis), vec!["g"]);
+        assert_eq!(names(&f, g, Axis::Parent), vec!["d"]);
+        assert_eq!(names(&f, g, Axis::Ancestor), vec!["d", "a", "/"]);
+        assert_eq!(names(&f, g, Axis::AncestorOrSelf), vec!["g", "d", "a", "/"]);
+        assert_eq!(names(&f, d, Axis::Child), vec!["e", "g", "i"]);
+        assert_eq!(names(&f, d, Axis::Descendant), vec!["e", "g", "h", "i"]);
+        assert_eq!(
+            names(&f, d, Axis::DescendantOrSelf),
+            vec!["d", "e", "g", "h", "i"]
+        );
+        assert_eq!(names(&f, g, Axis::FollowingSibling), vec!["i"]);
+        assert_eq!(names(&f, g, Axis::PrecedingSibling), vec!["e"]);
+        assert_eq!(names(&f, g, Axis::Following), vec!["i", "j", "k"]);
+        assert_eq!(names(&f, g, Axis::Preceding), vec!["e", "t", "c", "b"]);
+        assert_eq!(names(&f, d, Axis::Attribute), vec!["x"]);
+        assert!(names(&f, d, Axis::Namespace).is_empty());
+
+        // Attributes
+        assert_eq!(names(&f, x, Axis::Parent), vec!["d"]);
+        assert_eq!(names(&f, x, Axis::Ancestor), vec!["d", "a", "/"]);
+        assert_eq!(
+            names(&f, x, Axis::Following),
+            vec!["e", "g", "h", "i", "j", "k"]
+        );
+        assert_eq!(names(&f, x, Axis::Preceding), vec!["t", "c", "b"]);
+        assert!(names(&f, x, Axis::FollowingSibling).is_empty());
+        assert!(names(&f, x, Axis::Child).is_empty());
+
+        // The Document-type node
+        assert!(names(&f, doc, Axis::Parent).is_empty());
+        assert!(names(&f, doc, Axis::Following).is_empty());
+        assert!(names(&f, doc, Axis::Preceding).is_empty());
+        assert!(names(&f, doc, Axis::PrecedingSibling).is_empty());
+        assert_eq!(names(&f, doc, Axis::Descendant).len(), 11);
+    }
+
+    #[test]
+    fn axis_names() {
+        assert_eq!(
+            Axis::try_from("preceding-sibling").unwrap(),
+            Axis::PrecedingSibling
+        );
+        assert_eq!(Axis::SelfAxis.name(), "self");
+        assert!(Axis::Ancestor.is_reverse());
+        assert!(!Axis::Following.is_reverse());
+        assert!(Axis::try_from("sideways").is_err())
+    }
+}
@@ -0,0 +1,333 @@
+/*! ## Transforming many documents
+
+[transform_all] transforms a batch of documents with the same stylesheet, using a [rayon] thread pool with [BatchOptions::threads] threads. Each document is transformed independently: a document that fails does not stop the others, unless [BatchOptions::stop_on_error] is set. The result of each document is passed to a [BatchSink] as soon as it is ready, so the results of a large batch are not held in memory.
+
+Trees and evaluators cannot be shared between threads, so each thread compiles its own copy of the stylesheet and parses the documents that it is given into its own [Forest]. The trees of each document are removed from the forest once its result has been serialised, so memory does not grow with the size of the batch.
+
+```rust
+use xrust::batch::{transform_all, BatchOptions};
+
+let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::doc'><out><xsl:apply-templates/></out></xsl:template>
+</xsl:stylesheet>";
+let inputs = vec!["<doc>one</doc>", "<doc>two</doc>", "not XML"];
+let mut outputs = vec![String::new(); inputs.len()];
+let mut failed = vec![];
+let options = BatchOptions { threads: 2, ..BatchOptions::default() };
+let summary = transform_all(inputs, style, &options, &mut |i: usize, r: Result<String, xrust::Error>| match r {
+    Ok(s) => outputs[i] = s,
+    Err(_) => failed.push(i),
+}).expect("unable to compile stylesheet");
+assert_eq!(summary.succeeded, 2);
+assert_eq!(outputs[1], "<out>two</out>");
+assert_eq!(failed, vec![2]);
+```
+*/
+
+use crate::evaluate::{Evaluator, StaticContext, DEFAULT_MAX_DEPTH};
+use crate::forest::Forest;
+use crate::xdmerror::*;
+use crate::xslt::{from_document, transform_document};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use url::Url;
+
+/// Options for [transform_all].
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    /// The number of threads. The default is the available parallelism of the machine.
+    pub threads: usize,
+    /// The base URL of the stylesheet, used to resolve xsl:include and xsl:import.
+    pub base: Option<Url>,
+    /// Give the results to the sink in the order of the inputs, rather than as soon as each is finished.
+    pub ordered: bool,
+    /// Stop transforming documents after one fails. Documents that are already being transformed are finished.
+    pub stop_on_error: bool,
+    /// How deeply templates may be nested. See [Evaluator::set_max_depth].
+    pub max_depth: usize,
+    /// The size of the stack of each thread, in bytes. The stack is extended as evaluation goes deeper, so this only needs to be changed if other work done by the thread needs a large stack.
+    pub stack_size: usize,
+}
+
+/// The default size of the stack of a thread that transforms documents. This is the size of the stack of a main thread on most platforms.
+pub const DEFAULT_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            base: None,
+            ordered: false,
+            stop_on_error: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+}
+
+/// Receives the result of transforming each document.
+///
+/// The sink is called on the thread that called [transform_all], so it need not be thread-safe. Closures that take the index of the document and its result are sinks.
+pub trait BatchSink {
+    /// The serialised result of the document at the given position in the inputs, or the error that stopped it from being parsed or transformed.
+    fn result(&mut self, index: usize, output: Result<String, Error>);
+}
+
+impl<F: FnMut(usize, Result<String, Error>)> BatchSink for F {
+    fn result(&mut self, index: usize, output: Result<String, Error>) {
+        self(index, output)
+    }
+}
+
+/// How many documents were transformed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Transform each of the inputs with the stylesheet, using a pool of threads, and give the results to the sink.
+///
+/// The inputs are only taken as a thread becomes free, so they may be read lazily, for example from files. The stylesheet is compiled before any document is transformed; if it can't be compiled then that error is returned and the sink is not called.
+pub fn transform_all<I, S, K>(
+    inputs: I,
+    style: &str,
+    options: &BatchOptions,
+    sink: &mut K,
+) -> Result<BatchSummary, Error>
+where
+    I: IntoIterator<Item = S>,
+    I::IntoIter: Send,
+    S: AsRef<str> + Send,
+    K: BatchSink + ?Sized,
+{
+    compile(style, options)?;
+    let inputs = Mutex::new(inputs.into_iter().enumerate());
+    let stop = AtomicBool::new(false);
+    let mut summary = BatchSummary::default();
+    let (tx, rx) = mpsc::channel();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.max(1))
+        .stack_size(options.stack_size)
+        .thread_name(|i| format!("xrust-batch-{}", i))
+        .build()
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Unknown,
+                format!("unable to start transformation threads: {}", e),
+            )
+        })?;
+    // The workers run in the pool, while this thread gives their results to the sink
+    pool.in_place_scope(|scope| {
+        for _ in 0..pool.current_num_threads() {
+            let tx = tx.clone();
+            let (inputs, stop) = (&inputs, &stop);
+            scope.spawn(move |_| {
+                let mut compiled = compile(style, options);
+                while !stop.load(Ordering::Relaxed) {
+                    let next = match inputs.lock() {
+                        Ok(mut i) => i.next(),
+                        Err(_) => None,
+                    };
+                    let (i, xml) = match next {
+                        Some(n) => n,
+                        None => break,
+                    };
+                    let r = match compiled.as_mut() {
+                        Ok((f, ev)) => {
+                            // The document and its result are discarded once it has been serialised, so that the forest only holds the stylesheet between documents
+                            let n = f.tree_count();
+                            let r = transform_document(ev, f, xml.as_ref());
+                            f.truncate(n);
+                            ev.forget_trees(n);
+                            r
+                        }
+                        Err(e) => Err(e.clone()),
+                    };
+                    if r.is_err() && options.stop_on_error {
+                        stop.store(true, Ordering::Relaxed)
+                    }
+                    if tx.send((i, r)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        // Results that are waiting for earlier documents to finish, when the results are ordered
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (i, r) in rx {
+            match r {
+                Ok(_) => summary.succeeded += 1,
+                Err(_) => summary.failed += 1,
+            }
+            if options.ordered {
+                pending.insert(i, r);
+                while let Some(r) = pending.remove(&next) {
+                    sink.result(next, r);
+                    next += 1
+                }
+            } else {
+                sink.result(i, r)
+            }
+        }
+        // After stopping, documents that were not transformed leave gaps
+        for (i, r) in pending {
+            sink.result(i, r)
+        }
+    });
+    Ok(summary)
+}
+
+// Compile the stylesheet in a new forest, which will also hold the documents that are transformed
+fn compile(style: &str, options: &BatchOptions) -> Result<(Forest, Evaluator), Error> {
+    let mut sc = StaticContext::new_with_xslt_builtins();
+    let mut f = Forest::new();
+    let styledoc = f.grow_tree(style)?;
+    let mut ev = from_document(&mut f, styledoc, &mut sc, options.base.clone())?;
+    ev.set_max_depth(options.max_depth);
+    Ok((f, ev))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STYLE: &str = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::doc'><out><xsl:apply-templates/></out></xsl:template>
+</xsl:stylesheet>";
+
+    #[test]
+    fn ordered() {
+        let inputs: Vec<String> = (0..50).map(|i| format!("<doc>{}</doc>", i)).collect();
+        let mut results = vec![];
+        let options = BatchOptions {
+            threads: 4,
+            ordered: true,
+            ..BatchOptions::default()
+        };
+        let summary = transform_all(inputs.iter(), STYLE, &options, &mut |i: usize,
+                                                                          r: Result<
+            String,
+            Error,
+        >| {
+            results.push((i, r.expect("transform failed")))
+        })
+        .expect("unable to compile stylesheet");
+        assert_eq!(
+            summary,
+            BatchSummary {
+                succeeded: 50,
+                failed: 0
+            }
+        );
+        let expected: Vec<(usize, String)> =
+            (0..50).map(|i| (i, format!("<out>{}</out>", i))).collect();
+        assert_eq!(results, expected)
+    }
+
+    #[test]
+    fn errors() {
+        let inputs = vec!["<doc>a</doc>", "<doc>", "<doc>b</doc>"];
+        let mut failed = vec![];
+        let summary = transform_all(
+            inputs.clone(),
+            STYLE,
+            &BatchOptions::default(),
+            &mut |i: usize, r: Result<String, Error>| {
+                if r.is_err() {
+                    failed.push(i)
+                }
+            },
+        )
+        .expect("unable to compile stylesheet");
+        assert_eq!(summary.succeeded, 2);
+        assert_eq!(failed, vec![1]);
+
+        // With one thread, the documents after the failure are not transformed
+        let options = BatchOptions {
+            threads: 1,
+            stop_on_error: true,
+            ..BatchOptions::default()
+        };
+        let mut seen = vec![];
+        let summary = transform_all(
+            inputs,
+            STYLE,
+            &options,
+            &mut |i: usize, _r: Result<String, Error>| seen.push(i),
+        )
+        .expect("unable to compile stylesheet");
+        assert_eq!(summary.succeeded + summary.failed, 2);
+        assert_eq!(seen, vec![0, 1]);
+
+        let mut called = false;
+        assert!(transform_all(
+            vec!["<doc/>"],
+            "<notxsl/>",
+            &BatchOptions::default(),
+            &mut |_: usize, _: Result<String, Error>| called = true,
+        )
+        .is_err());
+        assert!(!called)
+    }
+
+    #[test]
+    fn documents_are_discarded() {
+        // One thread transforms every document, reusing the trees of those before it
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:key name='k' match='child::item' use='attribute::ref'/>
+  <xsl:template match='child::doc'><out><xsl:sequence select='string(key(\"k\", \"x\"))'/></out></xsl:template>
+</xsl:stylesheet>";
+        let inputs: Vec<String> = (0..5)
+            .map(|i| format!("<doc>{}<item ref='x'>{}</item></doc>", "<p/>".repeat(i), i))
+            .collect();
+        let mut results = vec![];
+        let options = BatchOptions {
+            threads: 1,
+            ordered: true,
+            ..BatchOptions::default()
+        };
+        transform_all(inputs.iter(), style, &options, &mut |_: usize,
+                                                            r: Result<
+            String,
+            Error,
+        >| {
+            results.push(r.expect("transform failed"))
+        })
+        .expect("unable to compile stylesheet");
+        let expected: Vec<String> = (0..5).map(|i| format!("<out>{}</out>", i)).collect();
+        assert_eq!(results, expected)
+    }
+
+    #[test]
+    fn max_depth() {
+        let transform = |max_depth: usize| {
+            let options = BatchOptions {
+                threads: 1,
+                max_depth,
+                stack_size: 256 * 1024,
+                ..BatchOptions::default()
+            };
+            let mut result = None;
+            transform_all(
+                vec!["<doc><doc>x</doc></doc>"],
+                STYLE,
+                &options,
+                &mut |_: usize, r: Result<String, Error>| result = Some(r),
+            )
+            .expect("unable to compile stylesheet");
+            result.expect("no result")
+        };
+        assert_eq!(
+            transform(3).expect("transform failed"),
+            "<out><out>x</out></out>"
+        );
+        match transform(2) {
+            Err(e) => assert!(matches!(e.kind, ErrorKind::RecursionLimit)),
+            Ok(_) => panic!("recursion limit was not enforced"),
+        }
+    }
+}
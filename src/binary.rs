@@ -0,0 +1,457 @@
+/*! ## A binary document format
+
+Documents may be stored in a pre-parsed binary format, so that they can be read again without parsing XML. [write] encodes a [Node] and its descendants.
+
+A [BinaryDocument] reads the format from a byte slice, without copying or decoding it in advance. Only the nodes that are visited are decoded, so a very large document can be navigated cheaply when the slice is a memory-mapped file. A [BinaryNode] may be loaded into a [Forest] when it needs to be queried or transformed.
+
+```rust
+use xrust::binary::{write, BinaryDocument};
+use xrust::forest::Forest;
+
+let mut f = Forest::new();
+let src = f.grow_tree("<catalogue><book id='1'>Emma</book><book id='2'>Persuasion</book></catalogue>")
+    .expect("unable to parse XML");
+let data = write(&f, f.get_ref(src).unwrap().get_doc_node()).expect("unable to write document");
+
+let d = BinaryDocument::new(&data).expect("not a binary document");
+let catalogue = d.root().children().next().unwrap().expect("corrupt document");
+let second = catalogue.children().nth(1).unwrap().expect("corrupt document");
+assert_eq!(second.attributes().expect("corrupt document")[0].1, "2");
+
+let t = second.load(&mut f).expect("unable to load node");
+assert_eq!(
+    f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+    "<book id='2'>Persuasion</book>"
+);
+```
+
+The format starts with the bytes "XRB1". Each node is then written in document order: a byte for the node type, the length of the node in bytes including its descendants, the node's content, and then its children. Strings are written as their length followed by UTF-8 bytes. Numbers are little-endian u32s.
+*/
+
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::qname::QualifiedName;
+use crate::value::Value;
+use crate::xdmerror::*;
+use std::convert::TryFrom;
+
+const MAGIC: &[u8] = b"XRB1";
+// The type byte and the length of the node
+const HEADER: usize = 5;
+
+fn type_byte(t: NodeType) -> Option<u8> {
+    match t {
+        NodeType::Document => Some(0),
+        NodeType::Element => Some(1),
+        NodeType::Text => Some(2),
+        NodeType::Comment => Some(3),
+        NodeType::ProcessingInstruction => Some(4),
+        _ => None,
+    }
+}
+
+fn corrupt() -> Error {
+    Error::new(
+        ErrorKind::Unknown,
+        String::from("the binary document is corrupt"),
+    )
+}
+
+/// Encode a node, and its descendants, in the binary format. Entity references are replaced by their expansion.
+pub fn write(f: &Forest, n: Node) -> Result<Vec<u8>, Error> {
+    let mut data = MAGIC.to_vec();
+    match n.node_type(f) {
+        NodeType::EntityReference => {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("an entity reference cannot be the root of a binary document"),
+            ))
+        }
+        _ => write_node(f, n, &mut data)?,
+    }
+    Ok(data)
+}
+
+fn write_u32(n: usize, data: &mut Vec<u8>) -> Result<(), Error> {
+    let m = u32::try_from(n).map_err(|_| {
+        Error::new(
+            ErrorKind::Unknown,
+            String::from("the document is too large for the binary format"),
+        )
+    })?;
+    data.extend_from_slice(&m.to_le_bytes());
+    Ok(())
+}
+
+fn write_str(s: &str, data: &mut Vec<u8>) -> Result<(), Error> {
+    write_u32(s.len(), data)?;
+    data.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn write_name(qn: &QualifiedName, data: &mut Vec<u8>) -> Result<(), Error> {
+    write_str(qn.get_nsuri_ref().unwrap_or(""), data)?;
+    write_str(qn.get_prefix().as_deref().unwrap_or(""), data)?;
+    write_str(qn.get_localname().as_str(), data)
+}
+
+fn write_node(f: &Forest, n: Node, data: &mut Vec<u8>) -> Result<(), Error> {
+    let t = n.node_type(f);
+    if t == NodeType::EntityReference {
+        let mut cit = n.child_iter();
+        while let Some(c) = cit.next(f) {
+            write_node(f, c, data)?
+        }
+        return Ok(());
+    }
+    let tb = type_byte(t).ok_or(Error::new(
+        ErrorKind::TypeError,
+        format!(
+            "cannot write a node of type {} in the binary format",
+            t.to_string()
+        ),
+    ))?;
+    let start = data.len();
+    data.push(tb);
+    // The length is filled in when the descendants have been written
+    data.extend_from_slice(&[0; 4]);
+    match t {
+        NodeType::Element => {
+            write_name(&n.to_name(f), data)?;
+            let mut attrs = vec![];
+            let mut ait = n.attribute_iter(f);
+            while let Some(a) = ait.next() {
+                attrs.push(a)
+            }
+            write_u32(attrs.len(), data)?;
+            for a in attrs {
+                write_name(&a.to_name(f), data)?;
+                write_str(a.to_string(f).as_str(), data)?
+            }
+        }
+        NodeType::Text | NodeType::Comment => write_str(n.to_string(f).as_str(), data)?,
+        NodeType::ProcessingInstruction => {
+            write_name(&n.to_name(f), data)?;
+            write_str(n.to_string(f).as_str(), data)?
+        }
+        _ => {}
+    }
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        write_node(f, c, data)?
+    }
+    let mut len = vec![];
+    write_u32(data.len() - start, &mut len)?;
+    data[start + 1..start + HEADER].copy_from_slice(&len);
+    Ok(())
+}
+
+/// A document in the binary format.
+#[derive(Clone, Copy)]
+pub struct BinaryDocument<'a> {
+    root: BinaryNode<'a>,
+}
+
+impl<'a> BinaryDocument<'a> {
+    /// Read a document from a byte slice. Only the header of the root node is decoded.
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        if !data.starts_with(MAGIC) {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("not a binary document"),
+            ));
+        }
+        Ok(BinaryDocument {
+            root: BinaryNode::new(data, MAGIC.len(), data.len())?,
+        })
+    }
+    /// The node that was written, usually a Document-type node.
+    pub fn root(&self) -> BinaryNode<'a> {
+        self.root
+    }
+}
+
+/// A node in a [BinaryDocument]. Its content is decoded when it is asked for.
+#[derive(Clone, Copy)]
+pub struct BinaryNode<'a> {
+    data: &'a [u8],
+    offset: usize,
+    t: NodeType,
+    end: usize,
+}
+
+// Decode the content of a node
+struct Reader<'a> {
+    data: &'a [u8],
+    posn: usize,
+    end: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u32(&mut self) -> Result<usize, Error> {
+        if self.posn + 4 > self.end {
+            return Result::Err(corrupt());
+        }
+        let mut b = [0; 4];
+        b.copy_from_slice(&self.data[self.posn..self.posn + 4]);
+        self.posn += 4;
+        Ok(u32::from_le_bytes(b) as usize)
+    }
+    fn str(&mut self) -> Result<&'a str, Error> {
+        let len = self.u32()?;
+        if self.posn + len > self.end {
+            return Result::Err(corrupt());
+        }
+        let s =
+            std::str::from_utf8(&self.data[self.posn..self.posn + len]).map_err(|_| corrupt())?;
+        self.posn += len;
+        Ok(s)
+    }
+    fn name(&mut self) -> Result<QualifiedName, Error> {
+        let nsuri = self.str()?;
+        let prefix = self.str()?;
+        let local = self.str()?;
+        Ok(QualifiedName::new(
+            (!nsuri.is_empty()).then(|| nsuri.to_string()),
+            (!prefix.is_empty()).then(|| prefix.to_string()),
+            local.to_string(),
+        ))
+    }
+}
+
+impl<'a> BinaryNode<'a> {
+    // Decode the header of the node at the given offset, which must be within its parent.
+    fn new(data: &'a [u8], offset: usize, limit: usize) -> Result<Self, Error> {
+        let mut r = Reader {
+            data,
+            posn: offset + 1,
+            end: limit,
+        };
+        let t = match data.get(offset) {
+            Some(0) => NodeType::Document,
+            Some(1) => NodeType::Element,
+            Some(2) => NodeType::Text,
+            Some(3) => NodeType::Comment,
+            Some(4) => NodeType::ProcessingInstruction,
+            _ => return Result::Err(corrupt()),
+        };
+        let len = r.u32()?;
+        if len < HEADER || offset + len > limit {
+            return Result::Err(corrupt());
+        }
+        Ok(BinaryNode {
+            data,
+            offset,
+            t,
+            end: offset + len,
+        })
+    }
+    fn reader(&self) -> Reader<'a> {
+        Reader {
+            data: self.data,
+            posn: self.offset + HEADER,
+            end: self.end,
+        }
+    }
+    // The position of the first child
+    fn content_end(&self) -> Result<usize, Error> {
+        let mut r = self.reader();
+        match self.t {
+            NodeType::Element => {
+                r.name()?;
+                for _ in 0..r.u32()? {
+                    r.name()?;
+                    r.str()?;
+                }
+            }
+            NodeType::Text | NodeType::Comment => {
+                r.str()?;
+            }
+            NodeType::ProcessingInstruction => {
+                r.name()?;
+                r.str()?;
+            }
+            _ => {}
+        }
+        Ok(r.posn)
+    }
+
+    pub fn node_type(&self) -> NodeType {
+        self.t
+    }
+    /// The name of an element or processing instruction.
+    pub fn name(&self) -> Result<Option<QualifiedName>, Error> {
+        match self.t {
+            NodeType::Element | NodeType::ProcessingInstruction => Ok(Some(self.reader().name()?)),
+            _ => Ok(None),
+        }
+    }
+    /// The content of a text, comment or processing instruction node.
+    pub fn value(&self) -> Result<Option<&'a str>, Error> {
+        let mut r = self.reader();
+        match self.t {
+            NodeType::Text | NodeType::Comment => Ok(Some(r.str()?)),
+            NodeType::ProcessingInstruction => {
+                r.name()?;
+                Ok(Some(r.str()?))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// The attributes of an element, with their values.
+    pub fn attributes(&self) -> Result<Vec<(QualifiedName, &'a str)>, Error> {
+        if self.t != NodeType::Element {
+            return Ok(vec![]);
+        }
+        let mut r = self.reader();
+        r.name()?;
+        let mut result = vec![];
+        for _ in 0..r.u32()? {
+            let qn = r.name()?;
+            result.push((qn, r.str()?))
+        }
+        Ok(result)
+    }
+    /// An iterator over the children of the node. Each child is skipped over, rather than decoded, when the iterator moves to the next child.
+    pub fn children(&self) -> BinaryChildren<'a> {
+        BinaryChildren {
+            data: self.data,
+            next: self.content_end(),
+            end: self.end,
+        }
+    }
+
+    /// Load the node, and its descendants, into a new [Tree](../forest/struct.Tree.html). If the node is not a Document-type node then it becomes the only child of the new tree's Document-type node.
+    pub fn load(&self, f: &mut Forest) -> Result<TreeIndex, Error> {
+        let t = match self.t {
+            NodeType::Document | NodeType::Element => f.plant_tree(),
+            _ => f.plant_fragment(),
+        };
+        if self.t == NodeType::Document {
+            for c in self.children() {
+                let n = c?.load_node(f, t)?;
+                f.get_ref_mut(t).unwrap().push_doc_node(n)?
+            }
+        } else {
+            let n = self.load_node(f, t)?;
+            f.get_ref_mut(t).unwrap().push_doc_node(n)?
+        }
+        Ok(t)
+    }
+    // Create a node, and its descendants, in the given tree.
+    fn load_node(&self, f: &mut Forest, t: TreeIndex) -> Result<Node, Error> {
+        let d = f.get_ref_mut(t).ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?;
+        let value = || -> Result<Value, Error> { Ok(Value::from(self.value()?.unwrap_or(""))) };
+        let n = match self.t {
+            NodeType::Element => d.new_element(self.name()?.ok_or_else(corrupt)?)?,
+            NodeType::Text => d.new_text(value()?)?,
+            NodeType::Comment => d.new_comment(value()?)?,
+            NodeType::ProcessingInstruction => {
+                d.new_processing_instruction(self.name()?.ok_or_else(corrupt)?, value()?)?
+            }
+            _ => return Result::Err(corrupt()),
+        };
+        for (qn, v) in self.attributes()? {
+            let a = f
+                .get_ref_mut(t)
+                .unwrap()
+                .new_attribute(qn, Value::from(v))?;
+            n.add_attribute(f, a)?
+        }
+        for c in self.children() {
+            let m = c?.load_node(f, t)?;
+            n.append_child(f, m)?
+        }
+        Ok(n)
+    }
+}
+
+/// Iterates over the children of a [BinaryNode].
+pub struct BinaryChildren<'a> {
+    data: &'a [u8],
+    next: Result<usize, Error>,
+    end: usize,
+}
+
+impl<'a> Iterator for BinaryChildren<'a> {
+    type Item = Result<BinaryNode<'a>, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let posn = match &self.next {
+            Ok(p) if *p < self.end => *p,
+            Ok(_) => return None,
+            Err(_) => {
+                // Report the error once
+                let e = std::mem::replace(&mut self.next, Ok(self.end));
+                return e.err().map(Result::Err);
+            }
+        };
+        match BinaryNode::new(self.data, posn, self.end) {
+            Ok(c) => {
+                self.next = Ok(c.end);
+                Some(Ok(c))
+            }
+            Err(e) => {
+                self.next = Ok(self.end);
+                Some(Result::Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<p:a xmlns:p='urn:p' p:x='1'><b>text</b><!--note--><?go now?><c/></p:a>")
+            .expect("unable to parse XML");
+        let doc = f.get_ref(src).unwrap().get_doc_node();
+        let data = write(&f, doc).expect("unable to write document");
+        let d = BinaryDocument::new(&data).expect("not a binary document");
+        assert_eq!(d.root().node_type(), NodeType::Document);
+        let a = d.root().children().next().unwrap().unwrap();
+        let qn = a.name().unwrap().unwrap();
+        assert_eq!(qn.get_nsuri_ref(), Some("urn:p"));
+        let kinds: Vec<_> = a.children().map(|c| c.unwrap().node_type()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                NodeType::Element,
+                NodeType::Comment,
+                NodeType::ProcessingInstruction,
+                NodeType::Element
+            ]
+        );
+        let pi = a.children().nth(2).unwrap().unwrap();
+        assert_eq!(pi.value().unwrap(), Some("now"));
+
+        let t = d.root().load(&mut f).expect("unable to load document");
+        let e = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(e.to_xml(&f), doc.get_first_element(&f).unwrap().to_xml(&f))
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let mut f = Forest::new();
+        let src = f.grow_tree("<a><b/><c/></a>").expect("unable to parse XML");
+        let data = write(&f, f.get_ref(src).unwrap().get_doc_node()).expect("unable to write");
+        assert!(BinaryDocument::new(b"<a/>").is_err());
+        assert!(BinaryDocument::new(&data[..data.len() - 1]).is_err());
+        // The length of the document element is too large for its parent
+        let mut bad = data.clone();
+        bad[MAGIC.len() + HEADER + 1] = 0xff;
+        let d = BinaryDocument::new(&bad).expect("the root header is intact");
+        assert!(d.root().children().next().unwrap().is_err());
+        assert!(d.root().load(&mut f).is_err())
+    }
+}
@@ -0,0 +1,177 @@
+/*! ## Building documents in Rust code
+
+An [ElementBuilder] describes an element, its attributes and its content, which may include other ElementBuilders. It is then built into a [Tree](../forest/struct.Tree.html) in one step. This is more concise than creating each node with new_element and attaching it with append_child, which is useful for test fixtures and for generating documents.
+
+```rust
+use xrust::builder::ElementBuilder;
+use xrust::forest::Forest;
+
+let mut f = Forest::new();
+let t = ElementBuilder::new("root")
+    .attr("a", "1")
+    .text("hi")
+    .child(ElementBuilder::new("item").attr("n", 1).text("first"))
+    .comment(" more to come ")
+    .build_document(&mut f)
+    .expect("unable to build document");
+assert_eq!(
+    f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+    "<root a='1'>hi<item n='1'>first</item><!-- more to come --></root>"
+)
+```
+*/
+
+use crate::forest::{Forest, Node, Tree, TreeIndex};
+use crate::qname::QualifiedName;
+use crate::value::Value;
+use crate::xdmerror::{Error, ErrorKind};
+
+/// The description of an element, from which element nodes are built. See the [module documentation](index.html).
+#[derive(Clone)]
+pub struct ElementBuilder {
+    name: QualifiedName,
+    attributes: Vec<(QualifiedName, Value)>,
+    content: Vec<Content>,
+}
+
+#[derive(Clone)]
+enum Content {
+    Element(ElementBuilder),
+    Text(Value),
+    Comment(Value),
+    PI(QualifiedName, Value),
+}
+
+fn local(name: &str) -> QualifiedName {
+    QualifiedName::new(None, None, name.to_string())
+}
+
+impl ElementBuilder {
+    /// Describe an element with the given local name, in no namespace.
+    pub fn new(name: &str) -> Self {
+        Self::qualified(local(name))
+    }
+    /// Describe an element with the given name.
+    pub fn qualified(name: QualifiedName) -> Self {
+        ElementBuilder {
+            name,
+            attributes: vec![],
+            content: vec![],
+        }
+    }
+    /// Add an attribute with the given local name, in no namespace.
+    pub fn attr(self, name: &str, v: impl Into<Value>) -> Self {
+        self.attribute(local(name), v)
+    }
+    /// Add an attribute with the given name.
+    pub fn attribute(mut self, name: QualifiedName, v: impl Into<Value>) -> Self {
+        self.attributes.push((name, v.into()));
+        self
+    }
+    /// Append a child element.
+    pub fn child(mut self, e: ElementBuilder) -> Self {
+        self.content.push(Content::Element(e));
+        self
+    }
+    /// Append child elements.
+    pub fn children(mut self, e: impl IntoIterator<Item = ElementBuilder>) -> Self {
+        self.content.extend(e.into_iter().map(Content::Element));
+        self
+    }
+    /// Append text.
+    pub fn text(mut self, v: impl Into<Value>) -> Self {
+        self.content.push(Content::Text(v.into()));
+        self
+    }
+    /// Append a comment.
+    pub fn comment(mut self, v: impl Into<Value>) -> Self {
+        self.content.push(Content::Comment(v.into()));
+        self
+    }
+    /// Append a processing instruction.
+    pub fn pi(mut self, target: &str, v: impl Into<Value>) -> Self {
+        self.content.push(Content::PI(local(target), v.into()));
+        self
+    }
+
+    /// Create the element, and its attributes and content, in the given tree. The element is not attached to the tree.
+    ///
+    /// Returns an error if any name is not a valid XML name, or any comment or processing instruction cannot be serialised.
+    pub fn build(&self, f: &mut Forest, t: TreeIndex) -> Result<Node, Error> {
+        let e = tree(f, t)?.new_element(self.name.clone())?;
+        e.set_attributes(f, self.attributes.iter().cloned())?;
+        for c in &self.content {
+            let n = match c {
+                Content::Element(b) => b.build(f, t)?,
+                Content::Text(v) => tree(f, t)?.new_text(v.clone())?,
+                Content::Comment(v) => tree(f, t)?.new_comment(v.clone())?,
+                Content::PI(qn, v) => {
+                    tree(f, t)?.new_processing_instruction(qn.clone(), v.clone())?
+                }
+            };
+            e.append_child(f, n)?
+        }
+        Ok(e)
+    }
+    /// Create a new [Tree](../forest/struct.Tree.html) that has this element as its document element.
+    pub fn build_document(&self, f: &mut Forest) -> Result<TreeIndex, Error> {
+        let t = f.plant_tree();
+        let e = self.build(f, t)?;
+        tree(f, t)?.push_doc_node(e)?;
+        Ok(t)
+    }
+}
+
+fn tree(f: &mut Forest, t: TreeIndex) -> Result<&mut Tree, Error> {
+    f.get_ref_mut(t).ok_or(Error::new(
+        ErrorKind::Unknown,
+        String::from("unable to find tree"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build() {
+        let mut f = Forest::new();
+        let t = ElementBuilder::qualified(QualifiedName::new(
+            Some(String::from("urn:x")),
+            Some(String::from("x")),
+            String::from("doc"),
+        ))
+        .children((1..=3).map(|i| ElementBuilder::new("n").text(i)))
+        .pi("go", "now")
+        .build_document(&mut f)
+        .expect("unable to build document");
+        let d = f.get_ref(t).unwrap().get_doc_node();
+        assert_eq!(
+            d.get_first_element(&f).unwrap().to_xml(&f),
+            "<x:doc xmlns:x='urn:x'><n>1</n><n>2</n><n>3</n><?go now?></x:doc>"
+        );
+
+        // A fragment element
+        let u = f.plant_fragment();
+        let e = ElementBuilder::new("e")
+            .attr("a", true)
+            .build(&mut f, u)
+            .expect("unable to build element");
+        assert_eq!(e.parent(&f), None);
+        assert_eq!(e.to_xml(&f), "<e a='true'></e>");
+    }
+
+    #[test]
+    fn invalid() {
+        let mut f = Forest::new();
+        assert!(ElementBuilder::new("1bad").build_document(&mut f).is_err());
+        assert!(ElementBuilder::new("ok")
+            .attr("", 1)
+            .build_document(&mut f)
+            .is_err());
+        assert!(ElementBuilder::new("ok")
+            .comment("no -- here")
+            .build_document(&mut f)
+            .is_err());
+    }
+}
@@ -0,0 +1,416 @@
+/*! ## Editing documents incrementally
+
+An [EditableDocument] keeps the text of a document together with the [Tree](../forest/struct.Tree.html) that was parsed from it, for editors and language servers that change the text a little at a time.
+
+When the text is edited, only the innermost element that contains the edit is parsed again, and it replaces the old element in the tree. The rest of the tree is untouched, so nodes outside that element remain valid. If the edit changes the markup of the document element, or the prologue, then the whole document is parsed again.
+
+The source range of each element is recorded, so that a node can be found from a position in the text and vice versa.
+
+```rust
+use xrust::edit::{EditableDocument, Reparse, TextEdit};
+use xrust::forest::Forest;
+
+let mut f = Forest::new();
+let mut d = EditableDocument::new(&mut f, "<doc><p>Hello</p><p>World</p></doc>")
+    .expect("unable to parse XML");
+
+// Change "World" to "there"
+let r = d.edit(&mut f, &TextEdit::new(20..25, "there")).expect("unable to apply edit");
+assert!(matches!(r, Reparse::Element(_)));
+assert_eq!(d.text(), "<doc><p>Hello</p><p>there</p></doc>");
+assert_eq!(
+    f.get_ref(d.tree()).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+    "<doc><p>Hello</p><p>there</p></doc>"
+);
+```
+
+Entity references are not expanded when an element is parsed again, since the document type declaration is not available. An edit that contains one causes the whole document to be parsed again.
+*/
+
+use crate::forest::{make_node, Forest, Node, NodeType, TreeIndex};
+use crate::parsexml::{next_token, parse_fragment, ParserConfig, Token, XMLNode};
+use crate::qname::XML_NAMESPACE;
+use crate::xdmerror::*;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A change to the text of a document: the bytes in the range are replaced by the text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, text: &str) -> Self {
+        TextEdit {
+            range,
+            text: text.to_string(),
+        }
+    }
+}
+
+/// The source range of an element.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    /// From the start of the start tag to the end of the end tag.
+    pub outer: Range<usize>,
+    /// The content between the start tag and the end tag. An empty element tag has no content.
+    pub inner: Option<Range<usize>>,
+}
+
+/// What was parsed again after an edit.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reparse {
+    /// The element that contained the edit was parsed again. This is the new element, which has replaced the old one in the tree.
+    Element(Node),
+    /// The whole document was parsed again, creating a new tree.
+    Document(TreeIndex),
+}
+
+/// The text of a document and the tree parsed from it.
+pub struct EditableDocument {
+    text: String,
+    tree: TreeIndex,
+    // The elements of the tree, in document order. None if the ranges could not be found, e.g. when entity references have been expanded, or when the text is not well-formed.
+    spans: Option<Vec<(Node, Span)>>,
+}
+
+impl EditableDocument {
+    /// Parse the text to create a tree.
+    pub fn new(f: &mut Forest, text: &str) -> Result<Self, Error> {
+        let tree = f.grow_tree(text)?;
+        let mut d = EditableDocument {
+            text: text.to_string(),
+            tree,
+            spans: None,
+        };
+        let doc = f.get_ref(tree).unwrap().get_doc_node();
+        d.spans = element_spans(f, text, 0, doc);
+        Ok(d)
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// The tree for the text, as at the last successful parse.
+    pub fn tree(&self) -> TreeIndex {
+        self.tree
+    }
+    /// The source range of an element.
+    pub fn span(&self, n: Node) -> Option<&Span> {
+        self.spans
+            .as_ref()?
+            .iter()
+            .find(|(m, _)| *m == n)
+            .map(|(_, s)| s)
+    }
+    /// The line and column of an offset in the text, both starting at 1. Columns are counted in characters. Returns None if the offset is not in the text, or is not at a character boundary.
+    pub fn line_column(&self, offset: usize) -> Option<(usize, usize)> {
+        let before = self.text.get(..offset)?;
+        let line = before.matches('\n').count() + 1;
+        let start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some((line, before[start..].chars().count() + 1))
+    }
+    /// The line and column of the start tag of an element.
+    pub fn position(&self, n: Node) -> Option<(usize, usize)> {
+        self.line_column(self.span(n)?.outer.start)
+    }
+    /// The innermost element whose source range contains the offset, including its tags.
+    pub fn element_at(&self, offset: usize) -> Option<Node> {
+        self.spans
+            .as_ref()?
+            .iter()
+            .rev()
+            .find(|(_, s)| s.outer.start <= offset && offset < s.outer.end)
+            .map(|(n, _)| *n)
+    }
+
+    /// Change the text and update the tree.
+    ///
+    /// If the new text is not well-formed then an error is returned. The text is still changed, but the tree is the one from the last successful parse. The next edit parses the whole document again.
+    pub fn edit(&mut self, f: &mut Forest, e: &TextEdit) -> Result<Reparse, Error> {
+        if e.range.start > e.range.end
+            || e.range.end > self.text.len()
+            || !self.text.is_char_boundary(e.range.start)
+            || !self.text.is_char_boundary(e.range.end)
+        {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("the edit is outside the text"),
+            ));
+        }
+        self.text.replace_range(e.range.clone(), e.text.as_str());
+        let delta = e.text.len() as isize - e.range.len() as isize;
+        if let Some(i) = self.container(&e.range) {
+            if let Some(r) = self.reparse_element(f, i, delta) {
+                return Ok(r);
+            }
+        }
+        self.spans = None;
+        let tree = f.grow_tree(self.text.as_str())?;
+        self.tree = tree;
+        let doc = f.get_ref(tree).unwrap().get_doc_node();
+        self.spans = element_spans(f, self.text.as_str(), 0, doc);
+        Ok(Reparse::Document(tree))
+    }
+
+    // The position in the span list of the innermost element whose content contains the edit.
+    fn container(&self, r: &Range<usize>) -> Option<usize> {
+        self.spans.as_ref()?.iter().rposition(|(_, s)| {
+            s.inner
+                .as_ref()
+                .is_some_and(|c| c.start <= r.start && r.end <= c.end)
+        })
+    }
+
+    // Parse an element again, after the text has been changed by delta bytes within its content.
+    // Returns None if the element could not be parsed by itself.
+    fn reparse_element(&mut self, f: &mut Forest, i: usize, delta: isize) -> Option<Reparse> {
+        let spans = self.spans.as_mut()?;
+        let (old, span) = spans[i].clone();
+        let end = span.outer.end.checked_add_signed(delta)?;
+        let src = self.text.get(span.outer.start..end)?;
+        let mut v = parse_fragment(src).ok()?;
+        if v.len() != 1 || !matches!(v[0], XMLNode::Element(_, _, _)) {
+            return None;
+        }
+        let mut ns = in_scope_namespaces(self.text.as_str(), spans, i);
+        let new = make_node(v.pop()?, f, self.tree, &mut ns, &ParserConfig::new()).ok()?;
+        let new_spans = match element_spans(f, src, span.outer.start, new) {
+            Some(s) => s,
+            None => {
+                // The new element has an entity reference
+                return None;
+            }
+        };
+        old.replace_with(f, vec![new]).ok()?;
+
+        // Remove the spans of the old element and its descendants, which end before the old end of the element
+        let last = spans[i..]
+            .iter()
+            .position(|(_, s)| s.outer.start >= span.outer.end)
+            .map_or(spans.len(), |p| p + i);
+        spans.drain(i..last);
+        // Move the spans that follow the element, and the ends of its ancestors
+        let shift = |n: usize| n.checked_add_signed(delta).unwrap_or(n);
+        for (_, s) in spans.iter_mut() {
+            if s.outer.start >= span.outer.end {
+                s.outer = shift(s.outer.start)..shift(s.outer.end);
+                s.inner = s.inner.as_ref().map(|c| shift(c.start)..shift(c.end));
+            } else if s.outer.start < span.outer.start && s.outer.end >= span.outer.end {
+                s.outer.end = shift(s.outer.end);
+                s.inner = s.inner.as_ref().map(|c| c.start..shift(c.end));
+            }
+        }
+        // Put the spans of the new element in its place
+        spans.splice(i..i, new_spans);
+        Some(Reparse::Element(new))
+    }
+}
+
+// The prefixes declared by the start tags of the ancestors of an element.
+fn in_scope_namespaces(text: &str, spans: &[(Node, Span)], i: usize) -> HashMap<String, String> {
+    let mut ns = HashMap::new();
+    ns.insert(String::from("xml"), String::from(XML_NAMESPACE));
+    let e = &spans[i].1.outer;
+    // Ancestors are in document order, so nearer declarations replace those further out
+    for (_, s) in spans[..i]
+        .iter()
+        .filter(|(_, s)| s.outer.start < e.start && e.end <= s.outer.end)
+    {
+        let tag = &text[s.outer.start..s.inner.as_ref().map_or(s.outer.end, |c| c.start)];
+        if let Ok((_, Token::StartTag(_, a, _))) = next_token(tag, tag) {
+            for b in a {
+                if let XMLNode::Attribute(qn, v) = b {
+                    if qn.get_prefix().as_deref() == Some("xmlns") {
+                        ns.insert(qn.get_localname(), v.to_string());
+                    }
+                }
+            }
+        }
+    }
+    ns
+}
+
+// The spans of a node, if it is an element, and its descendant elements, in document order.
+// The text is the source of the node, starting at the given offset in the document.
+fn element_spans(f: &Forest, text: &str, base: usize, n: Node) -> Option<Vec<(Node, Span)>> {
+    let mut elements = vec![];
+    if n.node_type(f) == NodeType::Element {
+        elements.push(n)
+    }
+    let mut it = n.descend_iter(f);
+    while let Some(m) = it.next(f) {
+        match m.node_type(f) {
+            NodeType::Element => elements.push(m),
+            NodeType::EntityReference => return None,
+            _ => {}
+        }
+    }
+    let ranges = tag_ranges(text, base)?;
+    (ranges.len() == elements.len()).then(|| elements.into_iter().zip(ranges).collect())
+}
+
+// Find the source range of each element in the text, in the order of their start tags.
+fn tag_ranges(text: &str, base: usize) -> Option<Vec<Span>> {
+    let mut result: Vec<Span> = vec![];
+    let mut open = vec![];
+    let mut input = text;
+    while !input.is_empty() {
+        let start = base + text.len() - input.len();
+        let (rest, t) = next_token(text, input).ok()?;
+        let end = base + text.len() - rest.len();
+        match t {
+            Token::StartTag(_, _, true) => result.push(Span {
+                outer: start..end,
+                inner: None,
+            }),
+            Token::StartTag(_, _, false) => {
+                open.push(result.len());
+                result.push(Span {
+                    outer: start..end,
+                    inner: Some(end..end),
+                })
+            }
+            Token::EndTag(_) => {
+                let s = &mut result[open.pop()?];
+                s.outer.end = end;
+                s.inner = s.inner.as_ref().map(|c| c.start..start);
+            }
+            Token::Reference(_) => return None,
+            _ => {}
+        }
+        input = rest;
+    }
+    open.is_empty().then_some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xml(f: &Forest, d: &EditableDocument) -> String {
+        f.get_ref(d.tree())
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(f)
+            .unwrap()
+            .to_xml(f)
+    }
+
+    #[test]
+    fn incremental() {
+        let mut f = Forest::new();
+        let src = "<?xml version='1.0'?><doc xmlns:p='urn:p'><p:a>one</p:a><b><c/>two</b></doc>";
+        let mut d = EditableDocument::new(&mut f, src).expect("unable to parse XML");
+        let tree = d.tree();
+        let doc = f
+            .get_ref(tree)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let a = doc.child_iter().next(&f).unwrap();
+        assert_eq!(d.element_at(src.find("one").unwrap()), Some(a));
+        assert_eq!(d.span(a).unwrap().outer, 42..56);
+
+        // Edit the content of b
+        let two = d.text().find("two").unwrap();
+        let r = d
+            .edit(&mut f, &TextEdit::new(two..two + 3, "<d p:x='1'>three</d>"))
+            .expect("unable to apply edit");
+        let b = match r {
+            Reparse::Element(b) => b,
+            _ => panic!("expected the element to be parsed again"),
+        };
+        assert_eq!(d.tree(), tree);
+        assert_eq!(
+            xml(&f, &d),
+            "<doc><p:a xmlns:p='urn:p'>one</p:a><b><c></c><d p:x='1'>three</d></b></doc>"
+        );
+        // Nodes outside the edited element are unchanged
+        assert_eq!(doc.child_iter().next(&f), Some(a));
+        let mut cit = b.child_iter();
+        cit.next(&f);
+        let new = cit.next(&f).unwrap();
+        assert_eq!(d.element_at(d.text().find("three").unwrap()), Some(new));
+        // The prefix declared on the document element is in scope
+        let mut ait = new.attribute_iter(&f);
+        let x = ait.next().unwrap();
+        assert_eq!(x.to_name(&f).get_nsuri_ref(), Some("urn:p"));
+        // The spans that follow the edit have moved
+        assert_eq!(d.span(doc).unwrap().outer.end, d.text().len());
+        assert_eq!(
+            &d.text()[d.span(b).unwrap().outer.clone()],
+            "<b><c/><d p:x='1'>three</d></b>"
+        );
+
+        // An edit that is not well-formed keeps the last tree
+        let c = d.text().find("<c/>").unwrap();
+        assert!(d.edit(&mut f, &TextEdit::new(c..c + 4, "<c>")).is_err());
+        assert_eq!(d.tree(), tree);
+        assert!(d.text().contains("<b><c><d p:x='1'>"));
+        let r = d
+            .edit(&mut f, &TextEdit::new(c..c + 3, "<c/>"))
+            .expect("unable to apply edit");
+        assert!(matches!(r, Reparse::Document(t) if t != tree));
+
+        // Edit the prologue
+        let tree = d.tree();
+        let r = d
+            .edit(&mut f, &TextEdit::new(0..21, ""))
+            .expect("unable to apply edit");
+        assert!(matches!(r, Reparse::Document(t) if t != tree));
+        assert_eq!(d.span(a), None);
+        assert!(d.edit(&mut f, &TextEdit::new(0..1000, "")).is_err())
+    }
+
+    #[test]
+    fn line_column() {
+        let mut f = Forest::new();
+        let d = EditableDocument::new(&mut f, "<doc>\n<p>é</p><q/>\n</doc>")
+            .expect("unable to parse XML");
+        assert_eq!(d.line_column(0), Some((1, 1)));
+        assert_eq!(d.line_column(6), Some((2, 1)));
+        // é is two bytes but one character
+        let q = d.element_at(15).expect("no element");
+        assert_eq!(d.position(q), Some((2, 9)));
+        assert_eq!(d.line_column(100), None)
+    }
+
+    #[test]
+    fn reparse_spans() {
+        let mut f = Forest::new();
+        // The document element is parsed again by itself
+        let mut d = EditableDocument::new(&mut f, "<doc>x</doc>").expect("unable to parse XML");
+        let tree = d.tree();
+        let r = d
+            .edit(&mut f, &TextEdit::new(5..6, "y"))
+            .expect("unable to apply edit");
+        assert!(matches!(r, Reparse::Element(_)));
+        assert_eq!(d.tree(), tree);
+        assert_eq!(xml(&f, &d), "<doc>y</doc>");
+
+        // Deleting text removes the spans of the old descendants
+        let mut d = EditableDocument::new(&mut f, "<doc><p>aaaaaaaaaa<b/></p><q/></doc>")
+            .expect("unable to parse XML");
+        let r = d
+            .edit(&mut f, &TextEdit::new(8..18, ""))
+            .expect("unable to apply edit");
+        assert!(matches!(r, Reparse::Element(_)));
+        assert_eq!(xml(&f, &d), "<doc><p><b></b></p><q></q></doc>");
+        let spans = d.spans.as_ref().expect("no spans");
+        assert_eq!(
+            spans
+                .iter()
+                .map(|(n, s)| (n.to_name(&f).to_string(), s.outer.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (String::from("doc"), 0..26),
+                (String::from("p"), 5..16),
+                (String::from("b"), 8..12),
+                (String::from("q"), 16..20),
+            ]
+        );
+    }
+}
@@ -4,25 +4,46 @@
 //!
 //! This library uses the traits defined in [Item], so it is independent of the tree implementation.
 
-use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::axes::{self, AxisIter};
+use crate::forest::{Forest, KeyIndex, Node, NodeType, TreeIndex};
 use crate::item::{Item, Sequence, SequenceTrait};
-use crate::output::OutputDefinition;
+use crate::json::{json_to_xml, xml_to_json};
+use crate::output::{DisableOutputEscaping, OutputDefinition};
 use crate::parsepicture::parse as picture_parse;
 use crate::qname::*;
+use crate::resolvers::{resolve_href, resolve_reference, NoFetchResolver, Resolver};
+#[cfg(feature = "async")]
+use crate::resolvers::{with_prefetch, AsyncResolver, Prefetched};
+use crate::sequencetype::{AtomicType, SequenceType};
 use crate::value::{Operator, Value};
 use crate::xdmerror::*;
 #[allow(unused_imports)]
 use chrono::{DateTime, Datelike, FixedOffset, Local, Timelike};
 #[cfg(test)]
 use rust_decimal_macros::dec;
-use std::cell::{RefCell, RefMut};
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell, RefMut};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::UnicodeSegmentation;
 use url::Url;
 
+/// The default limit on how deeply templates may be nested. See [Evaluator::set_max_depth].
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// The default limit on the number of integers in a sequence constructed by a range expression. See [Evaluator::set_max_range].
+pub const DEFAULT_MAX_RANGE: usize = 10_000_000;
+
+// Evaluation is recursive. When less than RED_ZONE bytes of the stack are left, evaluation continues on a new segment of STACK_SEGMENT bytes that is allocated on the heap.
+// The red zone must be larger than the frames between two calls of evaluate, which are large in an unoptimised build.
+const RED_ZONE: usize = 1024 * 1024;
+const STACK_SEGMENT: usize = 8 * 1024 * 1024;
+
 // The dynamic evaluation context.
 //
 // The dynamic context stores parts that can change as evaluation proceeds,
@@ -32,8 +53,176 @@ pub struct DynamicContext {
     depth: RefCell<usize>,
     current_grouping_key: RefCell<Vec<Option<Rc<Item>>>>,
     current_group: RefCell<Vec<Option<Sequence>>>,
+    current_merge: RefCell<Vec<MergeGroup>>,
     current_import: RefCell<usize>,
     deps: RefCell<Vec<Url>>, // URIs for included/imported stylesheets
+    documents: RefCell<HashMap<Url, TreeIndex>>, // Secondary source documents
+    fired: RefCell<usize>,   // The number of template bodies evaluated
+    origins: RefCell<Option<HashMap<Node, Origin>>>, // The source map, if it is being recorded
+    templates: RefCell<Vec<Rc<OriginTemplate>>>, // The templates being evaluated, when recording the source map
+    stop_at: Cell<Option<usize>>, // The debugger stops at the next template at this depth or less
+    iterations: RefCell<Vec<Option<Iteration>>>, // How the current iteration of each enclosing xsl:iterate ended
+    max_depth: Cell<usize>,                      // The deepest that templates may be nested
+    max_range: Cell<usize>, // The most integers that a range expression may construct
+}
+
+// The operand of cast as and castable as: a single atomic value, or None if the sequence is empty and that is allowed.
+// Nodes are atomized to their string value.
+fn cast_operand(s: &Sequence, empty: bool, f: &Forest) -> Result<Option<Value>, Error> {
+    match s.len() {
+        0 if empty => Ok(None),
+        1 => match &*s[0] {
+            Item::Value(v) => Ok(Some(v.clone())),
+            Item::Node(n) => Ok(Some(Value::from(n.to_string(f)))),
+            Item::Function => Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("a function cannot be cast"),
+            )),
+        },
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("cast requires a single item"),
+        )),
+    }
+}
+
+// An operand of a range expression is either empty or a single integer. The untyped value of a node is cast to xs:integer.
+fn range_operand(s: &Sequence, f: &Forest) -> Result<Option<i64>, Error> {
+    match s.len() {
+        0 => Ok(None),
+        1 => match &*s[0] {
+            Item::Node(n) => i64::try_from(&Value::from(n.to_string(f))).map(Some),
+            Item::Value(v) if AtomicType::Integer.matches(v) => v.to_int().map(Some),
+            _ => Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("operand of a range expression must be an integer"),
+            )),
+        },
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("operand of a range expression must be a single item"),
+        )),
+    }
+}
+
+// The number of integers in a range
+fn range_len(r: &RangeInclusive<i64>) -> i128 {
+    *r.end() as i128 - *r.start() as i128 + 1
+}
+
+// The positions, counting from 1, of the items that fn:subsequence selects from a sequence of the given length.
+// These are the positions p such that round(start) <= p < round(start) + round(length).
+fn subsequence_positions(len: i128, start: f64, length: Option<f64>) -> std::ops::Range<i128> {
+    let first = (start + 0.5).floor();
+    let end = length.map_or(f64::INFINITY, |l| first + (l + 0.5).floor());
+    if first.is_nan() || end.is_nan() {
+        return 1..1;
+    }
+    let from = first.max(1.0);
+    let to = end.min(len as f64 + 1.0);
+    if from < to {
+        (from as i128)..(to as i128)
+    } else {
+        1..1
+    }
+}
+
+// A numeric argument of a function: a single item that is converted to a double
+fn numeric_argument(s: &Sequence) -> Result<f64, Error> {
+    match s.as_slice() {
+        [i] => Ok(i.to_double()),
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("argument must be a single number"),
+        )),
+    }
+}
+
+// Apply an arithmetic operator to two numbers, which are either integers or doubles.
+// If both are integers then so is the result, except for division.
+fn arithmetic(op: &ArithmeticOperator, a: &Value, b: &Value) -> Result<Value, Error> {
+    let zero = || {
+        Error::new(
+            ErrorKind::Unknown,
+            String::from("FOAR0001: division by zero"),
+        )
+    };
+    if let (Value::Integer(x), Value::Integer(y)) = (a, b) {
+        let r = match op {
+            ArithmeticOperator::Add => x.checked_add(*y),
+            ArithmeticOperator::Subtract => x.checked_sub(*y),
+            ArithmeticOperator::Multiply => x.checked_mul(*y),
+            ArithmeticOperator::IntegerDivide | ArithmeticOperator::Modulo if *y == 0 => {
+                return Result::Err(zero())
+            }
+            ArithmeticOperator::IntegerDivide => x.checked_div(*y),
+            ArithmeticOperator::Modulo => x.checked_rem(*y),
+            ArithmeticOperator::Divide | ArithmeticOperator::Noop => {
+                return arithmetic(op, &Value::from(*x as f64), &Value::from(*y as f64))
+            }
+        };
+        return r.map(Value::from).ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("FOAR0002: integer overflow"),
+        ));
+    }
+    let (x, y) = (a.to_double(), b.to_double());
+    Ok(match op {
+        ArithmeticOperator::Noop => Value::from(y),
+        ArithmeticOperator::Add => Value::from(x + y),
+        ArithmeticOperator::Subtract => Value::from(x - y),
+        ArithmeticOperator::Multiply => Value::from(x * y),
+        ArithmeticOperator::Divide => Value::from(x / y),
+        ArithmeticOperator::IntegerDivide => {
+            if y == 0.0 {
+                return Result::Err(zero());
+            }
+            let q = (x / y).trunc();
+            if !q.is_finite() || q.abs() >= i64::MAX as f64 {
+                return Result::Err(Error::new(
+                    ErrorKind::Unknown,
+                    String::from("FOAR0002: result of integer division is out of range"),
+                ));
+            }
+            Value::from(q as i64)
+        }
+        ArithmeticOperator::Modulo => Value::from(x % y),
+    })
+}
+
+// A parameter whose value does not have its declared type is a type error (XTTE0570)
+fn parameter_type_error(name: &str, e: Error) -> Error {
+    match e.kind {
+        ErrorKind::TypeError => Error::new(
+            ErrorKind::TypeError,
+            format!("XTTE0570: value of parameter \"{}\": {}", name, e.message),
+        ),
+        _ => e,
+    }
+}
+
+// The declared type of a parameter, if it has one.
+// A parameter with an as attribute is compiled to a declaration whose value is a single Convert constructor.
+fn parameter_type(p: &Constructor) -> Option<&SequenceType> {
+    match p {
+        Constructor::VariableDeclaration(_, v) => match v.as_slice() {
+            [Constructor::Convert(_, t)] => Some(t),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The items that have the same merge key, with the name of the source of each item
+struct MergeGroup {
+    key: Sequence,
+    items: Vec<(Option<String>, Rc<Item>)>,
+}
+
+// How an iteration of xsl:iterate ended, if not by reaching the end of the body
+enum Iteration {
+    Break,
+    Next(Vec<(String, Sequence)>),
 }
 
 impl DynamicContext {
@@ -43,8 +232,17 @@ impl DynamicContext {
             depth: RefCell::new(0),
             current_grouping_key: RefCell::new(vec![None]),
             current_group: RefCell::new(vec![None]),
+            current_merge: RefCell::new(vec![]),
             current_import: RefCell::new(0),
             deps: RefCell::new(vec![]),
+            documents: RefCell::new(HashMap::new()),
+            fired: RefCell::new(0),
+            origins: RefCell::new(None),
+            templates: RefCell::new(vec![]),
+            stop_at: Cell::new(None),
+            iterations: RefCell::new(vec![]),
+            max_depth: Cell::new(DEFAULT_MAX_DEPTH),
+            max_range: Cell::new(DEFAULT_MAX_RANGE),
         }
     }
     /// Retrieve the dependencies for the stylesheet
@@ -56,6 +254,20 @@ impl DynamicContext {
     pub fn add_dependency(&self, u: Url) {
         self.deps.borrow_mut().push(u);
     }
+    /// Make a secondary source document available to the document() function.
+    /// The document will not be fetched when the function is called with this URL.
+    pub fn add_document(&self, u: Url, t: TreeIndex) {
+        self.documents.borrow_mut().insert(u, t);
+    }
+    /// Find a secondary source document that has already been loaded.
+    pub fn get_document(&self, u: &Url) -> Option<TreeIndex> {
+        self.documents.borrow().get(u).copied()
+    }
+    // Forget the documents of trees that have been removed from the forest, i.e. those with an index of n or more
+    #[cfg(feature = "xslt")]
+    pub(crate) fn forget_trees(&self, n: TreeIndex) {
+        self.documents.borrow_mut().retain(|_, t| *t < n);
+    }
 
     fn push_current_grouping_key(&self, k: Item) {
         self.current_grouping_key
@@ -72,13 +284,45 @@ impl DynamicContext {
     fn pop_current_group(&self) {
         self.current_group.borrow_mut().pop();
     }
-    fn depth_incr(&self) {
+    fn depth_incr(&self, t: &Template) -> Result<(), Error> {
         let mut d = self.depth.borrow_mut();
+        if *d >= self.max_depth.get() {
+            return Result::Err(Error::new(
+                ErrorKind::RecursionLimit,
+                format!(
+                    "templates are nested more than {} deep; the stylesheet may have infinite recursion",
+                    self.max_depth.get()
+                ),
+            ));
+        }
         *d += 1;
+        *self.fired.borrow_mut() += 1;
+        if self.origins.borrow().is_some() {
+            self.templates.borrow_mut().push(Rc::new(OriginTemplate {
+                pattern: t.source.clone(),
+                mode: t.mode.clone(),
+                priority: t.priority,
+            }))
+        }
+        Ok(())
     }
     fn depth_decr(&self) {
         let mut d = self.depth.borrow_mut();
         *d -= 1;
+        self.templates.borrow_mut().pop();
+    }
+    // Add a result node to the source map, if it is being recorded
+    fn record_origin(&self, n: Node, source: Option<Node>, instruction: Instruction) {
+        if let Some(o) = self.origins.borrow_mut().as_mut() {
+            o.insert(
+                n,
+                Origin {
+                    source,
+                    template: self.templates.borrow().last().cloned(),
+                    instruction,
+                },
+            );
+        }
     }
     fn import_incr(&self) {
         let mut d = self.current_import.borrow_mut();
@@ -120,6 +364,160 @@ impl DynamicContext {
     }
 }
 
+/// Where a node in the result tree came from. See [Evaluator::set_source_map].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    /// The context item when the node was constructed, or the node that was copied. None if there was no context item, or it was not a node.
+    pub source: Option<Node>,
+    /// The template whose body constructed the node. None if the node was constructed outside of a template.
+    pub template: Option<Rc<OriginTemplate>>,
+    /// The kind of instruction that constructed the node.
+    pub instruction: Instruction,
+}
+
+/// The template that constructed a result node. See [Origin].
+#[derive(Clone, Debug, PartialEq)]
+pub struct OriginTemplate {
+    /// The match pattern of the template. None for built-in templates.
+    pub pattern: Option<String>,
+    pub mode: Option<String>,
+    pub priority: f64,
+}
+
+/// The kinds of instruction that construct result nodes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    LiteralElement,
+    LiteralAttribute,
+    /// A text node made from an atomic value in the content of an element
+    Text,
+    /// xsl:copy
+    Copy,
+    /// xsl:copy-of. The source is the corresponding node in the copied tree.
+    DeepCopy,
+    /// An attribute added to the context element. The source is that element.
+    SetAttribute,
+}
+
+// The context item, if it is a node
+fn context_node(ctxt: &Option<Sequence>, posn: Option<usize>) -> Option<Node> {
+    match ctxt
+        .as_ref()
+        .zip(posn)
+        .and_then(|(c, p)| c.get(p))
+        .map(|i| &**i)
+    {
+        Some(Item::Node(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Receives control as templates are evaluated. See [Evaluator::set_debugger].
+///
+/// Templates are identified by their match pattern, i.e. the text of the match attribute. A [Frame] describes the template, its context item, and the variables that are in scope.
+pub trait Debugger {
+    /// Called before the body of every template is evaluated.
+    fn enter(&mut self, _frame: &Frame) {}
+    /// Called when a template has a breakpoint, or when the previous [Step] stops at it. Called after [Debugger::enter].
+    fn stop(&mut self, frame: &Frame) -> Step;
+    /// Called after the body of a template has been evaluated successfully, with its result.
+    fn leave(&mut self, _frame: &Frame, _result: &Sequence) {}
+}
+
+/// How evaluation continues after a [Debugger] has stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// Run until the next breakpoint.
+    Continue,
+    /// Stop at the next template.
+    Into,
+    /// Stop at the next template that is not applied from within this template.
+    Over,
+    /// Stop at the next template after this template has finished.
+    Out,
+    /// Stop the transformation. Evaluation returns an error.
+    Abort,
+}
+
+/// A template that is about to be evaluated, as reported to a [Debugger].
+pub struct Frame<'a> {
+    /// The match pattern of the template. None for built-in templates.
+    pub pattern: Option<&'a str>,
+    pub mode: Option<&'a str>,
+    pub priority: f64,
+    /// The number of templates being evaluated, including this one.
+    pub depth: usize,
+    /// The context item for the template.
+    pub context: &'a Rc<Item>,
+    /// The forest that contains the source and result documents.
+    pub forest: &'a Forest,
+    dc: &'a DynamicContext,
+}
+
+impl<'a> Frame<'a> {
+    /// The value of a variable or parameter that is in scope.
+    pub fn variable(&self, name: &str) -> Option<Sequence> {
+        self.dc
+            .vars
+            .borrow()
+            .get(name)
+            .and_then(|v| v.last().cloned())
+    }
+    /// The names of the variables and parameters that are in scope, in no particular order.
+    pub fn variables(&self) -> Vec<String> {
+        self.dc
+            .vars
+            .borrow()
+            .iter()
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+}
+
+/// Statistics about the result of a transformation, produced by [Evaluator::dry_run].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransformStats {
+    /// The number of template bodies that were evaluated, including built-in templates
+    pub templates_fired: usize,
+    /// The number of nodes in the result, including the descendants and attributes of elements
+    pub nodes: usize,
+    /// The number of items in the result that are not nodes
+    pub items: usize,
+    /// An estimate of the length, in bytes, of the serialised result. Escaping of special characters is not included.
+    pub output_size: usize,
+}
+
+// Add a node of the result, and its descendants, to the statistics
+fn estimate_node(f: &Forest, n: Node, stats: &mut TransformStats) {
+    stats.nodes += 1;
+    match n.node_type(f) {
+        NodeType::Document => {}
+        NodeType::Element => {
+            // <name></name>
+            let name = n.to_name(f).to_string().len();
+            stats.output_size += 2 * name + 5;
+            let mut ait = n.attribute_iter(f);
+            while let Some(a) = ait.next() {
+                // name='value'
+                stats.nodes += 1;
+                stats.output_size += a.to_name(f).to_string().len() + a.to_string(f).len() + 4
+            }
+        }
+        NodeType::Comment => stats.output_size += n.to_string(f).len() + 7,
+        NodeType::ProcessingInstruction => {
+            stats.output_size += n.to_name(f).to_string().len() + n.to_string(f).len() + 5
+        }
+        _ => stats.output_size += n.to_string(f).len(),
+    }
+    if matches!(n.node_type(f), NodeType::Document | NodeType::Element) {
+        let mut cit = n.child_iter();
+        while let Some(c) = cit.next(f) {
+            estimate_node(f, c, stats)
+        }
+    }
+}
+
 /// A sequence constructor evaluator.
 /// This interprets the sequence constructor to produce a sequence.
 /// IDEA: make the evaluate method an iterator, emitting one sequence item at a time
@@ -129,7 +527,19 @@ pub struct Evaluator {
     templates: Vec<Template>,
     builtin_templates: Vec<Template>, // TODO: use import precedence for builtins
     od: OutputDefinition,             // Output definition for the final result tree
+    named_od: HashMap<QualifiedName, OutputDefinition>, // Named output definitions
     base: Option<Url>,                // The base URL of the primary stylesheet
+    resolver: Option<Rc<dyn Resolver>>, // Fetches secondary source documents
+    debugger: Option<Rc<RefCell<dyn Debugger>>>,
+    breakpoints: HashSet<String>, // Match patterns of templates to stop at
+    keys: HashMap<QualifiedName, Vec<Key>>, // Key declarations, by name
+    functions: HashSet<String>,   // The functions that are available, for function-available()
+}
+
+// A key declaration (XSLT 20.2.1). A key may have several declarations with the same name.
+struct Key {
+    pattern: Vec<Constructor>,
+    key_use: Vec<Constructor>,
 }
 
 impl Evaluator {
@@ -140,7 +550,13 @@ impl Evaluator {
             templates: Vec::new(),
             builtin_templates: Vec::new(),
             od: OutputDefinition::new(),
+            named_od: HashMap::new(),
             base: None,
+            resolver: None,
+            debugger: None,
+            breakpoints: HashSet::new(),
+            keys: HashMap::new(),
+            functions: HashSet::new(),
         }
     }
     pub fn from_dynamic_context(dc: DynamicContext) -> Evaluator {
@@ -149,7 +565,13 @@ impl Evaluator {
             templates: Vec::new(),
             builtin_templates: Vec::new(),
             od: OutputDefinition::new(),
+            named_od: HashMap::new(),
             base: None,
+            resolver: None,
+            debugger: None,
+            breakpoints: HashSet::new(),
+            keys: HashMap::new(),
+            functions: HashSet::new(),
         }
     }
 
@@ -161,6 +583,88 @@ impl Evaluator {
     pub fn set_baseurl(&mut self, url: Url) {
         self.base = Some(url);
     }
+    /// Set the [Resolver] used to fetch secondary source documents, for document() and unparsed-text(). Nothing is fetched until a resolver is set: use a [PolicyResolver](crate::resolvers::PolicyResolver) to control access, or the [DefaultResolver](crate::resolvers::DefaultResolver) for unrestricted access.
+    pub fn set_resolver(&mut self, r: Rc<dyn Resolver>) {
+        self.resolver = Some(r);
+    }
+    /// Add a declaration for the key with the given name. Nodes that match the pattern are indexed by the string value of each item that the key_use constructor returns, with the node as the context item.
+    pub fn add_key(
+        &mut self,
+        name: QualifiedName,
+        pattern: Vec<Constructor>,
+        key_use: Vec<Constructor>,
+    ) {
+        self.keys
+            .entry(name)
+            .or_default()
+            .push(Key { pattern, key_use });
+    }
+    /// Record the functions that are defined in the static context, so that function-available() can report them. Compiling a stylesheet does this. If it is not done then function-available() reports the builtin XSLT functions.
+    pub fn set_available_functions(&mut self, sc: &StaticContext) {
+        self.functions = sc.funcs.borrow().keys().cloned().collect();
+    }
+    /// Set the [Debugger] that is called as templates are evaluated.
+    pub fn set_debugger(&mut self, d: Rc<RefCell<dyn Debugger>>) {
+        self.debugger = Some(d);
+    }
+    /// Stop in the debugger whenever a template with the given match pattern is evaluated. The pattern must be the same as the template's match attribute.
+    pub fn add_breakpoint(&mut self, pattern: &str) {
+        self.breakpoints.insert(pattern.to_string());
+    }
+    /// Remove a breakpoint. Returns whether there was a breakpoint for the pattern.
+    pub fn remove_breakpoint(&mut self, pattern: &str) -> bool {
+        self.breakpoints.remove(pattern)
+    }
+    /// Set how deeply templates may be nested, i.e. how deep the recursion of xsl:apply-templates may go. Evaluation fails with [ErrorKind::RecursionLimit] rather than exhausting the stack when the limit is reached.
+    ///
+    /// The default is [DEFAULT_MAX_DEPTH]. The stack is extended on the heap as evaluation goes deeper, so a deep document does not overflow the thread's stack; the limit stops runaway recursion before it exhausts memory. Use usize::MAX for no limit.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.dc.max_depth.set(depth)
+    }
+    /// How deeply templates may be nested.
+    pub fn max_depth(&self) -> usize {
+        self.dc.max_depth.get()
+    }
+    // Forget what is known about trees that have been removed from the forest. See [Forest::truncate].
+    #[cfg(feature = "xslt")]
+    pub(crate) fn forget_trees(&self, n: TreeIndex) {
+        self.dc.forget_trees(n)
+    }
+    /// Set the largest sequence that a range expression, such as 1 to 1000, may construct. Evaluation fails with [ErrorKind::SizeLimit] rather than exhausting memory when a larger sequence is needed.
+    ///
+    /// The default is [DEFAULT_MAX_RANGE]. A range is not constructed when a for expression iterates over it, or when it is the argument of fn:count or the first argument of fn:subsequence, so the limit does not apply then (although it does apply to the result of fn:subsequence).
+    pub fn set_max_range(&mut self, n: usize) {
+        self.dc.max_range.set(n)
+    }
+    /// The largest sequence that a range expression may construct.
+    pub fn max_range(&self) -> usize {
+        self.dc.max_range.get()
+    }
+    /// The dynamic context.
+    pub fn dynamic_context(&self) -> &DynamicContext {
+        &self.dc
+    }
+    /// Record, or stop recording, the [Origin] of each node that is constructed in the result tree. Enabling the source map clears any origins that were recorded previously.
+    pub fn set_source_map(&mut self, enable: bool) {
+        *self.dc.origins.borrow_mut() = enable.then(HashMap::new);
+    }
+    /// Where a node in the result tree came from, if the source map has been recorded.
+    pub fn origin(&self, n: &Node) -> Option<Origin> {
+        self.dc
+            .origins
+            .borrow()
+            .as_ref()
+            .and_then(|o| o.get(n).cloned())
+    }
+    /// The result nodes whose [Origin] has the given source node, in no particular order.
+    pub fn derived_from(&self, source: &Node) -> Vec<Node> {
+        self.dc.origins.borrow().as_ref().map_or(vec![], |o| {
+            o.iter()
+                .filter(|(_, v)| v.source.as_ref() == Some(source))
+                .map(|(k, _)| *k)
+                .collect()
+        })
+    }
 
     /// Add a template to the dynamic context. The first argument is the pattern. The second argument is the body of the template. The third argument is the mode. The fourth argument is the priority. The fifth argument is the import precedence.
     pub fn add_template(
@@ -177,6 +681,27 @@ impl Evaluator {
             mode: m,
             priority: pr,
             import: im,
+            source: None,
+        });
+    }
+    // Add a template that was compiled from a stylesheet. The source is the text of the match attribute.
+    #[cfg(feature = "xslt")]
+    pub(crate) fn add_template_from_source(
+        &mut self,
+        p: Vec<Constructor>,
+        b: Vec<Constructor>,
+        m: Option<String>,
+        pr: f64,
+        im: usize,
+        source: String,
+    ) {
+        self.templates.push(Template {
+            pattern: p,
+            body: b,
+            mode: m,
+            priority: pr,
+            import: im,
+            source: Some(source),
         });
     }
     /// Add a template to the set of builtin templates in the dynamic context. See above for arguments.
@@ -194,11 +719,13 @@ impl Evaluator {
             mode: m,
             priority: pr,
             import: im,
+            source: None,
         });
     }
     /// Determine if an item matches a pattern and return the highest priority sequence constructor for that template.
     /// If import precedence is None, then return the lowest import precedence. Otherwise return the matching template with the highest priority that has an imoprt precedence higher than the given value.
     /// If no template is found, returns None.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
     pub fn find_match(
         &self,
         i: &Rc<Item>,
@@ -260,6 +787,16 @@ impl Evaluator {
     pub fn set_output_definition(&mut self, od: OutputDefinition) {
         self.od = od;
     }
+    /// Find the output definition with the given name, i.e. from a named xsl:output declaration.
+    pub fn get_named_output_definition(&self, name: &QualifiedName) -> Option<OutputDefinition> {
+        self.named_od.get(name).cloned()
+    }
+    /// Add an output definition that can be retrieved by its name. An output definition that does not have a name is ignored.
+    pub fn add_named_output_definition(&mut self, od: OutputDefinition) {
+        if let Some(n) = od.get_name() {
+            self.named_od.insert(n, od);
+        }
+    }
 
     // Printout templates, for debugging.
     pub fn dump_templates(&self) {
@@ -287,11 +824,70 @@ impl Evaluator {
 	)
     }
 
+    /// Evaluate a sequence constructor, as for [Evaluator::evaluate], but return statistics about the result instead of the result itself.
+    ///
+    /// The result is constructed, but not serialised. This allows a quota on the size of the output to be enforced before it is produced.
+    pub fn dry_run(
+        &self,
+        ctxt: Option<Sequence>,
+        posn: Option<usize>,
+        c: &Vec<Constructor>,
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+    ) -> Result<TransformStats, Error> {
+        let fired = *self.dc.fired.borrow();
+        let seq = self.evaluate(ctxt, posn, c, f, sd, rd)?;
+        let mut stats = TransformStats {
+            templates_fired: *self.dc.fired.borrow() - fired,
+            ..Default::default()
+        };
+        for (k, i) in seq.iter().enumerate() {
+            match &**i {
+                Item::Node(n) => estimate_node(f, *n, &mut stats),
+                Item::Value(v) => {
+                    stats.items += 1;
+                    // Adjacent atomic values are separated by a space
+                    if k > 0 && matches!(&*seq[k - 1], Item::Value(_)) {
+                        stats.output_size += 1
+                    }
+                    stats.output_size += v.to_string().len()
+                }
+                _ => stats.items += 1,
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Evaluate a sequence constructor, as [Evaluator::evaluate] does, fetching the resources used by the document() and unparsed-text() functions with an [AsyncResolver] instead of the evaluator's resolver.
+    ///
+    /// Resources are fetched as they are found to be needed, and the sequence constructor is evaluated again once they have been fetched, so it may be evaluated more than once. Documents that have been loaded are kept, so each resource is only fetched once.
+    #[cfg(feature = "async")]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn evaluate_async<R: AsyncResolver>(
+        &mut self,
+        ctxt: Option<Sequence>,
+        posn: Option<usize>,
+        c: &Vec<Constructor>,
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+        r: &R,
+    ) -> Result<Sequence, Error> {
+        let p = Rc::new(Prefetched::new());
+        let previous = self.resolver.replace(p.clone());
+        let ev: &Evaluator = self;
+        let result = with_prefetch(&p, r, |_| ev.evaluate(ctxt.clone(), posn, c, f, sd, rd)).await;
+        self.resolver = previous;
+        result
+    }
+
     /// Evaluate a sequence constructor, given a dynamic context.
     ///
     /// The dynamic context consists of the supplied context, as well as the context item. The context item, which is optional, consists of a [Sequence] and an index to an item. If the context sequence is supplied, then the index (posn) must also be supplied and be a valid index for the sequence.
     ///
     /// Any nodes created by the sequence constructor are created in the result Tree.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(constructors = c.len())))]
     pub fn evaluate(
         &self,
         ctxt: Option<Sequence>,
@@ -305,9 +901,14 @@ impl Evaluator {
         // If an error occurs, propagate the first error (TODO: return all errors)
         // Otherwise, flatten the sequences into a single sequence
 
+        // Grow the stack if necessary, so that deep recursion cannot overflow it
         let (results, errors): (Vec<_>, Vec<_>) = c
             .iter()
-            .map(|a| self.evaluate_one(ctxt.clone(), posn, a, f, sd, rd))
+            .map(|a| {
+                stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+                    self.evaluate_one(ctxt.clone(), posn, a, f, sd, rd)
+                })
+            })
             .partition(Result::is_ok);
         if errors.len() != 0 {
             Result::Err(
@@ -329,6 +930,283 @@ impl Evaluator {
         }
     }
 
+    // Evaluate the values of variable declarations
+    fn declarations(
+        &self,
+        ctxt: Option<Sequence>,
+        posn: Option<usize>,
+        d: &[Constructor],
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+    ) -> Result<Vec<(String, Sequence)>, Error> {
+        let mut result = vec![];
+        for c in d {
+            if let Constructor::VariableDeclaration(v, a) = c {
+                let value = match a.as_slice() {
+                    [Constructor::Convert(e, t)] => {
+                        let s = self.evaluate(ctxt.clone(), posn, e, f, sd, rd)?;
+                        t.convert(s, f).map_err(|e| parameter_type_error(v, e))?
+                    }
+                    _ => self.evaluate(ctxt.clone(), posn, a, f, sd, rd)?,
+                };
+                result.push((v.clone(), value))
+            }
+        }
+        Ok(result)
+    }
+    // Bind the variables to each combination of the items of their values in turn, as in for and quantified expressions, and call g for each.
+    // Each variable is in scope for the later declarations. g returns false to stop; the result is false if it did.
+    #[allow(clippy::too_many_arguments)]
+    fn bindings(
+        &self,
+        ctxt: &Option<Sequence>,
+        posn: Option<usize>,
+        v: &[Constructor],
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+        g: &mut dyn FnMut(&mut Forest) -> Result<bool, Error>,
+    ) -> Result<bool, Error> {
+        match v.split_first() {
+            None => g(f),
+            Some((Constructor::VariableDeclaration(n, a), rest)) => {
+                let items: Box<dyn Iterator<Item = Rc<Item>>> = match a.as_slice() {
+                    // Iterate over a range of integers without constructing the whole sequence
+                    [Constructor::Range(r)] => {
+                        match self.range(ctxt.clone(), posn, r, f, sd, rd)? {
+                            Some(k) => Box::new(k.map(|i| Rc::new(Item::Value(Value::Integer(i))))),
+                            None => Box::new(std::iter::empty()),
+                        }
+                    }
+                    _ => Box::new(self.evaluate(ctxt.clone(), posn, a, f, sd, rd)?.into_iter()),
+                };
+                for i in items {
+                    self.dc.var_push(n, vec![i]);
+                    let r = self.bindings(ctxt, posn, rest, f, sd, rd, g);
+                    self.dc.var_pop(n);
+                    if !r? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Some(_) => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("not a variable binding"),
+            )),
+        }
+    }
+    // Evaluate a value template. The items of each operand are separated by a space.
+    fn value_template(
+        &self,
+        ctxt: Option<Sequence>,
+        posn: Option<usize>,
+        v: &[Vec<Constructor>],
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+    ) -> Result<Sequence, Error> {
+        let mut r = String::new();
+        for u in v {
+            let t = self.evaluate(ctxt.clone(), posn, u, f, sd, rd)?;
+            let s: Vec<String> = t.iter().map(|i| i.to_string(Some(f))).collect();
+            r.push_str(s.join(" ").as_str());
+        }
+        let mut seq = Sequence::new();
+        seq.push_value(Value::from(r));
+        Ok(seq)
+    }
+    // Evaluate the operands of a range expression. The result is None if the range is empty.
+    fn range(
+        &self,
+        ctxt: Option<Sequence>,
+        posn: Option<usize>,
+        v: &[Vec<Constructor>],
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+    ) -> Result<Option<RangeInclusive<i64>>, Error> {
+        match v {
+            [s, e] => {
+                let start = self.evaluate(ctxt.clone(), posn, s, f, sd, rd)?;
+                let end = self.evaluate(ctxt, posn, e, f, sd, rd)?;
+                match (range_operand(&start, f)?, range_operand(&end, f)?) {
+                    (Some(i), Some(j)) if i <= j => Ok(Some(i..=j)),
+                    _ => Ok(None),
+                }
+            }
+            _ => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                "incorrect number of operands".to_string(),
+            )),
+        }
+    }
+    // Construct the sequence of integers in a range, provided it is not too large
+    fn range_sequence(&self, r: RangeInclusive<i64>) -> Result<Sequence, Error> {
+        let len = range_len(&r);
+        if len > self.dc.max_range.get() as i128 {
+            return Result::Err(Error::new(
+                ErrorKind::SizeLimit,
+                format!(
+                    "range expression {} to {} has {} items, more than the limit of {}",
+                    r.start(),
+                    r.end(),
+                    len,
+                    self.dc.max_range.get()
+                ),
+            ));
+        }
+        Ok(r.map(|k| Rc::new(Item::Value(Value::Integer(k)))).collect())
+    }
+    // Some functions of a range are computed without constructing the sequence of integers, which may be very large.
+    // Returns None if the call is not one of these.
+    #[allow(clippy::too_many_arguments)]
+    fn range_function(
+        &self,
+        ctxt: &Option<Sequence>,
+        posn: Option<usize>,
+        h: &Function,
+        a: &[Vec<Constructor>],
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+    ) -> Result<Option<Sequence>, Error> {
+        let r = match (h.body, a.first().map(|c| c.as_slice())) {
+            (Some(_), Some([Constructor::Range(r)])) => r,
+            _ => return Ok(None),
+        };
+        match (h.name.as_str(), a.len()) {
+            ("count", 1) => {
+                let n = self
+                    .range(ctxt.clone(), posn, r, f, sd, rd)?
+                    .map_or(0, |k| range_len(&k));
+                Ok(Some(vec![Rc::new(Item::Value(Value::Integer(
+                    i64::try_from(n).map_err(|_| {
+                        Error::new(
+                            ErrorKind::Unknown,
+                            String::from("FOAR0002: integer overflow"),
+                        )
+                    })?,
+                )))]))
+            }
+            ("subsequence", 2 | 3) => {
+                let k = self.range(ctxt.clone(), posn, r, f, sd, rd)?;
+                let start = self.evaluate(ctxt.clone(), posn, &a[1], f, sd, rd)?;
+                let length = match a.get(2) {
+                    Some(l) => Some(self.evaluate(ctxt.clone(), posn, l, f, sd, rd)?),
+                    None => None,
+                };
+                match k {
+                    Some(k) => {
+                        let p = subsequence_positions(
+                            range_len(&k),
+                            numeric_argument(&start)?,
+                            length.as_ref().map(numeric_argument).transpose()?,
+                        );
+                        if p.is_empty() {
+                            Ok(Some(vec![]))
+                        } else {
+                            // The positions are within the range, so these do not overflow
+                            let first = (*k.start() as i128 + p.start - 1) as i64;
+                            let last = (*k.start() as i128 + p.end - 2) as i64;
+                            self.range_sequence(first..=last).map(Some)
+                        }
+                    }
+                    None => Ok(Some(vec![])),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+    // Record how the current iteration of the innermost xsl:iterate ended
+    fn end_iteration(&self, how: Iteration) -> Result<(), Error> {
+        match self.dc.iterations.borrow_mut().last_mut() {
+            Some(i) => {
+                i.replace(how);
+                Ok(())
+            }
+            None => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("xsl:break or xsl:next-iteration is not within xsl:iterate"),
+            )),
+        }
+    }
+
+    // Evaluate the body of a template, reporting it to the debugger
+    fn evaluate_template(
+        &self,
+        t: &Template,
+        ctxt: Option<Sequence>,
+        posn: Option<usize>,
+        f: &mut Forest,
+        sd: TreeIndex,
+        rd: TreeIndex,
+    ) -> Result<Sequence, Error> {
+        self.dc.depth_incr(t)?;
+        let result = match (&self.debugger, ctxt.as_ref().zip(posn)) {
+            (Some(d), Some((c, p))) => {
+                self.debug_stop(d, t, &self.frame(t, &c[p], f))
+                    .and_then(|_| {
+                        let r = self.evaluate(ctxt.clone(), posn, &t.body, f, sd, rd)?;
+                        d.borrow_mut().leave(&self.frame(t, &c[p], f), &r);
+                        Ok(r)
+                    })
+            }
+            _ => self.evaluate(ctxt, posn, &t.body, f, sd, rd),
+        };
+        self.dc.depth_decr();
+        result
+    }
+    fn frame<'a>(
+        &'a self,
+        t: &'a Template,
+        context: &'a Rc<Item>,
+        forest: &'a Forest,
+    ) -> Frame<'a> {
+        Frame {
+            pattern: t.source.as_deref(),
+            mode: t.mode.as_deref(),
+            priority: t.priority,
+            depth: *self.dc.depth.borrow(),
+            context,
+            forest,
+            dc: &self.dc,
+        }
+    }
+    // Call the debugger, if the template has a breakpoint or is the next step
+    fn debug_stop(
+        &self,
+        d: &Rc<RefCell<dyn Debugger>>,
+        t: &Template,
+        frame: &Frame,
+    ) -> Result<(), Error> {
+        let mut d = d.borrow_mut();
+        d.enter(frame);
+        let breakpoint = t
+            .source
+            .as_ref()
+            .is_some_and(|s| self.breakpoints.contains(s));
+        if !breakpoint && self.dc.stop_at.get().is_none_or(|s| frame.depth > s) {
+            return Ok(());
+        }
+        let stop_at = match d.stop(frame) {
+            Step::Continue => None,
+            Step::Into => Some(usize::MAX),
+            Step::Over => Some(frame.depth),
+            Step::Out => Some(frame.depth - 1),
+            Step::Abort => {
+                self.dc.stop_at.set(None);
+                return Result::Err(Error::new(
+                    ErrorKind::Unknown,
+                    String::from("transformation stopped by the debugger"),
+                ));
+            }
+        };
+        self.dc.stop_at.set(stop_at);
+        Ok(())
+    }
+
     // Evaluate an item constructor, given a context
     // If a constructor returns a non-singleton sequence, then it is unpacked
     fn evaluate_one(
@@ -356,6 +1234,9 @@ impl Evaluator {
                         String::from("no result document"),
                     ))?
                     .new_element(n.clone())?;
+                let source = context_node(&ctxt, posn);
+                self.dc
+                    .record_origin(l, source, Instruction::LiteralElement);
 
                 // add content to newly created element
                 let seq = self.evaluate(ctxt.clone(), posn, c, f, sd, rd)?;
@@ -373,6 +1254,7 @@ impl Evaluator {
                                     String::from("no result document"),
                                 ))?
                                 .new_text(v)?;
+                            self.dc.record_origin(t, source, Instruction::Text);
                             l.append_child(f, t)
                         }
                     }
@@ -380,6 +1262,19 @@ impl Evaluator {
 
                 Ok(vec![Rc::new(Item::Node(l))])
             }
+            Constructor::UnescapedText(v) => {
+                let t = f
+                    .get_ref_mut(rd)
+                    .ok_or(Error::new(
+                        ErrorKind::Unknown,
+                        String::from("no result document"),
+                    ))?
+                    .new_text(v.clone())?;
+                t.set_user_data(f, DisableOutputEscaping)?;
+                self.dc
+                    .record_origin(t, context_node(&ctxt, posn), Instruction::Text);
+                Ok(vec![Rc::new(Item::Node(t))])
+            }
             // This creates a Node in the current result document
             Constructor::LiteralAttribute(n, v) => {
                 let w = self.evaluate(ctxt.clone(), posn, v, f, sd, rd)?;
@@ -391,6 +1286,8 @@ impl Evaluator {
                         String::from("no result document"),
                     ))?
                     .new_attribute(n.clone(), x)?;
+                self.dc
+                    .record_origin(l, context_node(&ctxt, posn), Instruction::LiteralAttribute);
                 Ok(vec![Rc::new(Item::Node(l))])
             }
             Constructor::Copy(i, c) => {
@@ -407,11 +1304,55 @@ impl Evaluator {
 
                 let mut results = Sequence::new();
                 for j in orig {
-                    let m = self.item_copy(j.clone(), c, ctxt.clone(), posn, f, sd, rd)?;
+                    let m = self.item_copy(
+                        j.clone(),
+                        c,
+                        Instruction::Copy,
+                        ctxt.clone(),
+                        posn,
+                        f,
+                        sd,
+                        rd,
+                    )?;
                     results.push(m);
                 }
                 Ok(results)
             }
+            Constructor::InstanceOf(e, t) => {
+                let seq = self.evaluate(ctxt.clone(), posn, e, f, sd, rd)?;
+                Ok(vec![Rc::new(Item::Value(Value::from(t.matches(&seq, f))))])
+            }
+            Constructor::Treat(e, t) => {
+                let seq = self.evaluate(ctxt.clone(), posn, e, f, sd, rd)?;
+                if t.matches(&seq, f) {
+                    Ok(seq)
+                } else {
+                    Result::Err(Error::new(
+                        ErrorKind::TypeError,
+                        format!("sequence does not match the type {}", t),
+                    ))
+                }
+            }
+            Constructor::Convert(e, t) => {
+                let seq = self.evaluate(ctxt.clone(), posn, e, f, sd, rd)?;
+                t.convert(seq, f)
+            }
+            Constructor::Cast(e, t, empty) => {
+                let seq = self.evaluate(ctxt.clone(), posn, e, f, sd, rd)?;
+                match cast_operand(&seq, *empty, f)? {
+                    Some(v) => Ok(vec![Rc::new(Item::Value(t.cast(&v)?))]),
+                    None => Ok(vec![]),
+                }
+            }
+            Constructor::Castable(e, t, empty) => {
+                let seq = self.evaluate(ctxt.clone(), posn, e, f, sd, rd)?;
+                let castable = match cast_operand(&seq, *empty, f) {
+                    Ok(Some(v)) => t.cast(&v).is_ok(),
+                    Ok(None) => true,
+                    Err(_) => false,
+                };
+                Ok(vec![Rc::new(Item::Value(Value::from(castable)))])
+            }
             // Does the same as identity stylesheet template
             Constructor::DeepCopy(sel) => {
                 let orig = self.evaluate(ctxt.clone(), posn, sel, f, sd, rd)?;
@@ -446,41 +1387,26 @@ impl Evaluator {
                             match nd.node_type(f) {
                                 NodeType::Element => {
                                     let attval = self.evaluate(ctxt.clone(), posn, v, f, sd, rd)?;
-                                    if attval.len() == 1 {
-                                        match &*attval[0] {
-                                            Item::Value(av) => {
-                                                let atnode = f
-                                                    .get_ref_mut(rd)
-                                                    .ok_or(Error::new(
-                                                        ErrorKind::Unknown,
-                                                        String::from("no result document"),
-                                                    ))?
-                                                    .new_attribute(n.clone(), av.clone())?;
-                                                nd.add_attribute(f, atnode)?
-                                            }
-                                            _ => {
-                                                let w = Value::from(attval.to_string(Some(f)));
-                                                let atnode = f
-                                                    .get_ref_mut(rd)
-                                                    .ok_or(Error::new(
-                                                        ErrorKind::Unknown,
-                                                        String::from("no result document"),
-                                                    ))?
-                                                    .new_attribute(n.clone(), w)?;
-                                                nd.add_attribute(f, atnode)?
-                                            }
-                                        }
-                                    } else {
-                                        let w = Value::from(attval.to_string(Some(f)));
-                                        let atnode = f
-                                            .get_ref_mut(rd)
-                                            .ok_or(Error::new(
-                                                ErrorKind::Unknown,
-                                                String::from("no result document"),
-                                            ))?
-                                            .new_attribute(n.clone(), w)?;
-                                        nd.add_attribute(f, atnode)?
-                                    }
+                                    let w = match attval.as_slice() {
+                                        [i] => match &**i {
+                                            Item::Value(av) => av.clone(),
+                                            _ => Value::from(attval.to_string(Some(f))),
+                                        },
+                                        _ => Value::from(attval.to_string(Some(f))),
+                                    };
+                                    let atnode = f
+                                        .get_ref_mut(rd)
+                                        .ok_or(Error::new(
+                                            ErrorKind::Unknown,
+                                            String::from("no result document"),
+                                        ))?
+                                        .new_attribute(n.clone(), w)?;
+                                    self.dc.record_origin(
+                                        atnode,
+                                        Some(*nd),
+                                        Instruction::SetAttribute,
+                                    );
+                                    nd.add_attribute(f, atnode)?;
                                     Ok(vec![])
                                 }
                                 _ => Result::Err(Error {
@@ -569,85 +1495,52 @@ impl Evaluator {
                 seq.push_value(Value::from(r));
                 Ok(seq)
             }
+            Constructor::ValueTemplate(v) => self.value_template(ctxt, posn, v, f, sd, rd),
             Constructor::Range(v) => {
-                if v.len() == 2 {
-                    // Evaluate the two operands: they must both be literal integer items
-                    let start = self.evaluate(ctxt.clone(), posn, &v[0], f, sd, rd)?;
-                    let end = self.evaluate(ctxt.clone(), posn, &v[1], f, sd, rd)?;
-                    if start.len() == 0 || end.len() == 0 {
-                        // empty sequence is the result
-                        Ok(vec![])
-                    } else if start.len() == 1 {
-                        if end.len() == 1 {
-                            let i = start[0].to_int().unwrap();
-                            let j = end[0].to_int().unwrap();
-                            if i > j {
-                                // empty sequence result
-                                Ok(vec![])
-                            } else if i == j {
-                                let mut seq = Sequence::new();
-                                seq.push_value(Value::Integer(i));
-                                Ok(seq)
-                            } else {
-                                let mut result = Sequence::new();
-                                for k in i..=j {
-                                    result.push_value(Value::from(k))
-                                }
-                                Ok(result)
-                            }
-                        } else {
-                            Result::Err(Error {
-                                kind: ErrorKind::Unknown,
-                                message: String::from("end operand must be singleton"),
-                            })
-                        }
-                    } else {
-                        Result::Err(Error {
-                            kind: ErrorKind::Unknown,
-                            message: String::from("start operand must be singleton"),
-                        })
-                    }
-                } else {
-                    Result::Err(Error {
-                        kind: ErrorKind::Unknown,
-                        message: "incorrect number of operands".to_string(),
-                    })
+                // The sequence is only constructed when it is needed; a for expression iterates over the range directly
+                match self.range(ctxt, posn, v, f, sd, rd)? {
+                    Some(r) => self.range_sequence(r),
+                    None => Ok(vec![]),
                 }
             }
             Constructor::Arithmetic(v) => {
-                // Type: the result will be a number, but integer or double?
-                // If all of the operands are integers, then the result is integer otherwise double
-                // TODO: check the type of all operands to determine type of result (can probably do this in static analysis phase)
-                // In the meantime, let's assume the result will be double and convert any integers
-
-                let mut acc: f64 = 0.0;
+                // Type: if both operands of an operator are integers then the result is an integer, otherwise it is a double.
+                // TODO: division of integers should give a decimal
+                let mut acc = Value::from(0.0);
 
                 for j in v {
                     let k = self.evaluate(ctxt.clone(), posn, &j.operand, f, sd, rd)?;
-                    let u: f64;
                     if k.len() != 1 {
                         return Result::Err(Error {
                             kind: ErrorKind::TypeError,
                             message: String::from("type error (not a singleton sequence)"),
                         });
-                    } else {
-                        u = k[0].to_double();
-                        match j.op {
-                            ArithmeticOperator::Noop => acc = u,
-                            ArithmeticOperator::Add => acc += u,
-                            ArithmeticOperator::Subtract => acc -= u,
-                            ArithmeticOperator::Multiply => acc *= u,
-                            ArithmeticOperator::Divide => acc /= u,
-                            ArithmeticOperator::IntegerDivide => acc /= u, // TODO: convert to integer
-                            ArithmeticOperator::Modulo => acc = acc % u,
+                    }
+                    let u = match &*k[0] {
+                        Item::Value(u) if AtomicType::Integer.matches(u) => {
+                            Value::from(u.to_int()?)
                         }
+                        i => Value::from(i.to_double()),
+                    };
+                    acc = match j.op {
+                        ArithmeticOperator::Noop => u,
+                        _ => arithmetic(&j.op, &acc, &u)?,
                     }
                 }
                 let mut seq = Sequence::new();
-                seq.push_value(Value::from(acc));
+                seq.push_value(acc);
                 Ok(seq)
             }
-            Constructor::Root => match f.get_ref(sd) {
+            Constructor::Root => match f.get_ref(
+                // The root of the tree containing the context node, which may be a secondary source document
+                match ctxt.as_ref().and_then(|c| posn.and_then(|p| c.get(p))) {
+                    Some(i) => match **i {
+                        Item::Node(n) => n.tree_index(),
+                        _ => sd,
+                    },
+                    None => sd,
+                },
+            ) {
                 Some(d) => Ok(vec![Rc::new(Item::Node(d.get_doc_node()))]),
                 _ => Result::Err(Error {
                     kind: ErrorKind::ContextNotNode,
@@ -667,20 +1560,16 @@ impl Evaluator {
                     u = vec![]
                 }
 
-                // TODO: Don't Panic
-                let result = s.iter().fold(u, |a, c| {
+                s.iter().try_fold(u, |a, c| {
                     // evaluate this step for each item in the context
                     // Add the result of each evaluation to an accummulator sequence
                     let mut b: Sequence = Vec::new();
                     for i in 0..a.len() {
-                        let mut d = self
-                            .evaluate(Some(a.clone()), Some(i), c, f, sd, rd)
-                            .expect("failed to evaluate step");
+                        let mut d = self.evaluate(Some(a.clone()), Some(i), c, f, sd, rd)?;
                         b.append(&mut d);
                     }
-                    b
-                });
-                Ok(result)
+                    Ok(b)
+                })
             }
             Constructor::Step(nm, p) => {
                 // For this step to be valid the source document must not be None
@@ -692,7 +1581,7 @@ impl Evaluator {
                         Item::Node(n) => {
                             match nm.axis {
                                 Axis::Selfaxis => {
-                                    if is_node_match(&nm.nodetest, &n, f) {
+                                    if nm.nodetest.matches(f, n) {
                                         let mut seq = Sequence::new();
                                         seq.push_node(*n);
                                         Ok(self.predicates(seq, p, f, sd, rd)?)
@@ -706,7 +1595,7 @@ impl Evaluator {
                                     loop {
                                         match it.next(f) {
                                             Some(c) => {
-                                                if is_node_match(&nm.nodetest, &c, f) {
+                                                if nm.nodetest.matches(f, &c) {
                                                     seq.push_node(c)
                                                 }
                                             }
@@ -744,12 +1633,15 @@ impl Evaluator {
                                     }
                                 }
                                 Axis::Descendant => {
+                                    if let Some(seq) = indexed_descendants(f, *n, nm, p, false) {
+                                        return self.predicates(seq, p, f, sd, rd);
+                                    }
                                     let mut seq = Sequence::new();
                                     let mut it = n.descend_iter(f);
                                     loop {
                                         match it.next(f) {
                                             Some(c) => {
-                                                if is_node_match(&nm.nodetest, &c, f) {
+                                                if nm.nodetest.matches(f, &c) {
                                                     seq.push_node(c)
                                                 }
                                             }
@@ -764,15 +1656,18 @@ impl Evaluator {
                                     Ok(self.predicates(seq, p, f, sd, rd)?)
                                 }
                                 Axis::DescendantOrSelf => {
+                                    if let Some(seq) = indexed_descendants(f, *n, nm, p, true) {
+                                        return self.predicates(seq, p, f, sd, rd);
+                                    }
                                     let mut seq = Sequence::new();
-                                    if is_node_match(&nm.nodetest, &n, f) {
+                                    if nm.nodetest.matches(f, n) {
                                         seq.push_item(&Rc::new(Item::Node(*n)));
                                     }
                                     let mut it = n.descend_iter(f);
                                     loop {
                                         match it.next(f) {
                                             Some(c) => {
-                                                if is_node_match(&nm.nodetest, &c, f) {
+                                                if nm.nodetest.matches(f, &c) {
                                                     seq.push_node(c)
                                                 }
                                             }
@@ -789,49 +1684,33 @@ impl Evaluator {
 
                                     Ok(self.predicates(seq, p, f, sd, rd)?)
                                 }
-                                Axis::Ancestor => {
-                                    let mut seq = Sequence::new();
-                                    let mut it = n.ancestor_iter();
-                                    loop {
-                                        match it.next(f) {
-                                            Some(a) => {
-                                                if is_node_match(&nm.nodetest, &a, f) {
-                                                    seq.push_node(a)
-                                                }
-                                            }
-                                            None => break,
-                                        }
-                                    }
-
-                                    //			      let seq = n.ancestors().iter()
-                                    //				  .filter(|p| is_node_match(&nm.nodetest, &p))
-                                    //				  .fold(Sequence::new(), |mut c, a| {c.new_node(Rc::clone(a)); c});
-
-                                    Ok(self.predicates(seq, p, f, sd, rd)?)
-                                }
-                                Axis::AncestorOrSelf => {
+                                Axis::Ancestor
+                                | Axis::AncestorOrSelf
+                                | Axis::Following
+                                | Axis::Preceding
+                                | Axis::PrecedingSibling => {
+                                    // Positions in the predicates count in the direction of the axis,
+                                    // i.e. nearest first for the reverse axes
+                                    let axis = match nm.axis {
+                                        Axis::Ancestor => axes::Axis::Ancestor,
+                                        Axis::AncestorOrSelf => axes::Axis::AncestorOrSelf,
+                                        Axis::Following => axes::Axis::Following,
+                                        Axis::Preceding => axes::Axis::Preceding,
+                                        _ => axes::Axis::PrecedingSibling,
+                                    };
                                     let mut seq = Sequence::new();
-                                    let mut it = n.ancestor_iter();
-                                    loop {
-                                        match it.next(f) {
-                                            Some(a) => {
-                                                if is_node_match(&nm.nodetest, &a, f) {
-                                                    seq.push_node(a)
-                                                }
-                                            }
-                                            None => break,
+                                    let mut it = AxisIter::new(f, *n, axis);
+                                    while let Some(a) = it.next(f) {
+                                        if nm.nodetest.matches(f, &a) {
+                                            seq.push_node(a)
                                         }
                                     }
-
-                                    //			      let mut seq = n.ancestors().iter()
-                                    //				  .filter(|c| is_node_match(&nm.nodetest, &c))
-                                    //				  .fold(Sequence::new(), |mut c, a| {c.new_node(Rc::clone(a)); c});
-
-                                    if is_node_match(&nm.nodetest, &n, f) {
-                                        seq.push_item(&Rc::new(Item::Node(*n)));
+                                    let mut result = self.predicates(seq, p, f, sd, rd)?;
+                                    if axis.is_reverse() {
+                                        // The result of the step is in document order
+                                        result.reverse()
                                     }
-
-                                    Ok(self.predicates(seq, p, f, sd, rd)?)
+                                    Ok(result)
                                 }
                                 Axis::FollowingSibling => {
                                     let mut seq = Sequence::new();
@@ -839,7 +1718,7 @@ impl Evaluator {
                                     loop {
                                         match it.next(f) {
                                             Some(g) => {
-                                                if is_node_match(&nm.nodetest, &g, f) {
+                                                if nm.nodetest.matches(f, &g) {
                                                     seq.push_node(g)
                                                 }
                                             }
@@ -853,176 +1732,13 @@ impl Evaluator {
 
                                     Ok(self.predicates(seq, p, f, sd, rd)?)
                                 }
-                                Axis::PrecedingSibling => {
-                                    let mut seq = Sequence::new();
-                                    let mut it = n.prev_iter(f);
-                                    loop {
-                                        match it.next(f) {
-                                            Some(g) => {
-                                                if is_node_match(&nm.nodetest, &g, f) {
-                                                    seq.push_node(g)
-                                                }
-                                            }
-                                            None => break,
-                                        }
-                                    }
-
-                                    //			      let seq = n.preceding_siblings().iter()
-                                    //				  .filter(|c| is_node_match(&nm.nodetest, &c))
-                                    //				  .fold(Sequence::new(), |mut c, a| {c.new_node(Rc::clone(a)); c});
-
-                                    Ok(self.predicates(seq, p, f, sd, rd)?)
-                                }
-                                Axis::Following => {
-                                    // XPath 3.3.2.1: the following axis contains all nodes that are descendants of the root of the tree in which the context node is found, are not descendants of the context node, and occur after the context node in document order.
-                                    // iow, for each ancestor-or-self node, include every next sibling and its descendants
-
-                                    let mut d: Vec<Node> = Vec::new();
-
-                                    // Start with following siblings of self
-                                    let mut fit = n.next_iter(f);
-                                    loop {
-                                        match fit.next(f) {
-                                            Some(a) => {
-                                                d.push(a);
-                                                let mut dit = a.descend_iter(f);
-                                                loop {
-                                                    match dit.next(f) {
-                                                        Some(c) => d.push(c),
-                                                        None => break,
-                                                    }
-                                                }
-                                            }
-                                            None => break,
-                                        }
-                                    }
-                                    //			      for a in n.following_siblings() {
-                                    //				  d.push(a.clone());
-                                    //				  let mut b = a.descendants();
-                                    //				  d.append(&mut b);
-                                    //			      }
-
-                                    // Now traverse ancestors
-                                    let mut ait = n.ancestor_iter();
-                                    loop {
-                                        match ait.next(f) {
-                                            Some(a) => {
-                                                let mut sit = a.next_iter(f);
-                                                loop {
-                                                    match sit.next(f) {
-                                                        Some(b) => {
-                                                            d.push(b);
-                                                            let mut dit = b.descend_iter(f);
-                                                            loop {
-                                                                match dit.next(f) {
-                                                                    Some(e) => d.push(e),
-                                                                    None => break,
-                                                                }
-                                                            }
-                                                        }
-                                                        None => break,
-                                                    }
-                                                }
-                                            }
-                                            None => break,
-                                        }
-                                    }
-                                    //			      for a in anc {
-                                    //				  let sibs: Vec<Node> = a.following_siblings();
-                                    //				  for b in sibs {
-                                    //				      d.push(b.clone());
-                                    //				      let mut sib_descs: Vec<Node> = b.descendants();
-                                    //				      d.append(&mut sib_descs)
-                                    //				  }
-                                    //			      }
-                                    let seq = d
-                                        .iter()
-                                        .filter(|e| is_node_match(&nm.nodetest, &e, f))
-                                        .fold(Sequence::new(), |mut h, g| {
-                                            h.push_node(*g);
-                                            h
-                                        });
-                                    Ok(self.predicates(seq, p, f, sd, rd)?)
-                                }
-                                Axis::Preceding => {
-                                    // XPath 3.3.2.1: the preceding axis contains all nodes that are descendants of the root of the tree in which the context node is found, are not ancestors of the context node, and occur before the context node in document order.
-                                    // iow, for each ancestor-or-self node, include every previous sibling and its descendants
-
-                                    let mut d: Vec<Node> = Vec::new();
-
-                                    // Start with preceding siblings of self
-                                    let mut pit = n.prev_iter(f);
-                                    loop {
-                                        match pit.next(f) {
-                                            Some(a) => {
-                                                d.push(a);
-                                                let mut dit = a.descend_iter(f);
-                                                loop {
-                                                    match dit.next(f) {
-                                                        Some(b) => d.push(b),
-                                                        None => break,
-                                                    }
-                                                }
-                                            }
-                                            None => break,
-                                        }
-                                    }
-                                    //			      for a in n.preceding_siblings() {
-                                    //				  d.push(a.clone());
-                                    //				  let mut b = a.descendants();
-                                    //				  d.append(&mut b);
-                                    //			      }
-
-                                    // Now traverse ancestors
-                                    let mut ait = n.ancestor_iter();
-                                    loop {
-                                        match ait.next(f) {
-                                            Some(a) => {
-                                                let mut pit = a.prev_iter(f);
-                                                loop {
-                                                    match pit.next(f) {
-                                                        Some(b) => {
-                                                            d.push(b);
-                                                            let mut dit = b.descend_iter(f);
-                                                            loop {
-                                                                match dit.next(f) {
-                                                                    Some(c) => d.push(c),
-                                                                    None => break,
-                                                                }
-                                                            }
-                                                        }
-                                                        None => break,
-                                                    }
-                                                }
-                                            }
-                                            None => break,
-                                        }
-                                    }
-                                    //			      let anc: Vec<Node> = n.ancestors();
-                                    //			      for a in anc {
-                                    //				  let sibs: Vec<Node> = a.preceding_siblings();
-                                    //				  for b in sibs {
-                                    //				      d.push(b.clone());
-                                    //				      let mut sib_descs: Vec<Node> = b.descendants();
-                                    //				      d.append(&mut sib_descs)
-                                    //				  }
-                                    //			      }
-                                    let seq = d
-                                        .iter()
-                                        .filter(|e| is_node_match(&nm.nodetest, &e, f))
-                                        .fold(Sequence::new(), |mut h, g| {
-                                            h.push_node(*g);
-                                            h
-                                        });
-                                    Ok(self.predicates(seq, p, f, sd, rd)?)
-                                }
                                 Axis::Attribute => {
                                     let mut atit = n.attribute_iter(f);
                                     let mut attrs = Sequence::new();
                                     loop {
                                         match atit.next() {
                                             Some(a) => {
-                                                if is_node_match(&nm.nodetest, &a, f) {
+                                                if nm.nodetest.matches(f, &a) {
                                                     attrs.push_node(a)
                                                 }
                                             }
@@ -1067,6 +1783,9 @@ impl Evaluator {
                 }
             }
             Constructor::FunctionCall(h, a) => {
+                if let Some(s) = self.range_function(&ctxt, posn, h, a, f, sd, rd)? {
+                    return Ok(s);
+                }
                 match h.body {
                     Some(g) => {
                         // Evaluate the arguments
@@ -1079,8 +1798,8 @@ impl Evaluator {
                         Ok(g(&self, ctxt, posn, b, f, sd, rd)?)
                     }
                     None => Result::Err(Error {
-                        kind: ErrorKind::NotImplemented,
-                        message: format!("call to undefined function \"{}\"", h.name),
+                        kind: ErrorKind::StaticBadFunction,
+                        message: format!("XPST0017: call to unknown function \"{}\"", h.name),
                     }),
                 }
             }
@@ -1114,12 +1833,7 @@ impl Evaluator {
                 }),
             },
             Constructor::Loop(v, b) => {
-                // TODO: this supports only one variable binding - need to support more than one binding
-                // Evaluate the variable value
-                // Iterate over the items in the sequence
-                // Set the variable value to the item
-                // Evaluate the body, collecting the results
-
+                // Evaluate the body for each combination of the items of the variables' values, collecting the results
                 if v.is_empty() {
                     Result::Err(Error {
                         kind: ErrorKind::Unknown,
@@ -1127,25 +1841,261 @@ impl Evaluator {
                     })
                 } else {
                     let mut result: Sequence = vec![];
-                    match &v[0] {
-                        Constructor::VariableDeclaration(v, a) => {
-                            let s = self.evaluate(ctxt.clone(), posn, &a, f, sd, rd)?;
-
-                            for i in s {
-                                // Push the new value for this variable
-                                self.dc.var_push(v, vec![i]);
-                                let mut x = self.evaluate(ctxt.clone(), posn, b, f, sd, rd)?;
-                                result.append(&mut x);
-                                // Pop the value for this variable
-                                self.dc.var_pop(v);
+                    self.bindings(&ctxt, posn, v, f, sd, rd, &mut |f| {
+                        let mut x = self.evaluate(ctxt.clone(), posn, b, f, sd, rd)?;
+                        result.append(&mut x);
+                        Ok(true)
+                    })?;
+                    Ok(result)
+                }
+            }
+            Constructor::Let(v, b) => {
+                let mut bound = vec![];
+                let mut r = Ok(());
+                for d in v {
+                    if let Constructor::VariableDeclaration(n, a) = d {
+                        match self.evaluate(ctxt.clone(), posn, a, f, sd, rd) {
+                            Ok(s) => {
+                                self.dc.var_push(n, s);
+                                bound.push(n)
+                            }
+                            Err(e) => {
+                                r = Err(e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                let result = r.and_then(|_| self.evaluate(ctxt, posn, b, f, sd, rd));
+                // The variables go out of scope, whether or not the body succeeded
+                bound.iter().for_each(|n| self.dc.var_pop(n));
+                result
+            }
+            Constructor::SetOperation(o, v) => {
+                let mut operands: Vec<Vec<Node>> = vec![];
+                for c in v {
+                    let mut nodes = vec![];
+                    for i in self.evaluate(ctxt.clone(), posn, c, f, sd, rd)? {
+                        match *i {
+                            Item::Node(n) => nodes.push(n),
+                            _ => {
+                                return Result::Err(Error::new(
+                                    ErrorKind::TypeError,
+                                    format!("operands of {} must be sequences of nodes", o),
+                                ))
+                            }
+                        }
+                    }
+                    operands.push(nodes)
+                }
+                let nodes = match (o, operands.as_slice()) {
+                    (SetOperator::Union, _) => operands.concat(),
+                    (SetOperator::Intersect, [l, r]) | (SetOperator::Except, [l, r]) => {
+                        let other: HashSet<&Node> = r.iter().collect();
+                        l.iter()
+                            .filter(|n| other.contains(n) == (*o == SetOperator::Intersect))
+                            .copied()
+                            .collect()
+                    }
+                    _ => {
+                        return Result::Err(Error::new(
+                            ErrorKind::Unknown,
+                            format!("{} must have two operands", o),
+                        ))
+                    }
+                };
+                let mut result: Sequence =
+                    nodes.into_iter().map(|n| Rc::new(Item::Node(n))).collect();
+                result.sort_document_order(f)?;
+                Ok(result)
+            }
+            Constructor::SimpleMap(v) => {
+                let mut it = v.iter();
+                let mut result = match it.next() {
+                    Some(c) => self.evaluate(ctxt, posn, c, f, sd, rd)?,
+                    None => vec![],
+                };
+                for c in it {
+                    let mut next = vec![];
+                    for i in 0..result.len() {
+                        let mut r = self.evaluate(Some(result.clone()), Some(i), c, f, sd, rd)?;
+                        next.append(&mut r)
+                    }
+                    result = next
+                }
+                Ok(result)
+            }
+            Constructor::Quantified(q, v, t) => {
+                // Stop at the first binding that decides the result
+                let mut result = *q == Quantifier::Every;
+                self.bindings(&ctxt, posn, v, f, sd, rd, &mut |f| {
+                    if self.evaluate(ctxt.clone(), posn, t, f, sd, rd)?.to_bool() == result {
+                        Ok(true)
+                    } else {
+                        result = !result;
+                        Ok(false)
+                    }
+                })?;
+                Ok(vec![Rc::new(Item::Value(Value::from(result)))])
+            }
+            Constructor::Iterate(s, p, b, c) => {
+                let sel = self.evaluate(ctxt.clone(), posn, s, f, sd, rd)?;
+                let mut params = self.declarations(ctxt.clone(), posn, p, f, sd, rd)?;
+                let mut result = vec![];
+                let mut completed = true;
+                for i in 0..sel.len() {
+                    params
+                        .iter()
+                        .for_each(|(v, s)| self.dc.var_push(v, s.clone()));
+                    self.dc.iterations.borrow_mut().push(None);
+                    let r = self.evaluate(Some(sel.clone()), Some(i), b, f, sd, rd);
+                    let ended = self.dc.iterations.borrow_mut().pop().flatten();
+                    params.iter().for_each(|(v, _)| self.dc.var_pop(v));
+                    result.append(&mut r?);
+                    match ended {
+                        Some(Iteration::Break) => {
+                            completed = false;
+                            break;
+                        }
+                        Some(Iteration::Next(n)) => {
+                            for (v, s) in n {
+                                match params.iter().position(|(w, _)| *w == v) {
+                                    // The new value must also have the declared type of the parameter
+                                    Some(j) => {
+                                        params[j].1 = match parameter_type(&p[j]) {
+                                            Some(t) => t
+                                                .convert(s, f)
+                                                .map_err(|e| parameter_type_error(&v, e))?,
+                                            None => s,
+                                        }
+                                    }
+                                    None => {
+                                        return Result::Err(Error::new(
+                                            ErrorKind::Unknown,
+                                            format!("no parameter named \"{}\" to iterate", v),
+                                        ))
+                                    }
+                                }
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                if completed {
+                    // The parameters have the values from the last iteration, and there is no context item
+                    params
+                        .iter()
+                        .for_each(|(v, s)| self.dc.var_push(v, s.clone()));
+                    let r = self.evaluate(None, None, c, f, sd, rd);
+                    params.iter().for_each(|(v, _)| self.dc.var_pop(v));
+                    result.append(&mut r?);
+                }
+                Ok(result)
+            }
+            Constructor::Merge(m, a) => {
+                // Find the merge key values of the items of each source
+                let mut sources = vec![];
+                for s in m {
+                    let sel = self.evaluate(ctxt.clone(), posn, &s.select, f, sd, rd)?;
+                    let mut items = vec![];
+                    for i in 0..sel.len() {
+                        let mut key = vec![];
+                        for k in &s.keys {
+                            // Merge keys are atomised
+                            let v =
+                                self.evaluate(Some(sel.clone()), Some(i), &k.select, f, sd, rd)?;
+                            key.push(
+                                v.iter()
+                                    .map(|j| match &**j {
+                                        Item::Node(_) => {
+                                            Rc::new(Item::Value(Value::from(j.to_string(Some(f)))))
+                                        }
+                                        _ => j.clone(),
+                                    })
+                                    .collect(),
+                            )
+                        }
+                        items.push((key, sel[i].clone()))
+                    }
+                    sources.push(items)
+                }
+                // The order of the keys is given by the first source
+                let keys = m.first().map_or(&[][..], |s| s.keys.as_slice());
+                // Each source must already be in the order of the merge keys
+                for (s, items) in sources.iter().enumerate() {
+                    for w in items.windows(2) {
+                        if merge_order(&w[0].0, &w[1].0, keys, f)? == Ordering::Greater {
+                            return Result::Err(Error::new(
+                                ErrorKind::Unknown,
+                                format!(
+                                    "XTDE2220: the items of merge source {} are not in the order of the merge keys",
+                                    m[s].name.as_ref().map_or_else(|| (s + 1).to_string(), |n| format!("\"{}\"", n))
+                                ),
+                            ));
+                        }
+                    }
+                }
+                // Repeatedly take the item with the lowest key from the heads of the sources. When keys are equal the earlier source goes first.
+                let mut heads = vec![0; sources.len()];
+                let mut groups: Vec<(Vec<Sequence>, MergeGroup)> = vec![];
+                loop {
+                    let mut next: Option<usize> = None;
+                    for (s, items) in sources.iter().enumerate() {
+                        if let Some((k, _)) = items.get(heads[s]) {
+                            next = match next {
+                                Some(n)
+                                    if merge_order(k, &sources[n][heads[n]].0, keys, f)?
+                                        != Ordering::Less =>
+                                {
+                                    Some(n)
+                                }
+                                _ => Some(s),
                             }
                         }
+                    }
+                    let s = match next {
+                        Some(s) => s,
+                        None => break,
+                    };
+                    let (k, i) = sources[s][heads[s]].clone();
+                    heads[s] += 1;
+                    let name = m[s].name.clone();
+                    match groups.last_mut() {
+                        Some((gk, g)) if merge_order(gk, &k, keys, f)? == Ordering::Equal => {
+                            g.items.push((name, i))
+                        }
                         _ => {
-                            // Error: no variable bindings
+                            let key = k.iter().flatten().cloned().collect();
+                            groups.push((
+                                k,
+                                MergeGroup {
+                                    key,
+                                    items: vec![(name, i)],
+                                },
+                            ))
                         }
                     }
-                    Ok(result)
                 }
+                // The context item is the first item of the group
+                let mut result = vec![];
+                for (_, g) in groups {
+                    let items: Sequence = g.items.iter().map(|(_, i)| i.clone()).collect();
+                    self.dc.current_merge.borrow_mut().push(g);
+                    let r = self.evaluate(Some(items), Some(0), a, f, sd, rd);
+                    self.dc.current_merge.borrow_mut().pop();
+                    result.append(&mut r?)
+                }
+                Ok(result)
+            }
+            Constructor::NextIteration(p) => {
+                let n = self.declarations(ctxt, posn, p, f, sd, rd)?;
+                self.end_iteration(Iteration::Next(n))?;
+                Ok(vec![])
+            }
+            Constructor::Break(b) => {
+                let r = self.evaluate(ctxt, posn, b, f, sd, rd)?;
+                self.end_iteration(Iteration::Break)?;
+                Ok(r)
             }
             Constructor::Switch(v, o) => {
                 // 'v' are pairs of test,body
@@ -1169,7 +2119,7 @@ impl Evaluator {
 
                 let sel = self.evaluate(ctxt.clone(), posn, s, f, sd, rd)?;
                 // TODO: Don't Panic
-                let result = sel.iter().fold(vec![], |mut acc, i| {
+                let result = sel.iter().try_fold(vec![], |mut acc, i| {
                     let mut matching_template: Vec<&Template> = vec![];
                     for t in &self.templates {
                         let e = self
@@ -1181,19 +2131,19 @@ impl Evaluator {
                     }
 
                     if matching_template.len() != 0 {
-                        // find the template(s) with the lowest priority
+                        // find the template(s) with the highest priority
                         matching_template
-                            .sort_unstable_by(|s, t| s.priority.partial_cmp(&t.priority).unwrap());
+                            .sort_unstable_by(|s, t| t.priority.partial_cmp(&s.priority).unwrap());
                         let l = matching_template[0].priority;
-                        let mut mt_lowest: Vec<&Template> = matching_template
+                        let mut mt_highest: Vec<&Template> = matching_template
                             .into_iter()
                             .take_while(|t| t.priority == l)
                             .collect();
 
                         // It's OK to have more than one matching template, if they all have different import precedence
-                        mt_lowest.sort_unstable_by_key(|t| t.import);
-                        let mut p = mt_lowest[0].import;
-                        mt_lowest.iter().skip(1).for_each(|t| {
+                        mt_highest.sort_unstable_by_key(|t| t.import);
+                        let mut p = mt_highest[0].import;
+                        mt_highest.iter().skip(1).for_each(|t| {
                             if t.import == p {
                                 panic!("too many matching templates")
                             } else {
@@ -1204,18 +2154,14 @@ impl Evaluator {
 
                         // Use the template with the lowest import precedence
                         // Unless we're inside an apply-imports
-                        let mut u = mt_lowest
-                            .iter()
-                            .take(1)
-                            .flat_map(|t| {
-                                self.dc.depth_incr();
-                                let rs = self
-                                    .evaluate(Some(vec![i.clone()]), Some(0), &t.body, f, sd, rd)
-                                    .expect("failed to evaluate template body");
-                                self.dc.depth_decr();
-                                rs
-                            })
-                            .collect::<Sequence>();
+                        let mut u = self.evaluate_template(
+                            mt_highest[0],
+                            Some(vec![i.clone()]),
+                            Some(0),
+                            f,
+                            sd,
+                            rd,
+                        )?;
                         acc.append(&mut u);
                     } else {
                         // If no templates match then apply a built-in template
@@ -1246,21 +2192,20 @@ impl Evaluator {
                         if builtin_template.len() > 1 {
                             panic!("too many matching builtin templates")
                         }
-                        let mut u = builtin_template
-                            .iter()
-                            .flat_map(|t| {
-                                self.dc.depth_incr();
-                                let rs = self
-                                    .evaluate(Some(vec![i.clone()]), Some(0), &t.body, f, sd, rd)
-                                    .expect("failed to evaluate template body");
-                                self.dc.depth_decr();
-                                rs
-                            })
-                            .collect::<Sequence>();
-                        acc.append(&mut u);
+                        for t in builtin_template {
+                            let mut u = self.evaluate_template(
+                                t,
+                                Some(vec![i.clone()]),
+                                Some(0),
+                                f,
+                                sd,
+                                rd,
+                            )?;
+                            acc.append(&mut u);
+                        }
                     }
-                    acc
-                });
+                    Ok::<Sequence, Error>(acc)
+                })?;
                 Ok(result)
             }
             Constructor::ApplyImports => {
@@ -1281,37 +2226,28 @@ impl Evaluator {
                 }
 
                 if matching_template.len() != 0 {
-                    // find the template(s) with the lowest priority
+                    // find the template(s) with the highest priority
                     matching_template
-                        .sort_unstable_by(|s, t| s.priority.partial_cmp(&t.priority).unwrap());
+                        .sort_unstable_by(|s, t| t.priority.partial_cmp(&s.priority).unwrap());
                     let l = matching_template[0].priority;
-                    let mut mt_lowest: Vec<&Template> = matching_template
+                    let mut mt_highest: Vec<&Template> = matching_template
                         .into_iter()
                         .take_while(|t| t.priority == l)
                         .collect();
 
                     // No need to check for multiple matches here,
                     // since this was checked by the apply-templates
-                    mt_lowest.sort_unstable_by_key(|t| t.import);
+                    mt_highest.sort_unstable_by_key(|t| t.import);
 
                     // Find the template with the lowest import precedence
                     // higher than the current precedence
-                    let mut u = mt_lowest
-                        .iter()
-                        .skip_while(|t| t.import <= *self.dc.current_import.borrow())
-                        .take(1)
-                        .flat_map(|t| {
-                            self.dc.depth_incr();
-                            self.dc.import_incr();
-                            let rs = self
-                                .evaluate(ctxt.clone(), posn, &t.body, f, sd, rd)
-                                .expect("failed to evaluate template body");
-                            self.dc.depth_decr();
-                            self.dc.import_decr();
-                            rs
-                        })
-                        .collect::<Sequence>();
-                    result.append(&mut u);
+                    let current = *self.dc.current_import.borrow();
+                    if let Some(t) = mt_highest.iter().find(|t| t.import > current) {
+                        self.dc.import_incr();
+                        let u = self.evaluate_template(t, ctxt.clone(), posn, f, sd, rd);
+                        self.dc.import_decr();
+                        result.append(&mut u?);
+                    }
                 } else {
                     // If no templates match then apply a built-in template
                     // See XSLT 6.7.
@@ -1341,18 +2277,10 @@ impl Evaluator {
                     if builtin_template.len() > 1 {
                         panic!("too many matching builtin templates")
                     }
-                    let mut u = builtin_template
-                        .iter()
-                        .flat_map(|t| {
-                            self.dc.depth_incr();
-                            let rs = self
-                                .evaluate(ctxt.clone(), posn, &t.body, f, sd, rd)
-                                .expect("failed to evaluate template body");
-                            self.dc.depth_decr();
-                            rs
-                        })
-                        .collect::<Sequence>();
-                    result.append(&mut u);
+                    for t in builtin_template {
+                        let mut u = self.evaluate_template(t, ctxt.clone(), posn, f, sd, rd)?;
+                        result.append(&mut u);
+                    }
                 }
                 Ok(result)
             }
@@ -1431,7 +2359,7 @@ impl Evaluator {
                     }
                 }
 
-                let result = groups.iter().fold(vec![], |mut result, grp| {
+                groups.iter().try_fold(vec![], |mut result, grp| {
                     let (o, v) = grp;
                     // set current-grouping-key, current-group
                     match o {
@@ -1442,18 +2370,15 @@ impl Evaluator {
                         }
                         None => {}
                     }
-                    // TODO: Don't Panic
-                    let mut tmp = self
-                        .evaluate(Some(v.to_vec()), Some(0), t, f, sd, rd)
-                        .expect("failed to evaluate template");
-                    result.append(&mut tmp);
+                    let tmp = self.evaluate(Some(v.to_vec()), Some(0), t, f, sd, rd);
                     // Restore current-grouping-key, current-group
                     self.dc.pop_current_grouping_key();
                     self.dc.pop_current_group();
-                    result
-                });
-                Ok(result)
+                    result.append(&mut tmp?);
+                    Ok(result)
+                })
             }
+            Constructor::Empty => Ok(vec![]),
             Constructor::NotImplemented(m) => Result::Err(Error {
                 kind: ErrorKind::NotImplemented,
                 message: format!("sequence constructor not implemented: {}", m),
@@ -1471,7 +2396,16 @@ impl Evaluator {
         sd: TreeIndex,
         rd: TreeIndex,
     ) -> Result<Rc<Item>, Error> {
-        let cp = self.item_copy(orig.clone(), &vec![], ctxt.clone(), posn, f, sd, rd)?;
+        let cp = self.item_copy(
+            orig.clone(),
+            &vec![],
+            Instruction::DeepCopy,
+            ctxt.clone(),
+            posn,
+            f,
+            sd,
+            rd,
+        )?;
 
         // If this item is an element node, then copy all of its attributes and children
         match *orig {
@@ -1495,17 +2429,18 @@ impl Evaluator {
                         let mut atit = n.attribute_iter(f);
                         loop {
                             match atit.next() {
-                                Some(a) => new.push((a.to_name(f), Value::from(a.to_string(f)))),
+                                Some(a) => new.push((a, a.to_name(f), Value::from(a.to_string(f)))),
                                 None => break,
                             }
                         }
                         // TODO: Don't Panic
-                        new.iter().for_each(|(qn, v)| {
+                        new.iter().for_each(|(a, qn, v)| {
                             let at = f
                                 .get_ref_mut(rd)
                                 .unwrap()
                                 .new_attribute(qn.clone(), v.clone())
                                 .expect("unable to create attribute");
+                            self.dc.record_origin(at, Some(*a), Instruction::DeepCopy);
                             cur.add_attribute(f, at).expect("unable to add attribute");
                         });
                         let mut child_list = n.child_iter();
@@ -1541,11 +2476,12 @@ impl Evaluator {
         Ok(cp)
     }
 
-    // Copy an item
+    // Copy an item. A copied node is recorded in the source map as made by the given instruction.
     fn item_copy(
         &self,
         orig: Rc<Item>,
         content: &Vec<Constructor>,
+        instruction: Instruction,
         ctxt: Option<Sequence>,
         posn: Option<usize>,
         f: &mut Forest,
@@ -1567,6 +2503,7 @@ impl Evaluator {
                             .new_element(qn)
                         {
                             Ok(e) => {
+                                self.dc.record_origin(e, Some(n), instruction);
                                 // Add content to the new element
                                 // TODO: Don't Panic
                                 let r = self.evaluate(ctxt.clone(), posn, content, f, sd, rd)?;
@@ -1594,6 +2531,7 @@ impl Evaluator {
                                                 .unwrap()
                                                 .new_text(x)
                                                 .expect("unable to create text node");
+                                            self.dc.record_origin(h, Some(n), Instruction::Text);
                                             e.append_child(f, h)
                                                 .expect("unable to add child text node");
                                         }
@@ -1619,7 +2557,10 @@ impl Evaluator {
                             ))?
                             .new_text(x)
                         {
-                            Ok(m) => Ok(Rc::new(Item::Node(m))),
+                            Ok(m) => {
+                                self.dc.record_origin(m, Some(n), instruction);
+                                Ok(Rc::new(Item::Node(m)))
+                            }
                             _ => {
                                 return Result::Err(Error {
                                     kind: ErrorKind::Unknown,
@@ -1640,7 +2581,10 @@ impl Evaluator {
                             ))?
                             .new_attribute(qn, x)
                         {
-                            Ok(a) => Ok(Rc::new(Item::Node(a))),
+                            Ok(a) => {
+                                self.dc.record_origin(a, Some(n), instruction);
+                                Ok(Rc::new(Item::Node(a)))
+                            }
                             _ => Result::Err(Error {
                                 kind: ErrorKind::Unknown,
                                 message: "unable to create attribute node".to_string(),
@@ -1681,7 +2625,14 @@ impl Evaluator {
                 // for each predicate, evaluate each item in s to a boolean
                 for i in 0..result.len() {
                     let b = self.evaluate(Some(result.clone()), Some(i), q, f, sd, rd)?;
-                    if b.to_bool() == true {
+                    // A numeric predicate is true if it is the position of the item
+                    let selected = match b.first().map(|j| &**j) {
+                        Some(Item::Value(v)) if b.len() == 1 && is_numeric(v) => {
+                            f64::try_from(v).is_ok_and(|d| d == (i + 1) as f64)
+                        }
+                        _ => b.to_bool(),
+                    };
+                    if selected {
                         new.push(result[i].clone());
                     }
                 }
@@ -1795,6 +2746,8 @@ pub enum Constructor {
     /// TODO: allow for attribute value templates
     /// Arguments are: attribute name, value
     LiteralAttribute(QualifiedName, Vec<Constructor>),
+    /// Text that is written without escaping, i.e. xsl:text with disable-output-escaping="yes". This will become a text node in the result tree, marked with [DisableOutputEscaping].
+    UnescapedText(Value),
     /// Construct a node by copying something. The first argument is what to copy; an empty vector selects the current item. The second argument constructs the content.
     Copy(Vec<Constructor>, Vec<Constructor>),
     DeepCopy(Vec<Constructor>),
@@ -1835,6 +2788,8 @@ pub enum Constructor {
     // After,
     /// Concatentate string values
     Concat(Vec<Vec<Constructor>>),
+    /// A value template: the string values of the items of each operand are separated by a space, and the operands are concatenated
+    ValueTemplate(Vec<Vec<Constructor>>),
     /// Construct a range of integers
     Range(Vec<Vec<Constructor>>),
     /// Perform addition, subtraction, multiply, divide
@@ -1850,6 +2805,18 @@ pub enum Constructor {
     /// The first argument declares variables.
     /// The second argument is the body of the loop.
     Loop(Vec<Constructor>, Vec<Constructor>),
+    /// Binds variables, then evaluates an expression (i.e. a let expression).
+    /// The first argument declares variables, each of which is in scope for the later declarations and the body.
+    /// The second argument is the body.
+    Let(Vec<Constructor>, Vec<Constructor>),
+    /// Tests whether some, or every, binding of the variables satisfies the test (i.e. a quantified expression).
+    /// The second argument declares variables, as for Loop. The third argument is the test.
+    Quantified(Quantifier, Vec<Constructor>, Vec<Constructor>),
+    /// Combines sequences of nodes (i.e. the union, intersect and except operators).
+    /// The result is in document order, without duplicates. Union may have any number of operands; intersect and except have two.
+    SetOperation(SetOperator, Vec<Vec<Constructor>>),
+    /// Evaluates each operand with each item of the result of the previous operand as the context item, concatenating the results (i.e. the simple map operator "!").
+    SimpleMap(Vec<Vec<Constructor>>),
     /// Selects an arm to evaluate.
     /// The first argument is pairs of (test,body) clauses.
     /// The second argument is the otherwise clause
@@ -1864,13 +2831,116 @@ pub enum Constructor {
     /// First argument is the select expression, second argument is the template,
     /// third argument is the (optional) grouping spec.
     ForEach(Vec<Constructor>, Vec<Constructor>, Option<Grouping>),
+    /// Evaluate a sequence constructor for each item, passing parameters from one iteration to the next (i.e. xsl:iterate).
+    /// First argument is the select expression, second argument declares the parameters and their initial values,
+    /// third argument is the body, fourth argument is evaluated if the iteration was not broken off (xsl:on-completion).
+    Iterate(
+        Vec<Constructor>,
+        Vec<Constructor>,
+        Vec<Constructor>,
+        Vec<Constructor>,
+    ),
+    /// Merge sequences that are already sorted, and evaluate a sequence constructor for each group of items that have the same merge key (i.e. xsl:merge).
+    /// First argument is the sources, second argument is the merge action.
+    Merge(Vec<MergeSource>, Vec<Constructor>),
+    /// Start the next iteration of the enclosing Iterate with new values for the parameters.
+    /// The argument declares the new values. Parameters that are not given keep their value.
+    NextIteration(Vec<Constructor>),
+    /// Stop the enclosing Iterate. The argument is the final result of the iteration.
+    Break(Vec<Constructor>),
     /// Set the value of an attribute. Context item must be an element node.
     /// First argument is the name of the attribute, second attribute is the value to set
     SetAttribute(QualifiedName, Vec<Constructor>),
+    /// Does the sequence match the sequence type (i.e. instance of)?
+    InstanceOf(Vec<Constructor>, SequenceType),
+    /// The sequence, if it matches the sequence type, otherwise an error (i.e. treat as)
+    Treat(Vec<Constructor>, SequenceType),
+    /// Convert the sequence to the sequence type using the function conversion rules, or an error if it cannot be converted (e.g. the value of an xsl:param with an as attribute)
+    Convert(Vec<Constructor>, SequenceType),
+    /// Cast a single atomic value to the type (i.e. cast as). The third argument is true if the empty sequence is allowed, in which case the result is empty.
+    Cast(Vec<Constructor>, AtomicType, bool),
+    /// Whether the cast would succeed (i.e. castable as). Arguments as for Cast.
+    Castable(Vec<Constructor>, AtomicType, bool),
+    /// The empty sequence. For example, an xsl:fallback instruction that is not used in place of an unsupported instruction.
+    Empty,
     /// Something that is not yet implemented
     NotImplemented(String),
 }
 
+/// A sequence of items to be merged by [Constructor::Merge] (i.e. xsl:merge-source).
+#[derive(Clone)]
+pub struct MergeSource {
+    /// The name of the source, for current-merge-group().
+    pub name: Option<String>,
+    /// Selects the items. These must be in the order of the merge keys, otherwise evaluation fails (XTDE2220).
+    pub select: Vec<Constructor>,
+    pub keys: Vec<MergeKey>,
+}
+
+/// A merge key (i.e. xsl:merge-key). The key is evaluated with each item of the source as the context item.
+#[derive(Clone)]
+pub struct MergeKey {
+    pub select: Vec<Constructor>,
+    pub descending: bool,
+}
+
+// Compare merge key values. An empty key comes first.
+fn merge_order(
+    a: &[Sequence],
+    b: &[Sequence],
+    keys: &[MergeKey],
+    f: &Forest,
+) -> Result<Ordering, Error> {
+    for ((x, y), k) in a.iter().zip(b).zip(keys) {
+        let o = match (x.first(), y.first()) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (Some(i), Some(j)) => {
+                if i.compare(j, Operator::LessThan, Some(f))? {
+                    Ordering::Less
+                } else if i.compare(j, Operator::GreaterThan, Some(f))? {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            }
+        };
+        if o != Ordering::Equal {
+            return Ok(if k.descending { o.reverse() } else { o });
+        }
+    }
+    Ok(Ordering::Equal)
+}
+
+/// The quantifier of a quantified expression.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Quantifier {
+    Some,
+    Every,
+}
+
+/// The operator of a [Constructor::SetOperation].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SetOperator {
+    /// The nodes that are in any operand
+    Union,
+    /// The nodes that are in both operands
+    Intersect,
+    /// The nodes in the first operand that are not in the second
+    Except,
+}
+
+impl fmt::Display for SetOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SetOperator::Union => "union",
+            SetOperator::Intersect => "intersect",
+            SetOperator::Except => "except",
+        })
+    }
+}
+
 /// Determine how a collection is to be divided into groups.
 /// This enum would normally be inside an Option. The None value means that the collection is not to be grouped.
 #[derive(Clone)]
@@ -1881,57 +2951,101 @@ pub enum Grouping {
     Adjacent(Vec<Constructor>),
 }
 
-// Apply the node test to a Node.
-// TODO: Make this a method of the Node trait?
-fn is_node_match(nt: &NodeTest, n: &Node, f: &Forest) -> bool {
-    match nt {
-        NodeTest::Name(t) => {
-            match n.node_type(f) {
-                NodeType::Element | NodeType::Attribute => {
-                    // TODO: namespaces
-                    match &t.name {
-                        Some(a) => match a {
-                            WildcardOrName::Wildcard => true,
-                            WildcardOrName::Name(s) => *s == n.to_name(f).get_localname(),
-                        },
-                        None => false,
-                    }
-                }
-                _ => false,
-            }
+// Find the descendants of a node that match an element name test, using the indexes of the tree.
+// If the first predicate compares an attribute with a string, such as [attribute::id = "a"], then only the elements that have that attribute value are candidates.
+// The candidates are in document order, and must still be filtered by the predicates.
+// Returns None if the tree is not indexed, or the step can't use the indexes.
+fn indexed_descendants(
+    f: &Forest,
+    n: Node,
+    nm: &NodeMatch,
+    p: &[Vec<Constructor>],
+    or_self: bool,
+) -> Option<Sequence> {
+    let local = indexed_name(nm)?;
+    let t = f.get_ref(n.tree_index())?;
+    let candidates = match indexed_attribute(p) {
+        Some((an, av)) => t.elements_by_attribute(an, av)?,
+        None => t.elements_by_name(local)?,
+    };
+    let mut seq = Sequence::new();
+    for c in candidates {
+        if !nm.nodetest.matches(f, &c) {
+            continue;
         }
-        NodeTest::Kind(k) => {
-            match k {
-                KindTest::DocumentTest => match n.node_type(f) {
-                    NodeType::Document => true,
-                    _ => false,
-                },
-                KindTest::ElementTest => match n.node_type(f) {
-                    NodeType::Element => true,
-                    _ => false,
-                },
-                KindTest::PITest => match n.node_type(f) {
-                    NodeType::ProcessingInstruction => true,
-                    _ => false,
-                },
-                KindTest::CommentTest => match n.node_type(f) {
-                    NodeType::Comment => true,
-                    _ => false,
-                },
-                KindTest::TextTest => match n.node_type(f) {
-                    NodeType::Text => true,
-                    _ => false,
-                },
-                KindTest::AnyKindTest => true,
-                KindTest::AttributeTest
-                | KindTest::SchemaElementTest
-                | KindTest::SchemaAttributeTest
-                | KindTest::NamespaceNodeTest => false, // TODO: not yet implemented
-            }
+        let mut within = or_self && c == n;
+        let mut a = c.parent_node(f);
+        while let Some(q) = a.filter(|_| !within) {
+            within = q == n;
+            a = q.parent_node(f)
         }
+        if within {
+            seq.push_node(c)
+        }
+    }
+    Some(seq)
+}
+
+// The element name that a step can look up in the indexes of a tree.
+pub(crate) fn indexed_name(nm: &NodeMatch) -> Option<&String> {
+    match &nm.nodetest {
+        NodeTest::Name(NameTest {
+            name: Some(WildcardOrName::Name(local)),
+            ..
+        }) => Some(local),
+        _ => None,
     }
 }
 
+// The attribute name and value that the predicates of a step can look up in the indexes of a tree.
+pub(crate) fn indexed_attribute(p: &[Vec<Constructor>]) -> Option<(&String, &str)> {
+    p.first().and_then(|q| match q.as_slice() {
+        [Constructor::GeneralComparison(Operator::Equal, v)] => match v.as_slice() {
+            [a, b] => match (a.as_slice(), b.as_slice()) {
+                (
+                    [Constructor::Step(
+                        NodeMatch {
+                            axis: Axis::Attribute,
+                            nodetest:
+                                NodeTest::Name(NameTest {
+                                    name: Some(WildcardOrName::Name(an)),
+                                    ..
+                                }),
+                        },
+                        ap,
+                    )],
+                    [Constructor::Literal(Value::String(av))],
+                ) if ap.is_empty() => Some((an, av.as_ref())),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn is_numeric(v: &Value) -> bool {
+    matches!(
+        v,
+        Value::Integer(_)
+            | Value::Int(_)
+            | Value::Long(_)
+            | Value::Short(_)
+            | Value::Byte(_)
+            | Value::UnsignedLong(_)
+            | Value::UnsignedInt(_)
+            | Value::UnsignedShort(_)
+            | Value::UnsignedByte(_)
+            | Value::NonPositiveInteger(_)
+            | Value::NegativeInteger(_)
+            | Value::NonNegativeInteger(_)
+            | Value::PositiveInteger(_)
+            | Value::Decimal(_)
+            | Value::Float(_)
+            | Value::Double(_)
+    )
+}
+
 #[derive(Clone)]
 pub struct NodeMatch {
     pub axis: Axis,
@@ -2018,22 +3132,63 @@ impl TryFrom<&str> for NodeTest {
 }
 
 impl NodeTest {
+    /// Does the node pass the test?
+    pub fn matches(&self, f: &Forest, n: &Node) -> bool {
+        match self {
+            NodeTest::Name(nt) => nt.matches(f, n),
+            NodeTest::Kind(kt) => kt.matches(f, n),
+        }
+    }
     pub fn to_string(&self) -> String {
         match self {
             NodeTest::Name(nt) => nt.to_string(),
             NodeTest::Kind(kt) => kt.to_string().to_string(),
         }
     }
-}
-
-#[derive(Clone)]
-pub enum KindTest {
-    DocumentTest,
-    ElementTest,
-    AttributeTest,
+    /// The test in XPath syntax, such as "p:a", "*:a" or "text()".
+    pub fn to_xpath(&self) -> String {
+        match self {
+            NodeTest::Name(nt) => {
+                let mut r = match (&nt.ns, &nt.prefix) {
+                    (Some(WildcardOrName::Name(ns)), _) => format!("Q{{{}}}", ns),
+                    (Some(WildcardOrName::Wildcard), _) => String::from("*:"),
+                    (None, Some(p)) => format!("{}:", p),
+                    (None, None) => String::new(),
+                };
+                match &nt.name {
+                    Some(WildcardOrName::Name(n)) => r.push_str(n),
+                    _ => r.push('*'),
+                }
+                r
+            }
+            NodeTest::Kind(KindTest::PITest(Some(n))) => {
+                format!("processing-instruction({})", n)
+            }
+            NodeTest::Kind(kt) => String::from(match kt {
+                KindTest::DocumentTest => "document-node()",
+                KindTest::ElementTest => "element()",
+                KindTest::AttributeTest => "attribute()",
+                KindTest::SchemaElementTest => "schema-element()",
+                KindTest::SchemaAttributeTest => "schema-attribute()",
+                KindTest::PITest(_) => "processing-instruction()",
+                KindTest::CommentTest => "comment()",
+                KindTest::TextTest => "text()",
+                KindTest::NamespaceNodeTest => "namespace-node()",
+                KindTest::AnyKindTest => "node()",
+            }),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum KindTest {
+    DocumentTest,
+    ElementTest,
+    AttributeTest,
     SchemaElementTest,
     SchemaAttributeTest,
-    PITest,
+    /// A processing instruction, optionally with the given name
+    PITest(Option<String>),
     CommentTest,
     TextTest,
     NamespaceNodeTest,
@@ -2041,6 +3196,26 @@ pub enum KindTest {
 }
 
 impl KindTest {
+    /// Does the node have the kind, and name if one is given, of the test?
+    pub fn matches(&self, f: &Forest, n: &Node) -> bool {
+        match self {
+            KindTest::DocumentTest => n.node_type(f) == NodeType::Document,
+            KindTest::ElementTest => n.node_type(f) == NodeType::Element,
+            KindTest::AttributeTest => n.node_type(f) == NodeType::Attribute,
+            KindTest::PITest(name) => {
+                n.node_type(f) == NodeType::ProcessingInstruction
+                    && name
+                        .as_ref()
+                        .is_none_or(|m| *m == n.to_name(f).get_localname())
+            }
+            KindTest::CommentTest => n.node_type(f) == NodeType::Comment,
+            KindTest::TextTest => n.node_type(f) == NodeType::Text,
+            KindTest::AnyKindTest => true,
+            KindTest::SchemaElementTest
+            | KindTest::SchemaAttributeTest
+            | KindTest::NamespaceNodeTest => false, // TODO: not yet implemented
+        }
+    }
     pub fn to_string(&self) -> &'static str {
         match self {
             KindTest::DocumentTest => "DocumentTest",
@@ -2048,7 +3223,7 @@ impl KindTest {
             KindTest::AttributeTest => "AttributeTest",
             KindTest::SchemaElementTest => "SchemaElementTest",
             KindTest::SchemaAttributeTest => "SchemaAttributeTest",
-            KindTest::PITest => "PITest",
+            KindTest::PITest(_) => "PITest",
             KindTest::CommentTest => "CommentTest",
             KindTest::TextTest => "TextTest",
             KindTest::NamespaceNodeTest => "NamespaceNodeTest",
@@ -2065,6 +3240,26 @@ pub struct NameTest {
 }
 
 impl NameTest {
+    /// Does the element or attribute have a matching name?
+    ///
+    /// A namespace URI in the test is compared with the namespace URI of the node. The prefix of a test is resolved to a namespace URI by [StaticContext::static_analysis]; a prefix that was not resolved, because no namespaces were in scope, is compared with the prefix of the node. If the test has neither then only the local name is compared.
+    pub fn matches(&self, f: &Forest, n: &Node) -> bool {
+        if !matches!(n.node_type(f), NodeType::Element | NodeType::Attribute) {
+            return false;
+        }
+        let qn = n.to_name(f);
+        let local = match &self.name {
+            Some(WildcardOrName::Wildcard) => true,
+            Some(WildcardOrName::Name(s)) => *s == qn.get_localname(),
+            None => false,
+        };
+        local
+            && match (&self.ns, &self.prefix) {
+                (Some(WildcardOrName::Wildcard), _) | (None, None) => true,
+                (Some(WildcardOrName::Name(ns)), _) => qn.get_nsuri_ref() == Some(ns.as_str()),
+                (None, Some(p)) => qn.get_prefix().as_ref() == Some(p),
+            }
+    }
     pub fn to_string(&self) -> String {
         if self.name.is_some() {
             match self.name.as_ref().unwrap() {
@@ -2206,6 +3401,15 @@ pub struct ArithmeticOperand {
 pub fn to_pattern(sc: Vec<Constructor>) -> Result<Vec<Constructor>, Error> {
     if sc.len() == 1 {
         match sc[0] {
+            // An item matches a union pattern if it matches any of the alternatives
+            Constructor::SetOperation(SetOperator::Union, ref v) => {
+                Ok(vec![Constructor::SetOperation(
+                    SetOperator::Union,
+                    v.iter()
+                        .map(|p| to_pattern(p.clone()))
+                        .collect::<Result<Vec<Vec<Constructor>>, Error>>()?,
+                )])
+            }
             Constructor::Root => Ok(vec![Constructor::Step(
                 NodeMatch {
                     axis: Axis::SelfDocument,
@@ -2355,6 +3559,7 @@ pub struct Template {
     priority: f64,
     mode: Option<String>,
     import: usize,
+    source: Option<String>, // The match attribute in the stylesheet
 }
 
 impl fmt::Debug for Template {
@@ -2377,6 +3582,7 @@ impl fmt::Debug for Template {
 pub struct StaticContext {
     pub funcs: RefCell<HashMap<String, Function>>,
     pub vars: RefCell<HashMap<String, Vec<Sequence>>>, // each entry in the vector is an inner scope of the variable
+    pub namespaces: HashMap<String, String>, // the namespace URI bound to each prefix, for resolving names in expressions
 }
 
 impl StaticContext {
@@ -2385,6 +3591,7 @@ impl StaticContext {
         StaticContext {
             funcs: RefCell::new(HashMap::new()),
             vars: RefCell::new(HashMap::new()),
+            namespaces: HashMap::new(),
         }
     }
     /// Creates a new StaticContext and initializes it with the pre-defined XPath functions.
@@ -2422,10 +3629,23 @@ impl StaticContext {
     /// * format-dateTime()
     /// * format-date()
     /// * format-time()
+    /// * upper-case()
+    /// * lower-case()
+    /// * normalize-unicode()
+    /// * encode-for-uri()
+    /// * iri-to-uri()
+    /// * escape-html-uri()
+    /// * resolve-uri()
+    /// * doc()
+    /// * doc-available()
+    /// * unparsed-text()
+    /// * unparsed-text-available()
+    /// * id()
     pub fn new_with_builtins() -> StaticContext {
         let sc = StaticContext {
             funcs: RefCell::new(HashMap::new()),
             vars: RefCell::new(HashMap::new()),
+            namespaces: HashMap::new(),
         };
         sc.funcs.borrow_mut().insert(
             "position".to_string(),
@@ -2457,6 +3677,16 @@ impl StaticContext {
                 body: Some(func_count),
             },
         );
+        sc.funcs.borrow_mut().insert(
+            "subsequence".to_string(),
+            Function {
+                name: "subsequence".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_subsequence),
+            },
+        );
         sc.funcs.borrow_mut().insert(
             "local-name".to_string(),
             Function {
@@ -2567,6 +3797,76 @@ impl StaticContext {
                 body: Some(func_translate),
             },
         );
+        sc.funcs.borrow_mut().insert(
+            "upper-case".to_string(),
+            Function {
+                name: "upper-case".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_uppercase),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "lower-case".to_string(),
+            Function {
+                name: "lower-case".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_lowercase),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "normalize-unicode".to_string(),
+            Function {
+                name: "normalize-unicode".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_normalizeunicode),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "encode-for-uri".to_string(),
+            Function {
+                name: "encode-for-uri".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_encodeforuri),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "iri-to-uri".to_string(),
+            Function {
+                name: "iri-to-uri".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_iritouri),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "escape-html-uri".to_string(),
+            Function {
+                name: "escape-html-uri".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_escapehtmluri),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "resolve-uri".to_string(),
+            Function {
+                name: "resolve-uri".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_resolveuri),
+            },
+        );
         sc.funcs.borrow_mut().insert(
             "boolean".to_string(),
             Function {
@@ -2718,6 +4018,87 @@ impl StaticContext {
             },
         );
 
+        sc.funcs.borrow_mut().insert(
+            "doc".to_string(),
+            Function {
+                name: "doc".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_doc),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "doc-available".to_string(),
+            Function {
+                name: "doc-available".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_docavailable),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "json-to-xml".to_string(),
+            Function {
+                name: "json-to-xml".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_jsontoxml),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "xml-to-json".to_string(),
+            Function {
+                name: "xml-to-json".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_xmltojson),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "serialize".to_string(),
+            Function {
+                name: "serialize".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_serialize),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "unparsed-text".to_string(),
+            Function {
+                name: "unparsed-text".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_unparsedtext),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "unparsed-text-available".to_string(),
+            Function {
+                name: "unparsed-text-available".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_unparsedtextavailable),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "id".to_string(),
+            Function {
+                name: "id".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_id),
+            },
+        );
+
         sc
     }
     /// Create a new StaticContext with builtin functions defined,
@@ -2745,7 +4126,86 @@ impl StaticContext {
                 body: Some(func_current_group),
             },
         );
-
+        sc.funcs.borrow_mut().insert(
+            "current-merge-group".to_string(),
+            Function {
+                name: "current-merge-group".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_current_merge_group),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "current-merge-key".to_string(),
+            Function {
+                name: "current-merge-key".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_current_merge_key),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "document".to_string(),
+            Function {
+                name: "document".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![Param::new("uri-sequence".to_string(), "item".to_string())],
+                body: Some(func_document),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "generate-id".to_string(),
+            Function {
+                name: "generate-id".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_generateid),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "key".to_string(),
+            Function {
+                name: "key".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_key),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "element-available".to_string(),
+            Function {
+                name: "element-available".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_elementavailable),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "function-available".to_string(),
+            Function {
+                name: "function-available".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_functionavailable),
+            },
+        );
+        sc.funcs.borrow_mut().insert(
+            "system-property".to_string(),
+            Function {
+                name: "system-property".to_string(),
+                nsuri: None,
+                prefix: None,
+                params: vec![],
+                body: Some(func_systemproperty),
+            },
+        );
         sc
     }
     /// Register an extension function
@@ -2770,10 +4230,14 @@ impl StaticContext {
     pub fn declare_variable(&self, n: String, _ns: String) {
         self.vars.borrow_mut().insert(n.clone(), vec![]);
     }
+    /// Set the namespaces that are in scope for the expressions that are analysed next, such as the result of [Node::in_scope_namespaces] for an element of a stylesheet. The prefixes of name tests are resolved to these namespace URIs.
+    pub fn set_namespaces(&mut self, ns: HashMap<String, String>) {
+        self.namespaces = ns
+    }
 
     /// Perform static analysis of a sequence constructor.
     ///
-    /// This checks that functions and variables are declared. It also rewrites the constructors to provide the implementation of functions that are used in expressions.
+    /// This checks that functions and variables are declared. It also rewrites the constructors to provide the implementation of functions that are used in expressions, and to give name tests the namespace URI that their prefix is bound to.
     pub fn static_analysis(&mut self, e: &mut Vec<Constructor>) {
         // TODO: return Result
         // TODO: iterate through the tree structure instead of doing a recursive depth first search. This should mean that the method would not have to use interior mutability
@@ -2785,7 +4249,9 @@ impl StaticContext {
                     }
                     self.static_analysis(o);
                 }
-                Constructor::Loop(v, a) => {
+                Constructor::Loop(v, a)
+                | Constructor::Let(v, a)
+                | Constructor::Quantified(_, v, a) => {
                     self.static_analysis(v);
                     self.static_analysis(a);
                 }
@@ -2793,14 +4259,22 @@ impl StaticContext {
                     self.static_analysis(v);
                 }
                 Constructor::FunctionCall(f, a) => {
-                    // Fill in function body
-                    match self.funcs.borrow().get(&f.name) {
-                        Some(g) => {
-                            f.body.replace(g.body.unwrap());
-                        }
-                        None => {
-                            // TODO: Don't Panic
-                            panic!("call to unknown function \"{}\"", f.name)
+                    // Fill in function body.
+                    // An unknown function is only an error if the call is evaluated,
+                    // so that the call may be guarded by function-available().
+                    if let Some(g) = self.funcs.borrow().get(&f.name) {
+                        f.body.replace(g.body.unwrap());
+                    }
+                    // The name of a key is a QName, so resolve its prefix while the namespaces are known
+                    if f.name == "key" {
+                        if let Some([Constructor::Literal(Value::String(k))]) =
+                            a.first_mut().map(|k| k.as_mut_slice())
+                        {
+                            if let Some((p, l)) = k.trim().split_once(':') {
+                                if let Some(u) = self.namespaces.get(p) {
+                                    *k = format!("Q{{{}}}{}", u, l).into()
+                                }
+                            }
                         }
                     }
                     for i in a {
@@ -2818,12 +4292,22 @@ impl StaticContext {
                 | Constructor::And(a)
                 | Constructor::Path(a)
                 | Constructor::Concat(a)
-                | Constructor::Range(a) => {
+                | Constructor::ValueTemplate(a)
+                | Constructor::Range(a)
+                | Constructor::SetOperation(_, a)
+                | Constructor::SimpleMap(a) => {
                     for i in a {
                         self.static_analysis(i)
                     }
                 }
-                Constructor::Step(_, a) => {
+                Constructor::Step(nm, a) => {
+                    if let NodeTest::Name(nt) = &mut nm.nodetest {
+                        if let (None, Some(p)) = (&nt.ns, &nt.prefix) {
+                            if let Some(u) = self.namespaces.get(p) {
+                                nt.ns = Some(WildcardOrName::Name(u.clone()))
+                            }
+                        }
+                    }
                     for i in a {
                         self.static_analysis(i)
                     }
@@ -2838,7 +4322,24 @@ impl StaticContext {
                         self.static_analysis(&mut i.operand)
                     }
                 }
-                Constructor::ApplyTemplates(s) => self.static_analysis(s),
+                Constructor::ApplyTemplates(s)
+                | Constructor::NextIteration(s)
+                | Constructor::Break(s) => self.static_analysis(s),
+                Constructor::Merge(m, a) => {
+                    for s in m {
+                        self.static_analysis(&mut s.select);
+                        for k in &mut s.keys {
+                            self.static_analysis(&mut k.select)
+                        }
+                    }
+                    self.static_analysis(a)
+                }
+                Constructor::Iterate(s, p, b, c) => {
+                    self.static_analysis(s);
+                    self.static_analysis(p);
+                    self.static_analysis(b);
+                    self.static_analysis(c);
+                }
                 Constructor::ForEach(s, t, _g) => {
                     self.static_analysis(s);
                     self.static_analysis(t);
@@ -2846,14 +4347,21 @@ impl StaticContext {
                 Constructor::Copy(_, c) | Constructor::LiteralElement(_, c) => {
                     self.static_analysis(c)
                 }
-                Constructor::DeepCopy(c) => {
+                Constructor::DeepCopy(c)
+                | Constructor::InstanceOf(c, _)
+                | Constructor::Treat(c, _)
+                | Constructor::Convert(c, _)
+                | Constructor::Cast(c, _, _)
+                | Constructor::Castable(c, _, _) => {
                     self.static_analysis(c);
                 }
                 Constructor::Literal(_)
                 | Constructor::LiteralAttribute(_, _)
+                | Constructor::UnescapedText(_)
                 | Constructor::ContextItem
                 | Constructor::Root
                 | Constructor::ApplyImports
+                | Constructor::Empty
                 | Constructor::NotImplemented(_) => {}
             }
         }
@@ -2967,6 +4475,29 @@ fn func_last(
     }
 }
 
+/// fn:subsequence($source, $start, $length?). The items of the source whose position p is such that round($start) <= p < round($start) + round($length).
+pub fn func_subsequence(
+    _: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    _f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    match args.as_slice() {
+        [s, start] | [s, start, _] => {
+            let length = args.get(2).map(numeric_argument).transpose()?;
+            let p = subsequence_positions(s.len() as i128, numeric_argument(start)?, length);
+            Ok(s[(p.start - 1) as usize..(p.end - 1) as usize].to_vec())
+        }
+        _ => Result::Err(Error {
+            kind: ErrorKind::TypeError,
+            message: String::from("wrong number of arguments"),
+        }),
+    }
+}
+
 pub fn func_count(
     _: &Evaluator,
     ctxt: Option<Sequence>,
@@ -3013,7 +4544,7 @@ pub fn func_localname(
         Some(u) => {
             // Current item must be a node
             match *u[posn.unwrap()] {
-                Item::Node(ref n) => Ok(vec![Rc::new(Item::Value(Value::String(
+                Item::Node(ref n) => Ok(vec![Rc::new(Item::Value(Value::from(
                     n.to_name(f).get_localname(),
                 )))]),
                 _ => Result::Err(Error {
@@ -3045,7 +4576,7 @@ pub fn func_name(
             match *u[posn.unwrap()] {
                 Item::Node(ref n) => {
                     // TODO: handle QName prefixes
-                    Ok(vec![Rc::new(Item::Value(Value::String(
+                    Ok(vec![Rc::new(Item::Value(Value::from(
                         n.to_name(f).get_localname(),
                     )))])
                 }
@@ -3075,7 +4606,7 @@ pub fn func_string(
     match args.len() {
         1 => {
             // return string value
-            Ok(vec![Rc::new(Item::Value(Value::String(
+            Ok(vec![Rc::new(Item::Value(Value::from(
                 args[0].to_string(Some(f)),
             )))])
         }
@@ -3095,7 +4626,7 @@ pub fn func_concat(
     _sd: TreeIndex,
     _rd: TreeIndex,
 ) -> Result<Sequence, Error> {
-    Ok(vec![Rc::new(Item::Value(Value::String(args.iter().fold(
+    Ok(vec![Rc::new(Item::Value(Value::from(args.iter().fold(
         String::new(),
         |mut a, b| {
             a.push_str(b.to_string(Some(f)).as_str());
@@ -3171,25 +4702,25 @@ pub fn func_substring(
             // arg[0] is the string to search
             // arg[1] is the index to start at
             // 2-argument version takes the rest of the string
-            Ok(vec![Rc::new(Item::Value(Value::String(
+            Ok(vec![Rc::new(Item::Value(Value::from(
                 args[0]
                     .to_string(Some(f))
                     .graphemes(true)
                     .skip(args[1].to_int()? as usize - 1)
-                    .collect(),
+                    .collect::<String>(),
             )))])
         }
         3 => {
             // arg[0] is the string to search
             // arg[1] is the index to start at
             // arg[2] is the length of the substring to extract
-            Ok(vec![Rc::new(Item::Value(Value::String(
+            Ok(vec![Rc::new(Item::Value(Value::from(
                 args[0]
                     .to_string(Some(f))
                     .graphemes(true)
                     .skip(args[1].to_int()? as usize - 1)
                     .take(args[2].to_int()? as usize)
-                    .collect(),
+                    .collect::<String>(),
             )))])
         }
         _ => Result::Err(Error {
@@ -3219,7 +4750,7 @@ pub fn func_substringbefore(
             {
                 Some(i) => {
                     match args[0].to_string(Some(f)).get(0..i) {
-                        Some(s) => Ok(vec![Rc::new(Item::Value(Value::String(String::from(s))))]),
+                        Some(s) => Ok(vec![Rc::new(Item::Value(Value::from(s)))]),
                         None => {
                             // This shouldn't happen!
                             Result::Err(Error {
@@ -3262,7 +4793,7 @@ pub fn func_substringafter(
                         .to_string(Some(f))
                         .get(i + args[1].to_string(Some(f)).len()..args[0].to_string(Some(f)).len())
                     {
-                        Some(s) => Ok(vec![Rc::new(Item::Value(Value::String(String::from(s))))]),
+                        Some(s) => Ok(vec![Rc::new(Item::Value(Value::from(s)))]),
                         None => {
                             // This shouldn't happen!
                             Result::Err(Error {
@@ -3309,8 +4840,8 @@ pub fn func_normalizespace(
 
     match s {
         Ok(u) => match u {
-            Some(t) => Ok(vec![Rc::new(Item::Value(Value::String(
-                t.split_whitespace().collect(),
+            Some(t) => Ok(vec![Rc::new(Item::Value(Value::from(
+                t.split_whitespace().collect::<String>(),
             )))]),
             None => Result::Err(Error {
                 kind: ErrorKind::DynamicAbsent,
@@ -3367,7 +4898,7 @@ pub fn func_translate(
                     }
                 }
             }
-            Ok(vec![Rc::new(Item::Value(Value::String(result)))])
+            Ok(vec![Rc::new(Item::Value(Value::from(result)))])
         }
         _ => Result::Err(Error {
             kind: ErrorKind::TypeError,
@@ -3376,43 +4907,265 @@ pub fn func_translate(
     }
 }
 
-pub fn func_boolean(
-    _: &Evaluator,
+/// upper-case($arg). The case mapping is the one defined by Unicode, which is not specific to any language.
+///
+/// As an extension, a second argument gives the language, as in upper-case("istanbul", "tr"). Language-sensitive case mapping requires the icu feature.
+pub fn func_uppercase(
+    _e: &Evaluator,
     _ctxt: Option<Sequence>,
     _posn: Option<usize>,
     args: Vec<Sequence>,
-    _f: &mut Forest,
+    f: &mut Forest,
     _sd: TreeIndex,
     _rd: TreeIndex,
 ) -> Result<Sequence, Error> {
-    // must have 1 arguments
-    match args.len() {
-        1 => Ok(vec![Rc::new(Item::Value(Value::Boolean(
-            args[0].to_bool(),
-        )))]),
-        _ => Result::Err(Error {
-            kind: ErrorKind::TypeError,
-            message: String::from("wrong number of arguments"),
-        }),
-    }
+    change_case(args, f, true)
 }
 
-pub fn func_not(
-    _: &Evaluator,
+/// lower-case($arg). See [func_uppercase] for the optional language argument.
+pub fn func_lowercase(
+    _e: &Evaluator,
     _ctxt: Option<Sequence>,
     _posn: Option<usize>,
     args: Vec<Sequence>,
-    _f: &mut Forest,
+    f: &mut Forest,
     _sd: TreeIndex,
     _rd: TreeIndex,
 ) -> Result<Sequence, Error> {
-    // must have 1 arguments
-    match args.len() {
-        1 => Ok(vec![Rc::new(Item::Value(Value::Boolean(
-            !args[0].to_bool(),
-        )))]),
-        _ => Result::Err(Error {
-            kind: ErrorKind::TypeError,
+    change_case(args, f, false)
+}
+
+fn change_case(args: Vec<Sequence>, f: &Forest, upper: bool) -> Result<Sequence, Error> {
+    let s = match args.len() {
+        1 | 2 => args[0].to_string(Some(f)),
+        _ => {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("wrong number of arguments"),
+            ))
+        }
+    };
+    let result = match args.get(1).map(|l| l.to_string(Some(f))) {
+        Some(lang) if !lang.is_empty() => language_case(&s, &lang, upper)?,
+        _ if upper => s.to_uppercase(),
+        _ => s.to_lowercase(),
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(result)))])
+}
+
+#[cfg(feature = "icu")]
+fn language_case(s: &str, lang: &str, upper: bool) -> Result<String, Error> {
+    let id: icu_locid::LanguageIdentifier = lang.parse().map_err(|_| {
+        Error::new(
+            ErrorKind::TypeError,
+            format!("\"{}\" is not a valid language", lang),
+        )
+    })?;
+    let cm = icu_casemap::CaseMapper::new();
+    Ok(if upper {
+        cm.uppercase_to_string(s, &id)
+    } else {
+        cm.lowercase_to_string(s, &id)
+    })
+}
+
+#[cfg(not(feature = "icu"))]
+fn language_case(_s: &str, _lang: &str, _upper: bool) -> Result<String, Error> {
+    Result::Err(Error::new(
+        ErrorKind::NotImplemented,
+        String::from("language-sensitive case mapping requires the icu feature"),
+    ))
+}
+
+/// normalize-unicode($arg, $normalizationForm?). The form is one of "NFC" (the default), "NFD", "NFKC" or "NFKD", ignoring case and surrounding whitespace. If it is the empty string, the value is returned unchanged.
+pub fn func_normalizeunicode(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    let (s, form) = match args.len() {
+        1 => (args[0].to_string(Some(f)), String::from("NFC")),
+        2 => (
+            args[0].to_string(Some(f)),
+            args[1].to_string(Some(f)).trim().to_uppercase(),
+        ),
+        _ => {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("wrong number of arguments"),
+            ))
+        }
+    };
+    let result = match form.as_str() {
+        "" => s,
+        "NFC" => s.nfc().collect(),
+        "NFD" => s.nfd().collect(),
+        "NFKC" => s.nfkc().collect(),
+        "NFKD" => s.nfkd().collect(),
+        _ => {
+            return Result::Err(Error::new(
+                ErrorKind::NotImplemented,
+                format!("normalization form \"{}\" is not supported", form),
+            ))
+        }
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(result)))])
+}
+
+// Percent-encode the UTF-8 bytes of the characters that are not kept
+fn percent_encode(s: &str, keep: impl Fn(char) -> bool) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        if keep(c) {
+            result.push(c)
+        } else {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                result.push_str(format!("%{:02X}", b).as_str())
+            }
+        }
+    }
+    result
+}
+
+// The single string argument of the URI functions
+fn uri_function(
+    args: Vec<Sequence>,
+    f: &Forest,
+    keep: impl Fn(char) -> bool,
+) -> Result<Sequence, Error> {
+    match args.len() {
+        1 => Ok(vec![Rc::new(Item::Value(Value::from(percent_encode(
+            args[0].to_string(Some(f)).as_str(),
+            keep,
+        ))))]),
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        )),
+    }
+}
+
+/// encode-for-uri($uri-part). All characters except the unreserved characters of RFC 3986 are escaped.
+pub fn func_encodeforuri(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    uri_function(args, f, |c| c.is_ascii_alphanumeric() || "-_.~".contains(c))
+}
+
+/// iri-to-uri($iri). Characters that are not allowed in a URI are escaped: non-ASCII characters, control characters, space, and the characters <>"{}|\\^`.
+pub fn func_iritouri(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    uri_function(args, f, |c| {
+        c.is_ascii_graphic() && !"<>\"{}|\\^`".contains(c)
+    })
+}
+
+/// escape-html-uri($uri). Characters other than printable ASCII characters are escaped.
+pub fn func_escapehtmluri(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    uri_function(args, f, |c| (' '..='~').contains(&c))
+}
+
+/// resolve-uri($relative, $base?). The relative reference is resolved against the base URI using RFC 3986. If there is no second argument, then the base URL of the evaluator is used.
+pub fn func_resolveuri(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    if args.is_empty() || args.len() > 2 {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        ));
+    }
+    if args[0].is_empty() {
+        return Ok(vec![]);
+    }
+    let relative = args[0].to_string(Some(f));
+    let base = match args.get(1) {
+        Some(b) => b.to_string(Some(f)),
+        None => match &e.base {
+            Some(u) => u.to_string(),
+            None => {
+                return Result::Err(Error::new(
+                    ErrorKind::DynamicAbsent,
+                    String::from("no base URI"),
+                ))
+            }
+        },
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(resolve_reference(
+        base.as_str(),
+        relative.as_str(),
+    )?)))])
+}
+
+pub fn func_boolean(
+    _: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    _f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    // must have 1 arguments
+    match args.len() {
+        1 => Ok(vec![Rc::new(Item::Value(Value::Boolean(
+            args[0].to_bool(),
+        )))]),
+        _ => Result::Err(Error {
+            kind: ErrorKind::TypeError,
+            message: String::from("wrong number of arguments"),
+        }),
+    }
+}
+
+pub fn func_not(
+    _: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    _f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    // must have 1 arguments
+    match args.len() {
+        1 => Ok(vec![Rc::new(Item::Value(Value::Boolean(
+            !args[0].to_bool(),
+        )))]),
+        _ => Result::Err(Error {
+            kind: ErrorKind::TypeError,
             message: String::from("wrong number of arguments"),
         }),
     }
@@ -3646,7 +5399,9 @@ pub fn func_current_date(
     // TODO: check number of arguments
     // TODO: do the check in static analysis phase
 
-    Ok(vec![Rc::new(Item::Value(Value::Date(Local::today())))])
+    Ok(vec![Rc::new(Item::Value(Value::Date(
+        Local::now().date_naive(),
+    )))])
 }
 
 pub fn func_current_time(
@@ -3696,12 +5451,12 @@ pub fn func_format_date_time(
                 1 => {
                     match *args[0][0] {
                         Item::Value(Value::DateTime(dt)) => Ok(vec![Rc::new(Item::Value(
-                            Value::String(dt.format(&pic).to_string()),
+                            Value::from(dt.format(&pic).to_string()),
                         ))]),
                         Item::Value(Value::String(ref s)) => {
                             // Try and coerce into a DateTime value
-                            match DateTime::<FixedOffset>::parse_from_rfc3339(s.as_str()) {
-                                Ok(dt) => Ok(vec![Rc::new(Item::Value(Value::String(
+                            match DateTime::<FixedOffset>::parse_from_rfc3339(s) {
+                                Ok(dt) => Ok(vec![Rc::new(Item::Value(Value::from(
                                     dt.format(&pic).to_string(),
                                 )))]),
                                 Err(_) => Result::Err(Error {
@@ -3733,7 +5488,615 @@ pub fn func_format_date_time(
     }
 }
 
-pub fn func_format_date(
+pub fn func_format_date(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    // must have 2 or 5 arguments
+    // TODO: implement 5 argument version
+
+    match args.len() {
+        2 => {
+            // First argument is the date value
+            // Second argument is the picture
+            let pic = match picture_parse(&args[1].to_string(Some(f))) {
+                Ok(p) => p,
+                Err(_) => {
+                    return Result::Err(Error {
+                        kind: ErrorKind::Unknown,
+                        message: String::from("bad picture"),
+                    })
+                }
+            };
+            match args[0].len() {
+                0 => Ok(vec![]), // Empty value returns empty sequence
+                1 => {
+                    match *args[0][0] {
+                        Item::Value(Value::Date(dt)) => Ok(vec![Rc::new(Item::Value(
+                            Value::from(dt.format(&pic).to_string()),
+                        ))]),
+                        Item::Value(Value::String(ref s)) => {
+                            // Try and coerce into a Date value
+                            let a = format!("{}T00:00:00Z", s);
+                            match DateTime::<FixedOffset>::parse_from_rfc3339(a.as_str()) {
+                                Ok(dt) => Ok(vec![Rc::new(Item::Value(Value::from(
+                                    dt.date().format(&pic).to_string(),
+                                )))]),
+                                Err(_) => Result::Err(Error {
+                                    kind: ErrorKind::TypeError,
+                                    message: String::from("unable to determine date value"),
+                                }),
+                            }
+                        }
+                        _ => Result::Err(Error {
+                            kind: ErrorKind::TypeError,
+                            message: String::from("not a date value"),
+                        }),
+                    }
+                }
+                _ => Result::Err(Error {
+                    kind: ErrorKind::TypeError,
+                    message: String::from("not a singleton sequence"),
+                }),
+            }
+        }
+        5 => Result::Err(Error {
+            kind: ErrorKind::NotImplemented,
+            message: String::from("not yet implemented"),
+        }),
+        _ => Result::Err(Error {
+            kind: ErrorKind::TypeError,
+            message: String::from("wrong number of arguments"),
+        }),
+    }
+}
+
+pub fn func_format_time(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    // must have 2 or 5 arguments
+    // TODO: implement 5 argument version
+
+    match args.len() {
+        2 => {
+            // First argument is the time value
+            // Second argument is the picture
+            let pic = match picture_parse(&args[1].to_string(Some(f))) {
+                Ok(p) => p,
+                Err(_) => {
+                    return Result::Err(Error {
+                        kind: ErrorKind::Unknown,
+                        message: String::from("bad picture"),
+                    })
+                }
+            };
+            match args[0].len() {
+                0 => Ok(vec![]), // Empty value returns empty sequence
+                1 => {
+                    match *args[0][0] {
+                        Item::Value(Value::Time(dt)) => Ok(vec![Rc::new(Item::Value(
+                            Value::from(dt.format(&pic).to_string()),
+                        ))]),
+                        Item::Value(Value::String(ref s)) => {
+                            // Try and coerce into a DateTime value
+                            let a = format!("1900-01-01T{}Z", s);
+                            match DateTime::<FixedOffset>::parse_from_rfc3339(a.as_str()) {
+                                Ok(dt) => Ok(vec![Rc::new(Item::Value(Value::from(
+                                    dt.time().format(&pic).to_string(),
+                                )))]),
+                                Err(_) => Result::Err(Error {
+                                    kind: ErrorKind::TypeError,
+                                    message: String::from("unable to determine time value"),
+                                }),
+                            }
+                        }
+                        _ => Result::Err(Error {
+                            kind: ErrorKind::TypeError,
+                            message: String::from("not a time value"),
+                        }),
+                    }
+                }
+                _ => Result::Err(Error {
+                    kind: ErrorKind::TypeError,
+                    message: String::from("not a singleton sequence"),
+                }),
+            }
+        }
+        5 => Result::Err(Error {
+            kind: ErrorKind::NotImplemented,
+            message: String::from("not yet implemented"),
+        }),
+        _ => Result::Err(Error {
+            kind: ErrorKind::TypeError,
+            message: String::from("wrong number of arguments"),
+        }),
+    }
+}
+
+pub fn func_current_grouping_key(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    _args: Vec<Sequence>,
+    _f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    match e.dc.current_grouping_key.borrow().last() {
+        Some(k) => match k {
+            Some(l) => Ok(vec![l.clone()]),
+            None => Ok(vec![]),
+        },
+        None => Result::Err(Error {
+            kind: ErrorKind::DynamicAbsent,
+            message: String::from("no current grouping key"),
+        }),
+    }
+}
+
+pub fn func_current_group(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    _args: Vec<Sequence>,
+    _f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    match e.dc.current_group.borrow().last() {
+        Some(k) => match k {
+            Some(l) => Ok(l.clone()),
+            None => Ok(vec![]),
+        },
+        None => Result::Err(Error {
+            kind: ErrorKind::DynamicAbsent,
+            message: String::from("no current group"),
+        }),
+    }
+}
+
+/// XSLT 15.6 current-merge-group(). With an argument, only the items from the merge source of that name.
+pub fn func_current_merge_group(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    let source = args.first().map(|a| a.to_string(Some(f)));
+    match e.dc.current_merge.borrow().last() {
+        Some(g) => Ok(g
+            .items
+            .iter()
+            .filter(|(n, _)| source.is_none() || *n == source)
+            .map(|(_, i)| i.clone())
+            .collect()),
+        None => Result::Err(Error::new(
+            ErrorKind::DynamicAbsent,
+            String::from("no current merge group"),
+        )),
+    }
+}
+
+/// XSLT 15.6 current-merge-key()
+pub fn func_current_merge_key(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    _args: Vec<Sequence>,
+    _f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    match e.dc.current_merge.borrow().last() {
+        Some(g) => Ok(g.key.clone()),
+        None => Result::Err(Error::new(
+            ErrorKind::DynamicAbsent,
+            String::from("no current merge key"),
+        )),
+    }
+}
+
+/// XSLT 20.1 document(). Each URI is resolved against the base URL of the stylesheet. A document is only fetched and parsed once; subsequent calls with the same URL return the same document node.
+pub fn func_document(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    if args.len() != 1 {
+        return Result::Err(Error {
+            kind: ErrorKind::TypeError,
+            message: String::from("wrong number of arguments"),
+        });
+    }
+    let mut result = Sequence::new();
+    for u in &args[0] {
+        result.push_node(load_document(e, f, u.to_string(Some(f)).as_str())?)
+    }
+    result.sort_document_order(f)?;
+    Ok(result)
+}
+
+/// XSLT 20.4 generate-id(). The identifier is made from the node's tree, slot and generation (see [NodeRef::to_id](crate::forest::NodeRef::to_id)), so it is the same for each call on the same node and unique within the [Forest].
+pub fn func_generateid(
+    _e: &Evaluator,
+    ctxt: Option<Sequence>,
+    posn: Option<usize>,
+    args: Vec<Sequence>,
+    _f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    let i = match args.len() {
+        0 => match (ctxt, posn) {
+            (Some(c), Some(p)) => Some(Rc::clone(&c[p])),
+            _ => {
+                return Result::Err(Error::new(
+                    ErrorKind::DynamicAbsent,
+                    String::from("no context item"),
+                ))
+            }
+        },
+        1 => match args[0].len() {
+            0 => None,
+            1 => Some(Rc::clone(&args[0][0])),
+            _ => {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    String::from("argument must be a single node"),
+                ))
+            }
+        },
+        _ => {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("wrong number of arguments"),
+            ))
+        }
+    };
+    let id = match i.as_deref() {
+        Some(Item::Node(n)) => n.node_ref().to_id(),
+        Some(_) => {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("argument is not a node"),
+            ))
+        }
+        None => String::new(),
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(id)))])
+}
+
+// The node that a function applies to: the given argument, or else the context item
+fn node_argument(
+    arg: Option<&Sequence>,
+    ctxt: &Option<Sequence>,
+    posn: Option<usize>,
+) -> Result<Node, Error> {
+    let i = match (arg, ctxt, posn) {
+        (Some(a), _, _) if a.len() == 1 => Rc::clone(&a[0]),
+        (Some(_), _, _) => {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("argument must be a single node"),
+            ))
+        }
+        (None, Some(c), Some(p)) => Rc::clone(&c[p]),
+        _ => {
+            return Result::Err(Error::new(
+                ErrorKind::DynamicAbsent,
+                String::from("no context item"),
+            ))
+        }
+    };
+    match *i {
+        Item::Node(n) => Ok(n),
+        _ => Result::Err(Error::new(
+            ErrorKind::ContextNotNode,
+            String::from("not a node"),
+        )),
+    }
+}
+
+// The Document-type node of the tree that contains a node
+fn document_node(n: &Node, f: &Forest) -> Result<Node, Error> {
+    Ok(f.get_ref(n.tree_index())
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?
+        .get_doc_node())
+}
+
+// Find, or build, the index of a key for the document that contains a node.
+// Each document has its own index, so a key only finds nodes in the same document as the node. The index is kept with the tree, and is discarded when the tree changes.
+fn key_index(
+    e: &Evaluator,
+    name: &QualifiedName,
+    n: &Node,
+    f: &mut Forest,
+    sd: TreeIndex,
+    rd: TreeIndex,
+) -> Result<Arc<KeyIndex>, Error> {
+    let tree = f.get_ref(n.tree_index()).ok_or(Error::new(
+        ErrorKind::Unknown,
+        String::from("unable to find tree"),
+    ))?;
+    if let Some(i) = tree.key_index(name) {
+        return Ok(i);
+    }
+    let decls = e.keys.get(name).ok_or(Error::new(
+        ErrorKind::StaticUndefined,
+        format!("XTDE1260: no key named \"{}\"", name.to_string()),
+    ))?;
+
+    // Every node in the document may be indexed, including attributes
+    let d = document_node(n, f)?;
+    let mut nodes = vec![d];
+    let mut it = d.descend_iter(f);
+    while let Some(c) = it.next(f) {
+        nodes.push(c);
+        let mut at = c.attribute_iter(f);
+        while let Some(a) = at.next() {
+            nodes.push(a)
+        }
+    }
+
+    let mut index = KeyIndex::new();
+    for c in nodes {
+        let i = Rc::new(Item::Node(c));
+        for decl in decls {
+            if e.item_matches(&decl.pattern, &i, f, sd, rd)? {
+                let v = e.evaluate(Some(vec![Rc::clone(&i)]), Some(0), &decl.key_use, f, sd, rd)?;
+                for u in v {
+                    let entry = index.entry(u.to_string(Some(f))).or_default();
+                    if entry.last() != Some(&c) {
+                        entry.push(c)
+                    }
+                }
+            }
+        }
+    }
+    let index = Arc::new(index);
+    if let Some(t) = f.get_ref(n.tree_index()) {
+        t.set_key_index(name.clone(), Arc::clone(&index))
+    }
+    Ok(index)
+}
+
+// The name of a key, as given to the key() function. A prefix is resolved when the stylesheet is compiled, giving a URIQualifiedName; it can't be resolved when the name is computed as the stylesheet is evaluated.
+fn key_name(s: &str) -> Result<QualifiedName, Error> {
+    let s = s.trim();
+    if let Some((u, l)) = s.strip_prefix("Q{").and_then(|r| r.split_once('}')) {
+        return Ok(QualifiedName::new(
+            Some(u.to_string()).filter(|u| !u.is_empty()),
+            None,
+            l.to_string(),
+        ));
+    }
+    match s.split_once(':') {
+        Some((p, _)) => Result::Err(Error::new(
+            ErrorKind::StaticUndefined,
+            format!(
+                "XTDE1260: the prefix \"{}\" of the key name \"{}\" is not declared",
+                p, s
+            ),
+        )),
+        None => Ok(QualifiedName::new(None, None, s.to_string())),
+    }
+}
+
+/// XSLT 20.2.2 key($name, $value, $top?). Finds the nodes that have the value of the named key, in the document that contains $top, or the context node if it is not given. If $top is given then only nodes that are it, or its descendants, are returned.
+///
+/// The name of the key is compared as an expanded QName; a prefix in a literal name is resolved when the stylesheet is compiled. An index of the key is built for each document the first time the key is used with a node from that document, and kept until the document changes.
+pub fn func_key(
+    e: &Evaluator,
+    ctxt: Option<Sequence>,
+    posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    sd: TreeIndex,
+    rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    if args.len() != 2 && args.len() != 3 {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        ));
+    }
+    let top = node_argument(args.get(2), &ctxt, posn)?;
+    let name = key_name(args[0].to_string(Some(f)).as_str())?;
+    let index = key_index(e, &name, &top, f, sd, rd)?;
+    let mut result = Sequence::new();
+    for v in &args[1] {
+        if let Some(nodes) = index.get(&v.to_string(Some(f))) {
+            for n in nodes {
+                result.push_node(*n)
+            }
+        }
+    }
+    if args.len() == 3 {
+        result.retain(|i| match **i {
+            Item::Node(n) => {
+                let mut a = Some(n);
+                while let Some(p) = a {
+                    if p == top {
+                        return true;
+                    }
+                    a = p.parent_node(f)
+                }
+                false
+            }
+            _ => false,
+        })
+    }
+    result.sort_document_order(f)?;
+    Ok(result)
+}
+
+/// id($idrefs, $node?). Finds the elements that have an ID in the whitespace-separated list $idrefs, in the document that contains $node, or the context node if it is not given.
+///
+/// An element's ID is the value of its xml:id attribute, or of an attribute that the document type declaration declares to have type ID (see [Node::is_id]). If more than one element has the same ID then only the first is returned.
+pub fn func_id(
+    _e: &Evaluator,
+    ctxt: Option<Sequence>,
+    posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    if args.len() != 1 && args.len() != 2 {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        ));
+    }
+    let n = node_argument(args.get(1), &ctxt, posn)?;
+    let mut idrefs: HashSet<String> = HashSet::new();
+    for i in &args[0] {
+        idrefs.extend(i.to_string(Some(f)).split_whitespace().map(String::from))
+    }
+    let mut result = Sequence::new();
+    let mut it = document_node(&n, f)?.descend_iter(f);
+    while let Some(c) = it.next(f) {
+        if idrefs.is_empty() {
+            break;
+        }
+        if c.node_type(f) != NodeType::Element {
+            continue;
+        }
+        let mut v = None;
+        let mut at = c.attribute_iter(f);
+        while let Some(a) = at.next() {
+            if a.is_id(f) {
+                v = Some(a.to_string(f));
+                break;
+            }
+        }
+        let v = match v {
+            Some(v) => v,
+            None => continue,
+        };
+        if idrefs.remove(v.trim()) {
+            result.push_node(c)
+        }
+    }
+    Ok(result)
+}
+
+/// The XSLT instructions and declarations that are supported, as reported by element-available(). This is kept in step with the elements that the [xslt](crate::xslt) module compiles.
+pub const XSLT_ELEMENTS_AVAILABLE: &[&str] = &[
+    "apply-imports",
+    "apply-templates",
+    "attribute",
+    "break",
+    "choose",
+    "copy",
+    "copy-of",
+    "fallback",
+    "for-each",
+    "for-each-group",
+    "if",
+    "import",
+    "include",
+    "iterate",
+    "key",
+    "merge",
+    "next-iteration",
+    "output",
+    "preserve-space",
+    "sequence",
+    "strip-space",
+    "template",
+    "text",
+];
+
+const XSLT_NAMESPACE: &str = "http://www.w3.org/1999/XSL/Transform";
+const FN_NAMESPACE: &str = "http://www.w3.org/2005/xpath-functions";
+
+// The local part of a name given as a string, if the name is in the given namespace.
+// The name may be a URIQualifiedName, or a lexical QName with the conventional prefix for the namespace, since the namespace declarations of the stylesheet are not available when it is evaluated.
+fn local_name_in(name: &str, nsuri: &str, prefix: &str, unprefixed: bool) -> Option<String> {
+    let name = name.trim();
+    if let Some(r) = name.strip_prefix("Q{") {
+        let (ns, local) = r.split_once('}')?;
+        return (ns == nsuri).then(|| local.to_string());
+    }
+    match name.split_once(':') {
+        Some((p, local)) => (p == prefix).then(|| local.to_string()),
+        None => unprefixed.then(|| name.to_string()),
+    }
+}
+
+// The single string argument of the capability functions
+fn name_argument(args: &[Sequence], f: &Forest, arity: &[usize]) -> Result<String, Error> {
+    if !arity.contains(&args.len()) {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        ));
+    }
+    Ok(args[0].to_string(Some(f)))
+}
+
+/// XSLT 20.6 element-available($element-name). True if the name, which must use the prefix "xsl" or be a URIQualifiedName, is an XSLT instruction or declaration that is supported (see [XSLT_ELEMENTS_AVAILABLE]).
+pub fn func_elementavailable(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    let name = name_argument(&args, f, &[1])?;
+    let available = local_name_in(&name, XSLT_NAMESPACE, "xsl", false)
+        .is_some_and(|l| XSLT_ELEMENTS_AVAILABLE.contains(&l.as_str()));
+    Ok(vec![Rc::new(Item::Value(Value::from(available)))])
+}
+
+/// XSLT 20.5 function-available($function-name, $arity?). True if the function is in the static context of the stylesheet (see [Evaluator::set_available_functions]). The name may be unprefixed, use the prefix "fn", or be a URIQualifiedName. The arity of functions is not recorded, so it is not checked.
+pub fn func_functionavailable(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    let name = name_argument(&args, f, &[1, 2])?;
+    let available = match local_name_in(&name, FN_NAMESPACE, "fn", true) {
+        Some(l) if e.functions.is_empty() => StaticContext::new_with_xslt_builtins()
+            .funcs
+            .borrow()
+            .contains_key(&l),
+        Some(l) => e.functions.contains(&l),
+        None => false,
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(available)))])
+}
+
+/// XSLT 20.4.5 system-property($property-name). Describes this processor. The name must use the prefix "xsl" or be a URIQualifiedName. Unknown properties are the empty string.
+pub fn func_systemproperty(
     _e: &Evaluator,
     _ctxt: Option<Sequence>,
     _posn: Option<usize>,
@@ -3742,67 +6105,67 @@ pub fn func_format_date(
     _sd: TreeIndex,
     _rd: TreeIndex,
 ) -> Result<Sequence, Error> {
-    // must have 2 or 5 arguments
-    // TODO: implement 5 argument version
+    let name = name_argument(&args, f, &[1])?;
+    let v = match local_name_in(&name, XSLT_NAMESPACE, "xsl", false).as_deref() {
+        Some("version") => "3.0",
+        Some("vendor") | Some("product-name") => "xrust",
+        Some("vendor-url") => "https://github.com/ballsteve/xrust",
+        Some("product-version") => env!("CARGO_PKG_VERSION"),
+        Some("supports-serialization") => "yes",
+        Some("is-schema-aware")
+        | Some("supports-backwards-compatibility")
+        | Some("supports-namespace-axis")
+        | Some("supports-streaming")
+        | Some("supports-dynamic-evaluation")
+        | Some("supports-higher-order-functions") => "no",
+        _ => "",
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(v)))])
+}
 
-    match args.len() {
-        2 => {
-            // First argument is the date value
-            // Second argument is the picture
-            let pic = match picture_parse(&args[1].to_string(Some(f))) {
-                Ok(p) => p,
-                Err(_) => {
-                    return Result::Err(Error {
-                        kind: ErrorKind::Unknown,
-                        message: String::from("bad picture"),
-                    })
-                }
-            };
-            match args[0].len() {
-                0 => Ok(vec![]), // Empty value returns empty sequence
-                1 => {
-                    match *args[0][0] {
-                        Item::Value(Value::Date(dt)) => Ok(vec![Rc::new(Item::Value(
-                            Value::String(dt.format(&pic).to_string()),
-                        ))]),
-                        Item::Value(Value::String(ref s)) => {
-                            // Try and coerce into a Date value
-                            let a = format!("{}T00:00:00Z", s);
-                            match DateTime::<FixedOffset>::parse_from_rfc3339(a.as_str()) {
-                                Ok(dt) => Ok(vec![Rc::new(Item::Value(Value::String(
-                                    dt.date().format(&pic).to_string(),
-                                )))]),
-                                Err(_) => Result::Err(Error {
-                                    kind: ErrorKind::TypeError,
-                                    message: String::from("unable to determine date value"),
-                                }),
-                            }
-                        }
-                        _ => Result::Err(Error {
-                            kind: ErrorKind::TypeError,
-                            message: String::from("not a date value"),
-                        }),
-                    }
-                }
-                _ => Result::Err(Error {
-                    kind: ErrorKind::TypeError,
-                    message: String::from("not a singleton sequence"),
-                }),
-            }
+// Resolve a URI against the base URL, and fetch the resource
+fn load_text(e: &Evaluator, href: &str) -> Result<String, Error> {
+    let url = resolve_href(e.base.as_ref(), href)?;
+    match &e.resolver {
+        Some(r) => r.resolve(&url),
+        None => NoFetchResolver.resolve(&url),
+    }
+}
+
+// Find the document node for a URI. A document is only fetched and parsed once.
+fn load_document(e: &Evaluator, f: &mut Forest, href: &str) -> Result<Node, Error> {
+    let url = resolve_href(e.base.as_ref(), href)?;
+    let t = match e.dc.get_document(&url) {
+        Some(t) => t,
+        None => {
+            let xml = load_text(e, url.as_str())?;
+            let t = f.grow_tree(xml.as_str())?;
+            e.dc.add_document(url, t);
+            t
         }
-        5 => Result::Err(Error {
-            kind: ErrorKind::NotImplemented,
-            message: String::from("not yet implemented"),
-        }),
-        _ => Result::Err(Error {
-            kind: ErrorKind::TypeError,
-            message: String::from("wrong number of arguments"),
-        }),
+    };
+    Ok(f.get_ref(t)
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?
+        .get_doc_node())
+}
+
+// The URI argument of doc() and the other resource functions. None if it is the empty sequence.
+fn href_argument(args: &[Sequence], f: &Forest, arity: &[usize]) -> Result<Option<String>, Error> {
+    if !arity.contains(&args.len()) {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        ));
     }
+    Ok((!args[0].is_empty()).then(|| args[0].to_string(Some(f))))
 }
 
-pub fn func_format_time(
-    _e: &Evaluator,
+/// doc($uri). The URI is resolved against the base URL. A document is only fetched and parsed once, and is shared with document() and doc-available().
+pub fn func_doc(
+    e: &Evaluator,
     _ctxt: Option<Sequence>,
     _posn: Option<usize>,
     args: Vec<Sequence>,
@@ -3810,114 +6173,161 @@ pub fn func_format_time(
     _sd: TreeIndex,
     _rd: TreeIndex,
 ) -> Result<Sequence, Error> {
-    // must have 2 or 5 arguments
-    // TODO: implement 5 argument version
+    match href_argument(&args, f, &[1])? {
+        Some(u) => Ok(vec![Rc::new(Item::Node(load_document(e, f, u.as_str())?))]),
+        None => Ok(vec![]),
+    }
+}
 
-    match args.len() {
-        2 => {
-            // First argument is the time value
-            // Second argument is the picture
-            let pic = match picture_parse(&args[1].to_string(Some(f))) {
-                Ok(p) => p,
-                Err(_) => {
-                    return Result::Err(Error {
-                        kind: ErrorKind::Unknown,
-                        message: String::from("bad picture"),
-                    })
-                }
-            };
-            match args[0].len() {
-                0 => Ok(vec![]), // Empty value returns empty sequence
-                1 => {
-                    match *args[0][0] {
-                        Item::Value(Value::Time(dt)) => Ok(vec![Rc::new(Item::Value(
-                            Value::String(dt.format(&pic).to_string()),
-                        ))]),
-                        Item::Value(Value::String(ref s)) => {
-                            // Try and coerce into a DateTime value
-                            let a = format!("1900-01-01T{}Z", s);
-                            match DateTime::<FixedOffset>::parse_from_rfc3339(a.as_str()) {
-                                Ok(dt) => Ok(vec![Rc::new(Item::Value(Value::String(
-                                    dt.time().format(&pic).to_string(),
-                                )))]),
-                                Err(_) => Result::Err(Error {
-                                    kind: ErrorKind::TypeError,
-                                    message: String::from("unable to determine time value"),
-                                }),
-                            }
-                        }
-                        _ => Result::Err(Error {
-                            kind: ErrorKind::TypeError,
-                            message: String::from("not a time value"),
-                        }),
-                    }
-                }
-                _ => Result::Err(Error {
-                    kind: ErrorKind::TypeError,
-                    message: String::from("not a singleton sequence"),
-                }),
-            }
+/// doc-available($uri). Returns true if doc() would return a document. Any error in resolving, fetching or parsing the document returns false, rather than being raised.
+pub fn func_docavailable(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    let available = match href_argument(&args, f, &[1])? {
+        Some(u) => load_document(e, f, u.as_str()).is_ok(),
+        None => false,
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(available)))])
+}
+
+/// json-to-xml($json-text). Returns a new document that has the XML representation of the JSON text. Options are not supported, since there are no maps.
+pub fn func_jsontoxml(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    match args.as_slice() {
+        [s] if s.is_empty() => Ok(vec![]),
+        [s] => {
+            let t = json_to_xml(f, s.to_string(Some(f)).as_str())?;
+            Ok(vec![Rc::new(Item::Node(
+                f.get_ref(t).unwrap().get_doc_node(),
+            ))])
         }
-        5 => Result::Err(Error {
-            kind: ErrorKind::NotImplemented,
-            message: String::from("not yet implemented"),
-        }),
-        _ => Result::Err(Error {
-            kind: ErrorKind::TypeError,
-            message: String::from("wrong number of arguments"),
-        }),
+        [_, _] => Result::Err(Error::new(
+            ErrorKind::NotImplemented,
+            String::from("options for json-to-xml are not supported"),
+        )),
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        )),
     }
 }
 
-pub fn func_current_grouping_key(
-    e: &Evaluator,
+/// xml-to-json($input). The input is a node that has the XML representation of JSON. Options are not supported, since there are no maps.
+pub fn func_xmltojson(
+    _e: &Evaluator,
     _ctxt: Option<Sequence>,
     _posn: Option<usize>,
-    _args: Vec<Sequence>,
-    _f: &mut Forest,
+    args: Vec<Sequence>,
+    f: &mut Forest,
     _sd: TreeIndex,
     _rd: TreeIndex,
 ) -> Result<Sequence, Error> {
-    match e.dc.current_grouping_key.borrow().last() {
-        Some(k) => match k {
-            Some(l) => Ok(vec![l.clone()]),
-            None => Ok(vec![]),
+    match args.as_slice() {
+        [s] if s.is_empty() => Ok(vec![]),
+        [s] => match &*s[0] {
+            Item::Node(n) if s.len() == 1 => {
+                Ok(vec![Rc::new(Item::Value(Value::from(xml_to_json(f, *n)?)))])
+            }
+            _ => Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("xml-to-json requires a single node"),
+            )),
         },
-        None => Result::Err(Error {
-            kind: ErrorKind::DynamicAbsent,
-            message: String::from("no current grouping key"),
-        }),
+        [_, _] => Result::Err(Error::new(
+            ErrorKind::NotImplemented,
+            String::from("options for xml-to-json are not supported"),
+        )),
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        )),
     }
 }
 
-pub fn func_current_group(
+/// serialize($arg). The sequence is serialized using the adaptive output method. Serialization parameters are not supported, since there are no maps.
+pub fn func_serialize(
+    _e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    match args.as_slice() {
+        [s] => Ok(vec![Rc::new(Item::Value(Value::from(
+            s.to_adaptive(Some(f)),
+        )))]),
+        [_, _] => Result::Err(Error::new(
+            ErrorKind::NotImplemented,
+            String::from("serialization parameters are not supported"),
+        )),
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("wrong number of arguments"),
+        )),
+    }
+}
+
+/// unparsed-text($href, $encoding?). The resource is decoded by the resolver, so the encoding argument is ignored.
+pub fn func_unparsedtext(
     e: &Evaluator,
     _ctxt: Option<Sequence>,
     _posn: Option<usize>,
-    _args: Vec<Sequence>,
-    _f: &mut Forest,
+    args: Vec<Sequence>,
+    f: &mut Forest,
     _sd: TreeIndex,
     _rd: TreeIndex,
 ) -> Result<Sequence, Error> {
-    match e.dc.current_group.borrow().last() {
-        Some(k) => match k {
-            Some(l) => Ok(l.clone()),
-            None => Ok(vec![]),
-        },
-        None => Result::Err(Error {
-            kind: ErrorKind::DynamicAbsent,
-            message: String::from("no current group"),
-        }),
+    match href_argument(&args, f, &[1, 2])? {
+        Some(u) => Ok(vec![Rc::new(Item::Value(Value::from(load_text(
+            e,
+            u.as_str(),
+        )?)))]),
+        None => Ok(vec![]),
     }
 }
 
+/// unparsed-text-available($href, $encoding?). Returns true if unparsed-text() would return the resource, and false if there is any error.
+pub fn func_unparsedtextavailable(
+    e: &Evaluator,
+    _ctxt: Option<Sequence>,
+    _posn: Option<usize>,
+    args: Vec<Sequence>,
+    f: &mut Forest,
+    _sd: TreeIndex,
+    _rd: TreeIndex,
+) -> Result<Sequence, Error> {
+    let available = match href_argument(&args, f, &[1, 2])? {
+        Some(u) => load_text(e, u.as_str()).is_ok(),
+        None => false,
+    };
+    Ok(vec![Rc::new(Item::Value(Value::from(available)))])
+}
+
 pub fn format_constructor(c: &Vec<Constructor>, i: usize) -> String {
     let mut result = String::new();
     for v in c {
         result.push_str(", ");
         let t = match v {
             Constructor::Literal(l) => {
-                format!("{:in$} Construct literal \"{}\"", "", l.to_string(), in=i)
+                format!("{:in$} Construct literal \"{}\"", "", l, in=i)
+            }
+            Constructor::UnescapedText(v) => {
+                format!("{:in$} Construct unescaped text \"{}\"", "", v, in=i)
             }
             Constructor::LiteralAttribute(qn, v) => {
                 format!("{:in$} Construct literal attribute \"{}\" with value \"{}\"", "",
@@ -3933,6 +6343,31 @@ pub fn format_constructor(c: &Vec<Constructor>, i: usize) -> String {
             Constructor::Copy(_sel, c) => {
                 format!("{:in$} Construct copy with content:\n{}", "",
 	  format_constructor(&c, i + 4),
+	  in=i)
+            }
+            Constructor::InstanceOf(c, t) => {
+                format!("{:in$} Test whether sequence is an instance of {}:\n{}", "", t,
+	  format_constructor(c, i + 4),
+	  in=i)
+            }
+            Constructor::Treat(c, t) => {
+                format!("{:in$} Treat sequence as {}:\n{}", "", t,
+	  format_constructor(c, i + 4),
+	  in=i)
+            }
+            Constructor::Convert(c, t) => {
+                format!("{:in$} Convert sequence to {}:\n{}", "", t,
+	  format_constructor(c, i + 4),
+	  in=i)
+            }
+            Constructor::Cast(c, t, _) => {
+                format!("{:in$} Cast value to {}:\n{}", "", t,
+	  format_constructor(c, i + 4),
+	  in=i)
+            }
+            Constructor::Castable(c, t, _) => {
+                format!("{:in$} Test whether value is castable to {}:\n{}", "", t,
+	  format_constructor(c, i + 4),
 	  in=i)
             }
             Constructor::DeepCopy(c) => {
@@ -3996,6 +6431,9 @@ pub fn format_constructor(c: &Vec<Constructor>, i: usize) -> String {
             Constructor::Concat(_v) => {
                 format!("{:in$} concat constructor", "", in=i)
             }
+            Constructor::ValueTemplate(_v) => {
+                format!("{:in$} value template constructor", "", in=i)
+            }
             Constructor::Range(_v) => {
                 format!("{:in$} range constructor", "", in=i)
             }
@@ -4018,6 +6456,28 @@ pub fn format_constructor(c: &Vec<Constructor>, i: usize) -> String {
             Constructor::Loop(_, _) => {
                 format!("{:in$} loop constructor", "", in=i)
             }
+            Constructor::Let(_, _) => {
+                format!("{:in$} let constructor", "", in=i)
+            }
+            Constructor::Quantified(_, _, _) => {
+                format!("{:in$} quantified constructor", "", in=i)
+            }
+            Constructor::SimpleMap(v) => {
+                let mut result =
+                    format!("{:in$} simple map constructor of {} operands:\n", "", v.len(), in=i);
+                for c in v {
+                    result.push_str(format_constructor(c, i + 4).as_str())
+                }
+                result
+            }
+            Constructor::SetOperation(o, v) => {
+                let mut result =
+                    format!("{:in$} {} constructor of {} operands:\n", "", o, v.len(), in=i);
+                for c in v {
+                    result.push_str(format_constructor(c, i + 4).as_str())
+                }
+                result
+            }
             Constructor::Switch(_, _) => {
                 format!("{:in$} switch constructor", "", in=i)
             }
@@ -4030,6 +6490,21 @@ pub fn format_constructor(c: &Vec<Constructor>, i: usize) -> String {
             Constructor::ForEach(_, _, _) => {
                 format!("{:in$} for-each constructor", "", in=i)
             }
+            Constructor::Iterate(_, _, _, _) => {
+                format!("{:in$} iterate constructor", "", in=i)
+            }
+            Constructor::Merge(m, _) => {
+                format!("{:in$} merge constructor with {} sources", "", m.len(), in=i)
+            }
+            Constructor::NextIteration(_) => {
+                format!("{:in$} next-iteration constructor", "", in=i)
+            }
+            Constructor::Break(_) => {
+                format!("{:in$} break constructor", "", in=i)
+            }
+            Constructor::Empty => {
+                format!("{:in$} empty constructor", "", in=i)
+            }
             Constructor::NotImplemented(m) => {
                 format!("{:in$} NotImplemented constructor: {}", "", m, in=i)
             }
@@ -4043,6 +6518,53 @@ pub fn format_constructor(c: &Vec<Constructor>, i: usize) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn node_test_matches() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<p:a xmlns:p='urn:p' p:x='1' y='2'><b/><?go here?><?stop?></p:a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = a.child_iter();
+        let b = cit.next(&f).unwrap();
+        let go = cit.next(&f).unwrap();
+        let nt = |s: &str| NodeTest::try_from(s).expect("invalid node test");
+        assert!(nt("p:a").matches(&f, &a));
+        assert!(nt("p:*").matches(&f, &a));
+        assert!(nt("*:a").matches(&f, &a));
+        assert!(nt("a").matches(&f, &a));
+        assert!(!nt("q:*").matches(&f, &a));
+        assert!(!nt("p:*").matches(&f, &b));
+        assert!(nt("*").matches(&f, &b));
+        assert!(!nt("*").matches(&f, &go));
+        let ns = NodeTest::Name(NameTest {
+            ns: Some(WildcardOrName::Name(String::from("urn:p"))),
+            prefix: None,
+            name: Some(WildcardOrName::Wildcard),
+        });
+        assert!(ns.matches(&f, &a));
+        assert!(!ns.matches(&f, &b));
+        let mut ait = a.attribute_iter(&f);
+        let mut attrs = vec![];
+        while let Some(at) = ait.next() {
+            attrs.push(at)
+        }
+        assert_eq!(attrs.iter().filter(|at| ns.matches(&f, at)).count(), 1);
+
+        let pi = |n: Option<&str>| NodeTest::Kind(KindTest::PITest(n.map(String::from)));
+        assert!(pi(None).matches(&f, &go));
+        assert!(pi(Some("go")).matches(&f, &go));
+        assert!(!pi(Some("stop")).matches(&f, &go));
+        assert!(!pi(None).matches(&f, &b));
+        assert!(NodeTest::Kind(KindTest::ElementTest).matches(&f, &b));
+        assert!(NodeTest::Kind(KindTest::AttributeTest).matches(&f, &attrs[0]));
+    }
+
     #[test]
     fn literal_string() {
         let e = Evaluator::new();
@@ -4441,7 +6963,28 @@ mod tests {
             panic!("sequence does not have 10 items")
         }
     }
-    // TODO: ranges resulting in empty sequence, start = end, negative tests
+    #[test]
+    fn range_loop() {
+        let e = Evaluator::new();
+        let mut f = Forest::new();
+        let sd = f.plant_tree();
+        let rd = f.plant_tree();
+        // The range is not materialised, so the loop only does as much work as its body
+        let cons = vec![Constructor::Loop(
+            vec![Constructor::VariableDeclaration(
+                "i".to_string(),
+                vec![Constructor::Range(vec![
+                    vec![Constructor::Literal(Value::Integer(i64::MAX - 2))],
+                    vec![Constructor::Literal(Value::Integer(i64::MAX))],
+                ])],
+            )],
+            vec![Constructor::Literal(Value::from("x"))],
+        )];
+        let s = e
+            .evaluate(None, None, &cons, &mut f, sd, rd)
+            .expect("evaluation failed");
+        assert_eq!(s.to_string(None), "xxx")
+    }
 
     #[test]
     fn arithmetic_double_add() {
@@ -5134,9 +7677,9 @@ mod tests {
         assert_eq!(r.len(), 1);
         match &*r[0] {
             Item::Value(Value::Date(d)) => {
-                assert_eq!(d.year(), Local::today().year());
-                assert_eq!(d.month(), Local::today().month());
-                assert_eq!(d.day(), Local::today().day());
+                assert_eq!(d.year(), Local::now().date_naive().year());
+                assert_eq!(d.month(), Local::now().date_naive().month());
+                assert_eq!(d.day(), Local::now().date_naive().day());
             }
             _ => panic!("not a singleton date value"),
         }
@@ -5218,7 +7761,7 @@ mod tests {
             .expect("evaluation failed");
         assert_eq!(r.len(), 1);
         match &*r[0] {
-            Item::Value(Value::String(d)) => assert_eq!(d, "03 01 2022"),
+            Item::Value(Value::String(d)) => assert_eq!(d.as_ref(), "03 01 2022"),
             _ => panic!("not a singleton string value"),
         }
     }
@@ -5248,7 +7791,7 @@ mod tests {
             .expect("evaluation failed");
         assert_eq!(r.len(), 1);
         match &*r[0] {
-            Item::Value(Value::String(d)) => assert_eq!(d, "04:05 03/01/2022"),
+            Item::Value(Value::String(d)) => assert_eq!(d.as_ref(), "04:05 03/01/2022"),
             _ => panic!("not a singleton string value"),
         }
     }
@@ -5272,7 +7815,7 @@ mod tests {
             .expect("evaluation failed");
         assert_eq!(r.len(), 1);
         match &*r[0] {
-            Item::Value(Value::String(d)) => assert_eq!(d, "04:05:06"),
+            Item::Value(Value::String(d)) => assert_eq!(d.as_ref(), "04:05:06"),
             _ => panic!("not a singleton string value"),
         }
     }
@@ -5475,4 +8018,75 @@ mod tests {
     }
 
     // for-each, for-each-group
+
+    #[test]
+    fn indexed_descendants() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree(
+                "<a><b id='1'><b id='2'/></b><c><b id='3'/><p:b xmlns:p='urn:p' id='2'/></c></a>",
+            )
+            .expect("unable to parse XML");
+        let rd = f.plant_tree();
+        let doc = f.get_ref(t).unwrap().get_doc_node();
+        let mut cit = doc.get_first_element(&f).unwrap().child_iter();
+        cit.next(&f);
+        let c = cit.next(&f).unwrap();
+        let ev = Evaluator::new();
+        let query = |f: &mut Forest, n: Node, e: &str| {
+            ev.evaluate(
+                Some(vec![Rc::new(Item::Node(n))]),
+                Some(0),
+                &crate::xpath::parse(e).expect("unable to parse expression"),
+                f,
+                t,
+                rd,
+            )
+            .expect("evaluation failed")
+            .iter()
+            .map(|i| i.to_string(Some(f)))
+            .collect::<Vec<_>>()
+        };
+        let queries = [
+            (doc, "descendant::b/attribute::id"),
+            (doc, "descendant::b[attribute::id = '2']/attribute::id"),
+            (doc, "descendant::p:b[attribute::id = '2']/attribute::id"),
+            (doc, "descendant::b[attribute::id = '2'][1]/attribute::id"),
+            (c, "descendant-or-self::b/attribute::id"),
+            (c, "descendant::b[attribute::id = '1']"),
+        ];
+        let expected: Vec<_> = queries.iter().map(|(n, e)| query(&mut f, *n, e)).collect();
+        assert_eq!(expected[1], vec!["2", "2"]);
+        f.get_ref_mut(t).unwrap().set_indexed(true);
+        for ((n, e), r) in queries.iter().zip(expected.iter()) {
+            assert_eq!(&query(&mut f, *n, e), r, "{}", e)
+        }
+        assert_eq!(
+            f.get_ref(t)
+                .unwrap()
+                .elements_by_attribute("id", "2")
+                .map(|v| v.len()),
+            Some(2)
+        );
+
+        // Replacing an attribute discards the indexes
+        let b = c.child_iter().next(&f).unwrap();
+        let d = f
+            .get_ref_mut(t)
+            .unwrap()
+            .new_attribute(
+                QualifiedName::new(None, None, String::from("id")),
+                Value::from("2"),
+            )
+            .expect("unable to create attribute");
+        b.add_attribute(&mut f, d).expect("unable to add attribute");
+        assert_eq!(
+            query(
+                &mut f,
+                doc,
+                "descendant::b[attribute::id = '2']/attribute::id"
+            ),
+            vec!["2", "2", "2"]
+        )
+    }
 }
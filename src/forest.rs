@@ -4,20 +4,45 @@
 //!
 //! Both [Forest]s and [Tree]s use an arena allocator, so the object itself is simply an index that may be copied and cloned. However, in order to dererence the [Tree] or [Node] the [Forest] must be passed as an argument. This also makes deallocating memory difficult; the objects will persist until the entire [Forest] is freed.
 
-use crate::output::OutputDefinition;
-use crate::parsexml::{XMLDocument, XMLNode};
-use crate::qname::QualifiedName;
+use crate::evaluate::{Evaluator, StaticContext};
+use crate::item::Item;
+use crate::json::write_string;
+use crate::output::{write_attribute_value, write_text, DisableOutputEscaping, OutputDefinition};
+use crate::parsecommon::{is_char, ncname};
+use crate::parsexml::{
+    parse_fragment, parse_recover, AttributeType, DTDDecl, DtdCache, DtdPolicy, ParserConfig,
+    XMLDocument, XMLNode,
+};
+use crate::qname::{QualifiedName, XML_NAMESPACE, XSI_NAMESPACE};
+#[cfg(feature = "async")]
+use crate::resolvers::{with_prefetch, AsyncResolver, Prefetched};
+use crate::resolvers::{NoFetchResolver, Resolver};
+use crate::sax::{
+    parse_incremental, ContentHandler, PathStep, Projector, SimplePath, TreeBuilder, Truncator,
+};
 use crate::value::Value;
 use crate::xdmerror::{Error, ErrorKind};
+use crate::xpath::parse;
+use chrono::NaiveDate;
 use generational_arena::{Arena, Index};
+use smallvec::SmallVec;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::hash_map::Iter;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+use url::Url;
 
 /// A Forest. Forests contain [Tree]s. Each [Tree] is identified by a copyable value, similar to a Node value, that can be easily stored and passed as a parameter.
 #[derive(Clone)]
 pub struct Forest {
     a: Vec<Tree>,
+    dtds: DtdCache, // External DTD subsets that have been parsed
 }
 
 pub type TreeIndex = usize;
@@ -25,7 +50,10 @@ pub type TreeIndex = usize;
 impl Forest {
     /// Create a new, empty forest.
     pub fn new() -> Forest {
-        Forest { a: vec![] }
+        Forest {
+            a: vec![],
+            dtds: DtdCache::new(),
+        }
     }
     /// Start a [Tree] in the forest. The [Tree] will have a single node, which is a Document type [Node].
     pub fn plant_tree(&mut self) -> TreeIndex {
@@ -33,6 +61,21 @@ impl Forest {
         self.a.push(Tree::new(i));
         i
     }
+    /// Create a [Tree] in fragment mode. See [Tree::set_fragment].
+    pub fn plant_fragment(&mut self) -> TreeIndex {
+        let i = self.plant_tree();
+        self.a[i].set_fragment(true);
+        i
+    }
+
+    /// The number of [Tree]s in the forest.
+    pub(crate) fn tree_count(&self) -> usize {
+        self.a.len()
+    }
+    /// Remove the [Tree]s after the first n, which were the last to be planted. Nodes in the removed trees are no longer valid, and their [TreeIndex]es will be reused.
+    pub(crate) fn truncate(&mut self, n: usize) {
+        self.a.truncate(n)
+    }
 
     /// Borrow a [Tree], given a [TreeIndex]. Return None if no suh [Tree] exists.
     pub fn get_ref(&self, i: TreeIndex) -> Option<&Tree> {
@@ -52,23 +95,332 @@ impl Forest {
     ///    .expect("unable to parse XML");
     pub fn grow_tree(&mut self, s: &str) -> Result<TreeIndex, Error> {
         let d = XMLDocument::try_from(s)?;
+        self.grow_tree_from_document(d)
+    }
+    /// Parse a string as XML to create a [Tree], using the given configuration for namespace processing and entity expansion.
+    ///
+    /// Unless the configuration forbids a document type declaration, the entity and attribute-list declarations of the internal subset are applied. Nothing is fetched, even with [DtdPolicy::Process]: use [Forest::grow_tree_with_resolver] for a document that needs external resources.
+    pub fn grow_tree_with_config(
+        &mut self,
+        s: &str,
+        config: &ParserConfig,
+    ) -> Result<TreeIndex, Error> {
+        let d = XMLDocument::try_from(s)?;
+        self.grow_tree_from_document_with_config(d, config)
+    }
+    /// Parse a string as XML to create a [Tree], expanding general entities. External entities and the external DTD subset are resolved against the base URL and fetched using the resolver.
+    ///
+    /// External DTD subsets are cached in the forest, so that a DTD that is used by many documents is only fetched and parsed once. See [Forest::dtd_cache].
+    pub fn grow_tree_with_resolver(
+        &mut self,
+        s: &str,
+        base: Option<&Url>,
+        resolver: &dyn Resolver,
+    ) -> Result<TreeIndex, Error> {
+        let mut d = XMLDocument::try_from(s)?;
+        let config = ParserConfig::new().dtd_policy(DtdPolicy::Process);
+        d.expand_with_cache(base, resolver, &config, &self.dtds)?;
+        self.grow_tree_from_document_with_config(d, &config)
+    }
+    /// Parse a string as XML to create a [Tree], as [Forest::grow_tree_with_resolver] does, fetching external entities and the external DTD subset with an [AsyncResolver].
+    #[cfg(feature = "async")]
+    pub async fn grow_tree_async<R: AsyncResolver>(
+        &mut self,
+        s: &str,
+        base: Option<&Url>,
+        resolver: &R,
+    ) -> Result<TreeIndex, Error> {
+        let mut d = XMLDocument::try_from(s)?;
+        let config = ParserConfig::new().dtd_policy(DtdPolicy::Process);
+        let dtds = &self.dtds;
+        with_prefetch(&Prefetched::new(), resolver, |p| {
+            d.expand_with_cache(base, p, &config, dtds)
+        })
+        .await?;
+        self.grow_tree_from_document_with_config(d, &config)
+    }
+    /// The external DTD subsets that have been parsed by [Forest::grow_tree_with_resolver].
+    pub fn dtd_cache(&self) -> &DtdCache {
+        &self.dtds
+    }
+    /// Create a [Tree] from a parsed XML document.
+    ///
+    /// Use this when the document needs to be processed before it is added to the forest, for example to expand or resolve general entities.
+    pub fn grow_tree_from_document(&mut self, d: XMLDocument) -> Result<TreeIndex, Error> {
+        self.grow_tree_from_document_with_config(d, &ParserConfig::new())
+    }
+    /// Create a [Tree] from a parsed XML document, using the given configuration for namespace processing.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "build_tree", skip_all)
+    )]
+    pub fn grow_tree_from_document_with_config(
+        &mut self,
+        mut d: XMLDocument,
+        config: &ParserConfig,
+    ) -> Result<TreeIndex, Error> {
         if d.content.len() == 0 {
             Result::Err(Error::new(
                 ErrorKind::Unknown,
                 String::from("unable to parse XML"),
             ))
         } else {
+            // The internal subset is applied, and references that remain are expanded, but nothing is fetched: a document that needs external resources is expanded beforehand (see Forest::grow_tree_with_resolver)
+            let local = match config.dtd {
+                DtdPolicy::Process => config.clone().dtd_policy(DtdPolicy::Ignore),
+                _ => config.clone(),
+            };
+            d.expand_with_config(None, &NoFetchResolver, &local)?;
             let mut ns: HashMap<String, String> = HashMap::new();
+            // The xml prefix is always bound
+            ns.insert(String::from("xml"), String::from(XML_NAMESPACE));
             let ti = self.plant_tree();
-            for c in d.content {
-                let e = make_node(c, self, ti, &mut ns)?;
+            // The attributes that are declared to be IDs. The first declaration of an attribute is binding.
+            for p in &d.prologue {
+                if let XMLNode::DTD(DTDDecl::AttributeList(e, defs)) = p {
+                    for (a, t, _) in defs {
+                        if *t == AttributeType::Id {
+                            self.a[ti].ids.entry(e.to_string()).or_insert(a.to_string());
+                        }
+                    }
+                }
+            }
+            // Comments and PIs in the prologue and epilogue are also children of the Document node
+            for c in d
+                .prologue
+                .into_iter()
+                .chain(d.content)
+                .chain(d.epilogue)
+                .filter(|c| !matches!(c, XMLNode::DTD(_)))
+            {
+                let e = make_node(c, self, ti, &mut ns, config)?;
                 self.get_ref_mut(ti).unwrap().push_doc_node(e)?;
             }
             Ok(ti)
         }
     }
+
+    /// Parse a string as XML to create a [Tree], recovering from well-formedness errors. See [parse_recover](../parsexml/fn.parse_recover.html).
+    ///
+    /// Returns the best-effort tree, along with the errors that were found. It is an error if the document does not have an element.
+    pub fn grow_tree_recover(&mut self, s: &str) -> Result<(TreeIndex, Vec<Error>), Error> {
+        let (d, errors) = parse_recover(s);
+        let ti = self.grow_tree_from_document(d)?;
+        Ok((ti, errors))
+    }
+
+    /// Parse the start of a string as XML to create a [Tree], stopping after the first element whose path satisfies the predicate. See [Truncator](../sax/struct.Truncator.html).
+    ///
+    /// The rest of the document is not parsed. If no element satisfies the predicate then the whole document is parsed.
+    pub fn grow_tree_until<P: FnMut(&[PathStep]) -> bool>(
+        &mut self,
+        s: &str,
+        p: P,
+    ) -> Result<TreeIndex, Error> {
+        let mut b = TreeBuilder::new(self);
+        parse_incremental(s, &mut Truncator::new(&mut b, p))?;
+        b.finish()
+    }
+
+    /// Parse a string as XML to create a [Tree] that only has the elements selected by the paths, along with their ancestors and descendants. See [Projector](../sax/struct.Projector.html).
+    pub fn grow_tree_projection(
+        &mut self,
+        s: &str,
+        paths: Vec<SimplePath>,
+    ) -> Result<TreeIndex, Error> {
+        let mut b = TreeBuilder::new(self);
+        parse_incremental(s, &mut Projector::new(&mut b, paths))?;
+        b.finish()
+    }
+
+    /// Create a [Node] in the given tree from the parser's representation of a node, such as one of the nodes in an [XMLDocument]'s content. The new node is not attached to the tree.
+    ///
+    /// Prefixes are resolved using the namespace declarations in the node and its descendants, as for [Forest::grow_tree]. Nodes in the parser's representation can be created by parsing, or constructed by an application, or made from a tree using [Node::to_xml_node].
+    ///
+    ///```rust
+    ///use std::convert::TryFrom;
+    ///use xrust::forest::Forest;
+    ///use xrust::parsexml::{XMLDocument, XMLNode};
+    ///use xrust::value::Value;
+    ///let mut f = Forest::new();
+    ///let mut d = XMLDocument::try_from("<Example><Data>one</Data></Example>")
+    ///    .expect("unable to parse XML");
+    ///// Add text to the document element, before it is made into a tree
+    ///if let XMLNode::Element(_, _, c) = &mut d.content[0] {
+    ///    c.push(XMLNode::Text(Value::from("two")))
+    ///}
+    ///let t = f.plant_fragment();
+    ///let n = f.grow_node(t, d.content.remove(0)).expect("unable to create node");
+    ///assert_eq!(n.to_xml(&f), "<Example><Data>one</Data>two</Example>");
+    ///// And back again
+    ///let x = n.to_xml_node(&f).expect("unable to convert node");
+    ///let m = f.grow_node(t, x).expect("unable to create node");
+    ///assert_eq!(m.to_xml(&f), n.to_xml(&f))
+    ///```
+    pub fn grow_node(&mut self, t: TreeIndex, n: XMLNode) -> Result<Node, Error> {
+        if self.get_ref(t).is_none() {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to find tree"),
+            ));
+        }
+        let mut ns: HashMap<String, String> = HashMap::new();
+        ns.insert(String::from("xml"), String::from(XML_NAMESPACE));
+        make_node(n, self, t, &mut ns, &ParserConfig::new())
+    }
+
+    /// Parse a string as a fragment of XML, to create a [Tree] in fragment mode.
+    /// The children of the Document-type node are the top-level nodes of the fragment. See [parse_fragment](../parsexml/fn.parse_fragment.html).
+    pub fn grow_fragment(&mut self, s: &str) -> Result<TreeIndex, Error> {
+        let v = parse_fragment(s)?;
+        let mut ns: HashMap<String, String> = HashMap::new();
+        ns.insert(String::from("xml"), String::from(XML_NAMESPACE));
+        let ti = self.plant_fragment();
+        for c in v {
+            let e = make_node(c, self, ti, &mut ns, &ParserConfig::new())?;
+            self.get_ref_mut(ti).unwrap().push_doc_node(e)?;
+        }
+        Ok(ti)
+    }
+
+    /// Remove whitespace-only text nodes from a [Tree].
+    ///
+    /// The filter is called for each whitespace-only text node. If it returns true then the node is removed. Returns the number of nodes removed.
+    pub fn strip_whitespace_nodes<F>(&mut self, t: TreeIndex, filter: F) -> Result<usize, Error>
+    where
+        F: Fn(&Forest, Node) -> bool,
+    {
+        let d = self
+            .get_ref(t)
+            .ok_or(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to find tree"),
+            ))?
+            .get_doc_node();
+        let mut ws = vec![];
+        find_whitespace_nodes(self, d, &mut ws);
+        let mut count = 0;
+        for n in ws {
+            if filter(self, n) {
+                n.remove(self)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Evaluate an XPath expression, with the Document-type node of a [Tree] as the context item, and replace each node that it selects.
+    ///
+    /// The closure is called for each selected node and returns the replacement nodes. If it returns no nodes, then the selected node is removed. Replacement nodes from a different [Tree] are deep copied. Nodes that are no longer in the [Tree], because an ancestor has already been replaced, are skipped. Returns the number of nodes replaced.
+    ///
+    ///```rust
+    ///use xrust::forest::Forest;
+    ///use xrust::value::Value;
+    ///let mut f = Forest::new();
+    ///let src = f.grow_tree("<Example><old/><keep/><old/></Example>")
+    ///    .expect("unable to parse XML");
+    ///let count = f.replace_matching(src, "child::Example/child::old", |_, f| {
+    ///    Ok(vec![f.get_ref_mut(src).unwrap().new_text(Value::from("new"))?])
+    ///}).expect("unable to replace nodes");
+    ///assert_eq!(count, 2);
+    ///```
+    pub fn replace_matching<F>(
+        &mut self,
+        t: TreeIndex,
+        xpath: &str,
+        mut replace: F,
+    ) -> Result<usize, Error>
+    where
+        F: FnMut(Node, &mut Forest) -> Result<Vec<Node>, Error>,
+    {
+        let d = self
+            .get_ref(t)
+            .ok_or(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to find tree"),
+            ))?
+            .get_doc_node();
+        let mut e = parse(xpath)?;
+        StaticContext::new_with_builtins().static_analysis(&mut e);
+        // An XPath expression does not construct nodes, so the tree itself serves as the result document
+        let s = Evaluator::new().evaluate(
+            Some(vec![Rc::new(Item::Node(d))]),
+            Some(0),
+            &e,
+            self,
+            t,
+            t,
+        )?;
+
+        let mut count = 0;
+        for i in s {
+            let n = match *i {
+                Item::Node(n) => n,
+                _ => {
+                    return Result::Err(Error::new(
+                        ErrorKind::TypeError,
+                        String::from("expression must select nodes"),
+                    ))
+                }
+            };
+            match n.node_type(self) {
+                NodeType::Document | NodeType::Attribute => {
+                    return Result::Err(Error::new(
+                        ErrorKind::Unknown,
+                        format!("unable to replace {} node", n.node_type(self).to_string()),
+                    ))
+                }
+                _ => {}
+            }
+            // A node may have been detached by an earlier replacement, along with an ancestor
+            if !self.get_ref(t).is_some_and(|d| d.is_attached(n.0)) {
+                continue;
+            }
+            let r = replace(n, self)?;
+            n.replace_with(self, r)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+// Allows the serialiser to write to an io::Write, encoding the output as UTF-8 or UTF-16 (big-endian). The io error is kept so that it can be reported.
+struct IoAdapter<'a, W: io::Write> {
+    w: &'a mut W,
+    utf16: bool,
+    err: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let r = if self.utf16 {
+            let b: Vec<u8> = s.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+            self.w.write_all(&b)
+        } else {
+            self.w.write_all(s.as_bytes())
+        };
+        r.map_err(|e| {
+            self.err = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+fn find_whitespace_nodes(f: &Forest, n: Node, ws: &mut Vec<Node>) {
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        if c.is_whitespace_only(f) {
+            ws.push(c)
+        } else if c.node_type(f) == NodeType::Element {
+            find_whitespace_nodes(f, c, ws)
+        }
+    }
 }
 
+// The spacing of order keys when they are computed for the whole tree.
+// This leaves room to give keys to nodes that are inserted later, without renumbering the rest of the tree.
+const ORDER_GAP: usize = 1 << (usize::BITS / 2);
+
 /// A Tree, using an Arena Allocator.
 /// Nodes can be detached, but not deleted
 #[derive(Clone)]
@@ -76,6 +428,55 @@ pub struct Tree {
     i: TreeIndex, // The index in the Forest
     a: Arena<NodeContent>,
     d: Index, // The document node
+    // Cache of an order key for each attached node: the keys increase in document order, but are spaced out.
+    // When nodes are attached they are given keys between those of their neighbours, and when they are detached their keys are removed.
+    // Only if there is no room between the neighbours is the cache discarded, to be rebuilt when next needed.
+    order: RefCell<Option<HashMap<Index, usize>>>,
+    fragment: bool, // Whether the document-level constraints are relaxed
+    indexed: bool,  // Whether indexes are maintained
+    // Indexes of elements by name and by attribute value.
+    // These are discarded whenever any node in the tree changes, and rebuilt when next needed.
+    indexes: RefCell<Option<Indexes>>,
+    // Indexes of the keys that have been used with the tree (see xsl:key), by name. These are built by the evaluator, and are also discarded whenever any node in the tree changes.
+    keys: RefCell<HashMap<QualifiedName, Arc<KeyIndex>>>,
+    // The attribute that is declared to have type ID for each element type, by their names in the document type declaration
+    ids: HashMap<String, String>,
+}
+
+// The nodes that have each value of a key, in document order
+pub(crate) type KeyIndex = HashMap<String, Vec<Node>>;
+
+// Attached elements, in document order, keyed by local name.
+// Names are not resolved to namespaces here; callers filter the candidates with a full name test.
+#[derive(Clone, Default)]
+struct Indexes {
+    elements: HashMap<String, Vec<Index>>,
+    // Keyed by the local name and the string value of an attribute. The values are the owner elements.
+    attributes: HashMap<(String, String), Vec<Index>>,
+}
+
+/// Statistics about the structure of a [Tree]. See [Tree::summary].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TreeStats {
+    /// The number of elements with each name.
+    pub elements: HashMap<QualifiedName, usize>,
+    /// The number of attributes with each name.
+    pub attributes: HashMap<QualifiedName, usize>,
+    /// The greatest depth of any element. The document element has depth 1.
+    pub max_depth: usize,
+    /// The total length of the text nodes, in bytes.
+    pub text_bytes: usize,
+}
+
+impl TreeStats {
+    /// The total number of elements.
+    pub fn element_count(&self) -> usize {
+        self.elements.values().sum()
+    }
+    /// The total number of attributes.
+    pub fn attribute_count(&self) -> usize {
+        self.attributes.values().sum()
+    }
 }
 
 impl Tree {
@@ -85,25 +486,216 @@ impl Tree {
     pub fn new(i: TreeIndex) -> Self {
         let mut a = Arena::new();
         let d = a.insert(NodeBuilder::new(NodeType::Document).build());
-        Tree { i: i, a: a, d: d }
+        Tree {
+            i,
+            a,
+            d,
+            order: RefCell::new(None),
+            fragment: false,
+            indexed: false,
+            indexes: RefCell::new(None),
+            keys: RefCell::new(HashMap::new()),
+            ids: HashMap::new(),
+        }
+    }
+    /// A tree in fragment mode may have any number of element and text nodes as children of the Document-type node. This is useful for intermediate results, such as temporary trees. By default, a tree is a well-formed document.
+    pub fn set_fragment(&mut self, fragment: bool) {
+        self.fragment = fragment
+    }
+    pub fn is_fragment(&self) -> bool {
+        self.fragment
+    }
+    /// Maintain indexes of the elements in the tree by name, and by the values of their attributes. The indexes are built when they are first needed, and discarded whenever the tree is changed. They are used by the [Evaluator] to find descendants without searching the tree, so they are worthwhile when a document that does not change is queried repeatedly.
+    pub fn set_indexed(&mut self, indexed: bool) {
+        self.indexed = indexed;
+        self.indexes.replace(None);
+    }
+    pub fn is_indexed(&self) -> bool {
+        self.indexed
+    }
+    /// The attached elements with the given local name, in document order. Returns None if the tree is not indexed.
+    pub fn elements_by_name(&self, local: &str) -> Option<Vec<Node>> {
+        self.with_indexes(|x| {
+            x.elements.get(local).map_or(vec![], |v| {
+                v.iter().map(|i| Node::new(*i, self.i)).collect()
+            })
+        })
+    }
+    /// The attached elements that have an attribute with the given local name and string value, in document order. Returns None if the tree is not indexed.
+    pub fn elements_by_attribute(&self, local: &str, value: &str) -> Option<Vec<Node>> {
+        self.with_indexes(|x| {
+            x.attributes
+                .get(&(local.to_string(), value.to_string()))
+                .map_or(vec![], |v| {
+                    v.iter().map(|i| Node::new(*i, self.i)).collect()
+                })
+        })
+    }
+    /// Count the attached elements, attributes and text in the tree. Detached nodes are not included.
+    pub fn summary(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let mut stack = vec![(self.d, 0)];
+        while let Some((i, depth)) = stack.pop() {
+            let nc = match self.get(i) {
+                Some(nc) => nc,
+                None => continue,
+            };
+            match nc.t {
+                NodeType::Element => {
+                    if let Some(qn) = &nc.name {
+                        *stats.elements.entry(qn.clone()).or_default() += 1
+                    }
+                    stats.max_depth = stats.max_depth.max(depth);
+                    for at in nc.attributes.iter().flat_map(|a| a.keys()) {
+                        *stats.attributes.entry(at.clone()).or_default() += 1
+                    }
+                }
+                NodeType::Text => {
+                    stats.text_bytes += nc.v.as_ref().map_or(0, |v| v.to_string().len())
+                }
+                _ => {}
+            }
+            nc.children
+                .iter()
+                .for_each(|c| stack.push((c.0, depth + 1)));
+        }
+        stats
+    }
+    // Write an outline of a node and its descendants, one node per line
+    fn write_outline<W: fmt::Write>(&self, i: Index, depth: usize, w: &mut W) -> fmt::Result {
+        let nc = match self.get(i) {
+            Some(nc) => nc,
+            None => return Ok(()),
+        };
+        for _ in 0..depth {
+            w.write_str("  ")?
+        }
+        w.write_str(nc.t.to_string())?;
+        if let Some(qn) = &nc.name {
+            write!(w, " {}", qn.to_string())?
+        }
+        if let Some(v) = &nc.v {
+            let v = v.to_string();
+            match v.char_indices().nth(OUTLINE_PREVIEW) {
+                Some((n, _)) => write!(w, " {:?}...", &v[..n])?,
+                None => write!(w, " {:?}", v)?,
+            }
+        }
+        w.write_char('\n')?;
+        for at in nc.attributes.iter().flat_map(|a| a.values()) {
+            self.write_outline(at.0, depth + 1, w)?
+        }
+        for c in &nc.children {
+            self.write_outline(c.0, depth + 1, w)?
+        }
+        Ok(())
+    }
+    /// Export the structure of the tree in the Graphviz DOT language, starting from the document node. See [Node::to_dot].
+    pub fn to_dot(&self) -> String {
+        let mut result = String::from("digraph {\n");
+        let _ = self.write_dot(self.d, &mut result);
+        result.push_str("}\n");
+        result
+    }
+    // Write the DOT statements for a node and its descendants
+    fn write_dot<W: fmt::Write>(&self, i: Index, w: &mut W) -> fmt::Result {
+        let nc = match self.get(i) {
+            Some(nc) => nc,
+            None => return Ok(()),
+        };
+        let mut label = String::from(nc.t.to_string());
+        if let Some(qn) = &nc.name {
+            label.push(' ');
+            label.push_str(qn.to_string().as_str())
+        }
+        if let Some(v) = &nc.v {
+            let v = v.to_string();
+            label.push('\n');
+            label.push_str(&v.chars().take(OUTLINE_PREVIEW).collect::<String>())
+        }
+        writeln!(w, "  {} [label={:?}];", dot_id(i), label)?;
+        if let Some(p) = nc.parent {
+            writeln!(w, "  {} -> {} [style=dashed];", dot_id(i), dot_id(p.0))?
+        }
+        for at in nc.attributes.iter().flat_map(|a| a.values()) {
+            writeln!(w, "  {} -> {} [style=dotted];", dot_id(i), dot_id(at.0))?;
+            self.write_dot(at.0, w)?
+        }
+        for (n, c) in nc.children.iter().enumerate() {
+            writeln!(w, "  {} -> {} [label={}];", dot_id(i), dot_id(c.0), n)?;
+            self.write_dot(c.0, w)?
+        }
+        Ok(())
+    }
+    // Build the indexes, if necessary, and look something up.
+    fn with_indexes<T>(&self, lookup: impl FnOnce(&Indexes) -> T) -> Option<T> {
+        if !self.indexed {
+            return None;
+        }
+        if let Some(x) = self.indexes.borrow().as_ref() {
+            return Some(lookup(x));
+        }
+        let mut x = Indexes::default();
+        let mut stack = vec![self.d];
+        while let Some(i) = stack.pop() {
+            let nc = self.get(i)?;
+            if nc.t == NodeType::Element {
+                if let Some(qn) = &nc.name {
+                    x.elements.entry(qn.get_localname()).or_default().push(i);
+                }
+                for at in nc.attributes.iter().flat_map(|a| a.values()) {
+                    if let Some(anc) = self.get(at.0) {
+                        let key = (
+                            anc.name
+                                .as_ref()
+                                .map_or(String::new(), |qn| qn.get_localname()),
+                            anc.v.as_ref().map_or(String::new(), |v| v.to_string()),
+                        );
+                        x.attributes.entry(key).or_default().push(i)
+                    }
+                }
+            }
+            nc.children.iter().rev().for_each(|c| stack.push(c.0));
+        }
+        let result = lookup(&x);
+        self.indexes.replace(Some(x));
+        Some(result)
     }
 
     fn get(&self, i: Index) -> Option<&NodeContent> {
         self.a.get(i)
     }
+    // Any change to a node may change the indexes
     fn get_mut(&mut self, i: Index) -> Option<&mut NodeContent> {
+        self.indexes.get_mut().take();
+        self.keys.get_mut().clear();
         self.a.get_mut(i)
     }
+    // The index of the named key, if it has been built since the tree last changed
+    pub(crate) fn key_index(&self, name: &QualifiedName) -> Option<Arc<KeyIndex>> {
+        self.keys.borrow().get(name).cloned()
+    }
+    pub(crate) fn set_key_index(&self, name: QualifiedName, index: Arc<KeyIndex>) {
+        self.keys.borrow_mut().insert(name, index);
+    }
+    // The name of the attribute that is declared to be the ID of the element type
+    fn id_attribute(&self, element: &str) -> Option<&str> {
+        self.ids.get(element).map(|a| a.as_str())
+    }
     /// Return the Document-type [Node].
     pub fn get_doc_node(&self) -> Node {
         Node::new(self.d, self.i)
     }
     /// Append a [Node] as a child of the Document-type [Node].
+    ///
+    /// Unless the tree is in fragment mode, the Document-type node may have at most one element child, and may not have text children.
     pub fn push_doc_node(&mut self, n: Node) -> Result<(), Error> {
+        let t = self.get(n.0).map_or(NodeType::Unknown, |nc| nc.node_type());
+        self.check_doc_child(t, Some(n.0))?;
         // Set the parent to the document node
         self.get_mut(n.0).unwrap().parent = Some(Node::new(self.d, self.i));
         // Push the node onto the doc node's children
-        self.get_mut(self.d).map_or_else(
+        let last = self.get_mut(self.d).map_or_else(
             || {
                 Result::Err(Error::new(
                     ErrorKind::Unknown,
@@ -112,18 +704,243 @@ impl Tree {
             },
             |e| {
                 e.children.push(n);
+                Ok(e.children.len() - 1)
+            },
+        )?;
+        self.renumber_children(self.d, last);
+        self.order_attached(n.0);
+        Ok(())
+    }
+    // Check that a node of the given type may become a child of the Document-type node.
+    // A node in this tree is ignored when looking for an existing document element, since it may be being moved.
+    fn check_doc_child(&self, t: NodeType, moving: Option<Index>) -> Result<(), Error> {
+        match t {
+            NodeType::Element | NodeType::Text | NodeType::EntityReference if self.fragment => {
                 Ok(())
+            }
+            NodeType::Element => {
+                let d = self.get(self.d).unwrap();
+                if d.children.iter().any(|c| {
+                    Some(c.0) != moving
+                        && self
+                            .get(c.0)
+                            .is_some_and(|cnc| cnc.node_type() == NodeType::Element)
+                }) {
+                    Result::Err(Error::new(
+                        ErrorKind::Unknown,
+                        String::from("document already has a document element"),
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            NodeType::Comment | NodeType::ProcessingInstruction => Ok(()),
+            _ => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("a {} node cannot be a child of a document", t.to_string()),
+            )),
+        }
+    }
+    // Detach a node from its parent: an attribute from its element's attributes, or any other node from its parent's children.
+    // A node that is not attached is unchanged.
+    fn detach(&mut self, i: Index) {
+        let (p, t, posn, name) = match self.get(i) {
+            Some(nc) => match nc.parent {
+                Some(p) => (p.0, nc.t, nc.posn, nc.name.clone()),
+                None => return,
             },
-        )
+            None => return,
+        };
+        self.order_detached(i);
+        if t == NodeType::Attribute {
+            if let (Some(pnc), Some(qn)) = (self.get_mut(p), name) {
+                if let Some(at) = pnc.attributes.as_mut() {
+                    if at.get(&qn).is_some_and(|a| a.0 == i) {
+                        at.remove(&qn);
+                    }
+                }
+            }
+        } else {
+            if let Some(pnc) = self.get_mut(p) {
+                if pnc.children.get(posn).is_some_and(|c| c.0 == i) {
+                    pnc.children.remove(posn);
+                }
+            }
+            self.renumber_children(p, posn);
+        }
+        // This node now has no parent
+        if let Some(nc) = self.get_mut(i) {
+            nc.parent = None
+        }
+    }
+    // Record the position of each child in its parent's child list, starting from the given position.
+    // This must be done whenever a child list is changed, so that siblings can be found without searching the parent's child list.
+    fn renumber_children(&mut self, p: Index, from: usize) {
+        let len = self.get(p).map_or(0, |nc| nc.children.len());
+        for i in from..len {
+            let c = self.get(p).unwrap().children[i];
+            if let Some(cnc) = self.get_mut(c.0) {
+                cnc.posn = i
+            }
+        }
+    }
+
+    // Is the node attached to the tree, i.e. is the Document-type node one of its ancestors?
+    fn is_attached(&self, mut i: Index) -> bool {
+        while let Some(p) = self.get(i).and_then(|nc| nc.parent) {
+            i = p.0
+        }
+        i == self.d
+    }
+    // The nodes of a subtree in document order: each node is followed by its attributes, and then its descendants.
+    fn subtree_order(&self, n: Index) -> Vec<Index> {
+        let mut v = vec![];
+        let mut stack = vec![n];
+        while let Some(i) = stack.pop() {
+            v.push(i);
+            if let Some(nc) = self.get(i) {
+                v.extend(nc.attributes.iter().flat_map(|a| a.values()).map(|a| a.0));
+                nc.children.iter().rev().for_each(|c| stack.push(c.0));
+            }
+        }
+        v
+    }
+    // The greatest order key of a node and its attributes.
+    fn key_with_attributes(&self, m: &HashMap<Index, usize>, i: Index) -> Option<usize> {
+        let mut k = *m.get(&i)?;
+        for a in self.get(i)?.attributes.iter().flat_map(|a| a.values()) {
+            k = k.max(*m.get(&a.0)?)
+        }
+        Some(k)
+    }
+    // The order key of the node that immediately precedes the given node in document order.
+    fn preceding_key(&self, m: &HashMap<Index, usize>, i: Index) -> Option<usize> {
+        let nc = self.get(i)?;
+        let p = nc.parent?.0;
+        if nc.posn == 0 {
+            return self.key_with_attributes(m, p);
+        }
+        // The last node in the preceding sibling's subtree
+        let mut j = self.get(p)?.children.get(nc.posn - 1)?.0;
+        while let Some(c) = self.get(j)?.children.last() {
+            j = c.0
+        }
+        self.key_with_attributes(m, j)
+    }
+    // The order key of the node that immediately follows the subtree of the given node in document order.
+    // At the end of the document this is usize::MAX, which is greater than any key.
+    fn following_key(&self, m: &HashMap<Index, usize>, mut i: Index) -> Option<usize> {
+        loop {
+            let nc = self.get(i)?;
+            match nc.parent {
+                Some(p) => match self.get(p.0)?.children.get(nc.posn + 1) {
+                    Some(s) => return m.get(&s.0).copied(),
+                    None => i = p.0,
+                },
+                None => return Some(usize::MAX),
+            }
+        }
+    }
+    // Give order keys to the nodes, spread evenly between the keys lo and hi.
+    // If there is no room then the cache is discarded.
+    fn assign_keys(&self, nodes: Vec<Index>, lo: Option<usize>, hi: Option<usize>) {
+        let mut order = self.order.borrow_mut();
+        if let Some(m) = order.as_mut() {
+            let step = lo.zip(hi).map_or(0, |(l, h)| {
+                (h.saturating_sub(l) / (nodes.len() + 1)).min(ORDER_GAP)
+            });
+            if step == 0 {
+                *order = None;
+            } else {
+                let lo = lo.unwrap();
+                for (k, i) in nodes.into_iter().enumerate() {
+                    m.insert(i, lo + step * (k + 1));
+                }
+            }
+        }
+    }
+    // A subtree has been attached to a node, so give its nodes order keys between those of the nodes on either side of it.
+    fn order_attached(&self, n: Index) {
+        let (lo, hi) = match self.order.borrow().as_ref() {
+            Some(m) => {
+                match self.get(n).and_then(|nc| nc.parent) {
+                    // The parent is not attached to the tree, so neither is the subtree
+                    Some(p) if !m.contains_key(&p.0) => return,
+                    _ => {}
+                }
+                (self.preceding_key(m, n), self.following_key(m, n))
+            }
+            None => return,
+        };
+        self.assign_keys(self.subtree_order(n), lo, hi)
+    }
+    // A subtree is being detached, so its nodes no longer have a position in document order.
+    fn order_detached(&self, n: Index) {
+        if let Some(m) = self.order.borrow_mut().as_mut() {
+            if m.contains_key(&n) {
+                for i in self.subtree_order(n) {
+                    m.remove(&i);
+                }
+            }
+        }
+    }
+    // The attributes of an element have changed, so give them order keys between those of the element and its first child.
+    fn order_attributes(&self, e: Index) {
+        let (lo, hi) = match self.order.borrow().as_ref() {
+            Some(m) => match m.get(&e) {
+                Some(k) => (
+                    Some(*k),
+                    match self.get(e).and_then(|nc| nc.children.first()) {
+                        Some(c) => m.get(&c.0).copied(),
+                        None => self.following_key(m, e),
+                    },
+                ),
+                // The element is not attached to the tree
+                None => return,
+            },
+            None => return,
+        };
+        let attributes = self.get(e).map_or(vec![], |nc| {
+            nc.attributes
+                .iter()
+                .flat_map(|a| a.values())
+                .map(|a| a.0)
+                .collect()
+        });
+        self.assign_keys(attributes, lo, hi)
+    }
+    // Find the order key of a node.
+    // The first time this is called, or after the cache has been discarded, keys are computed for every attached node.
+    fn order_of(&self, n: Index) -> Option<usize> {
+        if let Some(m) = self.order.borrow().as_ref() {
+            return m.get(&n).copied();
+        }
+        let nodes = self.subtree_order(self.d);
+        let gap = ORDER_GAP.min(usize::MAX / (nodes.len() + 1));
+        let m: HashMap<Index, usize> = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(k, i)| (i, k * gap))
+            .collect();
+        let result = m.get(&n).copied();
+        self.order.replace(Some(m));
+        result
     }
 
     /// Create a new Element-type [Node] in this tree. The newly created [Node] is not attached to the tree, i.e. it has no parent.
+    ///
+    /// Returns an error if the name is not a valid XML name.
     pub fn new_element(&mut self, name: QualifiedName) -> Result<Node, Error> {
-        Ok(Node::new(
+        check_name(&name)?;
+        Ok(self.new_element_unchecked(name))
+    }
+    /// Create a new Element-type [Node] in this tree, without checking that the name is valid. Use this when the name is known to be valid, for example when it has been produced by a parser.
+    pub fn new_element_unchecked(&mut self, name: QualifiedName) -> Node {
+        Node::new(
             self.a
                 .insert(NodeBuilder::new(NodeType::Element).name(name).build()),
             self.i,
-        ))
+        )
     }
     /// Create a new Text-type [Node] in this tree. The newly created [Node] is not attached to the tree, i.e. it has no parent.
     pub fn new_text(&mut self, c: Value) -> Result<Node, Error> {
@@ -134,8 +951,15 @@ impl Tree {
         ))
     }
     /// Create a new Attribute-type [Node] in this tree. The newly created [Node] is not attached to the tree, i.e. it has no parent.
+    ///
+    /// Returns an error if the name is not a valid XML name.
     pub fn new_attribute(&mut self, name: QualifiedName, v: Value) -> Result<Node, Error> {
-        Ok(Node::new(
+        check_name(&name)?;
+        Ok(self.new_attribute_unchecked(name, v))
+    }
+    /// Create a new Attribute-type [Node] in this tree, without checking that the name is valid. Use this when the name is known to be valid, for example when it has been produced by a parser.
+    pub fn new_attribute_unchecked(&mut self, name: QualifiedName, v: Value) -> Node {
+        Node::new(
             self.a.insert(
                 NodeBuilder::new(NodeType::Attribute)
                     .name(name)
@@ -143,22 +967,63 @@ impl Tree {
                     .build(),
             ),
             self.i,
-        ))
+        )
     }
     /// Create a new Comment-type [Node] in this tree. The newly created [Node] is not attached to the tree, i.e. it has no parent.
+    ///
+    /// Returns an error if the value would not serialise as a well-formed comment: it must not contain "--" or end with "-", and must only contain characters that are allowed in XML.
     pub fn new_comment(&mut self, v: Value) -> Result<Node, Error> {
+        let s = v.to_string();
+        if s.contains("--") || s.ends_with('-') || !s.chars().all(|c| is_char(&c)) {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("\"{}\" is not a valid comment", s),
+            ));
+        }
         Ok(Node::new(
             self.a
                 .insert(NodeBuilder::new(NodeType::Comment).value(v).build()),
             self.i,
         ))
     }
+    /// Create a new EntityReference-type [Node] in this tree. The expansion of the entity is added as the children of the node. The newly created [Node] is not attached to the tree, i.e. it has no parent.
+    pub fn new_entity_reference(&mut self, name: QualifiedName) -> Result<Node, Error> {
+        Ok(Node::new(
+            self.a.insert(
+                NodeBuilder::new(NodeType::EntityReference)
+                    .name(name)
+                    .build(),
+            ),
+            self.i,
+        ))
+    }
     /// Create a new ProcessingInstruction-type [Node] in this tree. The newly created [Node] is not attached to the tree, i.e. it has no parent.
+    ///
+    /// Returns an error if the target is not a valid name, or is "xml" (in any case), or if the data contains "?>" or characters that are not allowed in XML.
     pub fn new_processing_instruction(
         &mut self,
         name: QualifiedName,
         v: Value,
     ) -> Result<Node, Error> {
+        if name.get_prefix().is_some()
+            || !name.is_valid()
+            || name.get_localname().eq_ignore_ascii_case("xml")
+        {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!(
+                    "\"{}\" is not a valid processing instruction target",
+                    name.to_string()
+                ),
+            ));
+        }
+        let s = v.to_string();
+        if s.contains("?>") || !s.chars().all(|c| is_char(&c)) {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("\"{}\" is not valid processing instruction data", s),
+            ));
+        }
         Ok(Node::new(
             self.a.insert(
                 NodeBuilder::new(NodeType::ProcessingInstruction)
@@ -171,98 +1036,169 @@ impl Tree {
     }
 }
 
-fn make_node(
-    n: XMLNode,
-    f: &mut Forest,
+fn check_name(name: &QualifiedName) -> Result<(), Error> {
+    if name.is_valid() {
+        Ok(())
+    } else {
+        Result::Err(Error::new(
+            ErrorKind::Unknown,
+            format!("\"{}\" is not a valid name", name.to_string()),
+        ))
+    }
+}
+
+// NSC: Prefix Declared
+pub(crate) fn undeclared_prefix(p: &str) -> Error {
+    Error::new(
+        ErrorKind::StaticNamespace,
+        format!("NSC: Prefix Declared: prefix \"{}\" is not declared", p),
+    )
+}
+
+pub(crate) fn make_node(
+    n: XMLNode,
+    f: &mut Forest,
     ti: TreeIndex,
     ns: &mut HashMap<String, String>,
+    config: &ParserConfig,
 ) -> Result<Node, Error> {
     match n {
         XMLNode::Element(m, a, c) => {
-            a.iter()
-                .filter(|b| match b {
-                    XMLNode::Attribute(qn, _) => match qn.get_prefix() {
-                        Some(p) => p == "xmlns",
-                        _ => false,
-                    },
-                    _ => false,
-                })
-                .for_each(|b| {
-                    if let XMLNode::Attribute(qn, v) = b {
+            if let Some(XMLNode::UnexpandedAttribute(qn, _)) = a
+                .iter()
+                .find(|b| matches!(b, XMLNode::UnexpandedAttribute(_, _)))
+            {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    format!(
+                        "general entity references in the value of attribute \"{}\" have not been expanded",
+                        qn.to_string()
+                    ),
+                ));
+            }
+            // Declarations are in scope for this element and its descendants only, so the namespaces are copied if the element declares any
+            let declares = a.iter().any(|b| match b {
+                XMLNode::Attribute(qn, _) => qn.get_prefix().as_deref() == Some("xmlns"),
+                _ => false,
+            });
+            let mut scoped;
+            let ns = if declares {
+                scoped = ns.clone();
+                &mut scoped
+            } else {
+                ns
+            };
+            let mut decls = vec![];
+            for b in &a {
+                if let XMLNode::Attribute(qn, v) = b {
+                    if qn.get_prefix().is_none() && qn.get_localname() == "xmlns" {
+                        decls.push((None, v.to_string()));
+                    }
+                    if qn.get_prefix().as_deref() != Some("xmlns") {
+                        continue;
+                    }
+                    let p = qn.get_localname();
+                    let u = v.to_string();
+                    decls.push((Some(p.clone()), u.clone()));
+                    if p == "xmlns" || (p == "xml") != (u == XML_NAMESPACE) {
+                        return Result::Err(Error::new(
+                            ErrorKind::StaticNamespace,
+                            format!("prefix \"{}\" cannot be bound to \"{}\"", p, u),
+                        ));
+                    }
+                    if u.is_empty() {
+                        // Prefix undeclaration
+                        if !config.namespaces11 {
+                            return Result::Err(Error::new(
+                                ErrorKind::StaticNamespace,
+                                format!(
+                                    "undeclaring prefix \"{}\" is only allowed in XML Namespaces 1.1",
+                                    p
+                                ),
+                            ));
+                        }
+                        ns.remove(&p);
+                    } else {
                         // add map from prefix to uri in hashmap
-                        ns.insert(qn.get_localname(), v.to_string()).map(|_| {});
+                        ns.insert(p, u);
                     }
-                });
+                }
+            }
             // Add element to the tree
             let newns = match m.get_prefix() {
                 Some(p) => match ns.get(&p) {
                     Some(q) => Some(q.clone()),
-                    None => {
-                        return Result::Err(Error::new(
-                            ErrorKind::Unknown,
-                            String::from("namespace URI not found for prefix"),
-                        ))
-                    }
+                    None if config.undeclared_prefixes => None,
+                    None => return Result::Err(undeclared_prefix(&p)),
                 },
                 None => None,
             };
-            let new = f.get_ref_mut(ti).unwrap().new_element(QualifiedName::new(
-                newns,
-                m.get_prefix(),
-                m.get_localname(),
-            ))?;
+            // NSC: Attributes Unique. The parser has checked the qualified names, but two prefixes may be bound to the same namespace.
+            let mut expanded = HashSet::new();
+            for b in &a {
+                if let XMLNode::Attribute(qn, _) = b {
+                    let uri = match qn.get_prefix() {
+                        Some(p) if p == "xmlns" => continue,
+                        Some(p) => ns.get(&p).cloned(),
+                        None => None,
+                    };
+                    if !expanded.insert((uri, qn.get_localname())) {
+                        return Result::Err(Error::new(
+                            ErrorKind::StaticNamespace,
+                            format!(
+                                "attribute \"{}\" has the same namespace and local name as another attribute",
+                                qn.to_string()
+                            ),
+                        ));
+                    }
+                }
+            }
+            // The parser has already checked the name
+            let new = f
+                .get_ref_mut(ti)
+                .unwrap()
+                .new_element_unchecked(QualifiedName::new(
+                    newns,
+                    m.get_prefix(),
+                    m.get_localname(),
+                ));
+            if config.namespace_declarations && !decls.is_empty() {
+                new.set_user_data(f, NamespaceDeclarations(decls))?
+            }
 
             // Attributes
-            a.iter().for_each(|b| {
+            let mut attrs = Vec::with_capacity(a.len());
+            for b in a {
                 if let XMLNode::Attribute(qn, v) = b {
-                    match qn.get_prefix() {
-                        Some(p) => {
-                            if p != "xmlns" {
-                                let ans = ns.get(&p).unwrap_or(&"".to_string()).clone();
-                                match f.get_ref_mut(ti).unwrap().new_attribute(
-                                    QualifiedName::new(Some(ans), Some(p), qn.get_localname()),
-                                    v.clone(),
-                                ) {
-                                    Ok(c) => {
-                                        new.add_attribute(f, c).expect("unable to add attribute");
-                                        // TODO: Don't Panic
-                                    }
-                                    Err(_) => {
-                                        //return Result::Err(e);
-                                    }
-                                };
+                    let an = match qn.get_prefix() {
+                        // A namespace declaration, see above
+                        Some(p) if p == "xmlns" => continue,
+                        Some(p) => match ns.get(&p) {
+                            Some(u) => {
+                                QualifiedName::new(Some(u.clone()), Some(p), qn.get_localname())
                             }
-                            // otherwise it is a namespace declaration, see above
-                        }
-                        _ => {
-                            // Unqualified name
-                            match f
-                                .get_ref_mut(ti)
-                                .unwrap()
-                                .new_attribute(qn.clone(), v.clone())
-                            {
-                                Ok(c) => {
-                                    new.add_attribute(f, c).expect("unable to add attribute");
-                                    // TODO: Don't Panic
-                                }
-                                Err(_) => {
-                                    //return Result::Err(e);
-                                }
+                            None if config.undeclared_prefixes => {
+                                QualifiedName::new(None, Some(p), qn.get_localname())
                             }
-                        }
-                    }
+                            None => return Result::Err(undeclared_prefix(&p)),
+                        },
+                        // Unqualified name
+                        None => qn,
+                    };
+                    attrs.push((an, v))
                 }
-            });
+            }
+            new.set_attributes(f, attrs)?;
 
             // Element content
-            for h in c.iter().cloned() {
-                let g = make_node(h, f, ti, ns)?;
+            for h in c {
+                let g = make_node(h, f, ti, ns, config)?;
                 new.append_child(f, g)?
             }
 
             Ok(new)
         }
-        XMLNode::Attribute(_qn, _v) => {
+        XMLNode::Attribute(_, _) | XMLNode::UnexpandedAttribute(_, _) => {
             // Handled in element arm
             Result::Err(Error::new(
                 ErrorKind::NotImplemented,
@@ -275,6 +1211,14 @@ fn make_node(
             .get_ref_mut(ti)
             .unwrap()
             .new_processing_instruction(QualifiedName::new(None, None, m), v)?),
+        XMLNode::EntityReference(m, c) => {
+            let new = f.get_ref_mut(ti).unwrap().new_entity_reference(m)?;
+            for h in c {
+                let g = make_node(h, f, ti, ns, config)?;
+                new.append_child(f, g)?
+            }
+            Ok(new)
+        }
         XMLNode::Reference(_) | XMLNode::DTD(_) => Result::Err(Error::new(
             ErrorKind::TypeError,
             String::from("not expected"),
@@ -282,6 +1226,44 @@ fn make_node(
     }
 }
 
+fn xsi_name(local: &str) -> QualifiedName {
+    QualifiedName::new(
+        Some(String::from(XSI_NAMESPACE)),
+        Some(String::from("xsi")),
+        String::from(local),
+    )
+}
+
+fn is_ncname(s: &str) -> bool {
+    ncname(s).is_ok_and(|(rest, _)| rest.is_empty())
+}
+
+// The number of characters of a value that are shown in an outline
+const OUTLINE_PREVIEW: usize = 40;
+
+// The DOT identifier of a node, from its slot and generation
+fn dot_id(i: Index) -> String {
+    let (slot, generation) = i.into_raw_parts();
+    format!("n{}_{}", slot, generation)
+}
+
+/// The outline of the tree's document node, as produced by [Node::display_tree].
+impl fmt::Debug for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_outline(self.d, 0, f)
+    }
+}
+
+impl fmt::Debug for Forest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, t) in self.a.iter().enumerate() {
+            writeln!(f, "Tree {}:", i)?;
+            t.fmt(f)?
+        }
+        Ok(())
+    }
+}
+
 /// All [Node]s have a type. The type of the [Node] determines what components are meaningful, such as name and content.
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum NodeType {
@@ -291,6 +1273,8 @@ pub enum NodeType {
     Attribute,
     Comment,
     ProcessingInstruction,
+    /// A general entity reference that has been preserved. The name is the entity's name, and the children are its expansion.
+    EntityReference,
     Unknown,
 }
 
@@ -304,6 +1288,7 @@ impl NodeType {
             NodeType::Text => "Text",
             NodeType::ProcessingInstruction => "Processing-Instruction",
             NodeType::Comment => "Comment",
+            NodeType::EntityReference => "Entity-Reference",
             NodeType::Unknown => "--None--",
         }
     }
@@ -315,8 +1300,57 @@ impl Default for NodeType {
     }
 }
 
+/// The identity of a [Node], that is unique across the [Forest].
+///
+/// A NodeRef may be hashed, so that nodes from any number of trees (for example, a source document and documents loaded by the document() function) can be collected and deduplicated. It includes the [TreeIndex], so it distinguishes nodes in different trees, and the generation of the node's slot in the [Tree]'s allocator, so it does not match a different node that reuses the slot.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct NodeRef {
+    tree: TreeIndex,
+    slot: usize,
+    generation: u64,
+}
+
+impl NodeRef {
+    pub fn tree_index(&self) -> TreeIndex {
+        self.tree
+    }
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+    /// An identifier for the node that is an XML name, such as "d0n5g1". It is made from the [TreeIndex], slot and generation, so it is the same each time it is computed for a node, and different for every other node in the [Forest]. This is the value of the generate-id() function.
+    pub fn to_id(&self) -> String {
+        format!("d{}n{}g{}", self.tree, self.slot, self.generation)
+    }
+    /// The NodeRef for an identifier made by [NodeRef::to_id]. Returns None if the string is not such an identifier.
+    pub fn from_id(id: &str) -> Option<NodeRef> {
+        let (tree, rest) = id.strip_prefix('d')?.split_once('n')?;
+        let (slot, generation) = rest.split_once('g')?;
+        fn num<T: std::str::FromStr>(s: &str) -> Option<T> {
+            s.bytes()
+                .all(|b| b.is_ascii_digit())
+                .then(|| s.parse().ok())?
+        }
+        Some(NodeRef {
+            tree: num(tree)?,
+            slot: num(slot)?,
+            generation: num(generation)?,
+        })
+    }
+    /// Find the [Node] that this refers to. Returns None if the node no longer exists.
+    pub fn to_node(&self, f: &Forest) -> Option<Node> {
+        let i = Index::from_raw_parts(self.slot, self.generation);
+        f.get_ref(self.tree)?
+            .get(i)
+            .map(|_| Node::new(i, self.tree))
+    }
+}
+
+/// The namespace declarations of an element: the prefix, or None for the default namespace, and the namespace URI. An empty URI undeclares the prefix. See [Node::namespace_declarations].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NamespaceDeclarations(pub Vec<(Option<String>, String)>);
+
 /// A node in the [Tree]. Depending on the type of the node, it may have a name, value, content, or attributes.
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Node(Index, TreeIndex);
 
 impl Node {
@@ -326,7 +1360,7 @@ impl Node {
     }
 
     fn get<'a>(&self, f: &'a Forest) -> Option<&'a NodeContent> {
-        f.get_ref(self.1).unwrap().get(self.0)
+        f.get_ref(self.1).and_then(|d| d.get(self.0))
     }
 
     /// Programmer view of the Node. This is needed since the std::fmt::Debug trait cannot be implemented (because the Forest is required to gather the necessary data).
@@ -350,48 +1384,311 @@ impl Node {
     }
 
     /// Return the string representation of the node.
+    ///
+    /// For element and document nodes this is the concatenation of the text of all descendant text nodes.
     pub fn to_string(&self, f: &Forest) -> String {
-        match f.get_ref(self.1) {
-            Some(e) => e,
-            None => return String::from(""),
+        let mut result = String::new();
+        self.collect_text(f, &mut result);
+        result
+    }
+    /// Append the string value of the node to the given String.
+    /// This walks the descendants of an element or document node without creating intermediate Strings.
+    pub fn collect_text(&self, f: &Forest, s: &mut String) {
+        let nc = match self.get(f) {
+            Some(nc) => nc,
+            None => return,
         };
-        match self.node_type(f) {
+        match nc.t {
+            NodeType::Element | NodeType::Document | NodeType::EntityReference => {
+                nc.children.iter().for_each(|c| match c.node_type(f) {
+                    NodeType::Element | NodeType::Text | NodeType::EntityReference => {
+                        c.collect_text(f, s)
+                    }
+                    _ => {}
+                })
+            }
+            NodeType::Text
+            | NodeType::Attribute
+            | NodeType::Comment
+            | NodeType::ProcessingInstruction => match &nc.v {
+                Some(Value::String(t)) => s.push_str(t),
+                Some(v) => s.push_str(v.to_string().as_str()),
+                None => {}
+            },
+            _ => {}
+        }
+    }
+    /// An indented outline of the node and its attributes and descendants, for debugging. Each line shows the type and name of a node, and the start of its value.
+    ///
+    ///```rust
+    ///use xrust::forest::Forest;
+    ///let mut f = Forest::new();
+    ///let src = f.grow_tree("<Example id='x1'>document</Example>")
+    ///    .expect("unable to parse XML");
+    ///let d = f.get_ref(src).unwrap().get_doc_node();
+    ///assert_eq!(
+    ///    d.display_tree(&f),
+    ///    "Document\n  Element Example\n    Attribute id \"x1\"\n    Text \"document\"\n"
+    ///)
+    ///```
+    pub fn display_tree(&self, f: &Forest) -> String {
+        let mut result = String::new();
+        if let Some(d) = f.get_ref(self.1) {
+            let _ = d.write_outline(self.0, 0, &mut result);
+        }
+        result
+    }
+    /// Convert the node and its descendants to the parser's representation. This is the reverse of [Forest::grow_node].
+    ///
+    /// Declarations are added for the prefixes of element and attribute names that have a namespace URI, where the prefix is not already bound to that URI by an ancestor in the result. Returns None for a document node; see [Node::to_xml_document].
+    pub fn to_xml_node(&self, f: &Forest) -> Option<XMLNode> {
+        let mut scope = HashMap::new();
+        scope.insert(String::from("xml"), String::from(XML_NAMESPACE));
+        self.xml_node_int(f, &scope)
+    }
+    fn xml_node_int(&self, f: &Forest, scope: &HashMap<String, String>) -> Option<XMLNode> {
+        let nc = self.get(f)?;
+        let name = || self.to_name(f);
+        let value = || nc.v.clone().unwrap_or_else(|| Value::from(""));
+        match nc.t {
             NodeType::Element => {
-                // TODO: string value of all descendant text nodes
-                String::new()
+                let mut scope = scope.clone();
+                let mut decls = vec![];
+                let mut attributes = vec![];
+                let mut declare = |qn: &QualifiedName| {
+                    if let (Some(p), Some(u)) = (qn.get_prefix(), qn.get_nsuri()) {
+                        if scope.get(&p) != Some(&u) {
+                            decls.push(XMLNode::Attribute(
+                                QualifiedName::new(None, Some(String::from("xmlns")), p.clone()),
+                                Value::from(u.clone()),
+                            ));
+                            scope.insert(p, u);
+                        }
+                    }
+                };
+                declare(&name());
+                let mut ait = self.attribute_iter(f);
+                while let Some(a) = ait.next() {
+                    let qn = a.to_name(f);
+                    declare(&qn);
+                    attributes.push(XMLNode::Attribute(qn, a.to_value(f)))
+                }
+                decls.append(&mut attributes);
+                Some(XMLNode::Element(
+                    name(),
+                    decls,
+                    nc.children
+                        .iter()
+                        .filter_map(|c| c.xml_node_int(f, &scope))
+                        .collect(),
+                ))
             }
-            NodeType::Text | NodeType::Attribute | NodeType::Comment => self
-                .get(f)
-                .unwrap()
-                .value()
-                .as_ref()
-                .map_or(String::new(), |v| v.to_string()),
-            _ => String::new(),
+            NodeType::Attribute => Some(XMLNode::Attribute(name(), value())),
+            NodeType::Text => Some(XMLNode::Text(value())),
+            NodeType::Comment => Some(XMLNode::Comment(value())),
+            NodeType::ProcessingInstruction => Some(XMLNode::PI(name().get_localname(), value())),
+            NodeType::EntityReference => Some(XMLNode::EntityReference(
+                name(),
+                nc.children
+                    .iter()
+                    .filter_map(|c| c.xml_node_int(f, scope))
+                    .collect(),
+            )),
+            NodeType::Document | NodeType::Unknown => None,
+        }
+    }
+    /// Convert a document node and its descendants to the parser's representation. The children before the document element are in the prologue, and those after it are in the epilogue. This is the reverse of [Forest::grow_tree_from_document].
+    ///
+    /// Returns an error if this is not a document node. The XML declaration and document type declaration are not kept in the tree, so they are not included.
+    pub fn to_xml_document(&self, f: &Forest) -> Result<XMLDocument, Error> {
+        if self.node_type(f) != NodeType::Document {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("must be a document node"),
+            ));
+        }
+        let mut d = XMLDocument {
+            prologue: vec![],
+            content: vec![],
+            epilogue: vec![],
+            xmldecl: None,
+            doctype: None,
+        };
+        let mut cit = self.child_iter();
+        while let Some(c) = cit.next(f) {
+            let x = match c.to_xml_node(f) {
+                Some(x) => x,
+                None => continue,
+            };
+            if matches!(x, XMLNode::Element(_, _, _) | XMLNode::Text(_)) {
+                d.content.push(x)
+            } else if d.content.is_empty() {
+                d.prologue.push(x)
+            } else {
+                d.epilogue.push(x)
+            }
+        }
+        Ok(d)
+    }
+    /// Export the structure of the node and its attributes and descendants in the Graphviz DOT language, for visualising the tree when debugging.
+    ///
+    /// Each child edge is labelled with the child's position in its parent's list of children. A dashed edge goes from each node to the parent that it records, so that inconsistent parent and child links can be seen. Attributes are joined to their element by dotted edges.
+    pub fn to_dot(&self, f: &Forest) -> String {
+        let mut result = String::from("digraph {\n");
+        if let Some(d) = f.get_ref(self.1) {
+            let _ = d.write_dot(self.0, &mut result);
         }
+        result.push_str("}\n");
+        result
     }
-    /// Serialise the node as XML.
+    /// Serialise the node as XML. An XML declaration is never written.
     pub fn to_xml(&self, f: &Forest) -> String {
+        let mut result = String::new();
+        let mut ns: HashMap<String, Option<String>> = HashMap::new();
+        // Writing to a String cannot fail
+        let _ = self.write_xml_int(f, &OutputDefinition::new(), 0, &mut ns, &mut result);
+        result
+    }
+    /// Serialise the node as XML, under the control of the given OutputDefinition. The usual use is to perform indenting, i.e. "pretty-printing".
+    ///
+    /// A Document node is written with an XML declaration, unless the OutputDefinition omits it. The declaration has the output encoding (UTF-8 by default) and, if it is set, standalone.
+    ///
+    ///```rust
+    ///use xrust::forest::Forest;
+    ///use xrust::output::OutputDefinitionBuilder;
+    ///let mut f = Forest::new();
+    ///let src = f.grow_tree("<Example><Data>document</Data></Example>")
+    ///    .expect("unable to parse XML");
+    ///let d = f.get_ref(src).unwrap().get_doc_node();
+    ///let od = OutputDefinitionBuilder::new()
+    ///    .indent(true)
+    ///    .standalone(Some(true))
+    ///    .build()
+    ///    .expect("invalid output definition");
+    ///assert_eq!(
+    ///    d.to_xml_with_options(&f, &od),
+    ///    "<?xml version='1.0' encoding='UTF-8' standalone='yes'?>
+    ///<Example>
+    ///  <Data>document</Data>
+    ///</Example>"
+    ///)
+    ///```
+    pub fn to_xml_with_options(&self, f: &Forest, od: &OutputDefinition) -> String {
+        let mut result = String::new();
+        let _ = self.write_xml_fmt(f, &mut result, od);
+        result
+    }
+    /// Serialise the node as XML to a writer, such as a file or socket, under the control of the given OutputDefinition.
+    ///
+    /// The output is written as it is produced, rather than building the entire document as a String. It is encoded as UTF-8, or as UTF-16 with a byte order mark if that is the OutputDefinition's encoding.
+    ///
+    ///```rust
+    ///use xrust::forest::Forest;
+    ///use xrust::output::OutputDefinition;
+    ///let mut f = Forest::new();
+    ///let src = f.grow_tree("<Example>document</Example>")
+    ///    .expect("unable to parse XML");
+    ///let e = f.get_ref(src).unwrap().get_doc_node().get_first_element(&f).unwrap();
+    ///let mut out: Vec<u8> = vec![];
+    ///e.write_xml(&f, &mut out, &OutputDefinition::new())
+    ///    .expect("unable to write XML");
+    ///assert_eq!(out, b"<Example>document</Example>")
+    ///```
+    pub fn write_xml<W: io::Write>(
+        &self,
+        f: &Forest,
+        w: &mut W,
+        od: &OutputDefinition,
+    ) -> Result<(), Error> {
+        let mut a = IoAdapter {
+            w,
+            utf16: od.is_utf16(),
+            err: None,
+        };
+        let r = if a.utf16 {
+            fmt::Write::write_char(&mut a, '\u{FEFF}')
+        } else {
+            Ok(())
+        };
+        match r.and_then(|_| self.write_xml_fmt(f, &mut a, od)) {
+            Ok(()) => Ok(()),
+            Err(_) => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                a.err
+                    .map_or(String::from("unable to write XML"), |e| e.to_string()),
+            )),
+        }
+    }
+    /// Serialise the node as XML to a [fmt::Write], under the control of the given OutputDefinition.
+    pub fn write_xml_fmt<W: fmt::Write>(
+        &self,
+        f: &Forest,
+        w: &mut W,
+        od: &OutputDefinition,
+    ) -> fmt::Result {
         let mut ns: HashMap<String, Option<String>> = HashMap::new();
-        self.to_xml_int(f, &OutputDefinition::new(), 0, &mut ns)
+        let indent = if od.get_indent() { 2 } else { 0 };
+        if self.node_type(f) == NodeType::Document
+            && !od.get_omit_xml_declaration()
+            && matches!(od.get_method(), None | Some("xml"))
+        {
+            w.write_str("<?xml version=")?;
+            write_attribute_value(w, "1.0", od)?;
+            w.write_str(" encoding=")?;
+            write_attribute_value(w, od.get_encoding().unwrap_or("UTF-8"), od)?;
+            if let Some(s) = od.get_standalone() {
+                w.write_str(" standalone=")?;
+                write_attribute_value(w, if s { "yes" } else { "no" }, od)?;
+            }
+            w.write_str("?>")?;
+            if od.get_indent() {
+                w.write_char('\n')?
+            }
+        }
+        self.write_xml_int(f, od, indent, &mut ns, w)
     }
-    fn to_xml_int(
+    fn write_xml_int<W: fmt::Write>(
         &self,
         f: &Forest,
         od: &OutputDefinition,
         indent: usize,
         ns: &mut HashMap<String, Option<String>>,
-    ) -> String {
+        w: &mut W,
+    ) -> fmt::Result {
         let d = match f.get_ref(self.1) {
             Some(e) => e,
-            None => return String::from(""),
+            None => return Ok(()),
         };
         let nc = match d.get(self.0) {
             Some(e) => e,
-            None => return String::from(""),
+            None => return Ok(()),
         };
         match nc.node_type() {
+            NodeType::Document => {
+                // If indenting, then put each top-level node on a separate line
+                let do_indent = od.get_indent() && {
+                    let mut acc = true;
+                    let mut children = self.child_iter();
+                    while let Some(c) = children.next(f) {
+                        if c.node_type(f) == NodeType::Text {
+                            acc = false
+                        }
+                    }
+                    acc
+                };
+                let mut first = true;
+                let mut children = self.child_iter();
+                while let Some(c) = children.next(f) {
+                    if do_indent && !first {
+                        w.write_char('\n')?
+                    }
+                    first = false;
+                    c.write_xml_int(f, od, indent, ns, w)?;
+                }
+                Ok(())
+            }
             NodeType::Element => {
-                let mut result = String::from("<");
+                w.write_char('<')?;
 
                 let name = nc.name().as_ref().unwrap();
 
@@ -427,62 +1724,58 @@ impl Node {
                         None => {
                             // Default namespace
                             match ns.get(uri.as_str()) {
-                                Some(_) => {
+                                Some(None) => {
+                                    // Already declared
+                                }
+                                _ => {
                                     ns.insert(uri.clone(), None);
                                     newns.push((None, uri));
                                 }
-                                None => {
-                                    // Already declared
-                                }
                             }
                         }
                     }
                 }
 
-                result.push_str(name.to_string().as_str());
-                newns.iter().for_each(|(p, u)| {
-                    result.push_str(" xmlns");
+                w.write_str(name.to_string().as_str())?;
+                for (p, u) in &newns {
+                    w.write_str(" xmlns")?;
                     if let Some(q) = p {
-                        result.push(':');
-                        result.push_str(q.as_str());
+                        w.write_char(':')?;
+                        w.write_str(q.as_str())?;
                     }
-                    result.push_str("='");
-                    result.push_str(u);
-                    result.push('\'');
-                });
-                nc.attributes.iter().for_each(|(k, v)| {
+                    w.write_char('=')?;
+                    write_attribute_value(w, u, od)?;
+                }
+                // Attributes are unordered, so write them in order of their name to make the output predictable
+                let mut attrs: Vec<(&QualifiedName, &Node)> =
+                    nc.attributes.iter().flat_map(|m| m.iter()).collect();
+                attrs.sort_by_cached_key(|(k, _)| k.to_string());
+                for (k, v) in attrs {
                     // Declare namespace for attribute, if not already declared
                     if let Some(uri) = k.get_nsuri() {
-                        if ns.get(uri.as_str()).is_none() {
+                        if ns.get(uri.as_str()).is_none() && uri != XML_NAMESPACE {
                             ns.insert(uri.clone(), k.get_prefix());
-                            result.push_str(" xmlns:");
-                            result.push_str(k.get_prefix().unwrap().as_str());
-                            result.push_str("='");
-                            result.push_str(uri.as_str());
-                            result.push('\'');
+                            w.write_str(" xmlns:")?;
+                            w.write_str(k.get_prefix().unwrap().as_str())?;
+                            w.write_char('=')?;
+                            write_attribute_value(w, uri.as_str(), od)?;
                         }
                     }
-                    result.push(' ');
-                    result.push_str(k.to_string().as_str());
-                    result.push_str("='");
-                    result.push_str(v.to_string(f).as_str());
-                    result.push('\'');
-                });
-                result.push_str(">");
+                    w.write_char(' ')?;
+                    w.write_str(k.to_string().as_str())?;
+                    w.write_char('=')?;
+                    write_attribute_value(w, v.to_string(f).as_str(), od)?;
+                }
+                w.write_char('>')?;
 
                 // Content of the element.
                 // If the indent option is enabled, then if no child is a text node then add spacing
                 let do_indent: bool = if od.get_indent() {
                     let mut acc = true;
                     let mut children = self.child_iter();
-                    loop {
-                        match children.next(f) {
-                            Some(c) => {
-                                if c.node_type(f) == NodeType::Text {
-                                    acc = false
-                                }
-                            }
-                            None => break,
+                    while let Some(c) = children.next(f) {
+                        if c.node_type(f) == NodeType::Text {
+                            acc = false
                         }
                     }
                     acc
@@ -490,66 +1783,188 @@ impl Node {
                     false
                 };
                 let mut children = self.child_iter();
-                loop {
-                    match children.next(f) {
-                        Some(c) => {
-                            if do_indent {
-                                result.push('\n');
-                                (0..indent).for_each(|_| result.push(' '));
-                            };
-                            result.push_str(c.to_xml_int(f, od, indent, ns).as_str());
+                while let Some(c) = children.next(f) {
+                    if do_indent {
+                        w.write_char('\n')?;
+                        for _ in 0..indent {
+                            w.write_char(' ')?
                         }
-                        None => break,
-                    }
+                    };
+                    c.write_xml_int(f, od, indent, ns, w)?;
                 }
                 if do_indent {
-                    result.push('\n');
-                    (0..(indent - 2)).for_each(|_| result.push(' '));
+                    w.write_char('\n')?;
+                    for _ in 0..(indent - 2) {
+                        w.write_char(' ')?
+                    }
                 };
 
-                result.push_str("</");
-                result.push_str(name.to_string().as_str());
-                result.push_str(">");
-                result
+                w.write_str("</")?;
+                w.write_str(name.to_string().as_str())?;
+                w.write_char('>')
+            }
+            NodeType::Text => {
+                let v = nc.value().as_ref().unwrap().to_string();
+                if od.get_disable_output_escaping()
+                    && self.get_user_data::<DisableOutputEscaping>(f).is_some()
+                {
+                    w.write_str(v.as_str())
+                } else {
+                    write_text(w, v.as_str())
+                }
             }
-            NodeType::Text => nc.value().as_ref().unwrap().to_string(),
             NodeType::Comment => {
-                let mut result = String::from("<!--");
-                result.push_str(nc.value().as_ref().unwrap().to_string().as_str());
-                result.push_str("-->");
-                result
+                w.write_str("<!--")?;
+                w.write_str(nc.value().as_ref().unwrap().to_string().as_str())?;
+                w.write_str("-->")
             }
             NodeType::ProcessingInstruction => {
-                let mut result = String::from("<?");
-                result.push_str(nc.name().as_ref().unwrap().to_string().as_str());
-                result.push(' ');
-                result.push_str(nc.value().as_ref().unwrap().to_string().as_str());
-                result.push_str("?>");
-                result
+                w.write_str("<?")?;
+                w.write_str(nc.name().as_ref().unwrap().to_string().as_str())?;
+                w.write_char(' ')?;
+                w.write_str(nc.value().as_ref().unwrap().to_string().as_str())?;
+                w.write_str("?>")
+            }
+            NodeType::EntityReference => {
+                w.write_char('&')?;
+                w.write_str(nc.name().as_ref().unwrap().to_string().as_str())?;
+                w.write_char(';')
             }
             _ => {
                 // TODO
-                String::from("-- not implemented --")
+                w.write_str("-- not implemented --")
             }
         }
     }
-    /// Serialise the node as XML, under the control of the given OutputDefinition. The usual use is to perform indenting, i.e. "pretty-printing".
-    pub fn to_xml_with_options(&self, f: &Forest, od: &OutputDefinition) -> String {
-        let mut ns: HashMap<String, Option<String>> = HashMap::new();
-        self.to_xml_int(f, od, 2, &mut ns)
+    /// Serialise the node as JSON: a string that has the node serialised as XML.
+    ///
+    /// To convert the XML representation of JSON, use [xml_to_json](../json/fn.xml_to_json.html).
+    pub fn to_json(&self, f: &Forest) -> String {
+        let mut r = String::new();
+        write_string(&mut r, self.to_xml(f).as_str());
+        r
+    }
+    /// Walk the subtree rooted at this node, firing the equivalent events to the [ContentHandler].
+    ///
+    /// A Document-type node produces start and end document events. Names are reported with their namespace URI, but namespace declarations are not reported; use a [NamespaceFixer](../sax/struct.NamespaceFixer.html) to add them.
+    pub fn emit_events<H: ContentHandler>(&self, f: &Forest, h: &mut H) -> Result<(), Error> {
+        match self.node_type(f) {
+            NodeType::Document => {
+                h.start_document()?;
+                self.emit_children(f, h)?;
+                h.end_document()
+            }
+            NodeType::Element => {
+                let mut attributes = vec![];
+                let mut ait = self.attribute_iter(f);
+                while let Some(a) = ait.next() {
+                    attributes.push((a.to_name(f), a.to_value(f)))
+                }
+                h.start_element(self.to_name(f), attributes)?;
+                self.emit_children(f, h)?;
+                h.end_element(self.to_name(f))
+            }
+            NodeType::Text => h.characters(self.to_string(f).as_str()),
+            NodeType::Comment => h.comment(self.to_string(f).as_str()),
+            NodeType::ProcessingInstruction => h.processing_instruction(
+                self.to_name(f).get_localname().as_str(),
+                self.to_string(f).as_str(),
+            ),
+            NodeType::EntityReference => {
+                let name = self.to_name(f);
+                h.start_entity(&name)?;
+                self.emit_children(f, h)?;
+                h.end_entity(&name)
+            }
+            t => Result::Err(Error::new(
+                ErrorKind::TypeError,
+                format!("cannot emit events for a {} node", t.to_string()),
+            )),
+        }
     }
-    /// Serialise the node as JSON.
-    pub fn to_json(&self, _f: &Forest) -> String {
-        String::from("not implemented yet")
+    fn emit_children<H: ContentHandler>(&self, f: &Forest, h: &mut H) -> Result<(), Error> {
+        let mut cit = self.child_iter();
+        while let Some(c) = cit.next(f) {
+            c.emit_events(f, h)?
+        }
+        Ok(())
     }
 
-    /// A convenience method that converts the value to a string and then converts the string to an integer.
+    /// A convenience method that converts the value to a string and then converts the string to an integer. See also [Node::as_i64].
     pub fn to_int(&self, f: &Forest) -> Result<i64, Error> {
         // Convert to a string, then try parsing that as an integer
         self.to_string(f)
             .parse::<i64>()
             .map_err(|e| Error::new(ErrorKind::Unknown, e.to_string()))
     }
+    /// The string-value of the node. This is the same as [Node::to_string], and is provided for symmetry with the other typed getters.
+    pub fn as_str(&self, f: &Forest) -> String {
+        self.to_string(f)
+    }
+    /// The string-value of the node as an integer. Leading and trailing whitespace is ignored.
+    ///
+    /// The error message includes the name of the node and the value that could not be converted.
+    pub fn as_i64(&self, f: &Forest) -> Result<i64, Error> {
+        let v = self.to_string(f);
+        v.trim()
+            .parse::<i64>()
+            .map_err(|e| self.typed_error(f, &v, "an integer", e.to_string().as_str()))
+    }
+    /// The string-value of the node as a double. Leading and trailing whitespace is ignored. As well as numbers, the values "INF", "-INF" and "NaN" are accepted.
+    pub fn as_f64(&self, f: &Forest) -> Result<f64, Error> {
+        let v = self.to_string(f);
+        match v.trim() {
+            "INF" | "+INF" => Ok(f64::INFINITY),
+            "-INF" => Ok(f64::NEG_INFINITY),
+            "NaN" => Ok(f64::NAN),
+            t if t
+                .chars()
+                .any(|c| c.is_ascii_alphabetic() && c != 'e' && c != 'E') =>
+            {
+                Result::Err(self.typed_error(f, &v, "a double", "invalid float literal"))
+            }
+            t => t
+                .parse::<f64>()
+                .map_err(|e| self.typed_error(f, &v, "a double", e.to_string().as_str())),
+        }
+    }
+    /// The string-value of the node as a boolean. The values "true" and "1" are true, and "false" and "0" are false. Leading and trailing whitespace is ignored.
+    pub fn as_bool(&self, f: &Forest) -> Result<bool, Error> {
+        let v = self.to_string(f);
+        match v.trim() {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => Result::Err(self.typed_error(
+                f,
+                &v,
+                "a boolean",
+                "expected \"true\", \"false\", \"1\" or \"0\"",
+            )),
+        }
+    }
+    /// The string-value of the node as a date, in the form YYYY-MM-DD. Leading and trailing whitespace is ignored. Timezones are not supported.
+    pub fn as_date(&self, f: &Forest) -> Result<NaiveDate, Error> {
+        let v = self.to_string(f);
+        NaiveDate::parse_from_str(v.trim(), "%Y-%m-%d")
+            .map_err(|e| self.typed_error(f, &v, "a date", e.to_string().as_str()))
+    }
+    fn typed_error(&self, f: &Forest, v: &str, ty: &str, reason: &str) -> Error {
+        let name = self.to_name(f).to_string();
+        Error::new(
+            ErrorKind::TypeError,
+            format!(
+                "{} \"{}\" is not {}: {}",
+                if name.is_empty() {
+                    String::from(self.node_type(f).to_string())
+                } else {
+                    format!("{} \"{}\" with value", self.node_type(f).to_string(), name)
+                },
+                v,
+                ty,
+                reason
+            ),
+        )
+    }
     /// A convenience method that converts the value to a string and then converts the string to a double.
     pub fn to_double(&self, f: &Forest) -> f64 {
         // Convert to a string, then try parsing that as a double
@@ -588,12 +2003,15 @@ impl Node {
         })
     }
 
-    /// Append the given node to this node's child list. This node must be an element-type node. The node to be appended must not be an attribute-type node.
+    /// Append the given node to this node's child list. This node must be an element-type, or entity reference-type, node. The node to be appended must not be an attribute-type node.
     ///
     /// If the given node is not in the same [Tree] as this node, makes a deep copy of the given node and appends that to this node's child list.
     pub fn append_child(&self, f: &mut Forest, c: Node) -> Result<(), Error> {
         // Check that self is an element and that c is not an attribute
-        if self.node_type(f) != NodeType::Element {
+        if !matches!(
+            self.node_type(f),
+            NodeType::Element | NodeType::EntityReference
+        ) {
             return Result::Err(Error::new(
                 ErrorKind::Unknown,
                 String::from("must be an element"),
@@ -637,6 +2055,10 @@ impl Node {
                 ))?
                 .children
                 .push(c);
+            let d = f.get_ref_mut(self.1).unwrap();
+            let last = d.get(self.0).unwrap().children.len() - 1;
+            d.renumber_children(self.0, last);
+            d.order_attached(c.0);
         } else {
             // c is in a different Tree, so deep copy
             let cp = c.deep_copy(f, Some(self.1))?;
@@ -667,18 +2089,38 @@ impl Node {
                 ))?
                 .children
                 .push(cp);
+            let d = f.get_ref_mut(self.1).unwrap();
+            let last = d.get(self.0).unwrap().children.len() - 1;
+            d.renumber_children(self.0, last);
+            d.order_attached(cp.0);
         }
 
         Ok(())
     }
     /// Insert the given node before this node in the parent's child list. This node must be an element-type node. The given node must not be an attribute-type node.
+    /// If this node is a child of the Document-type node, then the same constraints apply as for [Tree::push_doc_node].
     /// If the given node is in the same tree, then it is removed from the tree and then inserted so that it becomes the first preceding of this node.
     /// If the given node is in a different tree, then it is deep copied. The copied node will then become the first preceding sibling of this node.
     pub fn insert_before(&self, f: &mut Forest, insert: Node) -> Result<(), Error> {
-        let p = self.parent(f).ok_or(Error::new(
+        // NB. parent() does not return the Document-type node, so use the stored parent instead
+        let p = f
+            .get_ref(self.1)
+            .and_then(|d| d.get(self.0))
+            .and_then(|nc| nc.parent)
+            .ok_or(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to insert before document node"),
+            ))?;
+        let d = f.get_ref(self.1).ok_or(Error::new(
             ErrorKind::Unknown,
-            String::from("unable to insert before document node"),
+            String::from("unable to find tree"),
         ))?;
+        if p.0 == d.d {
+            d.check_doc_child(
+                insert.node_type(f),
+                Some(insert.0).filter(|_| insert.1 == self.1),
+            )?;
+        }
 
         if self.1 == insert.1 {
             // Given node is in the same tree. Detach from it's current position, and then insert before this node.
@@ -687,16 +2129,11 @@ impl Node {
                 ErrorKind::Unknown,
                 String::from("unable to find tree"),
             ))?;
-            let cl = &mut d.get_mut(p.0).unwrap().children;
-            let i = cl
-                .iter()
-                .enumerate()
-                .skip_while(|(_, x)| x.0 != self.0)
-                .nth(0)
-                .map(|(e, _)| e)
-                .unwrap();
-            cl.insert(i, insert);
+            let i = d.get(self.0).unwrap().posn;
+            d.get_mut(p.0).unwrap().children.insert(i, insert);
             d.get_mut(insert.0).unwrap().parent = Some(p);
+            d.renumber_children(p.0, i);
+            d.order_attached(insert.0);
         } else {
             // Given node is in a different tree. Deep copy the node.
             // First find where to insert the copied node
@@ -704,16 +2141,7 @@ impl Node {
                 ErrorKind::Unknown,
                 String::from("unable to find tree"),
             ))?;
-            let i = d
-                .get(p.0)
-                .unwrap()
-                .children
-                .iter()
-                .enumerate()
-                .skip_while(|(_, x)| x.0 != self.0)
-                .nth(0)
-                .map(|(e, _)| e)
-                .unwrap();
+            let i = d.get(self.0).unwrap().posn;
 
             // Then do the copy and insert it
             let cp = insert.deep_copy(f, Some(self.1))?;
@@ -729,46 +2157,84 @@ impl Node {
             clm.insert(i, cp);
 
             // Update the copied node with it's new parent
-            f.get_ref_mut(self.1)
-                .ok_or(Error::new(
-                    ErrorKind::Unknown,
-                    String::from("unable to find tree"),
-                ))?
-                .get_mut(cp.0)
-                .unwrap()
-                .parent = Some(p);
+            let d = f.get_ref_mut(self.1).ok_or(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to find tree"),
+            ))?;
+            d.get_mut(cp.0).unwrap().parent = Some(p);
+            d.renumber_children(p.0, i);
+            d.order_attached(cp.0);
         }
 
         Ok(())
     }
 
-    /// Detach the node from the tree
-    pub fn remove(&self, f: &mut Forest) -> Result<(), Error> {
-        let d = f.get_ref_mut(self.1).ok_or(Error::new(
-            ErrorKind::Unknown,
+    /// Replace this node with the given nodes, which take its place in its parent's child list. This node is detached from the tree.
+    ///
+    /// The nodes are inserted as for [Node::insert_before]. Since this node is replaced, the document element may be replaced by another element, but not by more than one.
+    pub fn replace_with(&self, f: &mut Forest, with: Vec<Node>) -> Result<(), Error> {
+        if with.is_empty() {
+            return self.remove(f);
+        }
+        let d = f.get_ref(self.1).ok_or(Error::new(
+            ErrorKind::Unknown,
             String::from("unable to find tree"),
         ))?;
+        let (p, posn) = d
+            .get(self.0)
+            .and_then(|nc| nc.parent.map(|p| (p, nc.posn)))
+            .ok_or(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to replace a node that has no parent"),
+            ))?;
+        let next = d
+            .get(p.0)
+            .and_then(|pnc| pnc.children.get(posn + 1).copied());
+        let document = p.0 == d.d;
+        if document {
+            // Check the document-level constraints before changing the tree
+            for m in &with {
+                d.check_doc_child(m.node_type(f), Some(self.0))?
+            }
+            if !d.fragment
+                && with
+                    .iter()
+                    .filter(|m| m.node_type(f) == NodeType::Element)
+                    .count()
+                    > 1
+            {
+                return Result::Err(Error::new(
+                    ErrorKind::Unknown,
+                    String::from("document already has a document element"),
+                ));
+            }
+        }
+        self.remove(f)?;
+        for m in with {
+            match next {
+                Some(s) => s.insert_before(f, m)?,
+                None if document => {
+                    let m = if m.1 == self.1 {
+                        m.remove(f)?;
+                        m
+                    } else {
+                        m.deep_copy(f, Some(self.1))?
+                    };
+                    f.get_ref_mut(self.1).unwrap().push_doc_node(m)?
+                }
+                None => p.append_child(f, m)?,
+            }
+        }
+        Ok(())
+    }
 
-        // Is this node in the tree? If not, then do nothing
-        let p = match d.get(self.0).unwrap().parent {
-            Some(q) => q.0,
-            None => return Ok(()),
-        };
-
-        // Remove from parent's child list
-        let cl = &mut d.get_mut(p).unwrap().children;
-        let i = cl
-            .iter()
-            .enumerate()
-            .skip_while(|(_, x)| x.0 != self.0)
-            .nth(0)
-            .map(|(e, _)| e)
-            .unwrap();
-        cl.remove(i);
-
-        // This node now has no parent
-        d.get_mut(self.0).unwrap().parent = None;
-
+    /// Detach the node from the tree. An attribute is removed from its element.
+    pub fn remove(&self, f: &mut Forest) -> Result<(), Error> {
+        let d = f.get_ref_mut(self.1).ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?;
+        d.detach(self.0);
         Ok(())
     }
 
@@ -797,16 +2263,78 @@ impl Node {
             }
         };
 
-        // TODO: detach a from wherever it is currently located
+        d.detach(a.0);
 
         // self will now be a's parent
         d.get_mut(a.0).unwrap().parent = Some(self.clone());
-        // Add a to self's attribute hashmap
+        // Add a to self's attribute hashmap. An attribute with the same name is replaced, and detached.
         let qn = d.get(a.0).unwrap().name().as_ref().unwrap().clone();
-        d.get_mut(self.0).unwrap().attributes.insert(qn, a);
+        let old = d
+            .get_mut(self.0)
+            .unwrap()
+            .attributes
+            .get_or_insert_with(Default::default)
+            .insert(qn, a);
+        if let Some(o) = old.filter(|o| *o != a) {
+            d.order_detached(o.0);
+            if let Some(onc) = d.get_mut(o.0) {
+                onc.parent = None
+            }
+        }
+        d.order_attributes(self.0);
         Ok(())
     }
 
+    /// Create attributes with the given names and values, and add them to this node, which must be an element-type node. An existing attribute with the same name is replaced.
+    ///
+    /// This is equivalent to creating each attribute with [Tree::new_attribute] and adding it with [Node::add_attribute], but looks up the tree only once. Returns an error if any name is not a valid XML name, in which case no attributes are added.
+    pub fn set_attributes(
+        &self,
+        f: &mut Forest,
+        attributes: impl IntoIterator<Item = (QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        if self.node_type(f) != NodeType::Element {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("must be an element"),
+            ));
+        }
+        let attributes: Vec<(QualifiedName, Value)> = attributes.into_iter().collect();
+        if attributes.is_empty() {
+            return Ok(());
+        }
+        for (qn, _) in &attributes {
+            check_name(qn)?
+        }
+        let d = f.get_ref_mut(self.1).ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?;
+        let mut nodes = Vec::with_capacity(attributes.len());
+        for (qn, v) in attributes {
+            let a = d.new_attribute_unchecked(qn.clone(), v);
+            d.get_mut(a.0).unwrap().parent = Some(*self);
+            nodes.push((qn, a))
+        }
+        let map = d
+            .get_mut(self.0)
+            .unwrap()
+            .attributes
+            .get_or_insert_with(Default::default);
+        map.reserve(nodes.len());
+        let replaced: Vec<Node> = nodes
+            .into_iter()
+            .filter_map(|(qn, a)| map.insert(qn, a))
+            .collect();
+        for o in replaced {
+            d.order_detached(o.0);
+            if let Some(onc) = d.get_mut(o.0) {
+                onc.parent = None
+            }
+        }
+        d.order_attributes(self.0);
+        Ok(())
+    }
     /// Creates an interator for the ancestors of this node.
     pub fn ancestor_iter(&self) -> Ancestors {
         Ancestors::new(self.0, self.1)
@@ -817,6 +2345,10 @@ impl Node {
     pub fn parent(&self, f: &Forest) -> Option<Node> {
         self.ancestor_iter().next(f).map(|p| p)
     }
+    // The parent of the node, including the Document-type node. The parent of an attribute is its element.
+    pub(crate) fn parent_node(&self, f: &Forest) -> Option<Node> {
+        self.get(f)?.parent
+    }
     /// Creates an iterator over the children of this node.
     pub fn child_iter(&self) -> Children {
         Children::new(self.0, self.1)
@@ -839,6 +2371,10 @@ impl Node {
         }
         ret
     }
+    /// Creates an iterator over the children of this node, starting with the last child.
+    pub fn child_iter_rev(&self) -> ReverseChildren {
+        ReverseChildren::new(self.0, self.1)
+    }
     /// Creates an iterator over the following siblings of this node.
     pub fn next_iter(&self, f: &Forest) -> Siblings {
         Siblings::new(self.0, self.1, 1, f)
@@ -851,6 +2387,10 @@ impl Node {
     pub fn descend_iter(&self, f: &Forest) -> Descendants {
         Descendants::new(self.0, self.1, f)
     }
+    /// Creates an iterator over the descendants of this node, in reverse document order.
+    pub fn descend_iter_rev(&self, f: &Forest) -> ReverseDescendants {
+        ReverseDescendants::new(self.0, self.1, f)
+    }
     /// Creates an iterator over the attributes of this node.
     pub fn attribute_iter<'a>(&self, f: &'a Forest) -> Attributes<'a> {
         Attributes::new(self.0, f.get_ref(self.1).unwrap())
@@ -859,16 +2399,193 @@ impl Node {
     pub fn get_attribute(&self, f: &Forest, qn: &QualifiedName) -> Option<Node> {
         match f.get_ref(self.1) {
             Some(d) => match d.get(self.0) {
-                Some(nc) => match nc.attributes.get(qn) {
-                    Some(m) => Some(m.clone()),
-                    None => None,
-                },
+                Some(nc) => nc.attributes.as_ref().and_then(|m| m.get(qn)).cloned(),
                 None => None,
             },
             None => None,
         }
     }
 
+    /// The value of the xsi:type attribute, which names the type of the element for schema validation.
+    ///
+    /// The prefix of a type name is resolved using the [namespaces that are in scope](Node::in_scope_namespaces) for the element; it is an error if the prefix is not bound. An unprefixed type name has no namespace URI.
+    pub fn xsi_type(&self, f: &Forest) -> Result<Option<QualifiedName>, Error> {
+        let v = match self.get_attribute(f, &xsi_name("type")) {
+            Some(a) => a.to_string(f),
+            None => return Ok(None),
+        };
+        let v = v.trim();
+        let (prefix, local) = match v.split_once(':') {
+            Some((p, l)) => (Some(p), l),
+            None => (None, v),
+        };
+        if prefix.is_some_and(|p| !is_ncname(p)) || !is_ncname(local) {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                format!("xsi:type value \"{}\" is not a QName", v),
+            ));
+        }
+        let nsuri = match prefix {
+            Some(p) => match self.in_scope_namespaces(f).remove(p) {
+                Some(u) => Some(u),
+                None => {
+                    return Result::Err(Error::new(
+                        ErrorKind::TypeError,
+                        format!("xsi:type value \"{}\" has an undeclared prefix", v),
+                    ))
+                }
+            },
+            None => None,
+        };
+        Ok(Some(QualifiedName::new(
+            nsuri,
+            prefix.map(String::from),
+            String::from(local),
+        )))
+    }
+    /// The value of the xsi:nil attribute, which indicates that the element has no content even though its type requires it. An element without the attribute is not nil.
+    pub fn xsi_nil(&self, f: &Forest) -> Result<bool, Error> {
+        match self.get_attribute(f, &xsi_name("nil")) {
+            Some(a) => match a.to_string(f).trim() {
+                "true" | "1" => Ok(true),
+                "false" | "0" => Ok(false),
+                v => Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    format!("xsi:nil value \"{}\" is not a boolean", v),
+                )),
+            },
+            None => Ok(false),
+        }
+    }
+    /// The value of the xsi:schemaLocation attribute, as pairs of namespace URI and schema location.
+    pub fn xsi_schema_location(&self, f: &Forest) -> Result<Vec<(String, String)>, Error> {
+        match self.get_attribute(f, &xsi_name("schemaLocation")) {
+            Some(a) => {
+                let v = a.to_string(f);
+                let tokens: Vec<&str> = v.split_whitespace().collect();
+                let pairs = tokens.chunks_exact(2);
+                if pairs.remainder().is_empty() {
+                    Ok(pairs
+                        .map(|p| (String::from(p[0]), String::from(p[1])))
+                        .collect())
+                } else {
+                    Result::Err(Error::new(
+                        ErrorKind::TypeError,
+                        String::from(
+                            "xsi:schemaLocation must have pairs of namespace URI and location",
+                        ),
+                    ))
+                }
+            }
+            None => Ok(vec![]),
+        }
+    }
+    /// The value of the xsi:noNamespaceSchemaLocation attribute, which is the location of a schema for elements that are not in a namespace.
+    pub fn xsi_no_namespace_schema_location(&self, f: &Forest) -> Option<String> {
+        self.get_attribute(f, &xsi_name("noNamespaceSchemaLocation"))
+            .map(|a| a.to_string(f).trim().to_string())
+    }
+
+    /// Returns the index of the [Tree] that contains this node.
+    pub fn tree_index(&self) -> TreeIndex {
+        self.1
+    }
+    /// Returns the identity of this node, which is unique across the [Forest].
+    pub fn node_ref(&self) -> NodeRef {
+        let (slot, generation) = self.0.into_raw_parts();
+        NodeRef {
+            tree: self.1,
+            slot,
+            generation,
+        }
+    }
+    /// Returns a key for the position of the node in document order: a node that comes later in the [Tree] has a greater key. The Document-type node has key 0, and the attributes of an element come after the element and before its children. Keys are not consecutive, so they should only be compared with each other.
+    ///
+    /// Returns None if the node is not attached to the [Tree].
+    ///
+    /// The keys of all nodes are computed when first needed and then cached. Keys are spaced out, so that when nodes are attached to the [Tree] they are given keys between those of their neighbours, without changing the keys of any other nodes. Only when there is no room between the neighbours are the keys of the whole [Tree] computed again. [Node::cmp_document_order] compares the order of two nodes without using the cache.
+    pub fn document_order(&self, f: &Forest) -> Option<usize> {
+        f.get_ref(self.1)?.order_of(self.0)
+    }
+    /// Compare the position of this node and another node in document order.
+    ///
+    /// This is done by comparing the paths from the Document-type node to each node, using the position of each node in its parent's child list. These positions are kept up to date as the [Tree] is changed, so the comparison takes time proportional to the depth of the nodes, rather than the size of the [Tree].
+    ///
+    /// Nodes in different trees are ordered by their [TreeIndex]. Returns None if either node is not attached to its [Tree].
+    pub fn cmp_document_order(&self, f: &Forest, other: &Node) -> Option<Ordering> {
+        let p = self.order_path(f.get_ref(self.1)?)?;
+        let q = other.order_path(f.get_ref(other.1)?)?;
+        Some(self.1.cmp(&other.1).then(p.cmp(&q)))
+    }
+    // The path from the Document-type node to this node.
+    // Each step is (0, i) for the i'th attribute of an element, or (1, i) for the i'th child, so that attributes come before children.
+    fn order_path(&self, d: &Tree) -> Option<Vec<(u8, usize)>> {
+        let mut path = vec![];
+        let mut n = self.0;
+        loop {
+            let nc = d.get(n)?;
+            match nc.parent {
+                Some(p) => {
+                    if nc.t == NodeType::Attribute {
+                        let i = d
+                            .get(p.0)?
+                            .attributes
+                            .iter()
+                            .flat_map(|m| m.values())
+                            .position(|a| a.0 == n)?;
+                        path.push((0, i))
+                    } else {
+                        path.push((1, nc.posn))
+                    }
+                    n = p.0
+                }
+                None => break,
+            }
+        }
+        if n != d.d {
+            // Not attached to the tree
+            return None;
+        }
+        path.reverse();
+        Some(path)
+    }
+    /// An absolute XPath expression that selects this node, such as /root/item[3]/@id.
+    ///
+    /// A position is only given for a step when the node has siblings that the same step would also select. Unprefixed names in a namespace use the Q{uri}local form. If the node is not attached to its tree then the path starts at the topmost detached ancestor, and so is not absolute.
+    pub fn path(&self, f: &Forest) -> String {
+        let d = match f.get_ref(self.1) {
+            Some(d) => d,
+            None => return String::new(),
+        };
+        let mut steps = vec![];
+        let mut n = self.0;
+        while let Some(nc) = d.get(n) {
+            if nc.t == NodeType::Document {
+                break;
+            }
+            let mut step = path_test(nc);
+            if let Some(p) = nc.parent {
+                if nc.t != NodeType::Attribute {
+                    let siblings = &d.get(p.0).unwrap().children;
+                    let same = |s: &&Node| d.get(s.0).is_some_and(|sc| same_test(nc, sc));
+                    if siblings.iter().filter(same).count() > 1 {
+                        let posn = siblings[..nc.posn].iter().filter(same).count() + 1;
+                        step.push_str(format!("[{}]", posn).as_str())
+                    }
+                }
+            }
+            steps.push(step);
+            match nc.parent {
+                Some(p) => n = p.0,
+                None => {
+                    steps.reverse();
+                    return steps.join("/");
+                }
+            }
+        }
+        steps.reverse();
+        format!("/{}", steps.join("/"))
+    }
     /// Convenience method that returns if this node is an element-type node
     pub fn is_element(&self, f: &Forest) -> bool {
         match f.get_ref(self.1) {
@@ -879,17 +2596,138 @@ impl Node {
             None => false,
         }
     }
+    /// Convenience method that returns if this node is a text-type node that consists only of whitespace characters, i.e. space, tab, carriage return or line feed.
+    pub fn is_whitespace_only(&self, f: &Forest) -> bool {
+        self.node_type(f) == NodeType::Text
+            && self
+                .to_string(f)
+                .chars()
+                .all(|c| matches!(c, ' ' | '\t' | '\r' | '\n'))
+    }
+    /// The namespace declarations on this element in the source document, in the order that they appear: the prefix, or None for the default namespace, and the namespace URI.
+    ///
+    /// Declarations are kept unless the tree is built with [ParserConfig::namespace_declarations] turned off. They are kept as user data of type [NamespaceDeclarations], so they are not copied by [Node::deep_copy].
+    pub fn namespace_declarations<'a>(&self, f: &'a Forest) -> &'a [(Option<String>, String)] {
+        self.get_user_data::<NamespaceDeclarations>(f)
+            .map_or(&[], |d| d.0.as_slice())
+    }
+    /// Whether this is an attribute that is an ID: either it is xml:id, or the internal subset of the document type declaration declares it to have type ID.
+    pub fn is_id(&self, f: &Forest) -> bool {
+        if self.node_type(f) != NodeType::Attribute {
+            return false;
+        }
+        let name = self.to_name(f);
+        if name.get_nsuri_ref() == Some(XML_NAMESPACE) && name.get_localname() == "id" {
+            return true;
+        }
+        match (self.parent_node(f), f.get_ref(self.1)) {
+            (Some(e), Some(t)) => {
+                t.id_attribute(e.to_name(f).to_string().as_str()) == Some(name.to_string().as_str())
+            }
+            _ => false,
+        }
+    }
+    /// The prefixes that are bound in the scope of this node, and their namespace URIs. The "xml" prefix is always bound; the default namespace is not included.
+    ///
+    /// The bindings are found from the [namespace declarations](Node::namespace_declarations) of the node and its ancestors. The prefixes of their names, and of the names of their attributes, are also in scope, so that something is known even if the declarations were not kept.
+    pub fn in_scope_namespaces(&self, f: &Forest) -> HashMap<String, String> {
+        let mut ns: HashMap<String, String> = HashMap::new();
+        let mut a = Some(*self);
+        while let Some(n) = a {
+            if n.node_type(f) == NodeType::Element {
+                // Inner declarations, including undeclarations, hide outer ones
+                for (p, u) in n.namespace_declarations(f) {
+                    if let Some(p) = p {
+                        ns.entry(p.clone()).or_insert(u.clone());
+                    }
+                }
+                let mut names = vec![n.to_name(f)];
+                let mut at = n.attribute_iter(f);
+                while let Some(b) = at.next() {
+                    names.push(b.to_name(f))
+                }
+                for qn in names {
+                    if let (Some(p), Some(u)) = (qn.get_prefix(), qn.get_nsuri()) {
+                        ns.entry(p).or_insert(u);
+                    }
+                }
+            }
+            a = n.parent_node(f)
+        }
+        ns.retain(|_, u| !u.is_empty());
+        ns.insert(String::from("xml"), String::from(XML_NAMESPACE));
+        ns
+    }
+    /// Attach user data to the node. A node has one slot for each type of data, so this replaces any data of the same type that was previously attached.
+    ///
+    /// User data is shared, not cloned, when the [Tree] is cloned. It is not copied by [Node::deep_copy].
+    pub fn set_user_data<T: Any + Send + Sync>(
+        &self,
+        f: &mut Forest,
+        data: T,
+    ) -> Result<(), Error> {
+        let nc = f
+            .get_ref_mut(self.1)
+            .and_then(|d| d.get_mut(self.0))
+            .ok_or(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to find node"),
+            ))?;
+        nc.user
+            .get_or_insert_with(Default::default)
+            .insert(TypeId::of::<T>(), Arc::new(data));
+        Ok(())
+    }
+    /// The user data of the given type that is attached to the node, if any.
+    pub fn get_user_data<'a, T: Any>(&self, f: &'a Forest) -> Option<&'a T> {
+        self.get(f)?
+            .user
+            .as_ref()?
+            .get(&TypeId::of::<T>())?
+            .downcast_ref::<T>()
+    }
+    /// Remove the user data of the given type from the node. Returns whether the node had data of that type.
+    pub fn clear_user_data<T: Any>(&self, f: &mut Forest) -> bool {
+        match f
+            .get_ref_mut(self.1)
+            .and_then(|d| d.get_mut(self.0))
+            .and_then(|nc| nc.user.as_mut())
+        {
+            Some(u) => u.remove(&TypeId::of::<T>()).is_some(),
+            None => false,
+        }
+    }
+    /// Remove all user data from the node.
+    pub fn clear_all_user_data(&self, f: &mut Forest) {
+        if let Some(nc) = f.get_ref_mut(self.1).and_then(|d| d.get_mut(self.0)) {
+            nc.user = None
+        }
+    }
     /// Make a recursive copy of the node, i.e. a "deep" copy.
     ///
     /// The new node will be created in a different tree if one is supplied.
+    /// User data is not copied; see [Node::deep_copy_with_user_data].
     pub fn deep_copy(&self, f: &mut Forest, t: Option<TreeIndex>) -> Result<Node, Error> {
+        self.copy_node(f, t, false)
+    }
+    /// Make a deep copy of the node, along with any user data attached to the node and its descendants.
+    ///
+    /// The user data is shared with the original nodes, rather than cloned.
+    pub fn deep_copy_with_user_data(
+        &self,
+        f: &mut Forest,
+        t: Option<TreeIndex>,
+    ) -> Result<Node, Error> {
+        self.copy_node(f, t, true)
+    }
+    fn copy_node(&self, f: &mut Forest, t: Option<TreeIndex>, user: bool) -> Result<Node, Error> {
         let cptreeidx = t.map_or_else(|| self.1, |u| u);
         // TODO: check that this is a valid tree index
 
-        match self.node_type(f) {
+        let new = match self.node_type(f) {
             NodeType::Element => {
                 let nm = self.to_name(f);
-                let new = f.get_ref_mut(cptreeidx).unwrap().new_element(nm)?;
+                let new = f.get_ref_mut(cptreeidx).unwrap().new_element_unchecked(nm);
                 let mut attrs = vec![];
                 let mut ait = self.attribute_iter(f);
                 loop {
@@ -899,14 +2737,14 @@ impl Node {
                     }
                 }
                 attrs.iter().for_each(|a| {
-                    let cp = a.deep_copy(f, t).expect("unable to copy attribute");
+                    let cp = a.copy_node(f, t, user).expect("unable to copy attribute");
                     new.add_attribute(f, cp).expect("unable to add attribute");
                 });
                 let mut cit = self.child_iter();
                 loop {
                     match cit.next(f) {
                         Some(d) => {
-                            let cp = d.deep_copy(f, t)?;
+                            let cp = d.copy_node(f, t, user)?;
                             new.append_child(f, cp)?;
                         }
                         None => break,
@@ -917,7 +2755,9 @@ impl Node {
             NodeType::Attribute => {
                 let nm = self.to_name(f);
                 let v = self.to_value(f);
-                f.get_ref_mut(cptreeidx).unwrap().new_attribute(nm, v)
+                Ok(f.get_ref_mut(cptreeidx)
+                    .unwrap()
+                    .new_attribute_unchecked(nm, v))
             }
             NodeType::Text => {
                 let v = self.to_value(f);
@@ -934,14 +2774,175 @@ impl Node {
                     .unwrap()
                     .new_processing_instruction(nm, v)
             }
+            NodeType::EntityReference => {
+                let nm = self.to_name(f);
+                let new = f.get_ref_mut(cptreeidx).unwrap().new_entity_reference(nm)?;
+                let mut cit = self.child_iter();
+                while let Some(d) = cit.next(f) {
+                    let cp = d.copy_node(f, t, user)?;
+                    new.append_child(f, cp)?;
+                }
+                Ok(new)
+            }
             _ => Result::Err(Error::new(
                 ErrorKind::Unknown,
                 String::from("unable to copy node"),
             )),
+        }?;
+        if user {
+            let data = self.get(f).and_then(|nc| nc.user.clone());
+            if let Some(nc) = f.get_ref_mut(cptreeidx).and_then(|d| d.get_mut(new.0)) {
+                nc.user = data
+            }
+        }
+        Ok(new)
+    }
+    /// Make a copy of the node that is cut down according to the given [Truncation], for logging and debugging large documents.
+    ///
+    /// The copy is created in a different tree if one is supplied. Omitted content is replaced by a comment that says how many nodes were left out, and truncated text ends with "...".
+    ///
+    ///```rust
+    ///use xrust::forest::{Forest, Truncation};
+    ///let mut f = Forest::new();
+    ///let src = f.grow_tree("<list><item>first</item><item>second</item><item>third</item></list>")
+    ///    .expect("unable to parse XML");
+    ///let e = f.get_ref(src).unwrap().get_doc_node().get_first_element(&f).unwrap();
+    ///let scratch = f.plant_fragment();
+    ///let preview = e
+    ///    .truncated_copy(&mut f, Some(scratch), &Truncation::new().max_children(1).max_text(3))
+    ///    .expect("unable to copy");
+    ///assert_eq!(preview.to_xml(&f), "<list><item>fir...</item><!-- 2 more nodes --></list>")
+    ///```
+    pub fn truncated_copy(
+        &self,
+        f: &mut Forest,
+        t: Option<TreeIndex>,
+        limits: &Truncation,
+    ) -> Result<Node, Error> {
+        self.truncate_node(f, t.unwrap_or(self.1), limits, 0)
+    }
+    fn truncate_node(
+        &self,
+        f: &mut Forest,
+        t: TreeIndex,
+        limits: &Truncation,
+        depth: usize,
+    ) -> Result<Node, Error> {
+        match self.node_type(f) {
+            NodeType::Element => {
+                let nm = self.to_name(f);
+                let new = f.get_ref_mut(t).unwrap().new_element_unchecked(nm);
+                let mut attrs = vec![];
+                let mut ait = self.attribute_iter(f);
+                while let Some(a) = ait.next() {
+                    attrs.push(a)
+                }
+                for a in attrs {
+                    let cp = a.copy_node(f, Some(t), false)?;
+                    new.add_attribute(f, cp)?
+                }
+                let mut children = vec![];
+                let mut cit = self.child_iter();
+                while let Some(c) = cit.next(f) {
+                    children.push(c)
+                }
+                let keep = if limits.max_depth.is_some_and(|d| depth >= d) {
+                    0
+                } else {
+                    limits.max_children.unwrap_or(usize::MAX)
+                };
+                for c in children.iter().take(keep) {
+                    let cp = c.truncate_node(f, t, limits, depth + 1)?;
+                    new.append_child(f, cp)?
+                }
+                if children.len() > keep {
+                    let omitted = children.len() - keep;
+                    let c = f.get_ref_mut(t).unwrap().new_comment(Value::from(format!(
+                        " {} more node{} ",
+                        omitted,
+                        if omitted == 1 { "" } else { "s" }
+                    )))?;
+                    new.append_child(f, c)?
+                }
+                Ok(new)
+            }
+            NodeType::Text => {
+                let v = self.to_string(f);
+                match limits.max_text {
+                    Some(n) if v.chars().count() > n => {
+                        let mut p: String = v.chars().take(n).collect();
+                        p.push_str("...");
+                        f.get_ref_mut(t).unwrap().new_text(Value::from(p))
+                    }
+                    _ => f.get_ref_mut(t).unwrap().new_text(Value::from(v)),
+                }
+            }
+            _ => self.copy_node(f, Some(t), false),
         }
     }
 }
 
+/// Limits on the size of a copy made by [Node::truncated_copy]. By default there are no limits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Truncation {
+    /// The number of children of each element that are kept.
+    pub max_children: Option<usize>,
+    /// The depth below which content is omitted. The children of the copied node are at depth 1.
+    pub max_depth: Option<usize>,
+    /// The number of characters of each text node that are kept.
+    pub max_text: Option<usize>,
+}
+
+impl Truncation {
+    pub fn new() -> Self {
+        Truncation::default()
+    }
+    /// Keep at most this many children of each element.
+    pub fn max_children(mut self, n: usize) -> Self {
+        self.max_children = Some(n);
+        self
+    }
+    /// Omit the content of elements at this depth.
+    pub fn max_depth(mut self, d: usize) -> Self {
+        self.max_depth = Some(d);
+        self
+    }
+    /// Keep at most this many characters of each text node.
+    pub fn max_text(mut self, n: usize) -> Self {
+        self.max_text = Some(n);
+        self
+    }
+}
+
+// The node test of a path step that selects the node
+fn path_test(nc: &NodeContent) -> String {
+    let name = || {
+        nc.name.as_ref().map_or(String::new(), |qn| {
+            match (qn.get_prefix(), qn.get_nsuri_ref()) {
+                (None, Some(ns)) => format!("Q{{{}}}{}", ns, qn.get_localname()),
+                _ => qn.to_string(),
+            }
+        })
+    };
+    match nc.t {
+        NodeType::Element => name(),
+        NodeType::Attribute => format!("@{}", name()),
+        NodeType::Text => String::from("text()"),
+        NodeType::Comment => String::from("comment()"),
+        NodeType::ProcessingInstruction => format!("processing-instruction({})", name()),
+        _ => String::from("node()"),
+    }
+}
+
+// Would the path step for one node also select the other?
+fn same_test(a: &NodeContent, b: &NodeContent) -> bool {
+    a.t == b.t
+        && match a.t {
+            NodeType::Element | NodeType::ProcessingInstruction => a.name == b.name,
+            _ => true,
+        }
+}
+
 /// Navigate the ancestors of a [Node].
 pub struct Ancestors {
     t: TreeIndex,
@@ -974,86 +2975,75 @@ impl Ancestors {
     }
 }
 
-/// Navigate the descendants of a [Node].
+/// Navigate the descendants of a [Node], in document order.
 pub struct Descendants {
     t: TreeIndex,
-    start: Index,
-    cur: Index,
-    stack: Vec<(Index, usize)>,
+    stack: Vec<(Index, usize)>, // A node, and the position of its next child to visit
 }
 
 impl Descendants {
-    fn new(cur: Index, t: TreeIndex, f: &Forest) -> Descendants {
-        // Find cur in the parent's child list
-        let d = f.get_ref(t).unwrap();
-        let pi = d.get(cur).unwrap().parent.unwrap().0;
-        let p = d.get(pi).unwrap();
-        let q = p
-            .children
-            .iter()
-            .enumerate()
-            .skip_while(|(_, i)| i.0 != cur)
-            .nth(0)
-            .map(|(e, _)| e)
-            .unwrap();
+    fn new(cur: Index, t: TreeIndex, _f: &Forest) -> Descendants {
         Descendants {
             t,
-            start: cur,
-            cur: cur,
-            stack: vec![(pi, q)],
+            stack: vec![(cur, 0)],
         }
     }
     pub fn next(&mut self, f: &Forest) -> Option<Node> {
-        if self.stack.is_empty() {
-            None
-        } else {
-            // Return the first child,
-            // otherwise return the next sibling
-            // otherwise return an ancestor's next sibling
-            // (don't go past start)
-            match Node::new(self.cur, self.t).child_iter().next(f) {
-                Some(n) => {
-                    self.stack.push((self.cur, 0));
-                    self.cur = n.0;
-                    Some(n)
+        let d = f.get_ref(self.t)?;
+        loop {
+            let (p, i) = self.stack.last_mut()?;
+            match d.get(*p).and_then(|nc| nc.children.get(*i)) {
+                Some(c) => {
+                    // Return the next child, and then visit its children
+                    *i += 1;
+                    let c = *c;
+                    self.stack.push((c.0, 0));
+                    return Some(c);
                 }
                 None => {
-                    let d = f.get_ref(self.t).unwrap();
-                    let (i, mut s) = self.stack.last_mut().unwrap();
-                    let pnc = d.get(*i).unwrap();
-                    if pnc.children.len() < s {
-                        // have a next sibling
-                        s += 1;
-                        self.cur = pnc.children.get(s).unwrap().0;
-                        Some(Node::new(self.cur, self.t))
-                    } else {
-                        // ancestor next sibling
-                        let result: Option<Node>;
-                        loop {
-                            self.stack.pop();
-                            if self.stack.is_empty() {
-                                result = None;
-                                break;
-                            } else {
-                                let l = self.stack.last_mut().unwrap();
-                                let (j, mut t) = l;
-                                let qnc = d.get(*j).unwrap();
-                                if qnc.children.len() > t + 1 {
-                                    t += 1;
-                                    *l = (*j, t);
-                                    self.cur = qnc.children.get(t).unwrap().0;
-                                    result = Some(Node::new(self.cur, self.t));
-                                    break;
-                                } else {
-                                    if *j == self.start {
-                                        result = None;
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        result
-                    }
+                    // No more children, so continue with the parent's next child
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Navigate the descendants of a [Node], in reverse document order.
+///
+/// The descendants of a node are visited before the node itself, and the last child is visited first.
+pub struct ReverseDescendants {
+    t: TreeIndex,
+    stack: Vec<(Index, usize)>, // A node, and the number of its children that are still to be visited
+}
+
+impl ReverseDescendants {
+    fn new(cur: Index, t: TreeIndex, f: &Forest) -> ReverseDescendants {
+        let len = f
+            .get_ref(t)
+            .and_then(|d| d.get(cur))
+            .map_or(0, |nc| nc.children.len());
+        ReverseDescendants {
+            t,
+            stack: vec![(cur, len)],
+        }
+    }
+    pub fn next(&mut self, f: &Forest) -> Option<Node> {
+        let d = f.get_ref(self.t)?;
+        loop {
+            let (p, i) = self.stack.last_mut()?;
+            if *i > 0 {
+                // Visit the descendants of the previous child before the child itself
+                *i -= 1;
+                let c = d.get(*p)?.children[*i];
+                let len = d.get(c.0).map_or(0, |nc| nc.children.len());
+                self.stack.push((c.0, len));
+            } else {
+                let p = *p;
+                self.stack.pop();
+                // The starting node is not one of its descendants
+                if !self.stack.is_empty() {
+                    return Some(Node::new(p, self.t));
                 }
             }
         }
@@ -1089,6 +3079,34 @@ impl Children {
     }
 }
 
+/// Navigate the children of a [Node], starting with the last child.
+pub struct ReverseChildren {
+    t: TreeIndex,
+    parent: Index,
+    cur: Option<usize>, // The number of children still to be visited, once it is known
+}
+
+impl ReverseChildren {
+    fn new(parent: Index, t: TreeIndex) -> ReverseChildren {
+        ReverseChildren {
+            t,
+            parent,
+            cur: None,
+        }
+    }
+    pub fn next(&mut self, f: &Forest) -> Option<Node> {
+        let n = f.get_ref(self.t)?.get(self.parent)?;
+        let i = self.cur.unwrap_or(n.children.len());
+        if i > 0 && i <= n.children.len() {
+            self.cur = Some(i - 1);
+            Some(n.children[i - 1])
+        } else {
+            self.cur = Some(0);
+            None
+        }
+    }
+}
+
 /// Navigate the siblings of a [Node]. Nodes may be navigated before (preceding) or after (following) the current [Node].
 pub struct Siblings {
     t: TreeIndex,
@@ -1101,20 +3119,11 @@ impl Siblings {
     fn new(n: Index, t: TreeIndex, dir: i16, f: &Forest) -> Siblings {
         let d = f.get_ref(t).unwrap();
         let nc = d.get(n).unwrap();
-        let pnc = d.get(nc.parent.unwrap().0).unwrap();
-        let cur = pnc
-            .children
-            .iter()
-            .enumerate()
-            .skip_while(|(_, i)| i.0 != n)
-            .nth(0)
-            .map(|(e, _)| e)
-            .unwrap();
         Siblings {
             t,
             parent: nc.parent.unwrap().0,
             dir,
-            cur: cur,
+            cur: nc.posn,
         }
     }
     pub fn next(&mut self, f: &Forest) -> Option<Node> {
@@ -1140,17 +3149,17 @@ impl Siblings {
 
 /// Navigate the attributes of a [Node]. The order in which the attributes are visited is undefined.
 pub struct Attributes<'a> {
-    it: Iter<'a, QualifiedName, Node>,
+    it: Option<Iter<'a, QualifiedName, Node>>,
 }
 
 impl<'a> Attributes<'a> {
     fn new(i: Index, d: &'a Tree) -> Attributes {
         Attributes {
-            it: d.get(i).unwrap().attributes.iter(),
+            it: d.get(i).unwrap().attributes.as_ref().map(|m| m.iter()),
         }
     }
     pub fn next(&mut self) -> Option<Node> {
-        self.it.next().map(|(_, n)| *n)
+        self.it.as_mut().and_then(|it| it.next()).map(|(_, n)| *n)
     }
 }
 
@@ -1160,11 +3169,17 @@ pub struct NodeContent {
     t: NodeType,
     name: Option<QualifiedName>,
     v: Option<Value>,
-    parent: Option<Node>,                     // The document node has no parent
-    attributes: HashMap<QualifiedName, Node>, // for non-elements nodes this is always. Should this be an Option?
-    children: Vec<Node>, // for non-element nodes this is always empty. Should this be an Option?
+    parent: Option<Node>, // The document node has no parent
+    #[allow(clippy::box_collection)]
+    attributes: Option<Box<HashMap<QualifiedName, Node>>>, // Only element nodes have attributes, so other nodes only pay for a pointer
+    children: SmallVec<[Node; 1]>, // A single child, such as the text content of an element, is stored inline
+    posn: usize,                   // The position of this node in the parent's child list
+    #[allow(clippy::box_collection)]
+    user: Option<Box<UserData>>, // Application data, keyed by type. Most nodes have none.
 }
 
+type UserData = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
 impl NodeContent {
     /// Create a NodeContent of the given type
     pub fn new(t: NodeType) -> Self {
@@ -1698,7 +3713,8 @@ mod tests {
     #[test]
     fn descendants() {
         let mut f = Forest::new();
-        let ti = f.plant_tree();
+        // More than one top-level element
+        let ti = f.plant_fragment();
         let e = f
             .get_ref_mut(ti)
             .unwrap()
@@ -1758,7 +3774,8 @@ mod tests {
     #[test]
     fn get_first_element() {
         let mut f = Forest::new();
-        let ti = f.plant_tree();
+        // More than one top-level element
+        let ti = f.plant_fragment();
         let cm = f
             .get_ref_mut(ti)
             .unwrap()
@@ -1799,7 +3816,8 @@ mod tests {
     #[test]
     fn serialise_1() {
         let mut f = Forest::new();
-        let ti = f.plant_tree();
+        // More than one top-level element
+        let ti = f.plant_fragment();
         let e = f
             .get_ref_mut(ti)
             .unwrap()
@@ -1861,7 +3879,8 @@ mod tests {
     #[test]
     fn serialise_2() {
         let mut f = Forest::new();
-        let ti = f.plant_tree();
+        // More than one top-level element
+        let ti = f.plant_fragment();
         let e = f
             .get_ref_mut(ti)
             .unwrap()
@@ -1954,16 +3973,56 @@ mod tests {
                 .to_xml(&f),
             "<Test><empty></empty>
 <data mode='mixed'>This contains <i>mixed</i> content.</data>
-<special>Some escaped chars <&></special>
+<special>Some escaped chars &lt;&amp;&gt;</special>
 </Test>"
         )
     }
 
     #[test]
-    fn deep_copy_1() {
+    fn attribute_quoting() {
         let mut f = Forest::new();
-        let t1 = f
-            .grow_tree("<Test><one/><two/><three/></Test>")
+        let ti = f.plant_tree();
+        let d = f.get_ref_mut(ti).unwrap();
+        let e = d
+            .new_element(QualifiedName::new(None, None, String::from("Test")))
+            .unwrap();
+        d.push_doc_node(e).unwrap();
+        for (n, v) in [
+            ("a", "it's"),
+            ("b", "say \"hi\""),
+            ("c", "both '\""),
+            ("d", "<&\n"),
+        ] {
+            let a = f
+                .get_ref_mut(ti)
+                .unwrap()
+                .new_attribute(
+                    QualifiedName::new(None, None, String::from(n)),
+                    Value::from(v),
+                )
+                .unwrap();
+            e.add_attribute(&mut f, a).unwrap()
+        }
+        let result = e.to_xml(&f);
+        assert!(result.contains(" a=\"it's\""));
+        assert!(result.contains(" b='say \"hi\"'"));
+        assert!(result.contains(" c='both &apos;\"'"));
+        assert!(result.contains(" d='&lt;&amp;&#10;'"));
+
+        let mut od = OutputDefinition::new();
+        od.set_attribute_quote('"').expect("unable to set quote");
+        let result = e.to_xml_with_options(&f, &od);
+        assert!(result.contains(" a=\"it's\""));
+        assert!(result.contains(" b='say \"hi\"'"));
+        assert!(result.contains(" c=\"both '&quot;\""));
+        assert!(od.set_attribute_quote('`').is_err())
+    }
+
+    #[test]
+    fn deep_copy_1() {
+        let mut f = Forest::new();
+        let t1 = f
+            .grow_tree("<Test><one/><two/><three/></Test>")
             .expect("unable to parse document 1");
         let t1root = f
             .get_ref(t1)
@@ -2049,4 +4108,1240 @@ mod tests {
             .expect("unable to append node");
         assert_eq!(t1root.to_xml(&f), "<Test><one></one><two><Another><test>document</test></Another></two><three></three></Test>");
     }
+
+    #[test]
+    fn whitespace_only() {
+        let mut f = Forest::new();
+        let ti = f.plant_tree();
+        let ws = f
+            .get_ref_mut(ti)
+            .unwrap()
+            .new_text(Value::from(" \t\r\n"))
+            .expect("unable to create text node");
+        let nws = f
+            .get_ref_mut(ti)
+            .unwrap()
+            .new_text(Value::from(" x "))
+            .expect("unable to create text node");
+        let nbsp = f
+            .get_ref_mut(ti)
+            .unwrap()
+            .new_text(Value::from("\u{a0}"))
+            .expect("unable to create text node");
+        assert!(ws.is_whitespace_only(&f));
+        assert!(!nws.is_whitespace_only(&f));
+        assert!(!nbsp.is_whitespace_only(&f))
+    }
+
+    #[test]
+    fn strip_whitespace() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test> <one> </one> <two>two</two><pre> </pre> </Test>")
+            .expect("unable to parse XML");
+        let n = f
+            .strip_whitespace_nodes(ti, |g, t| {
+                t.parent(g)
+                    .map_or(true, |p| p.to_name(g).get_localname() != "pre")
+            })
+            .expect("unable to strip whitespace");
+        assert_eq!(n, 4);
+        assert_eq!(
+            f.get_ref(ti)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f),
+            "<Test><one></one><two>two</two><pre> </pre></Test>"
+        )
+    }
+
+    #[test]
+    fn replace_matching() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test><a><a/></a><b/><a>text</a></Test>")
+            .expect("unable to parse XML");
+        let count = f
+            .replace_matching(ti, "child::Test/descendant-or-self::a", |n, g| {
+                let s = n.to_string(g);
+                let d = g.get_ref_mut(ti).unwrap();
+                if s.is_empty() {
+                    Ok(vec![])
+                } else {
+                    let e = d.new_element(QualifiedName::new(None, None, String::from("c")))?;
+                    let t = d.new_text(Value::from(s))?;
+                    e.append_child(g, t)?;
+                    Ok(vec![e])
+                }
+            })
+            .expect("unable to replace nodes");
+        // The nested a element is removed along with its parent
+        assert_eq!(count, 2);
+        // No other tree is created
+        assert!(f.get_ref(ti + 1).is_none());
+        assert_eq!(
+            f.get_ref(ti)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f),
+            "<Test><b></b><c>text</c></Test>"
+        );
+        assert!(f
+            .replace_matching(ti, "count(child::Test)", |_, _| Ok(vec![]))
+            .is_err())
+    }
+
+    #[test]
+    fn write_xml() {
+        struct Broken;
+        impl io::Write for Broken {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "broken pipe"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test a='1'><one>text</one><!--c--></Test>")
+            .expect("unable to parse XML");
+        let e = f
+            .get_ref(ti)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut out: Vec<u8> = vec![];
+        e.write_xml(&f, &mut out, &OutputDefinition::new())
+            .expect("unable to write XML");
+        assert_eq!(String::from_utf8(out).unwrap(), e.to_xml(&f));
+        let r = e.write_xml(&f, &mut Broken, &OutputDefinition::new());
+        assert_eq!(r.err().unwrap().message, "broken pipe")
+    }
+
+    #[test]
+    fn serialise_document() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<?xml version='1.0'?><!--c--><Test><one>text</one></Test>")
+            .expect("unable to parse XML");
+        let d = f.get_ref(ti).unwrap().get_doc_node();
+        assert_eq!(d.to_xml(&f), "<!--c--><Test><one>text</one></Test>");
+        assert_eq!(
+            d.to_xml_with_options(&f, &OutputDefinition::new()),
+            "<?xml version='1.0' encoding='UTF-8'?><!--c--><Test><one>text</one></Test>"
+        );
+        let od = crate::output::OutputDefinitionBuilder::new()
+            .indent(true)
+            .encoding("UTF-16")
+            .standalone(Some(false))
+            .attribute_quote('"')
+            .build()
+            .expect("invalid output definition");
+        assert_eq!(
+            d.to_xml_with_options(&f, &od),
+            "<?xml version=\"1.0\" encoding=\"UTF-16\" standalone=\"no\"?>
+<!--c-->
+<Test>
+  <one>text</one>
+</Test>"
+        );
+        // The bytes that are written have the declared encoding
+        let mut out: Vec<u8> = vec![];
+        d.write_xml(&f, &mut out, &od).expect("unable to write XML");
+        let expected: Vec<u8> = "\u{FEFF}"
+            .chars()
+            .chain(d.to_xml_with_options(&f, &od).chars())
+            .collect::<String>()
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        assert_eq!(out, expected);
+        assert_eq!(&out[..4], &[0xFE, 0xFF, 0x00, b'<']);
+        assert!(crate::output::OutputDefinitionBuilder::new()
+            .encoding("ISO-8859-1")
+            .build()
+            .is_err());
+        let mut od = OutputDefinition::new();
+        assert!(od.set_encoding(Some(String::from("ISO-8859-1"))).is_err());
+        assert!(od.set_encoding(Some(String::from("utf-8"))).is_ok());
+        let od = crate::output::OutputDefinitionBuilder::new()
+            .omit_xml_declaration(true)
+            .build()
+            .expect("invalid output definition");
+        assert_eq!(
+            d.to_xml_with_options(&f, &od),
+            "<!--c--><Test><one>text</one></Test>"
+        );
+        // Only a document node has an XML declaration
+        let e = d.get_first_element(&f).unwrap();
+        assert_eq!(
+            e.to_xml_with_options(&f, &OutputDefinition::new()),
+            "<Test><one>text</one></Test>"
+        );
+    }
+
+    #[test]
+    fn entity_reference() {
+        let mut d = XMLDocument::try_from(
+            "<!DOCTYPE doc [<!ENTITY general '<expansion>entity</expansion>'>]><doc>&general;</doc>",
+        )
+        .expect("unable to parse XML");
+        d.resolve_references(None, &crate::resolvers::MemoryResolver::new())
+            .expect("unable to resolve entities");
+        let mut f = Forest::new();
+        let ti = f.grow_tree_from_document(d).expect("unable to create tree");
+        let e = f
+            .get_ref(ti)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(e.to_xml(&f), "<doc>&general;</doc>");
+        assert_eq!(e.to_string(&f), "entity");
+        let r = e.child_iter().next(&f).unwrap();
+        assert_eq!(r.node_type(&f), NodeType::EntityReference);
+        assert_eq!(r.to_name(&f).to_string(), "general");
+        let cp = e.deep_copy(&mut f, None).expect("unable to copy node");
+        assert_eq!(cp.to_xml(&f), "<doc>&general;</doc>")
+    }
+
+    #[test]
+    fn name_validation() {
+        let mut f = Forest::new();
+        let ti = f.plant_tree();
+        let d = f.get_ref_mut(ti).unwrap();
+        assert!(d
+            .new_element(QualifiedName::new(None, None, String::from("not valid")))
+            .is_err());
+        assert!(d
+            .new_element(QualifiedName::new(
+                None,
+                Some(String::from("1x")),
+                String::from("valid")
+            ))
+            .is_err());
+        assert!(d
+            .new_attribute(
+                QualifiedName::new(None, None, String::from("")),
+                Value::from("v")
+            )
+            .is_err());
+        let e = d.new_element_unchecked(QualifiedName::new(None, None, String::from("not valid")));
+        assert_eq!(e.to_name(&f).get_localname(), "not valid")
+    }
+
+    #[test]
+    fn leaf_storage() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test><one>text</one></Test>")
+            .expect("unable to parse XML");
+        let d = f.get_ref(ti).unwrap().get_doc_node();
+        let e = d.get_first_element(&f).unwrap();
+        let one = e.child_iter().next(&f).unwrap();
+        let t = one.child_iter().next(&f).unwrap();
+        assert!(!one.get(&f).unwrap().children.spilled());
+        assert!(one.get(&f).unwrap().attributes.is_none());
+        assert!(t.get(&f).unwrap().children.is_empty());
+        assert!(t.attribute_iter(&f).next().is_none())
+    }
+
+    #[test]
+    fn sibling_positions() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test><a/><b/><c/><d/></Test>")
+            .expect("unable to parse XML");
+        let e = f
+            .get_ref(ti)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = e.child_iter();
+        let a = cit.next(&f).unwrap();
+        let b = cit.next(&f).unwrap();
+        let c = cit.next(&f).unwrap();
+        let d = cit.next(&f).unwrap();
+
+        // Remove a node and check that the positions of the following siblings have moved up
+        b.remove(&mut f).expect("unable to remove node");
+        assert_eq!(c.prev_iter(&f).next(&f), Some(a));
+        assert_eq!(a.next_iter(&f).next(&f), Some(c));
+
+        // Insert a node and check that the positions of the following siblings have moved down
+        c.insert_before(&mut f, b).expect("unable to insert node");
+        assert_eq!(d.prev_iter(&f).next(&f), Some(c));
+        assert_eq!(c.prev_iter(&f).next(&f), Some(b));
+        assert_eq!(b.next_iter(&f).next(&f), Some(c));
+
+        // Move a node to the end
+        e.append_child(&mut f, a).expect("unable to append node");
+        assert_eq!(b.prev_iter(&f).next(&f), None);
+        assert_eq!(a.prev_iter(&f).next(&f), Some(d));
+        assert_eq!(e.to_xml(&f), "<Test><b></b><c></c><d></d><a></a></Test>")
+    }
+
+    #[test]
+    fn string_value() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test>one<a>two<b>three</b></a><!--not text-->four<?pi not text?></Test>")
+            .expect("unable to parse XML");
+        let d = f.get_ref(ti).unwrap().get_doc_node();
+        let e = d.get_first_element(&f).unwrap();
+        assert_eq!(e.to_string(&f), "onetwothreefour");
+        assert_eq!(d.to_string(&f), "onetwothreefour");
+        let mut s = String::from("prefix:");
+        let mut cit = e.child_iter();
+        cit.next(&f);
+        let a = cit.next(&f).unwrap();
+        a.collect_text(&f, &mut s);
+        assert_eq!(s, "prefix:twothree")
+    }
+
+    #[test]
+    fn document_order() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test><a id='x'>one</a><b/></Test>")
+            .expect("unable to parse XML");
+        let d = f.get_ref(ti).unwrap().get_doc_node();
+        let e = d.get_first_element(&f).unwrap();
+        let mut cit = e.child_iter();
+        let a = cit.next(&f).unwrap();
+        let b = cit.next(&f).unwrap();
+        let id = a
+            .get_attribute(&f, &QualifiedName::new(None, None, String::from("id")))
+            .unwrap();
+        let t = a.child_iter().next(&f).unwrap();
+        assert_eq!(d.document_order(&f), Some(0));
+        let keys: Vec<usize> = [d, e, a, id, t, b]
+            .iter()
+            .map(|n| n.document_order(&f).expect("node is attached"))
+            .collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        b.remove(&mut f).expect("unable to remove node");
+        assert_eq!(b.document_order(&f), None)
+    }
+
+    #[test]
+    fn fragment() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_fragment("one<a>two</a><!--c--><b/>three")
+            .expect("unable to parse fragment");
+        assert!(f.get_ref(t).unwrap().is_fragment());
+        let d = f.get_ref(t).unwrap().get_doc_node();
+        let mut cit = d.child_iter();
+        let mut result = vec![];
+        while let Some(c) = cit.next(&f) {
+            result.push(c.to_xml(&f))
+        }
+        assert_eq!(
+            result,
+            vec!["one", "<a>two</a>", "<!--c-->", "<b></b>", "three"]
+        );
+        assert!(f.grow_fragment("<a>").is_err())
+    }
+
+    #[test]
+    fn document_constraints() {
+        let mut f = Forest::new();
+        let ti = f.plant_tree();
+        let d = f.get_ref_mut(ti).unwrap();
+        let c = d.new_comment(Value::from("c")).unwrap();
+        let e = d
+            .new_element(QualifiedName::new(None, None, String::from("Test")))
+            .unwrap();
+        let g = d
+            .new_element(QualifiedName::new(None, None, String::from("Another")))
+            .unwrap();
+        let t = d.new_text(Value::from("text")).unwrap();
+        d.push_doc_node(c).expect("unable to add comment");
+        d.push_doc_node(e).expect("unable to add element");
+        assert!(d.push_doc_node(g).is_err());
+        assert!(d.push_doc_node(t).is_err());
+
+        d.set_fragment(true);
+        d.push_doc_node(g).expect("unable to add element");
+        d.push_doc_node(t).expect("unable to add text")
+    }
+
+    #[test]
+    fn document_constraints_insert_before() {
+        let mut f = Forest::new();
+        let ti = f.grow_tree("<!--c--><Test/>").expect("unable to parse XML");
+        let d = f.get_ref_mut(ti).unwrap();
+        let doc = d.get_doc_node();
+        let g = d
+            .new_element(QualifiedName::new(None, None, String::from("Another")))
+            .unwrap();
+        let t = d.new_text(Value::from("text")).unwrap();
+        let p = d
+            .new_processing_instruction(
+                QualifiedName::new(None, None, String::from("pi")),
+                Value::from(""),
+            )
+            .unwrap();
+        let e = doc.get_first_element(&f).unwrap();
+        assert!(e.insert_before(&mut f, g).is_err());
+        assert!(e.insert_before(&mut f, t).is_err());
+        // A second document element from another tree is also rejected
+        let tj = f.grow_tree("<Other/>").expect("unable to parse XML");
+        let o = f
+            .get_ref(tj)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert!(e.insert_before(&mut f, o).is_err());
+        assert_eq!(doc.to_xml(&f), "<!--c--><Test></Test>");
+        // A comment, a processing instruction, or the document element itself may be moved
+        e.insert_before(&mut f, p)
+            .expect("unable to insert processing instruction");
+        let c = doc.child_iter().next(&f).unwrap();
+        c.insert_before(&mut f, e)
+            .expect("unable to move document element");
+        assert_eq!(doc.to_xml(&f), "<Test></Test><!--c--><?pi ?>");
+        // The document element may be replaced by one element, but not by two
+        let d = f.get_ref_mut(ti).unwrap();
+        let one = d
+            .new_element(QualifiedName::new(None, None, String::from("One")))
+            .unwrap();
+        let two = d
+            .new_element(QualifiedName::new(None, None, String::from("Two")))
+            .unwrap();
+        assert!(e.replace_with(&mut f, vec![one, two]).is_err());
+        assert_eq!(doc.to_xml(&f), "<Test></Test><!--c--><?pi ?>");
+        e.replace_with(&mut f, vec![one])
+            .expect("unable to replace document element");
+        assert_eq!(doc.to_xml(&f), "<One></One><!--c--><?pi ?>");
+
+        f.get_ref_mut(ti).unwrap().set_fragment(true);
+        one.insert_before(&mut f, g)
+            .expect("unable to insert element");
+        one.insert_before(&mut f, t).expect("unable to insert text")
+    }
+
+    #[test]
+    fn node_ref() {
+        let mut f = Forest::new();
+        let t1 = f.grow_tree("<Test/>").expect("unable to parse XML");
+        let t2 = f.grow_tree("<Test/>").expect("unable to parse XML");
+        let e1 = f
+            .get_ref(t1)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let e2 = f
+            .get_ref(t2)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        // The same slot in different trees
+        assert_ne!(e1.node_ref(), e2.node_ref());
+        assert_eq!(e1.node_ref(), e1.node_ref());
+        assert_eq!(e1.node_ref().tree_index(), t1);
+        assert_eq!(e2.node_ref().to_node(&f), Some(e2));
+        let mut h = std::collections::HashSet::new();
+        h.insert(e1.node_ref());
+        h.insert(e2.node_ref());
+        h.insert(e1.node_ref());
+        assert_eq!(h.len(), 2)
+    }
+
+    #[test]
+    fn document_order_mutation() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test><a id='x'><c/></a><b/></Test>")
+            .expect("unable to parse XML");
+        let e = f
+            .get_ref(ti)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = e.child_iter();
+        let a = cit.next(&f).unwrap();
+        let b = cit.next(&f).unwrap();
+        let c = a.child_iter().next(&f).unwrap();
+        let id = a
+            .get_attribute(&f, &QualifiedName::new(None, None, String::from("id")))
+            .unwrap();
+        assert!(a.document_order(&f) < b.document_order(&f));
+        assert_eq!(a.cmp_document_order(&f, &b), Some(Ordering::Less));
+        assert_eq!(id.cmp_document_order(&f, &c), Some(Ordering::Less));
+        assert_eq!(a.cmp_document_order(&f, &id), Some(Ordering::Less));
+        assert_eq!(e.cmp_document_order(&f, &e), Some(Ordering::Equal));
+
+        // Move b before a. b is given a new key, and the other keys are unchanged.
+        let before = [e, a, id, c].map(|n| n.document_order(&f));
+        a.insert_before(&mut f, b).expect("unable to insert node");
+        assert_eq!([e, a, id, c].map(|n| n.document_order(&f)), before);
+        assert!(e.document_order(&f) < b.document_order(&f));
+        assert!(b.document_order(&f) < a.document_order(&f));
+        assert_eq!(a.cmp_document_order(&f, &b), Some(Ordering::Greater));
+        assert_eq!(c.cmp_document_order(&f, &b), Some(Ordering::Greater));
+
+        // Add an attribute
+        let n = f
+            .get_ref_mut(ti)
+            .unwrap()
+            .new_attribute(
+                QualifiedName::new(None, None, String::from("n")),
+                Value::from("1"),
+            )
+            .expect("unable to create attribute");
+        b.add_attribute(&mut f, n).expect("unable to add attribute");
+        assert!(b.document_order(&f) < n.document_order(&f));
+        assert!(n.document_order(&f) < a.document_order(&f));
+
+        // Detached nodes have no position
+        c.remove(&mut f).expect("unable to remove node");
+        assert_eq!(c.document_order(&f), None);
+        assert_eq!(c.cmp_document_order(&f, &a), None);
+
+        // Nodes in different trees are ordered by tree
+        let tj = f.grow_tree("<Other/>").expect("unable to parse XML");
+        let o = f.get_ref(tj).unwrap().get_doc_node();
+        assert_eq!(a.cmp_document_order(&f, &o), Some(Ordering::Less))
+    }
+
+    #[test]
+    fn document_order_insertion() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test><a/><b/></Test>")
+            .expect("unable to parse XML");
+        let e = f
+            .get_ref(ti)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = e.child_iter();
+        let a = cit.next(&f).unwrap();
+        let b = cit.next(&f).unwrap();
+        let ka = a.document_order(&f);
+        // Insert many nodes at the same place, so that there is eventually no room between the neighbouring keys
+        let mut inserted = vec![];
+        for _ in 0..100 {
+            let n = f
+                .get_ref_mut(ti)
+                .unwrap()
+                .new_element(QualifiedName::new(None, None, String::from("n")))
+                .expect("unable to create element");
+            let t = f
+                .get_ref_mut(ti)
+                .unwrap()
+                .new_text(Value::from("t"))
+                .expect("unable to create text");
+            n.append_child(&mut f, t).expect("unable to append node");
+            b.insert_before(&mut f, n).expect("unable to insert node");
+            inserted.push(n);
+            // Inserting a node does not change the keys of the nodes before it
+            assert_eq!(a.document_order(&f), ka);
+        }
+        // The keys agree with the structure of the tree
+        let mut all = vec![e, a];
+        all.extend(inserted.iter());
+        all.push(b);
+        let keys: Vec<Option<usize>> = all.iter().map(|n| n.document_order(&f)).collect();
+        assert!(keys.iter().all(|k| k.is_some()));
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        for w in all.windows(2) {
+            assert_eq!(w[0].cmp_document_order(&f, &w[1]), Some(Ordering::Less))
+        }
+        let t = inserted[0].child_iter().next(&f).unwrap();
+        assert!(inserted[0].document_order(&f) < t.document_order(&f));
+        assert!(t.document_order(&f) < b.document_order(&f));
+        // The nodes in a detached subtree have no keys
+        inserted[0].remove(&mut f).expect("unable to remove node");
+        assert_eq!(t.document_order(&f), None);
+    }
+
+    #[test]
+    fn grow_recover() {
+        let mut f = Forest::new();
+        let (t, errors) = f
+            .grow_tree_recover("<Test><a>one<b>two</a>three</Test>")
+            .expect("unable to parse XML");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            f.get_ref(t)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f),
+            "<Test><a>one<b>two</b></a>three</Test>"
+        );
+        assert!(f.grow_tree_recover("no elements").is_err())
+    }
+
+    #[test]
+    fn grow_until() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree_until("<feed><head>h</head><entry/><entry/><entry/>", |p| {
+                p.len() == 2 && p[1].name.get_localname() == "head"
+            })
+            .expect("unable to parse XML");
+        assert_eq!(
+            f.get_ref(t)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f),
+            "<feed><head>h</head></feed>"
+        )
+    }
+
+    #[test]
+    fn grow_projection() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree_projection(
+                "<feed>\n<head>h</head>\n<entry><id>1</id><body/></entry>\n</feed>",
+                vec![SimplePath::try_from("/feed/entry/id").unwrap()],
+            )
+            .expect("unable to parse XML");
+        assert_eq!(
+            f.get_ref(t)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f),
+            "<feed><entry><id>1</id></entry></feed>"
+        )
+    }
+
+    #[test]
+    fn undeclare_prefix() {
+        let src = "<a xmlns:p='urn:p'><b xmlns:p=''><c/></b><p:d/></a>";
+        let mut f = Forest::new();
+        assert!(f.grow_tree(src).is_err());
+        let t = f
+            .grow_tree_with_config(src, &ParserConfig::new().namespaces11(true))
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(
+            a.to_xml(&f),
+            "<a><b><c></c></b><p:d xmlns:p='urn:p'></p:d></a>"
+        );
+        // The prefix is not in scope within the element that undeclares it
+        assert!(f
+            .grow_tree_with_config(
+                "<a xmlns:p='urn:p'><b xmlns:p=''><p:c/></b></a>",
+                &ParserConfig::new().namespaces11(true)
+            )
+            .is_err());
+        // Declarations are only in scope for descendants
+        assert!(f.grow_tree("<a><b xmlns:p='urn:p'/><p:c/></a>").is_err());
+        assert!(f.grow_tree("<a xmlns:xml='urn:x'/>").is_err());
+        assert!(f
+            .grow_tree("<a xmlns:xml='http://www.w3.org/XML/1998/namespace'/>")
+            .is_ok())
+    }
+
+    #[test]
+    fn xsi() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a xmlns:xsi='http://www.w3.org/2001/XMLSchema-instance' xmlns:t='urn:types' xsi:schemaLocation='urn:a a.xsd  urn:b b.xsd' t:x='1'><b xsi:type='t:Price' xsi:nil='true'/><c xsi:type='Local' xsi:nil='maybe' xsi:noNamespaceSchemaLocation=' c.xsd'/><d xsi:type='1x' xsi:schemaLocation='urn:a'/></a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = a.child_iter();
+        let b = cit.next(&f).unwrap();
+        let c = cit.next(&f).unwrap();
+        let d = cit.next(&f).unwrap();
+        assert_eq!(
+            a.xsi_schema_location(&f).unwrap(),
+            vec![
+                (String::from("urn:a"), String::from("a.xsd")),
+                (String::from("urn:b"), String::from("b.xsd"))
+            ]
+        );
+        assert_eq!(a.xsi_type(&f).unwrap(), None);
+        assert!(!a.xsi_nil(&f).unwrap());
+        let ty = b.xsi_type(&f).unwrap().unwrap();
+        assert_eq!(ty.get_nsuri_ref(), Some("urn:types"));
+        assert_eq!(ty.get_localname(), "Price");
+        assert!(b.xsi_nil(&f).unwrap());
+        assert_eq!(c.xsi_type(&f).unwrap().unwrap().get_nsuri(), None);
+        assert!(c.xsi_nil(&f).is_err());
+        assert_eq!(
+            c.xsi_no_namespace_schema_location(&f),
+            Some(String::from("c.xsd"))
+        );
+        assert!(d.xsi_type(&f).is_err());
+        assert!(d.xsi_schema_location(&f).is_err())
+    }
+
+    #[test]
+    fn xsi_type_declared_prefix() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<root xmlns:xs='http://www.w3.org/2001/XMLSchema' xmlns:xsi='http://www.w3.org/2001/XMLSchema-instance'><v xsi:type='xs:int'/><w xsi:type='u:int'/></root>")
+            .expect("unable to parse XML");
+        let root = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = root.child_iter();
+        let v = cit.next(&f).unwrap();
+        let w = cit.next(&f).unwrap();
+        // The prefix is only used in a declaration, not in a name
+        assert_eq!(
+            v.xsi_type(&f).unwrap().unwrap().get_nsuri_ref(),
+            Some("http://www.w3.org/2001/XMLSchema")
+        );
+        assert!(w.xsi_type(&f).is_err());
+    }
+
+    #[test]
+    fn comment_pi_content() {
+        let mut f = Forest::new();
+        let t = f.plant_tree();
+        let d = f.get_ref_mut(t).unwrap();
+        assert!(d.new_comment(Value::from(" a - b ")).is_ok());
+        assert!(d.new_comment(Value::from("a -- b")).is_err());
+        assert!(d.new_comment(Value::from("a-")).is_err());
+        assert!(d.new_comment(Value::from("a\u{1}")).is_err());
+        let pi = QualifiedName::new(None, None, String::from("pi"));
+        assert!(d
+            .new_processing_instruction(pi.clone(), Value::from("a ? > b"))
+            .is_ok());
+        assert!(d
+            .new_processing_instruction(pi.clone(), Value::from("a ?> b"))
+            .is_err());
+        assert!(d
+            .new_processing_instruction(pi, Value::from("\u{FFFE}"))
+            .is_err());
+        assert!(d
+            .new_processing_instruction(
+                QualifiedName::new(None, None, String::from("1pi")),
+                Value::from("")
+            )
+            .is_err())
+    }
+
+    #[test]
+    fn typed_getters() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<config port=' 8080 ' ratio='1.5e2' debug='true' start='2024-02-29'><limit>INF</limit><bad>x1</bad></config>")
+            .expect("unable to parse XML");
+        let c = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let attr = |f: &Forest, n: &str| {
+            c.get_attribute(f, &QualifiedName::new(None, None, String::from(n)))
+                .unwrap()
+        };
+        assert_eq!(attr(&f, "port").as_i64(&f).unwrap(), 8080);
+        assert_eq!(attr(&f, "ratio").as_f64(&f).unwrap(), 150.0);
+        assert!(attr(&f, "debug").as_bool(&f).unwrap());
+        assert_eq!(
+            attr(&f, "start").as_date(&f).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+        );
+        assert_eq!(attr(&f, "port").as_str(&f), " 8080 ");
+        let mut cit = c.child_iter();
+        let limit = cit.next(&f).unwrap();
+        let bad = cit.next(&f).unwrap();
+        assert_eq!(limit.as_f64(&f).unwrap(), f64::INFINITY);
+        assert!(bad.as_f64(&f).is_err());
+        assert!(bad.as_bool(&f).is_err());
+        assert!(bad.as_date(&f).is_err());
+        let e = bad.as_i64(&f).unwrap_err();
+        assert!(e
+            .message
+            .starts_with("Element \"bad\" with value \"x1\" is not an integer"))
+    }
+
+    #[test]
+    fn summary() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a x='1'><b x='2' y='3'>one</b><b><c>three</c></b>two</a>")
+            .expect("unable to parse XML");
+        let tr = f.get_ref(t).unwrap();
+        let stats = tr.summary();
+        let b = QualifiedName::new(None, None, String::from("b"));
+        let x = QualifiedName::new(None, None, String::from("x"));
+        assert_eq!(stats.elements.get(&b), Some(&2));
+        assert_eq!(stats.element_count(), 4);
+        assert_eq!(stats.attributes.get(&x), Some(&2));
+        assert_eq!(stats.attribute_count(), 3);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.text_bytes, 11);
+        assert_eq!(Tree::new(t).summary(), TreeStats::default());
+    }
+
+    #[test]
+    fn truncated_copy() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a x='1'><b><c><d/></c></b><b>a long text</b><e/></a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let cp = a
+            .truncated_copy(&mut f, None, &Truncation::new())
+            .expect("unable to copy");
+        assert_eq!(cp.to_xml(&f), a.to_xml(&f));
+        let cp = a
+            .truncated_copy(&mut f, None, &Truncation::new().max_depth(2).max_text(6))
+            .expect("unable to copy");
+        assert_eq!(
+            cp.to_xml(&f),
+            "<a x='1'><b><c><!-- 1 more node --></c></b><b>a long...</b><e></e></a>"
+        );
+        let cp = a
+            .truncated_copy(&mut f, None, &Truncation::new().max_children(0))
+            .expect("unable to copy");
+        assert_eq!(cp.to_xml(&f), "<a x='1'><!-- 3 more nodes --></a>");
+    }
+
+    #[test]
+    fn display_tree() {
+        let mut f = Forest::new();
+        let long = "x".repeat(50);
+        let t = f
+            .grow_tree(format!("<a><!--c--><b>{}</b><?pi data?></a>", long).as_str())
+            .expect("unable to parse XML");
+        let tr = f.get_ref(t).unwrap();
+        let expected = format!(
+            "Document\n  Element a\n    Comment \"c\"\n    Element b\n      Text \"{}\"...\n    Processing-Instruction pi \"data\"\n",
+            "x".repeat(40)
+        );
+        assert_eq!(tr.get_doc_node().display_tree(&f), expected);
+        assert_eq!(format!("{:?}", tr), expected);
+        assert_eq!(format!("{:?}", f), format!("Tree 0:\n{}", expected));
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a x='1'>one<b/></a>")
+            .expect("unable to parse XML");
+        let tr = f.get_ref(t).unwrap();
+        let dot = tr.to_dot();
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.ends_with("}\n"));
+        // document, element a, attribute, text, element b
+        assert_eq!(dot.matches("[label=\"").count(), 5);
+        assert!(dot.contains("[label=\"Attribute x\\n1\"]"));
+        assert_eq!(dot.matches("[style=dashed]").count(), 4);
+        assert_eq!(dot.matches("[style=dotted]").count(), 1);
+        assert!(dot.contains("[label=1];"));
+
+        let a = tr.get_doc_node().get_first_element(&f).unwrap();
+        assert_eq!(a.to_dot(&f).matches("[label=\"").count(), 4);
+    }
+
+    // Every attached node, after checking that parent and child links agree
+    fn attached_nodes(f: &Forest, n: Node, all: &mut Vec<Node>) {
+        all.push(n);
+        let mut ait = n.attribute_iter(f);
+        while let Some(a) = ait.next() {
+            assert_eq!(a.parent_node(f), Some(n));
+            all.push(a)
+        }
+        let mut cit = n.child_iter();
+        let mut i = 0;
+        while let Some(c) = cit.next(f) {
+            assert_eq!(c.parent_node(f), Some(n));
+            assert_eq!(c.get(f).unwrap().posn, i);
+            attached_nodes(f, c, all);
+            i += 1
+        }
+    }
+
+    #[test]
+    fn remove_nodes() {
+        let src =
+            "<a x='1' y='2'><b z='3'>one<c/>two</b><d><e w='4'/><e/>three</d><f v='5' u='6'/></a>";
+        // Remove nodes in a pseudo-random order, checking the structure of the tree after each removal
+        for seed in 0..20_u64 {
+            let mut f = Forest::new();
+            let t = f.grow_tree(src).expect("unable to parse XML");
+            let doc = f.get_ref(t).unwrap().get_doc_node();
+            let mut r = seed;
+            loop {
+                let mut all = vec![];
+                attached_nodes(&f, doc, &mut all);
+                if all.len() == 1 {
+                    break;
+                }
+                r = r
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let n = all[1 + (r >> 33) as usize % (all.len() - 1)];
+                let mut gone = vec![];
+                attached_nodes(&f, n, &mut gone);
+                n.remove(&mut f).expect("unable to remove node");
+                assert_eq!(n.parent_node(&f), None);
+                let mut after = vec![];
+                attached_nodes(&f, doc, &mut after);
+                assert_eq!(after.len(), all.len() - gone.len());
+                assert!(gone.iter().all(|g| !after.contains(g)));
+                // The order keys of the remaining nodes are unaffected, and the removed nodes have none
+                let keys: Vec<Option<usize>> = after.iter().map(|a| a.document_order(&f)).collect();
+                assert!(keys.windows(2).all(|w| w[0].is_some() && w[0] < w[1]));
+                assert!(gone.iter().all(|g| g.document_order(&f).is_none()));
+                // Removing a detached node does nothing
+                n.remove(&mut f).expect("unable to remove node");
+            }
+        }
+    }
+
+    #[test]
+    fn move_attribute() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a x='1'><b x='2'/><c/></a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = a.child_iter();
+        let b = cit.next(&f).unwrap();
+        let c = cit.next(&f).unwrap();
+        let ax = a.attribute_iter(&f).next().unwrap();
+        let bx = b.attribute_iter(&f).next().unwrap();
+        // Moving an attribute detaches it from its element
+        c.add_attribute(&mut f, bx)
+            .expect("unable to add attribute");
+        assert_eq!(a.to_xml(&f), "<a x='1'><b></b><c x='2'></c></a>");
+        // A replaced attribute is detached
+        c.add_attribute(&mut f, ax)
+            .expect("unable to add attribute");
+        assert_eq!(a.to_xml(&f), "<a><b></b><c x='1'></c></a>");
+        assert_eq!(bx.parent_node(&f), None);
+        assert_eq!(ax.parent_node(&f), Some(c));
+        ax.remove(&mut f).expect("unable to remove attribute");
+        assert_eq!(a.to_xml(&f), "<a><b></b><c></c></a>");
+    }
+
+    #[test]
+    fn set_attributes() {
+        let mut f = Forest::new();
+        let t = f.grow_tree("<a x='1'/>").expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let x = a.attribute_iter(&f).next().unwrap();
+        let name = |n: &str| QualifiedName::new(None, None, String::from(n));
+        a.set_attributes(
+            &mut f,
+            vec![(name("y"), Value::from("2")), (name("x"), Value::from("3"))],
+        )
+        .expect("unable to set attributes");
+        assert_eq!(a.get_attribute(&f, &name("x")).unwrap().to_string(&f), "3");
+        assert_eq!(a.get_attribute(&f, &name("y")).unwrap().to_string(&f), "2");
+        assert_eq!(x.parent_node(&f), None);
+        let mut all = vec![];
+        attached_nodes(&f, a, &mut all);
+        assert_eq!(all.len(), 3);
+
+        assert!(a
+            .set_attributes(
+                &mut f,
+                vec![
+                    (name("z"), Value::from("4")),
+                    (name("1z"), Value::from("5"))
+                ]
+            )
+            .is_err());
+        assert!(a.get_attribute(&f, &name("z")).is_none());
+    }
+
+    #[test]
+    fn xml_node_round_trip() {
+        let src = "<!--first--><a xmlns:p='urn:p'><p:b p:x='1' xml:lang='en'>one</p:b><c xmlns:p='urn:q'><p:d/></c></a><?pi last?>";
+        let mut f = Forest::new();
+        let t = f.grow_tree(src).expect("unable to parse XML");
+        let doc = f.get_ref(t).unwrap().get_doc_node();
+        assert!(doc.to_xml_node(&f).is_none());
+        let d = doc.to_xml_document(&f).expect("unable to convert document");
+        assert_eq!(d.prologue.len(), 1);
+        assert_eq!(d.content.len(), 1);
+        assert_eq!(d.epilogue.len(), 1);
+        let u = f.grow_tree_from_document(d).expect("unable to create tree");
+        assert_eq!(
+            f.get_ref(u).unwrap().get_doc_node().to_xml(&f),
+            doc.to_xml(&f)
+        );
+
+        // A node below the document element declares the prefixes that it uses
+        let a = doc.get_first_element(&f).unwrap();
+        let b = a.child_iter().next(&f).unwrap();
+        match b.to_xml_node(&f) {
+            Some(XMLNode::Element(_, attrs, _)) => assert_eq!(attrs.len(), 3),
+            _ => panic!("not an element"),
+        }
+        let w = f.plant_fragment();
+        let n = f
+            .grow_node(w, b.to_xml_node(&f).unwrap())
+            .expect("unable to create node");
+        assert_eq!(n.to_name(&f).get_nsuri_ref(), Some("urn:p"));
+        assert!(a
+            .child_iter()
+            .next(&f)
+            .unwrap()
+            .to_xml_document(&f)
+            .is_err());
+    }
+
+    #[test]
+    fn user_data() {
+        let mut f = Forest::new();
+        let t = f.grow_tree("<a><b/></a>").expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let b = a.child_iter().next(&f).unwrap();
+        assert!(b.get_user_data::<u32>(&f).is_none());
+        b.set_user_data(&mut f, 7_u32)
+            .expect("unable to set user data");
+        b.set_user_data(&mut f, String::from("valid"))
+            .expect("unable to set user data");
+        assert_eq!(b.get_user_data::<u32>(&f), Some(&7));
+        assert_eq!(
+            b.get_user_data::<String>(&f).map(|s| s.as_str()),
+            Some("valid")
+        );
+        assert!(a.get_user_data::<u32>(&f).is_none());
+
+        // Plain copies do not have user data, unless it is asked for
+        let cp = a.deep_copy(&mut f, None).expect("unable to copy");
+        let cpb = cp.child_iter().next(&f).unwrap();
+        assert!(cpb.get_user_data::<u32>(&f).is_none());
+        let cp = a
+            .deep_copy_with_user_data(&mut f, None)
+            .expect("unable to copy");
+        let cpb = cp.child_iter().next(&f).unwrap();
+        assert_eq!(cpb.get_user_data::<u32>(&f), Some(&7));
+
+        assert!(b.clear_user_data::<u32>(&mut f));
+        assert!(!b.clear_user_data::<u32>(&mut f));
+        assert!(b.get_user_data::<String>(&f).is_some());
+        b.clear_all_user_data(&mut f);
+        assert!(b.get_user_data::<String>(&f).is_none());
+        // The copy is unaffected
+        assert_eq!(cpb.get_user_data::<u32>(&f), Some(&7));
+        // User data does not stop the forest being moved to another thread
+        let h = std::thread::spawn(move || cpb.get_user_data::<u32>(&f).copied());
+        assert_eq!(h.join().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn namespace_declarations() {
+        let src = r#"<a xmlns="urn:d" xmlns:p="urn:p" p:x="1"><b xmlns:p="urn:q"/><c/></a>"#;
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree_with_config(src, &ParserConfig::new().namespace_declarations(true))
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(
+            a.namespace_declarations(&f),
+            &[
+                (None, String::from("urn:d")),
+                (Some(String::from("p")), String::from("urn:p"))
+            ]
+        );
+        let mut children = a.child_iter();
+        let b = children.next(&f).unwrap();
+        assert_eq!(
+            b.namespace_declarations(&f),
+            &[(Some(String::from("p")), String::from("urn:q"))]
+        );
+        assert!(children
+            .next(&f)
+            .unwrap()
+            .namespace_declarations(&f)
+            .is_empty());
+
+        // Kept by default, unless they are turned off
+        let t = f.grow_tree(src).expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(a.namespace_declarations(&f).len(), 2);
+        let t = f
+            .grow_tree_with_config(src, &ParserConfig::new().namespace_declarations(false))
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert!(a.namespace_declarations(&f).is_empty());
+    }
+
+    #[test]
+    fn undeclared_prefixes() {
+        let mut f = Forest::new();
+        for src in [
+            "<p:a/>",
+            "<a p:x='1'/>",
+            "<a><b xmlns:p='urn:p'/><p:c/></a>",
+        ] {
+            match f.grow_tree(src) {
+                Ok(_) => panic!("undeclared prefix was accepted in {}", src),
+                Err(e) => assert!(e.message.starts_with("NSC: Prefix Declared")),
+            }
+        }
+        let t = f
+            .grow_tree_with_config(
+                "<p:a q:x='1'/>",
+                &ParserConfig::new().undeclared_prefixes(true),
+            )
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(a.to_name(&f).get_prefix(), Some(String::from("p")));
+        assert_eq!(a.to_name(&f).get_nsuri(), None);
+        let x = a.attribute_iter(&f).next().unwrap();
+        assert_eq!(x.to_name(&f).get_nsuri(), None);
+        assert_eq!(x.to_string(&f), "1");
+    }
+
+    #[test]
+    fn attributes_unique_by_namespace() {
+        let mut f = Forest::new();
+        assert!(f
+            .grow_tree(r#"<a xmlns:p="urn:x" xmlns:q="urn:x" p:x="1" q:x="2"/>"#)
+            .is_err());
+        assert!(f
+            .grow_tree(r#"<a xmlns:p="urn:x" xmlns:q="urn:y" p:x="1" q:x="2" x="3"/>"#)
+            .is_ok());
+    }
+
+    #[test]
+    fn path() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<root xmlns:p='urn:p'><item/><other>text<!--c--></other><item/><item id='x'>a<?pi?>b</item><p:item/></root>")
+            .expect("unable to parse XML");
+        let root = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(f.get_ref(t).unwrap().get_doc_node().path(&f), "/");
+        assert_eq!(root.path(&f), "/root");
+        let mut cit = root.child_iter();
+        let mut children = vec![];
+        while let Some(c) = cit.next(&f) {
+            children.push(c)
+        }
+        assert_eq!(children[0].path(&f), "/root/item[1]");
+        assert_eq!(children[1].path(&f), "/root/other");
+        assert_eq!(
+            children[1].child_iter().next(&f).unwrap().path(&f),
+            "/root/other/text()"
+        );
+        let id = children[3]
+            .get_attribute(&f, &QualifiedName::new(None, None, String::from("id")))
+            .unwrap();
+        assert_eq!(id.path(&f), "/root/item[3]/@id");
+        let mut cit = children[3].child_iter();
+        assert_eq!(cit.next(&f).unwrap().path(&f), "/root/item[3]/text()[1]");
+        assert_eq!(
+            cit.next(&f).unwrap().path(&f),
+            "/root/item[3]/processing-instruction(pi)"
+        );
+        assert_eq!(children[4].path(&f), "/root/p:item");
+
+        // A detached node
+        children[3].remove(&mut f).expect("unable to remove node");
+        assert_eq!(id.path(&f), "item/@id");
+        assert_eq!(children[2].path(&f), "/root/item[2]")
+    }
+
+    #[test]
+    fn reverse_iterators() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a><b><c/><d/></b><e><g/></e></a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut names = vec![];
+        let mut cit = a.child_iter_rev();
+        while let Some(c) = cit.next(&f) {
+            names.push(c.to_name(&f).get_localname())
+        }
+        assert_eq!(names, vec!["e", "b"]);
+        assert!(cit.next(&f).is_none());
+
+        let mut names = vec![];
+        let mut dit = a.descend_iter_rev(&f);
+        while let Some(c) = dit.next(&f) {
+            names.push(c.to_name(&f).get_localname())
+        }
+        assert_eq!(names, vec!["g", "e", "d", "c", "b"]);
+    }
 }
@@ -0,0 +1,311 @@
+/*! ## Formatting XML documents
+
+Re-indent a document, for use as an XML code formatter.
+
+Indentation is only added to elements that have element-only content, i.e. elements whose children are elements, comments and processing instructions, along with whitespace-only text. The whitespace-only text in such an element is replaced by newlines and indentation. Elements with mixed content, that is any text that is not whitespace, are left unchanged, as are elements within the scope of xml:space="preserve". This means that the string-value of every element that contains significant text is unchanged by formatting, with the exception below.
+
+The document is parsed into a tree before it is formatted, so its markup is not all kept:
+- The document type declaration is dropped. Entity references are replaced by their content, and default attributes are written explicitly.
+- CDATA sections are written as escaped text.
+- Whitespace that is written as a character reference, such as `&#x20;`, or in a CDATA section can't be told apart from other whitespace. In element-only content it is replaced by indentation, which changes the string-value of the element.
+
+```rust
+use xrust::format::{check, format_str};
+
+let src = "<Example><Title>A <b>bold</b> title</Title>   <Para/></Example>";
+let formatted = format_str(src, "  ").expect("unable to format document");
+assert_eq!(formatted, "<Example>
+  <Title>A <b>bold</b> title</Title>
+  <Para></Para>
+</Example>");
+
+// Check mode reports the lines that would change, without producing output
+assert!(!check(src, "  ").expect("unable to check document").is_empty());
+assert!(check(formatted.as_str(), "  ").expect("unable to check document").is_empty());
+```
+*/
+
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::qname::{QualifiedName, XML_NAMESPACE};
+use crate::value::Value;
+use crate::xdmerror::*;
+
+/// A line that would be changed by formatting. Lines are numbered from 1 and are compared by position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub line: usize,
+    /// The line in the original document, or None if formatting adds lines
+    pub original: Option<String>,
+    /// The line in the formatted document, or None if formatting removes lines
+    pub formatted: Option<String>,
+}
+
+/// Format a tree, using the given string for each level of indentation. The tree is not modified.
+pub fn format(f: &mut Forest, t: TreeIndex, indent: &str) -> Result<String, Error> {
+    let d = f
+        .get_ref(t)
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?
+        .get_doc_node();
+    // Make a copy of the tree to add the indentation to. The copy is discarded afterwards.
+    let trees = f.tree_count();
+    let cp = f.plant_tree();
+    let result = format_copy(f, d, cp, indent);
+    f.truncate(trees);
+    result
+}
+
+// Copy the children of the document node into the tree, and format them
+fn format_copy(f: &mut Forest, d: Node, cp: TreeIndex, indent: &str) -> Result<String, Error> {
+    let mut children = vec![];
+    let mut cit = d.child_iter();
+    while let Some(c) = cit.next(f) {
+        children.push(c)
+    }
+    let mut result = vec![];
+    for c in children {
+        let n = c.deep_copy(f, Some(cp))?;
+        f.get_ref_mut(cp).unwrap().push_doc_node(n)?;
+        if n.node_type(f) == NodeType::Element {
+            reindent(f, n, 0, indent)?
+        }
+        if !n.is_whitespace_only(f) {
+            result.push(n.to_xml(f))
+        }
+    }
+    Ok(result.join("\n"))
+}
+
+/// Parse and format a document. The XML declaration, if any, is kept.
+pub fn format_str(src: &str, indent: &str) -> Result<String, Error> {
+    let mut f = Forest::new();
+    // The parser does not accept whitespace after the document element, and it isn't kept anyway
+    let t = f.grow_tree(src.trim_end())?;
+    let body = format(&mut f, t, indent)?;
+    match declaration(src) {
+        Some(d) => Ok(format!("{}\n{}", d, body)),
+        None => Ok(body),
+    }
+}
+
+/// Report the changes that formatting would make to a document, without producing the formatted document.
+/// An empty result means that the document is already formatted.
+pub fn check(src: &str, indent: &str) -> Result<Vec<Change>, Error> {
+    let formatted = format_str(src, indent)?;
+    // Only a final newline is ignored, so that line ends that are CRLF are reported as changes
+    let mut o = src.strip_suffix('\n').unwrap_or(src).split('\n');
+    let mut n = formatted.split('\n');
+    let mut result = vec![];
+    let mut line = 1;
+    loop {
+        match (o.next(), n.next()) {
+            (None, None) => break,
+            (a, b) => {
+                if a != b {
+                    result.push(Change {
+                        line,
+                        original: a.map(String::from),
+                        formatted: b.map(String::from),
+                    })
+                }
+            }
+        }
+        line += 1;
+    }
+    Ok(result)
+}
+
+// The XML declaration at the start of the document
+fn declaration(src: &str) -> Option<&str> {
+    if src.starts_with("<?xml") && src[5..].starts_with(|c: char| c.is_whitespace()) {
+        src.find("?>").map(|e| &src[..e + 2])
+    } else {
+        None
+    }
+}
+
+fn reindent(f: &mut Forest, n: Node, depth: usize, indent: &str) -> Result<(), Error> {
+    // Descendants of an element that preserves whitespace are not visited,
+    // so the scope of xml:space is respected.
+    let preserve = n
+        .get_attribute(
+            f,
+            &QualifiedName::new(
+                Some(String::from(XML_NAMESPACE)),
+                Some(String::from("xml")),
+                String::from("space"),
+            ),
+        )
+        .is_some_and(|a| a.to_string(f) == "preserve");
+    let mut children = vec![];
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        children.push(c)
+    }
+    // Any change inside mixed content would change the string-value of the element
+    let mixed = children.iter().any(|c| match c.node_type(f) {
+        NodeType::Text => !c.is_whitespace_only(f),
+        NodeType::EntityReference => true,
+        _ => false,
+    });
+    if preserve || mixed {
+        return Ok(());
+    }
+    let element_only = children.iter().any(|c| c.node_type(f) != NodeType::Text);
+    let t = n.tree_index();
+    for c in &children {
+        if element_only {
+            if c.node_type(f) == NodeType::Text {
+                c.remove(f)?;
+                continue;
+            }
+            let ws = f
+                .get_ref_mut(t)
+                .unwrap()
+                .new_text(Value::from(newline(indent, depth + 1)))?;
+            c.insert_before(f, ws)?
+        }
+        if c.node_type(f) == NodeType::Element {
+            reindent(f, *c, depth + 1, indent)?
+        }
+    }
+    if element_only {
+        let ws = f
+            .get_ref_mut(t)
+            .unwrap()
+            .new_text(Value::from(newline(indent, depth)))?;
+        n.append_child(f, ws)?
+    }
+    Ok(())
+}
+
+fn newline(indent: &str, depth: usize) -> String {
+    let mut s = String::from("\n");
+    (0..depth).for_each(|_| s.push_str(indent));
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn element_only() {
+        assert_eq!(
+            format_str("<a>\n<b><c/>\n\n</b>  <d/></a>", "\t").expect("unable to format"),
+            "<a>\n\t<b>\n\t\t<c></c>\n\t</b>\n\t<d></d>\n</a>"
+        )
+    }
+
+    #[test]
+    fn mixed_content() {
+        let src = "<a><p>Some <b> <i>text</i> </b> here</p><q> x <r/></q></a>";
+        let result = format_str(src, "  ").expect("unable to format");
+        assert_eq!(
+            result,
+            "<a>\n  <p>Some <b> <i>text</i> </b> here</p>\n  <q> x <r></r></q>\n</a>"
+        );
+        // The string-value of elements is unchanged
+        let mut f = Forest::new();
+        let t = f.grow_tree(result.as_str()).expect("unable to parse");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = a.child_iter();
+        let mut values = vec![];
+        while let Some(c) = cit.next(&f) {
+            if c.is_element(&f) {
+                values.push(c.to_string(&f))
+            }
+        }
+        assert_eq!(values, vec!["Some  text  here", " x "])
+    }
+
+    #[test]
+    fn no_trees_left() {
+        let mut f = Forest::new();
+        let t = f.grow_tree("<a><b/></a>").expect("unable to parse");
+        let trees = f.tree_count();
+        for _ in 0..3 {
+            assert_eq!(
+                format(&mut f, t, " ").expect("unable to format"),
+                "<a>\n <b></b>\n</a>"
+            )
+        }
+        assert_eq!(f.tree_count(), trees)
+    }
+
+    #[test]
+    fn preserve() {
+        assert_eq!(
+            format_str(
+                "<a><b xml:space='preserve'><c> <d/></c></b><e xml:space='default'><f/></e></a>",
+                " "
+            )
+            .expect("unable to format"),
+            "<a>\n <b xml:space='preserve'><c> <d></d></c></b>\n <e xml:space='default'>\n  <f></f>\n </e>\n</a>"
+        )
+    }
+
+    #[test]
+    fn prologue() {
+        let src = "<?xml version='1.0'?>\n<!-- c --><a><b/></a>";
+        let result = format_str(src, "  ").expect("unable to format");
+        assert_eq!(
+            result,
+            "<?xml version='1.0'?>\n<!-- c -->\n<a>\n  <b></b>\n</a>"
+        );
+        let changes = check(src, "  ").expect("unable to check");
+        assert_eq!(
+            changes[0],
+            Change {
+                line: 2,
+                original: Some(String::from("<!-- c --><a><b/></a>")),
+                formatted: Some(String::from("<!-- c -->")),
+            }
+        );
+        assert_eq!(changes.len(), 4);
+        assert!(check(result.as_str(), "  ").unwrap().is_empty())
+    }
+
+    #[test]
+    fn markup_not_kept() {
+        // Entities declared in the document type declaration are expanded, and the declaration is dropped
+        assert_eq!(
+            format_str("<!DOCTYPE a [<!ENTITY e 'x'>]><a><b>&e;</b></a>", "  ")
+                .expect("unable to format"),
+            "<a>\n  <b>x</b>\n</a>"
+        );
+        // A CDATA section is written as text
+        assert_eq!(
+            format_str("<a><b><![CDATA[<x>]]></b></a>", "  ").expect("unable to format"),
+            "<a>\n  <b>&lt;x&gt;</b>\n</a>"
+        );
+        // Whitespace written as a character reference or in a CDATA section is replaced in element-only content
+        for src in ["<a>&#x20;<b/></a>", "<a><![CDATA[ ]]><b/></a>"] {
+            assert_eq!(
+                format_str(src, "  ").expect("unable to format"),
+                "<a>\n  <b></b>\n</a>"
+            )
+        }
+    }
+
+    #[test]
+    fn check_line_ends() {
+        assert!(check("<a>\n  <b></b>\n</a>\n", "  ")
+            .expect("unable to check")
+            .is_empty());
+        let changes = check("<a>\r\n  <b></b>\r\n</a>\r\n", "  ").expect("unable to check");
+        assert_eq!(
+            changes.iter().map(|c| c.line).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(changes[0].original, Some(String::from("<a>\r")));
+        assert_eq!(changes[0].formatted, Some(String::from("<a>")))
+    }
+}
@@ -5,11 +5,15 @@
 //!
 //! Nodes are implemented as a trait.
 
-use crate::forest::{Forest, Node, NodeType};
+use crate::edit::EditableDocument;
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::json::write_string;
 use crate::output::OutputDefinition;
 use crate::qname::QualifiedName;
 use crate::value::{Operator, Value};
 use crate::xdmerror::{Error, ErrorKind};
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt;
 use std::rc::Rc;
 
@@ -29,6 +33,8 @@ pub trait SequenceTrait {
     fn to_xml_with_options(&self, od: &OutputDefinition, d: Option<&Forest>) -> String;
     /// Return a JSON formatted representation of the [Sequence].
     fn to_json(&self, d: Option<&Forest>) -> String;
+    /// Return a readable representation of the [Sequence], using the "adaptive" output method. Each item is on a separate line.
+    fn to_adaptive(&self, d: Option<&Forest>) -> String;
     /// Return the Effective Boolean Value of the [Sequence].
     fn to_bool(&self) -> bool;
     /// Convert the [Sequence] to an integer. The [Sequence] must be a singleton value.
@@ -39,6 +45,11 @@ pub trait SequenceTrait {
     fn push_value(&mut self, v: Value);
     /// Push an [Item] to the [Sequence]
     fn push_item(&mut self, i: &Rc<Item>);
+    /// Create a [Cursor] that visits each item in the [Sequence], along with its position.
+    fn cursor<'a>(&self, f: &'a Forest) -> Cursor<'a>;
+    /// Remove duplicate nodes and sort the [Sequence] into document order. The nodes may be from different trees. Nodes that are not attached to a tree are placed last.
+    /// Every item in the [Sequence] must be a [Node].
+    fn sort_document_order(&mut self, f: &Forest) -> Result<(), Error>;
 }
 
 impl SequenceTrait for Sequence {
@@ -46,7 +57,10 @@ impl SequenceTrait for Sequence {
     fn to_string(&self, d: Option<&Forest>) -> String {
         let mut r = String::new();
         for i in self {
-            r.push_str(i.to_string(d).as_str())
+            match (&**i, d) {
+                (Item::Node(n), Some(e)) => n.collect_text(e, &mut r),
+                _ => r.push_str(i.to_string(d).as_str()),
+            }
         }
         r
     }
@@ -58,21 +72,34 @@ impl SequenceTrait for Sequence {
         }
         r
     }
-    /// Renders the Sequence as XML
+    /// Renders the Sequence as XML, or as JSON or adaptive output if that is the output method
     fn to_xml_with_options(&self, od: &OutputDefinition, d: Option<&Forest>) -> String {
+        match od.get_method() {
+            Some("json") => return self.to_json(d),
+            Some("adaptive") => return self.to_adaptive(d),
+            _ => {}
+        }
         let mut r = String::new();
         for i in self {
             r.push_str(i.to_xml_with_options(od, d).as_str())
         }
         r
     }
-    /// Renders the Sequence as JSON
+    /// Renders the Sequence as JSON. An empty sequence is null, and a sequence of more than one item is an array.
     fn to_json(&self, d: Option<&Forest>) -> String {
-        let mut r = String::new();
-        for i in self {
-            r.push_str(i.to_json(d).as_str())
+        match self.as_slice() {
+            [] => String::from("null"),
+            [i] => i.to_json(d),
+            _ => {
+                let v: Vec<String> = self.iter().map(|i| i.to_json(d)).collect();
+                format!("[{}]", v.join(","))
+            }
         }
-        r
+    }
+    /// Renders the Sequence using the adaptive output method, with a newline between items
+    fn to_adaptive(&self, d: Option<&Forest>) -> String {
+        let v: Vec<String> = self.iter().map(|i| i.to_adaptive(d)).collect();
+        v.join("\n")
     }
     /// Push a Document's [Node] on to the [Sequence]
     fn push_node(&mut self, n: Node) {
@@ -107,6 +134,47 @@ impl SequenceTrait for Sequence {
         }
     }
 
+    fn cursor<'a>(&self, f: &'a Forest) -> Cursor<'a> {
+        Cursor {
+            seq: self.clone(),
+            f,
+            i: 0,
+            source: None,
+        }
+    }
+
+    fn sort_document_order(&mut self, f: &Forest) -> Result<(), Error> {
+        let mut seen = HashSet::new();
+        let mut nodes = vec![];
+        for i in self.iter() {
+            match **i {
+                Item::Node(n) => {
+                    if seen.insert(n.node_ref()) {
+                        nodes.push(n)
+                    }
+                }
+                _ => {
+                    return Result::Err(Error::new(
+                        ErrorKind::TypeError,
+                        String::from("sequence must contain only nodes"),
+                    ))
+                }
+            }
+        }
+        nodes.sort_by(|a, b| {
+            a.cmp_document_order(f, b).unwrap_or_else(|| {
+                // Detached nodes go last
+                match (a.document_order(f), b.document_order(f)) {
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    _ => Ordering::Equal,
+                }
+            })
+        });
+        *self = nodes.into_iter().map(|n| Rc::new(Item::Node(n))).collect();
+        Ok(())
+    }
+
     /// Convenience routine for integer value of the [Sequence]. The Sequence must be a singleton; i.e. be a single item.
     fn to_int(&self) -> Result<i64, Error> {
         if self.len() == 1 {
@@ -136,6 +204,61 @@ impl From<Item> for Sequence {
     }
 }
 
+/// An [Item] from a [Sequence], with information about where the item occurs.
+#[derive(Clone, Debug)]
+pub struct Match {
+    /// The item itself
+    pub item: Rc<Item>,
+    /// The position of the item in the [Sequence], starting at 1
+    pub position: usize,
+    /// If the item is a [Node], the index of the [Tree](crate::forest::Tree) that contains the node
+    pub tree: Option<TreeIndex>,
+    /// If the item is a [Node] that is attached to its [Tree](crate::forest::Tree), a key for the position of the node in document order. See [Node::document_order].
+    pub document_order: Option<usize>,
+    /// If the item is an element, and the [Cursor] has the source text of its document, the line and column of its start tag. See [Cursor::with_source].
+    pub line_column: Option<(usize, usize)>,
+}
+
+/// Visits the items in a [Sequence], reporting the position of each item.
+/// This is useful for tools that need to report where the result of an expression occurs in a document.
+pub struct Cursor<'a> {
+    seq: Sequence,
+    f: &'a Forest,
+    i: usize,
+    source: Option<&'a EditableDocument>,
+}
+
+impl<'a> Cursor<'a> {
+    /// Report the line and column of elements that were parsed from the text of the document. Source positions are not otherwise kept in the tree.
+    pub fn with_source(mut self, d: &'a EditableDocument) -> Self {
+        self.source = Some(d);
+        self
+    }
+}
+
+impl<'a> Iterator for Cursor<'a> {
+    type Item = Match;
+    fn next(&mut self) -> Option<Match> {
+        let item = self.seq.get(self.i)?;
+        self.i += 1;
+        let (tree, document_order, line_column) = match &**item {
+            Item::Node(n) => (
+                Some(n.tree_index()),
+                n.document_order(self.f),
+                self.source.and_then(|d| d.position(*n)),
+            ),
+            _ => (None, None, None),
+        };
+        Some(Match {
+            item: Rc::clone(item),
+            position: self.i,
+            tree,
+            document_order,
+            line_column,
+        })
+    }
+}
+
 /// An Item in a [Sequence]. Can be a [Node], Function or [Value].
 ///
 /// [Node]s are dynamic trait objects. [Node]s can only exist in the context of a Tree.
@@ -178,12 +301,96 @@ impl Item {
             Item::Value(v) => v.to_string(),
         }
     }
-    /// Serialize as JSON
+    /// Serialize as JSON. A node is serialized as XML, in a string. A number that is not finite is written as null.
     pub fn to_json(&self, d: Option<&Forest>) -> String {
         match self {
             Item::Node(n) => d.map_or(String::new(), |e| n.to_json(e)),
-            Item::Function => "".to_string(),
-            Item::Value(v) => v.to_string(),
+            Item::Function => "null".to_string(),
+            Item::Value(Value::Boolean(b)) => b.to_string(),
+            Item::Value(Value::Double(n)) if !n.is_finite() => "null".to_string(),
+            Item::Value(Value::Float(n)) if !n.is_finite() => "null".to_string(),
+            Item::Value(
+                v @ (Value::Decimal(_)
+                | Value::Float(_)
+                | Value::Double(_)
+                | Value::Integer(_)
+                | Value::NonPositiveInteger(_)
+                | Value::NegativeInteger(_)
+                | Value::Long(_)
+                | Value::Int(_)
+                | Value::Short(_)
+                | Value::Byte(_)
+                | Value::NonNegativeInteger(_)
+                | Value::UnsignedLong(_)
+                | Value::UnsignedInt(_)
+                | Value::UnsignedShort(_)
+                | Value::UnsignedByte(_)
+                | Value::PositiveInteger(_)),
+            ) => v.to_string(),
+            Item::Value(v) => {
+                let mut r = String::new();
+                write_string(&mut r, v.to_string().as_str());
+                r
+            }
+        }
+    }
+    /// Serialize using the adaptive output method, which is intended for debugging and logging.
+    ///
+    /// An attribute is written as name="value", and other nodes are serialized as XML.
+    /// A string is quoted, a boolean is written as true() or false(), and other atomic values are written as a constructor function call, such as xs:date("2000-01-01").
+    pub fn to_adaptive(&self, d: Option<&Forest>) -> String {
+        match self {
+            Item::Node(n) => d.map_or(String::new(), |e| match n.node_type(e) {
+                NodeType::Attribute => {
+                    format!(
+                        "{}={}",
+                        n.to_name(e).to_string(),
+                        adaptive_string(n.to_string(e).as_str())
+                    )
+                }
+                _ => n.to_xml(e),
+            }),
+            Item::Function => "function(*)".to_string(),
+            Item::Value(Value::Boolean(b)) => format!("{}()", b),
+            Item::Value(Value::Double(n)) if !n.is_finite() => {
+                format!("xs:double({})", adaptive_string(double_string(*n)))
+            }
+            Item::Value(Value::Float(n)) if !n.is_finite() => {
+                format!("xs:float({})", adaptive_string(double_string(*n as f64)))
+            }
+            Item::Value(
+                v @ (Value::Decimal(_)
+                | Value::Float(_)
+                | Value::Double(_)
+                | Value::Integer(_)
+                | Value::NonPositiveInteger(_)
+                | Value::NegativeInteger(_)
+                | Value::Long(_)
+                | Value::Int(_)
+                | Value::Short(_)
+                | Value::Byte(_)
+                | Value::NonNegativeInteger(_)
+                | Value::UnsignedLong(_)
+                | Value::UnsignedInt(_)
+                | Value::UnsignedShort(_)
+                | Value::UnsignedByte(_)
+                | Value::PositiveInteger(_)),
+            ) => v.to_string(),
+            Item::Value(
+                v @ (Value::String(_) | Value::NormalizedString(_) | Value::UntypedAtomic),
+            ) => adaptive_string(v.to_string().as_str()),
+            Item::Value(v) => {
+                let t = v.value_type();
+                let name = if t.chars().all(|c| c.is_ascii_uppercase()) {
+                    t.to_string()
+                } else {
+                    let mut c = t.chars();
+                    c.next()
+                        .map_or(String::new(), |h| h.to_ascii_lowercase().to_string())
+                        + c.as_str()
+                };
+                format!("xs:{}({})", name, adaptive_string(v.to_string().as_str()))
+            }
         }
     }
 
@@ -242,13 +449,13 @@ impl Item {
         match self {
             Item::Value(v) => match other {
                 Item::Value(w) => v.compare(w, op),
-                Item::Node(..) => v.compare(&Value::String(other.to_string(d)), op),
+                Item::Node(..) => v.compare(&Value::from(other.to_string(d)), op),
                 _ => Result::Err(Error {
                     kind: ErrorKind::TypeError,
                     message: String::from("type error"),
                 }),
             },
-            Item::Node(..) => other.compare(&Item::Value(Value::String(self.to_string(d))), op, d),
+            Item::Node(..) => other.compare(&Item::Value(Value::from(self.to_string(d))), op, d),
             _ => Result::Err(Error {
                 kind: ErrorKind::TypeError,
                 message: String::from("type error"),
@@ -277,6 +484,22 @@ impl Item {
     }
 }
 
+/// Quote a string for the adaptive output method. Quotation marks in the string are doubled.
+fn adaptive_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// The lexical form of a number that is not finite.
+fn double_string(n: f64) -> &'static str {
+    if n.is_nan() {
+        "NaN"
+    } else if n > 0.0 {
+        "INF"
+    } else {
+        "-INF"
+    }
+}
+
 impl fmt::Debug for Item {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -287,7 +510,7 @@ impl fmt::Debug for Item {
                 write!(f, "function type item")
             }
             Item::Value(v) => {
-                write!(f, "value type item ({})", v.to_string())
+                write!(f, "value type item ({})", v)
             }
         }
     }
@@ -350,4 +573,174 @@ mod tests {
         t.push_item(&s[0]);
         assert!(Rc::ptr_eq(&s[0], &t[0]))
     }
+
+    #[test]
+    fn sequence_json() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a b='1'>\"x\"</a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let od = crate::output::OutputDefinitionBuilder::new()
+            .method("json")
+            .build()
+            .expect("invalid output definition");
+        assert_eq!(Sequence::new().to_xml_with_options(&od, Some(&f)), "null");
+        let mut s = Sequence::new();
+        s.push_value(Value::Integer(1));
+        assert_eq!(s.to_xml_with_options(&od, Some(&f)), "1");
+        s.push_value(Value::from("a\tb"));
+        s.push_value(Value::Boolean(true));
+        s.push_value(Value::Double(f64::NAN));
+        s.push_node(a);
+        assert_eq!(
+            s.to_xml_with_options(&od, Some(&f)),
+            r#"[1,"a\tb",true,null,"<a b='1'>\"x\"</a>"]"#
+        );
+    }
+
+    #[test]
+    fn sequence_adaptive() {
+        use chrono::TimeZone;
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a b='1 \"2\"'><c/></a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let b = a
+            .get_attribute(&f, &QualifiedName::new(None, None, "b".to_string()))
+            .unwrap();
+        let od = crate::output::OutputDefinitionBuilder::new()
+            .method("adaptive")
+            .build()
+            .expect("invalid output definition");
+        assert_eq!(Sequence::new().to_xml_with_options(&od, Some(&f)), "");
+        let mut s = Sequence::new();
+        s.push_node(a);
+        s.push_node(b);
+        s.push_value(Value::from("say \"hi\""));
+        s.push_value(Value::Integer(3));
+        s.push_value(Value::Boolean(false));
+        s.push_value(Value::Double(f64::NEG_INFINITY));
+        assert_eq!(
+            s.to_xml_with_options(&od, Some(&f)),
+            "<a b='1 \"2\"'><c></c></a>\nb=\"1 \"\"2\"\"\"\n\"say \"\"hi\"\"\"\n3\nfalse()\nxs:double(\"-INF\")"
+        );
+        let dt = chrono::Local.with_ymd_and_hms(2000, 1, 2, 3, 4, 5).unwrap();
+        assert!(Item::Value(Value::DateTime(dt))
+            .to_adaptive(None)
+            .starts_with("xs:dateTime(\"2000-01-02T03:04:05"));
+    }
+
+    #[test]
+    fn cursor() {
+        let mut f = Forest::new();
+        let ti = f
+            .grow_tree("<Test><a/><b/></Test>")
+            .expect("unable to parse XML");
+        let e = f
+            .get_ref(ti)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = e.child_iter();
+        let _a = cit.next(&f).unwrap();
+        let b = cit.next(&f).unwrap();
+        let mut s = Sequence::new();
+        s.push_value(Value::from("value"));
+        s.push_node(b);
+        let m: Vec<Match> = s.cursor(&f).collect();
+        assert_eq!(m.len(), 2);
+        assert_eq!(m[0].position, 1);
+        assert_eq!(m[0].document_order, None);
+        assert_eq!(m[1].position, 2);
+        assert_eq!(m[1].tree, Some(ti));
+        assert_eq!(m[1].document_order, b.document_order(&f));
+        assert!(m[1].document_order.is_some());
+        assert_eq!(m[1].line_column, None)
+    }
+
+    #[test]
+    fn cursor_source() {
+        let mut f = Forest::new();
+        let d = EditableDocument::new(&mut f, "<Test>\n  <a/>\n  <b/></Test>")
+            .expect("unable to parse XML");
+        let e = f
+            .get_ref(d.tree())
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut s = Sequence::new();
+        let mut cit = e.child_iter();
+        while let Some(c) = cit.next(&f) {
+            s.push_node(c)
+        }
+        let m: Vec<Option<(usize, usize)>> = s
+            .cursor(&f)
+            .with_source(&d)
+            .map(|m| m.line_column)
+            .collect();
+        // Text nodes have no source position
+        assert_eq!(m, vec![None, Some((2, 3)), None, Some((3, 3))])
+    }
+
+    #[test]
+    fn sort_document_order() {
+        let mut f = Forest::new();
+        let t1 = f
+            .grow_tree("<Test><a/><b/></Test>")
+            .expect("unable to parse XML");
+        let t2 = f.grow_tree("<Other/>").expect("unable to parse XML");
+        let e = f
+            .get_ref(t1)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = e.child_iter();
+        let a = cit.next(&f).unwrap();
+        let b = cit.next(&f).unwrap();
+        let o = f
+            .get_ref(t2)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        // A detached node in the first tree
+        let d = f
+            .get_ref_mut(t1)
+            .unwrap()
+            .new_element(QualifiedName::new(None, None, String::from("Detached")))
+            .expect("unable to create element");
+        let mut s = Sequence::new();
+        s.push_node(d);
+        s.push_node(o);
+        s.push_node(b);
+        s.push_node(a);
+        s.push_node(b);
+        s.push_node(e);
+        s.sort_document_order(&f).expect("unable to sort sequence");
+        let names: Vec<String> = s
+            .iter()
+            .map(|i| match **i {
+                Item::Node(n) => n.to_name(&f).get_localname(),
+                _ => panic!("not a node"),
+            })
+            .collect();
+        assert_eq!(names, vec!["Test", "a", "b", "Other", "Detached"]);
+        s.push_value(Value::from(1));
+        assert!(s.sort_document_order(&f).is_err())
+    }
 }
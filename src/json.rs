@@ -0,0 +1,538 @@
+/*! ## JSON
+
+Conversion between JSON text and its XML representation, as defined by [XPath and XQuery Functions and Operators 3.1](https://www.w3.org/TR/xpath-functions-31/#json-to-xml-mapping). These are used by the fn:json-to-xml and fn:xml-to-json functions.
+
+In the XML representation each JSON value is an element in the [JSON_NAMESPACE]: map, array, string, number, boolean or null. The members of a map have a key attribute.
+
+```rust
+use xrust::forest::Forest;
+use xrust::json::{json_to_xml, xml_to_json};
+
+let mut f = Forest::new();
+let t = json_to_xml(&mut f, r#"{"name": "xrust", "tags": ["xml", "xslt"]}"#)
+    .expect("unable to parse JSON");
+let d = f.get_ref(t).unwrap().get_doc_node();
+assert_eq!(
+    d.get_first_element(&f).unwrap().to_xml(&f),
+    "<map xmlns='http://www.w3.org/2005/xpath-functions'><string key='name'>xrust</string><array key='tags'><string>xml</string><string>xslt</string></array></map>"
+);
+assert_eq!(
+    xml_to_json(&f, d).expect("unable to convert to JSON"),
+    r#"{"name":"xrust","tags":["xml","xslt"]}"#
+);
+```
+*/
+
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::parsecommon::is_char;
+use crate::qname::QualifiedName;
+use crate::value::Value;
+use crate::xdmerror::*;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while_m_n},
+    character::complete::{char, digit0, digit1, multispace0, none_of, one_of},
+    combinator::{cut, map, map_res, opt, recognize, value, verify},
+    multi::many0,
+    sequence::{delimited, preceded, terminated, tuple},
+    IResult,
+};
+use std::collections::HashSet;
+
+/// The namespace of the elements in the XML representation of JSON.
+pub const JSON_NAMESPACE: &str = "http://www.w3.org/2005/xpath-functions";
+
+// A parsed JSON value. Numbers keep their lexical form.
+#[derive(Clone)]
+enum Json {
+    Null,
+    Boolean(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Parse JSON text, and create a new tree that has its XML representation.
+///
+/// Duplicate keys in an object are retained. Escape sequences in strings are replaced by the characters they represent, and characters that are not allowed in XML are replaced by U+FFFD.
+pub fn json_to_xml(f: &mut Forest, s: &str) -> Result<TreeIndex, Error> {
+    let j = match json_value(s) {
+        Ok(("", j)) => j,
+        Ok((rest, _)) => return Result::Err(syntax_error(s, rest)),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            return Result::Err(syntax_error(s, e.input))
+        }
+        Err(nom::Err::Incomplete(_)) => return Result::Err(syntax_error(s, "")),
+    };
+    let t = f.plant_tree();
+    let e = to_node(f, t, &j, None)?;
+    f.get_ref_mut(t).unwrap().push_doc_node(e)?;
+    Ok(t)
+}
+
+/// Convert the XML representation of JSON to JSON text. The node is an element in the [JSON_NAMESPACE], or a document that has one.
+///
+/// A string element whose escaped attribute is true has content that is already escaped, and is written as it is.
+pub fn xml_to_json(f: &Forest, n: Node) -> Result<String, Error> {
+    let e = match n.node_type(f) {
+        NodeType::Document => n
+            .get_first_element(f)
+            .ok_or_else(|| invalid("the document does not have an element"))?,
+        NodeType::Element => n,
+        _ => return Result::Err(invalid("not an element or document")),
+    };
+    let mut r = String::new();
+    write_json(f, e, &mut r)?;
+    Ok(r)
+}
+
+/// Write a string as a JSON string literal, escaping characters as necessary.
+pub(crate) fn write_string(r: &mut String, s: &str) {
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\u{8}' => r.push_str("\\b"),
+            '\u{c}' => r.push_str("\\f"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if c < ' ' => r.push_str(format!("\\u{:04X}", c as u32).as_str()),
+            c => r.push(c),
+        }
+    }
+    r.push('"')
+}
+
+fn syntax_error(s: &str, rest: &str) -> Error {
+    Error::new(
+        ErrorKind::Unknown,
+        format!(
+            "FOJS0001: invalid JSON at character {}",
+            s[..s.len() - rest.len()].chars().count() + 1
+        ),
+    )
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(
+        ErrorKind::Unknown,
+        format!("FOJS0006: invalid XML representation of JSON: {}", msg),
+    )
+}
+
+fn json_name(local: &str) -> QualifiedName {
+    QualifiedName::new(
+        Some(String::from(JSON_NAMESPACE)),
+        None,
+        String::from(local),
+    )
+}
+
+// Create the element that represents a JSON value. A member of an object has its key.
+fn to_node(f: &mut Forest, t: TreeIndex, j: &Json, key: Option<&str>) -> Result<Node, Error> {
+    let (local, text) = match j {
+        Json::Null => ("null", None),
+        Json::Boolean(b) => ("boolean", Some(b.to_string())),
+        Json::Number(n) => ("number", Some(n.clone())),
+        Json::String(s) => ("string", Some(s.clone())),
+        Json::Array(_) => ("array", None),
+        Json::Object(_) => ("map", None),
+    };
+    let e = f
+        .get_ref_mut(t)
+        .unwrap()
+        .new_element_unchecked(json_name(local));
+    if let Some(k) = key {
+        let a = f.get_ref_mut(t).unwrap().new_attribute_unchecked(
+            QualifiedName::new(None, None, String::from("key")),
+            Value::from(k),
+        );
+        e.add_attribute(f, a)?
+    }
+    match (j, text) {
+        (_, Some(s)) if !s.is_empty() => {
+            let c = f.get_ref_mut(t).unwrap().new_text(Value::from(s))?;
+            e.append_child(f, c)?
+        }
+        (Json::Array(v), _) => {
+            for i in v {
+                let c = to_node(f, t, i, None)?;
+                e.append_child(f, c)?
+            }
+        }
+        (Json::Object(v), _) => {
+            for (k, i) in v {
+                let c = to_node(f, t, i, Some(k.as_str()))?;
+                e.append_child(f, c)?
+            }
+        }
+        _ => {}
+    }
+    Ok(e)
+}
+
+fn write_json(f: &Forest, n: Node, r: &mut String) -> Result<(), Error> {
+    let name = n.to_name(f);
+    if name.get_nsuri_ref() != Some(JSON_NAMESPACE) {
+        return Result::Err(invalid(
+            format!(
+                "element \"{}\" is not in the JSON namespace",
+                name.to_string()
+            )
+            .as_str(),
+        ));
+    }
+    match name.get_localname().as_str() {
+        "null" => {
+            leaf_text(f, n)?;
+            r.push_str("null")
+        }
+        "boolean" => match leaf_text(f, n)?.trim() {
+            "true" | "1" => r.push_str("true"),
+            "false" | "0" => r.push_str("false"),
+            b => return Result::Err(invalid(format!("\"{}\" is not a boolean", b).as_str())),
+        },
+        "number" => {
+            let v = leaf_text(f, n)?;
+            match v.trim().parse::<f64>() {
+                Ok(d) if d.is_finite() => r.push_str(d.to_string().as_str()),
+                _ => {
+                    return Result::Err(invalid(format!("\"{}\" is not a JSON number", v).as_str()))
+                }
+            }
+        }
+        "string" => {
+            let v = leaf_text(f, n)?;
+            if flag(f, n, "escaped")? {
+                r.push('"');
+                r.push_str(v.as_str());
+                r.push('"')
+            } else {
+                write_string(r, v.as_str())
+            }
+        }
+        "array" => {
+            r.push('[');
+            for (i, c) in children(f, n)?.into_iter().enumerate() {
+                if i > 0 {
+                    r.push(',')
+                }
+                write_json(f, c, r)?
+            }
+            r.push(']')
+        }
+        "map" => {
+            let mut keys = HashSet::new();
+            r.push('{');
+            for (i, c) in children(f, n)?.into_iter().enumerate() {
+                if i > 0 {
+                    r.push(',')
+                }
+                let k = c
+                    .get_attribute(f, &QualifiedName::new(None, None, String::from("key")))
+                    .ok_or_else(|| invalid("a member of a map does not have a key"))?
+                    .to_string(f);
+                if flag(f, c, "escaped-key")? {
+                    r.push('"');
+                    r.push_str(k.as_str());
+                    r.push('"')
+                } else {
+                    write_string(r, k.as_str())
+                }
+                if !keys.insert(k.clone()) {
+                    return Result::Err(invalid(format!("duplicate key \"{}\"", k).as_str()));
+                }
+                r.push(':');
+                write_json(f, c, r)?
+            }
+            r.push('}')
+        }
+        l => return Result::Err(invalid(format!("unknown element \"{}\"", l).as_str())),
+    }
+    Ok(())
+}
+
+// The element children of an array or map. Comments, processing instructions and whitespace are ignored, but other text is not allowed.
+fn children(f: &Forest, n: Node) -> Result<Vec<Node>, Error> {
+    let mut r = vec![];
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        match c.node_type(f) {
+            NodeType::Element => r.push(c),
+            NodeType::Text if c.to_string(f).trim().is_empty() => {}
+            NodeType::Text => return Result::Err(invalid("an array or map has text content")),
+            _ => {}
+        }
+    }
+    Ok(r)
+}
+
+// The content of a string, number, boolean or null element, which must not have element children
+fn leaf_text(f: &Forest, n: Node) -> Result<String, Error> {
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        if c.node_type(f) == NodeType::Element {
+            return Result::Err(invalid(
+                format!(
+                    "a {} element has element content",
+                    n.to_name(f).get_localname()
+                )
+                .as_str(),
+            ));
+        }
+    }
+    Ok(n.to_string(f))
+}
+
+// The value of a boolean attribute, such as escaped
+fn flag(f: &Forest, n: Node, name: &str) -> Result<bool, Error> {
+    match n.get_attribute(f, &QualifiedName::new(None, None, String::from(name))) {
+        Some(a) => match a.to_string(f).trim() {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            v => Result::Err(invalid(
+                format!("\"{}\" is not a valid value for {}", v, name).as_str(),
+            )),
+        },
+        None => Ok(false),
+    }
+}
+
+// value ::= ws (object | array | string | number | "true" | "false" | "null") ws
+fn json_value(input: &str) -> IResult<&str, Json> {
+    delimited(
+        multispace0,
+        alt((
+            object,
+            array,
+            map(string, Json::String),
+            number,
+            value(Json::Boolean(true), tag("true")),
+            value(Json::Boolean(false), tag("false")),
+            value(Json::Null, tag("null")),
+        )),
+        multispace0,
+    )(input)
+}
+
+// After the start of an object, array or string, or a separator, the rest must follow, so that a syntax error is reported where it is found
+fn object(input: &str) -> IResult<&str, Json> {
+    map(
+        preceded(
+            char('{'),
+            cut(terminated(
+                alt((
+                    map(
+                        tuple((member, many0(preceded(char(','), cut(member))))),
+                        |(m, mut v)| {
+                            v.insert(0, m);
+                            v
+                        },
+                    ),
+                    value(vec![], multispace0),
+                )),
+                char('}'),
+            )),
+        ),
+        Json::Object,
+    )(input)
+}
+
+fn member(input: &str) -> IResult<&str, (String, Json)> {
+    tuple((
+        delimited(multispace0, string, multispace0),
+        cut(preceded(char(':'), json_value)),
+    ))(input)
+}
+
+fn array(input: &str) -> IResult<&str, Json> {
+    map(
+        preceded(
+            char('['),
+            cut(terminated(
+                alt((
+                    map(
+                        tuple((json_value, many0(preceded(char(','), cut(json_value))))),
+                        |(j, mut v)| {
+                            v.insert(0, j);
+                            v
+                        },
+                    ),
+                    value(vec![], multispace0),
+                )),
+                char(']'),
+            )),
+        ),
+        Json::Array,
+    )(input)
+}
+
+// The characters of a string are collected as UTF-16 code units, so that escaped surrogate pairs are combined. An unpaired surrogate becomes U+FFFD.
+fn string(input: &str) -> IResult<&str, String> {
+    map(
+        preceded(
+            char('"'),
+            cut(terminated(
+                many0(alt((
+                    map(verify(none_of("\"\\"), |c| *c >= ' '), |c| {
+                        let mut b = [0; 2];
+                        c.encode_utf16(&mut b).to_vec()
+                    }),
+                    preceded(char('\\'), escape),
+                ))),
+                char('"'),
+            )),
+        ),
+        |v| {
+            String::from_utf16_lossy(&v.concat())
+                .chars()
+                .map(|c| if is_char(&c) { c } else { '\u{FFFD}' })
+                .collect()
+        },
+    )(input)
+}
+
+fn escape(input: &str) -> IResult<&str, Vec<u16>> {
+    alt((
+        map(
+            preceded(
+                char('u'),
+                map_res(take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit()), |h| {
+                    u16::from_str_radix(h, 16)
+                }),
+            ),
+            |u| vec![u],
+        ),
+        map(one_of("\"\\/bfnrt"), |c| {
+            vec![match c {
+                'b' => 0x8,
+                'f' => 0xc,
+                'n' => 0xa,
+                'r' => 0xd,
+                't' => 0x9,
+                c => c as u16,
+            }]
+        }),
+    ))(input)
+}
+
+// number ::= "-"? ("0" | [1-9] [0-9]*) ("." [0-9]+)? ([eE] [+-]? [0-9]+)?
+fn number(input: &str) -> IResult<&str, Json> {
+    map(
+        recognize(tuple((
+            opt(char('-')),
+            alt((tag("0"), recognize(tuple((one_of("123456789"), digit0))))),
+            opt(tuple((char('.'), digit1))),
+            opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+        ))),
+        |n: &str| Json::Number(String::from(n)),
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(s: &str) -> String {
+        let mut f = Forest::new();
+        let t = json_to_xml(&mut f, s).expect("unable to parse JSON");
+        xml_to_json(&f, f.get_ref(t).unwrap().get_doc_node()).expect("unable to convert")
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(round_trip(" null "), "null");
+        assert_eq!(round_trip("[true, false, null]"), "[true,false,null]");
+        assert_eq!(round_trip("[0, -1.5, 2e3, 10]"), "[0,-1.5,2000,10]");
+        assert_eq!(round_trip("{}"), "{}");
+        assert_eq!(round_trip("[ ]"), "[]");
+        assert_eq!(
+            round_trip(r#"{"a": {"b": [1, {"c": "d"}]}}"#),
+            r#"{"a":{"b":[1,{"c":"d"}]}}"#
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let mut f = Forest::new();
+        let t = json_to_xml(&mut f, r#"["a\"b\\c\/d\n", "é😀", "\ud800", "\u0000", ""]"#)
+            .expect("unable to parse JSON");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut cit = a.child_iter();
+        let mut v = vec![];
+        while let Some(c) = cit.next(&f) {
+            v.push(c.to_string(&f))
+        }
+        assert_eq!(v, vec!["a\"b\\c/d\n", "é😀", "\u{FFFD}", "\u{FFFD}", ""]);
+        assert_eq!(
+            xml_to_json(&f, a).expect("unable to convert"),
+            r#"["a\"b\\c/d\n","é😀","�","�",""]"#
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let mut f = Forest::new();
+        for s in [
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "01",
+            "[1] 2",
+            "\"a\nb\"",
+            "'a'",
+            "{\"a\":1,}",
+            "tru",
+        ] {
+            let e = json_to_xml(&mut f, s).expect_err(s);
+            assert!(e.message.starts_with("FOJS0001"), "{}: {}", s, e.message)
+        }
+        assert_eq!(
+            json_to_xml(&mut f, "[1, 2, x]").unwrap_err().message,
+            "FOJS0001: invalid JSON at character 8"
+        )
+    }
+
+    #[test]
+    fn representation() {
+        let mut f = Forest::new();
+        let json = |f: &mut Forest, x: &str| {
+            let t = f.grow_tree(x).expect("unable to parse XML");
+            xml_to_json(f, f.get_ref(t).unwrap().get_doc_node())
+        };
+        assert_eq!(
+            json(
+                &mut f,
+                "<j:map xmlns:j='http://www.w3.org/2005/xpath-functions'>
+  <!-- whitespace and comments are ignored -->
+  <j:boolean key='b'> 1 </j:boolean>
+  <j:string key='s' escaped='true'>\\u0041</j:string>
+  <j:number key='n'> 1.0 </j:number>
+</j:map>"
+            )
+            .expect("unable to convert"),
+            r#"{"b":true,"s":"\u0041","n":1}"#
+        );
+        for x in [
+            "<map/>",
+            "<map xmlns='http://www.w3.org/2005/xpath-functions'><null/></map>",
+            "<map xmlns='http://www.w3.org/2005/xpath-functions'><null key='a'/><null key='a'/></map>",
+            "<array xmlns='http://www.w3.org/2005/xpath-functions'>text</array>",
+            "<number xmlns='http://www.w3.org/2005/xpath-functions'>NaN</number>",
+            "<boolean xmlns='http://www.w3.org/2005/xpath-functions'>yes</boolean>",
+            "<string xmlns='http://www.w3.org/2005/xpath-functions'><null/></string>",
+            "<object xmlns='http://www.w3.org/2005/xpath-functions'/>",
+        ] {
+            let e = json(&mut f, x).expect_err(x);
+            assert!(e.message.starts_with("FOJS0006"), "{}: {}", x, e.message)
+        }
+    }
+}
@@ -0,0 +1,566 @@
+/*! ## Building blocks for XML language servers
+
+Editors and language servers need more from a parser than a tree: the errors in a document and where they are, the node at a position, and what may be typed at a position. This module provides those pieces without depending on any particular language server framework.
+
+- [diagnostics] checks that a document is well-formed, and reports every error found with its source range. Unlike the parser, it does not stop at the first error.
+- [Position] converts between byte offsets, which are used throughout xrust, and lines and columns. An [EditableDocument](../edit/struct.EditableDocument.html) maps positions to elements and elements to source ranges, and keeps its tree up to date as the text is edited.
+- [Grammar] collects the element and attribute declarations in a document type declaration, and [complete] uses them to find the names that are valid at a position.
+
+```rust
+use xrust::lang::{complete, diagnostics, Completion, Grammar, Position};
+
+let text = "<!DOCTYPE list [
+<!ELEMENT list (item*)>
+<!ELEMENT item (#PCDATA)>
+<!ATTLIST item id ID #REQUIRED done (yes|no) 'no'>
+]>
+<list><item id='a'></item><item ></list>";
+
+// The second item is not closed
+let d = diagnostics(text);
+assert_eq!(d.len(), 1);
+assert_eq!(&text[d[0].range.clone()], "<item >");
+assert_eq!(Position::of(text, d[0].range.start), Position { line: 5, column: 26 });
+
+let g = Grammar::from_document(text);
+let c = complete(text, text.find("<item >").unwrap() + 6, &g);
+assert_eq!(c, Completion::Attribute(String::from("item"), vec![String::from("done"), String::from("id")]));
+let c = complete(text, text.find("<item id").unwrap(), &g);
+assert_eq!(c, Completion::Element(vec![String::from("item")]));
+```
+
+Only the internal subset of the document type declaration is read. XML Schemas are not supported.
+*/
+
+use crate::parsexml::{next_token, Token};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// A problem found in a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The bytes of the text where the problem was found.
+    pub range: Range<usize>,
+    pub message: String,
+}
+
+/// Check that a document is well-formed. Errors in the syntax of markup are reported, and then checking continues from the next markup.
+///
+/// The declarations in a document type declaration are not checked.
+pub fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    let mut result = vec![];
+    let mut report = |range: Range<usize>, message: &str| {
+        result.push(Diagnostic {
+            range,
+            message: message.to_string(),
+        })
+    };
+    // The open elements, with the range of their start tag
+    let mut open: Vec<(String, Range<usize>)> = vec![];
+    let mut root = false;
+    let mut input = text;
+    while !input.is_empty() {
+        let start = text.len() - input.len();
+        if input.starts_with("<!DOCTYPE") {
+            // The parser does not support all declarations, so skip over the whole declaration
+            match doctype_end(input) {
+                Some(e) => {
+                    if root || !open.is_empty() {
+                        report(
+                            start..start + e,
+                            "a document type declaration must come before the document element",
+                        )
+                    }
+                    input = &input[e..];
+                }
+                None => {
+                    report(
+                        start..text.len(),
+                        "the document type declaration is not closed",
+                    );
+                    input = "";
+                }
+            }
+            continue;
+        }
+        match next_token(text, input) {
+            Ok((rest, t)) => {
+                let end = text.len() - rest.len();
+                match t {
+                    Token::StartTag(n, _, empty) => {
+                        if open.is_empty() && root {
+                            report(start..end, "a document may only have one document element")
+                        }
+                        root = true;
+                        if !empty {
+                            open.push((n.to_string(), start..end))
+                        }
+                    }
+                    Token::EndTag(n) => {
+                        let name = n.to_string();
+                        match open.iter().rposition(|(m, _)| *m == name) {
+                            Some(i) => {
+                                // Elements that were opened after this one were not closed
+                                for (m, r) in open.drain(i + 1..) {
+                                    report(r, format!("element \"{}\" is not closed", m).as_str())
+                                }
+                                open.pop();
+                            }
+                            None => match open.last() {
+                                Some((m, _)) => report(
+                                    start..end,
+                                    format!("expected the end tag for \"{}\"", m).as_str(),
+                                ),
+                                None => report(start..end, "end tag without a start tag"),
+                            },
+                        }
+                    }
+                    Token::Text(s) if open.is_empty() && !s.trim().is_empty() => report(
+                        start..end,
+                        "text is not allowed outside the document element",
+                    ),
+                    Token::Reference(_) if open.is_empty() => report(
+                        start..end,
+                        "references are not allowed outside the document element",
+                    ),
+                    _ => {}
+                }
+                input = rest;
+            }
+            Err(_) => {
+                // Resume at the next markup
+                let skip = input[1..].find('<').map_or(input.len(), |p| p + 1);
+                report(start..start + skip, "syntax error");
+                input = &input[skip..];
+            }
+        }
+    }
+    for (m, r) in open {
+        report(r, format!("element \"{}\" is not closed", m).as_str())
+    }
+    if !root {
+        report(text.len()..text.len(), "there is no document element")
+    }
+    result
+}
+
+/// A position in a text. Lines and columns are counted from zero, and columns count characters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// The position of a byte offset in the text. An offset past the end of the text is the end of the text.
+    pub fn of(text: &str, offset: usize) -> Position {
+        let mut p = Position { line: 0, column: 0 };
+        for (i, c) in text.char_indices() {
+            if i >= offset {
+                break;
+            }
+            if c == '\n' {
+                p.line += 1;
+                p.column = 0
+            } else {
+                p.column += 1
+            }
+        }
+        p
+    }
+    /// The byte offset of this position in the text. Returns None if the text does not have the position.
+    pub fn offset(&self, text: &str) -> Option<usize> {
+        let mut start = 0;
+        for _ in 0..self.line {
+            start += text[start..].find('\n')? + 1
+        }
+        let line = &text[start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+        if self.column == line.chars().count() {
+            return Some(start + line.len());
+        }
+        line.char_indices().nth(self.column).map(|(i, _)| start + i)
+    }
+}
+
+/// The declarations of an element type.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ElementDecl {
+    /// The elements that may be children. None if any element is allowed.
+    pub children: Option<Vec<String>>,
+    /// Whether the element may contain text.
+    pub text: bool,
+    /// The names of the declared attributes.
+    pub attributes: Vec<String>,
+}
+
+/// The element and attribute declarations of a document type declaration. Names are as written in the declarations, including any prefix.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Grammar {
+    /// The name of the document element, given by the document type declaration.
+    pub root: Option<String>,
+    elements: BTreeMap<String, ElementDecl>,
+}
+
+impl Grammar {
+    /// Read the declarations in the internal subset of the document type declaration of the document. A document without a document type declaration has an empty grammar.
+    pub fn from_document(text: &str) -> Grammar {
+        let mut g = Grammar::default();
+        let d = match text.find("<!DOCTYPE") {
+            Some(s) => &text[s..s + doctype_end(&text[s..]).unwrap_or(text.len() - s)],
+            None => return g,
+        };
+        let tokens = decl_tokens(&d[9..]);
+        g.root = tokens.first().cloned();
+        if let Some(subset) = tokens.iter().find(|t| t.starts_with('[')) {
+            g.read_subset(subset.trim_start_matches('[').trim_end_matches(']'))
+        }
+        g
+    }
+    fn read_subset(&mut self, subset: &str) {
+        let mut input = subset;
+        while let Some(s) = input.find('<') {
+            input = &input[s..];
+            if let Some(r) = input.strip_prefix("<!--") {
+                input = r.find("-->").map_or("", |e| &r[e + 3..]);
+                continue;
+            }
+            if let Some(r) = input.strip_prefix("<?") {
+                input = r.find("?>").map_or("", |e| &r[e + 2..]);
+                continue;
+            }
+            let e = markup_end(input).unwrap_or(input.len());
+            let decl = &input[..e];
+            input = &input[e..];
+            if let Some(body) = decl.strip_prefix("<!ELEMENT") {
+                let t = decl_tokens(body.trim_end_matches('>'));
+                if let (Some(name), Some(spec)) = (t.first(), t.get(1)) {
+                    let el = self.elements.entry(name.clone()).or_default();
+                    match spec.as_str() {
+                        "EMPTY" => el.children = Some(vec![]),
+                        "ANY" => {
+                            el.children = None;
+                            el.text = true
+                        }
+                        _ => {
+                            el.text = spec.contains("#PCDATA");
+                            el.children = Some(
+                                spec.split(|c: char| "()|,?*+".contains(c) || c.is_whitespace())
+                                    .filter(|n| !n.is_empty() && *n != "#PCDATA")
+                                    .map(String::from)
+                                    .collect(),
+                            )
+                        }
+                    }
+                }
+            } else if let Some(body) = decl.strip_prefix("<!ATTLIST") {
+                let t = decl_tokens(body.trim_end_matches('>'));
+                if let Some(name) = t.first() {
+                    let el = self.elements.entry(name.clone()).or_default();
+                    // Each definition is a name, a type and a default
+                    let mut i = 1;
+                    while i + 2 <= t.len() {
+                        el.attributes.push(t[i].clone());
+                        i += if t[i + 1] == "NOTATION" { 3 } else { 2 };
+                        i += if t.get(i).is_some_and(|d| d == "#FIXED") {
+                            2
+                        } else {
+                            1
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    /// The declared element types.
+    pub fn elements(&self) -> Vec<String> {
+        self.elements.keys().cloned().collect()
+    }
+    pub fn element(&self, name: &str) -> Option<&ElementDecl> {
+        self.elements.get(name)
+    }
+}
+
+/// The names that are valid at a position.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Completion {
+    /// The position is in content. These elements may be started.
+    Element(Vec<String>),
+    /// The position is in the start tag of the named element. These attributes have not yet been given.
+    Attribute(String, Vec<String>),
+    /// Nothing is known about the position.
+    None,
+}
+
+/// Find the names that are valid at an offset in the text, using the declarations of the grammar. The text before the offset need not be well-formed.
+pub fn complete(text: &str, offset: usize, g: &Grammar) -> Completion {
+    let before = match text.get(..offset) {
+        Some(b) => b,
+        None => return Completion::None,
+    };
+    // Is the offset inside a start tag?
+    if let Some(s) = before.rfind('<') {
+        let tag = &before[s + 1..];
+        if !tag.contains('>')
+            && !tag.starts_with(['/', '!', '?'])
+            && tag.contains(char::is_whitespace)
+        {
+            let t = decl_tokens(tag);
+            let name = t.first().cloned().unwrap_or_default();
+            let given: Vec<&str> = t
+                .iter()
+                .skip(1)
+                .filter_map(|a| a.split('=').next())
+                .collect();
+            let mut names: Vec<String> = g
+                .element(name.as_str())
+                .map_or(vec![], |e| e.attributes.clone())
+                .into_iter()
+                .filter(|a| !given.contains(&a.as_str()))
+                .collect();
+            names.sort();
+            return Completion::Attribute(name, names);
+        }
+    }
+    // Find the element that is open at the offset
+    let mut open: Vec<String> = vec![];
+    let mut input = before;
+    while !input.is_empty() {
+        if input.starts_with("<!DOCTYPE") {
+            match doctype_end(input) {
+                Some(e) => input = &input[e..],
+                None => return Completion::None,
+            }
+            continue;
+        }
+        match next_token(before, input) {
+            Ok((rest, Token::StartTag(n, _, false))) => {
+                open.push(n.to_string());
+                input = rest
+            }
+            Ok((rest, Token::EndTag(_))) => {
+                open.pop();
+                input = rest
+            }
+            Ok((rest, _)) => input = rest,
+            // An incomplete start tag, or text that continues past the offset
+            Err(_) => break,
+        }
+    }
+    match open.last() {
+        Some(p) => match g.element(p) {
+            Some(ElementDecl {
+                children: Some(c), ..
+            }) => {
+                let mut c = c.clone();
+                c.sort();
+                c.dedup();
+                Completion::Element(c)
+            }
+            Some(_) => Completion::Element(g.elements()),
+            None => Completion::None,
+        },
+        None => g
+            .root
+            .clone()
+            .map_or(Completion::None, |r| Completion::Element(vec![r])),
+    }
+}
+
+// The length of a document type declaration, including the internal subset.
+fn doctype_end(input: &str) -> Option<usize> {
+    markup_end(input)
+}
+
+// Find the end of a markup declaration, allowing for quoted strings, bracketed subsets and comments.
+fn markup_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut i = 1;
+    let b = input.as_bytes();
+    while i < b.len() {
+        let c = b[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                b'"' | b'\'' => quote = Some(c),
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                b'<' if input[i..].starts_with("<!--") => {
+                    i += input[i..].find("-->")? + 2;
+                }
+                b'>' if depth == 0 => return Some(i + 1),
+                _ => {}
+            },
+        }
+        i += 1
+    }
+    None
+}
+
+// Split a declaration into tokens: quoted strings, parenthesised groups, bracketed subsets, and names.
+fn decl_tokens(s: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut end = s.len();
+        match c {
+            '"' | '\'' => {
+                if let Some((j, _)) = chars.by_ref().find(|(_, d)| *d == c) {
+                    end = j + 1
+                }
+            }
+            '(' | '[' => {
+                let close = if c == '(' { ')' } else { ']' };
+                let mut depth = 1;
+                for (j, d) in chars.by_ref() {
+                    if d == c {
+                        depth += 1
+                    } else if d == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = j + 1;
+                            break;
+                        }
+                    }
+                }
+                // Occurrence indicators belong to the group
+                while let Some((j, d)) = chars.peek().copied() {
+                    if "?*+".contains(d) {
+                        end = j + 1;
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                while let Some((j, d)) = chars.peek().copied() {
+                    if d.is_whitespace() {
+                        end = j;
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+        }
+        result.push(s[i..end].to_string())
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn well_formedness() {
+        let messages = |t: &str| {
+            diagnostics(t)
+                .into_iter()
+                .map(|d| (t[d.range.clone()].to_string(), d.message))
+                .collect::<Vec<_>>()
+        };
+        assert!(diagnostics("<?xml version='1.0'?><a><b/>text</a>").is_empty());
+        assert_eq!(
+            messages("<a><b><c></a>"),
+            vec![
+                (
+                    String::from("<b>"),
+                    String::from("element \"b\" is not closed")
+                ),
+                (
+                    String::from("<c>"),
+                    String::from("element \"c\" is not closed")
+                )
+            ]
+        );
+        assert_eq!(
+            messages("<a></b><a x=></a>oops"),
+            vec![
+                (
+                    String::from("</b>"),
+                    String::from("expected the end tag for \"a\"")
+                ),
+                (String::from("<a x=>"), String::from("syntax error")),
+                (
+                    String::from("oops"),
+                    String::from("text is not allowed outside the document element")
+                )
+            ]
+        );
+        assert_eq!(
+            messages("<a/><b/>"),
+            vec![(
+                String::from("<b/>"),
+                String::from("a document may only have one document element")
+            )]
+        );
+        assert_eq!(diagnostics("").len(), 1)
+    }
+
+    #[test]
+    fn positions() {
+        let t = "<a>\n  <b>é</b>\n</a>";
+        let p = Position::of(t, t.find("</b>").unwrap());
+        assert_eq!(p, Position { line: 1, column: 6 });
+        assert_eq!(p.offset(t), t.find("</b>"));
+        assert_eq!(Position { line: 2, column: 4 }.offset(t), Some(t.len()));
+        assert_eq!(Position { line: 2, column: 5 }.offset(t), None);
+        assert_eq!(Position { line: 3, column: 0 }.offset(t), None)
+    }
+
+    #[test]
+    fn grammar() {
+        let t = "<!DOCTYPE doc SYSTEM 'doc.dtd' [
+<!-- a comment with <!ELEMENT ignored ANY> -->
+<!ELEMENT doc (head, (p | list)+)>
+<!ELEMENT head EMPTY>
+<!ELEMENT p (#PCDATA | em)*>
+<!ELEMENT list ANY>
+<!ATTLIST p class CDATA #IMPLIED lang NMTOKEN #FIXED 'en' align (left|right) 'left'>
+<!ATTLIST list type NOTATION (a|b) #REQUIRED>
+<!ENTITY greeting 'hello <there>'>
+]><doc><head/><p class='x' ></p></doc>";
+        let g = Grammar::from_document(t);
+        assert_eq!(g.root.as_deref(), Some("doc"));
+        assert_eq!(g.elements(), vec!["doc", "head", "list", "p"]);
+        assert_eq!(
+            g.element("p").unwrap().attributes,
+            vec!["class", "lang", "align"]
+        );
+        assert_eq!(g.element("list").unwrap().attributes, vec!["type"]);
+        assert!(g.element("p").unwrap().text);
+        assert_eq!(g.element("list").unwrap().children, None);
+
+        let at = |s: &str| complete(t, t.find(s).unwrap(), &g);
+        assert_eq!(
+            at("<head/>"),
+            Completion::Element(vec![
+                String::from("head"),
+                String::from("list"),
+                String::from("p")
+            ])
+        );
+        assert_eq!(
+            complete(t, t.find("class='x' ").unwrap() + 10, &g),
+            Completion::Attribute(
+                String::from("p"),
+                vec![String::from("align"), String::from("lang")]
+            )
+        );
+        assert_eq!(at("</p>"), Completion::Element(vec![String::from("em")]));
+        assert_eq!(at("<doc>"), Completion::Element(vec![String::from("doc")]));
+        assert_eq!(complete(t, t.len() + 1, &g), Completion::None);
+        assert_eq!(
+            complete("<doc>< ", 7, &g),
+            Completion::Attribute(String::new(), vec![])
+        );
+        assert_eq!(diagnostics(t), vec![])
+    }
+}
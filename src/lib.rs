@@ -43,7 +43,7 @@ We need your help!
 pub mod xdmerror;
 pub use xdmerror::{Error, ErrorKind};
 
-mod output;
+pub mod output;
 mod parsepicture;
 pub mod qname;
 
@@ -53,6 +53,7 @@ pub mod forest;
 pub use forest::Node;
 pub mod item;
 pub use item::{Item, Sequence, SequenceTrait};
+pub mod sequencetype;
 
 mod parsecommon;
 
@@ -62,10 +63,33 @@ pub use parsexml::XMLDocument;
 pub mod xpath;
 pub use xpath::parse;
 
+pub mod axes;
+pub mod binary;
+pub mod builder;
+pub mod edit;
+pub mod persistent;
+pub mod resolvers;
+pub mod sax;
+pub mod select;
+pub mod soap;
+
+pub mod format;
+pub mod json;
+pub mod lang;
+pub mod lint;
+pub mod visit;
+pub mod xmlenc;
+
 pub mod evaluate;
 pub use evaluate::{Constructor, Evaluator, StaticContext};
+#[cfg(feature = "ast")]
+pub mod ast;
 
 #[cfg(feature = "xslt")]
 pub mod xslt;
 #[cfg(feature = "xslt")]
 pub use xslt::from_document;
+#[cfg(feature = "xslt")]
+pub mod batch;
+#[cfg(feature = "xslt")]
+pub mod xspec;
@@ -0,0 +1,388 @@
+/*! ## Checking documents against a set of rules
+
+A [RuleSet] is a collection of [Rule]s. Checking a document against the rule set produces a list of [Diagnostic]s, one for each violation of a rule.
+
+There are several kinds of rule:
+
+* An assertion is an XPath expression that must have an effective boolean value of true, when evaluated with the document node as the context item.
+* A forbidden construct is an XPath expression that selects nodes that must not occur in the document. A diagnostic is reported for each node selected.
+* A naming convention is a regular expression that the local name of every element, or attribute, must match.
+
+```rust
+use xrust::forest::Forest;
+use xrust::lint::{Rule, RuleSet, Severity};
+
+let mut f = Forest::new();
+let src = f.grow_tree("<Example><Title/><para/></Example>")
+    .expect("unable to parse XML");
+
+let mut rules = RuleSet::new();
+rules.add(Rule::element_naming("upper-camel-case", "^[A-Z][a-zA-Z0-9]*$")
+    .expect("bad regular expression"));
+rules.add(Rule::forbid("no-title", "child::Example/descendant-or-self::Title")
+    .expect("bad XPath expression")
+    .with_severity(Severity::Warning)
+    .with_message("Title elements are deprecated"));
+
+let d = rules.check(&mut f, src).expect("unable to check document");
+assert_eq!(d.len(), 2);
+```
+*/
+
+use crate::evaluate::{Constructor, Evaluator, StaticContext};
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::item::{Item, SequenceTrait};
+use crate::xdmerror::*;
+use crate::xpath::parse;
+use core::fmt;
+use regex::Regex;
+use std::rc::Rc;
+
+/// How serious a violation of a [Rule] is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// The report of a violation of a [Rule].
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// The name of the rule that was violated
+    pub rule: String,
+    pub severity: Severity,
+    pub message: String,
+    /// The node where the violation occurred, if the rule applies to a particular node
+    pub node: Option<Node>,
+    /// A key for the position of the node in document order, for sorting diagnostics. See [Node::document_order].
+    pub document_order: Option<usize>,
+    /// An XPath expression that selects the node. See [Node::path].
+    pub path: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} [{}]",
+            self.severity.to_string(),
+            self.message,
+            self.rule
+        )?;
+        if let Some(p) = &self.path {
+            write!(f, " at {}", p)?;
+        }
+        Ok(())
+    }
+}
+
+// What a rule checks
+#[derive(Clone)]
+enum RuleKind {
+    Assert(Vec<Constructor>),
+    Forbid(Vec<Constructor>),
+    ElementNaming(Regex),
+    AttributeNaming(Regex),
+}
+
+/// A single check to be performed on a document.
+#[derive(Clone)]
+pub struct Rule {
+    name: String,
+    severity: Severity,
+    message: Option<String>,
+    kind: RuleKind,
+}
+
+impl Rule {
+    /// The XPath expression must be true for the document.
+    pub fn assert(name: &str, xpath: &str) -> Result<Rule, Error> {
+        Ok(Rule::new(name, RuleKind::Assert(compile(xpath)?)))
+    }
+    /// Every node selected by the XPath expression is a violation.
+    pub fn forbid(name: &str, xpath: &str) -> Result<Rule, Error> {
+        Ok(Rule::new(name, RuleKind::Forbid(compile(xpath)?)))
+    }
+    /// The local name of every element must match the regular expression.
+    pub fn element_naming(name: &str, re: &str) -> Result<Rule, Error> {
+        Ok(Rule::new(name, RuleKind::ElementNaming(regex(re)?)))
+    }
+    /// The local name of every attribute must match the regular expression.
+    pub fn attribute_naming(name: &str, re: &str) -> Result<Rule, Error> {
+        Ok(Rule::new(name, RuleKind::AttributeNaming(regex(re)?)))
+    }
+    fn new(name: &str, kind: RuleKind) -> Rule {
+        Rule {
+            name: name.to_string(),
+            severity: Severity::Error,
+            message: None,
+            kind,
+        }
+    }
+    /// Set the severity of violations of this rule. The default is [Severity::Error].
+    pub fn with_severity(mut self, s: Severity) -> Rule {
+        self.severity = s;
+        self
+    }
+    /// Set the message reported for violations of this rule.
+    pub fn with_message(mut self, m: &str) -> Rule {
+        self.message = Some(m.to_string());
+        self
+    }
+    pub fn get_name(&self) -> &str {
+        self.name.as_str()
+    }
+    pub fn get_severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn diagnostic(&self, f: &Forest, n: Option<Node>, default: String) -> Diagnostic {
+        Diagnostic {
+            rule: self.name.clone(),
+            severity: self.severity,
+            message: self.message.clone().unwrap_or(default),
+            node: n,
+            document_order: n.and_then(|m| m.document_order(f)),
+            path: n.map(|m| m.path(f)),
+        }
+    }
+
+    fn check(
+        &self,
+        f: &mut Forest,
+        t: TreeIndex,
+        result: &mut Vec<Diagnostic>,
+    ) -> Result<(), Error> {
+        match &self.kind {
+            RuleKind::Assert(c) => {
+                let s = evaluate(c, f, t)?;
+                if !s.to_bool() {
+                    result.push(self.diagnostic(
+                        f,
+                        None,
+                        format!("assertion \"{}\" failed", self.name),
+                    ))
+                }
+            }
+            RuleKind::Forbid(c) => {
+                let s = evaluate(c, f, t)?;
+                for i in s {
+                    match *i {
+                        Item::Node(n) => result.push(self.diagnostic(
+                            f,
+                            Some(n),
+                            format!(
+                                "forbidden {} \"{}\"",
+                                n.node_type(f).to_string(),
+                                n.to_name(f).to_string()
+                            ),
+                        )),
+                        _ => {
+                            if i.to_bool() {
+                                result.push(self.diagnostic(
+                                    f,
+                                    None,
+                                    String::from("forbidden construct found"),
+                                ))
+                            }
+                        }
+                    }
+                }
+            }
+            RuleKind::ElementNaming(re) | RuleKind::AttributeNaming(re) => {
+                let want = if let RuleKind::ElementNaming(_) = self.kind {
+                    NodeType::Element
+                } else {
+                    NodeType::Attribute
+                };
+                let d = f
+                    .get_ref(t)
+                    .ok_or(Error::new(
+                        ErrorKind::Unknown,
+                        String::from("unable to find tree"),
+                    ))?
+                    .get_doc_node();
+                let mut stack = vec![d];
+                while let Some(n) = stack.pop() {
+                    let mut candidates = vec![];
+                    if want == NodeType::Attribute {
+                        let mut ait = n.attribute_iter(f);
+                        while let Some(a) = ait.next() {
+                            candidates.push(a)
+                        }
+                    } else if n.node_type(f) == NodeType::Element {
+                        candidates.push(n)
+                    }
+                    for c in candidates {
+                        let name = c.to_name(f).get_localname();
+                        if !re.is_match(name.as_str()) {
+                            result.push(self.diagnostic(
+                                f,
+                                Some(c),
+                                format!(
+                                    "{} name \"{}\" does not match \"{}\"",
+                                    c.node_type(f).to_string(),
+                                    name,
+                                    re.as_str()
+                                ),
+                            ))
+                        }
+                    }
+                    let mut children = vec![];
+                    let mut cit = n.child_iter();
+                    while let Some(c) = cit.next(f) {
+                        if c.node_type(f) == NodeType::Element {
+                            children.push(c)
+                        }
+                    }
+                    children.into_iter().rev().for_each(|c| stack.push(c));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A collection of [Rule]s.
+#[derive(Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet { rules: vec![] }
+    }
+    pub fn add(&mut self, r: Rule) {
+        self.rules.push(r)
+    }
+    /// Remove the rule with the given name. Returns true if the rule was in the set.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|r| r.name != name);
+        len != self.rules.len()
+    }
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    /// Check a document against all of the rules in the set.
+    /// Diagnostics are reported in the order that the rules were added.
+    pub fn check(&self, f: &mut Forest, t: TreeIndex) -> Result<Vec<Diagnostic>, Error> {
+        let mut result = vec![];
+        for r in &self.rules {
+            r.check(f, t, &mut result)?
+        }
+        Ok(result)
+    }
+}
+
+fn compile(xpath: &str) -> Result<Vec<Constructor>, Error> {
+    let mut c = parse(xpath)?;
+    StaticContext::new_with_builtins().static_analysis(&mut c);
+    Ok(c)
+}
+
+fn regex(re: &str) -> Result<Regex, Error> {
+    Regex::new(re).map_err(|e| Error::new(ErrorKind::StaticSyntax, e.to_string()))
+}
+
+// Evaluate an expression with the document node as the context item
+fn evaluate(c: &Vec<Constructor>, f: &mut Forest, t: TreeIndex) -> Result<Vec<Rc<Item>>, Error> {
+    let d = f
+        .get_ref(t)
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?
+        .get_doc_node();
+    // XPath expressions don't construct nodes, so the result tree is discarded afterwards
+    let trees = f.tree_count();
+    let rd = f.plant_tree();
+    let result =
+        Evaluator::new().evaluate(Some(vec![Rc::new(Item::Node(d))]), Some(0), c, f, t, rd);
+    f.truncate(trees);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assertion() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<Test><a/><b/></Test>")
+            .expect("unable to parse XML");
+        let mut rules = RuleSet::new();
+        rules.add(Rule::assert("has-a", "count(child::Test/child::a) = 1").expect("bad rule"));
+        rules.add(Rule::assert("has-c", "count(child::Test/child::c) = 1").expect("bad rule"));
+        let d = rules.check(&mut f, t).expect("unable to check document");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].rule, "has-c");
+        assert_eq!(d[0].severity, Severity::Error);
+        assert!(d[0].node.is_none());
+        // Checking doesn't leave any trees behind
+        let trees = f.tree_count();
+        rules.check(&mut f, t).expect("unable to check document");
+        assert_eq!(f.tree_count(), trees)
+    }
+
+    #[test]
+    fn forbid() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<Test><a/><b/><a/></Test>")
+            .expect("unable to parse XML");
+        let mut rules = RuleSet::new();
+        rules.add(
+            Rule::forbid("no-a", "child::Test/child::a")
+                .expect("bad rule")
+                .with_severity(Severity::Warning)
+                .with_message("do not use a"),
+        );
+        let d = rules.check(&mut f, t).expect("unable to check document");
+        assert_eq!(d.len(), 2);
+        assert!(d[0].document_order.is_some() && d[0].document_order < d[1].document_order);
+        assert_eq!(d[1].path.as_deref(), Some("/Test/a[2]"));
+        assert_eq!(
+            d[0].to_string(),
+            "warning: do not use a [no-a] at /Test/a[1]"
+        )
+    }
+
+    #[test]
+    fn naming() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<Test bad_name='1' goodName='2'><Good/><not_good/></Test>")
+            .expect("unable to parse XML");
+        let mut rules = RuleSet::new();
+        rules.add(Rule::element_naming("elements", "^[A-Z][a-zA-Z]*$").expect("bad rule"));
+        rules.add(Rule::attribute_naming("attributes", "^[a-z][a-zA-Z]*$").expect("bad rule"));
+        let d = rules.check(&mut f, t).expect("unable to check document");
+        assert_eq!(d.len(), 2);
+        assert_eq!(d[0].node.unwrap().to_name(&f).get_localname(), "not_good");
+        assert_eq!(d[1].node.unwrap().to_name(&f).get_localname(), "bad_name")
+    }
+
+    #[test]
+    fn bad_rules() {
+        assert!(Rule::element_naming("bad", "[").is_err());
+        assert!(Rule::assert("bad", "child::").is_err())
+    }
+}
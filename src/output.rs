@@ -1,11 +1,21 @@
+use crate::forest::{Forest, Node, NodeType};
 use crate::qname::QualifiedName;
+use crate::xdmerror::*;
 use core::fmt;
+use std::collections::HashMap;
 
 /// An output definition. See XSLT v3.0 26 Serialization
 #[derive(Clone, Debug)]
 pub struct OutputDefinition {
     name: Option<QualifiedName>, // TODO: EQName
     indent: bool,
+    quote: char, // The preferred delimiter for attribute values
+    method: Option<String>,
+    encoding: Option<String>,
+    omit_xml_declaration: bool,
+    standalone: Option<bool>, // None means that standalone is omitted from the XML declaration
+    // Whether text marked with DisableOutputEscaping is written as is
+    disable_output_escaping: bool,
     // TODO: all the other myriad output parameters
 }
 
@@ -14,8 +24,42 @@ impl OutputDefinition {
         OutputDefinition {
             name: None,
             indent: false,
+            quote: '\'',
+            method: None,
+            encoding: None,
+            omit_xml_declaration: false,
+            standalone: None,
+            disable_output_escaping: true,
         }
     }
+    /// Read the serialization parameters from the attributes of an xsl:output element.
+    ///
+    /// Attributes that are not serialization parameters, or are in a namespace, are ignored.
+    pub fn from_xsl_output(f: &Forest, n: Node) -> Result<OutputDefinition, Error> {
+        if n.node_type(f) != NodeType::Element || n.to_name(f).get_localname() != "output" {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("not an xsl:output element"),
+            ));
+        }
+        let mut b = OutputDefinitionBuilder::new();
+        let mut ait = n.attribute_iter(f);
+        while let Some(a) = ait.next() {
+            let an = a.to_name(f);
+            if an.get_nsuri().is_none() {
+                b = b.parameter(an.get_localname().as_str(), a.to_string(f).as_str())?
+            }
+        }
+        b.build()
+    }
+    /// Create an output definition from a map of serialization parameter names to values, as used by fn:serialize.
+    pub fn from_parameters(params: &HashMap<String, String>) -> Result<OutputDefinition, Error> {
+        let mut b = OutputDefinitionBuilder::new();
+        for (k, v) in params {
+            b = b.parameter(k.as_str(), v.as_str())?
+        }
+        b.build()
+    }
     pub fn get_name(&self) -> Option<QualifiedName> {
         self.name.clone()
     }
@@ -35,6 +79,255 @@ impl OutputDefinition {
     pub fn set_indent(&mut self, ind: bool) {
         self.indent = ind;
     }
+    /// The output method, such as "xml", "html" or "text".
+    pub fn get_method(&self) -> Option<&str> {
+        self.method.as_deref()
+    }
+    pub fn set_method(&mut self, m: Option<String>) {
+        self.method = m
+    }
+    pub fn get_encoding(&self) -> Option<&str> {
+        self.encoding.as_deref()
+    }
+    /// Set the output encoding. Only UTF-8 and UTF-16 are supported.
+    pub fn set_encoding(&mut self, e: Option<String>) -> Result<(), Error> {
+        match e.as_deref() {
+            None => {}
+            Some(v) if v.eq_ignore_ascii_case("UTF-8") || v.eq_ignore_ascii_case("UTF-16") => {}
+            Some(v) => {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    format!(
+                        "SESU0007: encoding \"{}\" is not supported, only UTF-8 and UTF-16 are",
+                        v
+                    ),
+                ))
+            }
+        }
+        self.encoding = e;
+        Ok(())
+    }
+    /// Whether the output is encoded as UTF-16.
+    pub(crate) fn is_utf16(&self) -> bool {
+        self.encoding
+            .as_deref()
+            .is_some_and(|e| e.eq_ignore_ascii_case("UTF-16"))
+    }
+    pub fn get_omit_xml_declaration(&self) -> bool {
+        self.omit_xml_declaration
+    }
+    pub fn set_omit_xml_declaration(&mut self, o: bool) {
+        self.omit_xml_declaration = o
+    }
+    /// The value of standalone in the XML declaration. If None, then it is not included in the declaration.
+    pub fn get_standalone(&self) -> Option<bool> {
+        self.standalone
+    }
+    pub fn set_standalone(&mut self, s: Option<bool>) {
+        self.standalone = s
+    }
+    /// Whether text nodes that are marked with [DisableOutputEscaping] are written without escaping. The default is true.
+    pub fn get_disable_output_escaping(&self) -> bool {
+        self.disable_output_escaping
+    }
+    /// Set whether text nodes that are marked with [DisableOutputEscaping] are written without escaping. If false then they are escaped like any other text, so that the output is always well-formed. This is safer when the stylesheet is not trusted.
+    pub fn set_disable_output_escaping(&mut self, d: bool) {
+        self.disable_output_escaping = d
+    }
+    /// The character used to delimit attribute values. The default is an apostrophe.
+    pub fn get_attribute_quote(&self) -> char {
+        self.quote
+    }
+    /// Set the character used to delimit attribute values. This must be either an apostrophe or a quotation mark.
+    ///
+    /// If a value contains the delimiter, but not the other quote character, then the other character is used to delimit that value. If it contains both then the delimiter is escaped.
+    pub fn set_attribute_quote(&mut self, q: char) -> Result<(), Error> {
+        match q {
+            '\'' | '"' => {
+                self.quote = q;
+                Ok(())
+            }
+            _ => Result::Err(Error::new(
+                ErrorKind::TypeError,
+                format!("\"{}\" cannot delimit an attribute value", q),
+            )),
+        }
+    }
+}
+
+/// A builder for an [OutputDefinition].
+///
+///```rust
+///use xrust::output::OutputDefinitionBuilder;
+///let od = OutputDefinitionBuilder::new()
+///    .indent(true)
+///    .method("xml")
+///    .attribute_quote('"')
+///    .build()
+///    .expect("invalid output definition");
+///assert!(od.get_indent());
+///assert_eq!(od.get_attribute_quote(), '"')
+///```
+pub struct OutputDefinitionBuilder(OutputDefinition);
+
+impl OutputDefinitionBuilder {
+    /// Start building an [OutputDefinition], with the default settings.
+    pub fn new() -> Self {
+        OutputDefinitionBuilder(OutputDefinition::new())
+    }
+    pub fn name(mut self, qn: QualifiedName) -> Self {
+        self.0.name = Some(qn);
+        self
+    }
+    pub fn indent(mut self, i: bool) -> Self {
+        self.0.indent = i;
+        self
+    }
+    /// Set the preferred delimiter for attribute values. It is checked when the definition is built.
+    pub fn attribute_quote(mut self, q: char) -> Self {
+        self.0.quote = q;
+        self
+    }
+    pub fn method(mut self, m: &str) -> Self {
+        self.0.method = Some(m.to_string());
+        self
+    }
+    /// Set the output encoding. It is checked when the definition is built.
+    pub fn encoding(mut self, e: &str) -> Self {
+        self.0.encoding = Some(e.to_string());
+        self
+    }
+    pub fn omit_xml_declaration(mut self, o: bool) -> Self {
+        self.0.omit_xml_declaration = o;
+        self
+    }
+    /// Set the value of standalone in the XML declaration, or None to omit it. It cannot be set if the XML declaration is omitted.
+    pub fn standalone(mut self, s: Option<bool>) -> Self {
+        self.0.standalone = s;
+        self
+    }
+    /// Set whether text that is marked with [DisableOutputEscaping] is written without escaping.
+    pub fn disable_output_escaping(mut self, d: bool) -> Self {
+        self.0.disable_output_escaping = d;
+        self
+    }
+    /// Set a serialization parameter, given its name and value as they appear in an xsl:output element.
+    /// Parameters that are not supported are ignored.
+    pub fn parameter(self, name: &str, value: &str) -> Result<Self, Error> {
+        let v = value.trim();
+        match name {
+            "name" => Ok(self.name(match v.split_once(':') {
+                Some((p, l)) => QualifiedName::new(None, Some(p.to_string()), l.to_string()),
+                None => QualifiedName::new(None, None, v.to_string()),
+            })),
+            "indent" => Ok(self.indent(yes_no(name, v)?)),
+            "method" => Ok(self.method(v)),
+            "encoding" => Ok(self.encoding(v)),
+            "omit-xml-declaration" => Ok(self.omit_xml_declaration(yes_no(name, v)?)),
+            "standalone" => match v {
+                "omit" => Ok(self.standalone(None)),
+                _ => Ok(self.standalone(Some(yes_no(name, v)?))),
+            },
+            "attribute-quote" => match v {
+                "'" | "\"" => Ok(self.attribute_quote(v.chars().next().unwrap())),
+                _ => Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    format!("\"{}\" cannot delimit an attribute value", v),
+                )),
+            },
+            _ => Ok(self),
+        }
+    }
+    /// Complete building the [OutputDefinition].
+    pub fn build(self) -> Result<OutputDefinition, Error> {
+        let mut od = self.0;
+        let q = od.quote;
+        od.set_attribute_quote(q)?;
+        let e = od.encoding.take();
+        od.set_encoding(e)?;
+        if od.omit_xml_declaration && od.standalone.is_some() {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from(
+                    "SEPM0009: standalone cannot be specified when the XML declaration is omitted",
+                ),
+            ));
+        }
+        Ok(od)
+    }
+}
+
+impl Default for OutputDefinitionBuilder {
+    fn default() -> Self {
+        OutputDefinitionBuilder::new()
+    }
+}
+
+// The value of a boolean serialization parameter
+fn yes_no(name: &str, v: &str) -> Result<bool, Error> {
+    match v {
+        "yes" | "true" | "1" => Ok(true),
+        "no" | "false" | "0" => Ok(false),
+        _ => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            format!(
+                "invalid value \"{}\" for serialization parameter \"{}\"",
+                v, name
+            ),
+        )),
+    }
+}
+
+/// Write an attribute value, including its delimiters, escaping characters as necessary.
+pub(crate) fn write_attribute_value<W: fmt::Write>(
+    w: &mut W,
+    v: &str,
+    od: &OutputDefinition,
+) -> fmt::Result {
+    let other = if od.quote == '"' { '\'' } else { '"' };
+    let q = if v.contains(od.quote) && !v.contains(other) {
+        other
+    } else {
+        od.quote
+    };
+    w.write_char(q)?;
+    for c in v.chars() {
+        match c {
+            '&' => w.write_str("&amp;")?,
+            '<' => w.write_str("&lt;")?,
+            '"' if q == '"' => w.write_str("&quot;")?,
+            '\'' if q == '\'' => w.write_str("&apos;")?,
+            // Preserve whitespace characters that would otherwise be normalised by a parser
+            '\t' => w.write_str("&#9;")?,
+            '\n' => w.write_str("&#10;")?,
+            '\r' => w.write_str("&#13;")?,
+            _ => w.write_char(c)?,
+        }
+    }
+    w.write_char(q)
+}
+
+/// A marker for a text node whose content is written without escaping, as is done for xsl:text with disable-output-escaping="yes".
+///
+/// The marker is kept as user data of the node (see [Node::set_user_data](crate::forest::Node::set_user_data)). It is honoured by the serializer unless the [OutputDefinition] says otherwise (see [OutputDefinition::set_disable_output_escaping]). Like other user data, it is not copied when the node is copied.
+pub struct DisableOutputEscaping;
+
+/// Write character data, escaping the characters that would otherwise be interpreted as markup.
+pub(crate) fn write_text<W: fmt::Write>(w: &mut W, v: &str) -> fmt::Result {
+    for c in v.chars() {
+        match c {
+            '&' => w.write_str("&amp;")?,
+            '<' => w.write_str("&lt;")?,
+            '>' => w.write_str("&gt;")?,
+            _ => w.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+impl Default for OutputDefinition {
+    fn default() -> Self {
+        OutputDefinition::new()
+    }
 }
 impl fmt::Display for OutputDefinition {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -45,3 +338,81 @@ impl fmt::Display for OutputDefinition {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xsl_output() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<xsl:output xmlns:xsl='http://www.w3.org/1999/XSL/Transform' name='fmt' indent='yes' method='xml' omit-xml-declaration='true' xsl:other='x'/>")
+            .expect("unable to parse");
+        let n = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let od = OutputDefinition::from_xsl_output(&f, n).expect("unable to read xsl:output");
+        assert_eq!(od.get_name().unwrap().get_localname(), "fmt");
+        assert!(od.get_indent());
+        assert_eq!(od.get_method(), Some("xml"));
+        assert!(od.get_omit_xml_declaration());
+
+        let t = f
+            .grow_tree(
+                "<xsl:output xmlns:xsl='http://www.w3.org/1999/XSL/Transform' indent='maybe'/>",
+            )
+            .expect("unable to parse");
+        let n = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert!(OutputDefinition::from_xsl_output(&f, n).is_err())
+    }
+
+    #[test]
+    fn parameters() {
+        let mut p = HashMap::new();
+        p.insert(String::from("indent"), String::from("no"));
+        p.insert(String::from("encoding"), String::from("UTF-8"));
+        p.insert(String::from("attribute-quote"), String::from("\""));
+        let od = OutputDefinition::from_parameters(&p).expect("invalid parameters");
+        assert!(!od.get_indent());
+        assert_eq!(od.get_encoding(), Some("UTF-8"));
+        assert_eq!(od.get_attribute_quote(), '"');
+
+        assert!(OutputDefinitionBuilder::new()
+            .attribute_quote('x')
+            .build()
+            .is_err())
+    }
+
+    #[test]
+    fn standalone() {
+        let od = OutputDefinitionBuilder::new()
+            .parameter("standalone", "yes")
+            .and_then(|b| b.build())
+            .expect("invalid parameters");
+        assert_eq!(od.get_standalone(), Some(true));
+        let od = OutputDefinitionBuilder::new()
+            .parameter("standalone", "omit")
+            .and_then(|b| b.build())
+            .expect("invalid parameters");
+        assert_eq!(od.get_standalone(), None);
+        assert!(OutputDefinitionBuilder::new()
+            .parameter("standalone", "perhaps")
+            .is_err());
+        let e = OutputDefinitionBuilder::new()
+            .omit_xml_declaration(true)
+            .standalone(Some(false))
+            .build()
+            .err()
+            .expect("standalone with no XML declaration");
+        assert!(e.message.starts_with("SEPM0009"))
+    }
+}
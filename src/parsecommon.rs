@@ -17,7 +17,11 @@ pub fn ncname(input: &str) -> IResult<&str, &str> {
     recognize(pair(ncnamestartchar, take_while(is_ncnamechar)))(input)
 }
 pub fn name(input: &str) -> IResult<&str, &str> {
-    recognize(pair(namestartchar, take_while1(is_namechar)))(input)
+    recognize(pair(namestartchar, take_while(is_namechar)))(input)
+}
+// Nmtoken ::= (NameChar)+
+pub fn nmtoken(input: &str) -> IResult<&str, &str> {
+    take_while1(is_namechar)(input)
 }
 fn is_namechar(ch: char) -> bool {
     if is_namestartchar(ch) {
@@ -5,27 +5,34 @@
 //!
 //! This is a very simple, minimalist parser of XML. It excludes:
 //!	DTDs (and therefore entities)
+//!
+//! A [Reader] is a pull parser that returns the content of a document as a sequence of events, without building the whole document in memory.
 
 extern crate nom;
 
 use crate::parsecommon::*;
 use crate::qname::*;
+use crate::resolvers::*;
 use crate::value::Value;
 use crate::xdmerror::*;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until, take_while_m_n},
+    bytes::complete::{tag, take_until, take_while},
     character::complete::{char, digit1, hex_digit1, multispace0, multispace1, none_of},
     combinator::{map, map_opt, opt, recognize, value, verify},
-    multi::{many0, many1},
+    multi::{many0, many1, separated_list1},
     sequence::delimited,
     sequence::tuple,
     IResult,
 };
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::Arc;
+use url::Url;
 
 // nom doesn't pass additional parameters, only the input,
 // so this is a two-pass process.
@@ -42,34 +49,169 @@ pub struct XMLDocument {
     pub content: Vec<XMLNode>,
     pub epilogue: Vec<XMLNode>,
     pub xmldecl: Option<XMLdecl>,
+    pub doctype: Option<QualifiedName>, // The name in the document type declaration, if there is one
 }
 
 impl XMLDocument {
-    /// Expand general entities in the document
+    /// Check that the document is allowed by the configuration's [DtdPolicy].
+    pub fn check_dtd_policy(&self, config: &ParserConfig) -> Result<(), Error> {
+        match &self.doctype {
+            Some(n) if config.dtd == DtdPolicy::Forbid => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!(
+                    "document type declaration for \"{}\" is not allowed",
+                    n.to_string()
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+    /// Whether the XML declaration has standalone="yes".
+    /// The general entities of a standalone document must be declared in the internal subset.
+    pub fn is_standalone(&self) -> bool {
+        self.xmldecl
+            .as_ref()
+            .is_some_and(|x| x.standalone.as_deref() == Some("yes"))
+    }
+    /// The public and system identifiers of the external DTD subset, if the document type declaration has them.
+    pub fn external_id(&self) -> Option<(Option<&str>, &str)> {
+        self.prologue.iter().find_map(|p| match p {
+            XMLNode::DTD(DTDDecl::ExternalSubset(p, s)) => Some((p.as_deref(), s.as_str())),
+            _ => None,
+        })
+    }
+    /// Expand general entities in the document.
+    /// Nothing is fetched: the external DTD subset is skipped, and a reference to an external entity is an error. Use [XMLDocument::expand_with_resolver] to fetch external resources.
     pub fn expand(&mut self) -> Result<(), Error> {
-        let mut ent: HashMap<QualifiedName, Vec<XMLNode>> = HashMap::new();
+        self.expand_with_config(
+            None,
+            &NoFetchResolver,
+            &ParserConfig::new().dtd_policy(DtdPolicy::Ignore),
+        )
+    }
+    /// Expand general entities in the document.
+    /// The external DTD subset and external entities are resolved against the base URL, and their content fetched using the given [Resolver].
+    pub fn expand_with_resolver(
+        &mut self,
+        base: Option<&Url>,
+        resolver: &dyn Resolver,
+    ) -> Result<(), Error> {
+        self.expand_int(
+            base,
+            resolver,
+            false,
+            &ParserConfig::new().dtd_policy(DtdPolicy::Process),
+            &DtdCache::new(),
+        )
+    }
+    /// Expand general entities in the document, as [XMLDocument::expand_with_resolver] does, fetching the external DTD subset and external entities with an [AsyncResolver].
+    #[cfg(feature = "async")]
+    pub async fn expand_async<R: AsyncResolver>(
+        &mut self,
+        base: Option<&Url>,
+        resolver: &R,
+    ) -> Result<(), Error> {
+        with_prefetch(&Prefetched::new(), resolver, |p| {
+            self.expand_with_resolver(base, p)
+        })
+        .await
+    }
+    /// Expand general entities in the document.
+    /// The configuration determines what happens when a reference to an undeclared entity is found, and whether external resources are fetched using the resolver (see [DtdPolicy]).
+    pub fn expand_with_config(
+        &mut self,
+        base: Option<&Url>,
+        resolver: &dyn Resolver,
+        config: &ParserConfig,
+    ) -> Result<(), Error> {
+        self.expand_int(base, resolver, false, config, &DtdCache::new())
+    }
+    /// Expand general entities in the document, taking the declarations of an external DTD subset from the cache if it has already been parsed.
+    /// If the configuration's policy is [DtdPolicy::Process], an external subset that is not in the cache is fetched using the resolver, parsed, and added to the cache.
+    pub fn expand_with_cache(
+        &mut self,
+        base: Option<&Url>,
+        resolver: &dyn Resolver,
+        config: &ParserConfig,
+        cache: &DtdCache,
+    ) -> Result<(), Error> {
+        self.expand_int(base, resolver, false, config, cache)
+    }
+    /// Resolve general entities in the document, but keep the references.
+    /// Each reference is replaced by an [XMLNode::EntityReference] that has the entity's name and its expansion, so that the reference can be serialised rather than the expansion.
+    pub fn resolve_references(
+        &mut self,
+        base: Option<&Url>,
+        resolver: &dyn Resolver,
+    ) -> Result<(), Error> {
+        self.expand_int(
+            base,
+            resolver,
+            true,
+            &ParserConfig::new().dtd_policy(DtdPolicy::Process),
+            &DtdCache::new(),
+        )
+    }
+    fn expand_int(
+        &mut self,
+        base: Option<&Url>,
+        resolver: &dyn Resolver,
+        preserve: bool,
+        config: &ParserConfig,
+        cache: &DtdCache,
+    ) -> Result<(), Error> {
+        self.check_dtd_policy(config)?;
+        let mut ent = Entities::default();
+        let standalone = self.is_standalone();
+        let fetch = config.dtd == DtdPolicy::Process;
 
         // Process the entity declarations to get the definition of each entity
         for p in &self.prologue {
-            if let XMLNode::DTD(d) = p {
-                let DTDDecl::GeneralEntity(n, c) = d;
-                let (rest, e) = content(c.as_str())
-                    .map_err(|e| Error::new(ErrorKind::Unknown, e.to_string()))?;
-                if rest.len() != 0 {
-                    return Result::Err(Error::new(
-                        ErrorKind::Unknown,
-                        format!("unable to parse general entity \"{}\"", n.to_string()),
-                    ));
+            match p {
+                XMLNode::DTD(DTDDecl::ExternalSubset(_, _)) if !fetch => {}
+                XMLNode::DTD(DTDDecl::ExternalEntity(n, _, _)) if !fetch => {
+                    ent.attribute.insert(n.clone(), None);
                 }
-                match ent.insert(n.clone(), e) {
-                    Some(_) => {
-                        return Result::Err(Error::new(
-                            ErrorKind::Unknown,
-                            format!("general entity \"{}\" already defined", n.to_string()),
-                        ))
+                XMLNode::DTD(DTDDecl::ExternalSubset(_, sysid)) => {
+                    // Without a base URL a relative system identifier can't be resolved, so the external subset is skipped
+                    let u = match resolve_href(base, sysid.as_str()) {
+                        Ok(u) => u,
+                        Err(_) if base.is_none() => continue,
+                        Err(e) => return Result::Err(e),
+                    };
+                    // The internal subset has already been processed, and its declarations take precedence
+                    for d in cache.load(&u, resolver)?.iter() {
+                        if let Some((n, e)) = entity_definition(d, Some(&u))? {
+                            if ent.content.contains_key(&n) {
+                                continue;
+                            }
+                            if standalone {
+                                ent.external.insert(n);
+                                continue;
+                            }
+                            ent.content.insert(n, e);
+                        }
+                        if let Some((n, t)) = attribute_replacement(d) {
+                            ent.attribute.insert(n, t);
+                        }
+                        ent.declare_attributes(d);
                     }
-                    None => {}
                 }
+                XMLNode::DTD(d) => {
+                    if let Some((n, t)) = attribute_replacement(d) {
+                        ent.attribute.insert(n, t);
+                    }
+                    ent.declare_attributes(d);
+                    if let Some((n, e)) = entity_definition(d, base)? {
+                        if ent.content.insert(n.clone(), e).is_some() {
+                            return Result::Err(Error::new(
+                                ErrorKind::Unknown,
+                                format!("general entity \"{}\" already defined", n.to_string()),
+                            ));
+                        }
+                    }
+                }
+                _ => {}
             }
         }
 
@@ -77,8 +219,9 @@ impl XMLDocument {
         // This naieve implementation copies the entire document...
         // TODO: a better implementation that mutates the current document
         let mut new: Vec<XMLNode> = vec![];
+        let mut st = Expansion::new(resolver);
         for e in &self.content {
-            let mut a = expand_node(e, &ent);
+            let mut a = expand_node(e, &ent, preserve, config, &mut st)?;
             new.append(&mut a);
         }
         self.content = new;
@@ -87,37 +230,1341 @@ impl XMLDocument {
     }
 }
 
-fn expand_node(n: &XMLNode, ent: &HashMap<QualifiedName, Vec<XMLNode>>) -> Vec<XMLNode> {
+// The general entities that may be referenced in a document, and the attributes that are declared for each element type
+#[derive(Default)]
+struct Entities {
+    // The replacement content of each entity
+    content: HashMap<QualifiedName, Replacement>,
+    // The replacement text of each entity, for references in attribute values
+    attribute: HashMap<QualifiedName, Option<String>>,
+    // Entities that are declared in the external subset of a standalone document, and so may not be referenced
+    external: HashSet<QualifiedName>,
+    // The attribute definitions of each element type
+    attlists: HashMap<QualifiedName, Vec<AttributeDefinition>>,
+}
+
+impl Entities {
+    // When an attribute is declared more than once, the first declaration is binding and the others are ignored.
+    // The internal subset is processed first, so its declarations take precedence over the external subset.
+    fn declare_attributes(&mut self, d: &DTDDecl) {
+        if let DTDDecl::AttributeList(e, defs) = d {
+            let v = self.attlists.entry(e.clone()).or_default();
+            for def in defs {
+                if !v.iter().any(|(n, _, _)| *n == def.0) {
+                    v.push(def.clone())
+                }
+            }
+        }
+    }
+}
+
+// The replacement content of a general entity
+enum Replacement {
+    // An internal entity, which is parsed when it is declared
+    Content(Vec<XMLNode>),
+    // An external entity, which is fetched and parsed when it is first referenced: the base URL of its declaration and its system identifier
+    External(Option<Url>, String),
+}
+
+// The name and replacement content of a general entity declaration
+fn entity_definition(
+    d: &DTDDecl,
+    base: Option<&Url>,
+) -> Result<Option<(QualifiedName, Replacement)>, Error> {
+    match d {
+        DTDDecl::GeneralEntity(n, c) => Ok(Some((
+            n.clone(),
+            Replacement::Content(parse_entity(n, c.as_str())?),
+        ))),
+        DTDDecl::ExternalEntity(n, _, sysid) => Ok(Some((
+            n.clone(),
+            Replacement::External(base.cloned(), sysid.clone()),
+        ))),
+        DTDDecl::ExternalSubset(_, _)
+        | DTDDecl::Notation(_, _, _)
+        | DTDDecl::AttributeList(_, _) => Ok(None),
+    }
+}
+
+// Fetch and parse the replacement content of an external entity
+fn external_entity(
+    n: &QualifiedName,
+    base: Option<&Url>,
+    sysid: &str,
+    resolver: &dyn Resolver,
+) -> Result<Vec<XMLNode>, Error> {
+    let u = resolve_href(base, sysid)?;
+    // Line ends are normalised in an external entity, as in the document entity
+    parse_entity(
+        n,
+        resolver
+            .resolve(&u)?
+            .replace("\r\n", "\n")
+            .replace('\r', "\n")
+            .as_str(),
+    )
+}
+
+fn parse_entity(n: &QualifiedName, c: &str) -> Result<Vec<XMLNode>, Error> {
+    parse_fragment(c).map_err(|_| {
+        Error::new(
+            ErrorKind::Unknown,
+            format!("unable to parse general entity \"{}\"", n.to_string()),
+        )
+    })
+}
+
+// The replacement text of a general entity, for references in attribute values: the entity value with character references replaced. External entities have no replacement text, since they may not be referenced in attribute values.
+fn attribute_replacement(d: &DTDDecl) -> Option<(QualifiedName, Option<String>)> {
+    match d {
+        DTDDecl::GeneralEntity(n, c) => {
+            let c = c.replace("\r\n", "\n");
+            let mut r = String::new();
+            let mut s = c.as_str();
+            while let Some(i) = s.find("&#") {
+                r.push_str(&s[..i]);
+                match chardata_unicode_codepoint(&s[i..]) {
+                    Ok((rest, c)) => {
+                        r.push_str(c.as_str());
+                        s = rest
+                    }
+                    Err(_) => {
+                        r.push_str("&#");
+                        s = &s[i + 2..]
+                    }
+                }
+            }
+            r.push_str(s);
+            Some((n.clone(), Some(r)))
+        }
+        DTDDecl::ExternalEntity(n, _, _) => Some((n.clone(), None)),
+        DTDDecl::ExternalSubset(_, _)
+        | DTDDecl::Notation(_, _, _)
+        | DTDDecl::AttributeList(_, _) => None,
+    }
+}
+
+/// A cache of parsed external DTD subsets, keyed by the URL of their system identifier.
+///
+/// When many documents that use the same DTD are parsed, the external subset is only fetched and parsed once. Each [Forest](../forest/struct.Forest.html) has a cache that is used by [grow_tree_with_resolver](../forest/struct.Forest.html#method.grow_tree_with_resolver).
+#[derive(Clone, Default)]
+pub struct DtdCache {
+    subsets: RefCell<HashMap<Url, Arc<Vec<DTDDecl>>>>,
+}
+
+impl DtdCache {
+    pub fn new() -> DtdCache {
+        DtdCache::default()
+    }
+    /// The number of external subsets in the cache.
+    pub fn len(&self) -> usize {
+        self.subsets.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.subsets.borrow().is_empty()
+    }
+    pub fn contains(&self, url: &Url) -> bool {
+        self.subsets.borrow().contains_key(url)
+    }
+    /// Remove every external subset from the cache, so that they will be fetched again.
+    pub fn clear(&self) {
+        self.subsets.borrow_mut().clear()
+    }
+    // The declarations of an external subset, fetching and parsing it if it is not in the cache
+    fn load(&self, url: &Url, resolver: &dyn Resolver) -> Result<Arc<Vec<DTDDecl>>, Error> {
+        if let Some(d) = self.subsets.borrow().get(url) {
+            return Ok(d.clone());
+        }
+        let d = Arc::new(extsubset(resolver.resolve(url)?.as_str())?);
+        self.subsets.borrow_mut().insert(url.clone(), d.clone());
+        Ok(d)
+    }
+}
+
+/// Parse a fragment of XML: the content that is allowed in an element.
+///
+/// The fragment may have more than one element at the top level, as well as text, comments and processing instructions. It may begin with a text declaration, as in an external parsed entity.
+///
+/// General entity references are not expanded, and are returned as [XMLNode::Reference] nodes.
+///
+///```rust
+///use xrust::parsexml::{parse_fragment, XMLNode};
+///let f = parse_fragment("text <a/><b>more</b>").expect("unable to parse fragment");
+///assert_eq!(f.len(), 3);
+///assert!(matches!(f[0], XMLNode::Text(_)))
+///```
+pub fn parse_fragment(s: &str) -> Result<Vec<XMLNode>, Error> {
+    let s = strip_textdecl(s.to_string());
+    match content(s.as_str()) {
+        Ok((rest, v)) => {
+            if rest.is_empty() {
+                Ok(v)
+            } else {
+                Result::Err(Error::new(
+                    ErrorKind::Unknown,
+                    format!("extra characters after fragment: \"{}\"", rest),
+                ))
+            }
+        }
+        Err(e) => Result::Err(Error::new(ErrorKind::Unknown, e.to_string())),
+    }
+}
+
+// An external parsed entity may begin with a text declaration, which is not part of its replacement text
+fn strip_textdecl(s: String) -> String {
+    if s.starts_with("<?xml") {
+        s.find("?>")
+            .map_or(s.clone(), |i| String::from(&s[i + 2..]))
+    } else {
+        s
+    }
+}
+
+/// Parse a document, recovering from well-formedness errors.
+///
+/// Rather than stopping at the first error, each error is recorded and the parser attempts to continue: unclosed elements are closed automatically, end tags that don't match an open element are ignored, malformed markup and characters that are not allowed in XML are skipped, duplicate attributes are dropped, and references to undeclared entities are kept as literal text. Only the first top-level element is kept.
+///
+/// The result is a best-effort document, along with the list of errors. The message of each error gives the line and column where the problem was found. If the list is empty then the document is well-formed.
+///
+///```rust
+///use xrust::parsexml::parse_recover;
+///let (doc, errors) = parse_recover("<a><b>text</a>");
+///assert_eq!(doc.content.len(), 1);
+///assert_eq!(errors.len(), 1)
+///```
+pub fn parse_recover(s: &str) -> (XMLDocument, Vec<Error>) {
+    let mut r = Recovery {
+        src: s,
+        errors: vec![],
+        open: vec![],
+        top: vec![],
+        top_pos: 0,
+    };
+    let mut input = s;
+    let mut xmldecl_opt = None;
+    if let Ok((rest, x)) = xmldecl(input) {
+        xmldecl_opt = Some(x);
+        input = rest;
+    }
+    let mut prologue = vec![];
+    let mut doctype = None;
+    while !input.is_empty() {
+        if r.open.is_empty() && input.starts_with("<!DOCTYPE") {
+            match doctypedecl(input) {
+                Ok((rest, (n, mut d))) => {
+                    doctype = Some(n);
+                    prologue.append(&mut d);
+                    input = rest;
+                }
+                Err(_) => {
+                    r.error(input, "malformed document type declaration");
+                    input = skip_past(input, ">");
+                }
+            }
+            continue;
+        }
+        input = r.markup(input);
+    }
+    while let Some((n, _, _)) = r.open.last() {
+        let msg = format!("element \"{}\" is not closed", n.to_string());
+        r.error(input, msg.as_str());
+        r.close();
+    }
+
+    // Divide the top-level nodes into the prologue, document element and epilogue.
+    let mut content = vec![];
+    let mut epilogue = vec![];
+    for (n, pos) in r.top {
+        match n {
+            XMLNode::Element(..) => {
+                if content.is_empty() {
+                    content.push(n)
+                } else {
+                    r.errors
+                        .push(position_error(s, pos, "more than one document element"))
+                }
+            }
+            XMLNode::Text(v) => {
+                if !v.to_string().trim().is_empty() {
+                    r.errors.push(position_error(
+                        s,
+                        pos,
+                        "text is not allowed outside the document element",
+                    ))
+                }
+            }
+            XMLNode::Reference(_) => r.errors.push(position_error(
+                s,
+                pos,
+                "reference is not allowed outside the document element",
+            )),
+            _ => {
+                if content.is_empty() {
+                    prologue.push(n)
+                } else {
+                    epilogue.push(n)
+                }
+            }
+        }
+    }
+    if content.is_empty() {
+        r.errors
+            .push(position_error(s, s.len(), "no document element"))
+    }
+    let mut doc = XMLDocument {
+        prologue,
+        content,
+        epilogue,
+        xmldecl: xmldecl_opt,
+        doctype,
+    };
+    if let Err(e) = doc.expand_with_config(
+        None,
+        &NoFetchResolver,
+        &ParserConfig::new()
+            .unknown_entity(UnknownEntity::Literal)
+            .dtd_policy(DtdPolicy::Ignore),
+    ) {
+        r.errors.push(e);
+        doc.content = literal_references(doc.content)
+    }
+    (doc, r.errors)
+}
+
+// Replace entity references by their literal text
+fn literal_references(v: Vec<XMLNode>) -> Vec<XMLNode> {
+    v.into_iter()
+        .map(|n| match n {
+            XMLNode::Element(m, a, c) => {
+                XMLNode::Element(m, literal_references(a), literal_references(c))
+            }
+            XMLNode::UnexpandedAttribute(m, v) => XMLNode::Attribute(m, literal_value(&v)),
+            XMLNode::Reference(m) => XMLNode::Text(Value::from(format!("&{};", m.to_string()))),
+            _ => n,
+        })
+        .collect()
+}
+
+// The state of a recovering parse
+struct Recovery<'a> {
+    src: &'a str,
+    errors: Vec<Error>,
+    // Open elements: name, attributes, content
+    open: Vec<(QualifiedName, Vec<XMLNode>, Vec<XMLNode>)>,
+    // Top-level nodes, with their offset in the source
+    top: Vec<(XMLNode, usize)>,
+    // The offset of the open top-level element
+    top_pos: usize,
+}
+
+impl<'a> Recovery<'a> {
+    fn error(&mut self, input: &str, msg: &str) {
+        self.errors
+            .push(position_error(self.src, self.src.len() - input.len(), msg))
+    }
+    fn push(&mut self, input: &str, n: XMLNode) {
+        // Adjacent text is merged
+        let last = match self.open.last_mut() {
+            Some((_, _, c)) => c.last_mut(),
+            None => self.top.last_mut().map(|(l, _)| l),
+        };
+        if let (Some(XMLNode::Text(t)), XMLNode::Text(u)) = (last, &n) {
+            *t = Value::from(t.to_string() + u.to_string().as_str());
+            return;
+        }
+        match self.open.last_mut() {
+            Some((_, _, c)) => c.push(n),
+            None => {
+                let pos = self.src.len() - input.len();
+                self.top.push((n, pos))
+            }
+        }
+    }
+    fn close(&mut self) {
+        if let Some((n, a, c)) = self.open.pop() {
+            let e = XMLNode::Element(n, a, c);
+            match self.open.last_mut() {
+                Some((_, _, p)) => p.push(e),
+                None => self.top.push((e, self.top_pos)),
+            }
+        }
+    }
+    // Parse one item of markup or character data, returning the remaining input
+    fn markup(&mut self, input: &'a str) -> &'a str {
+        if input.starts_with("</") {
+            self.end_tag(input)
+        } else if input.starts_with("<!--") {
+            match comment(input) {
+                Ok((rest, c)) => {
+                    self.push(input, c);
+                    rest
+                }
+                Err(_) => {
+                    self.error(input, "malformed comment");
+                    skip_past(input, "-->")
+                }
+            }
+        } else if input.starts_with("<![CDATA[") {
+            match chardata_cdata(input) {
+                Ok((rest, t)) => {
+                    self.push(input, XMLNode::Text(Value::from(t)));
+                    rest
+                }
+                Err(_) => {
+                    self.error(input, "CDATA section is not closed");
+                    ""
+                }
+            }
+        } else if input.starts_with("<?") {
+            match processing_instruction(input) {
+                Ok((rest, p)) => {
+                    self.push(input, p);
+                    rest
+                }
+                Err(_) => {
+                    self.error(input, "malformed processing instruction");
+                    skip_past(input, "?>")
+                }
+            }
+        } else if input.starts_with("<!") {
+            self.error(input, "unexpected markup declaration");
+            skip_past(input, ">")
+        } else if input.starts_with('<') {
+            self.start_tag(input)
+        } else if let Some(after) = input.strip_prefix('&') {
+            if let Ok((rest, t)) = chardata_escapes(input) {
+                self.push(input, XMLNode::Text(Value::from(t)));
+                rest
+            } else if let Ok((rest, r)) = entityref(input) {
+                self.push(input, r);
+                rest
+            } else {
+                self.error(input, "malformed reference");
+                self.push(input, XMLNode::Text(Value::from("&")));
+                after
+            }
+        } else {
+            let end = input.find(['<', '&']).unwrap_or(input.len());
+            let mut t = String::new();
+            for (i, c) in input[..end].char_indices() {
+                if is_char(&c) {
+                    t.push(c)
+                } else {
+                    self.error(&input[i..], "character is not allowed in XML")
+                }
+            }
+            if t.contains("]]>") {
+                self.error(input, "\"]]>\" is not allowed in character data")
+            }
+            self.push(input, XMLNode::Text(Value::from(t)));
+            &input[end..]
+        }
+    }
+    fn start_tag(&mut self, input: &'a str) -> &'a str {
+        let (mut rest, n) = match qualname(&input[1..]) {
+            Ok(r) => r,
+            Err(_) => {
+                self.error(input, "\"<\" is not the start of markup");
+                self.push(input, XMLNode::Text(Value::from("<")));
+                return &input[1..];
+            }
+        };
+        let mut attrs: Vec<XMLNode> = vec![];
+        while let Ok((r, a)) = attribute(rest) {
+            if let Some(an) = attribute_name(&a) {
+                if attrs
+                    .iter()
+                    .any(|b| attribute_name(b).map(|bn| bn.to_string()) == Some(an.to_string()))
+                {
+                    let msg = format!("duplicate attribute \"{}\"", an.to_string());
+                    self.error(rest, msg.as_str());
+                } else {
+                    attrs.push(a)
+                }
+            }
+            rest = r;
+        }
+        let rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        let (rest, empty) = if let Some(r) = rest.strip_prefix("/>") {
+            (r, true)
+        } else if let Some(r) = rest.strip_prefix('>') {
+            (r, false)
+        } else {
+            let msg = format!("malformed start tag for element \"{}\"", n.to_string());
+            self.error(rest, msg.as_str());
+            match rest.find('>') {
+                Some(i) => (&rest[i + 1..], rest[..i].ends_with('/')),
+                None => ("", true),
+            }
+        };
+        if empty {
+            self.push(input, XMLNode::Element(n, attrs, vec![]))
+        } else {
+            if self.open.is_empty() {
+                self.top_pos = self.src.len() - input.len()
+            }
+            self.open.push((n, attrs, vec![]))
+        }
+        rest
+    }
+    fn end_tag(&mut self, input: &'a str) -> &'a str {
+        let (rest, n) = match qualname(&input[2..]) {
+            Ok((r, n)) => (r, n),
+            Err(_) => {
+                self.error(input, "malformed end tag");
+                return skip_past(input, ">");
+            }
+        };
+        let rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        let rest = match rest.strip_prefix('>') {
+            Some(r) => r,
+            None => {
+                self.error(rest, "malformed end tag");
+                skip_past(rest, ">")
+            }
+        };
+        match self
+            .open
+            .iter()
+            .rposition(|(o, _, _)| o.to_string() == n.to_string())
+        {
+            Some(i) => {
+                // Any elements that are open inside the matching element are closed
+                while self.open.len() > i + 1 {
+                    let msg = format!(
+                        "element \"{}\" is not closed",
+                        self.open.last().unwrap().0.to_string()
+                    );
+                    self.error(input, msg.as_str());
+                    self.close()
+                }
+                self.close()
+            }
+            None => {
+                let msg = format!(
+                    "end tag \"{}\" does not match an open element",
+                    n.to_string()
+                );
+                self.error(input, msg.as_str())
+            }
+        }
+        rest
+    }
+}
+
+// Skip the input up to and including the given string, or to the end of the input if it does not occur
+fn skip_past<'a>(input: &'a str, s: &str) -> &'a str {
+    let first = input.chars().next().map_or(0, |c| c.len_utf8());
+    match input[first..].find(s) {
+        Some(i) => &input[first + i + s.len()..],
+        None => "",
+    }
+}
+
+// A unit of markup or character data, for incremental parsing
+pub(crate) enum Token {
+    XMLDecl,
+    Doctype,
+    // Element name, attributes, and whether it is an empty element tag
+    StartTag(QualifiedName, Vec<XMLNode>, bool),
+    EndTag(QualifiedName),
+    Text(String),
+    Reference(QualifiedName),
+    Comment(String),
+    PI(String, String),
+}
+
+// Parse the next token from the input. The source is used to report the position of an error.
+pub(crate) fn next_token<'a>(src: &str, input: &'a str) -> Result<(&'a str, Token), Error> {
+    let result = if input.starts_with("<?xml") && input.len() == src.len() {
+        map(xmldecl, |_| Token::XMLDecl)(input)
+    } else if input.starts_with("<!DOCTYPE") {
+        map(doctypedecl, |_| Token::Doctype)(input)
+    } else if input.starts_with("<!--") {
+        map(comment, |c| match c {
+            XMLNode::Comment(v) => Token::Comment(v.to_string()),
+            _ => Token::Comment(String::new()),
+        })(input)
+    } else if input.starts_with("<?") {
+        map(processing_instruction, |p| match p {
+            XMLNode::PI(t, v) => Token::PI(t, v.to_string()),
+            _ => Token::PI(String::new(), String::new()),
+        })(input)
+    } else if input.starts_with("</") {
+        map(
+            tuple((tag("</"), qualname, multispace0, tag(">"))),
+            |(_, n, _, _)| Token::EndTag(n),
+        )(input)
+    } else if input.starts_with("<") && !input.starts_with("<![CDATA[") {
+        map(
+            tuple((
+                tag("<"),
+                qualname,
+                attributes,
+                multispace0,
+                alt((tag("/>"), tag(">"))),
+            )),
+            |(_, n, a, _, e)| Token::StartTag(n, a, e == "/>"),
+        )(input)
+    } else {
+        alt((
+            map(chardata, Token::Text),
+            map(entityref, |r| match r {
+                XMLNode::Reference(n) => Token::Reference(n),
+                _ => Token::Text(String::new()),
+            }),
+        ))(input)
+    };
+    result.map_err(|_| position_error(src, src.len() - input.len(), "syntax error"))
+}
+
+// Whether the token at the start of the input may not have been parsed because the input ends before the end of the token.
+// If the end of the token is in the input then the error is genuine, and there is no need to read more of the document.
+fn may_be_incomplete(input: &str) -> bool {
+    if ["<?xml", "<!DOCTYPE", "<!--", "<![CDATA["]
+        .iter()
+        .any(|m| m.len() > input.len() && m.starts_with(input))
+    {
+        return true;
+    }
+    if input.starts_with("<!DOCTYPE") {
+        !doctype_ends(input)
+    } else if input.starts_with("<!--") {
+        !input.contains("-->")
+    } else if input.starts_with("<![CDATA[") {
+        !input.contains("]]>")
+    } else if input.starts_with("<?") {
+        !input.contains("?>")
+    } else if input.starts_with('<') {
+        // '>' may occur in attribute values
+        let mut quote = None;
+        !input.chars().any(|c| match (quote, c) {
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                false
+            }
+            (Some(q), _) if q == c => {
+                quote = None;
+                false
+            }
+            (None, '>') => true,
+            _ => false,
+        })
+    } else if let Some(r) = input.strip_prefix('&') {
+        // The input ends within the reference
+        r.chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '#' | '_' | '-' | '.' | ':'))
+    } else {
+        false
+    }
+}
+// Whether the input contains the '>' that ends the document type declaration at its start, skipping literals, comments and processing instructions in the internal subset.
+fn doctype_ends(input: &str) -> bool {
+    let mut depth = 0;
+    let mut i = 0;
+    while let Some(c) = input[i..].chars().next() {
+        let rest = &input[i..];
+        let skip = if rest.starts_with("<!--") {
+            rest.find("-->").map(|e| e + 3)
+        } else if rest.starts_with("<?") {
+            rest.find("?>").map(|e| e + 2)
+        } else if c == '"' || c == '\'' {
+            rest[1..].find(c).map(|e| e + 2)
+        } else {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '>' if depth <= 0 => return true,
+                _ => {}
+            }
+            Some(c.len_utf8())
+        };
+        match skip {
+            Some(n) => i += n,
+            None => return false,
+        }
+    }
+    false
+}
+
+// An error at the given offset in the source, with the line and column
+pub(crate) fn position_error(src: &str, offset: usize, msg: &str) -> Error {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    Error::new(
+        ErrorKind::Unknown,
+        format!("line {}, column {}: {}", line, column, msg),
+    )
+}
+
+/// An event reported by a [Reader].
+#[derive(Clone, PartialEq)]
+pub enum XmlEvent {
+    /// The start of an element, with its attributes. An empty-element tag is reported as a StartElement followed by an EndElement.
+    StartElement(QualifiedName, Vec<(QualifiedName, Value)>),
+    EndElement(QualifiedName),
+    /// Character data. Character references, the predefined entities and CDATA sections are replaced by their text. A run of text may be reported as more than one event.
+    Text(String),
+    /// A reference to a general entity, which is not expanded.
+    EntityReference(QualifiedName),
+    Comment(String),
+    /// The target and data of a processing instruction.
+    ProcessingInstruction(String, String),
+}
+
+// The number of bytes that a Reader asks its source for at a time
+const READ_SIZE: usize = 64 * 1024;
+
+/// A pull parser, that reads a document from a byte stream and returns its content as a sequence of [XmlEvent]s.
+///
+/// Unlike parsing an [XMLDocument], only the markup or text that is being parsed is held in memory, so documents that are too large to be held in memory can be processed. The document is checked for well-formedness as it is read, and the first error ends the events. Names are not namespace-resolved, and the document type declaration is skipped.
+///
+/// ```rust
+/// use xrust::parsexml::{Reader, XmlEvent};
+///
+/// let doc = "<feed><entry>one</entry><entry>two</entry></feed>";
+/// let mut entries = 0;
+/// for e in Reader::new(doc.as_bytes()) {
+///     match e.expect("unable to parse document") {
+///         XmlEvent::StartElement(n, _) if n.get_localname() == "entry" => entries += 1,
+///         _ => {}
+///     }
+/// }
+/// assert_eq!(entries, 2)
+/// ```
+pub struct Reader<R: std::io::Read> {
+    source: R,
+    eof: bool,
+    // The input that has been decoded. The part that has not yet been parsed starts at pos.
+    buf: String,
+    pos: usize,
+    // Bytes read from the source that do not yet form a complete character
+    pending: Vec<u8>,
+    // The line and column of the start of buf
+    line: usize,
+    column: usize,
+    // The names of the elements that are open, outermost first
+    open: Vec<QualifiedName>,
+    seen_element: bool,
+    // The end of an empty element, which is the next event
+    end: Option<QualifiedName>,
+    failed: bool,
+}
+
+impl<R: std::io::Read> Reader<R> {
+    pub fn new(source: R) -> Self {
+        Reader {
+            source,
+            eof: false,
+            buf: String::new(),
+            pos: 0,
+            pending: vec![],
+            line: 1,
+            column: 1,
+            open: vec![],
+            seen_element: false,
+            end: None,
+            failed: false,
+        }
+    }
+    /// The next event in the document, or None at the end of the document.
+    pub fn next_event(&mut self) -> Result<Option<XmlEvent>, Error> {
+        if let Some(n) = self.end.take() {
+            return Ok(Some(XmlEvent::EndElement(n)));
+        }
+        loop {
+            if self.pos < self.buf.len() {
+                let input = &self.buf[self.pos..];
+                match next_token(&self.buf, input) {
+                    // Text at the end of the input is reported, and continues in the next event, unless it may be the start of a "]]>" that is not allowed
+                    Ok(("", Token::Text(_))) if !self.eof && input.ends_with(']') => {}
+                    Ok((rest, t)) => {
+                        let offset = self.pos;
+                        self.pos = self.buf.len() - rest.len();
+                        if let Some(e) = self.event(t, offset)? {
+                            return Ok(Some(e));
+                        }
+                        continue;
+                    }
+                    // The markup may be incomplete, in which case more input is read
+                    Err(_) if !self.eof && may_be_incomplete(input) => {}
+                    Err(_) => return Result::Err(self.error(self.pos, "syntax error")),
+                }
+            } else if self.eof {
+                return if let Some(n) = self.open.last() {
+                    Result::Err(Error::new(
+                        ErrorKind::Unknown,
+                        format!("element \"{}\" is not closed", n.to_string()),
+                    ))
+                } else if !self.seen_element {
+                    Result::Err(Error::new(
+                        ErrorKind::Unknown,
+                        String::from("no document element"),
+                    ))
+                } else {
+                    Ok(None)
+                };
+            }
+            self.fill()?
+        }
+    }
+    // Read more of the source, discarding the input that has been parsed
+    fn fill(&mut self) -> Result<(), Error> {
+        // The last character that has been parsed is kept, so that the rest of the input is not mistaken for the start of the document
+        let k = self.buf[..self.pos]
+            .char_indices()
+            .last()
+            .map_or(0, |(i, _)| i);
+        for c in self.buf[..k].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1
+            } else {
+                self.column += 1
+            }
+        }
+        self.buf.drain(..k);
+        self.pos -= k;
+
+        let mut chunk = vec![0; READ_SIZE];
+        let n = self.source.read(&mut chunk).map_err(|e| {
+            Error::new(
+                ErrorKind::Unknown,
+                format!("unable to read document: {}", e),
+            )
+        })?;
+        if n == 0 {
+            self.eof = true;
+            if !self.pending.is_empty() {
+                return Result::Err(self.error(self.buf.len(), "document is not valid UTF-8"));
+            }
+            return Ok(());
+        }
+        self.pending.extend_from_slice(&chunk[..n]);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(s) => s.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => {
+                return Result::Err(self.error(self.buf.len(), "document is not valid UTF-8"))
+            }
+        };
+        let rest = self.pending.split_off(valid);
+        self.buf
+            .push_str(std::str::from_utf8(&self.pending).unwrap_or_default());
+        self.pending = rest;
+        Ok(())
+    }
+    // The event for a token, if it has one. The token starts at the given offset in the input.
+    fn event(&mut self, t: Token, offset: usize) -> Result<Option<XmlEvent>, Error> {
+        let error = |r: &Self, msg: &str| Result::Err(r.error(offset, msg));
+        Ok(match t {
+            Token::XMLDecl => None,
+            Token::Doctype => {
+                if self.seen_element {
+                    return error(
+                        self,
+                        "document type declaration must come before the document element",
+                    );
+                }
+                None
+            }
+            Token::StartTag(n, a, empty) => {
+                if self.seen_element && self.open.is_empty() {
+                    return error(self, "more than one document element");
+                }
+                self.seen_element = true;
+                if empty {
+                    self.end = Some(n.clone())
+                } else {
+                    self.open.push(n.clone())
+                }
+                let attributes = a
+                    .into_iter()
+                    .filter_map(|b| match b {
+                        XMLNode::Attribute(an, av) => Some((an, av)),
+                        XMLNode::UnexpandedAttribute(an, av) => Some((an, literal_value(&av))),
+                        _ => None,
+                    })
+                    .collect();
+                Some(XmlEvent::StartElement(n, attributes))
+            }
+            Token::EndTag(n) => match self.open.pop() {
+                Some(o) if o.to_string() == n.to_string() => Some(XmlEvent::EndElement(o)),
+                _ => return error(self, "end tag does not match start tag"),
+            },
+            Token::Text(t) => {
+                if !self.open.is_empty() {
+                    Some(XmlEvent::Text(t))
+                } else if t.chars().all(|c| c.is_ascii_whitespace()) {
+                    None
+                } else {
+                    return error(self, "text is not allowed outside the document element");
+                }
+            }
+            Token::Reference(n) => {
+                if self.open.is_empty() {
+                    return error(
+                        self,
+                        "reference is not allowed outside the document element",
+                    );
+                }
+                Some(XmlEvent::EntityReference(n))
+            }
+            Token::Comment(c) => Some(XmlEvent::Comment(c)),
+            Token::PI(t, d) => Some(XmlEvent::ProcessingInstruction(t, d)),
+        })
+    }
+    // An error at the given offset in the input, with the line and column in the document
+    fn error(&self, offset: usize, msg: &str) -> Error {
+        let (mut line, mut column) = (self.line, self.column);
+        for c in self.buf[..offset].chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1
+            } else {
+                column += 1
+            }
+        }
+        Error::new(
+            ErrorKind::Unknown,
+            format!("line {}, column {}: {}", line, column, msg),
+        )
+    }
+}
+
+impl<R: std::io::Read> Iterator for Reader<R> {
+    type Item = Result<XmlEvent, Error>;
+    /// The next event, or an error. There are no more events after an error.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let r = self.next_event().transpose();
+        self.failed = matches!(r, Some(Err(_)));
+        r
+    }
+}
+
+// The maximum number of references to general entities that are expanded in the content of a document, to guard against entities that expand to a very large amount of content
+const MAX_ENTITY_REFERENCES: usize = 100_000;
+
+// The state of the expansion of general entities in content
+struct Expansion<'a> {
+    // Fetches external entities
+    resolver: &'a dyn Resolver,
+    // The replacement content of the external entities that have been referenced
+    fetched: HashMap<QualifiedName, Vec<XMLNode>>,
+    // The entities that are being expanded, outermost first
+    open: Vec<QualifiedName>,
+    // The number of references that have been expanded
+    references: usize,
+}
+
+impl<'a> Expansion<'a> {
+    fn new(resolver: &'a dyn Resolver) -> Expansion<'a> {
+        Expansion {
+            resolver,
+            fetched: HashMap::new(),
+            open: vec![],
+            references: 0,
+        }
+    }
+}
+
+fn expand_node(
+    n: &XMLNode,
+    ent: &Entities,
+    preserve: bool,
+    config: &ParserConfig,
+    st: &mut Expansion<'_>,
+) -> Result<Vec<XMLNode>, Error> {
     match n {
         XMLNode::Reference(qn) => {
-            ent.get(&qn).map_or(vec![], |x| x.clone()) // TODO: an undefined entity name is an error
+            let x = match ent.content.get(qn) {
+                // The replacement content may itself contain references, and elements with default attributes
+                Some(rep) => {
+                    if st.open.contains(qn) {
+                        return Result::Err(Error::new(
+                            ErrorKind::Unknown,
+                            format!(
+                                "WFC: No Recursion: general entity \"{}\" refers to itself",
+                                qn.to_string()
+                            ),
+                        ));
+                    }
+                    st.references += 1;
+                    if st.references > MAX_ENTITY_REFERENCES {
+                        return Result::Err(Error::new(
+                            ErrorKind::Unknown,
+                            String::from("too many references to general entities"),
+                        ));
+                    }
+                    let x = match rep {
+                        Replacement::Content(x) => x.clone(),
+                        Replacement::External(b, sysid) => match st.fetched.get(qn) {
+                            Some(x) => x.clone(),
+                            None => {
+                                let x = external_entity(qn, b.as_ref(), sysid, st.resolver)?;
+                                st.fetched.insert(qn.clone(), x.clone());
+                                x
+                            }
+                        },
+                    };
+                    st.open.push(qn.clone());
+                    let mut r = vec![];
+                    for c in &x {
+                        r.append(&mut expand_node(c, ent, preserve, config, st)?)
+                    }
+                    st.open.pop();
+                    r
+                }
+                None => vec![XMLNode::Text(Value::from(undeclared_entity(
+                    qn, ent, config,
+                )?))],
+            };
+            if preserve {
+                Ok(vec![XMLNode::EntityReference(qn.clone(), x)])
+            } else {
+                Ok(x)
+            }
         }
         XMLNode::Element(qn, attr, content) => {
             let mut attrs: Vec<XMLNode> = vec![];
             for a in attr {
-                let mut b = expand_node(a, ent);
+                let mut b = expand_node(a, ent, preserve, config, st)?;
                 attrs.append(&mut b);
             }
+            for (n, t, d) in ent.attlists.get(qn).map_or(&[][..], |v| v.as_slice()) {
+                match attrs.iter_mut().find(|a| attribute_name(a) == Some(n)) {
+                    Some(XMLNode::Attribute(_, v)) => {
+                        if *t != AttributeType::CData {
+                            *v = Value::from(normalize_attribute(t, v.to_string()))
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        if let AttributeDefault::Default(v) | AttributeDefault::Fixed(v) = d {
+                            let v = expand_attribute(v, ent, config, &mut vec![])?;
+                            attrs.push(XMLNode::Attribute(
+                                n.clone(),
+                                Value::from(normalize_attribute(t, v)),
+                            ))
+                        }
+                    }
+                }
+            }
             let mut newcontent: Vec<XMLNode> = vec![];
             for c in content {
-                let mut d = expand_node(c, ent);
+                let mut d = expand_node(c, ent, preserve, config, st)?;
                 newcontent.append(&mut d);
             }
-            vec![XMLNode::Element(qn.clone(), attrs, newcontent)]
+            Ok(vec![XMLNode::Element(qn.clone(), attrs, newcontent)])
         }
-        XMLNode::Attribute(qn, v) => {
-            // TODO: expand attribute value
-            vec![XMLNode::Attribute(qn.clone(), v.clone())]
+        XMLNode::Attribute(qn, v) => Ok(vec![XMLNode::Attribute(qn.clone(), v.clone())]),
+        XMLNode::UnexpandedAttribute(qn, v) => Ok(vec![XMLNode::Attribute(
+            qn.clone(),
+            Value::from(expand_attribute(v, ent, config, &mut vec![])?),
+        )]),
+        XMLNode::Text(t) => Ok(vec![XMLNode::Text(t.clone())]),
+        XMLNode::EntityReference(qn, content) => {
+            Ok(vec![XMLNode::EntityReference(qn.clone(), content.clone())])
         }
-        XMLNode::Text(t) => {
-            vec![XMLNode::Text(t.clone())]
+        XMLNode::PI(_, _) | XMLNode::Comment(_) => Ok(vec![n.clone()]),
+        XMLNode::DTD(_) => Ok(vec![]),
+    }
+}
+
+// The text of a reference to an undeclared general entity, as determined by the configuration
+fn undeclared_entity(
+    qn: &QualifiedName,
+    ent: &Entities,
+    config: &ParserConfig,
+) -> Result<String, Error> {
+    if ent.external.contains(qn) {
+        return Result::Err(Error::new(
+            ErrorKind::Unknown,
+            format!("WFC: Entity Declared: general entity \"{}\" is declared in the external subset, but the document is standalone", qn.to_string()),
+        ));
+    }
+    match &config.unknown_entity {
+        UnknownEntity::Error => Result::Err(Error::new(
+            ErrorKind::Unknown,
+            format!("general entity \"{}\" is not declared", qn.to_string()),
+        )),
+        UnknownEntity::Literal => Ok(format!("&{};", qn.to_string())),
+        UnknownEntity::Callback(cb) => cb(qn.to_string().as_str()).ok_or_else(|| {
+            Error::new(
+                ErrorKind::Unknown,
+                format!(
+                    "no replacement text for general entity \"{}\"",
+                    qn.to_string()
+                ),
+            )
+        }),
+    }
+}
+
+// Replace the general entity references in an attribute value by their replacement text, which is itself processed as part of the attribute value.
+// The well-formedness constraints are that the replacement text must not contain "<", and that external entities must not be referenced. The open entities are those whose replacement text is being expanded, to detect recursion.
+fn expand_attribute(
+    v: &[XMLNode],
+    ent: &Entities,
+    config: &ParserConfig,
+    open: &mut Vec<QualifiedName>,
+) -> Result<String, Error> {
+    let mut r = String::new();
+    for n in v {
+        match n {
+            XMLNode::Text(t) => r.push_str(t.to_string().as_str()),
+            XMLNode::Reference(qn) => match ent.attribute.get(qn) {
+                Some(Some(t)) => {
+                    if open.contains(qn) {
+                        return Result::Err(Error::new(
+                            ErrorKind::Unknown,
+                            format!("general entity \"{}\" refers to itself", qn.to_string()),
+                        ));
+                    }
+                    let c = match attvalue_content("<&", false)(t.as_str()) {
+                        Ok(("", c)) => c,
+                        Ok((rest, _)) if rest.starts_with('<') => {
+                            return Result::Err(Error::new(
+                                ErrorKind::Unknown,
+                                format!("general entity \"{}\" cannot be referenced in an attribute value, since its replacement text contains \"<\"", qn.to_string()),
+                            ))
+                        }
+                        _ => {
+                            return Result::Err(Error::new(
+                                ErrorKind::Unknown,
+                                format!("unable to parse general entity \"{}\" in an attribute value", qn.to_string()),
+                            ))
+                        }
+                    };
+                    open.push(qn.clone());
+                    r.push_str(expand_attribute(&c, ent, config, open)?.as_str());
+                    open.pop();
+                }
+                Some(None) => {
+                    return Result::Err(Error::new(
+                        ErrorKind::Unknown,
+                        format!(
+                            "external entity \"{}\" cannot be referenced in an attribute value",
+                            qn.to_string()
+                        ),
+                    ))
+                }
+                None => r.push_str(undeclared_entity(qn, ent, config)?.as_str()),
+            },
+            _ => {}
         }
-        XMLNode::PI(_, _) | XMLNode::Comment(_) | XMLNode::DTD(_) => vec![], // TODO
+    }
+    Ok(r)
+}
+
+// Attribute-value normalisation for a declared type other than CDATA: discard leading and trailing spaces, and replace each sequence of spaces by a single space.
+// Whitespace characters have already been replaced by spaces, unless they came from character references.
+fn normalize_attribute(t: &AttributeType, v: String) -> String {
+    if *t == AttributeType::CData {
+        v
+    } else {
+        v.split(' ')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// The value of an attribute whose general entity references have not been expanded, with each reference as literal text
+pub(crate) fn literal_value(v: &[XMLNode]) -> Value {
+    Value::from(
+        v.iter()
+            .map(|n| match n {
+                XMLNode::Reference(m) => format!("&{};", m.to_string()),
+                XMLNode::Text(t) => t.to_string(),
+                _ => String::new(),
+            })
+            .collect::<String>(),
+    )
+}
+
+/// Supplies the replacement text for an undeclared general entity, given its name.
+pub type EntityCallback = Rc<dyn Fn(&str) -> Option<String>>;
+
+/// The pseudo-attributes of an xml-stylesheet processing instruction. See [Associating Style Sheets with XML documents](https://www.w3.org/TR/xml-stylesheet/).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct XmlStylesheet {
+    pub href: String,
+    /// The "type" pseudo-attribute
+    pub media_type: Option<String>,
+    pub title: Option<String>,
+    pub media: Option<String>,
+    pub charset: Option<String>,
+    pub alternate: bool,
+}
+
+impl XmlStylesheet {
+    /// Parse the content of an xml-stylesheet processing instruction. The href pseudo-attribute is required.
+    pub fn parse(data: &str) -> Result<XmlStylesheet, Error> {
+        let mut result = XmlStylesheet::default();
+        let mut href = None;
+        for (n, v) in pseudo_attributes(data)? {
+            match n.as_str() {
+                "href" => href = Some(v),
+                "type" => result.media_type = Some(v),
+                "title" => result.title = Some(v),
+                "media" => result.media = Some(v),
+                "charset" => result.charset = Some(v),
+                "alternate" => match v.as_str() {
+                    "yes" => result.alternate = true,
+                    "no" => result.alternate = false,
+                    _ => {
+                        return Result::Err(Error::new(
+                            ErrorKind::Unknown,
+                            format!("invalid value \"{}\" for alternate pseudo-attribute", v),
+                        ))
+                    }
+                },
+                _ => {} // Unknown pseudo-attributes are ignored
+            }
+        }
+        result.href = href.ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("xml-stylesheet processing instruction must have an href"),
+        ))?;
+        Ok(result)
+    }
+    /// Is this a stylesheet that an XSLT processor can use?
+    pub fn is_xslt(&self) -> bool {
+        matches!(
+            self.media_type.as_deref(),
+            Some("text/xsl" | "application/xslt+xml" | "text/xml" | "application/xml")
+        )
+    }
+}
+
+/// Parse the content of a processing instruction as a sequence of pseudo-attributes, i.e. name="value" pairs.
+/// Character references and the predefined entities are expanded in the values.
+pub fn pseudo_attributes(data: &str) -> Result<Vec<(String, String)>, Error> {
+    match tuple((
+        many0(map(
+            tuple((
+                multispace0,
+                name,
+                multispace0,
+                char('='),
+                multispace0,
+                alt((
+                    delimited(char('"'), many0(pseudo_attribute_char('"')), char('"')),
+                    delimited(char('\''), many0(pseudo_attribute_char('\'')), char('\'')),
+                )),
+            )),
+            |(_, n, _, _, _, v)| (n.to_string(), v.concat()),
+        )),
+        multispace0,
+    ))(data)
+    {
+        Ok(("", (v, _))) => Ok(v),
+        _ => Result::Err(Error::new(
+            ErrorKind::Unknown,
+            format!("unable to parse pseudo-attributes \"{}\"", data),
+        )),
+    }
+}
+fn pseudo_attribute_char<'a>(delim: char) -> impl FnMut(&'a str) -> IResult<&'a str, String> {
+    move |input| {
+        alt((
+            chardata_escapes,
+            map(verify(none_of("<&"), |c: &char| *c != delim), |c| {
+                c.to_string()
+            }),
+        ))(input)
+    }
+}
+
+/// What to do when a reference to an undeclared general entity is found.
+#[derive(Clone, Default)]
+pub enum UnknownEntity {
+    /// The document is not well-formed. This is the default.
+    #[default]
+    Error,
+    /// Keep the reference as literal text, i.e. "&name;".
+    Literal,
+    /// Call the function with the name of the entity to supply the replacement text. If the function returns None then it is an error.
+    Callback(EntityCallback),
+}
+
+/// How the document type declaration is processed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DtdPolicy {
+    /// A document that has a document type declaration is an error. This is the safest choice for untrusted input.
+    Forbid,
+    /// The internal subset is processed, but external resources are not fetched: the external subset is skipped, and a reference to an external entity is treated as a reference to an undeclared entity. This is the default.
+    #[default]
+    Ignore,
+    /// The internal subset is processed, and the external subset and external entities are fetched using the resolver.
+    Process,
+}
+
+/// Configuration options for processing a parsed document.
+#[derive(Clone)]
+pub struct ParserConfig {
+    pub unknown_entity: UnknownEntity,
+    /// Whether XML Namespaces 1.1 is used, which allows a prefix to be undeclared, as in xmlns:p="". The default is false.
+    pub namespaces11: bool,
+    /// How the document type declaration is processed. The default is [DtdPolicy::Ignore], so that external resources are only fetched when this is [DtdPolicy::Process].
+    pub dtd: DtdPolicy,
+    /// Whether the namespace declarations of each element are kept, so that they can be found with [Node::namespace_declarations](../forest/struct.Node.html#method.namespace_declarations). The default is true, since they are needed to resolve the prefixes of QNames in content, such as XPath expressions in a stylesheet.
+    pub namespace_declarations: bool,
+    /// Whether an element or attribute name may have a prefix that is not declared. If so, the name has no namespace URI; otherwise it is an error (NSC: Prefix Declared). The default is false.
+    pub undeclared_prefixes: bool,
+}
+
+impl ParserConfig {
+    pub fn new() -> ParserConfig {
+        ParserConfig {
+            unknown_entity: UnknownEntity::Error,
+            namespaces11: false,
+            dtd: DtdPolicy::Ignore,
+            namespace_declarations: true,
+            undeclared_prefixes: false,
+        }
+    }
+    /// Set whether XML Namespaces 1.1 is used.
+    pub fn namespaces11(mut self, b: bool) -> ParserConfig {
+        self.namespaces11 = b;
+        self
+    }
+    /// Set whether the namespace declarations of each element are kept.
+    pub fn namespace_declarations(mut self, b: bool) -> ParserConfig {
+        self.namespace_declarations = b;
+        self
+    }
+    /// Set whether names may have prefixes that are not declared.
+    pub fn undeclared_prefixes(mut self, b: bool) -> ParserConfig {
+        self.undeclared_prefixes = b;
+        self
+    }
+    /// Set how references to undeclared general entities are handled.
+    pub fn unknown_entity(mut self, u: UnknownEntity) -> ParserConfig {
+        self.unknown_entity = u;
+        self
+    }
+    /// Set how the document type declaration is processed.
+    pub fn dtd_policy(mut self, p: DtdPolicy) -> ParserConfig {
+        self.dtd = p;
+        self
+    }
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig::new()
     }
 }
 
 impl TryFrom<&str> for XMLDocument {
     type Error = Error;
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "parse", skip_all, fields(len = e.len())))]
     fn try_from(e: &str) -> Result<Self, Self::Error> {
         let e = trim_whitespace(e);
         match document(&e) {
@@ -160,11 +1607,13 @@ impl TryFrom<String> for XMLDocument {
 pub enum XMLNode {
     Element(QualifiedName, Vec<XMLNode>, Vec<XMLNode>), // Element name, attributes, content
     Attribute(QualifiedName, Value),
+    UnexpandedAttribute(QualifiedName, Vec<XMLNode>), // An attribute value that has general entity references: its text and references. These need to be expanded before presentation to the application
     Text(Value),
     PI(String, Value),
-    Comment(Value),           // Comment value is a string
-    DTD(DTDDecl),             // These only occur in the prologue
+    Comment(Value),                               // Comment value is a string
+    DTD(DTDDecl),                                 // These only occur in the prologue
     Reference(QualifiedName), // General entity reference. These need to be resolved before presentation to the application
+    EntityReference(QualifiedName, Vec<XMLNode>), // A resolved general entity reference: the entity name and its expansion
 }
 
 #[derive(PartialEq)]
@@ -175,32 +1624,79 @@ pub struct XMLdecl {
 }
 
 /// DTD declarations.
-/// Only general entities are supported, so far.
-/// TODO: element, attribute declarations
+/// General entities, notations and attribute-list declarations are supported, so far.
+/// TODO: element type declarations
 #[derive(Clone, PartialEq)]
 pub enum DTDDecl {
     GeneralEntity(QualifiedName, String),
+    ExternalEntity(QualifiedName, Option<String>, String), // Entity name, public identifier, system identifier
+    ExternalSubset(Option<String>, String), // Public identifier, system identifier of the document type declaration
+    Notation(String, Option<String>, Option<String>), // Notation name, public identifier, system identifier
+    AttributeList(QualifiedName, Vec<AttributeDefinition>), // Element type name, attribute definitions
+}
+
+/// The declaration of an attribute in an attribute-list declaration: its name, type, and default.
+pub type AttributeDefinition = (QualifiedName, AttributeType, AttributeDefault);
+
+/// The declared type of an attribute.
+/// The value of an attribute that is not CDATA is normalised by removing leading and trailing spaces, and collapsing sequences of spaces to a single space.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeType {
+    CData,
+    Id,
+    IdRef,
+    IdRefs,
+    Entity,
+    Entities,
+    NmToken,
+    NmTokens,
+    Notation(Vec<String>),
+    Enumeration(Vec<String>),
+}
+
+/// The default of a declared attribute.
+/// A default or fixed value is added to an element that does not have the attribute when general entities are expanded.
+#[derive(Clone, PartialEq)]
+pub enum AttributeDefault {
+    Required,
+    Implied,
+    Fixed(Vec<XMLNode>), // The value's text and general entity references
+    Default(Vec<XMLNode>),
 }
 
 // document ::= ( prolog element misc*)
 fn document(input: &str) -> IResult<&str, XMLDocument> {
-    map(tuple((opt(prolog), element, opt(misc))), |(p, e, m)| {
-        let pr = p.unwrap_or((None, vec![]));
+    map(tuple((opt(prolog), element, opt(epilogue))), |(p, e, m)| {
+        let pr = p.unwrap_or((None, None, vec![]));
 
         XMLDocument {
             content: vec![e],
             epilogue: m.unwrap_or(vec![]),
             xmldecl: pr.0,
-            prologue: pr.1,
+            doctype: pr.1,
+            prologue: pr.2,
         }
     })(input)
 }
 
-// prolog ::= XMLDecl misc* (doctypedecl Misc*)?
-fn prolog(input: &str) -> IResult<&str, (Option<XMLdecl>, Vec<XMLNode>)> {
-    map(tuple((opt(xmldecl), opt(doctypedecl))), |(x, dtd)| {
-        (x, dtd.map_or(vec![], |d| d))
-    })(input)
+// The XML declaration, the name in the document type declaration, and the other nodes of the prologue
+type Prolog = (Option<XMLdecl>, Option<QualifiedName>, Vec<XMLNode>);
+
+// prolog ::= XMLDecl? Misc* (doctypedecl Misc*)?
+fn prolog(input: &str) -> IResult<&str, Prolog> {
+    map(
+        tuple((opt(xmldecl), misc, opt(doctypedecl), misc)),
+        |(x, mut m1, dtd, mut m2)| {
+            let mut p = vec![];
+            p.append(&mut m1);
+            let n = dtd.map(|(n, mut d)| {
+                p.append(&mut d);
+                n
+            });
+            p.append(&mut m2);
+            (x, n, p)
+        },
+    )(input)
 }
 
 fn xmldecl(input: &str) -> IResult<&str, XMLdecl> {
@@ -218,32 +1714,34 @@ fn xmldecl(input: &str) -> IResult<&str, XMLdecl> {
                 )),
                 |(_, _, _, _, v)| v,
             ),
-            multispace0,
+            // Pseudo-attributes must be separated by whitespace
             opt(map(
                 tuple((
+                    multispace1,
                     tag("encoding"),
                     multispace0,
                     tag("="),
                     multispace0,
                     delimited_string,
                 )),
-                |(_, _, _, _, e)| e,
+                |(_, _, _, _, _, e)| e,
             )),
-            multispace0,
             opt(map(
                 tuple((
+                    multispace1,
                     tag("standalone"),
                     multispace0,
                     tag("="),
                     multispace0,
-                    delimited_string,
+                    // SDDecl ::= S 'standalone' Eq (("'" ('yes' | 'no') "'") | ('"' ('yes' | 'no') '"'))
+                    verify(delimited_string, |s: &str| s == "yes" || s == "no"),
                 )),
-                |(_, _, _, _, s)| s,
+                |(_, _, _, _, _, s)| s,
             )),
             multispace0,
             tag("?>"),
         )),
-        |(_, _, ver, _, enc, _, sta, _, _)| XMLdecl {
+        |(_, _, ver, enc, sta, _, _)| XMLdecl {
             version: ver,
             encoding: enc,
             standalone: sta,
@@ -251,13 +1749,13 @@ fn xmldecl(input: &str) -> IResult<&str, XMLdecl> {
     )(input)
 }
 
-fn doctypedecl(input: &str) -> IResult<&str, Vec<XMLNode>> {
+fn doctypedecl(input: &str) -> IResult<&str, (QualifiedName, Vec<XMLNode>)> {
     map(
         tuple((
             tag("<!DOCTYPE"),
             multispace1,
             qualname,
-            map(opt(map(tuple((multispace1, externalid)), |e| e)), |e| e),
+            opt(map(tuple((multispace1, externalid)), |(_, e)| e)),
             multispace0,
             opt(map(
                 tuple((
@@ -272,9 +1770,14 @@ fn doctypedecl(input: &str) -> IResult<&str, Vec<XMLNode>> {
             )),
             tag(">"),
         )),
-        |(_, _, _n, _extid, _, intss, _)| {
+        |(_, _, n, extid, _, intss, _)| {
             // TODO: the name must match the document element
-            intss.map_or(vec![], |i| i)
+            // The internal subset is processed before the external subset
+            let mut d = intss.map_or(vec![], |i| i);
+            if let Some((p, s)) = extid {
+                d.push(XMLNode::DTD(DTDDecl::ExternalSubset(p, s)))
+            }
+            (n, d)
         },
     )(input)
 }
@@ -283,7 +1786,28 @@ fn doctypedecl(input: &str) -> IResult<&str, Vec<XMLNode>> {
 // intSubset ::= (markupdecl | DeclSep)*
 // markupdecl ::= elementdecl | AttlistDecl | EntityDecl | NotationDecl | PI | Comment
 fn intsubset(input: &str) -> IResult<&str, Vec<XMLNode>> {
-    many0(alt((entitydecl, processing_instruction, comment)))(input)
+    map(
+        many0(map(
+            tuple((
+                alt((
+                    map(
+                        alt((
+                            entitydecl,
+                            notationdecl,
+                            attlistdecl,
+                            processing_instruction,
+                            comment,
+                        )),
+                        Some,
+                    ),
+                    map(elementdecl, |_| None),
+                )),
+                multispace0,
+            )),
+            |(d, _)| d,
+        )),
+        |v| v.into_iter().flatten().collect(),
+    )(input)
 }
 
 // EntityDecl ::= GEDecl | PEDecl
@@ -296,51 +1820,309 @@ fn entitydecl(input: &str) -> IResult<&str, XMLNode> {
             multispace1,
             qualname,
             multispace1,
-            entityvalue,
+            alt((
+                map(entityvalue, |v| (v, None)),
+                map(externalid, |(p, s)| (s, Some(p))),
+            )),
+            multispace0,
+            tag(">"),
+        )),
+        |(_, _, n, _, (v, ext), _, _)| match ext {
+            Some(p) => XMLNode::DTD(DTDDecl::ExternalEntity(n, p, v)),
+            None => XMLNode::DTD(DTDDecl::GeneralEntity(n, v)),
+        },
+    )(input)
+}
+
+fn entityvalue(input: &str) -> IResult<&str, String> {
+    alt((entityvalue_single, entityvalue_double))(input)
+}
+// TODO: parameter entity references
+fn entityvalue_single(input: &str) -> IResult<&str, String> {
+    map(
+        delimited(
+            char('\''),
+            recognize(many0(alt((
+                map(recognize(reference), |r| String::from(r)),
+                map(many1(none_of("'&")), |v| v.iter().collect::<String>()),
+            )))),
+            char('\''),
+        ),
+        |e| String::from(e),
+    )(input)
+}
+fn entityvalue_double(input: &str) -> IResult<&str, String> {
+    map(
+        delimited(
+            char('"'),
+            recognize(many0(alt((
+                map(recognize(reference), |r| String::from(r)),
+                map(many1(none_of("\"&")), |v| v.iter().collect::<String>()),
+            )))),
+            char('"'),
+        ),
+        |e| String::from(e),
+    )(input)
+}
+
+// NotationDecl ::= '<!NOTATION' S Name S (ExternalID | PublicID) S? '>'
+// PublicID ::= 'PUBLIC' S PubidLiteral
+fn notationdecl(input: &str) -> IResult<&str, XMLNode> {
+    map(
+        tuple((
+            tag("<!NOTATION"),
+            multispace1,
+            name,
+            multispace1,
+            alt((
+                map(externalid, |(p, s)| (p, Some(s))),
+                map(
+                    tuple((tag("PUBLIC"), multispace1, pubidliteral)),
+                    |(_, _, p)| (Some(p), None),
+                ),
+            )),
+            multispace0,
+            tag(">"),
+        )),
+        |(_, _, n, _, (p, s), _, _)| XMLNode::DTD(DTDDecl::Notation(n.to_string(), p, s)),
+    )(input)
+}
+
+// AttlistDecl ::= '<!ATTLIST' S Name AttDef* S? '>'
+fn attlistdecl(input: &str) -> IResult<&str, XMLNode> {
+    map(
+        tuple((
+            tag("<!ATTLIST"),
+            multispace1,
+            qualname,
+            many0(attdef),
+            multispace0,
+            tag(">"),
+        )),
+        |(_, _, n, d, _, _)| XMLNode::DTD(DTDDecl::AttributeList(n, d)),
+    )(input)
+}
+
+// AttDef ::= S Name S AttType S DefaultDecl
+fn attdef(input: &str) -> IResult<&str, AttributeDefinition> {
+    map(
+        tuple((
+            multispace1,
+            qualname,
+            multispace1,
+            atttype,
+            multispace1,
+            defaultdecl,
+        )),
+        |(_, n, _, t, _, d)| (n, t, d),
+    )(input)
+}
+
+// AttType ::= StringType | TokenizedType | EnumeratedType
+// NotationType ::= 'NOTATION' S '(' S? Name (S? '|' S? Name)* S? ')'
+fn atttype(input: &str) -> IResult<&str, AttributeType> {
+    alt((
+        value(AttributeType::CData, tag("CDATA")),
+        value(AttributeType::IdRefs, tag("IDREFS")),
+        value(AttributeType::IdRef, tag("IDREF")),
+        value(AttributeType::Id, tag("ID")),
+        value(AttributeType::Entities, tag("ENTITIES")),
+        value(AttributeType::Entity, tag("ENTITY")),
+        value(AttributeType::NmTokens, tag("NMTOKENS")),
+        value(AttributeType::NmToken, tag("NMTOKEN")),
+        map(
+            tuple((tag("NOTATION"), multispace1, enumeration(name))),
+            |(_, _, v)| AttributeType::Notation(v),
+        ),
+        map(enumeration(nmtoken), AttributeType::Enumeration),
+    ))(input)
+}
+
+// Enumeration ::= '(' S? Nmtoken (S? '|' S? Nmtoken)* S? ')'
+fn enumeration<'a>(
+    token: fn(&'a str) -> IResult<&'a str, &'a str>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<String>> {
+    delimited(
+        tuple((char('('), multispace0)),
+        separated_list1(
+            tuple((multispace0, char('|'), multispace0)),
+            map(token, String::from),
+        ),
+        tuple((multispace0, char(')'))),
+    )
+}
+
+// DefaultDecl ::= '#REQUIRED' | '#IMPLIED' | (('#FIXED' S)? AttValue)
+fn defaultdecl(input: &str) -> IResult<&str, AttributeDefault> {
+    alt((
+        value(AttributeDefault::Required, tag("#REQUIRED")),
+        value(AttributeDefault::Implied, tag("#IMPLIED")),
+        map(
+            tuple((tag("#FIXED"), multispace1, attvalue)),
+            |(_, _, v)| AttributeDefault::Fixed(v),
+        ),
+        map(attvalue, AttributeDefault::Default),
+    ))(input)
+}
+
+// Element type declarations are recognised, but the content model is not kept
+// elementdecl ::= '<!ELEMENT' S Name S contentspec S? '>'
+// contentspec ::= 'EMPTY' | 'ANY' | Mixed | children
+fn elementdecl(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        tag("<!ELEMENT"),
+        multispace1,
+        qualname,
+        multispace1,
+        alt((tag("EMPTY"), tag("ANY"), mixed, recognize(children))),
+        multispace0,
+        tag(">"),
+    )))(input)
+}
+
+// Mixed ::= '(' S? '#PCDATA' (S? '|' S? Name)* S? ')*' | '(' S? '#PCDATA' S? ')'
+fn mixed(input: &str) -> IResult<&str, &str> {
+    alt((
+        recognize(tuple((
+            char('('),
             multispace0,
-            tag(">"),
-        )),
-        |(_, _, n, _, v, _, _)| XMLNode::DTD(DTDDecl::GeneralEntity(n, v)),
-    )(input)
+            tag("#PCDATA"),
+            many0(tuple((multispace0, char('|'), multispace0, qualname))),
+            multispace0,
+            tag(")*"),
+        ))),
+        recognize(tuple((
+            char('('),
+            multispace0,
+            tag("#PCDATA"),
+            multispace0,
+            char(')'),
+        ))),
+    ))(input)
 }
 
-fn entityvalue(input: &str) -> IResult<&str, String> {
-    alt((entityvalue_single, entityvalue_double))(input)
+// children ::= (choice | seq) ('?' | '*' | '+')?
+// cp ::= (Name | choice | seq) ('?' | '*' | '+')?
+// choice ::= '(' S? cp ( S? '|' S? cp )+ S? ')'
+// seq ::= '(' S? cp ( S? ',' S? cp )* S? ')'
+fn children(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        alt((content_particles('|'), content_particles(','))),
+        opt(alt((char('?'), char('*'), char('+')))),
+    )))(input)
 }
-// TODO: parameter entity references
-fn entityvalue_single(input: &str) -> IResult<&str, String> {
-    map(
-        delimited(
-            char('\''),
-            recognize(many0(alt((
-                map(recognize(reference), |r| String::from(r)),
-                map(many1(none_of("'&")), |v| v.iter().collect::<String>()),
-            )))),
-            char('\''),
+fn content_particle(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        alt((recognize(qualname), children)),
+        opt(alt((char('?'), char('*'), char('+')))),
+    )))(input)
+}
+fn content_particles<'a>(sep: char) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    recognize(tuple((
+        char('('),
+        multispace0,
+        separated_list1(
+            tuple((multispace0, char(sep), multispace0)),
+            content_particle,
         ),
-        |e| String::from(e),
-    )(input)
+        multispace0,
+        char(')'),
+    )))
 }
-fn entityvalue_double(input: &str) -> IResult<&str, String> {
-    map(
-        delimited(
-            char('"'),
-            recognize(many0(alt((
-                map(recognize(reference), |r| String::from(r)),
-                map(many1(none_of("\"&")), |v| v.iter().collect::<String>()),
-            )))),
-            char('"'),
+
+// ExternalID ::= 'SYSTEM' S SystemLiteral | 'PUBLIC' S PubidLiteral S SystemLiteral
+// The literals are taken as they are: a "%" is not a parameter entity reference.
+fn externalid(input: &str) -> IResult<&str, (Option<String>, String)> {
+    alt((
+        map(
+            tuple((tag("SYSTEM"), multispace1, delimited_string)),
+            |(_, _, s)| (None, s),
         ),
-        |e| String::from(e),
+        map(
+            tuple((
+                tag("PUBLIC"),
+                multispace1,
+                pubidliteral,
+                multispace1,
+                delimited_string,
+            )),
+            |(_, _, p, _, s)| (Some(p), s),
+        ),
+    ))(input)
+}
+
+// PubidLiteral ::= '"' PubidChar* '"' | "'" (PubidChar - "'")* "'"
+// PubidChar ::= #x20 | #xD | #xA | [a-zA-Z0-9] | [-'()+,./:=?;!*#@$_%]
+// Whitespace is normalised, as it must be before public identifiers are compared.
+fn pubidliteral(input: &str) -> IResult<&str, String> {
+    fn pubid_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || " \r\n-'()+,./:=?;!*#@$_%".contains(c)
+    }
+    map(
+        alt((
+            delimited(char('"'), take_while(pubid_char), char('"')),
+            delimited(
+                char('\''),
+                take_while(|c| c != '\'' && pubid_char(c)),
+                char('\''),
+            ),
+        )),
+        |p: &str| p.split_ascii_whitespace().collect::<Vec<_>>().join(" "),
     )(input)
 }
 
-fn externalid(input: &str) -> IResult<&str, Vec<XMLNode>> {
-    map(tag("not yet implemented"), |_| {
-        vec![XMLNode::Text(Value::String(
-            "external ID not yet implemented".to_string(),
-        ))]
-    })(input)
+// Element type declarations are not used yet, so they are recognised and skipped, as are attribute-list declarations that cannot be parsed, for example because they have parameter entity references
+fn ignored_decl(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        alt((tag("<!ELEMENT"), tag("<!ATTLIST"))),
+        multispace1,
+        many0(alt((
+            recognize(delimited_string),
+            recognize(none_of("'\">")),
+        ))),
+        tag(">"),
+    )))(input)
+}
+
+// extSubset ::= TextDecl? extSubsetDecl
+fn extsubset(s: &str) -> Result<Vec<DTDDecl>, Error> {
+    let s = strip_textdecl(s.to_string());
+    let r = match tuple((
+        multispace0,
+        many0(map(
+            tuple((
+                alt((
+                    map(
+                        alt((
+                            entitydecl,
+                            notationdecl,
+                            attlistdecl,
+                            processing_instruction,
+                            comment,
+                        )),
+                        Some,
+                    ),
+                    map(ignored_decl, |_| None),
+                )),
+                multispace0,
+            )),
+            |(d, _)| d,
+        )),
+    ))(s.as_str())
+    {
+        Ok(("", (_, d))) => Ok(d
+            .into_iter()
+            .filter_map(|n| match n {
+                Some(XMLNode::DTD(d)) => Some(d),
+                _ => None,
+            })
+            .collect()),
+        _ => Result::Err(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to parse external DTD subset"),
+        )),
+    };
+    r
 }
 
 // Element ::= EmptyElemTag | STag content ETag
@@ -395,10 +2177,7 @@ fn attributes(input: &str) -> IResult<&str, Vec<XMLNode>> {
         let attrs = v.clone();
         let uniqueattrs: HashSet<_> = attrs
             .iter()
-            .map(|xmlnode| match xmlnode {
-                XMLNode::Attribute(q, _) => q.to_string(),
-                _ => "".to_string(),
-            })
+            .map(|xmlnode| attribute_name(xmlnode).map_or(String::new(), |q| q.to_string()))
             .collect();
         if &v.len() == &uniqueattrs.len() {
             true
@@ -417,11 +2196,59 @@ fn attribute(input: &str) -> IResult<&str, XMLNode> {
             multispace0,
             tag("="),
             multispace0,
-            delimited_string,
+            attvalue,
         )),
-        |(_, n, _, _, _, s)| XMLNode::Attribute(n, Value::String(s)),
+        |(_, n, _, _, _, v)| match v.as_slice() {
+            [] => XMLNode::Attribute(n, Value::from("")),
+            [XMLNode::Text(t)] => XMLNode::Attribute(n, t.clone()),
+            _ => XMLNode::UnexpandedAttribute(n, v),
+        },
     )(input)
 }
+fn attribute_name(n: &XMLNode) -> Option<&QualifiedName> {
+    match n {
+        XMLNode::Attribute(q, _) | XMLNode::UnexpandedAttribute(q, _) => Some(q),
+        _ => None,
+    }
+}
+// AttValue ::= '"' ([^<&"] | Reference)* '"' | "'" ([^<&'] | Reference)* "'"
+// Character references and the predefined entities are replaced by their character, and whitespace characters by a space. References to other general entities are kept until the entity declarations are known.
+fn attvalue(input: &str) -> IResult<&str, Vec<XMLNode>> {
+    alt((
+        delimited(char('"'), attvalue_content("\"<&", true), char('"')),
+        delimited(char('\''), attvalue_content("'<&", true), char('\'')),
+    ))(input)
+}
+// Line ends are normalised in the text of the document, but not in replacement text, where they may come from character references
+fn attvalue_content<'a>(
+    exclude: &'static str,
+    eol: bool,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<XMLNode>> {
+    map(
+        many0(alt((
+            map(chardata_escapes, |s| XMLNode::Text(Value::from(s))),
+            entityref,
+            map(many1(none_of(exclude)), move |v| {
+                let t = v.into_iter().collect::<String>();
+                let t = if eol { t.replace("\r\n", "\n") } else { t };
+                XMLNode::Text(Value::from(t.replace(['\t', '\n', '\r'], " ")))
+            }),
+        ))),
+        |v| {
+            // Join adjacent text
+            let mut r: Vec<XMLNode> = vec![];
+            for n in v {
+                match (r.last_mut(), n) {
+                    (Some(XMLNode::Text(t)), XMLNode::Text(u)) => {
+                        *t = Value::from(t.to_string() + u.to_string().as_str())
+                    }
+                    (_, n) => r.push(n),
+                }
+            }
+            r
+        },
+    )
+}
 fn delimited_string(input: &str) -> IResult<&str, String> {
     alt((string_single, string_double))(input)
 }
@@ -458,14 +2285,14 @@ fn content(input: &str) -> IResult<&str, Vec<XMLNode>> {
         )),
         |(c, v)| {
             let mut new: Vec<XMLNode> = Vec::new();
-            if c.is_some() {
-                new.push(XMLNode::Text(Value::String(c.unwrap())));
+            if let Some(c) = c {
+                new.push(XMLNode::Text(Value::from(c)));
             }
             if v.len() != 0 {
                 for (w, d) in v {
                     new.push(w);
-                    if d.is_some() {
-                        new.push(XMLNode::Text(Value::String(d.unwrap())));
+                    if let Some(d) = d {
+                        new.push(XMLNode::Text(Value::from(d)));
                     }
                 }
             }
@@ -484,71 +2311,57 @@ fn entityref(input: &str) -> IResult<&str, XMLNode> {
     })(input)
 }
 fn charref(input: &str) -> IResult<&str, XMLNode> {
-    alt((charref_octal, charref_hex))(input)
-}
-fn charref_octal(input: &str) -> IResult<&str, XMLNode> {
-    map(
-        tuple((char('&'), char('#'), digit1, char(';'))),
-        |(_, _, n, _)| {
-            let u = match u32::from_str_radix(n, 8) {
-                Ok(c) => c,
-                Err(_) => 0, // TODO: pass back error to nom
-            };
-            match std::char::from_u32(u) {
-                Some(c) => XMLNode::Text(Value::from(c.to_string())),
-                None => {
-                    //make_error(input, NomErrorKind::OctDigit)
-                    XMLNode::Text(Value::from(""))
-                }
-            }
-        },
-    )(input)
-}
-fn charref_hex(input: &str) -> IResult<&str, XMLNode> {
-    map(
-        tuple((char('&'), char('#'), char('x'), hex_digit1, char(';'))),
-        |(_, _, _, n, _)| {
-            let u = match u32::from_str_radix(n, 16) {
-                Ok(c) => c,
-                Err(_) => 0, // TODO: pass back error to nom
-            };
-            match std::char::from_u32(u) {
-                Some(c) => XMLNode::Text(Value::from(c.to_string())),
-                None => {
-                    //make_error(input, NomErrorKind::OctDigit)
-                    XMLNode::Text(Value::from(""))
-                }
-            }
-        },
-    )(input)
+    map(chardata_unicode_codepoint, |c| {
+        XMLNode::Text(Value::from(c))
+    })(input)
 }
 
 // PI ::= '<?' PITarget (char* - '?>') '?>'
+// PITarget ::= Name - (('X' | 'x') ('M' | 'm') ('L' | 'l'))
+fn pitarget(input: &str) -> IResult<&str, &str> {
+    verify(name, |n: &str| !n.eq_ignore_ascii_case("xml"))(input)
+}
 fn processing_instruction(input: &str) -> IResult<&str, XMLNode> {
     map(
         delimited(
             tag("<?"),
-            tuple((multispace0, name, multispace0, take_until("?>"))),
+            tuple((multispace0, pitarget, multispace0, take_until("?>"))),
             tag("?>"),
         ),
-        |(_, n, _, v)| XMLNode::PI(String::from(n), Value::String(v.to_string())),
+        |(_, n, _, v)| XMLNode::PI(String::from(n), Value::from(v.to_string())),
     )(input)
 }
 
 // Comment ::= '<!--' (char* - '--') '-->'
 fn comment(input: &str) -> IResult<&str, XMLNode> {
     map(
-        delimited(tag("<!--"), take_until("--"), tag("-->")),
-        |v: &str| XMLNode::Comment(Value::String(v.to_string())),
+        delimited(
+            tag("<!--"),
+            verify(take_until("--"), |v: &str| v.chars().all(|c| is_char(&c))),
+            tag("-->"),
+        ),
+        |v: &str| XMLNode::Comment(Value::from(v.to_string())),
     )(input)
 }
 
+// Misc* following the document element.
+// NB. trailing whitespace is not (yet) accepted
+fn epilogue(input: &str) -> IResult<&str, Vec<XMLNode>> {
+    many0(map(
+        tuple((multispace0, alt((comment, processing_instruction)))),
+        |(_, n)| n,
+    ))(input)
+}
 // Misc ::= Comment | PI | S
 fn misc(input: &str) -> IResult<&str, Vec<XMLNode>> {
-    map(tag("not yet implemented"), |_| {
-        //vec![Node::new(NodeType::Comment).set_value("not yet implemented".to_string())]
-        vec![]
-    })(input)
+    map(
+        many0(alt((
+            map(comment, Some),
+            map(processing_instruction, Some),
+            value(None, multispace1),
+        ))),
+        |v| v.into_iter().flatten().collect(),
+    )(input)
 }
 
 // CharData ::= [^<&]* - (']]>')
@@ -577,22 +2390,23 @@ fn chardata_escapes(input: &str) -> IResult<&str, String> {
     ))(input)
 }
 
+// CharRef ::= '&#' [0-9]+ ';' | '&#x' [0-9a-fA-F]+ ';'
+// The character must be allowed in XML (WFC: Legal Character), so references to control characters, surrogates, #xFFFE, #xFFFF and values beyond #x10FFFF are errors.
 fn chardata_unicode_codepoint(input: &str) -> IResult<&str, String> {
-    let parse_hex = map(
-        take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()),
-        |hex| u32::from_str_radix(hex, 16),
-    );
-
-    let parse_decimal = map(take_while_m_n(1, 6, |c: char| c.is_ascii_digit()), |dec| {
-        u32::from_str(dec)
-    });
-
     map_opt(
         alt((
-            delimited(tag("&#x"), parse_hex, tag(";")),
-            delimited(tag("&#"), parse_decimal, tag(";")),
+            map(delimited(tag("&#x"), hex_digit1, tag(";")), |hex| {
+                u32::from_str_radix(hex, 16)
+            }),
+            map(delimited(tag("&#"), digit1, tag(";")), u32::from_str),
         )),
-        |value| Option::from(std::char::from_u32(value.unwrap()).unwrap().to_string()),
+        |value| {
+            value
+                .ok()
+                .and_then(std::char::from_u32)
+                .filter(is_char)
+                .map(String::from)
+        },
     )(input)
 }
 
@@ -648,6 +2462,105 @@ fn trim_whitespace(s: &str) -> String {
 mod tests {
     use super::*;
 
+    // A source that returns only a few bytes at a time, to split the input at awkward places
+    struct Trickle<'a>(&'a [u8], usize);
+    impl<'a> std::io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.1.min(self.0.len()).min(buf.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn events(doc: &str, size: usize) -> Result<String, Error> {
+        let mut r = String::new();
+        for e in Reader::new(Trickle(doc.as_bytes(), size)) {
+            match e? {
+                XmlEvent::StartElement(n, a) => {
+                    r.push_str(format!("<{}", n.to_string()).as_str());
+                    for (an, av) in a {
+                        r.push_str(format!(" {}='{}'", an.to_string(), av).as_str())
+                    }
+                    r.push('>')
+                }
+                XmlEvent::EndElement(n) => r.push_str(format!("</{}>", n.to_string()).as_str()),
+                XmlEvent::Text(t) => r.push_str(t.as_str()),
+                XmlEvent::EntityReference(n) => r.push_str(format!("&{};", n.to_string()).as_str()),
+                XmlEvent::Comment(c) => r.push_str(format!("<!--{}-->", c).as_str()),
+                XmlEvent::ProcessingInstruction(t, d) => {
+                    r.push_str(format!("<?{} {}?>", t, d).as_str())
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    #[test]
+    fn reader() {
+        let doc = "<?xml version='1.0'?>\n<!DOCTYPE a [<!ENTITY e 'x'>]>\n<?xml-stylesheet href='s.xsl'?><a x:b=\"1 &amp; 2\">caf\u{e9} &lt;\u{65e5}\u{672c}&gt; <![CDATA[<c>]]]]><!--note--><d/>&e;</a>\n";
+        let expected = "<?xml-stylesheet href='s.xsl'?><a x:b='1 & 2'>caf\u{e9} <\u{65e5}\u{672c}> <c>]]<!--note--><d></d>&e;</a>";
+        // The result is the same however the input is divided
+        for size in [1, 2, 3, 7, READ_SIZE] {
+            assert_eq!(
+                events(doc, size).expect("unable to read document"),
+                expected
+            )
+        }
+    }
+
+    #[test]
+    fn reader_errors() {
+        for (doc, msg) in [
+            (
+                "<a><b></a>",
+                "line 1, column 7: end tag does not match start tag",
+            ),
+            ("<a>\n<b>", "element \"b\" is not closed"),
+            (
+                "<a/><b/>",
+                "line 1, column 5: more than one document element",
+            ),
+            (
+                "<a/>text",
+                "line 1, column 5: text is not allowed outside the document element",
+            ),
+            ("<a>\n  <b c=>", "line 2, column 3: syntax error"),
+            ("<a>]]></a>", "line 1, column 4: syntax error"),
+            ("<!-- nothing -->", "no document element"),
+        ] {
+            for size in [1, 4, READ_SIZE] {
+                match events(doc, size) {
+                    Err(e) => assert_eq!(e.message, msg),
+                    Ok(_) => panic!("\"{}\" is not well-formed", doc),
+                }
+            }
+        }
+        // A syntax error is reported without reading the rest of the document
+        let mut doc = String::from("<a><b c=></b>");
+        doc.push_str("<c/>".repeat(READ_SIZE).as_str());
+        doc.push_str("</a>");
+        let mut source = doc.as_bytes();
+        let mut r = Reader::new(&mut source);
+        assert!(r.next().is_some_and(|e| e.is_ok()));
+        assert!(r.next().is_some_and(|e| e.is_err()));
+        assert!(!source.is_empty());
+        // Unless the markup is incomplete
+        assert!(may_be_incomplete("<a b='>"));
+        assert!(may_be_incomplete("<!DOCTYPE a [<!ENTITY e '>]>'>"));
+        assert!(!may_be_incomplete("<!DOCTYPE a [<!ENTITY e '>]>'>]>"));
+        assert!(may_be_incomplete("&am"));
+        assert!(!may_be_incomplete("&am p;"));
+        // An error ends the events
+        let mut r = Reader::new("<a></b><c/>".as_bytes());
+        assert!(r.next().is_some_and(|e| e.is_ok()));
+        assert!(r.next().is_some_and(|e| e.is_err()));
+        assert!(r.next().is_none());
+        // Invalid UTF-8
+        assert!(events("<a>\u{e9}</a>", 1).is_ok());
+        assert!(Reader::new(&b"<a>\xe9</a>"[..]).any(|e| e.is_err()))
+    }
+
     #[test]
     fn empty() {
         let doc = XMLDocument::try_from("<Test/>").expect("failed to parse XML \"<Test/>\"");
@@ -832,6 +2745,83 @@ mod tests {
         }
     }
 
+    #[test]
+    fn char_ref_range() {
+        let text = |s: &str| -> Option<String> {
+            match XMLDocument::try_from(s).ok()?.content.first() {
+                Some(XMLNode::Element(_, _, c)) => Some(
+                    c.iter()
+                        .map(|t| match t {
+                            XMLNode::Text(v) => v.to_string(),
+                            _ => panic!("expected text"),
+                        })
+                        .collect(),
+                ),
+                _ => None,
+            }
+        };
+        // Supplementary-plane characters, as in valid-sa-052/064
+        assert_eq!(
+            text("<doc>&#x10000;&#x10FFFD;</doc>"),
+            Some(String::from("\u{10000}\u{10FFFD}"))
+        );
+        assert_eq!(
+            text("<doc>&#65536;&#1114111;</doc>"),
+            Some(String::from("\u{10000}\u{10FFFF}"))
+        );
+        assert_eq!(
+            text("<doc>&#0000065;&#x0000042;</doc>"),
+            Some(String::from("AB"))
+        );
+        for r in [
+            "&#0;",
+            "&#31;",
+            "&#x1F;",
+            "&#xFFFE;",
+            "&#xFFFF;",
+            "&#xD800;",
+            "&#56320;",
+            "&#x110000;",
+            "&#1114112;",
+            "&#xFFFFFFFFF;",
+            "&#X58;",
+        ] {
+            assert!(
+                XMLDocument::try_from(format!("<doc>{}</doc>", r).as_str()).is_err(),
+                "{} in content",
+                r
+            );
+            assert!(
+                XMLDocument::try_from(format!("<doc a='{}'/>", r).as_str()).is_err(),
+                "{} in attribute value",
+                r
+            );
+            assert!(
+                XMLDocument::try_from(
+                    format!("<!DOCTYPE doc [<!ENTITY e '{}'>]><doc/>", r).as_str()
+                )
+                .is_err(),
+                "{} in entity value",
+                r
+            );
+        }
+
+        // Supplementary-plane characters in an entity, as in valid-sa-089
+        let mut d = XMLDocument::try_from(
+            "<!DOCTYPE doc [<!ENTITY e '&#x10000;&#x10FFFD;&#x10FFFF;'>]><doc>&e;</doc>",
+        )
+        .expect("failed to parse XML");
+        d.expand().expect("unable to expand entities");
+        match &d.content[0] {
+            XMLNode::Element(_, _, c) => {
+                assert!(
+                    matches!(&c[0], XMLNode::Text(t) if t.to_string() == "\u{10000}\u{10FFFD}\u{10FFFF}")
+                )
+            }
+            _ => panic!("root is not an element node"),
+        }
+    }
+
     #[test]
     fn mixed() {
         let doc = XMLDocument::try_from("<Test>i1<Foo>bar</Foo>i2</Test>")
@@ -1029,4 +3019,794 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn preserve_reference() {
+        let doc = r#"<!DOCTYPE doc [<!ENTITY general '<expansion>entity</expansion>'>]><doc>&general;</doc>"#;
+        let mut result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        result
+            .resolve_references(None, &MemoryResolver::new())
+            .expect("unable to resolve entities");
+        match &result.content[0] {
+            XMLNode::Element(_, _, c) => match &c[0] {
+                XMLNode::EntityReference(n, e) => {
+                    assert_eq!(n.to_string(), "general");
+                    assert_eq!(e.len(), 1);
+                    assert!(
+                        matches!(&e[0], XMLNode::Element(m, _, _) if m.get_localname() == "expansion")
+                    )
+                }
+                _ => panic!("failed to find entity reference"),
+            },
+            _ => panic!("root is not an element node"),
+        }
+    }
+
+    #[test]
+    fn unknown_entity() {
+        let doc = r#"<!DOCTYPE doc [<!ENTITY known 'yes'>]><doc>&known; &nbsp; &copy;</doc>"#;
+        let text = |d: &XMLDocument| match &d.content[0] {
+            XMLNode::Element(_, _, c) => c
+                .iter()
+                .map(|t| match t {
+                    XMLNode::Text(v) => v.to_string(),
+                    _ => panic!("expected text"),
+                })
+                .collect::<String>(),
+            _ => panic!("root is not an element node"),
+        };
+
+        let mut result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert!(result.expand().is_err());
+
+        let mut result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        result
+            .expand_with_config(
+                None,
+                &MemoryResolver::new(),
+                &ParserConfig::new().unknown_entity(UnknownEntity::Literal),
+            )
+            .expect("unable to expand entities");
+        assert_eq!(text(&result), "yes &nbsp; &copy;");
+
+        let cb = |n: &str| match n {
+            "nbsp" => Some(String::from("\u{a0}")),
+            _ => None,
+        };
+        let mut result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert!(result
+            .expand_with_config(
+                None,
+                &MemoryResolver::new(),
+                &ParserConfig::new().unknown_entity(UnknownEntity::Callback(Rc::new(cb))),
+            )
+            .is_err());
+        let doc = r#"<doc>&nbsp;</doc>"#;
+        let mut result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        result
+            .expand_with_config(
+                None,
+                &MemoryResolver::new(),
+                &ParserConfig::new().unknown_entity(UnknownEntity::Callback(Rc::new(cb))),
+            )
+            .expect("unable to expand entities");
+        assert_eq!(text(&result), "\u{a0}");
+
+        // The setting also applies when a tree is grown from the document
+        let mut f = crate::forest::Forest::new();
+        let grow = |f: &mut crate::forest::Forest, doc: &str, u: UnknownEntity| {
+            f.grow_tree_with_config(doc, &ParserConfig::new().unknown_entity(u))
+                .map(|t| f.get_ref(t).unwrap().get_doc_node().to_xml(f))
+        };
+        assert!(grow(&mut f, "<a>&undeclared;</a>", UnknownEntity::Error)
+            .is_err_and(|e| e.message == "general entity \"undeclared\" is not declared"));
+        assert_eq!(
+            grow(&mut f, "<a>&undeclared;</a>", UnknownEntity::Literal)
+                .expect("unable to parse XML"),
+            "<a>&amp;undeclared;</a>"
+        );
+        assert_eq!(
+            grow(
+                &mut f,
+                "<a>&nbsp;</a>",
+                UnknownEntity::Callback(Rc::new(cb))
+            )
+            .expect("unable to parse XML"),
+            "<a>\u{a0}</a>"
+        );
+    }
+
+    #[test]
+    fn attribute_entities() {
+        let value = |doc: &str| -> Result<String, Error> {
+            let mut d = XMLDocument::try_from(doc)?;
+            let mut r = MemoryResolver::new();
+            r.insert("file:///test/ext.ent", "external");
+            d.expand_with_resolver(
+                Some(&Url::parse("file:///test/doc.xml").expect("unable to parse URL")),
+                &r,
+            )?;
+            match &d.content[0] {
+                XMLNode::Element(_, a, _) => match &a[0] {
+                    XMLNode::Attribute(_, v) => Ok(v.to_string()),
+                    _ => panic!("attribute has not been expanded"),
+                },
+                _ => panic!("root is not an element node"),
+            }
+        };
+        let dtd = r#"<!DOCTYPE doc [
+<!ENTITY quote "&#34;">
+<!ENTITY nl "
+">
+<!ENTITY crlf "&#13;&#10;">
+<!ENTITY nested "[&quote;&amp;]">
+<!ENTITY less "&#60;">
+<!ENTITY escaped "&#38;#60;">
+<!ENTITY ext SYSTEM "ext.ent">
+<!ENTITY loop1 "&loop2;">
+<!ENTITY loop2 "&loop1;">
+]>"#;
+        let doc = |a: &str| format!("{}<doc a='{}'/>", dtd, a);
+
+        // Character references and predefined entities don't need the DTD
+        assert_eq!(
+            value("<doc a='&quot;&lt;&amp;&gt;&apos;&#65;&#x42;'/>").unwrap(),
+            "\"<&>'AB"
+        );
+        assert_eq!(value("<doc a='x\ty\r\nz'/>").unwrap(), "x y z");
+        assert_eq!(value("<doc a='x&#10;y'/>").unwrap(), "x\ny");
+        assert!(XMLDocument::try_from("<doc a='<'/>").is_err());
+        assert!(XMLDocument::try_from("<doc a='&'/>").is_err());
+
+        assert_eq!(value(doc("&quote;").as_str()).unwrap(), "\"");
+        // Whitespace characters in the replacement text become spaces
+        assert_eq!(value(doc("x&nl;y").as_str()).unwrap(), "x y");
+        assert_eq!(value(doc("x&crlf;y").as_str()).unwrap(), "x  y");
+        assert_eq!(value(doc("&nested;").as_str()).unwrap(), "[\"&]");
+        assert_eq!(value(doc("&escaped;").as_str()).unwrap(), "<");
+
+        // Well-formedness constraints
+        let e = value(doc("&less;").as_str()).expect_err("\"<\" in attribute value");
+        assert!(e.to_string().contains("<"));
+        let e = value(doc("x&ext;").as_str()).expect_err("external entity in attribute value");
+        assert!(e.to_string().contains("external entity"));
+        assert!(value(doc("&loop1;").as_str()).is_err());
+        assert!(value(doc("&undeclared;").as_str()).is_err());
+    }
+
+    #[test]
+    fn external_identifiers() {
+        // The unusual PubidChars of valid-sa-100
+        let doc = r#"<!DOCTYPE doc PUBLIC "-//Test//DTD  doc
+ 1.0//EN" 'doc%.dtd' [
+<!ENTITY e PUBLIC ";!*#@$_%" "100.xml">
+<!ENTITY f PUBLIC "it's" "f.xml">
+<!ENTITY g SYSTEM "%g;.xml">
+<!NOTATION n1 PUBLIC "-//Test//NOTATION n1//EN">
+<!NOTATION n2 SYSTEM "n2.exe">
+<!NOTATION n3 PUBLIC "n3" "n3.exe" >
+]><doc/>"#;
+        let result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert_eq!(
+            result.external_id(),
+            Some((Some("-//Test//DTD doc 1.0//EN"), "doc%.dtd"))
+        );
+        let decls: Vec<&DTDDecl> = result
+            .prologue
+            .iter()
+            .filter_map(|p| match p {
+                XMLNode::DTD(d) => Some(d),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(decls[0], DTDDecl::ExternalEntity(n, Some(p), s)
+            if n.to_string() == "e" && p == ";!*#@$_%" && s == "100.xml"));
+        assert!(matches!(decls[1], DTDDecl::ExternalEntity(_, Some(p), _) if p == "it's"));
+        assert!(matches!(decls[2], DTDDecl::ExternalEntity(_, None, s) if s == "%g;.xml"));
+        assert!(matches!(decls[3], DTDDecl::Notation(n, Some(p), None)
+            if n == "n1" && p == "-//Test//NOTATION n1//EN"));
+        assert!(matches!(decls[4], DTDDecl::Notation(_, None, Some(s)) if s == "n2.exe"));
+        assert!(
+            matches!(decls[5], DTDDecl::Notation(_, Some(p), Some(s)) if p == "n3" && s == "n3.exe")
+        );
+        assert!(XMLDocument::try_from("<doc/>")
+            .expect("failed to parse XML")
+            .external_id()
+            .is_none());
+
+        // Characters that are not PubidChars
+        for p in ["\"a{b}\"", "'it's'", "\"tab\there\"", "\"caf\u{e9}\""] {
+            let doc = format!("<!DOCTYPE doc [<!ENTITY e PUBLIC {} 'e.xml'>]><doc/>", p);
+            assert!(XMLDocument::try_from(doc.as_str()).is_err(), "{}", p)
+        }
+        // A public identifier without a system identifier is only allowed for notations
+        assert!(XMLDocument::try_from("<!DOCTYPE doc [<!ENTITY e PUBLIC 'e'>]><doc/>").is_err());
+    }
+
+    #[test]
+    fn prolog_misc() {
+        let doc = r#"<?xml version="1.0"?>
+<?xml-stylesheet href="style.xsl" type="text/xsl"?>
+<!-- a comment -->
+<doc/>
+<!-- after -->"#;
+        let result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert_eq!(result.prologue.len(), 2);
+        assert!(matches!(&result.prologue[0], XMLNode::PI(n, _) if n == "xml-stylesheet"));
+        assert!(matches!(&result.prologue[1], XMLNode::Comment(_)));
+        assert_eq!(result.epilogue.len(), 1);
+    }
+
+    #[test]
+    fn xmldecl_whitespace() {
+        assert!(XMLDocument::try_from(r#"<?xml version="1.0" encoding="UTF-8"?><doc/>"#).is_ok());
+        assert!(XMLDocument::try_from(r#"<?xml version="1.0"encoding="UTF-8"?><doc/>"#).is_err());
+        assert!(XMLDocument::try_from(r#"<?xml version="1.0"standalone="yes"?><doc/>"#).is_err());
+    }
+
+    #[test]
+    fn comment_chars() {
+        assert!(XMLDocument::try_from("<!-- ok \u{e9} --><doc/>").is_ok());
+        assert!(XMLDocument::try_from("<!-- \u{1} --><doc/>").is_err());
+        assert!(XMLDocument::try_from("<doc><!-- \u{fffe} --></doc>").is_err());
+    }
+
+    #[test]
+    fn nested_entities() {
+        let expand = |doc: &str| -> Result<String, Error> {
+            let mut f = crate::forest::Forest::new();
+            let t = f.grow_tree_with_resolver(doc, None, &MemoryResolver::new())?;
+            Ok(f.get_ref(t).unwrap().get_doc_node().to_xml(&f))
+        };
+        assert_eq!(
+            expand("<!DOCTYPE d [<!ENTITY a '&b;&b;'><!ENTITY b '<e/>'><!ATTLIST e x CDATA 'y'>]><d>&a;</d>")
+                .expect("unable to expand entities"),
+            "<d><e x='y'></e><e x='y'></e></d>"
+        );
+        assert!(
+            expand("<!DOCTYPE d [<!ENTITY a '&b;'><!ENTITY b '&a;'>]><d>&a;</d>")
+                .is_err_and(|e| e.message.starts_with("WFC: No Recursion"))
+        );
+        // An entity that expands to a very large amount of content is an error
+        let mut doc = String::from("<!DOCTYPE d [<!ENTITY e0 'x'>");
+        for i in 1..10 {
+            doc.push_str(
+                format!("<!ENTITY e{} '{}'>", i, format!("&e{};", i - 1).repeat(10)).as_str(),
+            )
+        }
+        doc.push_str("]><d>&e9;</d>");
+        assert!(expand(doc.as_str()).is_err())
+    }
+
+    #[test]
+    fn external_entity_on_reference() {
+        let mut r = MemoryResolver::new();
+        r.insert("mem:///e.ent", "<e/>");
+        let base = Url::parse("mem:///doc.xml").expect("unable to parse URL");
+        let expand = |doc: &str| -> Result<String, Error> {
+            let mut f = crate::forest::Forest::new();
+            let t = f.grow_tree_with_resolver(doc, Some(&base), &r)?;
+            Ok(f.get_ref(t).unwrap().get_doc_node().to_xml(&f))
+        };
+        // An external entity is only fetched when it is referenced
+        assert_eq!(
+            expand("<!DOCTYPE d [<!ENTITY unused SYSTEM 'missing.ent'>]><d/>")
+                .expect("unable to parse document"),
+            "<d></d>"
+        );
+        assert_eq!(
+            expand("<!DOCTYPE d [<!ENTITY e SYSTEM 'e.ent'>]><d>&e;&e;</d>")
+                .expect("unable to parse document"),
+            "<d><e></e><e></e></d>"
+        );
+        assert!(
+            expand("<!DOCTYPE d [<!ENTITY m SYSTEM 'missing.ent'>]><d>&m;</d>")
+                .is_err_and(|e| e.message == "resource \"mem:///missing.ent\" not found")
+        );
+    }
+
+    #[test]
+    fn pi_target() {
+        assert!(XMLDocument::try_from("<doc><?XmL data?></doc>").is_err());
+        assert!(XMLDocument::try_from("<doc><?xml-foo data?></doc>").is_ok());
+    }
+
+    #[test]
+    fn xml_stylesheet() {
+        let s = XmlStylesheet::parse(
+            r#"href="a.xsl?x=1&amp;y=2" type='text/xsl' alternate="yes" other="x""#,
+        )
+        .expect("unable to parse xml-stylesheet");
+        assert_eq!(s.href, "a.xsl?x=1&y=2");
+        assert_eq!(s.media_type, Some(String::from("text/xsl")));
+        assert!(s.alternate);
+        assert!(s.is_xslt());
+        assert!(XmlStylesheet::parse(r#"type="text/css""#).is_err());
+        assert!(XmlStylesheet::parse(r#"href="a.css" alternate="maybe""#).is_err());
+        assert!(XmlStylesheet::parse(r#"href="a.css"#).is_err());
+    }
+
+    #[test]
+    fn external_entity() {
+        let doc = r#"<!DOCTYPE doc [
+<!ENTITY ext SYSTEM "ext.ent">
+]><doc>&ext;</doc>"#;
+        let mut r = MemoryResolver::new();
+        r.insert(
+            "file:///test/ext.ent",
+            "<?xml encoding='UTF-8'?><expansion>external</expansion>",
+        );
+        let mut result = XMLDocument::try_from(doc).expect("failed to parse XML");
+        match &result.prologue[0] {
+            XMLNode::DTD(DTDDecl::ExternalEntity(n, _, s)) => {
+                assert_eq!(n.to_string(), "ext");
+                assert_eq!(s, "ext.ent");
+            }
+            _ => {
+                panic!("prologue contains something other than an external entity declaration")
+            }
+        }
+        result
+            .expand_with_resolver(
+                Some(&Url::parse("file:///test/doc.xml").expect("unable to parse URL")),
+                &r,
+            )
+            .expect("unable to expand entities");
+        match &result.content[0] {
+            XMLNode::Element(_, _, c) => match &c[0] {
+                XMLNode::Element(m, _, d) => {
+                    assert_eq!(m.get_localname(), "expansion");
+                    assert_eq!(d.len(), 1)
+                }
+                _ => panic!("failed to find \"expansion\" element"),
+            },
+            _ => panic!("root is not an element node"),
+        }
+    }
+
+    #[test]
+    fn standalone() {
+        let mut r = MemoryResolver::new();
+        r.insert(
+            "file:///test/doc.dtd",
+            "<!ENTITY ext 'external'><!ENTITY both 'external'>",
+        );
+        let base = Url::parse("file:///test/doc.xml").expect("unable to parse URL");
+        let expand = |sd: &str, content: &str| -> Result<XMLDocument, Error> {
+            let mut d = XMLDocument::try_from(
+                format!(
+                    "<?xml version='1.0'{}?><!DOCTYPE doc SYSTEM 'doc.dtd' [<!ENTITY both 'internal'>]><doc>{}</doc>",
+                    sd, content
+                )
+                .as_str(),
+            )?;
+            d.expand_with_resolver(Some(&base), &r)?;
+            Ok(d)
+        };
+        assert!(expand(" standalone='yes'", "&both;").is_ok());
+        assert!(expand(" standalone='no'", "&ext;").is_ok());
+        assert!(expand("", "&ext;").is_ok());
+        match expand(" standalone='yes'", "&ext;") {
+            Err(e) => assert!(e.to_string().contains("WFC: Entity Declared")),
+            Ok(_) => panic!("external declaration used in a standalone document"),
+        }
+        assert!(expand(" standalone=\"yes\"", "<x a='&ext;'/>").is_err());
+
+        let d = XMLDocument::try_from("<?xml version='1.0' standalone='yes'?><doc/>")
+            .expect("failed to parse XML");
+        assert!(d.is_standalone());
+        assert!(!XMLDocument::try_from("<doc/>")
+            .expect("failed to parse XML")
+            .is_standalone());
+        assert!(XMLDocument::try_from("<?xml version='1.0' standalone='YES'?><doc/>").is_err());
+    }
+
+    #[test]
+    fn expand_no_fetch() {
+        // Nothing is fetched unless a resolver is given
+        let mut m = MemoryResolver::new();
+        m.insert("file:///tmp/secret.txt", "secret");
+        m.insert("file:///tmp/doc.dtd", "<!ENTITY d 'from the DTD'>");
+        let doc = "<!DOCTYPE doc [<!ENTITY x SYSTEM 'file:///tmp/secret.txt'>]><doc>&x;</doc>";
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert!(d.expand().is_err());
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        d.expand_with_resolver(None, &m)
+            .expect("unable to expand entities");
+        match &d.content[0] {
+            XMLNode::Element(_, _, c) => {
+                assert!(matches!(&c[0], XMLNode::Text(t) if t.to_string() == "secret"))
+            }
+            _ => panic!("root is not an element node"),
+        }
+
+        // The external subset is skipped
+        let doc = "<!DOCTYPE doc SYSTEM 'file:///tmp/doc.dtd'><doc>text</doc>";
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        d.expand().expect("unable to expand entities");
+        let mut d =
+            XMLDocument::try_from("<!DOCTYPE doc SYSTEM 'file:///tmp/doc.dtd'><doc>&d;</doc>")
+                .expect("failed to parse XML");
+        assert!(d.expand().is_err());
+
+        // The reference is kept as it is
+        let (d, errors) = parse_recover(
+            "<!DOCTYPE doc [<!ENTITY x SYSTEM 'file:///tmp/secret.txt'>]><doc>&x;</doc>",
+        );
+        assert!(errors.is_empty());
+        match &d.content[0] {
+            XMLNode::Element(_, _, c) => {
+                assert!(matches!(&c[0], XMLNode::Text(t) if t.to_string() == "&x;"))
+            }
+            _ => panic!("root is not an element node"),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn expand_async() {
+        let mut m = MemoryResolver::new();
+        m.insert(
+            "http://example.org/dtd/doc.dtd",
+            "<!ENTITY greeting 'hello'><!ENTITY part SYSTEM 'part.ent'>",
+        );
+        m.insert("http://example.org/dtd/part.ent", "<part/>");
+        m.insert("http://example.org/ext.ent", "external");
+        let r = crate::resolvers::AsyncMemoryResolver::new(m);
+        let base = Url::parse("http://example.org/doc.xml").expect("unable to parse URL");
+        let doc = "<!DOCTYPE doc SYSTEM 'dtd/doc.dtd' [<!ENTITY ext SYSTEM 'ext.ent'>]><doc>&greeting; &ext;&part;</doc>";
+
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        d.expand_async(Some(&base), &r)
+            .await
+            .expect("unable to expand entities");
+        match &d.content[0] {
+            XMLNode::Element(_, _, c) => assert!(matches!(c.last(), Some(XMLNode::Element(..)))),
+            _ => panic!("root is not an element node"),
+        }
+
+        let mut f = crate::forest::Forest::new();
+        let t = f
+            .grow_tree_async(doc, Some(&base), &r)
+            .await
+            .expect("unable to parse XML");
+        assert_eq!(
+            f.get_ref(t).unwrap().get_doc_node().to_xml(&f),
+            "<doc>hello external<part></part></doc>"
+        );
+        assert_eq!(f.dtd_cache().len(), 1);
+        assert_eq!(r.fetched("http://example.org/dtd/doc.dtd"), 2);
+        assert_eq!(r.fetched("http://example.org/dtd/part.ent"), 2);
+        assert!(f
+            .grow_tree_async(
+                "<!DOCTYPE doc [<!ENTITY x SYSTEM 'missing.ent'>]><doc>&x;</doc>",
+                Some(&base),
+                &r
+            )
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn dtd_policy() {
+        // Fails if anything is fetched
+        struct NoFetch;
+        impl Resolver for NoFetch {
+            fn resolve(&self, url: &Url) -> Result<String, Error> {
+                panic!("fetched {}", url)
+            }
+        }
+        let doc = "<!DOCTYPE doc SYSTEM 'doc.dtd' [<!ENTITY int 'internal'><!ENTITY ext SYSTEM 'ext.ent'>]><doc>&int; &ext;</doc>";
+        let base = Url::parse("file:///test/doc.xml").expect("unable to parse URL");
+
+        assert_eq!(ParserConfig::new().dtd, DtdPolicy::Ignore);
+        assert_eq!(ParserConfig::default().dtd, DtdPolicy::Ignore);
+
+        let forbid = ParserConfig::new().dtd_policy(DtdPolicy::Forbid);
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert_eq!(
+            d.doctype.as_ref().map(|n| n.to_string()),
+            Some(String::from("doc"))
+        );
+        assert!(d
+            .expand_with_config(Some(&base), &NoFetch, &forbid)
+            .is_err());
+        let mut f = crate::forest::Forest::new();
+        assert!(f
+            .grow_tree_with_config("<!DOCTYPE doc><doc/>", &forbid)
+            .is_err());
+        assert!(f.grow_tree_with_config("<doc/>", &forbid).is_ok());
+
+        let ignore = ParserConfig::new()
+            .dtd_policy(DtdPolicy::Ignore)
+            .unknown_entity(UnknownEntity::Literal);
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        d.expand_with_config(Some(&base), &NoFetch, &ignore)
+            .expect("unable to expand entities");
+        match &d.content[0] {
+            XMLNode::Element(_, _, c) => assert_eq!(
+                c.iter()
+                    .map(|t| match t {
+                        XMLNode::Text(v) => v.to_string(),
+                        _ => panic!("expected text"),
+                    })
+                    .collect::<String>(),
+                "internal &ext;"
+            ),
+            _ => panic!("root is not an element node"),
+        }
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert!(d
+            .expand_with_config(
+                Some(&base),
+                &NoFetch,
+                &ParserConfig::new().dtd_policy(DtdPolicy::Ignore)
+            )
+            .is_err());
+        let mut d = XMLDocument::try_from(doc).expect("failed to parse XML");
+        assert!(d
+            .expand_with_config(Some(&base), &NoFetch, &ParserConfig::new())
+            .is_err());
+    }
+
+    #[test]
+    fn grow_tree_internal_subset() {
+        let doc =
+            "<!DOCTYPE a [<!ENTITY e 'x'><!ATTLIST a b CDATA 'dflt'>]><a>&e;<!--c--><?p d?></a>";
+        let mut f = crate::forest::Forest::new();
+        let xml = |f: &crate::forest::Forest, t| f.get_ref(t).unwrap().get_doc_node().to_xml(f);
+        // The internal subset is applied unless the document type declaration is forbidden, and nothing is fetched
+        for p in [DtdPolicy::Ignore, DtdPolicy::Process] {
+            let t = f
+                .grow_tree_with_config(doc, &ParserConfig::new().dtd_policy(p))
+                .expect("unable to parse XML");
+            assert_eq!(xml(&f, t), "<a b='dflt'>x<!--c--><?p d?></a>");
+        }
+        let t = f.grow_tree(doc).expect("unable to parse XML");
+        assert_eq!(xml(&f, t), "<a b='dflt'>x<!--c--><?p d?></a>");
+        assert!(f
+            .grow_tree_with_config(
+                "<!DOCTYPE a [<!ENTITY e SYSTEM 'e.ent'>]><a>&e;</a>",
+                &ParserConfig::new().dtd_policy(DtdPolicy::Process)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn attribute_defaults() {
+        let mut d = XMLDocument::try_from(
+            "<!DOCTYPE doc [
+<!ELEMENT doc (p | (q, p?)+)*>
+<!ELEMENT p (#PCDATA | q)*>
+<!ELEMENT q EMPTY>
+<!ENTITY e 'entity'>
+<!ATTLIST p
+    id ID #IMPLIED
+    class CDATA ' a  b '
+    tokens NMTOKENS '  x   y '
+    version CDATA #FIXED '1.0'
+    text CDATA 'from an &e;'>
+<!ATTLIST p class CDATA 'ignored' align (left | right) 'left' fmt NOTATION (n) #REQUIRED>
+<!ATTLIST q tokens NMTOKENS #IMPLIED>
+]>
+<doc><p tokens=' z  ' class='c&#32;&#32;d'/><q tokens=' &#9;a  b '/></doc>",
+        )
+        .expect("failed to parse XML");
+        d.expand().expect("unable to expand entities");
+        let mut f = crate::forest::Forest::new();
+        let t = f.grow_tree_from_document(d).expect("unable to create tree");
+        let doc = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut it = doc.child_iter();
+        let children = std::iter::from_fn(|| it.next(&f)).collect::<Vec<_>>();
+        let attr = |i: usize, n: &str| {
+            children[i]
+                .get_attribute(&f, &QualifiedName::new(None, None, String::from(n)))
+                .map(|a| a.to_string(&f))
+        };
+        // Specified values are normalised according to their type
+        assert_eq!(attr(0, "tokens"), Some(String::from("z")));
+        assert_eq!(attr(0, "class"), Some(String::from("c  d")));
+        // Default and fixed values are added, but not implied ones
+        assert_eq!(attr(0, "id"), None);
+        assert_eq!(attr(0, "version"), Some(String::from("1.0")));
+        assert_eq!(attr(0, "text"), Some(String::from("from an entity")));
+        // The first declaration of an attribute is binding
+        assert_eq!(attr(0, "align"), Some(String::from("left")));
+        // Spaces from character references are also normalised, but not other whitespace
+        assert_eq!(attr(1, "tokens"), Some(String::from("\ta b")));
+        assert_eq!(attr(1, "class"), None);
+    }
+
+    #[test]
+    fn attribute_defaults_normalised() {
+        let mut d = XMLDocument::try_from(
+            "<!DOCTYPE doc [<!ATTLIST doc a1 NMTOKENS ' x  y ' a2 CDATA ' x  y '>]><doc/>",
+        )
+        .expect("failed to parse XML");
+        d.expand().expect("unable to expand entities");
+        match &d.content[0] {
+            XMLNode::Element(_, a, _) => {
+                let v = a
+                    .iter()
+                    .filter_map(|a| match a {
+                        XMLNode::Attribute(n, v) => Some((n.to_string(), v.to_string())),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    v,
+                    vec![
+                        (String::from("a1"), String::from("x y")),
+                        (String::from("a2"), String::from(" x  y "))
+                    ]
+                )
+            }
+            _ => panic!("root is not an element node"),
+        }
+    }
+
+    #[test]
+    fn attribute_defaults_grow_tree() {
+        let doc = "<!DOCTYPE a [<!ATTLIST a b CDATA 'dflt'>]><a/>";
+        let mut f = crate::forest::Forest::new();
+        let t = f.grow_tree(doc).expect("unable to parse XML");
+        assert_eq!(
+            f.get_ref(t).unwrap().get_doc_node().to_xml(&f),
+            "<a b='dflt'></a>"
+        );
+        let t = f
+            .grow_tree_with_config(doc, &ParserConfig::new())
+            .expect("unable to parse XML");
+        assert_eq!(
+            f.get_ref(t).unwrap().get_doc_node().to_xml(&f),
+            "<a b='dflt'></a>"
+        );
+    }
+
+    #[test]
+    fn dtd_cache() {
+        // Records the URLs that are fetched
+        struct Counting(MemoryResolver, std::cell::RefCell<Vec<String>>);
+        impl Resolver for Counting {
+            fn resolve(&self, url: &Url) -> Result<String, Error> {
+                self.1.borrow_mut().push(url.to_string());
+                self.0.resolve(url)
+            }
+        }
+        let mut m = MemoryResolver::new();
+        m.insert(
+            "file:///test/dtd/doc.dtd",
+            "<?xml encoding='UTF-8'?>
+<!ENTITY greeting 'hello'>
+<!ENTITY who 'world'>
+<!ELEMENT doc (#PCDATA | part)*>
+<!ATTLIST doc id ID #IMPLIED title CDATA 'a > b'>
+<!ENTITY part SYSTEM 'part.ent'>",
+        );
+        m.insert("file:///test/dtd/part.ent", "<part/>");
+        let r = Counting(m, std::cell::RefCell::new(vec![]));
+        let base = Url::parse("file:///test/doc.xml").expect("unable to parse URL");
+        let mut f = crate::forest::Forest::new();
+        let xml = |f: &crate::forest::Forest, t| {
+            f.get_ref(t)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(f)
+                .unwrap()
+                .to_xml(f)
+        };
+
+        let t = f
+            .grow_tree_with_resolver(
+                "<!DOCTYPE doc SYSTEM 'dtd/doc.dtd'><doc>&greeting; &who;&part;</doc>",
+                Some(&base),
+                &r,
+            )
+            .expect("unable to parse XML");
+        // The default value of the title attribute is declared in the DTD
+        assert_eq!(
+            xml(&f, t),
+            "<doc title='a > b'>hello world<part></part></doc>"
+        );
+        // The declarations in the internal subset take precedence
+        let t = f
+            .grow_tree_with_resolver(
+                "<!DOCTYPE doc PUBLIC '-//Test//DTD doc//EN' 'dtd/doc.dtd' [<!ENTITY who 'there'>]><doc>&greeting; &who;</doc>",
+                Some(&base),
+                &r,
+            )
+            .expect("unable to parse XML");
+        assert_eq!(xml(&f, t), "<doc title='a > b'>hello there</doc>");
+        // The DTD was only fetched once
+        let fetched = r.1.borrow();
+        assert_eq!(
+            fetched
+                .iter()
+                .filter(|u| u.as_str() == "file:///test/dtd/doc.dtd")
+                .count(),
+            1
+        );
+        assert_eq!(f.dtd_cache().len(), 1);
+        // The cache may be moved to another thread
+        let cache = f.dtd_cache().clone();
+        assert_eq!(std::thread::spawn(move || cache.len()).join().unwrap(), 1);
+        assert!(f
+            .dtd_cache()
+            .contains(&Url::parse("file:///test/dtd/doc.dtd").unwrap()));
+        f.dtd_cache().clear();
+        assert!(f.dtd_cache().is_empty());
+        assert!(extsubset("<!ELEMENT-ish>").is_err());
+
+        // Without a base URL the external subset is skipped
+        let t = f
+            .grow_tree_with_resolver(
+                "<!DOCTYPE doc SYSTEM 'dtd/doc.dtd' [<!ENTITY who 'there'>]><doc>&who;</doc>",
+                None,
+                &r,
+            )
+            .expect("unable to parse XML");
+        assert_eq!(xml(&f, t), "<doc>there</doc>");
+        assert!(f.dtd_cache().is_empty());
+        let mut d = XMLDocument::try_from("<!DOCTYPE doc SYSTEM 'doc.dtd'><doc/>")
+            .expect("failed to parse XML");
+        d.expand().expect("unable to expand entities");
+        d.expand_with_resolver(None, &MemoryResolver::new())
+            .expect("unable to expand entities");
+    }
+
+    #[test]
+    fn recover() {
+        let (doc, errors) = parse_recover(
+            "<?xml version='1.0'?>\n<!-- c -->\n<a x='1' x='2'><b>one\u{1}<c></b>&bogus; & < two</d></a>",
+        );
+        assert!(doc.xmldecl.is_some());
+        assert_eq!(doc.prologue.len(), 1);
+        let messages: Vec<String> = errors.iter().map(|e| e.message.clone()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "line 3, column 9: duplicate attribute \"x\"",
+                "line 3, column 22: character is not allowed in XML",
+                "line 3, column 26: element \"c\" is not closed",
+                "line 3, column 38: malformed reference",
+                "line 3, column 40: \"<\" is not the start of markup",
+                "line 3, column 45: end tag \"d\" does not match an open element",
+            ]
+        );
+        match &doc.content[0] {
+            XMLNode::Element(n, a, c) => {
+                assert_eq!(n.to_string(), "a");
+                assert_eq!(a.len(), 1);
+                // The undeclared entity is kept as literal text
+                assert_eq!(c.len(), 3);
+                match (&c[1], &c[2]) {
+                    (XMLNode::Text(t), XMLNode::Text(u)) => {
+                        assert_eq!(t.to_string(), "&bogus;");
+                        assert_eq!(u.to_string(), " & < two")
+                    }
+                    _ => panic!("not a text node"),
+                }
+            }
+            _ => panic!("root is not an element node"),
+        }
+    }
+
+    #[test]
+    fn recover_unclosed() {
+        let (doc, errors) = parse_recover("<a><b>text");
+        assert_eq!(errors.len(), 2);
+        assert!(doc.content.len() == 1);
+        let (_, errors) = parse_recover("<a/><b/>text");
+        assert_eq!(errors.len(), 2);
+        let (_, errors) = parse_recover("<a></a é>");
+        assert_eq!(errors.len(), 1);
+        let (doc, errors) = parse_recover("<a><b/></a>");
+        assert!(errors.is_empty());
+        assert!(doc.content.len() == 1)
+    }
 }
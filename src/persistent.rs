@@ -0,0 +1,549 @@
+/*! ## Persistent trees
+
+A [PersistentTree] is an immutable tree. Editing it returns a new tree, and the new tree shares every node that was not changed with the original, so keeping old versions is cheap. [History] keeps versions of a tree for undo and redo.
+
+Nodes are reference counted with [Arc], so a tree may be shared between threads.
+
+A node is identified by its path: the position of each node in its parent's list of children, starting from the Document-type node. The empty path identifies the Document-type node.
+
+Persistent trees are converted to and from [Forest] trees for querying, transforming and serialising.
+
+```rust
+use xrust::forest::Forest;
+use xrust::persistent::{History, PersistentNode, PersistentTree};
+use xrust::qname::QualifiedName;
+use xrust::value::Value;
+
+let mut f = Forest::new();
+let src = f.grow_tree("<list><item>one</item></list>").expect("unable to parse XML");
+let doc = f.get_ref(src).unwrap().get_doc_node();
+let mut h = History::new(PersistentTree::from_node(&f, doc).expect("unable to copy tree"));
+
+// Add a second item to the list
+let item = PersistentNode::element(QualifiedName::new(None, None, String::from("item")))
+    .with_child(PersistentNode::text(Value::from("two")));
+let edited = h.current().insert_child(&[0], 1, item).expect("unable to insert item");
+h.commit(edited);
+
+let t = h.current().to_forest(&mut f).expect("unable to create tree");
+assert_eq!(
+    f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+    "<list><item>one</item><item>two</item></list>"
+);
+
+h.undo();
+assert_eq!(h.current().get(&[0]).unwrap().children().len(), 1);
+```
+*/
+
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::qname::QualifiedName;
+use crate::value::Value;
+use crate::xdmerror::*;
+use std::sync::Arc;
+
+/// A node in a [PersistentTree]. Nodes are immutable once they are part of a tree.
+#[derive(Clone)]
+pub struct PersistentNode {
+    t: NodeType,
+    name: Option<QualifiedName>,
+    value: Option<Value>,
+    attributes: Vec<(QualifiedName, Value)>,
+    children: Vec<Arc<PersistentNode>>,
+}
+
+impl PersistentNode {
+    fn new(t: NodeType) -> Self {
+        PersistentNode {
+            t,
+            name: None,
+            value: None,
+            attributes: vec![],
+            children: vec![],
+        }
+    }
+    /// Create an Element-type node.
+    pub fn element(name: QualifiedName) -> Self {
+        PersistentNode {
+            name: Some(name),
+            ..PersistentNode::new(NodeType::Element)
+        }
+    }
+    /// Create a Text-type node.
+    pub fn text(v: Value) -> Self {
+        PersistentNode {
+            value: Some(v),
+            ..PersistentNode::new(NodeType::Text)
+        }
+    }
+    /// Create a Comment-type node.
+    pub fn comment(v: Value) -> Self {
+        PersistentNode {
+            value: Some(v),
+            ..PersistentNode::new(NodeType::Comment)
+        }
+    }
+    /// Create a ProcessingInstruction-type node.
+    pub fn processing_instruction(name: QualifiedName, v: Value) -> Self {
+        PersistentNode {
+            name: Some(name),
+            value: Some(v),
+            ..PersistentNode::new(NodeType::ProcessingInstruction)
+        }
+    }
+    /// Add an attribute, replacing any attribute with the same name. Only elements have attributes.
+    pub fn with_attribute(mut self, name: QualifiedName, v: Value) -> Self {
+        self.set_attribute(name, v);
+        self
+    }
+    /// Add a child as the last child of this node.
+    pub fn with_child(mut self, c: PersistentNode) -> Self {
+        self.children.push(Arc::new(c));
+        self
+    }
+
+    pub fn node_type(&self) -> NodeType {
+        self.t
+    }
+    pub fn name(&self) -> Option<&QualifiedName> {
+        self.name.as_ref()
+    }
+    pub fn value(&self) -> Option<&Value> {
+        self.value.as_ref()
+    }
+    /// The attributes of an element, in the order in which they were added.
+    pub fn attributes(&self) -> &[(QualifiedName, Value)] {
+        &self.attributes
+    }
+    pub fn get_attribute(&self, name: &QualifiedName) -> Option<&Value> {
+        self.attributes
+            .iter()
+            .find(|(qn, _)| qn == name)
+            .map(|(_, v)| v)
+    }
+    pub fn children(&self) -> &[Arc<PersistentNode>] {
+        &self.children
+    }
+
+    fn set_attribute(&mut self, name: QualifiedName, v: Value) {
+        match self.attributes.iter_mut().find(|(qn, _)| *qn == name) {
+            Some(a) => a.1 = v,
+            None => self.attributes.push((name, v)),
+        }
+    }
+
+    // Copy a node, and its descendants, from a Forest tree.
+    // Entity references are replaced by their expansion.
+    fn from_node(f: &Forest, n: Node, parent: &mut Vec<Arc<PersistentNode>>) -> Result<(), Error> {
+        let mut p = match n.node_type(f) {
+            NodeType::Document => PersistentNode::new(NodeType::Document),
+            NodeType::Element => {
+                let mut e = PersistentNode::element(n.to_name(f));
+                let mut ait = n.attribute_iter(f);
+                while let Some(a) = ait.next() {
+                    e.attributes.push((a.to_name(f), a.to_value(f)))
+                }
+                e
+            }
+            NodeType::Text => PersistentNode::text(n.to_value(f)),
+            NodeType::Comment => PersistentNode::comment(n.to_value(f)),
+            NodeType::ProcessingInstruction => {
+                PersistentNode::processing_instruction(n.to_name(f), n.to_value(f))
+            }
+            NodeType::EntityReference => {
+                let mut cit = n.child_iter();
+                while let Some(c) = cit.next(f) {
+                    PersistentNode::from_node(f, c, parent)?
+                }
+                return Ok(());
+            }
+            NodeType::Attribute | NodeType::Unknown => {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    format!(
+                        "cannot copy a node of type {} into a persistent tree",
+                        n.node_type(f).to_string()
+                    ),
+                ))
+            }
+        };
+        let mut cit = n.child_iter();
+        while let Some(c) = cit.next(f) {
+            PersistentNode::from_node(f, c, &mut p.children)?
+        }
+        parent.push(Arc::new(p));
+        Ok(())
+    }
+
+    // Create a node, and its descendants, in a Forest tree.
+    fn to_node(&self, f: &mut Forest, t: TreeIndex) -> Result<Node, Error> {
+        let d = f.get_ref_mut(t).ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?;
+        let value = || self.value.clone().unwrap_or_else(|| Value::from(""));
+        let n = match self.t {
+            NodeType::Element => d.new_element(self.name.clone().unwrap())?,
+            NodeType::Text => d.new_text(value())?,
+            NodeType::Comment => d.new_comment(value())?,
+            NodeType::ProcessingInstruction => {
+                d.new_processing_instruction(self.name.clone().unwrap(), value())?
+            }
+            _ => {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    format!("cannot create a node of type {}", self.t.to_string()),
+                ))
+            }
+        };
+        for (qn, v) in &self.attributes {
+            let a = f
+                .get_ref_mut(t)
+                .unwrap()
+                .new_attribute(qn.clone(), v.clone())?;
+            n.add_attribute(f, a)?
+        }
+        for c in &self.children {
+            let m = c.to_node(f, t)?;
+            n.append_child(f, m)?
+        }
+        Ok(n)
+    }
+}
+
+/// An immutable tree. Each edit returns a new tree that shares the unchanged nodes with this one.
+#[derive(Clone)]
+pub struct PersistentTree {
+    root: Arc<PersistentNode>,
+}
+
+impl Default for PersistentTree {
+    fn default() -> Self {
+        PersistentTree::new()
+    }
+}
+
+impl PersistentTree {
+    /// Create a tree with a single Document-type node.
+    pub fn new() -> Self {
+        PersistentTree {
+            root: Arc::new(PersistentNode::new(NodeType::Document)),
+        }
+    }
+    /// Copy a node from a [Forest] tree. If the node is not a Document-type node then it becomes the only child of the Document-type node of the new tree.
+    pub fn from_node(f: &Forest, n: Node) -> Result<Self, Error> {
+        let mut v = vec![];
+        PersistentNode::from_node(f, n, &mut v)?;
+        let root = match v.pop() {
+            Some(r) if r.t == NodeType::Document => r,
+            Some(r) => {
+                let mut d = PersistentNode::new(NodeType::Document);
+                d.children.append(&mut v);
+                d.children.push(r);
+                Arc::new(d)
+            }
+            None => Arc::new(PersistentNode::new(NodeType::Document)),
+        };
+        Ok(PersistentTree { root })
+    }
+    /// Create a [Forest] tree with a copy of this tree. The new tree is in fragment mode if the document does not have exactly one element.
+    pub fn to_forest(&self, f: &mut Forest) -> Result<TreeIndex, Error> {
+        let elements = self
+            .root
+            .children
+            .iter()
+            .filter(|c| c.t == NodeType::Element)
+            .count();
+        let t = if elements == 1 && self.root.children.iter().all(|c| c.t != NodeType::Text) {
+            f.plant_tree()
+        } else {
+            f.plant_fragment()
+        };
+        for c in &self.root.children {
+            let n = c.to_node(f, t)?;
+            f.get_ref_mut(t).unwrap().push_doc_node(n)?
+        }
+        Ok(t)
+    }
+
+    /// The Document-type node.
+    pub fn root(&self) -> &PersistentNode {
+        &self.root
+    }
+    /// The node at the given path.
+    pub fn get(&self, path: &[usize]) -> Option<&PersistentNode> {
+        let mut n = &*self.root;
+        for i in path {
+            n = n.children.get(*i)?
+        }
+        Some(n)
+    }
+    /// Whether the node at the given path is the same node, rather than a copy, in both trees.
+    pub fn shares(&self, other: &PersistentTree, path: &[usize]) -> bool {
+        let mut n = &self.root;
+        let mut m = &other.root;
+        for i in path {
+            match (n.children.get(*i), m.children.get(*i)) {
+                (Some(c), Some(d)) => {
+                    n = c;
+                    m = d
+                }
+                _ => return false,
+            }
+        }
+        Arc::ptr_eq(n, m)
+    }
+
+    /// Insert a node as a child of the node at the given path, at the given position in its list of children.
+    pub fn insert_child(
+        &self,
+        path: &[usize],
+        posn: usize,
+        c: PersistentNode,
+    ) -> Result<Self, Error> {
+        if c.t == NodeType::Document {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("a Document-type node cannot be a child"),
+            ));
+        }
+        self.edit(path, |n| {
+            if !matches!(n.t, NodeType::Document | NodeType::Element) {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    format!("a node of type {} cannot have children", n.t.to_string()),
+                ));
+            }
+            if posn > n.children.len() {
+                return Result::Err(path_error());
+            }
+            n.children.insert(posn, Arc::new(c));
+            Ok(())
+        })
+    }
+    /// Remove the node at the given path. The Document-type node cannot be removed.
+    pub fn remove(&self, path: &[usize]) -> Result<Self, Error> {
+        let (last, parent) = path.split_last().ok_or(Error::new(
+            ErrorKind::TypeError,
+            String::from("the Document-type node cannot be removed"),
+        ))?;
+        self.edit(parent, |n| {
+            if *last >= n.children.len() {
+                return Result::Err(path_error());
+            }
+            n.children.remove(*last);
+            Ok(())
+        })
+    }
+    /// Replace the node at the given path.
+    pub fn replace(&self, path: &[usize], r: PersistentNode) -> Result<Self, Error> {
+        let (last, parent) = path.split_last().ok_or(Error::new(
+            ErrorKind::TypeError,
+            String::from("the Document-type node cannot be replaced"),
+        ))?;
+        if r.t == NodeType::Document {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("a Document-type node cannot be a child"),
+            ));
+        }
+        self.edit(parent, |n| match n.children.get_mut(*last) {
+            Some(c) => {
+                *c = Arc::new(r);
+                Ok(())
+            }
+            None => Result::Err(path_error()),
+        })
+    }
+    /// Set an attribute of the element at the given path, replacing any attribute with the same name.
+    pub fn set_attribute(
+        &self,
+        path: &[usize],
+        name: QualifiedName,
+        v: Value,
+    ) -> Result<Self, Error> {
+        self.edit(path, |n| {
+            if n.t != NodeType::Element {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    String::from("only elements have attributes"),
+                ));
+            }
+            n.set_attribute(name, v);
+            Ok(())
+        })
+    }
+
+    // Copy the nodes on the path, and change the last one.
+    // The other nodes are shared with this tree.
+    fn edit(
+        &self,
+        path: &[usize],
+        e: impl FnOnce(&mut PersistentNode) -> Result<(), Error>,
+    ) -> Result<Self, Error> {
+        fn edit_at(
+            n: &PersistentNode,
+            path: &[usize],
+            e: impl FnOnce(&mut PersistentNode) -> Result<(), Error>,
+        ) -> Result<Arc<PersistentNode>, Error> {
+            let mut m = n.clone();
+            match path.split_first() {
+                None => e(&mut m)?,
+                Some((i, rest)) => {
+                    let c = m.children.get(*i).ok_or_else(path_error)?;
+                    m.children[*i] = edit_at(c, rest, e)?
+                }
+            }
+            Ok(Arc::new(m))
+        }
+        Ok(PersistentTree {
+            root: edit_at(&self.root, path, e)?,
+        })
+    }
+}
+
+fn path_error() -> Error {
+    Error::new(
+        ErrorKind::Unknown,
+        String::from("there is no node at the given path"),
+    )
+}
+
+/// The versions of a [PersistentTree], for undo and redo.
+#[derive(Clone)]
+pub struct History {
+    versions: Vec<PersistentTree>,
+    current: usize,
+}
+
+impl History {
+    pub fn new(t: PersistentTree) -> Self {
+        History {
+            versions: vec![t],
+            current: 0,
+        }
+    }
+    /// The current version of the tree.
+    pub fn current(&self) -> &PersistentTree {
+        &self.versions[self.current]
+    }
+    /// Make a new version the current version. Versions that were undone are discarded.
+    pub fn commit(&mut self, t: PersistentTree) {
+        self.versions.truncate(self.current + 1);
+        self.versions.push(t);
+        self.current += 1
+    }
+    /// Go back to the previous version. Returns false if there is no previous version.
+    pub fn undo(&mut self) -> bool {
+        if self.current == 0 {
+            false
+        } else {
+            self.current -= 1;
+            true
+        }
+    }
+    /// Go forward to the version that was last undone. Returns false if there is no such version.
+    pub fn redo(&mut self) -> bool {
+        if self.current + 1 < self.versions.len() {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qn(s: &str) -> QualifiedName {
+        QualifiedName::new(None, None, String::from(s))
+    }
+
+    #[test]
+    fn structural_sharing() {
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<a><b x='1'><c/></b><d>text<!--note--></d></a>")
+            .expect("unable to parse XML");
+        let doc = f.get_ref(src).unwrap().get_doc_node();
+        let t = PersistentTree::from_node(&f, doc).expect("unable to copy tree");
+        assert_eq!(t.get(&[0, 1, 1]).unwrap().node_type(), NodeType::Comment);
+
+        let u = t
+            .set_attribute(&[0, 0], qn("x"), Value::from("2"))
+            .expect("unable to set attribute");
+        assert_eq!(
+            u.get(&[0, 0])
+                .unwrap()
+                .get_attribute(&qn("x"))
+                .map(|v| v.to_string()),
+            Some(String::from("2"))
+        );
+        // The original is unchanged
+        assert_eq!(
+            t.get(&[0, 0])
+                .unwrap()
+                .get_attribute(&qn("x"))
+                .map(|v| v.to_string()),
+            Some(String::from("1"))
+        );
+        // Only the nodes on the path were copied
+        assert!(!t.shares(&u, &[0, 0]));
+        assert!(t.shares(&u, &[0, 0, 0]));
+        assert!(t.shares(&u, &[0, 1]));
+
+        let v = u.remove(&[0, 1, 1]).expect("unable to remove node");
+        let w = v
+            .replace(&[0, 0, 0], PersistentNode::element(qn("e")))
+            .expect("unable to replace node");
+        assert!(w.remove(&[]).is_err());
+        assert!(w
+            .insert_child(&[0, 1, 0], 0, PersistentNode::element(qn("f")))
+            .is_err());
+        assert!(w.remove(&[0, 5]).is_err());
+
+        let r = w.to_forest(&mut f).expect("unable to create tree");
+        assert_eq!(
+            f.get_ref(r)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f),
+            "<a><b x='2'><e></e></b><d>text</d></a>"
+        )
+    }
+
+    #[test]
+    fn history() {
+        fn shared<T: Send + Sync>(_: &T) {}
+        let t = PersistentTree::new()
+            .insert_child(&[], 0, PersistentNode::element(qn("a")))
+            .expect("unable to insert element");
+        shared(&t);
+        let mut h = History::new(t);
+        for i in 0..3 {
+            let u = h
+                .current()
+                .insert_child(&[0], i, PersistentNode::element(qn("b")))
+                .expect("unable to insert element");
+            h.commit(u)
+        }
+        let count = |h: &History| h.current().get(&[0]).unwrap().children().len();
+        assert_eq!(count(&h), 3);
+        assert!(h.undo() && h.undo());
+        assert_eq!(count(&h), 1);
+        assert!(h.redo());
+        assert_eq!(count(&h), 2);
+        // A new version discards the versions that were undone
+        let u = h.current().remove(&[0, 0]).expect("unable to remove node");
+        h.commit(u);
+        assert!(!h.redo());
+        assert_eq!(count(&h), 1);
+        assert!(h.undo() && h.undo() && h.undo());
+        assert!(!h.undo());
+        assert_eq!(count(&h), 0)
+    }
+}
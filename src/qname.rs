@@ -2,9 +2,15 @@
 //!
 //! Support for Qualified Names.
 
+use crate::parsecommon::ncname;
 use core::hash::{Hash, Hasher};
 use std::collections::HashMap;
 
+/// The namespace URI that is bound to the "xml" prefix.
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+/// The XML Schema instance namespace URI, which is usually bound to the "xsi" prefix.
+pub const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
 #[derive(Clone, Debug)]
 pub struct QualifiedName {
     nsuri: Option<String>,
@@ -48,6 +54,24 @@ impl QualifiedName {
         result.push_str(self.localname.as_str());
         result
     }
+    /// Are the prefix (if any) and local name valid NCNames?
+    pub fn is_valid(&self) -> bool {
+        self.prefix.as_ref().is_none_or(|p| is_valid_ncname(p))
+            && is_valid_ncname(self.localname.as_str())
+    }
+}
+
+/// Is the string a valid NCName, i.e. an XML name that does not contain a colon?
+pub fn is_valid_ncname(s: &str) -> bool {
+    matches!(ncname(s), Ok(("", _)))
+}
+
+/// Is the string a valid QName, i.e. an NCName that may be preceded by a prefix (also an NCName) and a colon?
+pub fn is_valid_qname(s: &str) -> bool {
+    match s.split_once(':') {
+        Some((p, l)) => is_valid_ncname(p) && is_valid_ncname(l),
+        None => is_valid_ncname(s),
+    }
 }
 
 pub type QHash<T> = HashMap<QualifiedName, T>;
@@ -84,6 +108,23 @@ impl Hash for QualifiedName {
 mod tests {
     use super::*;
 
+    #[test]
+    fn valid_names() {
+        assert!(is_valid_ncname("Test"));
+        assert!(is_valid_ncname("_a-1.b"));
+        assert!(!is_valid_ncname(""));
+        assert!(!is_valid_ncname("1a"));
+        assert!(!is_valid_ncname("a:b"));
+        assert!(!is_valid_ncname("a b"));
+        assert!(is_valid_qname("a:b"));
+        assert!(is_valid_qname("b"));
+        assert!(!is_valid_qname("a:"));
+        assert!(!is_valid_qname(":b"));
+        assert!(!is_valid_qname("a:b:c"));
+        assert!(QualifiedName::new(None, Some(String::from("x")), String::from("y")).is_valid());
+        assert!(!QualifiedName::new(None, None, String::from("x y")).is_valid())
+    }
+
     #[test]
     fn unqualified() {
         assert_eq!(
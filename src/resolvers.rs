@@ -0,0 +1,1077 @@
+/*! ## Resolving and fetching external resources
+
+Stylesheet modules (xsl:include, xsl:import) and other external resources are identified by a URL. A [Resolver] is responsible for retrieving the content of the resource.
+
+The [DefaultResolver] supports the "file" scheme, and with the "http" feature enabled the "http" and "https" schemes.
+
+A [MemoryResolver] serves resources from memory, which is useful for testing.
+
+A [NoFetchResolver] doesn't fetch anything. It is used where no resolver has been given, by [XMLDocument::expand](../parsexml/struct.XMLDocument.html#method.expand) and by the document() and unparsed-text() functions of an [Evaluator](../evaluate/struct.Evaluator.html), so that external resources are only fetched when a resolver is given explicitly.
+
+An [HttpResolver] only fetches "http" and "https" URLs for the hosts that have been allowed, using a pluggable [HttpClient]. Redirections are only followed to allowed hosts.
+
+An [AccessPolicy] restricts the resources that any resolver may fetch, by URL scheme, host and size. Wrap a resolver in a [PolicyResolver], and use it wherever a resolver is needed (external entities and the external DTD subset, xsl:include and xsl:import, the document() function, and XInclude), so that one policy controls all outbound access.
+
+```rust
+use std::rc::Rc;
+use url::Url;
+use xrust::resolvers::{AccessPolicy, MemoryResolver, PolicyResolver, Resolver};
+
+let mut m = MemoryResolver::new();
+m.insert("https://example.org/a.xml", "<a/>");
+m.insert("file:///etc/passwd", "secret");
+let mut policy = AccessPolicy::new();
+policy.allow_scheme("https");
+policy.allow_host("example.org");
+policy.set_max_bytes(1024);
+let r = Rc::new(PolicyResolver::new(policy, m));
+
+assert!(r.resolve(&Url::parse("https://example.org/a.xml").unwrap()).is_ok());
+assert!(r.resolve(&Url::parse("file:///etc/passwd").unwrap()).is_err());
+// The same resolver can be given to an Evaluator for the document() function
+// ev.set_resolver(r.clone());
+```
+
+With the "async" feature enabled, the [AsyncResolver] trait allows resources to be retrieved without blocking the current thread. There are asynchronous counterparts of the functions that fetch resources: [from_document_async](../xslt/fn.from_document_async.html) for stylesheet modules, [transform_async](../xslt/fn.transform_async.html) and [Evaluator::evaluate_async](../evaluate/struct.Evaluator.html#method.evaluate_async) for the document() and unparsed-text() functions, and [XMLDocument::expand_async](../parsexml/struct.XMLDocument.html#method.expand_async) and [Forest::grow_tree_async](../forest/struct.Forest.html#method.grow_tree_async) for external entities and the external DTD subset. They are built on [with_prefetch].
+*/
+
+use crate::xdmerror::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+use url::Url;
+
+/// Retrieves the content of the resource identified by a URL.
+pub trait Resolver {
+    fn resolve(&self, url: &Url) -> Result<String, Error>;
+    /// Retrieve the resource under an [AccessPolicy]: only URLs that the policy allows are fetched, including the target of any redirection, and no more than the maximum size is read. This is used by a [PolicyResolver]. By default the resource is retrieved with [Resolver::resolve] and its size is checked afterwards.
+    fn resolve_with_policy(&self, url: &Url, policy: &AccessPolicy) -> Result<String, Error> {
+        let s = self.resolve(url)?;
+        match policy.max_bytes {
+            Some(n) if s.len() > n => Result::Err(too_large(url, n)),
+            _ => Ok(s),
+        }
+    }
+}
+
+/// A shared resolver, so that the same resolver can be used for all external resources.
+impl<R: Resolver + ?Sized> Resolver for Rc<R> {
+    fn resolve(&self, url: &Url) -> Result<String, Error> {
+        (**self).resolve(url)
+    }
+    fn resolve_with_policy(&self, url: &Url, policy: &AccessPolicy) -> Result<String, Error> {
+        (**self).resolve_with_policy(url, policy)
+    }
+}
+
+fn too_large(url: &Url, n: usize) -> Error {
+    Error::new(
+        ErrorKind::Unknown,
+        format!(
+            "resource \"{}\" is larger than the maximum of {} bytes",
+            url, n
+        ),
+    )
+}
+
+// Read at most n bytes of a resource, and decode it. It is an error if there are more.
+fn read_limited(url: &Url, r: impl Read, n: Option<usize>) -> Result<String, Error> {
+    let mut b = vec![];
+    r.take(n.map_or(u64::MAX, |n| n as u64 + 1))
+        .read_to_end(&mut b)
+        .map_err(|er| Error::new(ErrorKind::Unknown, er.to_string()))?;
+    if let Some(n) = n.filter(|n| b.len() > *n) {
+        return Result::Err(too_large(url, n));
+    }
+    decode(url, &b)
+}
+
+// Decode the content of a resource. A resource that starts with a byte order mark for UTF-16 is decoded as UTF-16, otherwise it must be UTF-8. The byte order mark is not part of the content.
+fn decode(url: &Url, b: &[u8]) -> Result<String, Error> {
+    let utf16 = |b: &[u8], f: fn([u8; 2]) -> u16| {
+        let u: Vec<u16> = b.chunks_exact(2).map(|c| f([c[0], c[1]])).collect();
+        String::from_utf16(&u).ok().filter(|_| b.len() % 2 == 0)
+    };
+    match b {
+        [0xff, 0xfe, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8(rest.to_vec()).ok(),
+        _ => String::from_utf8(b.to_vec()).ok(),
+    }
+    .ok_or_else(|| {
+        Error::new(
+            ErrorKind::Unknown,
+            format!("resource \"{}\" is not valid UTF-8 or UTF-16", url),
+        )
+    })
+}
+
+/// Fetches "file" URLs from the local filesystem and, with the "http" feature enabled, "http"/"https" URLs using a blocking HTTP client.
+#[derive(Clone, Debug, Default)]
+pub struct DefaultResolver;
+
+impl DefaultResolver {
+    pub fn new() -> DefaultResolver {
+        DefaultResolver
+    }
+    // Fetch a resource, reading no more than max_bytes and following redirections only to URLs that are allowed.
+    #[allow(unused_variables)]
+    fn fetch(
+        &self,
+        url: &Url,
+        max_bytes: Option<usize>,
+        allowed: impl Fn(&Url) -> bool,
+    ) -> Result<String, Error> {
+        match url.scheme() {
+            #[cfg(feature = "http")]
+            "http" | "https" => follow_redirects(&ReqwestClient, url, max_bytes, allowed),
+            #[cfg(not(feature = "http"))]
+            "http" | "https" => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!(
+                    "unable to fetch URL \"{}\": the \"http\" feature is not enabled",
+                    url
+                ),
+            )),
+            "file" => fs::File::open(Path::new(url.path()))
+                .map_err(|er| Error::new(ErrorKind::Unknown, er.to_string()))
+                .and_then(|r| read_limited(url, r, max_bytes)),
+            _ => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("unable to fetch URL \"{}\"", url),
+            )),
+        }
+    }
+}
+
+impl Resolver for DefaultResolver {
+    fn resolve(&self, url: &Url) -> Result<String, Error> {
+        self.fetch(url, None, |_| true)
+    }
+    fn resolve_with_policy(&self, url: &Url, policy: &AccessPolicy) -> Result<String, Error> {
+        self.fetch(url, policy.max_bytes, |u| policy.is_allowed(u))
+    }
+}
+
+/// Doesn't fetch any resources: every URL is an error.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoFetchResolver;
+
+impl Resolver for NoFetchResolver {
+    fn resolve(&self, url: &Url) -> Result<String, Error> {
+        Result::Err(Error::new(
+            ErrorKind::Unknown,
+            format!(
+                "fetching URL \"{}\" is not allowed: no resolver has been given",
+                url
+            ),
+        ))
+    }
+}
+
+/// Maps URLs to the content of resources held in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryResolver {
+    resources: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> MemoryResolver {
+        MemoryResolver {
+            resources: HashMap::new(),
+        }
+    }
+    /// Add a resource. Any existing resource for the URL is replaced.
+    pub fn insert(&mut self, url: &str, data: impl Into<Vec<u8>>) {
+        self.resources.insert(url.to_string(), data.into());
+    }
+    pub fn remove(&mut self, url: &str) -> Option<Vec<u8>> {
+        self.resources.remove(url)
+    }
+    pub fn contains(&self, url: &str) -> bool {
+        self.resources.contains_key(url)
+    }
+    pub fn len(&self) -> usize {
+        self.resources.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn resolve(&self, url: &Url) -> Result<String, Error> {
+        match self.resources.get(url.as_str()) {
+            Some(d) => decode(url, d),
+            None => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("resource \"{}\" not found", url),
+            )),
+        }
+    }
+}
+
+/// The most redirections that are followed for one request.
+pub const MAX_REDIRECTS: usize = 10;
+
+/// The response to an HTTP GET request.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HttpResponse {
+    /// The content of the resource
+    Body(String),
+    /// The resource is at another URL
+    Redirect(Url),
+}
+
+/// Performs HTTP GET requests on behalf of an [HttpResolver].
+pub trait HttpClient {
+    /// Fetch the resource, following any redirections.
+    fn get(&self, url: &Url) -> Result<String, Error>;
+    /// Fetch the resource, without following a redirection, so that the caller can decide whether to follow it. By default this uses [HttpClient::get].
+    fn get_response(&self, url: &Url) -> Result<HttpResponse, Error> {
+        self.get(url).map(HttpResponse::Body)
+    }
+    /// As [HttpClient::get_response], but it is an error if the body is larger than the given number of bytes. A client should stop reading the body once it is too large. By default the size of the body is checked after it has been fetched with [HttpClient::get_response].
+    fn get_response_limited(
+        &self,
+        url: &Url,
+        max_bytes: Option<usize>,
+    ) -> Result<HttpResponse, Error> {
+        match self.get_response(url)? {
+            HttpResponse::Body(s) if max_bytes.is_some_and(|n| s.len() > n) => {
+                Result::Err(too_large(url, max_bytes.unwrap_or(0)))
+            }
+            r => Ok(r),
+        }
+    }
+}
+
+// Fetch a resource, following redirections only to URLs that are allowed, and reading no more than max_bytes of the body.
+fn follow_redirects<C: HttpClient + ?Sized>(
+    client: &C,
+    url: &Url,
+    max_bytes: Option<usize>,
+    allowed: impl Fn(&Url) -> bool,
+) -> Result<String, Error> {
+    let mut u = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        if !allowed(&u) {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("access to URL \"{}\" is not allowed", u),
+            ));
+        }
+        match client.get_response_limited(&u, max_bytes)? {
+            HttpResponse::Body(s) => return Ok(s),
+            HttpResponse::Redirect(v) => u = v,
+        }
+    }
+    Result::Err(Error::new(
+        ErrorKind::Unknown,
+        format!("too many redirections fetching URL \"{}\"", url),
+    ))
+}
+
+/// An [HttpClient] using the blocking reqwest client. Redirections are not followed by reqwest, so that each one can be checked.
+#[cfg(feature = "http")]
+#[derive(Clone, Debug, Default)]
+pub struct ReqwestClient;
+
+#[cfg(feature = "http")]
+impl HttpClient for ReqwestClient {
+    fn get(&self, url: &Url) -> Result<String, Error> {
+        follow_redirects(self, url, None, |_| true)
+    }
+    fn get_response(&self, url: &Url) -> Result<HttpResponse, Error> {
+        self.get_response_limited(url, None)
+    }
+    fn get_response_limited(
+        &self,
+        url: &Url,
+        max_bytes: Option<usize>,
+    ) -> Result<HttpResponse, Error> {
+        let fetch_error = || {
+            Error::new(
+                ErrorKind::Unknown,
+                format!("unable to fetch href URL \"{}\"", url),
+            )
+        };
+        let response = reqwest::blocking::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| Error::new(ErrorKind::Unknown, e.to_string()))?
+            .get(url.as_str())
+            .send()
+            .map_err(|_| fetch_error())?;
+        if response.status().is_redirection() {
+            return response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| url.join(l).ok())
+                .map(HttpResponse::Redirect)
+                .ok_or_else(fetch_error);
+        }
+        let response = response
+            .error_for_status()
+            .map_err(|e| Error::new(ErrorKind::Unknown, e.to_string()))?;
+        if let Some(n) =
+            max_bytes.filter(|n| response.content_length().is_some_and(|l| l > *n as u64))
+        {
+            return Result::Err(too_large(url, n));
+        }
+        read_limited(url, response, max_bytes).map(HttpResponse::Body)
+    }
+}
+
+/// Resolves "http" and "https" URLs using an [HttpClient].
+/// Only URLs for the hosts that have been allowed are fetched, so nothing is fetched until a host is allowed. A redirection is only followed if its URL is allowed too.
+#[derive(Clone, Debug, Default)]
+pub struct HttpResolver<C: HttpClient> {
+    client: C,
+    allowed: Vec<String>,
+}
+
+#[cfg(feature = "http")]
+impl HttpResolver<ReqwestClient> {
+    pub fn new() -> HttpResolver<ReqwestClient> {
+        HttpResolver::with_client(ReqwestClient)
+    }
+}
+
+impl<C: HttpClient> HttpResolver<C> {
+    pub fn with_client(client: C) -> HttpResolver<C> {
+        HttpResolver {
+            client,
+            allowed: vec![],
+        }
+    }
+    /// Allow URLs for the given host to be fetched.
+    pub fn allow_host(&mut self, host: &str) {
+        self.allowed.push(host.to_lowercase())
+    }
+    pub fn get_allowed_hosts(&self) -> Vec<String> {
+        self.allowed.clone()
+    }
+    /// Is the URL permitted to be fetched?
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        match url.scheme() {
+            "http" | "https" => url
+                .host_str()
+                .is_some_and(|h| self.allowed.iter().any(|a| *a == h.to_lowercase())),
+            _ => false,
+        }
+    }
+}
+
+impl<C: HttpClient> Resolver for HttpResolver<C> {
+    fn resolve(&self, url: &Url) -> Result<String, Error> {
+        follow_redirects(&self.client, url, None, |u| self.is_allowed(u))
+    }
+    fn resolve_with_policy(&self, url: &Url, policy: &AccessPolicy) -> Result<String, Error> {
+        follow_redirects(&self.client, url, policy.max_bytes, |u| {
+            self.is_allowed(u) && policy.is_allowed(u)
+        })
+    }
+}
+
+/// Which external resources may be fetched.
+///
+/// Access is denied unless it is allowed: a URL may only be fetched if its scheme has been allowed and, if it has a host, its host has been allowed. A URL without a host, such as a "file" URL, only needs its scheme to be allowed. A maximum size may also be set for each resource. The policy is enforced by a [PolicyResolver].
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    schemes: Vec<String>,
+    hosts: Vec<String>,
+    max_bytes: Option<usize>,
+}
+
+impl AccessPolicy {
+    /// A policy that doesn't allow anything to be fetched.
+    pub fn new() -> AccessPolicy {
+        AccessPolicy::default()
+    }
+    /// Allow URLs with the given scheme, such as "https" or "file", to be fetched.
+    pub fn allow_scheme(&mut self, scheme: &str) {
+        self.schemes.push(scheme.to_lowercase())
+    }
+    pub fn get_allowed_schemes(&self) -> Vec<String> {
+        self.schemes.clone()
+    }
+    /// Allow URLs for the given host to be fetched, if their scheme is allowed.
+    pub fn allow_host(&mut self, host: &str) {
+        self.hosts.push(host.to_lowercase())
+    }
+    pub fn get_allowed_hosts(&self) -> Vec<String> {
+        self.hosts.clone()
+    }
+    /// Set the largest resource, in bytes, that may be fetched.
+    pub fn set_max_bytes(&mut self, n: usize) {
+        self.max_bytes = Some(n)
+    }
+    pub fn get_max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+    /// Is the URL permitted to be fetched?
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        self.schemes.iter().any(|s| s == url.scheme())
+            && url
+                .host_str()
+                .is_none_or(|h| self.hosts.iter().any(|a| *a == h.to_lowercase()))
+    }
+}
+
+/// A [Resolver] that only fetches the resources allowed by an [AccessPolicy], using another resolver.
+/// The resource is retrieved with [Resolver::resolve_with_policy], so that the [DefaultResolver] and [HttpResolver] check the URL of each redirection against the policy and stop reading once the maximum size is exceeded.
+#[derive(Clone, Debug, Default)]
+pub struct PolicyResolver<R: Resolver> {
+    policy: AccessPolicy,
+    resolver: R,
+}
+
+impl<R: Resolver> PolicyResolver<R> {
+    pub fn new(policy: AccessPolicy, resolver: R) -> PolicyResolver<R> {
+        PolicyResolver { policy, resolver }
+    }
+    pub fn get_policy(&self) -> &AccessPolicy {
+        &self.policy
+    }
+}
+
+impl<R: Resolver> Resolver for PolicyResolver<R> {
+    fn resolve(&self, url: &Url) -> Result<String, Error> {
+        if !self.policy.is_allowed(url) {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("access to URL \"{}\" is not allowed", url),
+            ));
+        }
+        self.resolver.resolve_with_policy(url, &self.policy)
+    }
+}
+
+/// Resolve a (possibly relative) reference against an optional base URL. The reference is resolved with [resolve_reference], and the result must be a valid URL.
+pub fn resolve_href(base: Option<&Url>, href: &str) -> Result<Url, Error> {
+    base.map_or(Some(href.to_string()), |b| {
+        resolve_reference(b.as_str(), href).ok()
+    })
+    .and_then(|u| Url::parse(u.as_str()).ok())
+    .ok_or_else(|| {
+        Error::new(
+            ErrorKind::Unknown,
+            format!(
+                "unable to parse href URL \"{}\" baseurl \"{}\"",
+                href,
+                base.map_or(String::from("--no base--"), |b| b.to_string())
+            ),
+        )
+    })
+}
+
+// The components of a URI reference, as in RFC 3986 appendix B
+struct Reference<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Reference<'a> {
+    fn parse(s: &'a str) -> Self {
+        let (s, fragment) = match s.split_once('#') {
+            Some((r, f)) => (r, Some(f)),
+            None => (s, None),
+        };
+        let (s, query) = match s.split_once('?') {
+            Some((r, q)) => (r, Some(q)),
+            None => (s, None),
+        };
+        // A scheme is only recognised before any "/"
+        let (scheme, s) = match s.split_once(':') {
+            Some((sch, r))
+                if sch.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && sch
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) =>
+            {
+                (Some(sch), r)
+            }
+            _ => (None, s),
+        };
+        let (authority, path) = match s.strip_prefix("//") {
+            Some(r) => {
+                let end = r.find('/').unwrap_or(r.len());
+                (Some(&r[..end]), &r[end..])
+            }
+            None => (None, s),
+        };
+        Reference {
+            scheme,
+            authority,
+            path,
+            query,
+            fragment,
+        }
+    }
+}
+
+// RFC 3986 5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output: Vec<&str> = vec![];
+    while !input.is_empty() {
+        if let Some(r) = input.strip_prefix("../") {
+            input = r
+        } else if let Some(r) = input.strip_prefix("./") {
+            input = r
+        } else if input.starts_with("/./") {
+            input = &input[2..]
+        } else if input == "/." {
+            input = "/"
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            output.pop();
+        } else if input == "." || input == ".." {
+            input = ""
+        } else {
+            // Move the first segment, with its leading "/", to the output
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.push(&input[..end]);
+            input = &input[end..]
+        }
+    }
+    output.concat()
+}
+
+/// Resolve a URI reference against an absolute base URI, using the algorithm in [RFC 3986 section 5.2](https://www.rfc-editor.org/rfc/rfc3986#section-5.2).
+///
+/// Unlike [Url::join], this works the same way for every scheme, and does not normalise the result. It returns an error if the base URI has no scheme.
+///
+///```rust
+///use xrust::resolvers::resolve_reference;
+///assert_eq!(resolve_reference("http://a/b/c/d;p?q", "../g").unwrap(), "http://a/b/g");
+///assert_eq!(resolve_reference("urn:example:a", "#frag").unwrap(), "urn:example:a#frag");
+///```
+pub fn resolve_reference(base: &str, reference: &str) -> Result<String, Error> {
+    let b = Reference::parse(base);
+    let r = Reference::parse(reference);
+    let bscheme = b.scheme.ok_or_else(|| {
+        Error::new(
+            ErrorKind::Unknown,
+            format!("base URI \"{}\" is not absolute", base),
+        )
+    })?;
+    let (scheme, authority, path, query) = if let Some(s) = r.scheme {
+        (s, r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.authority.is_some() {
+        (bscheme, r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.path.is_empty() {
+        (
+            bscheme,
+            b.authority,
+            b.path.to_string(),
+            r.query.or(b.query),
+        )
+    } else if r.path.starts_with('/') {
+        (bscheme, b.authority, remove_dot_segments(r.path), r.query)
+    } else {
+        // RFC 3986 5.2.3 Merge Paths
+        let merged = if b.authority.is_some() && b.path.is_empty() {
+            format!("/{}", r.path)
+        } else {
+            match b.path.rfind('/') {
+                Some(i) => format!("{}{}", &b.path[..=i], r.path),
+                None => r.path.to_string(),
+            }
+        };
+        (bscheme, b.authority, remove_dot_segments(&merged), r.query)
+    };
+    // RFC 3986 5.3 Component Recomposition
+    let mut result = format!("{}:", scheme);
+    if let Some(a) = authority {
+        result.push_str("//");
+        result.push_str(a)
+    }
+    result.push_str(&path);
+    if let Some(q) = query {
+        result.push('?');
+        result.push_str(q)
+    }
+    if let Some(f) = r.fragment {
+        result.push('#');
+        result.push_str(f)
+    }
+    Ok(result)
+}
+
+/// Retrieves the content of a resource without blocking the current thread.
+#[cfg(feature = "async")]
+pub trait AsyncResolver {
+    fn resolve(&self, url: &Url)
+        -> impl std::future::Future<Output = Result<String, Error>> + Send;
+}
+
+/// A [Resolver] that serves resources that have already been fetched by an [AsyncResolver].
+///
+/// A request for a resource that has not been fetched is an error, and the URL is recorded so that it can be fetched before the operation is tried again. See [with_prefetch].
+#[cfg(feature = "async")]
+#[derive(Default)]
+pub struct Prefetched {
+    resources: std::cell::RefCell<HashMap<Url, Result<String, Error>>>,
+    missing: std::cell::RefCell<Vec<Url>>,
+}
+
+#[cfg(feature = "async")]
+impl Prefetched {
+    pub fn new() -> Prefetched {
+        Prefetched::default()
+    }
+    /// Fetch a resource, unless it has been fetched already. If it cannot be fetched, then the error is kept and returned when the resource is requested.
+    pub async fn fetch<R: AsyncResolver>(&self, url: &Url, r: &R) {
+        if !self.contains(url) {
+            let s = r.resolve(url).await;
+            self.resources.borrow_mut().insert(url.clone(), s);
+        }
+    }
+    pub fn contains(&self, url: &Url) -> bool {
+        self.resources.borrow().contains_key(url)
+    }
+    /// The URLs that have been requested, but not fetched, since this was last called.
+    pub fn take_missing(&self) -> Vec<Url> {
+        std::mem::take(&mut *self.missing.borrow_mut())
+    }
+}
+
+#[cfg(feature = "async")]
+impl Resolver for Prefetched {
+    fn resolve(&self, url: &Url) -> Result<String, Error> {
+        match self.resources.borrow().get(url) {
+            Some(r) => r.clone(),
+            None => {
+                self.missing.borrow_mut().push(url.clone());
+                Result::Err(Error::new(
+                    ErrorKind::Unknown,
+                    format!("resource \"{}\" has not been fetched", url),
+                ))
+            }
+        }
+    }
+}
+
+/// Perform a synchronous operation that fetches resources with a [Resolver], fetching them with an [AsyncResolver] instead so that the current thread is not blocked waiting for I/O.
+///
+/// The operation is given a [Prefetched] resolver. When it finishes, if it requested resources that had not been fetched, they are fetched and the operation is performed again. This is repeated until it doesn't request any resource that has not been fetched. The operation must be repeatable: performing it again must have the same effect as performing it once.
+#[cfg(feature = "async")]
+pub async fn with_prefetch<R, T, F>(p: &Prefetched, r: &R, mut op: F) -> Result<T, Error>
+where
+    R: AsyncResolver,
+    F: FnMut(&Prefetched) -> Result<T, Error>,
+{
+    loop {
+        let result = op(p);
+        let missing = p.take_missing();
+        if missing.is_empty() {
+            return result;
+        }
+        for u in missing {
+            p.fetch(&u, r).await
+        }
+    }
+}
+
+/// The asynchronous counterpart of [DefaultResolver].
+#[cfg(feature = "async")]
+#[derive(Clone, Debug, Default)]
+pub struct AsyncDefaultResolver;
+
+#[cfg(feature = "async")]
+impl AsyncDefaultResolver {
+    pub fn new() -> AsyncDefaultResolver {
+        AsyncDefaultResolver
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncResolver for AsyncDefaultResolver {
+    fn resolve(
+        &self,
+        url: &Url,
+    ) -> impl std::future::Future<Output = Result<String, Error>> + Send {
+        let url = url.clone();
+        async move {
+            match url.scheme() {
+                #[cfg(feature = "http")]
+                "http" | "https" => reqwest::get(url.as_str())
+                    .await
+                    .map_err(|_| {
+                        Error::new(
+                            ErrorKind::Unknown,
+                            format!("unable to fetch href URL \"{}\"", url),
+                        )
+                    })?
+                    .text()
+                    .await
+                    .map_err(|_| {
+                        Error::new(
+                            ErrorKind::Unknown,
+                            String::from("unable to extract module data"),
+                        )
+                    }),
+                "file" => tokio::fs::read_to_string(url.path())
+                    .await
+                    .map_err(|er| Error::new(ErrorKind::Unknown, er.to_string())),
+                _ => Result::Err(Error::new(
+                    ErrorKind::Unknown,
+                    format!("unable to fetch URL \"{}\"", url),
+                )),
+            }
+        }
+    }
+}
+
+// Serves resources from memory asynchronously, counting the number of times each URL is fetched
+#[cfg(all(test, feature = "async"))]
+pub(crate) struct AsyncMemoryResolver(pub MemoryResolver, pub std::cell::RefCell<Vec<String>>);
+
+#[cfg(all(test, feature = "async"))]
+impl AsyncMemoryResolver {
+    pub(crate) fn new(m: MemoryResolver) -> AsyncMemoryResolver {
+        AsyncMemoryResolver(m, std::cell::RefCell::new(vec![]))
+    }
+    pub(crate) fn fetched(&self, url: &str) -> usize {
+        self.1.borrow().iter().filter(|u| u.as_str() == url).count()
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+impl AsyncResolver for AsyncMemoryResolver {
+    fn resolve(
+        &self,
+        url: &Url,
+    ) -> impl std::future::Future<Output = Result<String, Error>> + Send {
+        self.1.borrow_mut().push(url.to_string());
+        let r = self.0.resolve(url);
+        async move { r }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3986_examples() {
+        // RFC 3986 5.4
+        let base = "http://a/b/c/d;p?q";
+        for (r, expected) in [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            (";x", "http://a/b/c/;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g?y/./x", "http://a/b/c/g?y/./x"),
+            ("g#s/../x", "http://a/b/c/g#s/../x"),
+            ("http:g", "http:g"),
+        ] {
+            assert_eq!(resolve_reference(base, r).unwrap(), expected, "{}", r)
+        }
+        assert!(resolve_reference("a/b", "c").is_err());
+    }
+
+    #[test]
+    fn resolve_relative() {
+        let base = Url::parse("file:///tmp/style/main.xsl").expect("unable to parse URL");
+        let u = resolve_href(Some(&base), "module.xsl").expect("unable to resolve href");
+        assert_eq!(u.as_str(), "file:///tmp/style/module.xsl")
+    }
+
+    #[test]
+    fn resolve_no_base() {
+        assert!(resolve_href(None, "module.xsl").is_err())
+    }
+
+    #[test]
+    fn default_file() {
+        let pwd = std::env::current_dir().expect("unable to get current directory");
+        let u =
+            Url::from_file_path(pwd.join("tests/xsl/included.xsl")).expect("unable to create URL");
+        let s = DefaultResolver::new()
+            .resolve(&u)
+            .expect("unable to resolve file");
+        assert!(s.contains("Level2"))
+    }
+
+    #[test]
+    fn default_bad_scheme() {
+        let u = Url::parse("ftp://example.org/a.xml").expect("unable to parse URL");
+        assert!(DefaultResolver::new().resolve(&u).is_err())
+    }
+
+    #[test]
+    fn memory() {
+        let mut r = MemoryResolver::new();
+        r.insert("http://example.org/a.xml", "<a/>");
+        r.insert("http://example.org/bad.xml", vec![0xffu8, 0x00]);
+        // UTF-16, with a byte order mark
+        r.insert(
+            "http://example.org/le.xml",
+            vec![0xffu8, 0xfe, b'<', 0, b'b', 0, b'/', 0, b'>', 0],
+        );
+        r.insert(
+            "http://example.org/be.xml",
+            vec![0xfeu8, 0xff, 0, b'<', 0, b'c', 0, b'/', 0, b'>'],
+        );
+        r.insert("http://example.org/odd.xml", vec![0xfeu8, 0xff, 0]);
+        assert_eq!(r.len(), 5);
+        assert_eq!(
+            r.resolve(&Url::parse("http://example.org/a.xml").expect("unable to parse URL"))
+                .expect("unable to resolve URL"),
+            "<a/>"
+        );
+        assert!(r
+            .resolve(&Url::parse("http://example.org/b.xml").expect("unable to parse URL"))
+            .is_err());
+        assert!(r
+            .resolve(&Url::parse("http://example.org/bad.xml").expect("unable to parse URL"))
+            .is_err());
+        let get = |u: &str| r.resolve(&Url::parse(u).expect("unable to parse URL"));
+        assert_eq!(
+            get("http://example.org/le.xml").expect("unable to resolve URL"),
+            "<b/>"
+        );
+        assert_eq!(
+            get("http://example.org/be.xml").expect("unable to resolve URL"),
+            "<c/>"
+        );
+        assert!(get("http://example.org/odd.xml").is_err())
+    }
+
+    struct TestClient;
+    impl HttpClient for TestClient {
+        fn get(&self, url: &Url) -> Result<String, Error> {
+            Ok(format!("<doc>{}</doc>", url.path()))
+        }
+    }
+
+    // Redirects /moved/... to the URL given by the rest of the path
+    struct RedirectClient;
+    impl HttpClient for RedirectClient {
+        fn get(&self, url: &Url) -> Result<String, Error> {
+            follow_redirects(self, url, None, |_| true)
+        }
+        fn get_response(&self, url: &Url) -> Result<HttpResponse, Error> {
+            match url.path().strip_prefix("/moved/") {
+                Some(to) => Ok(HttpResponse::Redirect(Url::parse(to).unwrap())),
+                None => Ok(HttpResponse::Body(format!("<doc>{}</doc>", url.path()))),
+            }
+        }
+    }
+
+    #[test]
+    fn http_client() {
+        let mut r = HttpResolver::with_client(TestClient);
+        let u = Url::parse("https://example.org/a.xml").expect("unable to parse URL");
+        // No hosts are allowed
+        assert!(r.resolve(&u).is_err());
+        r.allow_host("example.org");
+        assert_eq!(
+            r.resolve(&u).expect("unable to resolve URL"),
+            "<doc>/a.xml</doc>"
+        )
+    }
+
+    #[test]
+    fn http_redirect() {
+        let mut r = HttpResolver::with_client(RedirectClient);
+        r.allow_host("example.org");
+        let u = |s: &str| Url::parse(s).expect("unable to parse URL");
+        assert_eq!(
+            r.resolve(&u("https://example.org/moved/https://example.org/b.xml"))
+                .expect("unable to resolve URL"),
+            "<doc>/b.xml</doc>"
+        );
+        // Each redirection is checked
+        assert!(r
+            .resolve(&u("https://example.org/moved/https://example.com/b.xml"))
+            .is_err());
+        assert!(r
+            .resolve(&u("https://example.org/moved/file:///etc/passwd"))
+            .is_err());
+        // Only so many redirections are followed
+        let chain = |n: usize| "https://example.org/moved/".repeat(n) + "https://example.org/c.xml";
+        assert!(r.resolve(&u(chain(MAX_REDIRECTS).as_str())).is_ok());
+        assert!(r.resolve(&u(chain(MAX_REDIRECTS + 1).as_str())).is_err());
+    }
+
+    #[test]
+    fn http_not_file() {
+        let mut r = HttpResolver::with_client(TestClient);
+        r.allow_host("example.org");
+        let u = Url::parse("file:///etc/passwd").expect("unable to parse URL");
+        assert!(r.resolve(&u).is_err())
+    }
+
+    #[test]
+    fn http_allowed() {
+        let mut r = HttpResolver::with_client(TestClient);
+        r.allow_host("Example.org");
+        assert!(r
+            .resolve(&Url::parse("http://example.org/a.xml").expect("unable to parse URL"))
+            .is_ok());
+        assert!(r
+            .resolve(&Url::parse("http://example.com/a.xml").expect("unable to parse URL"))
+            .is_err());
+    }
+
+    #[test]
+    fn access_policy() {
+        let mut m = MemoryResolver::new();
+        m.insert("https://example.org/a.xml", "<a/>");
+        m.insert("https://example.org/big.xml", "<big>0123456789</big>");
+        m.insert("http://example.org/a.xml", "<a/>");
+        m.insert("https://example.com/a.xml", "<a/>");
+        m.insert("file:///test/a.xml", "<a/>");
+        let u = |s: &str| Url::parse(s).expect("unable to parse URL");
+
+        // Nothing is allowed until it is allowed
+        let r = PolicyResolver::new(AccessPolicy::new(), m.clone());
+        assert!(r.resolve(&u("https://example.org/a.xml")).is_err());
+
+        let mut p = AccessPolicy::new();
+        p.allow_scheme("HTTPS");
+        p.allow_scheme("file");
+        let r = PolicyResolver::new(p.clone(), m.clone());
+        // Hosts must also be allowed
+        assert!(r.resolve(&u("https://example.com/a.xml")).is_err());
+        assert!(r.resolve(&u("file:///test/a.xml")).is_ok());
+        assert!(r.resolve(&u("http://example.org/a.xml")).is_err());
+
+        p.allow_host("Example.org");
+        p.set_max_bytes(10);
+        let r: Rc<dyn Resolver> = Rc::new(PolicyResolver::new(p, m));
+        assert_eq!(
+            r.resolve(&u("https://example.org/a.xml"))
+                .expect("unable to resolve URL"),
+            "<a/>"
+        );
+        assert!(r.resolve(&u("https://example.com/a.xml")).is_err());
+        assert!(r.resolve(&u("http://example.org/a.xml")).is_err());
+        assert!(r.resolve(&u("file:///test/a.xml")).is_ok());
+        assert!(r.resolve(&u("https://example.org/big.xml")).is_err());
+
+        // The policy is applied to each redirection, and the maximum size to each body
+        let mut h = HttpResolver::with_client(RedirectClient);
+        h.allow_host("example.org");
+        h.allow_host("example.com");
+        let mut p = AccessPolicy::new();
+        p.allow_scheme("https");
+        p.allow_host("example.org");
+        p.set_max_bytes(17);
+        let r = PolicyResolver::new(p, h);
+        assert!(r
+            .resolve(&u("https://example.org/moved/https://example.org/b.xml"))
+            .is_ok());
+        assert!(r
+            .resolve(&u("https://example.org/moved/https://example.com/b.xml"))
+            .is_err());
+        assert!(r
+            .resolve(&u(
+                "https://example.org/moved/https://example.org/bigger.xml"
+            ))
+            .is_err());
+
+        // Files are read no further than the maximum size
+        let pwd = std::env::current_dir().expect("unable to get current directory");
+        let f =
+            Url::from_file_path(pwd.join("tests/xsl/included.xsl")).expect("unable to create URL");
+        let mut p = AccessPolicy::new();
+        p.allow_scheme("file");
+        p.set_max_bytes(8);
+        assert!(PolicyResolver::new(p.clone(), DefaultResolver::new())
+            .resolve(&f)
+            .is_err());
+        p.set_max_bytes(1 << 20);
+        assert!(PolicyResolver::new(p, DefaultResolver::new())
+            .resolve(&f)
+            .is_ok());
+
+        // The policy also applies to external entities
+        let mut f = crate::forest::Forest::new();
+        let doc = "<!DOCTYPE doc [<!ENTITY e SYSTEM 'https://example.com/a.xml'>]><doc>&e;</doc>";
+        assert!(f.grow_tree_with_resolver(doc, None, &r).is_err());
+        let doc = "<!DOCTYPE doc [<!ENTITY e SYSTEM 'https://example.org/a.xml'>]><doc>&e;</doc>";
+        assert!(f.grow_tree_with_resolver(doc, None, &r).is_ok());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_default_file() {
+        let pwd = std::env::current_dir().expect("unable to get current directory");
+        let u =
+            Url::from_file_path(pwd.join("tests/xsl/included.xsl")).expect("unable to create URL");
+        let s = AsyncDefaultResolver::new()
+            .resolve(&u)
+            .await
+            .expect("unable to resolve file");
+        assert!(s.contains("Level2"))
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn prefetch() {
+        let mut m = MemoryResolver::new();
+        m.insert("http://example.org/a.txt", "a");
+        m.insert("http://example.org/b.txt", "b");
+        let r = AsyncMemoryResolver::new(m);
+        let u = |s: &str| Url::parse(s).expect("unable to parse URL");
+
+        let p = Prefetched::new();
+        assert!(p.resolve(&u("http://example.org/a.txt")).is_err());
+        assert_eq!(p.take_missing(), vec![u("http://example.org/a.txt")]);
+        assert!(p.take_missing().is_empty());
+
+        // b.txt is only requested once a.txt has been fetched, and missing.txt can't be fetched
+        let mut runs = 0;
+        let s = with_prefetch(&p, &r, |p| {
+            runs += 1;
+            let a = p.resolve(&u("http://example.org/a.txt"))?;
+            let b = p.resolve(&u("http://example.org/b.txt"))?;
+            let c = p
+                .resolve(&u("http://example.org/missing.txt"))
+                .unwrap_or(String::from("none"));
+            Ok(format!("{}{}{}", a, b, c))
+        })
+        .await
+        .expect("unable to resolve URLs");
+        assert_eq!(s, "abnone");
+        assert_eq!(runs, 4);
+        for f in ["a.txt", "b.txt", "missing.txt"] {
+            assert_eq!(r.fetched(format!("http://example.org/{}", f).as_str()), 1)
+        }
+    }
+}
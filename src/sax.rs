@@ -0,0 +1,1944 @@
+/*! ## Event-based processing of XML documents
+
+A [ContentHandler] receives a document as a stream of events: the start and end of each element, text, comments, and so on. This is the same model as SAX.
+
+Filters are ContentHandlers that process events before passing them on to another ContentHandler. Filters can be chained together to build a pre-processing pipeline between the parser and the final consumer of the events. The filters provided are:
+
+* [NamespaceFixer] resolves the namespace URIs of element and attribute names, and adds any missing namespace declarations.
+* [IncludeResolver] replaces XInclude include elements with the content of the included resource.
+* [WhitespaceStripper] removes whitespace-only text, except where xml:space="preserve" is in effect.
+* [Truncator] stops the document after the first element whose path satisfies a predicate.
+* [Projector] keeps only the elements selected by a set of [SimplePath]s, along with their ancestors and descendants.
+
+A [TreeBuilder] is a ContentHandler that creates a [Tree](../forest/struct.Tree.html) from the events it receives, so a filter chain, or a custom parser, produces the same data model as parsing a document with [Forest::grow_tree](../forest/struct.Forest.html#method.grow_tree).
+
+[parse_incremental] reports events as the document is parsed, and stops parsing as soon as the ContentHandler is finished. This makes it possible to extract the start of a large document without parsing all of it. [parse_resumable] parses as much of a document as is available and returns a [Checkpoint], so that parsing can be resumed when the document has grown. With [parse_resumable_with_state], the state of a [Resumable] filter chain is saved in the checkpoint too.
+
+The events produced by [parse] and [parse_incremental] have names that are not yet namespace-resolved. Filters that match names by namespace, such as the IncludeResolver, must come after a NamespaceFixer in the chain.
+
+```rust
+use xrust::qname::QualifiedName;
+use xrust::sax::{parse, ContentHandler, NamespaceFixer, WhitespaceStripper};
+use xrust::value::Value;
+use xrust::xdmerror::Error;
+
+// Count the elements in a document
+#[derive(Default)]
+struct Counter {
+    elements: usize,
+    text: usize,
+}
+impl ContentHandler for Counter {
+    fn start_element(
+        &mut self,
+        _name: QualifiedName,
+        _attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        self.elements += 1;
+        Ok(())
+    }
+    fn end_element(&mut self, _name: QualifiedName) -> Result<(), Error> {
+        Ok(())
+    }
+    fn characters(&mut self, _text: &str) -> Result<(), Error> {
+        self.text += 1;
+        Ok(())
+    }
+}
+
+let mut c = Counter::default();
+parse(
+    "<Example>\n  <Data>one</Data>\n  <Data>two</Data>\n</Example>",
+    &mut NamespaceFixer::new(WhitespaceStripper::new(&mut c)),
+)
+.expect("unable to parse document");
+assert_eq!(c.elements, 3);
+assert_eq!(c.text, 2);
+```
+*/
+
+use crate::forest::{undeclared_prefix, Forest, Node, NodeType, TreeIndex};
+use crate::parsecommon::ncname;
+use crate::parsexml::{literal_value, next_token, position_error, Token, XMLDocument, XMLNode};
+use crate::qname::{QualifiedName, XML_NAMESPACE};
+use crate::resolvers::{resolve_href, Resolver};
+use crate::value::Value;
+use crate::xdmerror::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use url::Url;
+
+/// The XInclude namespace URI.
+pub const XINCLUDE_NAMESPACE: &str = "http://www.w3.org/2001/XInclude";
+
+/// Receives the events for a document.
+///
+/// The default implementation of the optional events ignores them.
+pub trait ContentHandler {
+    fn start_document(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end_document(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// The start of an element. The attributes include any namespace declarations.
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error>;
+    fn end_element(&mut self, name: QualifiedName) -> Result<(), Error>;
+    /// Character data. Adjacent text may be reported by more than one event.
+    fn characters(&mut self, text: &str) -> Result<(), Error>;
+    fn comment(&mut self, _text: &str) -> Result<(), Error> {
+        Ok(())
+    }
+    fn processing_instruction(&mut self, _target: &str, _data: &str) -> Result<(), Error> {
+        Ok(())
+    }
+    /// The start of the expansion of a general entity reference.
+    fn start_entity(&mut self, _name: &QualifiedName) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end_entity(&mut self, _name: &QualifiedName) -> Result<(), Error> {
+        Ok(())
+    }
+    /// A reference to a general entity that has not been expanded.
+    fn skipped_entity(&mut self, _name: &QualifiedName) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Whether the handler needs any more events. An incremental parser stops parsing the document once this returns true.
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+impl<H: ContentHandler + ?Sized> ContentHandler for &mut H {
+    fn start_document(&mut self) -> Result<(), Error> {
+        (**self).start_document()
+    }
+    fn end_document(&mut self) -> Result<(), Error> {
+        (**self).end_document()
+    }
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        (**self).start_element(name, attributes)
+    }
+    fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+        (**self).end_element(name)
+    }
+    fn characters(&mut self, text: &str) -> Result<(), Error> {
+        (**self).characters(text)
+    }
+    fn comment(&mut self, text: &str) -> Result<(), Error> {
+        (**self).comment(text)
+    }
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Error> {
+        (**self).processing_instruction(target, data)
+    }
+    fn start_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        (**self).start_entity(name)
+    }
+    fn end_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        (**self).end_entity(name)
+    }
+    fn skipped_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        (**self).skipped_entity(name)
+    }
+    fn is_finished(&self) -> bool {
+        (**self).is_finished()
+    }
+}
+
+/// A [ContentHandler] that passes events on to another ContentHandler.
+pub trait Filter: ContentHandler {
+    type Inner: ContentHandler;
+    /// The ContentHandler that receives the events from this filter.
+    fn get_inner(&mut self) -> &mut Self::Inner;
+    /// Discard the filter, returning the next ContentHandler in the chain.
+    fn into_inner(self) -> Self::Inner;
+}
+
+/// Parse a document and report its content to the [ContentHandler].
+/// Entity references that are not expanded by the parser are reported as skipped entities.
+pub fn parse<H: ContentHandler>(src: &str, h: &mut H) -> Result<(), Error> {
+    emit_document(&XMLDocument::try_from(src)?, h)
+}
+
+/// Parse a document incrementally, reporting each event to the [ContentHandler] as soon as it is found.
+///
+/// Unlike [parse], the document is not parsed completely before the events are reported. Parsing stops as soon as the ContentHandler [is finished](ContentHandler::is_finished), so the rest of the document is not read and need not be well-formed. The end_document event is reported in either case. Entity references are reported as skipped entities.
+pub fn parse_incremental<H: ContentHandler>(src: &str, h: &mut H) -> Result<(), Error> {
+    h.start_document()?;
+    let mut state: Checkpoint = Checkpoint::default();
+    while state.offset < src.len() && !h.is_finished() {
+        let (rest, t) = next_token(src, &src[state.offset..])?;
+        report_token(src, &mut state, t, h)?;
+        state.offset = src.len() - rest.len();
+    }
+    if !h.is_finished() {
+        if let Some(n) = state.open.last() {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("element \"{}\" is not closed", n.to_string()),
+            ));
+        }
+        if !state.seen_element {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("no document element"),
+            ));
+        }
+    }
+    h.end_document()
+}
+
+/// The state of the parser at a boundary between markup, such as the start or end of an element. Parsing can be resumed from a Checkpoint with [parse_resumable], or with [parse_resumable_with_state] so that the state of the transform is resumed too.
+///
+/// The default Checkpoint is the start of a document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Checkpoint<S = ()> {
+    /// The number of bytes of the source that were parsed. Parsing resumes with the rest of the source, so the source that is given when parsing resumes begins with the bytes that follow these.
+    pub offset: usize,
+    /// The names of the elements that are open, outermost first.
+    pub open: Vec<QualifiedName>,
+    /// Whether the document element has started.
+    pub seen_element: bool,
+    /// Whether the start_document event has been reported.
+    pub started: bool,
+    /// The state of the [Resumable] ContentHandler, when parsing with [parse_resumable_with_state].
+    pub state: S,
+}
+
+impl<S> Checkpoint<S> {
+    /// Whether the document element has ended. Any comments or processing instructions that follow it are still reported as parsing resumes, and the end_document event is reported by [finish_resumable].
+    pub fn is_complete(&self) -> bool {
+        self.seen_element && self.open.is_empty()
+    }
+}
+
+/// A [ContentHandler] whose state can be saved in a [Checkpoint], so that a transform can be resumed by another handler, for example after the process has been restarted.
+pub trait Resumable: ContentHandler {
+    type State: Clone + Default;
+    /// The state of the handler, including that of any handler that it passes events on to.
+    fn save(&self) -> Self::State;
+    /// Return the handler to a saved state.
+    fn restore(&mut self, state: Self::State);
+}
+
+impl<H: Resumable + ?Sized> Resumable for &mut H {
+    type State = H::State;
+    fn save(&self) -> H::State {
+        (**self).save()
+    }
+    fn restore(&mut self, state: H::State) {
+        (**self).restore(state)
+    }
+}
+
+/// Parse a document that may not be complete, reporting each event to the [ContentHandler], and return a [Checkpoint] from which parsing can be resumed when more of the document is available.
+///
+/// This is intended for documents that grow by appending, such as logs. The source is the part of the document that follows the checkpoint, i.e. the input that was not parsed, together with anything that has been appended since. The ContentHandler (and its state) is expected to be the same one, or a restored copy of it, that received the events before the checkpoint. Use [parse_resumable_with_state] to have its state saved in the checkpoint.
+///
+/// The start_document event is reported when parsing starts from the default Checkpoint. Parsing stops at the end of the last complete markup: text at the end of the source, and markup that cannot be parsed, are assumed to be incomplete and are parsed again when parsing resumes. This means that a syntax error stops the parse, but isn't reported. Since comments and processing instructions may follow the document element, the end of the document isn't known until [finish_resumable] is called, which reports the end_document event.
+///
+/// ```rust
+/// use xrust::forest::Forest;
+/// use xrust::sax::{finish_resumable, parse_resumable, Checkpoint, TreeBuilder};
+///
+/// let mut f = Forest::new();
+/// let mut b = TreeBuilder::new(&mut f);
+/// let mut log = String::from("<log><entry>1</entry><entry>2</en");
+/// let c = parse_resumable(&log, &Checkpoint::default(), &mut b).expect("unable to parse");
+/// assert!(!c.is_complete());
+/// // The input that has been parsed is no longer needed
+/// log.drain(..c.offset);
+///
+/// // Later, once more has been written to the log
+/// log.push_str("try></log>");
+/// let c = parse_resumable(&log, &c, &mut b).expect("unable to parse");
+/// assert!(c.is_complete());
+/// log.drain(..c.offset);
+/// finish_resumable(&log, &c, &mut b).expect("unable to parse");
+/// let t = b.finish().expect("unable to build tree");
+/// assert_eq!(
+///     f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+///     "<log><entry>1</entry><entry>2</entry></log>"
+/// );
+/// ```
+pub fn parse_resumable<H: ContentHandler>(
+    src: &str,
+    from: &Checkpoint,
+    h: &mut H,
+) -> Result<Checkpoint, Error> {
+    resume(src, from, h)
+}
+
+/// Parse a document that may not be complete, as for [parse_resumable], and save the state of the ContentHandler in the returned [Checkpoint].
+///
+/// The handler is first restored to the state saved in the checkpoint, so parsing can be resumed with a new handler, for example one created after the process has been restarted.
+pub fn parse_resumable_with_state<H: Resumable>(
+    src: &str,
+    from: &Checkpoint<H::State>,
+    h: &mut H,
+) -> Result<Checkpoint<H::State>, Error> {
+    h.restore(from.state.clone());
+    let mut c = resume(src, from, h)?;
+    c.state = h.save();
+    Ok(c)
+}
+
+/// Parse the rest of a document that has been parsed with [parse_resumable] or [parse_resumable_with_state], once it is known to be complete, and report the end_document event.
+///
+/// The source is the part of the document that follows the checkpoint. It is an error if the document is not complete. The ContentHandler is expected to be in the state it was in at the checkpoint.
+pub fn finish_resumable<S: Clone, H: ContentHandler>(
+    src: &str,
+    from: &Checkpoint<S>,
+    h: &mut H,
+) -> Result<(), Error> {
+    let mut state = resume(src, from, h)?;
+    if !h.is_finished() {
+        // Parsing stops before text at the end of the source, or markup that cannot be parsed
+        let rest = &src[state.offset..];
+        if !rest.is_empty() {
+            let (_, t) = next_token(rest, rest)?;
+            report_token(rest, &mut state, t, h)?
+        }
+        if let Some(n) = state.open.last() {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("element \"{}\" is not closed", n.to_string()),
+            ));
+        }
+        if !state.seen_element {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("no document element"),
+            ));
+        }
+    }
+    h.end_document()
+}
+
+// Parse as much of the source as is complete
+fn resume<S: Clone, H: ContentHandler>(
+    src: &str,
+    from: &Checkpoint<S>,
+    h: &mut H,
+) -> Result<Checkpoint<S>, Error> {
+    let mut state = Checkpoint {
+        offset: 0,
+        ..from.clone()
+    };
+    if !state.started {
+        h.start_document()?;
+        state.started = true
+    }
+    while state.offset < src.len() && !h.is_finished() {
+        let (rest, t) = match next_token(src, &src[state.offset..]) {
+            Ok(("", Token::Text(_))) => break,
+            Ok(r) => r,
+            Err(_) => break,
+        };
+        report_token(src, &mut state, t, h)?;
+        state.offset = src.len() - rest.len();
+    }
+    Ok(state)
+}
+
+// Report the event for a token. The state is updated with the elements that are open.
+fn report_token<S, H: ContentHandler>(
+    src: &str,
+    state: &mut Checkpoint<S>,
+    t: Token,
+    h: &mut H,
+) -> Result<(), Error> {
+    let error = |msg: &str| Result::Err(position_error(src, state.offset, msg));
+    match t {
+        Token::XMLDecl => {}
+        Token::Doctype => {
+            if state.seen_element {
+                return error("document type declaration must come before the document element");
+            }
+        }
+        Token::StartTag(n, a, empty) => {
+            if state.is_complete() {
+                return error("more than one document element");
+            }
+            state.seen_element = true;
+            let attributes = a
+                .into_iter()
+                .filter_map(|b| match b {
+                    XMLNode::Attribute(an, av) => Some((an, av)),
+                    XMLNode::UnexpandedAttribute(an, av) => Some((an, literal_value(&av))),
+                    _ => None,
+                })
+                .collect();
+            h.start_element(n.clone(), attributes)?;
+            if empty {
+                h.end_element(n)?
+            } else {
+                state.open.push(n)
+            }
+        }
+        Token::EndTag(n) => match state.open.pop() {
+            Some(o) if o.to_string() == n.to_string() => h.end_element(o)?,
+            _ => return error("end tag does not match start tag"),
+        },
+        Token::Text(t) => {
+            if !state.open.is_empty() {
+                h.characters(t.as_str())?
+            } else if !t.chars().all(|c| c.is_ascii_whitespace()) {
+                return error("text is not allowed outside the document element");
+            }
+        }
+        Token::Reference(n) => {
+            if state.open.is_empty() {
+                return error("reference is not allowed outside the document element");
+            }
+            h.skipped_entity(&n)?
+        }
+        Token::Comment(c) => h.comment(c.as_str())?,
+        Token::PI(t, d) => h.processing_instruction(t.as_str(), d.as_str())?,
+    }
+    Ok(())
+}
+
+/// Report the content of a parsed document to the [ContentHandler].
+pub fn emit_document<H: ContentHandler>(d: &XMLDocument, h: &mut H) -> Result<(), Error> {
+    h.start_document()?;
+    emit_nodes(&d.prologue, h)?;
+    emit_nodes(&d.content, h)?;
+    emit_nodes(&d.epilogue, h)?;
+    h.end_document()
+}
+
+fn emit_nodes<H: ContentHandler>(v: &[XMLNode], h: &mut H) -> Result<(), Error> {
+    for n in v {
+        match n {
+            XMLNode::Element(name, a, c) => {
+                let attributes = a
+                    .iter()
+                    .filter_map(|b| match b {
+                        XMLNode::Attribute(an, av) => Some((an.clone(), av.clone())),
+                        XMLNode::UnexpandedAttribute(an, av) => {
+                            Some((an.clone(), literal_value(av)))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                h.start_element(name.clone(), attributes)?;
+                emit_nodes(c, h)?;
+                h.end_element(name.clone())?
+            }
+            XMLNode::Text(t) => h.characters(t.to_string().as_str())?,
+            XMLNode::Comment(t) => h.comment(t.to_string().as_str())?,
+            XMLNode::PI(t, d) => h.processing_instruction(t.as_str(), d.to_string().as_str())?,
+            XMLNode::Reference(name) => h.skipped_entity(name)?,
+            XMLNode::EntityReference(name, c) => {
+                h.start_entity(name)?;
+                emit_nodes(c, h)?;
+                h.end_entity(name)?
+            }
+            XMLNode::Attribute(_, _) | XMLNode::UnexpandedAttribute(_, _) | XMLNode::DTD(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the namespace URI of element and attribute names, using the namespace declarations that are in scope.
+///
+/// Names that already have a namespace URI are kept, and a namespace declaration is added for them if their prefix is not bound to that URI.
+pub struct NamespaceFixer<H: ContentHandler> {
+    next: H,
+    // The namespace declarations for each open element: (prefix, URI). The default namespace has no prefix.
+    scopes: Vec<Vec<(Option<String>, String)>>,
+    names: Vec<QualifiedName>,
+}
+
+impl<H: ContentHandler> NamespaceFixer<H> {
+    pub fn new(next: H) -> Self {
+        NamespaceFixer {
+            next,
+            scopes: vec![],
+            names: vec![],
+        }
+    }
+    fn lookup(&self, prefix: Option<&str>) -> Option<&str> {
+        if prefix == Some("xml") {
+            return Some(XML_NAMESPACE);
+        }
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|s| s.iter().rev())
+            .find(|(p, _)| p.as_deref() == prefix)
+            .map(|(_, u)| u.as_str())
+            .filter(|u| !u.is_empty())
+    }
+    // Resolve a name, adding a namespace declaration if one is needed
+    fn fix(
+        &mut self,
+        name: &QualifiedName,
+        element: bool,
+        decls: &mut Vec<(QualifiedName, Value)>,
+    ) -> Result<QualifiedName, Error> {
+        let prefix = name.get_prefix();
+        match name.get_nsuri() {
+            Some(u) => {
+                if self.lookup(prefix.as_deref()) != Some(u.as_str()) {
+                    // Unprefixed attributes are never in a namespace, so they cannot be declared
+                    if prefix.is_none() && !element {
+                        return Result::Err(Error::new(
+                            ErrorKind::Unknown,
+                            format!(
+                                "attribute \"{}\" in a namespace must have a prefix",
+                                name.get_localname()
+                            ),
+                        ));
+                    }
+                    self.scopes
+                        .last_mut()
+                        .unwrap()
+                        .push((prefix.clone(), u.clone()));
+                    decls.push((
+                        match &prefix {
+                            Some(p) => {
+                                QualifiedName::new(None, Some(String::from("xmlns")), p.clone())
+                            }
+                            None => QualifiedName::new(None, None, String::from("xmlns")),
+                        },
+                        Value::from(u),
+                    ))
+                }
+                Ok(name.clone())
+            }
+            None => match prefix {
+                Some(p) => match self.lookup(Some(p.as_str())) {
+                    Some(u) => Ok(QualifiedName::new(
+                        Some(u.to_string()),
+                        Some(p),
+                        name.get_localname(),
+                    )),
+                    None => Result::Err(undeclared_prefix(&p)),
+                },
+                None if element => Ok(QualifiedName::new(
+                    self.lookup(None).map(String::from),
+                    None,
+                    name.get_localname(),
+                )),
+                None => Ok(name.clone()),
+            },
+        }
+    }
+}
+
+// Is the attribute a namespace declaration?
+fn is_declaration(name: &QualifiedName) -> bool {
+    match name.get_prefix() {
+        Some(p) => p == "xmlns",
+        None => name.get_localname() == "xmlns",
+    }
+}
+
+impl<H: ContentHandler> ContentHandler for NamespaceFixer<H> {
+    fn start_document(&mut self) -> Result<(), Error> {
+        self.next.start_document()
+    }
+    fn is_finished(&self) -> bool {
+        self.next.is_finished()
+    }
+    fn end_document(&mut self) -> Result<(), Error> {
+        self.next.end_document()
+    }
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        let scope = attributes
+            .iter()
+            .filter(|(an, _)| is_declaration(an))
+            .map(|(an, av)| match an.get_prefix() {
+                Some(_) => (Some(an.get_localname()), av.to_string()),
+                None => (None, av.to_string()),
+            })
+            .collect();
+        self.scopes.push(scope);
+        let mut decls = vec![];
+        let ename = self.fix(&name, true, &mut decls)?;
+        let mut attrs = vec![];
+        for (an, av) in attributes {
+            if is_declaration(&an) {
+                attrs.push((an, av))
+            } else {
+                attrs.push((self.fix(&an, false, &mut decls)?, av))
+            }
+        }
+        decls.append(&mut attrs);
+        self.names.push(ename.clone());
+        self.next.start_element(ename, decls)
+    }
+    fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+        self.scopes.pop();
+        let n = self.names.pop().unwrap_or(name);
+        self.next.end_element(n)
+    }
+    fn characters(&mut self, text: &str) -> Result<(), Error> {
+        self.next.characters(text)
+    }
+    fn comment(&mut self, text: &str) -> Result<(), Error> {
+        self.next.comment(text)
+    }
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Error> {
+        self.next.processing_instruction(target, data)
+    }
+    fn start_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        self.next.start_entity(name)
+    }
+    fn end_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        self.next.end_entity(name)
+    }
+    fn skipped_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        self.next.skipped_entity(name)
+    }
+}
+
+impl<H: ContentHandler> Filter for NamespaceFixer<H> {
+    type Inner = H;
+    fn get_inner(&mut self) -> &mut H {
+        &mut self.next
+    }
+    fn into_inner(self) -> H {
+        self.next
+    }
+}
+
+/// The saved state of a [NamespaceFixer]: the namespace declarations that are in scope.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NamespaceFixerState<S> {
+    scopes: Vec<Vec<(Option<String>, String)>>,
+    names: Vec<QualifiedName>,
+    next: S,
+}
+
+impl<H: Resumable> Resumable for NamespaceFixer<H> {
+    type State = NamespaceFixerState<H::State>;
+    fn save(&self) -> Self::State {
+        NamespaceFixerState {
+            scopes: self.scopes.clone(),
+            names: self.names.clone(),
+            next: self.next.save(),
+        }
+    }
+    fn restore(&mut self, state: Self::State) {
+        self.scopes = state.scopes;
+        self.names = state.names;
+        self.next.restore(state.next)
+    }
+}
+
+/// Removes text that consists only of whitespace, unless it is within the scope of xml:space="preserve".
+pub struct WhitespaceStripper<H: ContentHandler> {
+    next: H,
+    text: String,
+    preserve: Vec<bool>,
+}
+
+impl<H: ContentHandler> WhitespaceStripper<H> {
+    pub fn new(next: H) -> Self {
+        WhitespaceStripper {
+            next,
+            text: String::new(),
+            preserve: vec![],
+        }
+    }
+    // Adjacent text is collected, so that it can be checked as a whole
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.text.is_empty() {
+            let t = std::mem::take(&mut self.text);
+            if self.preserve.last().copied().unwrap_or(false)
+                || !t.chars().all(|c| matches!(c, ' ' | '\t' | '\r' | '\n'))
+            {
+                self.next.characters(t.as_str())?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<H: ContentHandler> ContentHandler for WhitespaceStripper<H> {
+    fn start_document(&mut self) -> Result<(), Error> {
+        self.next.start_document()
+    }
+    fn is_finished(&self) -> bool {
+        self.next.is_finished()
+    }
+    fn end_document(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        self.next.end_document()
+    }
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        self.flush()?;
+        let inherited = self.preserve.last().copied().unwrap_or(false);
+        let p = attributes
+            .iter()
+            .find(|(an, _)| {
+                an.get_prefix().as_deref() == Some("xml") && an.get_localname() == "space"
+            })
+            .map_or(inherited, |(_, v)| match v.to_string().as_str() {
+                "preserve" => true,
+                "default" => false,
+                _ => inherited,
+            });
+        self.preserve.push(p);
+        self.next.start_element(name, attributes)
+    }
+    fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+        self.flush()?;
+        self.preserve.pop();
+        self.next.end_element(name)
+    }
+    fn characters(&mut self, text: &str) -> Result<(), Error> {
+        self.text.push_str(text);
+        Ok(())
+    }
+    fn comment(&mut self, text: &str) -> Result<(), Error> {
+        self.flush()?;
+        self.next.comment(text)
+    }
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Error> {
+        self.flush()?;
+        self.next.processing_instruction(target, data)
+    }
+    fn start_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        self.flush()?;
+        self.next.start_entity(name)
+    }
+    fn end_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        self.flush()?;
+        self.next.end_entity(name)
+    }
+    fn skipped_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        self.flush()?;
+        self.next.skipped_entity(name)
+    }
+}
+
+impl<H: ContentHandler> Filter for WhitespaceStripper<H> {
+    type Inner = H;
+    fn get_inner(&mut self) -> &mut H {
+        &mut self.next
+    }
+    fn into_inner(self) -> H {
+        self.next
+    }
+}
+
+/// The saved state of a [WhitespaceStripper]: the text that has not yet been passed on, and where xml:space="preserve" is in effect.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WhitespaceStripperState<S> {
+    text: String,
+    preserve: Vec<bool>,
+    next: S,
+}
+
+impl<H: Resumable> Resumable for WhitespaceStripper<H> {
+    type State = WhitespaceStripperState<H::State>;
+    fn save(&self) -> Self::State {
+        WhitespaceStripperState {
+            text: self.text.clone(),
+            preserve: self.preserve.clone(),
+            next: self.next.save(),
+        }
+    }
+    fn restore(&mut self, state: Self::State) {
+        self.text = state.text;
+        self.preserve = state.preserve;
+        self.next.restore(state.next)
+    }
+}
+
+/// Replaces XInclude include elements with the resource identified by their href attribute.
+///
+/// The href is resolved against the base URL, and fetched using the [Resolver]. With parse="text" the resource is included as text, otherwise it is parsed as XML and its document element is included. The content of the include element, such as any fallback element, is discarded. The xpointer attribute is not supported.
+pub struct IncludeResolver<H: ContentHandler, R: Resolver> {
+    next: H,
+    resolver: R,
+    base: Option<Url>,
+    // URLs of the resources currently being included, to detect inclusion loops
+    including: Vec<Url>,
+    // Depth within an include element whose content is being discarded
+    skip: usize,
+}
+
+impl<H: ContentHandler, R: Resolver> IncludeResolver<H, R> {
+    pub fn new(next: H, resolver: R, base: Option<Url>) -> Self {
+        IncludeResolver {
+            next,
+            resolver,
+            base,
+            including: vec![],
+            skip: 0,
+        }
+    }
+    fn include(&mut self, attributes: &[(QualifiedName, Value)]) -> Result<(), Error> {
+        let attr = |n: &str| {
+            attributes
+                .iter()
+                .find(|(an, _)| an.get_nsuri().is_none() && an.get_localname() == n)
+                .map(|(_, v)| v.to_string())
+        };
+        if attr("xpointer").is_some() {
+            return Result::Err(Error::new(
+                ErrorKind::NotImplemented,
+                String::from("xpointer is not supported"),
+            ));
+        }
+        let href = attr("href").ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("include does not have a href attribute"),
+        ))?;
+        let url = resolve_href(self.including.last().or(self.base.as_ref()), href.as_str())?;
+        if self.including.contains(&url) {
+            return Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("inclusion loop for \"{}\"", url),
+            ));
+        }
+        let content = self.resolver.resolve(&url)?;
+        match attr("parse").as_deref() {
+            Some("text") => self.next.characters(content.as_str()),
+            None | Some("xml") => {
+                let d = XMLDocument::try_from(content.as_str())?;
+                self.including.push(url);
+                // The included document has its own namespace declarations
+                let r = emit_nodes(&d.content, &mut NamespaceFixer::new(&mut *self));
+                self.including.pop();
+                r
+            }
+            Some(p) => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                format!("unknown parse attribute value \"{}\"", p),
+            )),
+        }
+    }
+}
+
+fn is_include(name: &QualifiedName) -> bool {
+    name.get_nsuri_ref() == Some(XINCLUDE_NAMESPACE) && name.get_localname() == "include"
+}
+
+impl<H: ContentHandler, R: Resolver> ContentHandler for IncludeResolver<H, R> {
+    fn start_document(&mut self) -> Result<(), Error> {
+        self.next.start_document()
+    }
+    fn is_finished(&self) -> bool {
+        self.next.is_finished()
+    }
+    fn end_document(&mut self) -> Result<(), Error> {
+        self.next.end_document()
+    }
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        if self.skip > 0 {
+            self.skip += 1;
+            Ok(())
+        } else if is_include(&name) {
+            self.include(&attributes)?;
+            self.skip = 1;
+            Ok(())
+        } else {
+            self.next.start_element(name, attributes)
+        }
+    }
+    fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            Ok(())
+        } else {
+            self.next.end_element(name)
+        }
+    }
+    fn characters(&mut self, text: &str) -> Result<(), Error> {
+        if self.skip > 0 {
+            Ok(())
+        } else {
+            self.next.characters(text)
+        }
+    }
+    fn comment(&mut self, text: &str) -> Result<(), Error> {
+        if self.skip > 0 {
+            Ok(())
+        } else {
+            self.next.comment(text)
+        }
+    }
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Error> {
+        if self.skip > 0 {
+            Ok(())
+        } else {
+            self.next.processing_instruction(target, data)
+        }
+    }
+    fn start_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.skip > 0 {
+            Ok(())
+        } else {
+            self.next.start_entity(name)
+        }
+    }
+    fn end_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.skip > 0 {
+            Ok(())
+        } else {
+            self.next.end_entity(name)
+        }
+    }
+    fn skipped_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.skip > 0 {
+            Ok(())
+        } else {
+            self.next.skipped_entity(name)
+        }
+    }
+}
+
+impl<H: ContentHandler, R: Resolver> Filter for IncludeResolver<H, R> {
+    type Inner = H;
+    fn get_inner(&mut self) -> &mut H {
+        &mut self.next
+    }
+    fn into_inner(self) -> H {
+        self.next
+    }
+}
+
+/// A step in the path from the document element to an element: the name of the element, and its position among its siblings that have the same name, counting from 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathStep {
+    pub name: QualifiedName,
+    pub position: usize,
+}
+
+/// Keeps track of the path to the current element.
+#[derive(Clone, Debug, Default)]
+struct PathTracker {
+    path: Vec<PathStep>,
+    // The number of elements of each name seen so far, for each open element
+    counts: Vec<HashMap<QualifiedName, usize>>,
+}
+
+impl PathTracker {
+    fn start(&mut self, name: &QualifiedName) {
+        if self.counts.len() <= self.path.len() {
+            self.counts.push(HashMap::new())
+        }
+        let c = self.counts[self.path.len()]
+            .entry(name.clone())
+            .or_insert(0);
+        *c += 1;
+        self.path.push(PathStep {
+            name: name.clone(),
+            position: *c,
+        });
+    }
+    fn end(&mut self) {
+        self.path.pop();
+        self.counts.truncate(self.path.len() + 1);
+    }
+}
+
+/// Stops the document after the first element whose path satisfies a predicate.
+///
+/// The predicate is called with the path to each element, in document order. When the matching element ends, end_element events are reported for each of its ancestors and no further events are passed on, except end_document. Used with [parse_incremental], the rest of the document is not parsed at all.
+///
+/// ```rust
+/// use xrust::forest::Forest;
+/// use xrust::sax::{parse_incremental, TreeBuilder, Truncator};
+///
+/// let mut f = Forest::new();
+/// let mut b = TreeBuilder::new(&mut f);
+/// // Stop after the second entry. The rest of the document is not parsed, so it doesn't matter that it is unfinished.
+/// parse_incremental(
+///     "<feed><title>News</title><entry>1</entry><entry>2</entry><entry>3</entry><entry>4",
+///     &mut Truncator::new(&mut b, |p| {
+///         p.len() == 2 && p[1].name.get_localname() == "entry" && p[1].position == 2
+///     }),
+/// )
+/// .expect("unable to parse document");
+/// let t = b.finish().expect("unable to build tree");
+/// assert_eq!(
+///     f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+///     "<feed><title>News</title><entry>1</entry><entry>2</entry></feed>"
+/// );
+/// ```
+pub struct Truncator<H: ContentHandler, P: FnMut(&[PathStep]) -> bool> {
+    next: H,
+    predicate: P,
+    tracker: PathTracker,
+    // The depth of the element that satisfied the predicate
+    target: Option<usize>,
+    finished: bool,
+}
+
+impl<H: ContentHandler, P: FnMut(&[PathStep]) -> bool> Truncator<H, P> {
+    pub fn new(next: H, predicate: P) -> Self {
+        Truncator {
+            next,
+            predicate,
+            tracker: PathTracker::default(),
+            target: None,
+            finished: false,
+        }
+    }
+}
+
+impl<H: ContentHandler, P: FnMut(&[PathStep]) -> bool> ContentHandler for Truncator<H, P> {
+    fn start_document(&mut self) -> Result<(), Error> {
+        self.next.start_document()
+    }
+    fn end_document(&mut self) -> Result<(), Error> {
+        self.next.end_document()
+    }
+    fn is_finished(&self) -> bool {
+        self.finished || self.next.is_finished()
+    }
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.tracker.start(&name);
+        if self.target.is_none() && (self.predicate)(&self.tracker.path) {
+            self.target = Some(self.tracker.path.len())
+        }
+        self.next.start_element(name, attributes)
+    }
+    fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.next.end_element(name)?;
+        if self.target == Some(self.tracker.path.len()) {
+            // Close the ancestors of the matching element
+            self.tracker.end();
+            while let Some(s) = self.tracker.path.pop() {
+                self.next.end_element(s.name)?
+            }
+            self.finished = true;
+        } else {
+            self.tracker.end()
+        }
+        Ok(())
+    }
+    fn characters(&mut self, text: &str) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else {
+            self.next.characters(text)
+        }
+    }
+    fn comment(&mut self, text: &str) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else {
+            self.next.comment(text)
+        }
+    }
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else {
+            self.next.processing_instruction(target, data)
+        }
+    }
+    fn start_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else {
+            self.next.start_entity(name)
+        }
+    }
+    fn end_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else {
+            self.next.end_entity(name)
+        }
+    }
+    fn skipped_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else {
+            self.next.skipped_entity(name)
+        }
+    }
+}
+
+impl<H: ContentHandler, P: FnMut(&[PathStep]) -> bool> Filter for Truncator<H, P> {
+    type Inner = H;
+    fn get_inner(&mut self) -> &mut H {
+        &mut self.next
+    }
+    fn into_inner(self) -> H {
+        self.next
+    }
+}
+
+/// A path that selects elements by their names and positions, such as "/feed/entry[2]/title".
+///
+/// The path is absolute, and each step is the name of an element, or "*" to match any element, optionally followed by a position among the siblings with that name, counting from 1. Names are compared by prefix and local name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimplePath(Vec<SimpleStep>);
+
+#[derive(Clone, Debug, PartialEq)]
+struct SimpleStep {
+    // None matches any name
+    name: Option<String>,
+    position: Option<usize>,
+}
+
+impl SimpleStep {
+    fn matches(&self, s: &PathStep) -> bool {
+        self.name.as_ref().is_none_or(|n| {
+            *n == match s.name.get_prefix() {
+                Some(p) => format!("{}:{}", p, s.name.get_localname()),
+                None => s.name.get_localname(),
+            }
+        }) && self.position.is_none_or(|p| p == s.position)
+    }
+}
+
+impl SimplePath {
+    /// Whether the path selects the element with the given path.
+    pub fn matches(&self, path: &[PathStep]) -> bool {
+        self.0.len() == path.len() && self.0.iter().zip(path).all(|(s, p)| s.matches(p))
+    }
+    /// Whether the element with the given path is an ancestor of the elements selected by this path.
+    pub fn is_ancestor(&self, path: &[PathStep]) -> bool {
+        self.0.len() > path.len() && self.0.iter().zip(path).all(|(s, p)| s.matches(p))
+    }
+}
+
+impl TryFrom<&str> for SimplePath {
+    type Error = Error;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let error = || {
+            Error::new(
+                ErrorKind::StaticSyntax,
+                format!("\"{}\" is not a simple path", s),
+            )
+        };
+        let steps = s.strip_prefix('/').ok_or_else(error)?;
+        let mut result = vec![];
+        for t in steps.split('/') {
+            let (name, position) = parse_step(t).ok_or_else(error)?;
+            result.push(SimpleStep { name, position })
+        }
+        Ok(SimplePath(result))
+    }
+}
+
+// Parse a step of a simple path: a name, or "*" for any name, optionally followed by a position in square brackets, counting from 1.
+// Returns the name, which is None for "*", and the position.
+pub(crate) fn parse_step(t: &str) -> Option<(Option<String>, Option<usize>)> {
+    let (name, position) = match t.strip_suffix(']').and_then(|u| u.split_once('[')) {
+        Some((n, p)) => (n, Some(p.parse::<usize>().ok()?)),
+        None => (t, None),
+    };
+    let valid = name == "*"
+        || name
+            .split(':')
+            .all(|u| ncname(u).is_ok_and(|(rest, _)| rest.is_empty()))
+            && name.split(':').count() <= 2;
+    if !valid || position == Some(0) {
+        return None;
+    }
+    Some(((name != "*").then(|| name.to_string()), position))
+}
+
+/// Keeps only the elements that are selected by any of a set of [SimplePath]s, along with their descendants and the other content within them.
+///
+/// The ancestors of selected elements are also kept, with their attributes, but their other content is discarded. Everything else is discarded. Used with [parse_incremental] and a [TreeBuilder], only the selected parts of a document are added to the tree.
+///
+/// ```rust
+/// use std::convert::TryFrom;
+/// use xrust::forest::Forest;
+/// use xrust::sax::{parse_incremental, Projector, SimplePath, TreeBuilder};
+///
+/// let mut f = Forest::new();
+/// let mut b = TreeBuilder::new(&mut f);
+/// parse_incremental(
+///     "<feed><title>News</title><entry><title>One</title><body>...</body></entry><entry><title>Two</title><body>...</body></entry></feed>",
+///     &mut Projector::new(&mut b, vec![SimplePath::try_from("/feed/entry/title").unwrap()]),
+/// )
+/// .expect("unable to parse document");
+/// let t = b.finish().expect("unable to build tree");
+/// assert_eq!(
+///     f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+///     "<feed><entry><title>One</title></entry><entry><title>Two</title></entry></feed>"
+/// );
+/// ```
+pub struct Projector<H: ContentHandler> {
+    next: H,
+    paths: Vec<SimplePath>,
+    tracker: PathTracker,
+    // Whether each open element was kept
+    kept: Vec<bool>,
+    // The depth of the selected element that is open
+    selected: Option<usize>,
+}
+
+impl<H: ContentHandler> Projector<H> {
+    pub fn new(next: H, paths: Vec<SimplePath>) -> Self {
+        Projector {
+            next,
+            paths,
+            tracker: PathTracker::default(),
+            kept: vec![],
+            selected: None,
+        }
+    }
+}
+
+impl<H: ContentHandler> ContentHandler for Projector<H> {
+    fn start_document(&mut self) -> Result<(), Error> {
+        self.next.start_document()
+    }
+    fn end_document(&mut self) -> Result<(), Error> {
+        self.next.end_document()
+    }
+    fn is_finished(&self) -> bool {
+        self.next.is_finished()
+    }
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        self.tracker.start(&name);
+        let path = &self.tracker.path;
+        let keep = if self.selected.is_some() {
+            true
+        } else if self.paths.iter().any(|p| p.matches(path)) {
+            self.selected = Some(path.len());
+            true
+        } else {
+            self.paths.iter().any(|p| p.is_ancestor(path))
+        };
+        self.kept.push(keep);
+        if keep {
+            self.next.start_element(name, attributes)
+        } else {
+            Ok(())
+        }
+    }
+    fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+        if self.selected == Some(self.tracker.path.len()) {
+            self.selected = None
+        }
+        self.tracker.end();
+        if self.kept.pop().unwrap_or(false) {
+            self.next.end_element(name)
+        } else {
+            Ok(())
+        }
+    }
+    fn characters(&mut self, text: &str) -> Result<(), Error> {
+        if self.selected.is_some() {
+            self.next.characters(text)
+        } else {
+            Ok(())
+        }
+    }
+    fn comment(&mut self, text: &str) -> Result<(), Error> {
+        if self.selected.is_some() {
+            self.next.comment(text)
+        } else {
+            Ok(())
+        }
+    }
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Error> {
+        if self.selected.is_some() {
+            self.next.processing_instruction(target, data)
+        } else {
+            Ok(())
+        }
+    }
+    fn start_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.selected.is_some() {
+            self.next.start_entity(name)
+        } else {
+            Ok(())
+        }
+    }
+    fn end_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.selected.is_some() {
+            self.next.end_entity(name)
+        } else {
+            Ok(())
+        }
+    }
+    fn skipped_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        if self.selected.is_some() {
+            self.next.skipped_entity(name)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<H: ContentHandler> Filter for Projector<H> {
+    type Inner = H;
+    fn get_inner(&mut self) -> &mut H {
+        &mut self.next
+    }
+    fn into_inner(self) -> H {
+        self.next
+    }
+}
+
+/// Creates a [Tree](../forest/struct.Tree.html) from events.
+///
+/// Namespace declarations are used to resolve the namespace URI of prefixed names, but are not added to the tree as attributes. Adjacent text is combined into a single text node.
+pub struct TreeBuilder<'a> {
+    f: &'a mut Forest,
+    t: TreeIndex,
+    // The open elements and entity references
+    stack: Vec<Node>,
+    // The prefixes declared by each open element
+    scopes: Vec<Vec<(String, String)>>,
+    text: String,
+    undeclared_prefixes: bool,
+}
+
+impl<'a> TreeBuilder<'a> {
+    /// Create a builder that adds a new [Tree](../forest/struct.Tree.html) to the [Forest].
+    pub fn new(f: &'a mut Forest) -> Self {
+        let t = f.plant_tree();
+        TreeBuilder {
+            f,
+            t,
+            stack: vec![],
+            scopes: vec![],
+            text: String::new(),
+            undeclared_prefixes: false,
+        }
+    }
+    /// Set whether names may have prefixes that are not declared, as for [ParserConfig::undeclared_prefixes](../parsexml/struct.ParserConfig.html#structfield.undeclared_prefixes). If so, the name has no namespace URI; otherwise it is an error. The default is false.
+    pub fn undeclared_prefixes(mut self, b: bool) -> Self {
+        self.undeclared_prefixes = b;
+        self
+    }
+    /// The index of the tree being built.
+    pub fn tree_index(&self) -> TreeIndex {
+        self.t
+    }
+    /// Finish building the tree. It is an error if any element has not been ended.
+    pub fn finish(mut self) -> Result<TreeIndex, Error> {
+        self.flush()?;
+        if self.stack.is_empty() {
+            Ok(self.t)
+        } else {
+            Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("element has not been ended"),
+            ))
+        }
+    }
+    fn add(&mut self, n: Node) -> Result<(), Error> {
+        match self.stack.last() {
+            Some(p) => p.append_child(self.f, n),
+            None => self.f.get_ref_mut(self.t).unwrap().push_doc_node(n),
+        }
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.text.is_empty() {
+            let t = std::mem::take(&mut self.text);
+            let n = self
+                .f
+                .get_ref_mut(self.t)
+                .unwrap()
+                .new_text(Value::from(t))?;
+            self.add(n)?
+        }
+        Ok(())
+    }
+    fn resolve(&self, name: QualifiedName) -> Result<QualifiedName, Error> {
+        match (name.get_nsuri(), name.get_prefix()) {
+            (None, Some(p)) => {
+                let u = if p == "xml" {
+                    Some(String::from(XML_NAMESPACE))
+                } else {
+                    // An empty URI undeclares the prefix
+                    self.scopes
+                        .iter()
+                        .rev()
+                        .flat_map(|s| s.iter())
+                        .find(|(q, _)| *q == p)
+                        .map(|(_, u)| u.clone())
+                        .filter(|u| !u.is_empty())
+                };
+                match u {
+                    Some(v) => Ok(QualifiedName::new(Some(v), Some(p), name.get_localname())),
+                    None if self.undeclared_prefixes => Ok(name),
+                    None => Result::Err(undeclared_prefix(&p)),
+                }
+            }
+            _ => Ok(name),
+        }
+    }
+}
+
+impl<'a> ContentHandler for TreeBuilder<'a> {
+    fn end_document(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+    fn start_element(
+        &mut self,
+        name: QualifiedName,
+        attributes: Vec<(QualifiedName, Value)>,
+    ) -> Result<(), Error> {
+        self.flush()?;
+        self.scopes.push(
+            attributes
+                .iter()
+                .filter(|(an, _)| an.get_prefix().as_deref() == Some("xmlns"))
+                .map(|(an, av)| (an.get_localname(), av.to_string()))
+                .collect(),
+        );
+        let ename = self.resolve(name)?;
+        let e = self.f.get_ref_mut(self.t).unwrap().new_element(ename)?;
+        for (an, av) in attributes {
+            if is_declaration(&an) {
+                continue;
+            }
+            let aname = self.resolve(an)?;
+            let a = self
+                .f
+                .get_ref_mut(self.t)
+                .unwrap()
+                .new_attribute(aname, av)?;
+            e.add_attribute(self.f, a)?
+        }
+        self.add(e)?;
+        self.stack.push(e);
+        Ok(())
+    }
+    fn end_element(&mut self, _name: QualifiedName) -> Result<(), Error> {
+        self.flush()?;
+        self.scopes.pop();
+        match self.stack.pop() {
+            Some(n) if n.node_type(self.f) == NodeType::Element => Ok(()),
+            _ => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("end of element does not match start"),
+            )),
+        }
+    }
+    fn characters(&mut self, text: &str) -> Result<(), Error> {
+        self.text.push_str(text);
+        Ok(())
+    }
+    fn comment(&mut self, text: &str) -> Result<(), Error> {
+        self.flush()?;
+        let n = self
+            .f
+            .get_ref_mut(self.t)
+            .unwrap()
+            .new_comment(Value::from(text))?;
+        self.add(n)
+    }
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Error> {
+        self.flush()?;
+        let n = self
+            .f
+            .get_ref_mut(self.t)
+            .unwrap()
+            .new_processing_instruction(
+                QualifiedName::new(None, None, target.to_string()),
+                Value::from(data),
+            )?;
+        self.add(n)
+    }
+    fn start_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        self.flush()?;
+        let n = self
+            .f
+            .get_ref_mut(self.t)
+            .unwrap()
+            .new_entity_reference(name.clone())?;
+        self.add(n)?;
+        self.stack.push(n);
+        Ok(())
+    }
+    fn end_entity(&mut self, _name: &QualifiedName) -> Result<(), Error> {
+        self.flush()?;
+        match self.stack.pop() {
+            Some(n) if n.node_type(self.f) == NodeType::EntityReference => Ok(()),
+            _ => Result::Err(Error::new(
+                ErrorKind::Unknown,
+                String::from("end of entity does not match start"),
+            )),
+        }
+    }
+    fn skipped_entity(&mut self, name: &QualifiedName) -> Result<(), Error> {
+        // The reference is kept, but it has no content
+        self.start_entity(name)?;
+        self.end_entity(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolvers::MemoryResolver;
+
+    // Records events as strings
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+    impl ContentHandler for Recorder {
+        fn start_element(
+            &mut self,
+            name: QualifiedName,
+            attributes: Vec<(QualifiedName, Value)>,
+        ) -> Result<(), Error> {
+            let mut s = format!(
+                "<{}{}",
+                name.get_nsuri()
+                    .map_or(String::new(), |u| format!("{{{}}}", u)),
+                name.get_localname()
+            );
+            for (an, av) in attributes {
+                s.push_str(format!(" {}={}", an.to_string(), av.to_string()).as_str())
+            }
+            self.0.push(s);
+            Ok(())
+        }
+        fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+            self.0.push(format!("</{}", name.get_localname()));
+            Ok(())
+        }
+        fn characters(&mut self, text: &str) -> Result<(), Error> {
+            self.0.push(format!("\"{}\"", text));
+            Ok(())
+        }
+        fn comment(&mut self, text: &str) -> Result<(), Error> {
+            self.0.push(format!("!{}", text));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events() {
+        let mut r = Recorder::default();
+        parse("<a b='1'><!--c-->text<d/></a>", &mut r).expect("unable to parse");
+        assert_eq!(r.0, vec!["<a b=1", "!c", "\"text\"", "<d", "</d", "</a"])
+    }
+
+    #[test]
+    fn strip_whitespace() {
+        let mut s = WhitespaceStripper::new(Recorder::default());
+        parse(
+            "<a>\n <b> x </b>\n <c xml:space='preserve'> <d> </d></c></a>",
+            &mut s,
+        )
+        .expect("unable to parse");
+        assert_eq!(
+            s.into_inner().0,
+            vec![
+                "<a",
+                "<b",
+                "\" x \"",
+                "</b",
+                "<c xml:space=preserve",
+                "\" \"",
+                "<d",
+                "\" \"",
+                "</d",
+                "</c",
+                "</a"
+            ]
+        )
+    }
+
+    #[test]
+    fn fix_namespaces() {
+        let mut r = Recorder::default();
+        parse(
+            "<a xmlns='urn:default' xmlns:p='urn:p'><p:b p:c='1' d='2'/><e xmlns=''/></a>",
+            &mut NamespaceFixer::new(&mut r),
+        )
+        .expect("unable to parse");
+        assert_eq!(
+            r.0,
+            vec![
+                "<{urn:default}a xmlns=urn:default xmlns:p=urn:p",
+                "<{urn:p}b p:c=1 d=2",
+                "</b",
+                "<e xmlns=",
+                "</e",
+                "</a"
+            ]
+        );
+
+        // Add a missing declaration
+        let mut f = NamespaceFixer::new(Recorder::default());
+        f.start_element(
+            QualifiedName::new(
+                Some(String::from("urn:x")),
+                Some(String::from("x")),
+                String::from("a"),
+            ),
+            vec![],
+        )
+        .expect("unable to fix namespace");
+        f.start_element(
+            QualifiedName::new(
+                Some(String::from("urn:x")),
+                Some(String::from("x")),
+                String::from("b"),
+            ),
+            vec![],
+        )
+        .expect("unable to fix namespace");
+        assert_eq!(
+            f.get_inner().0,
+            vec!["<{urn:x}a xmlns:x=urn:x", "<{urn:x}b"]
+        );
+
+        let mut r = Recorder::default();
+        assert!(parse("<p:a/>", &mut NamespaceFixer::new(&mut r)).is_err())
+    }
+
+    #[test]
+    fn include() {
+        let mut m = MemoryResolver::new();
+        m.insert("http://example.org/part.xml", "<part>\n <p/>\n</part>");
+        m.insert("http://example.org/text.txt", "some text");
+        m.insert(
+            "http://example.org/loop.xml",
+            "<xi:include xmlns:xi='http://www.w3.org/2001/XInclude' href='loop.xml'/>",
+        );
+        let base = Url::parse("http://example.org/doc.xml").ok();
+        let mut r = Recorder::default();
+        parse(
+            "<doc xmlns:xi='http://www.w3.org/2001/XInclude'>
+  <xi:include href='part.xml'><xi:fallback>missing</xi:fallback></xi:include>
+  <xi:include href='text.txt' parse='text'/>
+</doc>",
+            &mut NamespaceFixer::new(IncludeResolver::new(
+                WhitespaceStripper::new(&mut r),
+                m.clone(),
+                base.clone(),
+            )),
+        )
+        .expect("unable to parse");
+        assert_eq!(
+            r.0,
+            vec![
+                "<doc xmlns:xi=http://www.w3.org/2001/XInclude",
+                "<part",
+                "<p",
+                "</p",
+                "</part",
+                // Included text is merged with the surrounding text
+                "\"\n  some text\n\"",
+                "</doc"
+            ]
+        );
+
+        let mut r = Recorder::default();
+        assert!(parse(
+            "<xi:include xmlns:xi='http://www.w3.org/2001/XInclude' href='loop.xml'/>",
+            &mut NamespaceFixer::new(IncludeResolver::new(&mut r, m, base))
+        )
+        .is_err())
+    }
+
+    #[test]
+    fn tree_builder() {
+        let src = "<!--before--><a xmlns:p='urn:p' p:x='1'>one<p:b>two<?pi data?></p:b><c/></a>";
+        let mut f = Forest::new();
+        let mut b = TreeBuilder::new(&mut f);
+        parse(src, &mut b).expect("unable to parse");
+        let t = b.finish().expect("unable to build tree");
+        let u = f.grow_tree(src).expect("unable to parse");
+        let d = f.get_ref(t).unwrap().get_doc_node();
+        let e = d.get_first_element(&f).unwrap();
+        assert_eq!(
+            e.to_xml(&f),
+            f.get_ref(u)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f)
+        );
+        assert_eq!(
+            d.child_iter().next(&f).unwrap().node_type(&f),
+            NodeType::Comment
+        );
+        let mut cit = e.child_iter();
+        cit.next(&f);
+        let b = cit.next(&f).unwrap();
+        assert_eq!(b.to_name(&f).get_nsuri_ref(), Some("urn:p"))
+    }
+
+    #[test]
+    fn tree_builder_undeclared_prefix() {
+        let src = "<p:a q:x='1'/>";
+        let mut f = Forest::new();
+        let mut b = TreeBuilder::new(&mut f);
+        assert!(parse(src, &mut b).is_err());
+        let mut b = TreeBuilder::new(&mut f).undeclared_prefixes(true);
+        parse(src, &mut b).expect("unable to parse");
+        let t = b.finish().expect("unable to build tree");
+        let e = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(e.to_name(&f).get_nsuri_ref(), None);
+        assert_eq!(e.to_xml(&f), "<p:a q:x='1'></p:a>")
+    }
+
+    #[test]
+    fn tree_builder_chain() {
+        let mut f = Forest::new();
+        let mut b = TreeBuilder::new(&mut f);
+        parse(
+            "<a>\n  <b>x</b>\n  <c/>\n</a>",
+            &mut NamespaceFixer::new(WhitespaceStripper::new(&mut b)),
+        )
+        .expect("unable to parse");
+        let t = b.finish().expect("unable to build tree");
+        assert_eq!(
+            f.get_ref(t)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&f)
+                .unwrap()
+                .to_xml(&f),
+            "<a><b>x</b><c></c></a>"
+        );
+
+        let mut b = TreeBuilder::new(&mut f);
+        b.start_element(QualifiedName::new(None, None, String::from("a")), vec![])
+            .expect("unable to start element");
+        assert!(b.finish().is_err())
+    }
+
+    #[test]
+    fn emit_events() {
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<a xmlns:p='urn:p' p:x='1'>one<p:b><!--c-->two</p:b></a>")
+            .expect("unable to parse");
+        let d = f.get_ref(src).unwrap().get_doc_node();
+        let mut r = Recorder::default();
+        d.emit_events(&f, &mut r).expect("unable to emit events");
+        assert_eq!(
+            r.0,
+            vec![
+                "<a p:x=1",
+                "\"one\"",
+                "<{urn:p}b",
+                "!c",
+                "\"two\"",
+                "</b",
+                "</a"
+            ]
+        );
+
+        // Round trip through a TreeBuilder
+        let mut g = Forest::new();
+        let mut b = TreeBuilder::new(&mut g);
+        d.emit_events(&f, &mut NamespaceFixer::new(&mut b))
+            .expect("unable to emit events");
+        let t = b.finish().expect("unable to build tree");
+        assert_eq!(
+            g.get_ref(t)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(&g)
+                .unwrap()
+                .to_xml(&g),
+            d.get_first_element(&f).unwrap().to_xml(&f)
+        )
+    }
+
+    #[test]
+    fn incremental() {
+        let mut r = Recorder::default();
+        parse_incremental(
+            "<?xml version='1.0'?>\n<!DOCTYPE a>\n<a x='1'>one<b/>&amp;&ent;<!--c--></a>\n",
+            &mut r,
+        )
+        .expect("unable to parse document");
+        assert_eq!(
+            r.0,
+            vec!["<a x=1", "\"one\"", "<b", "</b", "\"&\"", "!c", "</a"]
+        );
+        assert!(parse_incremental("<a><b></a></b>", &mut Recorder::default()).is_err());
+        assert!(parse_incremental("<a></a><b/>", &mut Recorder::default()).is_err());
+        assert!(parse_incremental("<a>", &mut Recorder::default()).is_err());
+        assert!(parse_incremental("<a/>text", &mut Recorder::default()).is_err())
+    }
+
+    #[test]
+    fn resumable() {
+        let doc = "<?xml version='1.0'?>\n<a x='1'>one<b/>&amp;<!--c--><d>two</d></a>\n<!--e-->";
+        let mut whole = Recorder::default();
+        parse_incremental(doc, &mut whole).expect("unable to parse document");
+        // Parse the document as it grows one character at a time
+        let mut r = Recorder::default();
+        let mut c = Checkpoint::default();
+        let mut parsed = 0;
+        for i in 1..=doc.len() {
+            c = parse_resumable(&doc[parsed..i], &c, &mut r).expect("unable to parse document");
+            parsed += c.offset;
+            assert_eq!(c.is_complete(), i >= doc.len() - 9)
+        }
+        finish_resumable(&doc[parsed..], &c, &mut r).expect("unable to parse document");
+        assert_eq!(r.0, whole.0);
+        assert!(parse_resumable("<a></b>", &Checkpoint::default(), &mut r).is_err());
+        // The document must be complete when it is finished
+        let c = parse_resumable("<a>te", &Checkpoint::default(), &mut r)
+            .expect("unable to parse document");
+        assert!(finish_resumable("te", &c, &mut r).is_err());
+        assert!(finish_resumable("<!--c-", &Checkpoint::<()>::default(), &mut r).is_err())
+    }
+
+    // Records the end of the document, and can be resumed
+    #[derive(Default)]
+    struct Log(Vec<String>);
+    impl ContentHandler for Log {
+        fn end_document(&mut self) -> Result<(), Error> {
+            self.0.push(String::from("$"));
+            Ok(())
+        }
+        fn start_element(
+            &mut self,
+            name: QualifiedName,
+            _attributes: Vec<(QualifiedName, Value)>,
+        ) -> Result<(), Error> {
+            self.0.push(format!("<{}", name.to_string()));
+            Ok(())
+        }
+        fn end_element(&mut self, name: QualifiedName) -> Result<(), Error> {
+            self.0.push(format!("</{}", name.to_string()));
+            Ok(())
+        }
+        fn characters(&mut self, text: &str) -> Result<(), Error> {
+            self.0.push(format!("\"{}\"", text));
+            Ok(())
+        }
+        fn comment(&mut self, text: &str) -> Result<(), Error> {
+            self.0.push(format!("!{}", text));
+            Ok(())
+        }
+    }
+    impl Resumable for Log {
+        type State = Vec<String>;
+        fn save(&self) -> Vec<String> {
+            self.0.clone()
+        }
+        fn restore(&mut self, state: Vec<String>) {
+            self.0 = state
+        }
+    }
+
+    #[test]
+    fn resumable_state() {
+        let doc = "<a xmlns:p='urn:p'>\n  <p:b xml:space='preserve'> </p:b>\n  <p:c>x</p:c>\n</a>\n<!--end-->";
+        let mut whole = NamespaceFixer::new(WhitespaceStripper::new(Log::default()));
+        parse(doc, &mut whole).expect("unable to parse document");
+        let whole = whole.into_inner().into_inner().0;
+        assert_eq!(whole.last().map(String::as_str), Some("$"));
+        // Each part of the document is parsed by a new filter chain, as if the process had been restarted
+        let mut c = Checkpoint::default();
+        let mut rest = String::new();
+        for part in doc.as_bytes().chunks(7) {
+            rest.push_str(std::str::from_utf8(part).unwrap());
+            let mut h = NamespaceFixer::new(WhitespaceStripper::new(Log::default()));
+            c = parse_resumable_with_state(&rest, &c, &mut h).expect("unable to parse document");
+            rest.drain(..c.offset);
+        }
+        let mut h = NamespaceFixer::new(WhitespaceStripper::new(Log::default()));
+        h.restore(c.state.clone());
+        finish_resumable(&rest, &c, &mut h).expect("unable to parse document");
+        assert_eq!(h.into_inner().into_inner().0, whole)
+    }
+
+    #[test]
+    fn truncate() {
+        let mut r = Recorder::default();
+        parse_incremental(
+            "<a><b><c/><c/><c>x</c></b><d/></a><<<not XML",
+            &mut Truncator::new(&mut r, |p| {
+                p.last()
+                    .is_some_and(|s| s.name.get_localname() == "c" && s.position == 2)
+            }),
+        )
+        .expect("unable to parse document");
+        assert_eq!(
+            r.0,
+            vec!["<a", "<b", "<c", "</c", "<c", "</c", "</b", "</a"]
+        );
+
+        // The predicate is never satisfied
+        let mut r = Recorder::default();
+        parse("<a><b/></a>", &mut Truncator::new(&mut r, |p| p.len() > 2))
+            .expect("unable to parse document");
+        assert_eq!(r.0, vec!["<a", "<b", "</b", "</a"])
+    }
+
+    #[test]
+    fn simple_path() {
+        assert!(SimplePath::try_from("/a/b:c[2]/*/*[1]").is_ok());
+        assert!(SimplePath::try_from("a/b").is_err());
+        assert!(SimplePath::try_from("/a//b").is_err());
+        assert!(SimplePath::try_from("/a[0]").is_err());
+        assert!(SimplePath::try_from("/a[x]").is_err());
+        assert!(SimplePath::try_from("/a:b:c").is_err())
+    }
+
+    #[test]
+    fn projection() {
+        let mut r = Recorder::default();
+        parse(
+            "<a x='1'>t<b><c>one</c><d/></b><b><c>two<!--k--></c></b><e><c/></e></a>",
+            &mut Projector::new(
+                &mut r,
+                vec![
+                    SimplePath::try_from("/a/b[2]/c").unwrap(),
+                    SimplePath::try_from("/*/b/d").unwrap(),
+                ],
+            ),
+        )
+        .expect("unable to parse document");
+        assert_eq!(
+            r.0,
+            vec![
+                "<a x=1", "<b", "<d", "</d", "</b", "<b", "<c", "\"two\"", "!k", "</c", "</b",
+                "</a"
+            ]
+        )
+    }
+}
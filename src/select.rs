@@ -0,0 +1,219 @@
+/*! ## Selecting nodes with simple paths
+
+A small path language for extracting nodes from a [Tree](../forest/struct.Tree.html) without compiling and evaluating an XPath expression. A path is evaluated directly with the [Node] iterators.
+
+A path is a sequence of steps separated by '/'. Each step selects child elements by name, or any child element with '*'. A step may be followed by a position, such as `item[2]`, that selects the n'th matching child (counting from 1). The last step may instead select attributes, as `@name` or `@*`; the attributes of an element are selected in the order of their names. A path that starts with '/' is evaluated from the Document-type node, otherwise it is evaluated from the given node.
+
+Names are compared with the name as it is written in the document, i.e. the prefix (if any) and the local name. Namespace URIs are not resolved.
+
+```rust
+use xrust::forest::Forest;
+use xrust::select::select;
+
+let mut f = Forest::new();
+let t = f.grow_tree("<order><item id='a'/><item id='b'/><note/></order>")
+    .expect("unable to parse XML");
+let doc = f.get_ref(t).unwrap().get_doc_node();
+
+let ids: Vec<String> = select(&f, doc, "/order/item/@id")
+    .expect("invalid path")
+    .iter()
+    .map(|a| a.to_string(&f))
+    .collect();
+assert_eq!(ids, vec!["a", "b"]);
+assert_eq!(select(&f, doc, "order/item[2]/@id").expect("invalid path").len(), 1);
+```
+*/
+
+use crate::forest::{Forest, Node, NodeType};
+use crate::sax::parse_step;
+use crate::xdmerror::*;
+use std::convert::TryFrom;
+
+/// A compiled simple path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    absolute: bool,
+    steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Step {
+    attribute: bool,
+    // None matches any name
+    name: Option<String>,
+    position: Option<usize>,
+}
+
+impl Step {
+    fn matches(&self, f: &Forest, n: Node) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|m| *m == n.to_name(f).to_string())
+    }
+}
+
+impl Path {
+    /// The nodes selected by the path. Elements are in document order. The attributes of each element are in the order of their names, since the order in which they were written is not kept in the tree. The context node is used for relative paths.
+    pub fn select(&self, f: &Forest, n: Node) -> Vec<Node> {
+        let mut current = vec![if self.absolute {
+            match f.get_ref(n.tree_index()) {
+                Some(d) => d.get_doc_node(),
+                None => return vec![],
+            }
+        } else {
+            n
+        }];
+        for s in &self.steps {
+            let mut next = vec![];
+            for c in current {
+                if s.attribute {
+                    let mut attrs = vec![];
+                    let mut ait = c.attribute_iter(f);
+                    while let Some(a) = ait.next() {
+                        if s.matches(f, a) {
+                            attrs.push((a.to_name(f).to_string(), a))
+                        }
+                    }
+                    // The order of attributes is not kept in the tree
+                    attrs.sort_by(|a, b| a.0.cmp(&b.0));
+                    next.extend(attrs.into_iter().map(|(_, a)| a));
+                    continue;
+                }
+                let mut count = 0;
+                let mut cit = c.child_iter();
+                while let Some(d) = cit.next(f) {
+                    if d.node_type(f) == NodeType::Element && s.matches(f, d) {
+                        count += 1;
+                        match s.position {
+                            Some(p) if p == count => {
+                                next.push(d);
+                                break;
+                            }
+                            Some(_) => {}
+                            None => next.push(d),
+                        }
+                    }
+                }
+            }
+            current = next;
+        }
+        current
+    }
+    /// The first node selected by the path, if any.
+    pub fn select_first(&self, f: &Forest, n: Node) -> Option<Node> {
+        self.select(f, n).first().copied()
+    }
+}
+
+impl TryFrom<&str> for Path {
+    type Error = Error;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let error = || {
+            Error::new(
+                ErrorKind::StaticSyntax,
+                format!("\"{}\" is not a simple path", s),
+            )
+        };
+        let (absolute, rest) = match s.strip_prefix('/') {
+            Some(r) => (true, r),
+            None => (false, s),
+        };
+        let mut steps = vec![];
+        if rest.is_empty() {
+            // Only "/" is allowed to have no steps
+            return if absolute {
+                Ok(Path { absolute, steps })
+            } else {
+                Result::Err(error())
+            };
+        }
+        for t in rest.split('/') {
+            if steps.last().is_some_and(|l: &Step| l.attribute) {
+                // Attributes do not have children
+                return Result::Err(error());
+            }
+            let (attribute, t) = match t.strip_prefix('@') {
+                Some(u) => (true, u),
+                None => (false, t),
+            };
+            let (name, position) = parse_step(t).ok_or_else(error)?;
+            if attribute && position.is_some() {
+                return Result::Err(error());
+            }
+            steps.push(Step {
+                attribute,
+                name,
+                position,
+            })
+        }
+        Ok(Path { absolute, steps })
+    }
+}
+
+/// Compile the path and select nodes, starting from the given node.
+pub fn select(f: &Forest, n: Node, path: &str) -> Result<Vec<Node>, Error> {
+    Ok(Path::try_from(path)?.select(f, n))
+}
+
+/// Compile the path and select the first matching node, starting from the given node.
+pub fn select_first(f: &Forest, n: Node, path: &str) -> Result<Option<Node>, Error> {
+    Ok(Path::try_from(path)?.select_first(f, n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paths() {
+        assert!(Path::try_from("/").is_ok());
+        assert!(Path::try_from("a/b/@c").is_ok());
+        assert!(Path::try_from("*[2]/@*").is_ok());
+        assert!(Path::try_from("").is_err());
+        assert!(Path::try_from("a//b").is_err());
+        assert!(Path::try_from("@c/d").is_err());
+        assert!(Path::try_from("a[0]").is_err());
+        assert!(Path::try_from("@c[1]").is_err());
+        assert!(Path::try_from("a:b:c").is_err())
+    }
+
+    #[test]
+    fn select_nodes() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a><b n='1'><c/></b><x:b xmlns:x='urn:x' n='2'/><b n='3'><c/><c/></b></a>")
+            .expect("unable to parse XML");
+        let doc = f.get_ref(t).unwrap().get_doc_node();
+        let values = |v: Vec<Node>| v.iter().map(|n| n.to_string(&f)).collect::<Vec<_>>();
+        assert_eq!(values(select(&f, doc, "/a/b/@n").unwrap()), vec!["1", "3"]);
+        assert_eq!(
+            values(select(&f, doc, "/a/*/@n").unwrap()),
+            vec!["1", "2", "3"]
+        );
+        assert_eq!(values(select(&f, doc, "a/x:b/@*").unwrap()), vec!["2"]);
+        assert_eq!(select(&f, doc, "/a/b/c").unwrap().len(), 3);
+        assert_eq!(select(&f, doc, "/a/b/c[2]").unwrap().len(), 1);
+        let b = select_first(&f, doc, "/a/b[2]").unwrap().unwrap();
+        assert_eq!(values(select(&f, b, "@n").unwrap()), vec!["3"]);
+        assert_eq!(select(&f, b, "/").unwrap(), vec![doc]);
+        assert!(select(&f, doc, "/a/d").unwrap().is_empty())
+    }
+
+    #[test]
+    fn attribute_order() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a z='1' b='2' m='3'/>")
+            .expect("unable to parse XML");
+        let doc = f.get_ref(t).unwrap().get_doc_node();
+        assert_eq!(
+            select(&f, doc, "a/@*")
+                .unwrap()
+                .iter()
+                .map(|n| n.to_string(&f))
+                .collect::<Vec<_>>(),
+            vec!["2", "3", "1"]
+        )
+    }
+}
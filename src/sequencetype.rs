@@ -0,0 +1,409 @@
+/*! ## Sequence types
+
+A [SequenceType] describes the items that a sequence may contain, and how many of them (XPath 3.1 section 2.5.4). It is used by the instance of and treat as expressions, and an [AtomicType] is the target of the cast as and castable as expressions.
+
+```rust
+use std::convert::TryFrom;
+use std::rc::Rc;
+use xrust::forest::Forest;
+use xrust::item::Item;
+use xrust::sequencetype::SequenceType;
+use xrust::value::Value;
+
+let f = Forest::new();
+let st = SequenceType::try_from("xs:decimal+").expect("unable to parse sequence type");
+let s = vec![Rc::new(Item::Value(Value::from(1))), Rc::new(Item::Value(Value::from(2.5)))];
+// xs:integer is derived from xs:decimal; xs:double is not
+assert!(!st.matches(&s, &f));
+assert!(st.matches(&s[0..1].to_vec(), &f));
+assert_eq!(st.to_string(), "xs:decimal+")
+```
+*/
+
+use crate::evaluate::KindTest;
+use crate::forest::Forest;
+use crate::item::{Item, Sequence};
+use crate::value::{NormalizedString, Value};
+use crate::xdmerror::{Error, ErrorKind};
+use chrono::{DateTime, Local};
+use rust_decimal::Decimal;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+/// The XML Schema namespace, in which the atomic types are defined.
+pub const XS_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema";
+
+/// A sequence type: either the empty sequence, or items of a type with an occurrence indicator.
+#[derive(Clone)]
+pub enum SequenceType {
+    /// empty-sequence()
+    Empty,
+    Items(ItemType, Occurrence),
+}
+
+/// How many items a [SequenceType] allows.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Occurrence {
+    /// Exactly one item (no indicator)
+    One,
+    /// '?'
+    ZeroOrOne,
+    /// '*'
+    ZeroOrMore,
+    /// '+'
+    OneOrMore,
+}
+
+/// The type of an item.
+#[derive(Clone)]
+pub enum ItemType {
+    /// item(), i.e. any item
+    Item,
+    /// A node of the kind given by the test
+    Node(KindTest),
+    /// An atomic value of the given type, or of a type derived from it
+    Atomic(AtomicType),
+}
+
+/// The atomic types that are supported. Each is named by its local name in the [XS_NAMESPACE].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AtomicType {
+    AnyAtomicType,
+    UntypedAtomic,
+    String,
+    NormalizedString,
+    Boolean,
+    /// xs:numeric, the union of xs:double, xs:float and xs:decimal
+    Numeric,
+    Decimal,
+    Integer,
+    NonPositiveInteger,
+    NegativeInteger,
+    Long,
+    Int,
+    Short,
+    Byte,
+    NonNegativeInteger,
+    UnsignedLong,
+    UnsignedInt,
+    UnsignedShort,
+    UnsignedByte,
+    PositiveInteger,
+    Double,
+    Float,
+    Date,
+    DateTime,
+    Time,
+}
+
+const ATOMIC_TYPES: &[(&str, AtomicType)] = &[
+    ("anyAtomicType", AtomicType::AnyAtomicType),
+    ("untypedAtomic", AtomicType::UntypedAtomic),
+    ("string", AtomicType::String),
+    ("normalizedString", AtomicType::NormalizedString),
+    ("boolean", AtomicType::Boolean),
+    ("numeric", AtomicType::Numeric),
+    ("decimal", AtomicType::Decimal),
+    ("integer", AtomicType::Integer),
+    ("nonPositiveInteger", AtomicType::NonPositiveInteger),
+    ("negativeInteger", AtomicType::NegativeInteger),
+    ("long", AtomicType::Long),
+    ("int", AtomicType::Int),
+    ("short", AtomicType::Short),
+    ("byte", AtomicType::Byte),
+    ("nonNegativeInteger", AtomicType::NonNegativeInteger),
+    ("unsignedLong", AtomicType::UnsignedLong),
+    ("unsignedInt", AtomicType::UnsignedInt),
+    ("unsignedShort", AtomicType::UnsignedShort),
+    ("unsignedByte", AtomicType::UnsignedByte),
+    ("positiveInteger", AtomicType::PositiveInteger),
+    ("double", AtomicType::Double),
+    ("float", AtomicType::Float),
+    ("date", AtomicType::Date),
+    ("dateTime", AtomicType::DateTime),
+    ("time", AtomicType::Time),
+];
+
+impl SequenceType {
+    /// Does the sequence match this type? The number of items must be allowed by the occurrence indicator, and each item must match the item type.
+    pub fn matches(&self, s: &Sequence, f: &Forest) -> bool {
+        match self {
+            SequenceType::Empty => s.is_empty(),
+            SequenceType::Items(t, o) => o.allows(s.len()) && s.iter().all(|i| t.matches(i, f)),
+        }
+    }
+    /// Convert a sequence to this type using the function conversion rules (XPath 3.1 section 3.1.5.2), as is done for the value of a parameter that declares its type.
+    ///
+    /// When an atomic type is expected, nodes are atomised and their untyped values are cast to that type, and numeric values are promoted to xs:float or xs:double. The converted sequence must then match this type.
+    pub fn convert(&self, s: Sequence, f: &Forest) -> Result<Sequence, Error> {
+        let result = match self {
+            SequenceType::Items(ItemType::Atomic(t), _) => s
+                .iter()
+                .map(|i| match &**i {
+                    Item::Node(n) => Ok(Rc::new(Item::Value(t.cast_untyped(n.to_string(f))?))),
+                    Item::Value(v) => Ok(t
+                        .promote(v)?
+                        .map_or_else(|| i.clone(), |w| Rc::new(Item::Value(w)))),
+                    Item::Function => Ok(i.clone()),
+                })
+                .collect::<Result<Sequence, Error>>()?,
+            _ => s,
+        };
+        if self.matches(&result, f) {
+            Ok(result)
+        } else {
+            Result::Err(Error::new(
+                ErrorKind::TypeError,
+                format!(
+                    "a sequence of {} item(s) does not match the required type {}",
+                    result.len(),
+                    self
+                ),
+            ))
+        }
+    }
+}
+
+impl TryFrom<&str> for SequenceType {
+    type Error = Error;
+    /// Parse a sequence type, such as the as attribute of an xsl:param.
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        crate::xpath::parse_sequence_type(s)
+    }
+}
+
+impl Occurrence {
+    /// Is a sequence of the given length allowed?
+    pub fn allows(&self, n: usize) -> bool {
+        match self {
+            Occurrence::One => n == 1,
+            Occurrence::ZeroOrOne => n <= 1,
+            Occurrence::ZeroOrMore => true,
+            Occurrence::OneOrMore => n >= 1,
+        }
+    }
+}
+
+impl ItemType {
+    /// Does the item match this type?
+    pub fn matches(&self, i: &Item, f: &Forest) -> bool {
+        match (self, i) {
+            (ItemType::Item, _) => true,
+            (ItemType::Node(k), Item::Node(n)) => k.matches(f, n),
+            (ItemType::Atomic(t), Item::Value(v)) => t.matches(v),
+            _ => false,
+        }
+    }
+}
+
+impl AtomicType {
+    /// Find the type with the given local name.
+    pub fn from_local_name(name: &str) -> Option<AtomicType> {
+        ATOMIC_TYPES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, t)| *t)
+    }
+    /// The local name of the type.
+    pub fn local_name(&self) -> &'static str {
+        ATOMIC_TYPES
+            .iter()
+            .find(|(_, t)| t == self)
+            .map_or("", |(n, _)| n)
+    }
+    // The type that this type is derived from, if it is not a primitive type
+    fn base(&self) -> Option<AtomicType> {
+        match self {
+            AtomicType::NormalizedString => Some(AtomicType::String),
+            AtomicType::Integer => Some(AtomicType::Decimal),
+            AtomicType::NonPositiveInteger | AtomicType::Long | AtomicType::NonNegativeInteger => {
+                Some(AtomicType::Integer)
+            }
+            AtomicType::NegativeInteger => Some(AtomicType::NonPositiveInteger),
+            AtomicType::Int => Some(AtomicType::Long),
+            AtomicType::Short => Some(AtomicType::Int),
+            AtomicType::Byte => Some(AtomicType::Short),
+            AtomicType::UnsignedLong | AtomicType::PositiveInteger => {
+                Some(AtomicType::NonNegativeInteger)
+            }
+            AtomicType::UnsignedInt => Some(AtomicType::UnsignedLong),
+            AtomicType::UnsignedShort => Some(AtomicType::UnsignedInt),
+            AtomicType::UnsignedByte => Some(AtomicType::UnsignedShort),
+            _ => None,
+        }
+    }
+    /// Is this type the same as, or derived from, the other type?
+    pub fn derives_from(&self, other: AtomicType) -> bool {
+        match other {
+            AtomicType::AnyAtomicType => true,
+            AtomicType::Numeric => {
+                matches!(
+                    self,
+                    AtomicType::Double | AtomicType::Float | AtomicType::Numeric
+                ) || self.derives_from(AtomicType::Decimal)
+            }
+            _ => {
+                let mut t = Some(*self);
+                while let Some(u) = t {
+                    if u == other {
+                        return true;
+                    }
+                    t = u.base()
+                }
+                false
+            }
+        }
+    }
+    /// The type of a value, if it is a supported atomic type.
+    pub fn of(v: &Value) -> Option<AtomicType> {
+        Some(match v {
+            Value::String(_) => AtomicType::String,
+            Value::NormalizedString(_) => AtomicType::NormalizedString,
+            Value::Boolean(_) => AtomicType::Boolean,
+            Value::Decimal(_) => AtomicType::Decimal,
+            Value::Integer(_) => AtomicType::Integer,
+            Value::NonPositiveInteger(_) => AtomicType::NonPositiveInteger,
+            Value::NegativeInteger(_) => AtomicType::NegativeInteger,
+            Value::Long(_) => AtomicType::Long,
+            Value::Int(_) => AtomicType::Int,
+            Value::Short(_) => AtomicType::Short,
+            Value::Byte(_) => AtomicType::Byte,
+            Value::NonNegativeInteger(_) => AtomicType::NonNegativeInteger,
+            Value::UnsignedLong(_) => AtomicType::UnsignedLong,
+            Value::UnsignedInt(_) => AtomicType::UnsignedInt,
+            Value::UnsignedShort(_) => AtomicType::UnsignedShort,
+            Value::UnsignedByte(_) => AtomicType::UnsignedByte,
+            Value::PositiveInteger(_) => AtomicType::PositiveInteger,
+            Value::Double(_) => AtomicType::Double,
+            Value::Float(_) => AtomicType::Float,
+            Value::Date(_) => AtomicType::Date,
+            Value::DateTime(_) => AtomicType::DateTime,
+            Value::Time(_) => AtomicType::Time,
+            Value::UntypedAtomic => AtomicType::UntypedAtomic,
+            _ => return None,
+        })
+    }
+    /// Is the value an instance of this type?
+    pub fn matches(&self, v: &Value) -> bool {
+        AtomicType::of(v).is_some_and(|t| t.derives_from(*self))
+    }
+    // Cast the untyped value of a node to this type. xs:anyAtomicType and xs:untypedAtomic leave the value untyped, and xs:numeric becomes xs:double.
+    fn cast_untyped(&self, u: String) -> Result<Value, Error> {
+        match self {
+            AtomicType::AnyAtomicType | AtomicType::UntypedAtomic => Ok(Value::from(u)),
+            AtomicType::Numeric => AtomicType::Double.cast(&Value::from(u)),
+            _ => self.cast(&Value::from(u)),
+        }
+    }
+    // Numeric type promotion: xs:decimal to xs:float or xs:double, and xs:float to xs:double.
+    // Returns None if the value is left unchanged.
+    fn promote(&self, v: &Value) -> Result<Option<Value>, Error> {
+        match (self, AtomicType::of(v)) {
+            (AtomicType::Double, Some(t))
+                if t == AtomicType::Float || t.derives_from(AtomicType::Decimal) =>
+            {
+                Ok(Some(Value::Double(f64::try_from(v)?)))
+            }
+            (AtomicType::Float, Some(t)) if t.derives_from(AtomicType::Decimal) => {
+                Ok(Some(Value::Float(f32::try_from(v)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// Cast a value to this type, following the XPath rules for the cast as expression.
+    ///
+    /// Untyped atomic values are represented as strings. Casting to xs:date and xs:time is not yet supported.
+    pub fn cast(&self, v: &Value) -> Result<Value, Error> {
+        Ok(match self {
+            AtomicType::String | AtomicType::UntypedAtomic => Value::from(v.to_string()),
+            AtomicType::NormalizedString => {
+                Value::NormalizedString(NormalizedString::try_from(v.to_string().as_str())?)
+            }
+            AtomicType::Boolean => Value::from(bool::try_from(v)?),
+            AtomicType::Decimal => Value::from(Decimal::try_from(v)?),
+            AtomicType::Integer => Value::Integer(i64::try_from(v)?),
+            AtomicType::Long => Value::Long(i64::try_from(v)?),
+            AtomicType::Int => Value::Int(i32::try_from(v)?),
+            AtomicType::Short => Value::Short(i16::try_from(v)?),
+            AtomicType::Byte => Value::Byte(i8::try_from(v)?),
+            AtomicType::UnsignedLong => Value::UnsignedLong(u64::try_from(v)?),
+            AtomicType::UnsignedInt => Value::UnsignedInt(u32::try_from(v)?),
+            AtomicType::UnsignedShort => Value::UnsignedShort(u16::try_from(v)?),
+            AtomicType::UnsignedByte => Value::UnsignedByte(u8::try_from(v)?),
+            AtomicType::NonPositiveInteger => {
+                Value::NonPositiveInteger(i64::try_from(v)?.try_into()?)
+            }
+            AtomicType::NegativeInteger => Value::NegativeInteger(i64::try_from(v)?.try_into()?),
+            AtomicType::NonNegativeInteger => {
+                Value::NonNegativeInteger(i64::try_from(v)?.try_into()?)
+            }
+            AtomicType::PositiveInteger => Value::PositiveInteger(i64::try_from(v)?.try_into()?),
+            AtomicType::Double => Value::Double(f64::try_from(v)?),
+            AtomicType::Float => Value::Float(f32::try_from(v)?),
+            AtomicType::DateTime => Value::DateTime(DateTime::<Local>::try_from(v)?),
+            AtomicType::AnyAtomicType | AtomicType::Numeric => {
+                return Result::Err(Error::new(
+                    ErrorKind::StaticData,
+                    format!("cannot cast to abstract type xs:{}", self.local_name()),
+                ))
+            }
+            AtomicType::Date | AtomicType::Time => {
+                return Result::Err(Error::new(
+                    ErrorKind::NotImplemented,
+                    format!("casting to xs:{} is not yet supported", self.local_name()),
+                ))
+            }
+        })
+    }
+}
+
+impl fmt::Display for SequenceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceType::Empty => f.write_str("empty-sequence()"),
+            SequenceType::Items(t, o) => write!(f, "{}{}", t, o),
+        }
+    }
+}
+
+impl fmt::Display for Occurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Occurrence::One => "",
+            Occurrence::ZeroOrOne => "?",
+            Occurrence::ZeroOrMore => "*",
+            Occurrence::OneOrMore => "+",
+        })
+    }
+}
+
+impl fmt::Display for ItemType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemType::Item => f.write_str("item()"),
+            ItemType::Node(k) => f.write_str(match k {
+                KindTest::DocumentTest => "document-node()",
+                KindTest::ElementTest => "element()",
+                KindTest::AttributeTest => "attribute()",
+                KindTest::SchemaElementTest => "schema-element()",
+                KindTest::SchemaAttributeTest => "schema-attribute()",
+                KindTest::PITest(Some(n)) => {
+                    return write!(f, "processing-instruction({})", n);
+                }
+                KindTest::PITest(None) => "processing-instruction()",
+                KindTest::CommentTest => "comment()",
+                KindTest::TextTest => "text()",
+                KindTest::NamespaceNodeTest => "namespace-node()",
+                KindTest::AnyKindTest => "node()",
+            }),
+            ItemType::Atomic(t) => write!(f, "{}", t),
+        }
+    }
+}
+
+impl fmt::Display for AtomicType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "xs:{}", self.local_name())
+    }
+}
@@ -0,0 +1,257 @@
+/*! ## SOAP envelopes
+
+Helpers for building and reading [SOAP](https://www.w3.org/TR/soap12-part1/) messages as [Tree](../forest/struct.Tree.html)s. Both SOAP 1.1 and SOAP 1.2 are supported; the version of a message is determined by the namespace of its Envelope element.
+
+```rust
+use xrust::forest::Forest;
+use xrust::qname::QualifiedName;
+use xrust::soap::{get_body, new_envelope, Version};
+
+let mut f = Forest::new();
+let t = new_envelope(&mut f, Version::Soap12).expect("unable to create envelope");
+let body = get_body(&f, t).expect("no body");
+let request = f.get_ref_mut(t).unwrap()
+    .new_element(QualifiedName::new(None, None, String::from("GetPrice")))
+    .expect("unable to create element");
+body.append_child(&mut f, request).expect("unable to add request");
+assert_eq!(
+    f.get_ref(t).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+    "<env:Envelope xmlns:env='http://www.w3.org/2003/05/soap-envelope'><env:Header></env:Header><env:Body><GetPrice></GetPrice></env:Body></env:Envelope>"
+);
+```
+*/
+
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::qname::QualifiedName;
+use crate::xdmerror::*;
+
+/// The SOAP 1.1 envelope namespace URI.
+pub const SOAP11_NAMESPACE: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+/// The SOAP 1.2 envelope namespace URI.
+pub const SOAP12_NAMESPACE: &str = "http://www.w3.org/2003/05/soap-envelope";
+
+/// The version of SOAP.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Version {
+    Soap11,
+    Soap12,
+}
+
+impl Version {
+    /// The envelope namespace URI for this version.
+    pub fn namespace(&self) -> &'static str {
+        match self {
+            Version::Soap11 => SOAP11_NAMESPACE,
+            Version::Soap12 => SOAP12_NAMESPACE,
+        }
+    }
+    /// The prefix that is used for the envelope namespace in new messages.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Version::Soap11 => "soap",
+            Version::Soap12 => "env",
+        }
+    }
+}
+
+/// The details of a fault.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fault {
+    /// The fault code, e.g. "soap:Server" or "env:Sender". This is the faultcode element in SOAP 1.1, and the Value of the Code element in SOAP 1.2.
+    pub code: String,
+    /// The values of any Subcode elements, outermost first. These only occur in SOAP 1.2.
+    pub subcodes: Vec<String>,
+    /// The explanation of the fault. This is the faultstring element in SOAP 1.1, and the first Text of the Reason element in SOAP 1.2.
+    pub reason: String,
+    /// The URI of the node that caused the fault. This is the faultactor element in SOAP 1.1, and the Role element in SOAP 1.2.
+    pub actor: Option<String>,
+    /// The detail element, if any. Its children describe the fault in an application-specific way.
+    pub detail: Option<Node>,
+}
+
+/// Create a new tree that has an empty message: an Envelope element with an empty Header and Body.
+pub fn new_envelope(f: &mut Forest, v: Version) -> Result<TreeIndex, Error> {
+    let t = f.plant_tree();
+    let d = f.get_ref_mut(t).unwrap();
+    let env = d.new_element(soap_name(v, "Envelope"))?;
+    let header = d.new_element(soap_name(v, "Header"))?;
+    let body = d.new_element(soap_name(v, "Body"))?;
+    d.push_doc_node(env)?;
+    env.append_child(f, header)?;
+    env.append_child(f, body)?;
+    Ok(t)
+}
+
+/// The SOAP version of the message, if the document element is a SOAP Envelope.
+pub fn get_version(f: &Forest, t: TreeIndex) -> Option<Version> {
+    let env = f.get_ref(t)?.get_doc_node().get_first_element(f)?;
+    let name = env.to_name(f);
+    if name.get_localname() != "Envelope" {
+        return None;
+    }
+    match name.get_nsuri_ref() {
+        Some(SOAP11_NAMESPACE) => Some(Version::Soap11),
+        Some(SOAP12_NAMESPACE) => Some(Version::Soap12),
+        _ => None,
+    }
+}
+
+/// The Header element of the message, if it has one.
+pub fn get_header(f: &Forest, t: TreeIndex) -> Option<Node> {
+    envelope_child(f, t, "Header")
+}
+
+/// The Body element of the message.
+pub fn get_body(f: &Forest, t: TreeIndex) -> Option<Node> {
+    envelope_child(f, t, "Body")
+}
+
+/// The fault in the message, if the Body has a Fault element.
+pub fn get_fault(f: &Forest, t: TreeIndex) -> Result<Option<Fault>, Error> {
+    let v = get_version(f, t).ok_or(Error::new(
+        ErrorKind::Unknown,
+        String::from("not a SOAP message"),
+    ))?;
+    let fault = match get_body(f, t).and_then(|b| find_child(f, b, Some(v), "Fault")) {
+        Some(fault) => fault,
+        None => return Ok(None),
+    };
+    let missing = |n: &str| {
+        Error::new(
+            ErrorKind::Unknown,
+            format!("fault does not have a {} element", n),
+        )
+    };
+    match v {
+        // The children of the Fault element are not in a namespace
+        Version::Soap11 => Ok(Some(Fault {
+            code: find_child(f, fault, None, "faultcode")
+                .ok_or(missing("faultcode"))?
+                .to_string(f)
+                .trim()
+                .to_string(),
+            subcodes: vec![],
+            reason: find_child(f, fault, None, "faultstring")
+                .ok_or(missing("faultstring"))?
+                .to_string(f),
+            actor: find_child(f, fault, None, "faultactor")
+                .map(|a| a.to_string(f).trim().to_string()),
+            detail: find_child(f, fault, None, "detail"),
+        })),
+        Version::Soap12 => {
+            let code = find_child(f, fault, Some(v), "Code").ok_or(missing("Code"))?;
+            let mut subcodes = vec![];
+            let mut sc = find_child(f, code, Some(v), "Subcode");
+            while let Some(s) = sc {
+                if let Some(value) = find_child(f, s, Some(v), "Value") {
+                    subcodes.push(value.to_string(f).trim().to_string())
+                }
+                sc = find_child(f, s, Some(v), "Subcode")
+            }
+            Ok(Some(Fault {
+                code: find_child(f, code, Some(v), "Value")
+                    .ok_or(missing("Value"))?
+                    .to_string(f)
+                    .trim()
+                    .to_string(),
+                subcodes,
+                reason: find_child(f, fault, Some(v), "Reason")
+                    .and_then(|r| find_child(f, r, Some(v), "Text"))
+                    .ok_or(missing("Reason"))?
+                    .to_string(f),
+                actor: find_child(f, fault, Some(v), "Role")
+                    .map(|a| a.to_string(f).trim().to_string()),
+                detail: find_child(f, fault, Some(v), "Detail"),
+            }))
+        }
+    }
+}
+
+fn soap_name(v: Version, local: &str) -> QualifiedName {
+    QualifiedName::new(
+        Some(String::from(v.namespace())),
+        Some(String::from(v.prefix())),
+        String::from(local),
+    )
+}
+
+fn envelope_child(f: &Forest, t: TreeIndex, local: &str) -> Option<Node> {
+    let v = get_version(f, t)?;
+    let env = f.get_ref(t)?.get_doc_node().get_first_element(f)?;
+    find_child(f, env, Some(v), local)
+}
+
+// Find the first child element with the given name. If there is no version then the name is not in a namespace.
+fn find_child(f: &Forest, n: Node, v: Option<Version>, local: &str) -> Option<Node> {
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        if c.node_type(f) == NodeType::Element {
+            let name = c.to_name(f);
+            if name.get_localname() == local && name.get_nsuri_ref() == v.map(|w| w.namespace()) {
+                return Some(c);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope() {
+        let mut f = Forest::new();
+        let t = new_envelope(&mut f, Version::Soap11).expect("unable to create envelope");
+        assert_eq!(get_version(&f, t), Some(Version::Soap11));
+        assert!(get_header(&f, t).is_some());
+        assert!(get_body(&f, t).is_some());
+        assert_eq!(get_fault(&f, t).expect("not a SOAP message"), None);
+
+        let u = f.grow_tree("<Envelope/>").expect("unable to parse XML");
+        assert_eq!(get_version(&f, u), None);
+        assert!(get_body(&f, u).is_none());
+        assert!(get_fault(&f, u).is_err())
+    }
+
+    #[test]
+    fn fault11() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree(
+                "<soap:Envelope xmlns:soap='http://schemas.xmlsoap.org/soap/envelope/'><soap:Body><soap:Fault><faultcode>soap:Server</faultcode><faultstring>Out of stock</faultstring><detail><Item>42</Item></detail></soap:Fault></soap:Body></soap:Envelope>",
+            )
+            .expect("unable to parse XML");
+        assert!(get_header(&f, t).is_none());
+        let fault = get_fault(&f, t)
+            .expect("not a SOAP message")
+            .expect("no fault");
+        assert_eq!(fault.code, "soap:Server");
+        assert_eq!(fault.reason, "Out of stock");
+        assert_eq!(fault.actor, None);
+        assert_eq!(fault.detail.unwrap().to_string(&f), "42")
+    }
+
+    #[test]
+    fn fault12() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree(
+                "<env:Envelope xmlns:env='http://www.w3.org/2003/05/soap-envelope'><env:Body><env:Fault><env:Code><env:Value>env:Sender</env:Value><env:Subcode><env:Value>m:BadPrice</env:Value></env:Subcode></env:Code><env:Reason><env:Text xml:lang='en'>Price is negative</env:Text></env:Reason><env:Role>http://example.org/pricing</env:Role></env:Fault></env:Body></env:Envelope>",
+            )
+            .expect("unable to parse XML");
+        let fault = get_fault(&f, t)
+            .expect("not a SOAP message")
+            .expect("no fault");
+        assert_eq!(
+            fault,
+            Fault {
+                code: String::from("env:Sender"),
+                subcodes: vec![String::from("m:BadPrice")],
+                reason: String::from("Price is negative"),
+                actor: Some(String::from("http://example.org/pricing")),
+                detail: None,
+            }
+        )
+    }
+}
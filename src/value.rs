@@ -3,13 +3,15 @@
 //! An atomic value as an item in a sequence.
 
 use crate::xdmerror::{Error, ErrorKind};
-use chrono::{Date, DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate};
 use core::fmt;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
 use rust_decimal::Decimal;
 #[cfg(test)]
 use rust_decimal_macros::dec;
 use std::cmp::Ordering;
 use std::convert::TryFrom;
+use std::sync::Arc;
 
 /// Comparison operators for values
 #[derive(Copy, Clone)]
@@ -83,8 +85,9 @@ pub enum Value {
     PositiveInteger(PositiveInteger),
     DateTime(DateTime<Local>),
     DateTimeStamp,
-    Date(Date<Local>),
-    String(String),
+    Date(NaiveDate),
+    /// The text is shared, so cloning a string value does not copy it
+    String(Arc<str>),
     NormalizedString(NormalizedString),
     /// Like normalizedString, but without leading, trailing and consecutive whitespace
     Token,
@@ -106,34 +109,6 @@ pub enum Value {
 }
 
 impl Value {
-    /// Give the string value.
-    pub fn to_string(&self) -> String {
-        match self {
-            Value::String(s) => s.to_string(),
-            Value::NormalizedString(s) => s.0.to_string(),
-            Value::Decimal(d) => d.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::Double(d) => d.to_string(),
-            Value::Integer(i) => i.to_string(),
-            Value::Long(l) => l.to_string(),
-            Value::Short(s) => s.to_string(),
-            Value::Int(i) => i.to_string(),
-            Value::Byte(b) => b.to_string(),
-            Value::UnsignedLong(l) => l.to_string(),
-            Value::UnsignedShort(s) => s.to_string(),
-            Value::UnsignedInt(i) => i.to_string(),
-            Value::UnsignedByte(b) => b.to_string(),
-            Value::NonPositiveInteger(i) => i.0.to_string(),
-            Value::NonNegativeInteger(i) => i.0.to_string(),
-            Value::PositiveInteger(i) => i.0.to_string(),
-            Value::NegativeInteger(i) => i.0.to_string(),
-            Value::Time(t) => t.format("%H:%M:%S.%f").to_string(),
-            Value::DateTime(dt) => dt.format("%Y-%m-%dT%H:%M:%S%z").to_string(),
-            Value::Date(d) => d.format("%Y-%m-%d").to_string(),
-            _ => "".to_string(),
-        }
-    }
-
     /// Give the effective boolean value.
     pub fn to_bool(&self) -> bool {
         match &self {
@@ -267,13 +242,14 @@ impl Value {
             }
             Value::String(i) => {
                 let c = other.to_string();
+                let i = i.as_ref();
                 match op {
-                    Operator::Equal => Ok(*i == c),
-                    Operator::NotEqual => Ok(*i != c),
-                    Operator::LessThan => Ok(*i < c),
-                    Operator::LessThanEqual => Ok(*i <= c),
-                    Operator::GreaterThan => Ok(*i > c),
-                    Operator::GreaterThanEqual => Ok(*i >= c),
+                    Operator::Equal => Ok(i == c),
+                    Operator::NotEqual => Ok(i != c),
+                    Operator::LessThan => Ok(i < c.as_str()),
+                    Operator::LessThanEqual => Ok(i <= c.as_str()),
+                    Operator::GreaterThan => Ok(i > c.as_str()),
+                    Operator::GreaterThanEqual => Ok(i >= c.as_str()),
                     Operator::Is | Operator::Before | Operator::After => {
                         Result::Err(Error::new(ErrorKind::TypeError, String::from("type error")))
                     }
@@ -290,10 +266,177 @@ impl Value {
     }
 }
 
+/// The string value, following the XPath rules for casting to xs:string.
+/// Booleans are "true" or "false", and doubles and floats use exponent notation outside the range 1e-6 to 1e6.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => f.write_str(s),
+            Value::NormalizedString(s) => f.write_str(&s.0),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Float(n) => f.write_str(&float_to_string(*n)),
+            Value::Double(d) => f.write_str(&float_to_string(*d)),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Long(l) => write!(f, "{}", l),
+            Value::Short(s) => write!(f, "{}", s),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Byte(b) => write!(f, "{}", b),
+            Value::UnsignedLong(l) => write!(f, "{}", l),
+            Value::UnsignedShort(s) => write!(f, "{}", s),
+            Value::UnsignedInt(i) => write!(f, "{}", i),
+            Value::UnsignedByte(b) => write!(f, "{}", b),
+            Value::NonPositiveInteger(i) => write!(f, "{}", i.0),
+            Value::NonNegativeInteger(i) => write!(f, "{}", i.0),
+            Value::PositiveInteger(i) => write!(f, "{}", i.0),
+            Value::NegativeInteger(i) => write!(f, "{}", i.0),
+            Value::Time(t) => write!(f, "{}", t.format("%H:%M:%S.%f")),
+            Value::DateTime(dt) => write!(f, "{}", dt.format("%Y-%m-%dT%H:%M:%S%z")),
+            Value::Date(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            Value::Boolean(b) => write!(f, "{}", b),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Format a double or float as xs:string.
+// The value is formatted using its own type, so that a float does not gain spurious digits.
+fn float_to_string<T: fmt::Display + fmt::UpperExp + Into<f64> + Copy>(v: T) -> String {
+    let d: f64 = v.into();
+    if d.is_nan() {
+        String::from("NaN")
+    } else if d.is_infinite() {
+        String::from(if d > 0.0 { "INF" } else { "-INF" })
+    } else if d == 0.0 {
+        String::from(if d.is_sign_negative() { "-0" } else { "0" })
+    } else if (1e-6..1e6).contains(&d.abs()) {
+        v.to_string()
+    } else {
+        // The mantissa always has a fractional part, e.g. 1.0E20
+        let s = format!("{:E}", v);
+        match s.split_once('E') {
+            Some((m, e)) if !m.contains('.') => format!("{}.0E{}", m, e),
+            _ => s,
+        }
+    }
+}
+
+// The numeric value of a value, for comparison between different numeric types.
+enum Numeric {
+    Integer(i128),
+    Decimal(Decimal),
+    Double(f64),
+}
+
+impl Value {
+    fn numeric(&self) -> Option<Numeric> {
+        match self {
+            Value::Integer(i) | Value::Long(i) => Some(Numeric::Integer(*i as i128)),
+            Value::Int(i) => Some(Numeric::Integer(*i as i128)),
+            Value::Short(i) => Some(Numeric::Integer(*i as i128)),
+            Value::Byte(i) => Some(Numeric::Integer(*i as i128)),
+            Value::UnsignedLong(i) => Some(Numeric::Integer(*i as i128)),
+            Value::UnsignedInt(i) => Some(Numeric::Integer(*i as i128)),
+            Value::UnsignedShort(i) => Some(Numeric::Integer(*i as i128)),
+            Value::UnsignedByte(i) => Some(Numeric::Integer(*i as i128)),
+            Value::NonPositiveInteger(i) => Some(Numeric::Integer(i.0 as i128)),
+            Value::NegativeInteger(i) => Some(Numeric::Integer(i.0 as i128)),
+            Value::NonNegativeInteger(i) => Some(Numeric::Integer(i.0 as i128)),
+            Value::PositiveInteger(i) => Some(Numeric::Integer(i.0 as i128)),
+            Value::Decimal(d) => Some(Numeric::Decimal(*d)),
+            Value::Float(f) => Some(Numeric::Double(*f as f64)),
+            Value::Double(d) => Some(Numeric::Double(*d)),
+            _ => None,
+        }
+    }
+
+    // Cast to xs:integer. Doubles and decimals are truncated towards zero.
+    fn cast_integer(&self) -> Result<i128, Error> {
+        match self.numeric() {
+            Some(Numeric::Integer(i)) => Ok(i),
+            Some(Numeric::Decimal(d)) => d
+                .trunc()
+                .to_i128()
+                .ok_or_else(|| cast_error(self, "integer")),
+            Some(Numeric::Double(d)) => {
+                if d.is_finite() && d.trunc().abs() < i128::MAX as f64 {
+                    Ok(d.trunc() as i128)
+                } else {
+                    Err(cast_error(self, "integer"))
+                }
+            }
+            None => match self {
+                Value::Boolean(b) => Ok(*b as i128),
+                Value::String(_) | Value::NormalizedString(_) => self
+                    .to_string()
+                    .trim()
+                    .parse::<i128>()
+                    .map_err(|_| cast_error(self, "integer")),
+                _ => Err(cast_error(self, "integer")),
+            },
+        }
+    }
+
+    // Cast to xs:double.
+    fn cast_double(&self) -> Result<f64, Error> {
+        match self.numeric() {
+            Some(Numeric::Integer(i)) => Ok(i as f64),
+            Some(Numeric::Decimal(d)) => d.to_f64().ok_or_else(|| cast_error(self, "double")),
+            Some(Numeric::Double(d)) => Ok(d),
+            None => match self {
+                Value::Boolean(b) => Ok(if *b { 1.0 } else { 0.0 }),
+                Value::String(_) | Value::NormalizedString(_) => {
+                    let s = self.to_string();
+                    match s.trim() {
+                        "INF" | "+INF" => Ok(f64::INFINITY),
+                        "-INF" => Ok(f64::NEG_INFINITY),
+                        "NaN" => Ok(f64::NAN),
+                        // Rust also accepts "inf", "infinity" and "nan", which are not XPath lexical forms
+                        t if t.contains(|c: char| c.is_alphabetic() && c != 'e' && c != 'E') => {
+                            Err(cast_error(self, "double"))
+                        }
+                        t => t.parse::<f64>().map_err(|_| cast_error(self, "double")),
+                    }
+                }
+                _ => Err(cast_error(self, "double")),
+            },
+        }
+    }
+}
+
+fn cast_error(v: &Value, t: &str) -> Error {
+    Error::new(
+        ErrorKind::TypeError,
+        format!("unable to cast {} value \"{}\" to {}", v.value_type(), v, t),
+    )
+}
+
+fn numeric_cmp(a: &Numeric, b: &Numeric) -> Option<Ordering> {
+    match (a, b) {
+        (Numeric::Integer(i), Numeric::Integer(j)) => i.partial_cmp(j),
+        (Numeric::Decimal(d), Numeric::Decimal(e)) => d.partial_cmp(e),
+        (Numeric::Integer(i), Numeric::Decimal(d)) => Decimal::from_i128(*i)?.partial_cmp(d),
+        (Numeric::Decimal(d), Numeric::Integer(i)) => d.partial_cmp(&Decimal::from_i128(*i)?),
+        // Any comparison involving a double is done as doubles
+        _ => numeric_double(a).partial_cmp(&numeric_double(b)),
+    }
+}
+
+fn numeric_double(n: &Numeric) -> f64 {
+    match n {
+        Numeric::Integer(i) => *i as f64,
+        Numeric::Decimal(d) => d.to_f64().unwrap_or(f64::NAN),
+        Numeric::Double(d) => *d,
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Value) -> bool {
+        // Numeric values of different types are promoted to a common type
+        if let (Some(a), Some(b)) = (self.numeric(), other.numeric()) {
+            return numeric_cmp(&a, &b) == Some(Ordering::Equal);
+        }
         match self {
-            Value::String(s) => s.eq(&other.to_string()),
+            Value::String(s) => s.as_ref() == other.to_string(),
             Value::Boolean(b) => match other {
                 Value::Boolean(c) => b == c,
                 _ => false, // type error?
@@ -316,10 +459,13 @@ impl PartialEq for Value {
 }
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        if let (Some(a), Some(b)) = (self.numeric(), other.numeric()) {
+            return numeric_cmp(&a, &b);
+        }
         match self {
             Value::String(s) => {
                 let o: String = other.to_string();
-                s.partial_cmp(&o)
+                s.as_ref().partial_cmp(o.as_str())
             }
             Value::Boolean(_) => None,
             Value::Decimal(d) => match other {
@@ -341,12 +487,17 @@ impl PartialOrd for Value {
 
 impl From<String> for Value {
     fn from(s: String) -> Self {
-        Value::String(s)
+        Value::String(Arc::from(s))
     }
 }
 impl From<&str> for Value {
     fn from(s: &str) -> Self {
-        Value::String(String::from(s))
+        Value::String(Arc::from(s))
+    }
+}
+impl From<Arc<str>> for Value {
+    fn from(s: Arc<str>) -> Self {
+        Value::String(s)
     }
 }
 impl From<Decimal> for Value {
@@ -410,6 +561,156 @@ impl From<bool> for Value {
     }
 }
 
+impl From<isize> for Value {
+    fn from(i: isize) -> Self {
+        Value::Integer(i as i64)
+    }
+}
+impl From<usize> for Value {
+    fn from(i: usize) -> Self {
+        Value::UnsignedLong(i as u64)
+    }
+}
+/// An integer, or a decimal if the value does not fit in an xs:long.
+impl TryFrom<i128> for Value {
+    type Error = Error;
+    fn try_from(i: i128) -> Result<Self, Self::Error> {
+        match i64::try_from(i) {
+            Ok(j) => Ok(Value::Integer(j)),
+            Err(_) => Decimal::from_i128(i).map(Value::Decimal).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::TypeError,
+                    format!("{} is too large for a decimal", i),
+                )
+            }),
+        }
+    }
+}
+/// An unsigned long, or a decimal if the value does not fit in an xs:unsignedLong.
+impl TryFrom<u128> for Value {
+    type Error = Error;
+    fn try_from(i: u128) -> Result<Self, Self::Error> {
+        match u64::try_from(i) {
+            Ok(j) => Ok(Value::UnsignedLong(j)),
+            Err(_) => Decimal::from_u128(i).map(Value::Decimal).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::TypeError,
+                    format!("{} is too large for a decimal", i),
+                )
+            }),
+        }
+    }
+}
+impl From<DateTime<Local>> for Value {
+    fn from(dt: DateTime<Local>) -> Self {
+        Value::DateTime(dt)
+    }
+}
+
+// Conversions from a value cast the value to the target type, as for the XPath cast expression.
+// Doubles are truncated towards zero when cast to an integer type.
+macro_rules! try_from_value_integer {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<&Value> for $t {
+                type Error = Error;
+                fn try_from(v: &Value) -> Result<Self, Self::Error> {
+                    let i = v.cast_integer()?;
+                    <$t>::try_from(i).map_err(|_| {
+                        Error::new(
+                            ErrorKind::TypeError,
+                            format!("{} is out of range for {}", i, stringify!($t)),
+                        )
+                    })
+                }
+            }
+        )*
+    };
+}
+try_from_value_integer!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl TryFrom<&Value> for f64 {
+    type Error = Error;
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        v.cast_double()
+    }
+}
+impl TryFrom<&Value> for f32 {
+    type Error = Error;
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v {
+            Value::Float(f) => Ok(*f),
+            _ => v.cast_double().map(|d| d as f32),
+        }
+    }
+}
+impl TryFrom<&Value> for Decimal {
+    type Error = Error;
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v.numeric() {
+            Some(Numeric::Integer(i)) => Decimal::from_i128(i),
+            Some(Numeric::Decimal(d)) => Some(d),
+            Some(Numeric::Double(d)) => Decimal::from_f64(d),
+            None => match v {
+                Value::Boolean(b) => Some(Decimal::from(*b as i64)),
+                Value::String(_) | Value::NormalizedString(_) => {
+                    v.to_string().trim().parse::<Decimal>().ok()
+                }
+                _ => None,
+            },
+        }
+        .ok_or_else(|| cast_error(v, "decimal"))
+    }
+}
+/// Numbers are false if they are zero or NaN. Strings must be one of "true", "false", "1" or "0".
+impl TryFrom<&Value> for bool {
+    type Error = Error;
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v.numeric() {
+            Some(Numeric::Integer(i)) => Ok(i != 0),
+            Some(Numeric::Decimal(d)) => Ok(!d.is_zero()),
+            Some(Numeric::Double(d)) => Ok(d != 0.0 && !d.is_nan()),
+            None => match v {
+                Value::Boolean(b) => Ok(*b),
+                Value::String(_) | Value::NormalizedString(_) => match v.to_string().trim() {
+                    "true" | "1" => Ok(true),
+                    "false" | "0" => Ok(false),
+                    _ => Err(cast_error(v, "boolean")),
+                },
+                _ => Err(cast_error(v, "boolean")),
+            },
+        }
+    }
+}
+impl TryFrom<&Value> for NaiveDate {
+    type Error = Error;
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v {
+            Value::Date(d) => Ok(*d),
+            Value::DateTime(dt) => Ok(dt.naive_local().date()),
+            Value::String(_) | Value::NormalizedString(_) => {
+                NaiveDate::parse_from_str(v.to_string().trim(), "%Y-%m-%d")
+                    .map_err(|_| cast_error(v, "date"))
+            }
+            _ => Err(cast_error(v, "date")),
+        }
+    }
+}
+impl TryFrom<&Value> for DateTime<Local> {
+    type Error = Error;
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v {
+            Value::DateTime(dt) => Ok(*dt),
+            Value::String(_) | Value::NormalizedString(_) => {
+                DateTime::parse_from_rfc3339(v.to_string().trim())
+                    .map(|dt| dt.with_timezone(&Local))
+                    .map_err(|_| cast_error(v, "dateTime"))
+            }
+            _ => Err(cast_error(v, "dateTime")),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct NonPositiveInteger(i64);
 impl TryFrom<i64> for NonPositiveInteger {
@@ -533,6 +834,59 @@ mod tests {
         assert_eq!(Value::from(dec!(001.23)).to_string(), "1.23");
     }
 
+    #[test]
+    fn display_xpath() {
+        assert_eq!(Value::from(true).to_string(), "true");
+        assert_eq!(Value::from(2.0).to_string(), "2");
+        assert_eq!(Value::from(0.5_f32).to_string(), "0.5");
+        assert_eq!(Value::from(1e20).to_string(), "1.0E20");
+        assert_eq!(Value::from(1.5e-7).to_string(), "1.5E-7");
+        assert_eq!(Value::from(f64::NAN).to_string(), "NaN");
+        assert_eq!(Value::from(f64::NEG_INFINITY).to_string(), "-INF");
+    }
+    #[test]
+    fn from_wide_integers() {
+        assert_eq!(Value::from(3_usize).to_string(), "3");
+        assert_eq!(
+            Value::try_from(i128::from(i64::MAX) + 1)
+                .expect("unable to convert")
+                .value_type(),
+            "Decimal"
+        );
+        assert!(Value::try_from(i128::MAX).is_err());
+    }
+    #[test]
+    fn try_into_primitives() {
+        assert_eq!(i32::try_from(&Value::from(" 42 ")).unwrap(), 42);
+        assert_eq!(i64::try_from(&Value::from(-3.7)).unwrap(), -3);
+        assert!(u8::try_from(&Value::from(300)).is_err());
+        assert!(i64::try_from(&Value::from(f64::INFINITY)).is_err());
+        assert!(i64::try_from(&Value::from("1.5")).is_err());
+        assert_eq!(
+            f64::try_from(&Value::from("-INF")).unwrap(),
+            f64::NEG_INFINITY
+        );
+        assert!(f64::try_from(&Value::from("inf")).is_err());
+        assert_eq!(f64::try_from(&Value::from("1e3")).unwrap(), 1000.0);
+        assert_eq!(Decimal::try_from(&Value::from(7_u16)).unwrap(), dec!(7));
+        assert!(!bool::try_from(&Value::from(f64::NAN)).unwrap());
+        assert!(bool::try_from(&Value::from("1")).unwrap());
+        assert!(bool::try_from(&Value::from("yes")).is_err());
+        assert_eq!(
+            NaiveDate::try_from(&Value::from("2022-03-01")).unwrap(),
+            NaiveDate::from_ymd_opt(2022, 3, 1).unwrap()
+        );
+    }
+    #[test]
+    fn numeric_promotion() {
+        assert!(Value::from(1_i64) == Value::from(1_u8));
+        assert!(Value::from(dec!(1.0)) == Value::from(1_i32));
+        assert!(Value::from(1.5) == Value::from(dec!(1.5)));
+        assert!(Value::from(2_i16) < Value::from(2.5_f32));
+        assert!(Value::from(dec!(10)) > Value::from(9_u64));
+        assert!(Value::from(f64::NAN) != Value::from(f64::NAN));
+    }
+
     #[test]
     fn normalizedstring_valid_empty() {
         assert_eq!(
@@ -716,7 +1070,16 @@ mod tests {
     // String Values
     #[test]
     fn string_stringvalue() {
-        assert_eq!(Value::String("foobar".to_string()).to_string(), "foobar")
+        assert_eq!(Value::from("foobar").to_string(), "foobar")
+    }
+    #[test]
+    fn string_shared() {
+        let v = Value::from("foobar");
+        let w = v.clone();
+        match (v, w) {
+            (Value::String(s), Value::String(t)) => assert!(Arc::ptr_eq(&s, &t)),
+            _ => panic!("not a string value"),
+        }
     }
     #[test]
     fn decimal_stringvalue() {
@@ -0,0 +1,267 @@
+/*! ## Visiting and rewriting trees
+
+Programmatic transformations of a [Tree](../forest/struct.Tree.html) can be written without using the XSLT engine.
+
+A [Rewriter] is called for every node in a tree, in document order. The pre callback is called before a node's children are visited, and the post callback is called after. Each callback returns an [Action] that determines whether the node is kept, removed, or replaced by other nodes.
+
+[visit_mut] rewrites a tree in place. [identity] makes a copy of a tree and then rewrites the copy, leaving the original untouched. Using a Rewriter that keeps every node, such as [Identity], is the identity transformation.
+
+```rust
+use xrust::forest::{Forest, Node, NodeType};
+use xrust::visit::{visit_mut, Action, Rewriter};
+use xrust::xdmerror::Error;
+
+// Remove all comments
+struct NoComments;
+impl Rewriter for NoComments {
+    fn pre(&mut self, f: &mut Forest, n: Node) -> Result<Action, Error> {
+        if n.node_type(f) == NodeType::Comment {
+            Ok(Action::Remove)
+        } else {
+            Ok(Action::Continue)
+        }
+    }
+}
+
+let mut f = Forest::new();
+let src = f.grow_tree("<Example><!-- remove me --><Data/></Example>")
+    .expect("unable to parse XML");
+visit_mut(&mut f, src, NoComments).expect("unable to rewrite tree");
+assert_eq!(
+    f.get_ref(src).unwrap().get_doc_node().get_first_element(&f).unwrap().to_xml(&f),
+    "<Example><Data></Data></Example>"
+);
+```
+
+Attributes are not visited separately. They may be examined and modified by the callbacks for their element.
+*/
+
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::xdmerror::*;
+
+/// What to do with a node that has been visited.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Keep the node and, when returned by the pre callback, visit its children.
+    Continue,
+    /// Keep the node, but do not visit its children. When returned by the post callback, this is the same as Continue.
+    SkipChildren,
+    /// Detach the node from the tree. Its children are not visited.
+    Remove,
+    /// Replace the node with the given nodes. The replacement nodes are not visited. Nodes from a different tree are deep copied.
+    Replace(Vec<Node>),
+}
+
+/// Callbacks for visiting the nodes of a tree.
+///
+/// The default implementation of each callback keeps the node.
+pub trait Rewriter {
+    /// Called before the children of a node are visited.
+    fn pre(&mut self, _f: &mut Forest, _n: Node) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+    /// Called after the children of a node have been visited.
+    fn post(&mut self, _f: &mut Forest, _n: Node) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl<R: Rewriter + ?Sized> Rewriter for &mut R {
+    fn pre(&mut self, f: &mut Forest, n: Node) -> Result<Action, Error> {
+        (**self).pre(f, n)
+    }
+    fn post(&mut self, f: &mut Forest, n: Node) -> Result<Action, Error> {
+        (**self).post(f, n)
+    }
+}
+
+/// A [Rewriter] that keeps every node.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+impl Rewriter for Identity {}
+
+/// Visit every node in the tree, starting with the Document-type node, and apply the [Action]s returned by the [Rewriter].
+///
+/// The Document-type node cannot be removed or replaced.
+pub fn visit_mut(f: &mut Forest, t: TreeIndex, mut r: impl Rewriter) -> Result<(), Error> {
+    let d = f
+        .get_ref(t)
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?
+        .get_doc_node();
+    visit_node(f, d, &mut r)
+}
+
+/// Copy the tree to a new tree, and then rewrite the copy using [visit_mut]. Returns the index of the new tree.
+pub fn identity(f: &mut Forest, t: TreeIndex, r: impl Rewriter) -> Result<TreeIndex, Error> {
+    let d = f
+        .get_ref(t)
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?
+        .get_doc_node();
+    let result = f.plant_tree();
+    let mut children = vec![];
+    let mut cit = d.child_iter();
+    while let Some(c) = cit.next(f) {
+        children.push(c)
+    }
+    for c in children {
+        let cp = c.deep_copy(f, Some(result))?;
+        f.get_ref_mut(result).unwrap().push_doc_node(cp)?
+    }
+    visit_mut(f, result, r)?;
+    Ok(result)
+}
+
+fn visit_node<R: Rewriter>(f: &mut Forest, n: Node, r: &mut R) -> Result<(), Error> {
+    let descend = match r.pre(f, n)? {
+        Action::Continue => true,
+        Action::SkipChildren => false,
+        a => return apply(f, n, a),
+    };
+    if descend {
+        // The callbacks may change the child list, so take a copy of it first
+        let mut children = vec![];
+        let mut cit = n.child_iter();
+        while let Some(c) = cit.next(f) {
+            children.push(c)
+        }
+        for c in children {
+            visit_node(f, c, r)?
+        }
+    }
+    let a = r.post(f, n)?;
+    apply(f, n, a)
+}
+
+fn apply(f: &mut Forest, n: Node, a: Action) -> Result<(), Error> {
+    match a {
+        Action::Continue | Action::SkipChildren => Ok(()),
+        _ if n.node_type(f) == NodeType::Document => Result::Err(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to remove or replace the document node"),
+        )),
+        Action::Remove => n.remove(f),
+        Action::Replace(v) => n.replace_with(f, v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qname::QualifiedName;
+    use crate::value::Value;
+
+    struct Test {
+        visited: Vec<String>,
+    }
+    impl Rewriter for Test {
+        fn pre(&mut self, f: &mut Forest, n: Node) -> Result<Action, Error> {
+            if n.node_type(f) != NodeType::Element {
+                return Ok(Action::Continue);
+            }
+            let name = n.to_name(f).get_localname();
+            self.visited.push(name.clone());
+            match name.as_str() {
+                "a" => Ok(Action::Remove),
+                "b" => Ok(Action::SkipChildren),
+                _ => Ok(Action::Continue),
+            }
+        }
+        fn post(&mut self, f: &mut Forest, n: Node) -> Result<Action, Error> {
+            if n.node_type(f) == NodeType::Element && n.to_name(f).get_localname() == "c" {
+                let d = f.get_ref_mut(n.tree_index()).unwrap();
+                let e = d.new_element(QualifiedName::new(None, None, String::from("d")))?;
+                let t = d.new_text(Value::from("new"))?;
+                Ok(Action::Replace(vec![e, t]))
+            } else {
+                Ok(Action::Continue)
+            }
+        }
+    }
+
+    #[test]
+    fn rewrite() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<Test><a><e/></a><b><e/></b><c/></Test>")
+            .expect("unable to parse XML");
+        let mut r = Test { visited: vec![] };
+        visit_mut(&mut f, t, &mut r).expect("unable to rewrite tree");
+        assert_eq!(r.visited, vec!["Test", "a", "b", "c"]);
+        let e = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(e.to_xml(&f), "<Test><b><e></e></b><d></d>new</Test>")
+    }
+
+    #[test]
+    fn replace_document_element() {
+        struct Swap;
+        impl Rewriter for Swap {
+            fn pre(&mut self, f: &mut Forest, n: Node) -> Result<Action, Error> {
+                if n.node_type(f) == NodeType::Element {
+                    let e = f
+                        .get_ref_mut(n.tree_index())
+                        .unwrap()
+                        .new_element(QualifiedName::new(None, None, String::from("New")))?;
+                    Ok(Action::Replace(vec![e]))
+                } else {
+                    Ok(Action::Continue)
+                }
+            }
+        }
+        let mut f = Forest::new();
+        let t = f.grow_tree("<Old/>").expect("unable to parse XML");
+        visit_mut(&mut f, t, Swap).expect("unable to rewrite tree");
+        let e = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert_eq!(e.to_xml(&f), "<New></New>")
+    }
+
+    #[test]
+    fn document_node() {
+        struct RemoveAll;
+        impl Rewriter for RemoveAll {
+            fn pre(&mut self, _f: &mut Forest, _n: Node) -> Result<Action, Error> {
+                Ok(Action::Remove)
+            }
+        }
+        let mut f = Forest::new();
+        let t = f.grow_tree("<Test/>").expect("unable to parse XML");
+        assert!(visit_mut(&mut f, t, RemoveAll).is_err())
+    }
+
+    #[test]
+    fn identity_copy() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<Test><a/><c/></Test>")
+            .expect("unable to parse XML");
+        let u = identity(&mut f, t, Identity).expect("unable to copy tree");
+        let v = identity(&mut f, t, Test { visited: vec![] }).expect("unable to copy tree");
+        let first = |f: &Forest, i| {
+            f.get_ref(i)
+                .unwrap()
+                .get_doc_node()
+                .get_first_element(f)
+                .unwrap()
+                .to_xml(f)
+        };
+        assert_eq!(first(&f, t), "<Test><a></a><c></c></Test>");
+        assert_eq!(first(&f, u), "<Test><a></a><c></c></Test>");
+        assert_eq!(first(&f, v), "<Test><d></d>new</Test>")
+    }
+}
@@ -30,6 +30,10 @@ pub enum ErrorKind {
     ContextNotNode,
     /// XPTY0020
     NotImplemented,
+    /// Templates are nested more deeply than the evaluator allows
+    RecursionLimit,
+    /// A sequence is larger than the evaluator allows
+    SizeLimit,
     Unknown,
 }
 
@@ -49,6 +53,8 @@ impl ErrorKind {
             ErrorKind::NotNodes => "path expression is not a sequence of nodes",
             ErrorKind::ContextNotNode => "context item is not a node for an axis step",
             ErrorKind::NotImplemented => "not implemented",
+            ErrorKind::RecursionLimit => "recursion limit exceeded",
+            ErrorKind::SizeLimit => "size limit exceeded",
             ErrorKind::Unknown => "unknown",
         }
     }
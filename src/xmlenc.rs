@@ -0,0 +1,278 @@
+/*! ## Scaffolding for XML Encryption
+
+[XML Encryption](https://www.w3.org/TR/xmlenc-core1/) replaces an element, or the content of an element, with an EncryptedData element that holds the encrypted form of the serialised XML.
+
+This module performs the tree surgery, but not the cryptography. The encryption and decryption are done by a [Cipher] supplied by the application. [encrypt] serialises the node, passes it to the Cipher and replaces the node with an EncryptedData element. [decrypt] passes the CipherValue of an EncryptedData element to the Cipher, parses the result and replaces the EncryptedData element with the decrypted nodes.
+
+```rust
+use xrust::forest::Forest;
+use xrust::xdmerror::Error;
+use xrust::xmlenc::{decrypt, encrypt, Cipher, EncryptionType};
+
+// Not a real cipher!
+struct Reverse;
+impl Cipher for Reverse {
+    fn encrypt(&mut self, plaintext: &str) -> Result<String, Error> {
+        Ok(plaintext.chars().rev().collect())
+    }
+    fn decrypt(&mut self, cipher_value: &str) -> Result<String, Error> {
+        Ok(cipher_value.chars().rev().collect())
+    }
+}
+
+let mut f = Forest::new();
+let src = f.grow_tree("<Payment><Card>1234</Card></Payment>")
+    .expect("unable to parse XML");
+let payment = f.get_ref(src).unwrap().get_doc_node().get_first_element(&f).unwrap();
+let card = payment.child_iter().next(&f).unwrap();
+
+let ed = encrypt(&mut f, card, EncryptionType::Element, &mut Reverse)
+    .expect("unable to encrypt");
+assert_eq!(
+    payment.to_xml(&f),
+    "<Payment><xenc:EncryptedData xmlns:xenc='http://www.w3.org/2001/04/xmlenc#' Type='http://www.w3.org/2001/04/xmlenc#Element'><xenc:CipherData><xenc:CipherValue>&gt;draC/&lt;4321&gt;draC&lt;</xenc:CipherValue></xenc:CipherData></xenc:EncryptedData></Payment>"
+);
+
+decrypt(&mut f, ed, &mut Reverse).expect("unable to decrypt");
+assert_eq!(payment.to_xml(&f), "<Payment><Card>1234</Card></Payment>");
+```
+*/
+
+use crate::forest::{Forest, Node, NodeType};
+use crate::qname::QualifiedName;
+use crate::value::Value;
+use crate::xdmerror::*;
+
+/// The XML Encryption namespace URI.
+pub const XMLENC_NAMESPACE: &str = "http://www.w3.org/2001/04/xmlenc#";
+/// The Type of an EncryptedData element that replaces an element.
+pub const TYPE_ELEMENT: &str = "http://www.w3.org/2001/04/xmlenc#Element";
+/// The Type of an EncryptedData element that replaces the content of an element.
+pub const TYPE_CONTENT: &str = "http://www.w3.org/2001/04/xmlenc#Content";
+
+/// Performs the encryption and decryption. The cipher value is the text content of the CipherValue element, which is usually base64-encoded.
+pub trait Cipher {
+    /// Encrypt the serialised XML, returning the cipher value.
+    fn encrypt(&mut self, plaintext: &str) -> Result<String, Error>;
+    /// Decrypt the cipher value, returning the serialised XML.
+    fn decrypt(&mut self, cipher_value: &str) -> Result<String, Error>;
+    /// The URI of the encryption algorithm. If there is one then an EncryptionMethod element is added to the EncryptedData element.
+    fn algorithm(&self) -> Option<String> {
+        None
+    }
+}
+
+/// What is replaced by the EncryptedData element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncryptionType {
+    /// The element itself.
+    Element,
+    /// The content of the element. The EncryptedData element becomes the only child of the element.
+    Content,
+}
+
+/// Encrypt an element, or its content, and replace it with an EncryptedData element. Returns the EncryptedData element.
+pub fn encrypt(
+    f: &mut Forest,
+    n: Node,
+    et: EncryptionType,
+    c: &mut dyn Cipher,
+) -> Result<Node, Error> {
+    if n.node_type(f) != NodeType::Element {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("only elements can be encrypted"),
+        ));
+    }
+    let children = children(f, n);
+    let plaintext = match et {
+        EncryptionType::Element => n.to_xml(f),
+        EncryptionType::Content => children.iter().map(|c| c.to_xml(f)).collect(),
+    };
+    let cipher_value = c.encrypt(plaintext.as_str())?;
+
+    let d = f.get_ref_mut(n.tree_index()).unwrap();
+    let ed = d.new_element(xenc("EncryptedData"))?;
+    let ty = d.new_attribute(
+        QualifiedName::new(None, None, String::from("Type")),
+        Value::from(match et {
+            EncryptionType::Element => TYPE_ELEMENT,
+            EncryptionType::Content => TYPE_CONTENT,
+        }),
+    )?;
+    let method = match c.algorithm() {
+        Some(a) => {
+            let m = d.new_element(xenc("EncryptionMethod"))?;
+            let alg = d.new_attribute(
+                QualifiedName::new(None, None, String::from("Algorithm")),
+                Value::from(a),
+            )?;
+            Some((m, alg))
+        }
+        None => None,
+    };
+    let cd = d.new_element(xenc("CipherData"))?;
+    let cv = d.new_element(xenc("CipherValue"))?;
+    let t = d.new_text(Value::from(cipher_value))?;
+    ed.add_attribute(f, ty)?;
+    if let Some((m, alg)) = method {
+        m.add_attribute(f, alg)?;
+        ed.append_child(f, m)?
+    }
+    cv.append_child(f, t)?;
+    cd.append_child(f, cv)?;
+    ed.append_child(f, cd)?;
+
+    match et {
+        EncryptionType::Element => {
+            n.replace_with(f, vec![ed])?;
+        }
+        EncryptionType::Content => {
+            for c in children {
+                c.remove(f)?
+            }
+            n.append_child(f, ed)?
+        }
+    }
+    Ok(ed)
+}
+
+/// Decrypt an EncryptedData element and replace it with the decrypted nodes, which are returned.
+pub fn decrypt(f: &mut Forest, ed: Node, c: &mut dyn Cipher) -> Result<Vec<Node>, Error> {
+    if !is_xenc(f, ed, "EncryptedData") {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("not an EncryptedData element"),
+        ));
+    }
+    let cipher_value = children(f, ed)
+        .into_iter()
+        .find(|cd| is_xenc(f, *cd, "CipherData"))
+        .and_then(|cd| {
+            children(f, cd)
+                .into_iter()
+                .find(|cv| is_xenc(f, *cv, "CipherValue"))
+        })
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("EncryptedData element does not have a CipherValue"),
+        ))?
+        .to_string(f);
+    let plaintext = c.decrypt(cipher_value.trim())?;
+
+    // The plaintext is parsed into a temporary tree, which is removed once its nodes have been copied
+    let trees = f.tree_count();
+    let result = f.grow_fragment(plaintext.as_str()).and_then(|frag| {
+        children(f, f.get_ref(frag).unwrap().get_doc_node())
+            .into_iter()
+            .map(|m| m.deep_copy(f, Some(ed.tree_index())))
+            .collect::<Result<Vec<_>, Error>>()
+    });
+    f.truncate(trees);
+    let result = result?;
+    ed.replace_with(f, result.clone())?;
+    Ok(result)
+}
+
+fn xenc(local: &str) -> QualifiedName {
+    QualifiedName::new(
+        Some(String::from(XMLENC_NAMESPACE)),
+        Some(String::from("xenc")),
+        String::from(local),
+    )
+}
+
+fn is_xenc(f: &Forest, n: Node, local: &str) -> bool {
+    n.node_type(f) == NodeType::Element
+        && n.to_name(f).get_nsuri_ref() == Some(XMLENC_NAMESPACE)
+        && n.to_name(f).get_localname() == local
+}
+
+fn children(f: &Forest, n: Node) -> Vec<Node> {
+    let mut result = vec![];
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        result.push(c)
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Rot13;
+    impl Cipher for Rot13 {
+        fn encrypt(&mut self, plaintext: &str) -> Result<String, Error> {
+            Ok(plaintext
+                .chars()
+                .map(|c| match c {
+                    'a'..='m' | 'A'..='M' => (c as u8 + 13) as char,
+                    'n'..='z' | 'N'..='Z' => (c as u8 - 13) as char,
+                    _ => c,
+                })
+                .collect())
+        }
+        fn decrypt(&mut self, cipher_value: &str) -> Result<String, Error> {
+            self.encrypt(cipher_value)
+        }
+        fn algorithm(&self) -> Option<String> {
+            Some(String::from("urn:example:rot13"))
+        }
+    }
+
+    #[test]
+    fn content() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a><b>secret</b> text<c/></a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let ed =
+            encrypt(&mut f, a, EncryptionType::Content, &mut Rot13).expect("unable to encrypt");
+        assert_eq!(
+            a.to_xml(&f),
+            "<a><xenc:EncryptedData xmlns:xenc='http://www.w3.org/2001/04/xmlenc#' Type='http://www.w3.org/2001/04/xmlenc#Content'><xenc:EncryptionMethod Algorithm='urn:example:rot13'></xenc:EncryptionMethod><xenc:CipherData><xenc:CipherValue>&lt;o&gt;frperg&lt;/o&gt; grkg&lt;p&gt;&lt;/p&gt;</xenc:CipherValue></xenc:CipherData></xenc:EncryptedData></a>"
+        );
+        let nodes = decrypt(&mut f, ed, &mut Rot13).expect("unable to decrypt");
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(a.to_xml(&f), "<a><b>secret</b> text<c></c></a>")
+    }
+
+    #[test]
+    fn document_element() {
+        let mut f = Forest::new();
+        let t = f.grow_tree("<a>secret</a>").expect("unable to parse XML");
+        let doc = f.get_ref(t).unwrap().get_doc_node();
+        let a = doc.get_first_element(&f).unwrap();
+        let ed =
+            encrypt(&mut f, a, EncryptionType::Element, &mut Rot13).expect("unable to encrypt");
+        assert_eq!(doc.get_first_element(&f), Some(ed));
+        // Decrypting doesn't leave any trees behind
+        let trees = f.tree_count();
+        let nodes = decrypt(&mut f, ed, &mut Rot13).expect("unable to decrypt");
+        assert_eq!(f.tree_count(), trees);
+        assert_eq!(doc.get_first_element(&f), Some(nodes[0]));
+        assert_eq!(doc.to_xml(&f), "<a>secret</a>")
+    }
+
+    #[test]
+    fn not_encrypted() {
+        let mut f = Forest::new();
+        let t = f.grow_tree("<a>text</a>").expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert!(decrypt(&mut f, a, &mut Rot13).is_err());
+        let text = a.child_iter().next(&f).unwrap();
+        assert!(encrypt(&mut f, text, EncryptionType::Element, &mut Rot13).is_err())
+    }
+}
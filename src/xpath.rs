@@ -3,24 +3,26 @@
 //! An XPath parser as a nom parser combinator.
 
 extern crate nom;
+#[cfg(test)]
+use crate::evaluate::Evaluator;
 use crate::evaluate::{
-    ArithmeticOperand, ArithmeticOperator, Axis, Constructor, Function, KindTest, NameTest,
-    NodeMatch, NodeTest, WildcardOrName,
+    indexed_attribute, indexed_name, ArithmeticOperand, ArithmeticOperator, Axis, Constructor,
+    Function, KindTest, NameTest, NodeMatch, NodeTest, Quantifier, SetOperator, StaticContext,
+    WildcardOrName,
 };
-#[cfg(test)]
-use crate::evaluate::{Evaluator, StaticContext};
 use crate::parsecommon::*;
+use crate::sequencetype::{AtomicType, ItemType, Occurrence, SequenceType, XS_NAMESPACE};
 use crate::value::*;
 use crate::xdmerror::*;
 use nom::{
     branch::alt,
-    bytes::complete::tag,
+    bytes::complete::{tag, take_until},
     character::complete::*,
     character::complete::{char, none_of},
-    combinator::{complete, map, opt, recognize},
+    combinator::{complete, map, not, opt, recognize},
     error::{Error as NomError, ErrorKind as NomErrorKind},
     multi::{many0, separated_list0, separated_list1},
-    sequence::{delimited, pair, tuple},
+    sequence::{delimited, pair, preceded, tuple},
     Err as NomErr, IResult,
 };
 use rust_decimal::Decimal;
@@ -45,10 +47,7 @@ fn expr(input: &str) -> IResult<&str, Vec<Constructor>> {
 
 // ExprSingle ::= ForExpr | LetExpr | QuantifiedExpr | IfExpr | OrExpr
 fn expr_single(input: &str) -> IResult<&str, Vec<Constructor>> {
-    alt((
-        or_expr, let_expr, for_expr, if_expr,
-        // TODO: other branches
-    ))(input)
+    alt((quantified_expr, or_expr, let_expr, for_expr, if_expr))(input)
 }
 
 // IfExpr ::= 'if' '(' Expr ')' 'then' ExprSingle 'else' ExprSingle
@@ -88,25 +87,29 @@ fn for_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
 }
 
 // SimpleForClause ::= 'for' SimpleForBinding (',' SimpleForBinding)*
-// SimpleForBinding ::= '$' VarName 'in' ExprSingle
 fn simple_for_clause(input: &str) -> IResult<&str, Vec<Constructor>> {
     map(
-        tuple((
-            tag("for"),
-            xpwhitespace,
-            separated_list1(
-                tuple((xpwhitespace, tag(","), xpwhitespace)),
-                tuple((
-                    tag("$"),
-                    qname,
-                    xpwhitespace,
-                    tag("in"),
-                    xpwhitespace,
-                    expr_single,
-                )),
-            ),
-        )),
-        |(_, _, b)| {
+        tuple((tag("for"), xpwhitespace, in_bindings)),
+        |(_, _, b)| b,
+    )(input)
+}
+
+// SimpleForBinding ::= '$' VarName 'in' ExprSingle
+// The bindings of a for expression and a quantified expression have the same syntax
+fn in_bindings(input: &str) -> IResult<&str, Vec<Constructor>> {
+    map(
+        separated_list1(
+            tuple((xpwhitespace, tag(","), xpwhitespace)),
+            tuple((
+                tag("$"),
+                qname,
+                xpwhitespace,
+                tag("in"),
+                xpwhitespace,
+                expr_single,
+            )),
+        ),
+        |b| {
             b.iter()
                 .map(|(_, v, _, _, _, e)| {
                     Constructor::VariableDeclaration(get_nt_localname(v), e.to_vec())
@@ -116,6 +119,23 @@ fn simple_for_clause(input: &str) -> IResult<&str, Vec<Constructor>> {
     )(input)
 }
 
+// QuantifiedExpr ::= ('some' | 'every') '$' VarName 'in' ExprSingle (',' '$' VarName 'in' ExprSingle)* 'satisfies' ExprSingle
+fn quantified_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
+    map(
+        tuple((
+            alt((
+                map(tag("some"), |_| Quantifier::Some),
+                map(tag("every"), |_| Quantifier::Every),
+            )),
+            xpwhitespace,
+            in_bindings,
+            tuple((xpwhitespace, tag("satisfies"), xpwhitespace)),
+            expr_single,
+        )),
+        |(q, _, b, _, t)| vec![Constructor::Quantified(q, b, t)],
+    )(input)
+}
+
 // LetExpr ::= SimpleLetClause 'return' ExprSingle
 fn let_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
     map(
@@ -124,12 +144,7 @@ fn let_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
             tuple((xpwhitespace, tag("return"), xpwhitespace)),
             expr_single,
         )),
-        |(mut l, _, mut e)| {
-            // Variable declaration
-            // expression
-            l.append(&mut e);
-            l
-        },
+        |(l, _, e)| vec![Constructor::Let(l, e)],
     )(input)
 }
 
@@ -427,17 +442,11 @@ fn union_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
             )),
             intersectexcept_expr,
         ),
-        |v| {
+        |mut v| {
             if v.len() == 1 {
-                let mut s = Vec::new();
-                for i in v {
-                    for j in i {
-                        s.push(j)
-                    }
-                }
-                s
+                v.pop().unwrap()
             } else {
-                vec![Constructor::NotImplemented("union_expr".to_string())]
+                vec![Constructor::SetOperation(SetOperator::Union, v)]
             }
         },
     )(input)
@@ -457,24 +466,15 @@ fn intersectexcept_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
             ))),
         ),
         |(a, b)| {
-            if b.len() == 0 {
-                a
-            } else {
-                // The arguments to the intersectexcept function are the sequences to be operated upon.
-                // These are pair-wise items: first is the operator as a string literal,
-                // second is the value
-                //	let mut r = Vec::new();
-
-                //        r.push(vec![SequenceConstructor::new(cons_literal).set_data(Some(Box::new(Value::from(""))))]);
-                //	r.push(a);
-
-                //	for ((_x, c, _y), d) in b {
-                //	  r.push(vec![SequenceConstructor::new(cons_literal).set_data(Some(Box::new(Value::String(c.to_string()))))]);
-                //	  r.push(d);
-                //	}
-                //        vec![SequenceConstructor::new(cons_intersectexcept).set_args(Some(r))]
-                vec![Constructor::NotImplemented("intersectexcept".to_string())]
-            }
+            // The operators are left-associative
+            b.into_iter().fold(a, |l, ((_, o, _), r)| {
+                let op = if o == "intersect" {
+                    SetOperator::Intersect
+                } else {
+                    SetOperator::Except
+                };
+                vec![Constructor::SetOperation(op, vec![l, r])]
+            })
         },
     )(input)
 }
@@ -493,26 +493,68 @@ fn instanceof_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
                 sequencetype_expr,
             ))),
         ),
-        |(u, v)| {
-            match v {
-                None => u,
-                Some(_t) => {
-                    //let mut r = Vec::new();
-                    //r.push(u);
-                    //let (_a, _b, _c, _d, _e, st) = t;
-                    //r.push(st);
-                    //vec![SequenceConstructor::new(cons_instanceof).set_args(Some(r))]
-                    vec![Constructor::NotImplemented("instance_of".to_string())]
-                }
-            }
+        |(u, v)| match v {
+            None => u,
+            Some((_, _, _, _, _, st)) => vec![Constructor::InstanceOf(u, st)],
         },
     )(input)
 }
 
 // SequenceType ::= ( 'empty-sequence' '(' ')' | (ItemType OccurrenceIndicator?)
-// TODO: implement this parser fully
-fn sequencetype_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
-    map(tag("empty-sequence()"), |_v| Vec::new())(input)
+fn sequencetype_expr(input: &str) -> IResult<&str, SequenceType> {
+    alt((
+        map(
+            tuple((
+                tag("empty-sequence"),
+                xpwhitespace,
+                tag("("),
+                xpwhitespace,
+                tag(")"),
+            )),
+            |_| SequenceType::Empty,
+        ),
+        map(pair(itemtype, opt(occurrence_indicator)), |(t, o)| {
+            SequenceType::Items(t, o.unwrap_or(Occurrence::One))
+        }),
+    ))(input)
+}
+
+// ItemType ::= KindTest | ('item' '(' ')') | FunctionTest | MapTest | ArrayTest | AtomicOrUnionType | ParenthesizedItemType
+// TODO: function, map and array tests, and parenthesized item types
+fn itemtype(input: &str) -> IResult<&str, ItemType> {
+    alt((
+        map(kindtest, |k| match k {
+            NodeTest::Kind(kt) => ItemType::Node(kt),
+            NodeTest::Name(_) => ItemType::Item,
+        }),
+        map(
+            tuple((tag("item"), xpwhitespace, tag("("), xpwhitespace, tag(")"))),
+            |_| ItemType::Item,
+        ),
+        map(atomictype, ItemType::Atomic),
+    ))(input)
+}
+
+// OccurrenceIndicator ::= '?' | '*' | '+'
+fn occurrence_indicator(input: &str) -> IResult<&str, Occurrence> {
+    alt((
+        map(tag("?"), |_| Occurrence::ZeroOrOne),
+        map(tag("*"), |_| Occurrence::ZeroOrMore),
+        map(tag("+"), |_| Occurrence::OneOrMore),
+    ))(input)
+}
+
+// AtomicOrUnionType ::= EQName
+// The atomic types are in the XML Schema namespace, which must be given by the prefix "xs" or as a URIQualifiedName.
+fn atomictype(input: &str) -> IResult<&str, AtomicType> {
+    let (rest, local) = alt((
+        preceded(tag("xs:"), ncname),
+        preceded(tuple((tag("Q{"), tag(XS_NAMESPACE), tag("}"))), ncname),
+    ))(input)?;
+    match AtomicType::from_local_name(local) {
+        Some(t) => Ok((rest, t)),
+        None => Err(NomErr::Error(NomError::new(input, NomErrorKind::Tag))),
+    }
 }
 
 // TreatExpr ::= CastableExpr ( 'treat' 'as' SequenceType)?
@@ -529,18 +571,9 @@ fn treat_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
                 sequencetype_expr,
             ))),
         ),
-        |(u, v)| {
-            match v {
-                None => u,
-                Some(_t) => {
-                    //let mut r = Vec::new();
-                    //r.push(u);
-                    //let (_a, _b, _c, _d, _e, st) = t;
-                    //r.push(st);
-                    //vec![SequenceConstructor::new(cons_treat).set_args(Some(r))]
-                    vec![Constructor::NotImplemented("treat".to_string())]
-                }
-            }
+        |(u, v)| match v {
+            None => u,
+            Some((_, _, _, _, _, st)) => vec![Constructor::Treat(u, st)],
         },
     )(input)
 }
@@ -559,18 +592,9 @@ fn castable_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
                 singletype_expr,
             ))),
         ),
-        |(u, v)| {
-            match v {
-                None => u,
-                Some(_t) => {
-                    //let mut r = Vec::new();
-                    //r.push(u);
-                    //let (_a, _b, _c, _d, _e, st) = t;
-                    //r.push(st);
-                    //vec![SequenceConstructor::new(cons_castable).set_args(Some(r))]
-                    vec![Constructor::NotImplemented("castable".to_string())]
-                }
-            }
+        |(u, v)| match v {
+            None => u,
+            Some((_, _, _, _, _, (t, e))) => vec![Constructor::Castable(u, t, e)],
         },
     )(input)
 }
@@ -578,21 +602,9 @@ fn castable_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
 // SingleType ::= SimpleTypeName '?'?
 // SimpleTypeName ::= TypeName
 // TypeName ::= EQName
-// EQName ::= QName | URIQualifiedName
-// URIQualifiedName ::= BracedURILiteral NCName
-// QName ::= PrefixedName | UnprefixedName
-// PrefixedName ::= Prefix ':' LocalPart
-// UnprefixedName ::= LocalPart
-// Prefix ::= NCName
-// LocalPart ::= NCName
-// NCName ::= Name - (Char* ':' Char*)
-// Char ::= #x9 | #xA |#xD | [#x20-#xD7FF] | [#xE000-#xFFFD | [#x10000-#x10FFFF]
-// TODO: implement this parser fully
-fn singletype_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
-    map(
-        pair(qname, opt(tuple((xpwhitespace, tag("?"), xpwhitespace)))),
-        |(_u, _v)| Vec::new(),
-    )(input)
+// The result is the type, and whether the empty sequence is allowed.
+fn singletype_expr(input: &str) -> IResult<&str, (AtomicType, bool)> {
+    map(pair(atomictype, opt(tag("?"))), |(t, q)| (t, q.is_some()))(input)
 }
 
 // CastExpr ::= ArrowExpr ( 'cast' 'as' SingleType)?
@@ -609,23 +621,15 @@ fn cast_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
                 singletype_expr,
             ))),
         ),
-        |(u, v)| {
-            match v {
-                None => u,
-                Some(_t) => {
-                    //let mut r = Vec::new();
-                    //r.push(u);
-                    //let (_a, _b, _c, _d, _e, st) = t;
-                    //r.push(st);
-                    //vec![SequenceConstructor::new(cons_cast).set_args(Some(r))]
-                    vec![Constructor::NotImplemented("cast".to_string())]
-                }
-            }
+        |(u, v)| match v {
+            None => u,
+            Some((_, _, _, _, _, (t, e))) => vec![Constructor::Cast(u, t, e)],
         },
     )(input)
 }
 
 // ArrowExpr ::= UnaryExpr ( '=>' ArrowFunctionSpecifier ArgumentList)*
+// The operand becomes the first argument of the function call
 fn arrow_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
     map(
         pair(
@@ -636,52 +640,48 @@ fn arrow_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
                 xpwhitespace,
                 arrowfunctionspecifier,
                 xpwhitespace,
-                opt(argumentlist),
+                arglist,
             ))),
         ),
         |(u, v)| {
-            if v.len() == 0 {
-                u
-            } else {
-                //vec![SequenceConstructor::new(cons_arrow)]
-                vec![Constructor::NotImplemented("arrow".to_string())]
-            }
+            v.into_iter()
+                .fold(u, |a, (_, _, _, n, _, mut args)| match n {
+                    Some(name) => {
+                        args.insert(0, a);
+                        vec![Constructor::FunctionCall(
+                            Function::new(name, vec![], None),
+                            args,
+                        )]
+                    }
+                    None => vec![Constructor::NotImplemented(
+                        "dynamic function call".to_string(),
+                    )],
+                })
         },
     )(input)
 }
 
 // ArrowFunctionSpecifier ::= EQName | VarRef | ParenthesizedExpr
-// TODO: finish this parser with EQName and VarRef
-fn arrowfunctionspecifier(input: &str) -> IResult<&str, Vec<Constructor>> {
-    map(alt((qname_expr, parenthesized_expr)), |_v| Vec::new())(input)
-}
-fn qname_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
-    map(qname, |q| match q {
-        NodeTest::Name(NameTest {
-            name: Some(WildcardOrName::Name(localpart)),
-            ns: None,
-            prefix: None,
-        }) => {
-            vec![Constructor::Literal(Value::from(localpart))]
-        }
-        _ => {
-            vec![Constructor::Literal(Value::from("invalid qname"))]
-        }
-    })(input)
-}
-
-// ArgumentList ::= '(' (Argument (',' Argument)*)? ')'
-// TODO: finish this parser with actual arguments
-fn argumentlist(input: &str) -> IResult<&str, Vec<Constructor>> {
-    map(
-        tag("()"),
-        //tuple((
-        //tag("("),
-        //xpwhitespace,
-        //tag(")"),
-        //)),
-        |_v| Vec::new(),
-    )(input)
+// The result is the name of the function. Function items are not supported, so a VarRef or ParenthesizedExpr has no name.
+// A prefixed name is kept, as for a function call, so that calling an unknown function is an error.
+fn arrowfunctionspecifier(input: &str) -> IResult<&str, Option<String>> {
+    alt((
+        map(qname, |q| match q {
+            NodeTest::Name(NameTest {
+                name: Some(WildcardOrName::Name(localpart)),
+                ns: None,
+                prefix: None,
+            }) => Some(localpart),
+            NodeTest::Name(NameTest {
+                name: Some(WildcardOrName::Name(localpart)),
+                ns: None,
+                prefix: Some(p),
+            }) => Some(format!("{}:{}", p, localpart)),
+            _ => None,
+        }),
+        map(variable_reference, |_| None),
+        map(parenthesized_expr, |_| None),
+    ))(input)
 }
 
 // UnaryExpr ::= ('-' | '+')* ValueExpr
@@ -707,20 +707,19 @@ fn unary_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
 // ValueExpr (SimpleMapExpr) ::= PathExpr ('!' PathExpr)*
 fn value_expr(input: &str) -> IResult<&str, Vec<Constructor>> {
     map(
-        pair(path_expr, many0(tuple((tag("!"), path_expr)))),
-        |(u, v)| {
-            if v.len() == 0 {
+        pair(
+            path_expr,
+            many0(preceded(
+                tuple((xpwhitespace, tag("!"), not(tag("=")), xpwhitespace)),
+                path_expr,
+            )),
+        ),
+        |(u, mut v)| {
+            if v.is_empty() {
                 u
             } else {
-                //let mut s = Vec::new();
-                //s.push(vec![SequenceConstructor::new(cons_literal).set_data(Some(Box::new(Value::from(""))))]);
-                //s.push(u);
-                //for (a, b) in v {
-                //s.push(vec![SequenceConstructor::new(cons_literal).set_data(Some(Box::new(Value::from(a))))]);
-                //s.push(b);
-                //}
-                //vec![SequenceConstructor::new(cons_simplemap).set_args(Some(s))]
-                vec![Constructor::NotImplemented("value".to_string())]
+                v.insert(0, u);
+                vec![Constructor::SimpleMap(v)]
             }
         },
     )(input)
@@ -1075,7 +1074,6 @@ fn schemaattributetest(input: &str) -> IResult<&str, NodeTest> {
     )(input)
 }
 // PITest ::= 'processing-instruction' '(' (NCName | StringLiteral)? ')'
-// TODO: capture PI name
 fn pitest(input: &str) -> IResult<&str, NodeTest> {
     map(
         tuple((
@@ -1083,9 +1081,16 @@ fn pitest(input: &str) -> IResult<&str, NodeTest> {
             xpwhitespace,
             tag("("),
             xpwhitespace,
+            opt(alt((
+                map(ncname, String::from),
+                map(alt((string_literal_double, string_literal_single)), |s| {
+                    s.trim().to_string()
+                }),
+            ))),
+            xpwhitespace,
             tag(")"),
         )),
-        |(_, _, _, _, _)| NodeTest::Kind(KindTest::PITest),
+        |(_, _, _, _, n, _, _)| NodeTest::Kind(KindTest::PITest(n)),
     )(input)
 }
 // CommentTest ::= 'comment' '(' ')'
@@ -1131,19 +1136,44 @@ fn anykindtest(input: &str) -> IResult<&str, NodeTest> {
 // NameTest ::= EQName | Wildcard
 // TODO: allow EQName rather than QName
 fn nametest(input: &str) -> IResult<&str, NodeTest> {
-    alt((qname, wildcard))(input)
+    alt((wildcard, qname))(input)
 }
 
 // Wildcard ::= '*' | (NCName ':*') | ('*:' NCName) | (BracedURILiteral '*')
-// TODO: more specific wildcards
 fn wildcard(input: &str) -> IResult<&str, NodeTest> {
-    map(tag("*"), |_w| {
-        NodeTest::Name(NameTest {
-            ns: Some(WildcardOrName::Wildcard),
-            prefix: None,
-            name: Some(WildcardOrName::Wildcard),
-        })
-    })(input)
+    alt((
+        map(tuple((ncname, tag(":*"))), |(p, _)| {
+            NodeTest::Name(NameTest {
+                ns: None,
+                prefix: Some(String::from(p)),
+                name: Some(WildcardOrName::Wildcard),
+            })
+        }),
+        map(tuple((tag("*:"), ncname)), |(_, l)| {
+            NodeTest::Name(NameTest {
+                ns: Some(WildcardOrName::Wildcard),
+                prefix: None,
+                name: Some(WildcardOrName::Name(String::from(l))),
+            })
+        }),
+        map(
+            tuple((tag("Q{"), take_until("}"), tag("}*"))),
+            |(_, u, _): (&str, &str, &str)| {
+                NodeTest::Name(NameTest {
+                    ns: Some(WildcardOrName::Name(String::from(u))),
+                    prefix: None,
+                    name: Some(WildcardOrName::Wildcard),
+                })
+            },
+        ),
+        map(tag("*"), |_w| {
+            NodeTest::Name(NameTest {
+                ns: Some(WildcardOrName::Wildcard),
+                prefix: None,
+                name: Some(WildcardOrName::Wildcard),
+            })
+        }),
+    ))(input)
 }
 
 // PostfixExpr ::= PrimaryExpr (Predicate | ArgumentList | Lookup)*
@@ -1184,6 +1214,17 @@ fn function_call(input: &str) -> IResult<&str, Vec<Constructor>> {
                 a,
             )]
         }
+        // Extension functions are not supported. The call is kept so that it is an error only if it is evaluated.
+        NodeTest::Name(NameTest {
+            name: Some(WildcardOrName::Name(localpart)),
+            ns: None,
+            prefix: Some(p),
+        }) => {
+            vec![Constructor::FunctionCall(
+                Function::new(format!("{}:{}", p, localpart), vec![], None),
+                a,
+            )]
+        }
         _ => {
             vec![Constructor::Literal(Value::from("invalid qname"))]
         }
@@ -1398,6 +1439,10 @@ fn take_until_balanced<'a>(
 }
 
 /// Parse an XPath expression. The result is a Sequence constructor.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "compile_xpath", level = "debug")
+)]
 pub fn parse(e: &str) -> Result<Vec<Constructor>, crate::xdmerror::Error> {
     match expr(e) {
         Ok((rest, value)) => {
@@ -1428,6 +1473,418 @@ pub fn parse(e: &str) -> Result<Vec<Constructor>, crate::xdmerror::Error> {
     }
 }
 
+/// Parse a sequence type, such as "xs:integer+" or "element()?".
+pub fn parse_sequence_type(e: &str) -> Result<SequenceType, Error> {
+    match sequencetype_expr(e.trim()) {
+        Ok(("", st)) => Ok(st),
+        _ => Result::Err(Error::new(
+            ErrorKind::StaticSyntax,
+            format!("invalid sequence type \"{}\"", e),
+        )),
+    }
+}
+
+/// An XPath expression that has been parsed, together with the text it was parsed from.
+#[derive(Clone)]
+pub struct CompiledXPath {
+    source: String,
+    constructors: Vec<Constructor>,
+}
+
+impl CompiledXPath {
+    /// Parse an XPath expression, and perform static analysis using the built-in functions.
+    pub fn new(e: &str) -> Result<Self, Error> {
+        Self::new_with_context(e, &mut StaticContext::new_with_builtins())
+    }
+    /// Parse an XPath expression, and perform static analysis using the given static context.
+    pub fn new_with_context(e: &str, sc: &mut StaticContext) -> Result<Self, Error> {
+        let mut constructors = parse(e)?;
+        sc.static_analysis(&mut constructors);
+        Ok(CompiledXPath {
+            source: e.to_string(),
+            constructors,
+        })
+    }
+    /// The text of the expression.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    /// The sequence constructor, to be evaluated by an [Evaluator](../evaluate/struct.Evaluator.html).
+    pub fn constructors(&self) -> &Vec<Constructor> {
+        &self.constructors
+    }
+    /// A human-readable plan of how the expression will be evaluated. Each line describes an operation, indented beneath the operation that uses its result. Steps report how their axis is navigated, and whether the cost grows with the size of the document; descendant steps that can use the indexes of the tree report them; filters report whether they select by position; calls to the id and key functions are reported as index lookups, and calls to functions that are not defined are reported as such.
+    pub fn explain(&self) -> String {
+        let mut r = format!("expression {}\n", self.source);
+        explain(&self.constructors, 1, &mut r);
+        r
+    }
+}
+
+// Append an indented line to the plan
+fn plan_line(r: &mut String, depth: usize, s: &str) {
+    r.push_str(&"  ".repeat(depth));
+    r.push_str(s);
+    r.push('\n');
+}
+
+fn explain(c: &[Constructor], depth: usize, r: &mut String) {
+    for k in c {
+        match k {
+            Constructor::Literal(Value::String(v)) => {
+                plan_line(r, depth, format!("literal \"{}\"", v).as_str())
+            }
+            Constructor::Literal(v) => plan_line(r, depth, format!("literal {}", v).as_str()),
+            Constructor::ContextItem => plan_line(r, depth, "context item"),
+            Constructor::Root => {
+                plan_line(r, depth, "root of the tree containing the context node")
+            }
+            Constructor::Path(v) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!(
+                        "path of {} steps, each applied to every item selected by the previous step",
+                        v.len()
+                    )
+                    .as_str(),
+                );
+                for s in v {
+                    explain(s, depth + 1, r)
+                }
+            }
+            Constructor::Step(nm, p) => {
+                let indexed = indexed_name(nm).is_some();
+                let by_attribute = indexed && indexed_attribute(p).is_some();
+                let strategy = match nm.axis {
+                    Axis::Child => "scan the children",
+                    Axis::Descendant | Axis::DescendantOrSelf if by_attribute => {
+                        "look up the attribute value in the indexes of the tree if the document is indexed, otherwise scan the subtree"
+                    }
+                    Axis::Descendant | Axis::DescendantOrSelf if indexed => {
+                        "look up the element name in the indexes of the tree if the document is indexed, otherwise scan the subtree"
+                    }
+                    Axis::Descendant => {
+                        "scan the subtree; cost grows with the size of the document"
+                    }
+                    Axis::DescendantOrSelf => {
+                        "scan the node and its subtree; cost grows with the size of the document"
+                    }
+                    Axis::Attribute | Axis::SelfAttribute => "scan the attributes",
+                    Axis::Selfaxis | Axis::SelfDocument => "test the node itself",
+                    Axis::Parent | Axis::ParentDocument => "select the parent",
+                    Axis::Ancestor | Axis::AncestorOrSelf => "walk up the ancestors",
+                    Axis::FollowingSibling | Axis::PrecedingSibling => "scan the siblings",
+                    Axis::Following | Axis::Preceding => {
+                        "scan the document in order; cost grows with the size of the document"
+                    }
+                    Axis::Namespace => "scan the namespace nodes",
+                    Axis::Unknown => "unknown axis",
+                };
+                plan_line(
+                    r,
+                    depth,
+                    format!(
+                        "step {}::{}: {}",
+                        nm.axis.to_string(),
+                        nm.nodetest.to_xpath(),
+                        strategy
+                    )
+                    .as_str(),
+                );
+                for q in p {
+                    match q.as_slice() {
+                        [Constructor::Literal(
+                            v @ (Value::Integer(_) | Value::Decimal(_) | Value::Double(_)),
+                        )] => plan_line(
+                            r,
+                            depth + 1,
+                            format!("positional filter: keep the item at position {}", v).as_str(),
+                        ),
+                        [Constructor::FunctionCall(func, a)]
+                            if func.get_name() == "last" && a.is_empty() =>
+                        {
+                            plan_line(r, depth + 1, "positional filter: keep the last item")
+                        }
+                        _ => {
+                            plan_line(r, depth + 1, "filter: evaluated for each selected item");
+                            explain(q, depth + 2, r)
+                        }
+                    }
+                }
+            }
+            Constructor::Or(v) => {
+                plan_line(r, depth, "or");
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::And(v) => {
+                plan_line(r, depth, "and");
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::GeneralComparison(o, v) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!(
+                        "general comparison {}: every pair of items is compared",
+                        o.to_string()
+                    )
+                    .as_str(),
+                );
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::ValueComparison(o, v) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!("value comparison {}", o.to_string()).as_str(),
+                );
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::Concat(v) => {
+                plan_line(r, depth, "string concatenation");
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::ValueTemplate(v) => {
+                plan_line(r, depth, "value template");
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::SetOperation(o, v) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!("{} of nodes, in document order", o).as_str(),
+                );
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::SimpleMap(v) => {
+                plan_line(
+                    r,
+                    depth,
+                    "simple map: each operand is evaluated for each item of the previous result",
+                );
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::Range(v) => {
+                plan_line(r, depth, "range of integers");
+                for o in v {
+                    explain(o, depth + 1, r)
+                }
+            }
+            Constructor::Arithmetic(v) => {
+                plan_line(r, depth, "arithmetic");
+                for a in v {
+                    let op = match a.op {
+                        ArithmeticOperator::Noop => None,
+                        ArithmeticOperator::Add => Some("+"),
+                        ArithmeticOperator::Subtract => Some("-"),
+                        ArithmeticOperator::Multiply => Some("*"),
+                        ArithmeticOperator::Divide => Some("div"),
+                        ArithmeticOperator::IntegerDivide => Some("idiv"),
+                        ArithmeticOperator::Modulo => Some("mod"),
+                    };
+                    if let Some(o) = op {
+                        plan_line(r, depth + 1, format!("operator {}", o).as_str())
+                    }
+                    explain(&a.operand, depth + 1, r)
+                }
+            }
+            Constructor::FunctionCall(func, a) => {
+                let name = func.get_name();
+                if func.get_body().is_none() {
+                    plan_line(
+                        r,
+                        depth,
+                        format!("call to undefined function {}()", name).as_str(),
+                    )
+                } else if matches!(name.as_str(), "id" | "idref" | "element-with-id" | "key") {
+                    plan_line(r, depth, format!("index lookup {}()", name).as_str())
+                } else {
+                    plan_line(
+                        r,
+                        depth,
+                        format!("function call {}() with {} arguments", name, a.len()).as_str(),
+                    )
+                }
+                for b in a {
+                    explain(b, depth + 1, r)
+                }
+            }
+            Constructor::VariableDeclaration(v, e) => {
+                plan_line(r, depth, format!("bind variable ${}", v).as_str());
+                explain(e, depth + 1, r)
+            }
+            Constructor::VariableReference(v) => {
+                plan_line(r, depth, format!("variable ${}", v).as_str())
+            }
+            Constructor::Loop(d, b) => {
+                plan_line(r, depth, "for loop: the body is evaluated for each binding");
+                explain(d, depth + 1, r);
+                plan_line(r, depth + 1, "return");
+                explain(b, depth + 2, r)
+            }
+            Constructor::Let(d, b) => {
+                plan_line(r, depth, "let: the variables are bound in order");
+                explain(d, depth + 1, r);
+                plan_line(r, depth + 1, "return");
+                explain(b, depth + 2, r)
+            }
+            Constructor::Quantified(q, d, t) => {
+                plan_line(
+                    r,
+                    depth,
+                    match q {
+                        Quantifier::Some => "some: true if the test is satisfied by any binding",
+                        Quantifier::Every => "every: true if the test is satisfied by all bindings",
+                    },
+                );
+                explain(d, depth + 1, r);
+                plan_line(r, depth + 1, "satisfies");
+                explain(t, depth + 2, r)
+            }
+            Constructor::Switch(v, o) => {
+                plan_line(r, depth, "conditional: the tests are evaluated in order");
+                for (i, t) in v.iter().enumerate() {
+                    plan_line(r, depth + 1, if i % 2 == 0 { "if" } else { "then" });
+                    explain(t, depth + 2, r)
+                }
+                plan_line(r, depth + 1, "otherwise");
+                explain(o, depth + 2, r)
+            }
+            Constructor::LiteralElement(qn, c) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!("construct element {}", qn.to_string()).as_str(),
+                );
+                explain(c, depth + 1, r)
+            }
+            Constructor::LiteralAttribute(qn, c) | Constructor::SetAttribute(qn, c) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!("construct attribute {}", qn.to_string()).as_str(),
+                );
+                explain(c, depth + 1, r)
+            }
+            Constructor::UnescapedText(v) => plan_line(
+                r,
+                depth,
+                format!("construct unescaped text \"{}\"", v).as_str(),
+            ),
+            Constructor::Copy(s, c) => {
+                plan_line(r, depth, "shallow copy");
+                explain(s, depth + 1, r);
+                explain(c, depth + 1, r)
+            }
+            Constructor::InstanceOf(s, t) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!("test the sequence against {}", t).as_str(),
+                );
+                explain(s, depth + 1, r)
+            }
+            Constructor::Treat(s, t) => {
+                plan_line(r, depth, format!("check the sequence is {}", t).as_str());
+                explain(s, depth + 1, r)
+            }
+            Constructor::Convert(s, t) => {
+                plan_line(r, depth, format!("convert the sequence to {}", t).as_str());
+                explain(s, depth + 1, r)
+            }
+            Constructor::Cast(s, t, _) => {
+                plan_line(r, depth, format!("cast the value to {}", t).as_str());
+                explain(s, depth + 1, r)
+            }
+            Constructor::Castable(s, t, _) => {
+                plan_line(
+                    r,
+                    depth,
+                    format!("test whether the value can be cast to {}", t).as_str(),
+                );
+                explain(s, depth + 1, r)
+            }
+            Constructor::DeepCopy(s) => {
+                plan_line(r, depth, "deep copy");
+                explain(s, depth + 1, r)
+            }
+            Constructor::ApplyTemplates(s) => {
+                plan_line(r, depth, "apply templates to each selected item");
+                explain(s, depth + 1, r)
+            }
+            Constructor::ApplyImports => plan_line(r, depth, "apply imported templates"),
+            Constructor::Empty => plan_line(r, depth, "empty sequence"),
+            Constructor::ForEach(s, t, _) => {
+                plan_line(r, depth, "for each selected item");
+                explain(s, depth + 1, r);
+                plan_line(r, depth + 1, "do");
+                explain(t, depth + 2, r)
+            }
+            Constructor::Iterate(s, p, b, c) => {
+                plan_line(r, depth, "iterate over each selected item, in order");
+                explain(s, depth + 1, r);
+                plan_line(r, depth + 1, "with parameters");
+                explain(p, depth + 2, r);
+                plan_line(r, depth + 1, "do");
+                explain(b, depth + 2, r);
+                if !c.is_empty() {
+                    plan_line(r, depth + 1, "on completion");
+                    explain(c, depth + 2, r)
+                }
+            }
+            Constructor::Merge(m, a) => {
+                plan_line(
+                    r,
+                    depth,
+                    "merge sorted sources in one pass, taking the lowest merge key from the front of each source",
+                );
+                for s in m {
+                    plan_line(r, depth + 1, "source");
+                    explain(&s.select, depth + 2, r);
+                    plan_line(r, depth + 1, "merge keys");
+                    for k in &s.keys {
+                        explain(&k.select, depth + 2, r)
+                    }
+                }
+                plan_line(
+                    r,
+                    depth + 1,
+                    "for each group of items with the same merge key",
+                );
+                explain(a, depth + 2, r)
+            }
+            Constructor::NextIteration(p) => {
+                plan_line(r, depth, "next iteration with new parameter values");
+                explain(p, depth + 1, r)
+            }
+            Constructor::Break(b) => {
+                plan_line(r, depth, "stop iterating");
+                explain(b, depth + 1, r)
+            }
+            Constructor::NotImplemented(m) => {
+                plan_line(r, depth, format!("not implemented: {}", m).as_str())
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1783,8 +2240,8 @@ mod tests {
 
     #[test]
     fn nomxpath_parse_castable() {
-        let e = parse("'a' castable as type")
-            .expect("failed to parse expression \"'a' castable as type\"");
+        let e = parse("'a' castable as xs:string")
+            .expect("failed to parse expression \"'a' castable as xs:string\"");
         if e.len() == 1 {
             assert!(true) // TODO: check the sequence constructor
         } else {
@@ -1794,7 +2251,8 @@ mod tests {
 
     #[test]
     fn nomxpath_parse_cast() {
-        let e = parse("'a' cast as type").expect("failed to parse expression \"'a' cast as type\"");
+        let e = parse("'a' cast as xs:string")
+            .expect("failed to parse expression \"'a' cast as xs:string\"");
         if e.len() == 1 {
             assert!(true) // TODO: check the sequence constructor
         } else {
@@ -2278,6 +2736,200 @@ mod tests {
         }
     }
 
+    // Evaluate an expression with the built-in functions, and no context
+    fn eval_string(x: &str) -> Result<String, Error> {
+        let mut e = parse(x)?;
+        StaticContext::new_with_builtins().static_analysis(&mut e);
+        let mut f = Forest::new();
+        let sd = f.plant_tree();
+        let rd = f.plant_tree();
+        Evaluator::new()
+            .evaluate(None, None, &e, &mut f, sd, rd)
+            .map(|s| s.to_string(None))
+    }
+    #[test]
+    fn parse_eval_fncall_case() {
+        assert_eq!(eval_string("upper-case('abCd0')").unwrap(), "ABCD0");
+        assert_eq!(eval_string("lower-case('ÀBÇ')").unwrap(), "àbç");
+        assert_eq!(eval_string("upper-case('straße')").unwrap(), "STRASSE");
+        assert_eq!(eval_string("upper-case(())").unwrap(), "");
+        #[cfg(feature = "icu")]
+        assert_eq!(
+            eval_string("upper-case('istanbul', 'tr')").unwrap(),
+            "İSTANBUL"
+        );
+        #[cfg(not(feature = "icu"))]
+        assert!(eval_string("upper-case('istanbul', 'tr')").is_err());
+    }
+    #[test]
+    fn parse_eval_range() {
+        let t = |x: &str| eval_string(x).unwrap();
+        assert_eq!(t("1 to 5"), "12345");
+        assert_eq!(t("3 to 3"), "3");
+        assert_eq!(t("3 to 1"), "");
+        assert_eq!(t("() to 3"), "");
+        assert_eq!(t("count(1 to 1000)"), "1000");
+        assert_eq!(t("for $i in 1 to 4 return $i * $i"), "14916");
+        assert_eq!(t("for $i in 5 to 4 return $i"), "");
+        assert!(eval_string("1.5 to 3").is_err());
+        assert!(eval_string("'1' to 3").is_err());
+        assert!(eval_string("(1, 2) to 3").is_err());
+        assert!(eval_string("for $i in 1 to 'x' return $i").is_err());
+    }
+    #[test]
+    fn parse_eval_large_range() {
+        let t = |x: &str| eval_string(x).unwrap();
+        // The range is not constructed to count it, or to take part of it
+        assert_eq!(t("count(1 to 1000000000)"), "1000000000");
+        assert_eq!(t("count(5 to 4)"), "0");
+        assert_eq!(
+            t("subsequence(1 to 1000000000, 999999999)"),
+            "9999999991000000000"
+        );
+        assert_eq!(t("subsequence(1 to 1000000000, 2.5, 2)"), "34");
+        assert_eq!(t("subsequence(1 to 10, 0.4, 3)"), "12");
+        assert_eq!(t("subsequence(1 to 10, 11)"), "");
+        assert_eq!(t("subsequence(('a', 'b', 'c'), 2)"), "bc");
+        assert_eq!(t("subsequence(('a', 'b', 'c'), 0, 2)"), "a");
+        // Otherwise a range that is too large is an error, rather than exhausting memory
+        let e = eval_string("(1 to 1000000000, 0)").expect_err("range is too large");
+        assert!(matches!(e.kind, ErrorKind::SizeLimit));
+    }
+    #[test]
+    fn parse_eval_sequence_types() {
+        let t = |x: &str| eval_string(x).unwrap();
+        assert_eq!(t("1 instance of xs:integer"), "true");
+        assert_eq!(t("1 instance of xs:decimal"), "true");
+        assert_eq!(t("1 instance of xs:string"), "false");
+        assert_eq!(t("(1, 2) instance of xs:integer"), "false");
+        assert_eq!(t("(1, 2) instance of xs:integer+"), "true");
+        assert_eq!(t("() instance of xs:integer?"), "true");
+        assert_eq!(t("() instance of empty-sequence()"), "true");
+        assert_eq!(t("('a', 1.5) instance of item()*"), "true");
+        assert_eq!(t("1.5 instance of xs:numeric"), "true");
+        assert_eq!(t("'a' instance of node()"), "false");
+        assert_eq!(t("'12' cast as xs:integer + 1"), "13");
+        assert_eq!(t("3.7 cast as xs:integer"), "3");
+        assert_eq!(t("'1' cast as xs:boolean"), "true");
+        assert_eq!(t("() cast as xs:integer?"), "");
+        assert!(eval_string("() cast as xs:integer").is_err());
+        assert!(eval_string("'x' cast as xs:integer").is_err());
+        assert!(eval_string("300 cast as xs:byte").is_err());
+        assert_eq!(t("'12' castable as xs:integer"), "true");
+        assert_eq!(t("'x' castable as xs:integer"), "false");
+        assert_eq!(t("'-1' castable as xs:positiveInteger"), "false");
+        assert_eq!(t("() castable as xs:integer?"), "true");
+        assert_eq!(t("'a' treat as xs:string"), "a");
+        assert!(eval_string("'a' treat as xs:integer").is_err());
+        // Types that are not in the XML Schema namespace are not known
+        assert!(parse("1 instance of integer").is_err());
+        assert!(parse("1 cast as xs:unknown").is_err());
+        assert_eq!(
+            parse_sequence_type("Q{http://www.w3.org/2001/XMLSchema}int*")
+                .unwrap()
+                .to_string(),
+            "xs:int*"
+        );
+        assert_eq!(
+            parse_sequence_type(" element()? ").unwrap().to_string(),
+            "element()?"
+        );
+        assert!(parse_sequence_type("xs:int**").is_err());
+    }
+    #[test]
+    fn parse_eval_fncall_uri() {
+        assert_eq!(
+            eval_string("encode-for-uri('http://example.com/ a~b/ü')").unwrap(),
+            "http%3A%2F%2Fexample.com%2F%20a~b%2F%C3%BC"
+        );
+        assert_eq!(
+            eval_string("iri-to-uri('http://example.com/a b/ü?x={1}#f')").unwrap(),
+            "http://example.com/a%20b/%C3%BC?x=%7B1%7D#f"
+        );
+        assert_eq!(
+            eval_string("escape-html-uri('http://example.com/a b/ü?x={1}')").unwrap(),
+            "http://example.com/a b/%C3%BC?x={1}"
+        );
+        assert_eq!(
+            eval_string("resolve-uri('../g?y', 'http://a/b/c/d;p?q')").unwrap(),
+            "http://a/b/g?y"
+        );
+        assert_eq!(
+            eval_string("resolve-uri('#x', 'urn:example:doc')").unwrap(),
+            "urn:example:doc#x"
+        );
+        assert_eq!(eval_string("resolve-uri((), 'http://a/')").unwrap(), "");
+        // There is no base URL
+        assert!(eval_string("resolve-uri('g')").is_err());
+        assert!(eval_string("resolve-uri('g', 'not/absolute')").is_err());
+    }
+    #[test]
+    fn parse_eval_fncall_resource_available() {
+        let mut m = crate::resolvers::MemoryResolver::new();
+        m.insert("http://example.org/dir/a.xml", "<a>content</a>");
+        m.insert("http://example.org/dir/bad.xml", "<a>");
+        m.insert("http://example.org/dir/t.txt", "some text");
+        let mut ev = Evaluator::new();
+        ev.set_baseurl(url::Url::parse("http://example.org/dir/").unwrap());
+        ev.set_resolver(Rc::new(m));
+        let mut f = Forest::new();
+        let sd = f.plant_tree();
+        let rd = f.plant_tree();
+        let mut eval = |x: &str| {
+            let mut e = parse(x)?;
+            StaticContext::new_with_builtins().static_analysis(&mut e);
+            ev.evaluate(None, None, &e, &mut f, sd, rd)
+                .map(|s| s.to_string(None))
+        };
+        assert_eq!(eval("doc-available('a.xml')").unwrap(), "true");
+        assert_eq!(eval("doc-available('bad.xml')").unwrap(), "false");
+        assert_eq!(eval("doc-available('missing.xml')").unwrap(), "false");
+        assert_eq!(eval("doc-available(())").unwrap(), "false");
+        assert_eq!(eval("unparsed-text-available('t.txt')").unwrap(), "true");
+        assert_eq!(
+            eval("unparsed-text-available('missing.txt', 'utf-8')").unwrap(),
+            "false"
+        );
+        assert_eq!(eval("unparsed-text('t.txt')").unwrap(), "some text");
+        assert_eq!(eval("string(doc('a.xml'))").unwrap(), "content");
+        // Errors are only suppressed by the probes
+        assert!(eval("doc('bad.xml')").is_err());
+        assert!(eval("unparsed-text('missing.txt')").is_err());
+        // Nothing is fetched without a resolver
+        let mut ev = Evaluator::new();
+        ev.set_baseurl(url::Url::parse("http://example.org/dir/").unwrap());
+        let e = parse("unparsed-text('t.txt')").expect("unable to parse expression");
+        assert!(ev.evaluate(None, None, &e, &mut f, sd, rd).is_err());
+    }
+    #[test]
+    fn parse_eval_fncall_normalize_unicode() {
+        // "e" followed by a combining acute accent, and the precomposed character
+        assert_eq!(
+            eval_string("normalize-unicode('e\u{301}')").unwrap(),
+            "\u{e9}"
+        );
+        assert_eq!(
+            eval_string("normalize-unicode('\u{e9}', 'nfd')").unwrap(),
+            "e\u{301}"
+        );
+        assert_eq!(
+            eval_string("normalize-unicode('\u{fb01}', ' NFKC ')").unwrap(),
+            "fi"
+        );
+        assert_eq!(
+            eval_string("normalize-unicode('\u{fb01}', 'NFC')").unwrap(),
+            "\u{fb01}"
+        );
+        assert_eq!(
+            eval_string("normalize-unicode('\u{fb01}', 'NFKD')").unwrap(),
+            "fi"
+        );
+        assert_eq!(
+            eval_string("normalize-unicode('e\u{301}', '')").unwrap(),
+            "e\u{301}"
+        );
+        assert!(eval_string("normalize-unicode('a', 'FULLY-NORMALIZED')").is_err());
+    }
     // Variables
     #[test]
     fn parse_eval_let_1() {
@@ -2306,6 +2958,102 @@ mod tests {
         assert_eq!(s.to_string(None), "ab")
     }
 
+    #[test]
+    fn parse_eval_let_scope() {
+        let t = |x: &str| eval_string(x).unwrap();
+        // Each variable is in scope for the later bindings and the body
+        assert_eq!(t("let $x := 1, $y := $x + 1 return $x * 10 + $y"), "12");
+        // An inner binding shadows an outer one only within its body
+        assert_eq!(t("let $x := 1 return ((let $x := 2 return $x), $x)"), "21");
+        // ...and a variable is not in scope after the let expression
+        assert!(eval_string("(let $x := 1 return $x), $x").is_err());
+        assert!(eval_string("(for $x in 1 to 2 return $x), $x").is_err());
+    }
+    #[test]
+    fn parse_eval_json() {
+        let t = |x: &str| eval_string(x).unwrap();
+        assert_eq!(
+            t("xml-to-json(json-to-xml('[1, \"a\", {\"b\": null, \"c\": [true]}]'))"),
+            r#"[1,"a",{"b":null,"c":[true]}]"#
+        );
+        assert_eq!(
+            t("string(json-to-xml('{\"a\": \"x\", \"b\": [\"y\"]}'))"),
+            "xy"
+        );
+        assert_eq!(t("json-to-xml(())"), "");
+        assert!(eval_string("json-to-xml('[1,')").is_err());
+        assert!(eval_string("xml-to-json((1, 2))").is_err());
+    }
+
+    #[test]
+    fn parse_eval_serialize() {
+        let t = |x: &str| eval_string(x).unwrap();
+        assert_eq!(
+            t("serialize((1, 'a\"b', true(), 1.5))"),
+            "1\n\"a\"\"b\"\ntrue()\n1.5"
+        );
+        assert_eq!(t("serialize(())"), "");
+        assert_eq!(
+            t("serialize(json-to-xml('null'))"),
+            format!("<null xmlns='{}'></null>", crate::json::JSON_NAMESPACE)
+        );
+    }
+
+    #[test]
+    fn parse_eval_simple_map_arrow() {
+        let t = |x: &str| eval_string(x).unwrap();
+        assert_eq!(t("(1, 2, 3) ! (. * 2)"), "246");
+        assert_eq!(t("('a', 'b') ! upper-case(.) ! concat(., '-')"), "A-B-");
+        assert_eq!(t("() ! 1"), "");
+        // Not to be confused with !=
+        assert_eq!(t("1 != 2"), "true");
+        assert_eq!(t("'abc' => upper-case()"), "ABC");
+        assert_eq!(t("'abcd' => substring(2) => substring(1, 2)"), "bc");
+        // The simple map binds more tightly than the arrow
+        assert_eq!(t("(1, 2) ! (. + 1) => count()"), "2");
+        // Function items are not supported
+        assert!(eval_string("'a' => $f()").is_err());
+        // Nor are extension functions
+        let e = eval_string("'a' => my:f()").expect_err("call to unknown function");
+        assert!(e.message.starts_with("XPST0017"));
+    }
+    #[test]
+    fn parse_eval_quantified() {
+        let t = |x: &str| eval_string(x).unwrap();
+        assert_eq!(t("some $x in (1, 2, 3) satisfies $x > 2"), "true");
+        assert_eq!(t("every $x in (1, 2, 3) satisfies $x > 2"), "false");
+        assert_eq!(t("every $x in (1, 2, 3) satisfies $x > 0"), "true");
+        assert_eq!(t("some $x in () satisfies true()"), "false");
+        assert_eq!(t("every $x in () satisfies false()"), "true");
+        assert_eq!(
+            t("some $x in 1 to 3, $y in 1 to 3 satisfies $x + $y = 6"),
+            "true"
+        );
+        assert_eq!(
+            t("every $x in 1 to 3, $y in $x to 3 satisfies $y ge $x"),
+            "true"
+        );
+        // Evaluation stops at the first binding that decides the result
+        assert_eq!(
+            t("some $x in 1 to 9223372036854775807 satisfies $x = 3"),
+            "true"
+        );
+        assert_eq!(
+            t("if (some $x in ('a', 'b') satisfies $x = 'b') then 'yes' else 'no'"),
+            "yes"
+        );
+        assert!(eval_string("some $x in (1, 2) satisfies $y").is_err());
+    }
+    #[test]
+    fn parse_eval_for_bindings() {
+        let t = |x: &str| eval_string(x).unwrap();
+        assert_eq!(
+            t("for $x in (1, 2), $y in (10, 20) return $x + $y"),
+            "11211222"
+        );
+        assert_eq!(t("for $x in 1 to 3, $y in $x to 3 return $y"), "123233");
+    }
+
     // Loops
     #[test]
     fn parse_eval_for_1() {
@@ -2364,4 +3112,177 @@ mod tests {
         assert_eq!(s.len(), 1);
         assert_eq!(s.to_string(None), "not one")
     }
+
+    #[test]
+    fn parse_eval_wildcards() {
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<a xmlns:p='urn:p'><p:b/><c/><p:d/><?go one?><?stop two?></a>")
+            .expect("unable to parse XML");
+        let rd = f.plant_tree();
+        let d = f.get_ref(src).unwrap().get_doc_node();
+        let eval = |f: &mut Forest, x: &str| {
+            let mut e = parse(x).expect("failed to parse expression");
+            StaticContext::new_with_builtins().static_analysis(&mut e);
+            Evaluator::new()
+                .evaluate(Some(vec![Rc::new(Item::Node(d))]), Some(0), &e, f, src, rd)
+                .expect("evaluation failed")
+                .len()
+        };
+        assert_eq!(eval(&mut f, "/child::a/child::p:*"), 2);
+        assert_eq!(eval(&mut f, "/child::a/child::*:c"), 1);
+        assert_eq!(eval(&mut f, "/child::a/child::Q{urn:p}*"), 2);
+        assert_eq!(eval(&mut f, "/child::a/child::processing-instruction()"), 2);
+        assert_eq!(
+            eval(&mut f, "/child::a/child::processing-instruction(stop)"),
+            1
+        );
+        assert_eq!(
+            eval(&mut f, "/child::a/child::processing-instruction('go')"),
+            1
+        )
+    }
+
+    #[test]
+    fn parse_eval_reverse_axes() {
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<a><b><c/></b><d/><e><g/></e></a>")
+            .expect("unable to parse XML");
+        let rd = f.plant_tree();
+        let d = f.get_ref(src).unwrap().get_doc_node();
+        let eval = |f: &mut Forest, x: &str| {
+            let mut e = parse(x).expect("failed to parse expression");
+            StaticContext::new_with_builtins().static_analysis(&mut e);
+            let s = Evaluator::new()
+                .evaluate(Some(vec![Rc::new(Item::Node(d))]), Some(0), &e, f, src, rd)
+                .expect("evaluation failed");
+            s.iter()
+                .map(|i| match &**i {
+                    Item::Node(n) => n.to_name(f).get_localname(),
+                    _ => String::new(),
+                })
+                .collect::<Vec<_>>()
+        };
+        // Positions count backwards from the context node, but the result is in document order
+        assert_eq!(
+            eval(&mut f, "/child::a/child::e/preceding-sibling::*[1]"),
+            vec!["d"]
+        );
+        assert_eq!(
+            eval(&mut f, "/child::a/child::e/preceding-sibling::*"),
+            vec!["b", "d"]
+        );
+        assert_eq!(
+            eval(&mut f, "/child::a/child::e/child::g/preceding::*[2]"),
+            vec!["c"]
+        );
+        assert_eq!(
+            eval(&mut f, "/child::a/child::e/child::g/ancestor-or-self::*[1]"),
+            vec!["g"]
+        );
+        assert_eq!(
+            eval(&mut f, "/child::a/child::e/child::g/ancestor::*"),
+            vec!["a", "e"]
+        )
+    }
+
+    #[test]
+    fn parse_eval_set_operators() {
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<a><b><c/></b><d/><e><c/></e></a>")
+            .expect("unable to parse XML");
+        let other = f.grow_tree("<x/>").expect("unable to parse XML");
+        let rd = f.plant_tree();
+        let d = f.get_ref(src).unwrap().get_doc_node();
+        let x = f.get_ref(other).unwrap().get_doc_node();
+        let eval = |f: &mut Forest, e: &str| {
+            let mut e = parse(e)?;
+            StaticContext::new_with_builtins().static_analysis(&mut e);
+            let ev = Evaluator::new();
+            ev.dynamic_context()
+                .set_parameter(String::from("x"), vec![Rc::new(Item::Node(x))]);
+            let s = ev.evaluate(Some(vec![Rc::new(Item::Node(d))]), Some(0), &e, f, src, rd)?;
+            Ok::<Vec<String>, Error>(
+                s.iter()
+                    .map(|i| match &**i {
+                        Item::Node(n) => n.to_name(f).get_localname(),
+                        _ => String::new(),
+                    })
+                    .collect(),
+            )
+        };
+        let t = |f: &mut Forest, e: &str| eval(f, e).expect("evaluation failed").join(" ");
+        // The result is in document order, without duplicates
+        assert_eq!(t(&mut f, "/child::a/child::e | /child::a/child::b"), "b e");
+        assert_eq!(
+            t(
+                &mut f,
+                "/descendant::c union /child::a/child::*/child::c union /child::a"
+            ),
+            "a c c"
+        );
+        assert_eq!(
+            t(
+                &mut f,
+                "/descendant::* intersect /child::a/child::e/descendant-or-self::*"
+            ),
+            "e c"
+        );
+        assert_eq!(
+            t(&mut f, "/descendant::* except /child::a/child::*/child::c"),
+            "a b d e"
+        );
+        // Left-associative: (descendants intersect children of a) except e
+        assert_eq!(
+            t(
+                &mut f,
+                "/descendant::* intersect /child::a/child::* except /child::a/child::e"
+            ),
+            "b d"
+        );
+        assert_eq!(t(&mut f, "() | ()"), "");
+        // Nodes from different documents are combined
+        assert_eq!(t(&mut f, "$x/child::x | /child::a").split(' ').count(), 2);
+        // The operands must be nodes
+        assert!(eval(&mut f, "/child::a | 1").is_err());
+        assert!(eval(&mut f, "'a' except /child::a").is_err());
+    }
+
+    #[test]
+    fn explain_plan() {
+        let e = CompiledXPath::new("child::a[2]/descendant::b[attribute::id = 'x'][last()]")
+            .expect("unable to parse expression");
+        assert_eq!(e.constructors().len(), 1);
+        assert_eq!(
+            e.explain(),
+            "expression child::a[2]/descendant::b[attribute::id = 'x'][last()]
+  path of 2 steps, each applied to every item selected by the previous step
+    step child::a: scan the children
+      positional filter: keep the item at position 2
+    step descendant::b: look up the attribute value in the indexes of the tree if the document is indexed, otherwise scan the subtree
+      filter: evaluated for each selected item
+        general comparison =: every pair of items is compared
+          step attribute::id: scan the attributes
+          literal \"x\"
+      positional filter: keep the last item
+"
+        );
+        assert!(CompiledXPath::new("child::").is_err());
+        // Function calls are resolved when the expression is compiled
+        let e = CompiledXPath::new("count(descendant::*) + nosuch(1)")
+            .expect("unable to parse expression");
+        assert_eq!(
+            e.explain(),
+            "expression count(descendant::*) + nosuch(1)
+  arithmetic
+    function call count() with 1 arguments
+      step descendant::*:*: scan the subtree; cost grows with the size of the document
+    operator +
+    call to undefined function nosuch()
+      literal 1
+"
+        );
+    }
 }
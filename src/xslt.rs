@@ -60,21 +60,109 @@ let seq = ev.evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd
 assert_eq!(seq.to_xml(Some(&f)), "<html><head><title>XSLT in Rust</title></head><body><p>A simple document.</p></body></html>")
 */
 
-use std::convert::TryFrom;
-use std::fs;
-use std::path::Path;
-use url::Url;
-//use reqwest::blocking::get;
+use crate::batch::{transform_all, BatchOptions};
 use crate::evaluate::*;
 use crate::forest::*;
+#[cfg(feature = "async")]
+use crate::item::Sequence;
+use crate::item::{Item, SequenceTrait};
 use crate::output::*;
+use crate::parsexml::XmlStylesheet;
 use crate::qname::*;
+use crate::resolvers::*;
+use crate::sequencetype::SequenceType;
 use crate::value::*;
 use crate::xdmerror::*;
 use crate::xpath::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use url::Url;
 
 const XSLTNS: &str = "http://www.w3.org/1999/XSL/Transform";
 
+// The names of all elements defined by XSLT 3.0. Elements in the XSLT namespace with other names are only allowed in forwards-compatible mode.
+const XSLT_ELEMENTS: &[&str] = &[
+    "accept",
+    "accumulator",
+    "accumulator-rule",
+    "analyze-string",
+    "apply-imports",
+    "apply-templates",
+    "assert",
+    "attribute",
+    "attribute-set",
+    "break",
+    "call-template",
+    "catch",
+    "character-map",
+    "choose",
+    "comment",
+    "context-item",
+    "copy",
+    "copy-of",
+    "decimal-format",
+    "document",
+    "element",
+    "evaluate",
+    "expose",
+    "fallback",
+    "for-each",
+    "for-each-group",
+    "fork",
+    "function",
+    "global-context-item",
+    "if",
+    "import",
+    "import-schema",
+    "include",
+    "iterate",
+    "key",
+    "map",
+    "map-entry",
+    "matching-substring",
+    "merge",
+    "merge-action",
+    "merge-key",
+    "merge-source",
+    "message",
+    "mode",
+    "namespace",
+    "namespace-alias",
+    "next-iteration",
+    "next-match",
+    "non-matching-substring",
+    "number",
+    "on-completion",
+    "on-empty",
+    "on-non-empty",
+    "otherwise",
+    "output",
+    "output-character",
+    "override",
+    "package",
+    "param",
+    "perform-sort",
+    "preserve-space",
+    "processing-instruction",
+    "result-document",
+    "sequence",
+    "sort",
+    "source-document",
+    "strip-space",
+    "stylesheet",
+    "template",
+    "text",
+    "transform",
+    "try",
+    "use-package",
+    "value-of",
+    "variable",
+    "when",
+    "where-populated",
+    "with-param",
+];
+
 /// Compiles a [Tree] into an Evaluator.
 /// NB. Due to whitespace stripping, this is destructive of the stylesheet.
 pub fn from_document(
@@ -82,6 +170,515 @@ pub fn from_document(
     styledoc: TreeIndex,
     sc: &mut StaticContext,
     b: Option<Url>,
+) -> Result<Evaluator, Error> {
+    from_document_with_resolver(f, styledoc, sc, b, &DefaultResolver::new())
+}
+
+/// Compiles a [Tree] into an Evaluator, fetching included and imported modules with an [AsyncResolver].
+/// All modules, including those that are included or imported by other modules, are retrieved before compilation begins, so the calling thread is not blocked waiting for I/O.
+/// NB. Due to whitespace stripping, this is destructive of the stylesheet.
+#[cfg(feature = "async")]
+pub async fn from_document_async<R: AsyncResolver>(
+    f: &mut Forest,
+    styledoc: TreeIndex,
+    sc: &mut StaticContext,
+    b: Option<Url>,
+    r: &R,
+) -> Result<Evaluator, Error> {
+    let modules = Prefetched::new();
+    let mut pending = module_urls(f, styledoc, b.as_ref())?;
+    // The modules are parsed in a separate forest to find the modules that they include or import
+    let mut scratch = Forest::new();
+    while let Some(u) = pending.pop() {
+        if modules.contains(&u) {
+            continue;
+        }
+        modules.fetch(&u, r).await;
+        // A module that can't be fetched or parsed is reported when the stylesheet is compiled
+        if let Ok(t) = modules
+            .resolve(&u)
+            .and_then(|xml| scratch.grow_tree(xml.as_str().trim()))
+        {
+            pending.append(&mut module_urls(&scratch, t, Some(&u))?)
+        }
+    }
+    from_document_with_resolver(f, styledoc, sc, b, &modules)
+}
+
+/// Transform a source document, starting with the template for its document node, fetching the resources used by the document() and unparsed-text() functions with an [AsyncResolver]. See [Evaluator::evaluate_async].
+#[cfg(feature = "async")]
+pub async fn transform_async<R: AsyncResolver>(
+    ev: &mut Evaluator,
+    f: &mut Forest,
+    src: TreeIndex,
+    r: &R,
+) -> Result<Sequence, Error> {
+    let isrc = Rc::new(Item::Node(
+        f.get_ref(src)
+            .ok_or(Error::new(
+                ErrorKind::Unknown,
+                String::from("unable to find tree"),
+            ))?
+            .get_doc_node(),
+    ));
+    let rd = f.plant_tree();
+    let t = ev.find_match(&isrc, f, src, rd, None)?;
+    ev.evaluate_async(Some(vec![Rc::clone(&isrc)]), Some(0), &t, f, src, rd, r)
+        .await
+}
+
+// Find the URLs of the modules included or imported by a stylesheet module, resolved against the module's base URL
+#[cfg(feature = "async")]
+fn module_urls(f: &Forest, styledoc: TreeIndex, b: Option<&Url>) -> Result<Vec<Url>, Error> {
+    let mut result = vec![];
+    let stylenode = match f
+        .get_ref(styledoc)
+        .ok_or(Error::new(
+            ErrorKind::TypeError,
+            String::from("stylesheet document not found"),
+        ))?
+        .get_doc_node()
+        .get_first_element(f)
+    {
+        Some(s) => s,
+        None => return Ok(result),
+    };
+    let mut cit = stylenode.child_iter();
+    while let Some(c) = cit.next(f) {
+        if c.is_element(f)
+            && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
+            && (c.to_name(f).get_localname() == "include"
+                || c.to_name(f).get_localname() == "import")
+        {
+            if let Some(h) = c.get_attribute(f, &QualifiedName::new(None, None, "href".to_string()))
+            {
+                result.push(resolve_href(b, h.to_string(f).as_str())?)
+            }
+        }
+    }
+    Ok(result)
+}
+
+// Incorporate the modules that are included or imported by a stylesheet module.
+// The href of an xsl:include or xsl:import is resolved against the base URL of the module that contains it. Each module is fetched, its own includes and imports are incorporated, and then its top-level elements replace the xsl:include or xsl:import element.
+// The modules that are being incorporated are active, so that a module that includes or imports itself is detected.
+fn incorporate_modules(
+    f: &mut Forest,
+    styledoc: TreeIndex,
+    stylenode: Node,
+    base: Option<&Url>,
+    resolver: &dyn Resolver,
+    active: &mut Vec<Url>,
+) -> Result<(), Error> {
+    // Iterate over children, looking for includes
+    // * resolve href
+    // * fetch document
+    // * parse XML
+    // * replace xsl:include element with content
+    let mut incit = stylenode.child_iter();
+    while let Some(c) = incit.next(f) {
+        if c.is_element(f)
+            && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
+            && c.to_name(f).get_localname() == "include"
+        {
+            match c.get_attribute(f, &QualifiedName::new(None, None, "href".to_string())) {
+                Some(h) => {
+                    let url = resolve_href(base, h.to_string(f).as_str())?;
+                    let moddoc = load_module(f, &url, resolver, active)?;
+                    // Copy each top-level element of the module to the main stylesheet,
+                    // inserting before the xsl:include node
+                    let mut modit = moddoc.child_iter();
+                    while let Some(mc) = modit.next(f) {
+                        let newnode = copy_from_module(f, mc, moddoc, styledoc)?;
+                        c.insert_before(f, newnode)?;
+                    }
+                    // Remove the xsl:include element node
+                    c.remove(f)?;
+                }
+                None => {
+                    return Result::Err(Error {
+                        kind: ErrorKind::TypeError,
+                        message: "include does not have a href attribute".to_string(),
+                    })
+                }
+            }
+        }
+    }
+
+    // Iterate over children, looking for imports
+    // * resolve href
+    // * fetch document
+    // * parse XML
+    // * replace xsl:import element with content
+    let mut imcit = stylenode.child_iter();
+    while let Some(c) = imcit.next(f) {
+        if c.is_element(f)
+            && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
+            && c.to_name(f).get_localname() == "import"
+        {
+            match c.get_attribute(f, &QualifiedName::new(None, None, "href".to_string())) {
+                Some(h) => {
+                    let url = resolve_href(base, h.to_string(f).as_str())?;
+                    let moddoc = load_module(f, &url, resolver, active)?;
+                    // Copy each top-level element of the module to the main stylesheet,
+                    // inserting before the xsl:include node
+                    let mut modit = moddoc.child_iter();
+                    while let Some(mc) = modit.next(f) {
+                        if mc.node_type(f) == NodeType::Element {
+                            // Add the import precedence attribute
+                            let newnode = copy_from_module(f, mc, moddoc, styledoc)?;
+                            let newat = f.get_ref_mut(styledoc).unwrap().new_attribute(
+                                QualifiedName::new(
+                                    Some(String::from("http://github.com/ballsteve/xrust")),
+                                    None,
+                                    String::from("import"),
+                                ),
+                                Value::from(1),
+                            )?;
+                            newnode.add_attribute(f, newat)?;
+                            c.insert_before(f, newnode)?;
+                        } else {
+                            let newnode = copy_from_module(f, mc, moddoc, styledoc)?;
+                            c.insert_before(f, newnode)?;
+                        }
+                    }
+                    // Remove the xsl:import element node
+                    c.remove(f)?;
+                }
+                None => {
+                    return Result::Err(Error {
+                        kind: ErrorKind::TypeError,
+                        message: "include does not have a href attribute".to_string(),
+                    })
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Copy a top-level node of a stylesheet module to the main stylesheet.
+// An element keeps its namespace declarations, and is given those of the module's document element that it is taken out of, so that the names in its expressions resolve as they did in the module.
+fn copy_from_module(
+    f: &mut Forest,
+    mc: Node,
+    moddoc: Node,
+    styledoc: TreeIndex,
+) -> Result<Node, Error> {
+    let newnode = mc.deep_copy_with_user_data(f, Some(styledoc))?;
+    if newnode.node_type(f) == NodeType::Element && !moddoc.namespace_declarations(f).is_empty() {
+        let mut decls = newnode.namespace_declarations(f).to_vec();
+        decls.extend_from_slice(moddoc.namespace_declarations(f));
+        newnode.set_user_data(f, NamespaceDeclarations(decls))?
+    }
+    Ok(newnode)
+}
+
+// Fetch and parse a stylesheet module, and incorporate the modules that it includes or imports. Returns the module's document element.
+fn load_module(
+    f: &mut Forest,
+    url: &Url,
+    resolver: &dyn Resolver,
+    active: &mut Vec<Url>,
+) -> Result<Node, Error> {
+    if active.contains(url) {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            format!(
+                "XTSE0180: stylesheet module \"{}\" includes or imports itself",
+                url
+            ),
+        ));
+    }
+    let xml = resolver.resolve(url)?;
+    let module = f.grow_tree(xml.as_str().trim())?;
+    // TODO: check that the module is a valid XSLT stylesheet, etc
+    let moddoc = f
+        .get_ref(module)
+        .unwrap()
+        .get_doc_node()
+        .get_first_element(f)
+        .ok_or(Error::new(
+            ErrorKind::TypeError,
+            format!("module \"{}\" does not have a document element", url),
+        ))?;
+    active.push(url.clone());
+    let r = incorporate_modules(f, module, moddoc, Some(url), resolver, active);
+    active.pop();
+    r.map(|_| moddoc)
+}
+
+// Merge the xsl:output declarations that have the same name. XSLT 26.1.
+// A declaration with higher import precedence overrides one with lower precedence. It is an error for declarations with the same precedence to give different values for the same parameter.
+fn merge_outputs(f: &Forest, stylenode: Node) -> Result<Vec<OutputDefinition>, Error> {
+    let precedence = QualifiedName::new(
+        Some(String::from("http://github.com/ballsteve/xrust")),
+        None,
+        String::from("import"),
+    );
+    // For each name, the value and import precedence of each parameter
+    type Parameters = HashMap<String, (String, usize)>;
+    let mut outputs: Vec<(Option<QualifiedName>, Parameters)> = vec![];
+    let mut serit = stylenode.child_iter();
+    while let Some(c) = serit.next(f) {
+        if !(c.is_element(f)
+            && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
+            && c.to_name(f).get_localname() == "output")
+        {
+            continue;
+        }
+        let import = match c.get_attribute(f, &precedence) {
+            Some(im) => im.to_value(f).to_int()? as usize,
+            None => 0,
+        };
+        let name = match c.get_attribute(f, &QualifiedName::new(None, None, "name".to_string())) {
+            Some(n) => Some(resolve_qname(f, c, n.to_string(f).as_str())?),
+            None => None,
+        };
+        let params = match outputs.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, p)) => p,
+            None => {
+                outputs.push((name.clone(), HashMap::new()));
+                &mut outputs.last_mut().unwrap().1
+            }
+        };
+        let mut ait = c.attribute_iter(f);
+        while let Some(a) = ait.next() {
+            let an = a.to_name(f);
+            if an.get_nsuri().is_some() || an.get_localname() == "name" {
+                continue;
+            }
+            let v = a.to_string(f);
+            match params.get(&an.get_localname()) {
+                // A lower number is a higher import precedence
+                Some((_, p)) if *p < import => {}
+                Some((w, p)) if *p == import && *w != v => {
+                    return Result::Err(Error::new(
+                        ErrorKind::StaticData,
+                        format!(
+                            "conflicting values for serialization parameter \"{}\"",
+                            an.get_localname()
+                        ),
+                    ))
+                }
+                _ => {
+                    params.insert(an.get_localname(), (v, import));
+                }
+            }
+        }
+    }
+    let mut result = vec![];
+    for (name, params) in outputs {
+        let mut b = OutputDefinitionBuilder::new();
+        if let Some(n) = name {
+            b = b.name(n)
+        }
+        for (k, (v, _)) in params {
+            b = b.parameter(k.as_str(), v.as_str())?
+        }
+        result.push(b.build()?)
+    }
+    Ok(result)
+}
+
+// Resolve a lexical QName in the value of an attribute of a stylesheet element, such as the name of an output definition or a key, using the namespaces that are in scope for the element.
+// An unprefixed name is in no namespace.
+fn resolve_qname(f: &Forest, n: Node, name: &str) -> Result<QualifiedName, Error> {
+    let name = name.trim();
+    match name.split_once(':') {
+        Some((p, l)) => match n.in_scope_namespaces(f).get(p) {
+            Some(u) => Ok(QualifiedName::new(
+                Some(u.clone()),
+                Some(p.to_string()),
+                l.to_string(),
+            )),
+            None => Result::Err(Error::new(
+                ErrorKind::StaticNamespace,
+                format!("XTSE0280: prefix \"{}\" is not declared", p),
+            )),
+        },
+        None => Ok(QualifiedName::new(None, None, name.to_string())),
+    }
+}
+
+// XTSE3120: xsl:break and xsl:next-iteration may only appear in tail position within xsl:iterate.
+// That is, each must be the last instruction of the iterate's sequence constructor, or the last instruction of a branch of an xsl:if, xsl:choose or xsl:try that is itself in tail position.
+fn check_tail_position(f: &Forest, stylenode: Node) -> Result<(), Error> {
+    let xslt_name = |n: Node| {
+        if n.is_element(f) && n.to_name(f).get_nsuri_ref() == Some(XSLTNS) {
+            Some(n.to_name(f).get_localname())
+        } else {
+            None
+        }
+    };
+    // Instructions that follow in the same sequence constructor. Comments and PIs are not instructions, and the iterate's on-completion and the catch clauses of a try are not part of the sequence constructor.
+    let is_last = |n: Node| {
+        let mut sit = n.next_iter(f);
+        while let Some(s) = sit.next(f) {
+            match s.node_type(f) {
+                NodeType::Comment | NodeType::ProcessingInstruction => {}
+                NodeType::Element
+                    if matches!(
+                        xslt_name(s).as_deref(),
+                        Some("on-completion") | Some("catch") | Some("fallback")
+                    ) => {}
+                _ => return false,
+            }
+        }
+        true
+    };
+    let mut it = stylenode.descend_iter(f);
+    while let Some(n) = it.next(f) {
+        let name = match xslt_name(n).as_deref() {
+            Some("break") => "break",
+            Some("next-iteration") => "next-iteration",
+            _ => continue,
+        };
+        let mut e = n;
+        let ok = loop {
+            if !is_last(e) {
+                break false;
+            }
+            let p = match e.parent(f) {
+                Some(p) => p,
+                None => break false,
+            };
+            match xslt_name(p).as_deref() {
+                Some("iterate") => break true,
+                Some("if") | Some("try") => e = p,
+                // The branches of a choose, and the catch clauses of a try, need not be last
+                Some("when") | Some("otherwise") | Some("catch") => match p.parent(f) {
+                    Some(c) => e = c,
+                    None => break false,
+                },
+                _ => break false,
+            }
+        };
+        if !ok {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                format!(
+                    "XTSE3120: xsl:{} is not in tail position within xsl:iterate",
+                    name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Find the stylesheet associated with a source document by an xml-stylesheet processing instruction.
+///
+/// Returns the URL of the first XSLT stylesheet that is not an alternate stylesheet, resolved against the base URL of the source document.
+pub fn associated_stylesheet(
+    f: &Forest,
+    src: TreeIndex,
+    base: Option<&Url>,
+) -> Result<Option<Url>, Error> {
+    let d = f
+        .get_ref(src)
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("unable to find tree"),
+        ))?
+        .get_doc_node();
+    let mut cit = d.child_iter();
+    while let Some(c) = cit.next(f) {
+        if c.node_type(f) == NodeType::ProcessingInstruction
+            && c.to_name(f).get_localname() == "xml-stylesheet"
+        {
+            let s = XmlStylesheet::parse(c.to_string(f).as_str())?;
+            if s.is_xslt() && !s.alternate {
+                return resolve_href(base, s.href.as_str()).map(Some);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Transform a record-oriented document using several threads.
+///
+/// The element children of the document element are the records. Each record is transformed as if it were the document element of its own document, and the results are serialised and concatenated in document order. The document element itself, and any other children of it, do not appear in the result.
+///
+/// This is only correct for stylesheets whose templates do not depend on anything outside of the record that is being transformed, such as its ancestors, its siblings or its position. Trees cannot be shared between threads, so each thread parses and compiles its own copy of the stylesheet and parses the records that it is given.
+pub fn transform_parallel(style: &str, src: &str, threads: usize) -> Result<String, Error> {
+    transform_parallel_with_options(
+        style,
+        src,
+        &BatchOptions {
+            threads,
+            ..BatchOptions::default()
+        },
+    )
+}
+
+/// Transform a record-oriented document using several threads, as [transform_parallel] does, with the given options for the threads and the evaluators.
+///
+/// The records are always given in document order, and the transformation stops at the first record that fails, so the ordered and stop_on_error options are ignored.
+pub fn transform_parallel_with_options(
+    style: &str,
+    src: &str,
+    options: &BatchOptions,
+) -> Result<String, Error> {
+    let mut f = Forest::new();
+    let t = f.grow_tree(src)?;
+    let mut records = vec![];
+    if let Some(e) = f.get_ref(t).unwrap().get_doc_node().get_first_element(&f) {
+        let mut cit = e.child_iter();
+        while let Some(c) = cit.next(&f) {
+            if c.node_type(&f) == NodeType::Element {
+                records.push(c.to_xml(&f))
+            }
+        }
+    }
+    let options = BatchOptions {
+        ordered: true,
+        stop_on_error: true,
+        ..options.clone()
+    };
+    let mut results = vec![];
+    let mut failed = None;
+    transform_all(
+        records,
+        style,
+        &options,
+        &mut |_: usize, r: Result<String, Error>| match r {
+            Ok(s) => results.push(s),
+            Err(e) => {
+                failed.get_or_insert(e);
+            }
+        },
+    )?;
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(results.concat()),
+    }
+}
+
+// Parse and transform a document, starting with the template for the document node, and serialise the result
+#[cfg_attr(feature = "tracing", tracing::instrument(name = "execute", skip_all))]
+pub(crate) fn transform_document(
+    ev: &Evaluator,
+    f: &mut Forest,
+    xml: &str,
+) -> Result<String, Error> {
+    let src = f.grow_tree(xml)?;
+    let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+    let rd = f.plant_tree();
+    let t = ev.find_match(&isrc, f, src, rd, None)?;
+    let seq = ev.evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, f, src, rd)?;
+    Ok(seq.to_xml(Some(f)))
+}
+
+/// Compiles a [Tree] into an Evaluator, using the given [Resolver] to fetch included and imported modules.
+/// NB. Due to whitespace stripping, this is destructive of the stylesheet.
+#[cfg_attr(feature = "tracing", tracing::instrument(name = "compile", skip_all))]
+pub fn from_document_with_resolver(
+    f: &mut Forest,
+    styledoc: TreeIndex,
+    sc: &mut StaticContext,
+    b: Option<Url>,
+    resolver: &dyn Resolver,
 ) -> Result<Evaluator, Error> {
     let mut ev = Evaluator::new();
     if b.is_some() {
@@ -185,237 +782,57 @@ pub fn from_document(
     let bi3bod = vec![Constructor::ContextItem];
     ev.add_builtin_template(bi3pat, bi3bod, None, -1.0, 0);
 
-    // Setup the serialization of the primary result document
-    let mut serit = stylenode.child_iter();
-    loop {
-        match serit.next(f) {
-            Some(c) => {
-                if c.is_element(f)
-                    && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
-                    && c.to_name(f).get_localname() == "output"
-                {
-                    match c.get_attribute(f, &QualifiedName::new(None, None, "indent".to_string()))
-                    {
-                        Some(i) => {
-                            let b: bool = match i.to_string(f).as_str() {
-                                "yes" | "true" | "1" => true,
-                                _ => false,
-                            };
-
-                            let mut od = OutputDefinition::new();
-                            od.set_indent(b);
-                            ev.set_output_definition(od);
-                        }
-                        None => {}
-                    }
-                }
-            }
-            None => break,
+    // Replace xsl:include and xsl:import elements with the content of the modules
+    let mut active: Vec<Url> = ev.baseurl().into_iter().collect();
+    let base = ev.baseurl();
+    incorporate_modules(f, styledoc, stylenode, base.as_ref(), resolver, &mut active)?;
+    check_tail_position(f, stylenode)?;
+
+    // Setup the serialization of the primary result document, and named output definitions
+    for od in merge_outputs(f, stylenode)? {
+        match od.get_name() {
+            Some(_) => ev.add_named_output_definition(od),
+            None => ev.set_output_definition(od),
         }
     }
 
-    // Iterate over children, looking for includes
-    // * resolve href
-    // * fetch document
-    // * parse XML
-    // * replace xsl:include element with content
-    let mut incit = stylenode.child_iter();
-    loop {
-        match incit.next(f) {
-            Some(c) => {
-                if c.is_element(f)
-                    && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
-                    && c.to_name(f).get_localname() == "include"
-                {
-                    match c.get_attribute(f, &QualifiedName::new(None, None, "href".to_string())) {
-                        Some(h) => {
-                            let url = match ev.baseurl().map_or_else(
-                                || Url::parse(h.to_string(f).as_str()),
-                                |base| base.join(h.to_string(f).as_str()),
-                            ) {
-                                Ok(u) => u,
-                                Err(_) => {
-                                    return Result::Err(Error {
-                                        kind: ErrorKind::Unknown,
-                                        message: format!(
-                                            "unable to parse href URL \"{}\" baseurl \"{}\"",
-                                            h.to_string(f),
-                                            ev.baseurl().map_or(String::from("--no base--"), |b| b
-                                                .to_string())
-                                        ),
-                                    })
-                                }
-                            };
-                            // TODO: make a function to resolve http: vs file: scheme
-                            let xml = match url.scheme() {
-                                "http" => reqwest::blocking::get(url.to_string())
-                                    .map_err(|_| Error {
-                                        kind: ErrorKind::Unknown,
-                                        message: format!(
-                                            "unable to fetch href URL \"{}\"",
-                                            url.to_string()
-                                        ),
-                                    })?
-                                    .text()
-                                    .map_err(|_| Error {
-                                        kind: ErrorKind::Unknown,
-                                        message: "unable to extract module data".to_string(),
-                                    })?,
-                                "file" => fs::read_to_string(Path::new(url.path()))
-                                    .map_err(|er| Error::new(ErrorKind::Unknown, er.to_string()))?,
-                                _ => {
-                                    return Result::Err(Error::new(
-                                        ErrorKind::Unknown,
-                                        format!("unable to fetch URL \"{}\"", url.to_string()),
-                                    ))
-                                }
-                            };
-                            let module = f.grow_tree(xml.as_str().trim())?;
-                            // TODO: check that the module is a valid XSLT stylesheet, etc
-                            // Copy each top-level element of the module to the main stylesheet,
-                            // inserting before the xsl:include node
-                            // TODO: Don't Panic
-                            let moddoc = f
-                                .get_ref(module)
-                                .unwrap()
-                                .get_doc_node()
-                                .get_first_element(f)
-                                .unwrap();
-                            let mut modit = moddoc.child_iter();
-                            loop {
-                                match modit.next(f) {
-                                    Some(mc) => {
-                                        c.insert_before(f, mc)?;
-                                    }
-                                    None => break,
-                                }
-                            }
-                            // Remove the xsl:include element node
-                            c.remove(f)?;
-                        }
-                        None => {
-                            return Result::Err(Error {
-                                kind: ErrorKind::TypeError,
-                                message: "include does not have a href attribute".to_string(),
-                            })
-                        }
-                    }
-                }
-            }
-            None => break,
+    // Declare keys. The value of a key is given by either its use attribute or its content.
+    let mut keyit = stylenode.child_iter();
+    while let Some(c) = keyit.next(f) {
+        if !(c.is_element(f)
+            && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
+            && c.to_name(f).get_localname() == "key")
+        {
+            continue;
         }
-    }
-
-    // Iterate over children, looking for imports
-    // * resolve href
-    // * fetch document
-    // * parse XML
-    // * replace xsl:import element with content
-    let mut imcit = stylenode.child_iter();
-    loop {
-        match imcit.next(f) {
-            Some(c) => {
-                if c.is_element(f)
-                    && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
-                    && c.to_name(f).get_localname() == "import"
-                {
-                    match c.get_attribute(f, &QualifiedName::new(None, None, "href".to_string())) {
-                        Some(h) => {
-                            let url = match ev.baseurl().map_or_else(
-                                || Url::parse(h.to_string(f).as_str()),
-                                |base| base.join(h.to_string(f).as_str()),
-                            ) {
-                                Ok(u) => u,
-                                Err(_) => {
-                                    return Result::Err(Error {
-                                        kind: ErrorKind::Unknown,
-                                        message: format!(
-                                            "unable to parse href URL \"{}\" baseurl \"{}\"",
-                                            h.to_string(f),
-                                            ev.baseurl().map_or(String::from("--no base--"), |b| b
-                                                .to_string())
-                                        ),
-                                    })
-                                }
-                            };
-                            // TODO: make a function to resolve http: vs file: scheme
-                            let xml = match url.scheme() {
-                                "http" => reqwest::blocking::get(url.to_string())
-                                    .map_err(|_| Error {
-                                        kind: ErrorKind::Unknown,
-                                        message: format!(
-                                            "unable to fetch href URL \"{}\"",
-                                            url.to_string()
-                                        ),
-                                    })?
-                                    .text()
-                                    .map_err(|_| Error {
-                                        kind: ErrorKind::Unknown,
-                                        message: "unable to extract module data".to_string(),
-                                    })?,
-                                "file" => fs::read_to_string(Path::new(url.path()))
-                                    .map_err(|er| Error::new(ErrorKind::Unknown, er.to_string()))?,
-                                _ => {
-                                    return Result::Err(Error::new(
-                                        ErrorKind::Unknown,
-                                        format!("unable to fetch URL \"{}\"", url.to_string()),
-                                    ))
-                                }
-                            };
-                            let module = f.grow_tree(xml.as_str().trim())?;
-                            // TODO: check that the module is a valid XSLT stylesheet, etc
-                            // Copy each top-level element of the module to the main stylesheet,
-                            // inserting before the xsl:include node
-                            // TODO: Don't Panic
-                            let moddoc = f
-                                .get_ref(module)
-                                .unwrap()
-                                .get_doc_node()
-                                .get_first_element(f)
-                                .unwrap();
-                            let mut modit = moddoc.child_iter();
-                            loop {
-                                match modit.next(f) {
-                                    Some(mc) => {
-                                        if mc.node_type(f) == NodeType::Element {
-                                            // Add the import precedence attribute
-                                            let newnode = mc.deep_copy(f, Some(styledoc))?;
-                                            let newat =
-                                                f.get_ref_mut(styledoc).unwrap().new_attribute(
-                                                    QualifiedName::new(
-                                                        Some(String::from(
-                                                            "http://github.com/ballsteve/xrust",
-                                                        )),
-                                                        None,
-                                                        String::from("import"),
-                                                    ),
-                                                    Value::from(1),
-                                                )?;
-                                            newnode.add_attribute(f, newat)?;
-                                            c.insert_before(f, newnode)?;
-                                        } else {
-                                            let newnode = mc.deep_copy(f, Some(styledoc))?;
-                                            c.insert_before(f, newnode)?;
-                                        }
-                                    }
-                                    None => break,
-                                }
-                            }
-                            // Remove the xsl:import element node
-                            c.remove(f)?;
-                        }
-                        None => {
-                            return Result::Err(Error {
-                                kind: ErrorKind::TypeError,
-                                message: "include does not have a href attribute".to_string(),
-                            })
-                        }
-                    }
+        let attr = |a: &str| c.get_attribute(f, &QualifiedName::new(None, None, a.to_string()));
+        let (name, m) = match (attr("name"), attr("match")) {
+            (Some(name), Some(m)) => (name.to_string(f), m.to_string(f)),
+            _ => {
+                return Result::Err(Error::new(
+                    ErrorKind::TypeError,
+                    String::from("key must have name and match attributes"),
+                ))
+            }
+        };
+        let mut pat = to_pattern(parse(&m)?)?;
+        let mut key_use = match attr("use") {
+            Some(u) => parse(&u.to_string(f))?,
+            None => {
+                let mut body = vec![];
+                let mut cit = c.child_iter();
+                while let Some(d) = cit.next(f) {
+                    body.push(to_constructor(d, f)?)
                 }
+                body
             }
-            None => break,
-        }
+        };
+        sc.set_namespaces(c.in_scope_namespaces(f));
+        sc.static_analysis(&mut pat);
+        sc.static_analysis(&mut key_use);
+        ev.add_key(resolve_qname(f, c, name.as_str())?, pat, key_use);
     }
+    ev.set_available_functions(sc);
 
     // Iterate over children, looking for templates
     // * compile match pattern
@@ -442,66 +859,17 @@ pub fn from_document(
                                     None => break,
                                 }
                             }
+                            sc.set_namespaces(c.in_scope_namespaces(f));
                             sc.static_analysis(&mut pat);
                             sc.static_analysis(&mut body);
                             // Determine the priority of the template
-                            let prio;
-                            match c.get_attribute(
-                                f,
-                                &QualifiedName::new(None, None, "priority".to_string()),
-                            ) {
-                                Some(pr) => prio = pr.to_string(f).parse::<f64>().unwrap(), // TODO: better error handling
-                                None => {
-                                    // Calculate the default priority
-                                    // TODO: more work to be done interpreting XSLT 6.5
-                                    if pat.len() <= 1 {
-                                        match &pat[0] {
-                                            Constructor::Root => prio = -0.5,
-                                            Constructor::Path(_) => prio = -0.5,
-                                            Constructor::Step(nm, _pred) => match &nm.nodetest {
-                                                NodeTest::Name(nt) => {
-                                                    match (nt.ns.as_ref(), nt.name.as_ref()) {
-                                                        (
-                                                            Some(WildcardOrName::Wildcard),
-                                                            Some(WildcardOrName::Wildcard),
-                                                        ) => prio = -0.5,
-                                                        (
-                                                            Some(WildcardOrName::Wildcard),
-                                                            Some(WildcardOrName::Name(_)),
-                                                        )
-                                                        | (
-                                                            Some(WildcardOrName::Name(_)),
-                                                            Some(WildcardOrName::Wildcard),
-                                                        ) => prio = -0.25,
-                                                        (None, Some(WildcardOrName::Wildcard)) => {
-                                                            prio = -0.25
-                                                        }
-                                                        (
-                                                            Some(WildcardOrName::Name(_)),
-                                                            Some(WildcardOrName::Name(_)),
-                                                        ) => prio = 0.0,
-                                                        (None, Some(WildcardOrName::Name(_))) => {
-                                                            prio = 0.0
-                                                        }
-                                                        _ => prio = 0.5,
-                                                    }
-                                                }
-                                                NodeTest::Kind(kt) => match kt {
-                                                    KindTest::DocumentTest
-                                                    | KindTest::ElementTest
-                                                    | KindTest::AttributeTest => prio = -0.5,
-                                                    _ => prio = 0.5,
-                                                },
-                                            },
-                                            _ => prio = 0.5,
-                                        }
-                                    } else {
-                                        // TODO: calculate the priority of each branch of the pattern
-                                        prio = 0.5
-                                    }
-                                }
-                            }
-                            // Set the import precedence
+                            let prio = c
+                                .get_attribute(
+                                    f,
+                                    &QualifiedName::new(None, None, "priority".to_string()),
+                                )
+                                .map(|pr| pr.to_string(f).parse::<f64>().unwrap()); // TODO: better error handling
+                                                                                    // Set the import precedence
                             let mut import: usize = 0;
                             match c.get_attribute(
                                 f,
@@ -514,7 +882,32 @@ pub fn from_document(
                                 Some(im) => import = im.to_value(f).to_int()? as usize,
                                 None => {}
                             }
-                            ev.add_template(pat, body, None, prio, import);
+                            match (&pat[..], prio) {
+                                // A template rule with a union pattern and no priority is treated as a template rule for each alternative, with its own default priority
+                                ([Constructor::SetOperation(SetOperator::Union, alts)], None) => {
+                                    for a in alts {
+                                        ev.add_template_from_source(
+                                            a.clone(),
+                                            body.clone(),
+                                            None,
+                                            default_priority(a),
+                                            import,
+                                            n.clone(),
+                                        )
+                                    }
+                                }
+                                _ => {
+                                    let prio = prio.unwrap_or_else(|| default_priority(&pat));
+                                    ev.add_template_from_source(
+                                        pat,
+                                        body,
+                                        None,
+                                        prio,
+                                        import,
+                                        n.clone(),
+                                    )
+                                }
+                            }
                         }
                         None => {
                             return Result::Err(Error {
@@ -532,10 +925,43 @@ pub fn from_document(
     Ok(ev)
 }
 
+// The default priority of a template rule. See XSLT 6.5.
+// TODO: more work to be done interpreting XSLT 6.5
+fn default_priority(pat: &[Constructor]) -> f64 {
+    if pat.len() <= 1 {
+        match &pat[0] {
+            Constructor::Root => -0.5,
+            // A single step has the priority of its node test
+            Constructor::Path(p) if p.len() == 1 => default_priority(&p[0]),
+            Constructor::Path(_) => -0.5,
+            Constructor::Step(nm, _pred) => match &nm.nodetest {
+                NodeTest::Name(nt) => match (nt.ns.as_ref(), nt.name.as_ref()) {
+                    (Some(WildcardOrName::Wildcard), Some(WildcardOrName::Wildcard)) => -0.5,
+                    (Some(WildcardOrName::Wildcard), Some(WildcardOrName::Name(_)))
+                    | (Some(WildcardOrName::Name(_)), Some(WildcardOrName::Wildcard)) => -0.25,
+                    (None, Some(WildcardOrName::Wildcard)) => -0.25,
+                    (Some(WildcardOrName::Name(_)), Some(WildcardOrName::Name(_))) => 0.0,
+                    (None, Some(WildcardOrName::Name(_))) => 0.0,
+                    _ => 0.5,
+                },
+                NodeTest::Kind(kt) => match kt {
+                    KindTest::DocumentTest | KindTest::ElementTest | KindTest::AttributeTest => {
+                        -0.5
+                    }
+                    _ => 0.5,
+                },
+            },
+            _ => 0.5,
+        }
+    } else {
+        0.5
+    }
+}
+
 /// Compile a node in a template to a sequence constructor
 fn to_constructor(n: Node, f: &Forest) -> Result<Constructor, Error> {
     match n.node_type(f) {
-        NodeType::Text => Ok(Constructor::Literal(Value::String(n.to_string(f)))),
+        NodeType::Text => to_text(n, f),
         NodeType::Element => {
             match (
                 n.to_name(f).get_nsuri_ref(),
@@ -546,18 +972,10 @@ fn to_constructor(n: Node, f: &Forest) -> Result<Constructor, Error> {
                         f,
                         &QualifiedName::new(None, None, "disable-output-escaping".to_string()),
                     ) {
-                        Some(doe) => match &doe.to_string(f)[..] {
-                            "yes" => Ok(Constructor::Literal(Value::String(n.to_string(f)))),
-                            "no" => {
-                                let text = n
-                                    .to_string(f)
-                                    .replace("&", "&amp;")
-                                    .replace(">", "&gt;")
-                                    .replace("<", "&lt;")
-                                    .replace("'", "&apos;")
-                                    .replace("\"", "&quot;");
-                                Ok(Constructor::Literal(Value::from(text)))
-                            }
+                        // The text is escaped when it is serialised, unless escaping is disabled
+                        Some(doe) => match doe.to_string(f).trim() {
+                            "yes" => Ok(Constructor::UnescapedText(Value::from(n.to_string(f)))),
+                            "no" => text_content(n, f),
                             _ => {
                                 return Result::Err(Error {
                                     kind: ErrorKind::TypeError,
@@ -567,16 +985,7 @@ fn to_constructor(n: Node, f: &Forest) -> Result<Constructor, Error> {
                                 })
                             }
                         },
-                        None => {
-                            let text = n
-                                .to_string(f)
-                                .replace("&", "&amp;")
-                                .replace(">", "&gt;")
-                                .replace("<", "&lt;")
-                                .replace("'", "&apos;")
-                                .replace("\"", "&quot;");
-                            Ok(Constructor::Literal(Value::from(text)))
-                        }
+                        None => text_content(n, f),
                     }
                 }
                 (Some(XSLTNS), "apply-templates") => {
@@ -844,10 +1253,89 @@ fn to_constructor(n: Node, f: &Forest) -> Result<Constructor, Error> {
                         }
                     }
                 }
-                (Some(XSLTNS), "copy") => {
-                    // TODO: handle select attribute
-                    let mut cit = n.child_iter();
-                    let mut body = vec![];
+                (Some(XSLTNS), "iterate") => {
+                    match n.get_attribute(f, &QualifiedName::new(None, None, "select".to_string()))
+                    {
+                        Some(s) => {
+                            let mut params = vec![];
+                            let mut body = vec![];
+                            let mut completion = vec![];
+                            let mut cit = n.child_iter();
+                            while let Some(e) = cit.next(f) {
+                                match (
+                                    e.to_name(f).get_nsuri_ref(),
+                                    e.to_name(f).get_localname().as_str(),
+                                ) {
+                                    (Some(XSLTNS), "param") => params.push(to_param(e, f)?),
+                                    (Some(XSLTNS), "on-completion") => {
+                                        completion = select_or_content(e, f)?
+                                    }
+                                    _ => body.push(to_constructor(e, f)?),
+                                }
+                            }
+                            Ok(Constructor::Iterate(
+                                parse(&s.to_string(f))?,
+                                params,
+                                body,
+                                completion,
+                            ))
+                        }
+                        None => Result::Err(Error::new(
+                            ErrorKind::TypeError,
+                            String::from("missing select attribute"),
+                        )),
+                    }
+                }
+                (Some(XSLTNS), "merge") => {
+                    let mut sources = vec![];
+                    let mut action = None;
+                    let mut cit = n.child_iter();
+                    while let Some(e) = cit.next(f) {
+                        match (
+                            e.node_type(f),
+                            e.to_name(f).get_nsuri_ref(),
+                            e.to_name(f).get_localname().as_str(),
+                        ) {
+                            (NodeType::Element, Some(XSLTNS), "merge-source") => {
+                                sources.push(to_merge_source(e, f)?)
+                            }
+                            (NodeType::Element, Some(XSLTNS), "merge-action") => {
+                                action = Some(select_or_content(e, f)?)
+                            }
+                            (NodeType::Element, _, _) => {
+                                return Result::Err(Error::new(
+                                    ErrorKind::TypeError,
+                                    String::from("invalid element content in merge element"),
+                                ))
+                            }
+                            _ => {}
+                        }
+                    }
+                    match action {
+                        Some(a) if !sources.is_empty() => Ok(Constructor::Merge(sources, a)),
+                        _ => Result::Err(Error::new(
+                            ErrorKind::TypeError,
+                            String::from(
+                                "merge element must have a merge-source and a merge-action",
+                            ),
+                        )),
+                    }
+                }
+                (Some(XSLTNS), "next-iteration") => {
+                    let mut params = vec![];
+                    let mut cit = n.child_iter();
+                    while let Some(e) = cit.next(f) {
+                        if e.node_type(f) == NodeType::Element {
+                            params.push(to_param(e, f)?)
+                        }
+                    }
+                    Ok(Constructor::NextIteration(params))
+                }
+                (Some(XSLTNS), "break") => Ok(Constructor::Break(select_or_content(n, f)?)),
+                (Some(XSLTNS), "copy") => {
+                    // TODO: handle select attribute
+                    let mut cit = n.child_iter();
+                    let mut body = vec![];
                     loop {
                         match cit.next(f) {
                             Some(e) => body.push(to_constructor(e, f)?),
@@ -896,16 +1384,52 @@ fn to_constructor(n: Node, f: &Forest) -> Result<Constructor, Error> {
                         }
                     }
                 }
-                (Some(XSLTNS), u) => Ok(Constructor::NotImplemented(format!(
-                    "unsupported XSL element \"{}\"",
-                    u
-                ))),
+                // xsl:fallback is only evaluated in place of an instruction that is not supported
+                (Some(XSLTNS), "fallback") => Ok(Constructor::Empty),
+                (Some(XSLTNS), u) => {
+                    let known = XSLT_ELEMENTS.contains(&u);
+                    if !known && !forwards_compatible(n, f) {
+                        return Result::Err(Error::new(
+                            ErrorKind::StaticUndefined,
+                            format!("unknown XSL element \"{}\"", u),
+                        ));
+                    }
+                    // Use the content of the xsl:fallback children instead, if there are any
+                    let mut fallback = None;
+                    let mut cit = n.child_iter();
+                    while let Some(c) = cit.next(f) {
+                        if c.is_element(f)
+                            && c.to_name(f).get_nsuri_ref() == Some(XSLTNS)
+                            && c.to_name(f).get_localname() == "fallback"
+                        {
+                            let body: &mut Vec<Constructor> = fallback.get_or_insert(vec![]);
+                            let mut fit = c.child_iter();
+                            while let Some(d) = fit.next(f) {
+                                body.push(to_constructor(d, f)?)
+                            }
+                        }
+                    }
+                    match fallback {
+                        Some(body) => Ok(Constructor::Switch(vec![], body)),
+                        None if known => Ok(Constructor::NotImplemented(format!(
+                            "unsupported XSL element \"{}\"",
+                            u
+                        ))),
+                        // An unknown instruction is only an error if it is evaluated
+                        None => Ok(Constructor::NotImplemented(format!(
+                            "unknown XSL element \"{}\" has no fallback",
+                            u
+                        ))),
+                    }
+                }
                 (_, a) => {
                     // TODO: Handle qualified element name
                     let mut content = vec![];
                     let mut ait = n.attribute_iter(f);
                     loop {
                         match ait.next() {
+                            // Attributes in the XSLT namespace, such as xsl:version, are not copied
+                            Some(e) if e.to_name(f).get_nsuri_ref() == Some(XSLTNS) => {}
                             Some(e) => content.push(to_constructor(e, f)?),
                             None => break,
                         }
@@ -928,7 +1452,7 @@ fn to_constructor(n: Node, f: &Forest) -> Result<Constructor, Error> {
             // Get value as a Value
             Ok(Constructor::LiteralAttribute(
                 n.to_name(f),
-                vec![Constructor::Literal(Value::String(n.to_string(f)))],
+                vec![Constructor::Literal(Value::from(n.to_string(f)))],
             ))
         }
         _ => {
@@ -940,6 +1464,239 @@ fn to_constructor(n: Node, f: &Forest) -> Result<Constructor, Error> {
     }
 }
 
+// The content of xsl:text, which may be a text value template
+fn text_content(n: Node, f: &Forest) -> Result<Constructor, Error> {
+    match n.child_iter().next(f) {
+        Some(t) => to_text(t, f),
+        None => Ok(Constructor::Literal(Value::from(""))),
+    }
+}
+
+// Compile a text node in a sequence constructor.
+// When text value templates are enabled, expressions in curly brackets are evaluated, and "{{" and "}}" are literal brackets.
+fn to_text(n: Node, f: &Forest) -> Result<Constructor, Error> {
+    let s = n.to_string(f);
+    if !expand_text(n, f) {
+        return Ok(Constructor::Literal(Value::from(s)));
+    }
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' | '}' if chars.peek().map(|(_, d)| *d) == Some(c) => {
+                chars.next();
+                text.push(c)
+            }
+            '{' => {
+                let end = i
+                    + 1
+                    + expression_end(&s[i + 1..]).ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::StaticSyntax,
+                            format!(
+                                "XTSE0350: unclosed expression in text value template \"{}\"",
+                                s
+                            ),
+                        )
+                    })?;
+                if !text.is_empty() {
+                    parts.push(vec![Constructor::Literal(Value::from(std::mem::take(
+                        &mut text,
+                    )))])
+                }
+                let e = &s[i + 1..end];
+                // An empty expression is allowed, and has no effect
+                if !e.trim().is_empty() {
+                    parts.push(parse(e)?)
+                }
+                while chars.next_if(|(j, _)| *j <= end).is_some() {}
+            }
+            '}' => {
+                return Result::Err(Error::new(
+                    ErrorKind::StaticSyntax,
+                    format!(
+                        "XTSE0370: unescaped \"}}\" in text value template \"{}\"",
+                        s
+                    ),
+                ))
+            }
+            _ => text.push(c),
+        }
+    }
+    if parts.is_empty() {
+        return Ok(Constructor::Literal(Value::from(text)));
+    }
+    if !text.is_empty() {
+        parts.push(vec![Constructor::Literal(Value::from(text))])
+    }
+    Ok(Constructor::ValueTemplate(parts))
+}
+
+// The offset of the "}" that closes an expression in a value template, skipping over string literals and nested curly brackets
+fn expression_end(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') if depth == 0 => return Some(i),
+            (None, '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Whether text in the stylesheet is a text value template (XSLT 5.6.2).
+// This is determined by the expand-text attribute of the nearest XSLT element, or xsl:expand-text of a literal result element, that is an ancestor of the text.
+fn expand_text(n: Node, f: &Forest) -> bool {
+    let mut e = n.parent(f);
+    while let Some(m) = e {
+        let v = if m.to_name(f).get_nsuri_ref() == Some(XSLTNS) {
+            m.get_attribute(
+                f,
+                &QualifiedName::new(None, None, String::from("expand-text")),
+            )
+        } else {
+            m.get_attribute(
+                f,
+                &QualifiedName::new(
+                    Some(String::from(XSLTNS)),
+                    None,
+                    String::from("expand-text"),
+                ),
+            )
+        };
+        if let Some(v) = v {
+            return matches!(v.to_string(f).trim(), "yes" | "true" | "1");
+        }
+        e = m.parent(f)
+    }
+    false
+}
+
+// Whether an element is processed in forwards-compatible mode (XSLT 3.9), i.e. its effective version is greater than 3.0.
+// The effective version is the version attribute of the nearest XSLT element, or xsl:version of a literal result element, that is the element or an ancestor.
+fn forwards_compatible(n: Node, f: &Forest) -> bool {
+    let mut e = Some(n);
+    while let Some(m) = e {
+        let v = if m.to_name(f).get_nsuri_ref() == Some(XSLTNS) {
+            m.get_attribute(f, &QualifiedName::new(None, None, String::from("version")))
+        } else {
+            m.get_attribute(
+                f,
+                &QualifiedName::new(Some(String::from(XSLTNS)), None, String::from("version")),
+            )
+        };
+        if let Some(v) = v {
+            return v.to_string(f).trim().parse::<f64>().is_ok_and(|v| v > 3.0);
+        }
+        e = m.parent(f)
+    }
+    false
+}
+
+// Compile an xsl:merge-source element
+fn to_merge_source(n: Node, f: &Forest) -> Result<MergeSource, Error> {
+    let attr = |a: &str| n.get_attribute(f, &QualifiedName::new(None, None, a.to_string()));
+    if attr("for-each-item").is_some() || attr("for-each-source").is_some() {
+        return Result::Err(Error::new(
+            ErrorKind::NotImplemented,
+            String::from("for-each-item and for-each-source are not supported in merge-source"),
+        ));
+    }
+    let select = match attr("select") {
+        Some(s) => parse(&s.to_string(f))?,
+        None => {
+            return Result::Err(Error::new(
+                ErrorKind::TypeError,
+                String::from("missing select attribute"),
+            ))
+        }
+    };
+    let mut keys = vec![];
+    let mut cit = n.child_iter();
+    while let Some(e) = cit.next(f) {
+        if e.node_type(f) == NodeType::Element {
+            let descending = match e
+                .get_attribute(f, &QualifiedName::new(None, None, "order".to_string()))
+                .map(|o| o.to_string(f))
+                .as_deref()
+            {
+                None | Some("ascending") => false,
+                Some("descending") => true,
+                Some(_) => {
+                    return Result::Err(Error::new(
+                        ErrorKind::TypeError,
+                        String::from("order attribute must be ascending or descending"),
+                    ))
+                }
+            };
+            keys.push(MergeKey {
+                select: select_or_content(e, f)?,
+                descending,
+            })
+        }
+    }
+    if keys.is_empty() {
+        return Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("merge-source element must have a merge-key"),
+        ));
+    }
+    Ok(MergeSource {
+        name: attr("name").map(|a| a.to_string(f)),
+        select,
+        keys,
+    })
+}
+
+// Compile an xsl:param or xsl:with-param element to a variable declaration
+fn to_param(n: Node, f: &Forest) -> Result<Constructor, Error> {
+    match n.get_attribute(f, &QualifiedName::new(None, None, "name".to_string())) {
+        Some(m) => {
+            let mut value = select_or_content(n, f)?;
+            match n.get_attribute(f, &QualifiedName::new(None, None, "as".to_string())) {
+                Some(a) => {
+                    // The value is converted to the declared type. A parameter without a value is the empty sequence.
+                    let st = SequenceType::try_from(a.to_string(f).as_str())?;
+                    value = vec![Constructor::Convert(value, st)]
+                }
+                None => {
+                    if value.is_empty() {
+                        // A parameter without a value is a zero-length string
+                        value.push(Constructor::Literal(Value::from("")))
+                    }
+                }
+            }
+            Ok(Constructor::VariableDeclaration(m.to_string(f), value))
+        }
+        None => Result::Err(Error::new(
+            ErrorKind::TypeError,
+            String::from("missing name attribute"),
+        )),
+    }
+}
+
+// Compile the select attribute of an element, or its content if it has no select attribute
+fn select_or_content(n: Node, f: &Forest) -> Result<Vec<Constructor>, Error> {
+    match n.get_attribute(f, &QualifiedName::new(None, None, "select".to_string())) {
+        Some(s) => parse(&s.to_string(f)),
+        None => {
+            let mut body = vec![];
+            let mut cit = n.child_iter();
+            while let Some(e) = cit.next(f) {
+                body.push(to_constructor(e, f)?)
+            }
+            Ok(body)
+        }
+    }
+}
+
 /// Strip whitespace nodes from a XDM [Tree].
 /// See [XSLT 4.3](https://www.w3.org/TR/2017/REC-xslt-30-20170608/#stylesheet-stripping)
 pub fn strip_whitespace(
@@ -1157,10 +1914,8 @@ fn strip_whitespace_node(
                 }
             }
         }
-        NodeType::Text => {
-            if n.to_string(f).trim().is_empty() && !keep {
-                n.remove(f).expect("unable to remove text node");
-            }
+        NodeType::Text if n.is_whitespace_only(f) && !keep => {
+            n.remove(f).expect("unable to remove text node");
         }
         _ => {}
     }
@@ -1169,8 +1924,8 @@ fn strip_whitespace_node(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::item::*;
-    use std::rc::Rc;
+    use crate::forest::NodeRef;
+    use std::cell::RefCell;
 
     #[test]
     fn xslt_literal_text() {
@@ -1490,4 +2245,1279 @@ mod tests {
             "shallow1 deeper import level shallow2twothreefour"
         )
     }
+
+    struct TestResolver;
+    impl Resolver for TestResolver {
+        fn resolve(&self, url: &Url) -> Result<String, Error> {
+            if url.as_str() == "http://example.org/included.xsl" {
+                Ok(String::from(
+                    "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::Level1'>resolved module</xsl:template>
+</xsl:stylesheet>",
+                ))
+            } else {
+                Result::Err(Error::new(
+                    ErrorKind::Unknown,
+                    format!("no such module \"{}\"", url),
+                ))
+            }
+        }
+    }
+
+    #[test]
+    fn associated() {
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree(
+                r#"<?xml-stylesheet href="style.css" type="text/css"?>
+<?xml-stylesheet href="alt.xsl" type="text/xsl" alternate="yes"?>
+<?xml-stylesheet href="style.xsl" type="text/xsl"?>
+<Test/>"#,
+            )
+            .expect("unable to parse XML");
+        let base = Url::parse("file:///tmp/doc.xml").expect("unable to parse URL");
+        assert_eq!(
+            associated_stylesheet(&f, src, Some(&base))
+                .expect("unable to find stylesheet")
+                .map(|u| u.to_string()),
+            Some(String::from("file:///tmp/style.xsl"))
+        );
+        let none = f.grow_tree("<Test/>").expect("unable to parse XML");
+        assert_eq!(
+            associated_stylesheet(&f, none, Some(&base)).expect("unable to find stylesheet"),
+            None
+        )
+    }
+
+    // Modules that include and import other modules
+    fn nested_modules() -> crate::resolvers::MemoryResolver {
+        let module = |body: &str| {
+            format!(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>{}</xsl:stylesheet>",
+                body
+            )
+        };
+        let mut m = crate::resolvers::MemoryResolver::new();
+        m.insert(
+            "http://example.org/sub/a.xsl",
+            module("<xsl:include href='b.xsl'/><xsl:import href='c.xsl'/><xsl:template match='child::Level1'>A</xsl:template>"),
+        );
+        m.insert(
+            "http://example.org/sub/b.xsl",
+            module("<xsl:template match='child::Level2'>B</xsl:template>"),
+        );
+        m.insert(
+            "http://example.org/sub/c.xsl",
+            module("<xsl:template match='child::Level3'>C</xsl:template>"),
+        );
+        m.insert(
+            "http://example.org/b.xsl",
+            module("<xsl:template match='child::Level2'>wrong module</xsl:template>"),
+        );
+        m.insert(
+            "http://example.org/loop.xsl",
+            module("<xsl:include href='sub/../loop.xsl'/>"),
+        );
+        m
+    }
+    const NESTED_STYLE: &str = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:include href='sub/a.xsl'/>
+  <xsl:template match='child::Test'><xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::text()'><xsl:sequence select='.'/></xsl:template>
+</xsl:stylesheet>";
+
+    #[test]
+    fn include_nested() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test>one<Level1/>two<Level2/>three<Level3/></Test>")
+            .expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+        let style = f.grow_tree(NESTED_STYLE).expect("unable to parse XML");
+        let base = Url::parse("http://example.org/main.xsl").expect("unable to parse URL");
+        let ev = from_document_with_resolver(
+            &mut f,
+            style,
+            &mut sc,
+            Some(base.clone()),
+            &nested_modules(),
+        )
+        .expect("failed to compile stylesheet");
+
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+        // The modules included and imported by sub/a.xsl are relative to it
+        assert_eq!(seq.to_xml(Some(&f)), "oneAtwoBthreeC");
+
+        // A module that includes itself
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'><xsl:include href='loop.xsl'/></xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        match from_document_with_resolver(&mut f, style, &mut sc, Some(base), &nested_modules()) {
+            Err(e) => assert!(e.message.starts_with("XTSE0180")),
+            Ok(_) => panic!("module including itself was compiled"),
+        }
+    }
+
+    #[test]
+    fn include_resolver() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test>one<Level1/>two</Test>")
+            .expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:include href='included.xsl'/>
+  <xsl:template match='child::Test'><xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::text()'><xsl:sequence select='.'/></xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+
+        let ev = from_document_with_resolver(
+            &mut f,
+            style,
+            &mut sc,
+            Some(Url::parse("http://example.org/main.xsl").expect("unable to parse URL")),
+            &TestResolver,
+        )
+        .expect("failed to compile stylesheet");
+
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+
+        assert_eq!(seq.to_xml(Some(&f)), "oneresolved moduletwo")
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn include_async() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test>one<Level1/>two<Level2/>three</Test>")
+            .expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:include href='included.xsl'/>
+  <xsl:template match='child::Test'><xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::Level1'>found Level1 element</xsl:template>
+  <xsl:template match='child::text()'><xsl:sequence select='.'/></xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+
+        let pwd = std::env::current_dir().expect("unable to get current directory");
+        let ev = from_document_async(
+            &mut f,
+            style,
+            &mut sc,
+            Some(
+                Url::from_file_path(pwd.join("tests/xsl/including.xsl"))
+                    .expect("unable to create URL"),
+            ),
+            &AsyncDefaultResolver::new(),
+        )
+        .await
+        .expect("failed to compile stylesheet");
+
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+
+        assert_eq!(
+            seq.to_xml(Some(&f)),
+            "onefound Level1 elementtwofound Level2 elementthree"
+        )
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn include_nested_async() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test>one<Level1/>two<Level2/>three<Level3/></Test>")
+            .expect("unable to parse XML");
+        let style = f.grow_tree(NESTED_STYLE).expect("unable to parse XML");
+        let r = crate::resolvers::AsyncMemoryResolver::new(nested_modules());
+        let mut ev = from_document_async(
+            &mut f,
+            style,
+            &mut sc,
+            Some(Url::parse("http://example.org/main.xsl").expect("unable to parse URL")),
+            &r,
+        )
+        .await
+        .expect("failed to compile stylesheet");
+        for m in ["sub/a.xsl", "sub/b.xsl", "sub/c.xsl"] {
+            assert_eq!(r.fetched(format!("http://example.org/{}", m).as_str()), 1)
+        }
+        assert_eq!(r.fetched("http://example.org/b.xsl"), 0);
+
+        let seq = transform_async(&mut ev, &mut f, src, &r)
+            .await
+            .expect("transformation failed");
+        assert_eq!(seq.to_xml(Some(&f)), "oneAtwoBthreeC")
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn transform_document_async() {
+        let mut m = crate::resolvers::MemoryResolver::new();
+        m.insert(
+            "http://example.org/list.xml",
+            "<list><next>a.xml</next><next>b.xml</next></list>",
+        );
+        m.insert("http://example.org/a.xml", "<a>A</a>");
+        m.insert("http://example.org/b.xml", "<b>B</b>");
+        m.insert("http://example.org/note.txt", "note");
+        let r = crate::resolvers::AsyncMemoryResolver::new(m);
+
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let src = f.grow_tree("<Test/>").expect("unable to parse XML");
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:for-each select='doc(\"list.xml\")/child::list/child::next'><xsl:sequence select='string(doc(string(.)))'/></xsl:for-each><xsl:sequence select='unparsed-text(\"note.txt\")'/><xsl:sequence select='doc-available(\"missing.xml\")'/></xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        let mut ev = from_document(
+            &mut f,
+            style,
+            &mut sc,
+            Some(Url::parse("http://example.org/main.xsl").expect("unable to parse URL")),
+        )
+        .expect("failed to compile stylesheet");
+        let seq = transform_async(&mut ev, &mut f, src, &r)
+            .await
+            .expect("transformation failed");
+        assert_eq!(seq.to_xml(Some(&f)), "ABnotefalse");
+        // Each resource is fetched once, and the evaluator has no resolver afterwards
+        for u in ["list.xml", "a.xml", "b.xml", "note.txt", "missing.xml"] {
+            assert_eq!(r.fetched(format!("http://example.org/{}", u).as_str()), 1)
+        }
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        assert!(ev
+            .evaluate(Some(vec![isrc]), Some(0), &t, &mut f, src, rd)
+            .is_err())
+    }
+
+    #[test]
+    fn xslt_document_function() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test><Level1>one</Level1></Test>")
+            .expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:apply-templates select='child::*'/><xsl:apply-templates select='document(\"other.xml\")/child::*'/></xsl:template>
+  <xsl:template match='child::Test'>main <xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::Other'>other <xsl:sequence select='count(/descendant::Item)'/> <xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::Item'>[<xsl:sequence select='.'/>]</xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+
+        let mut ev = from_document(
+            &mut f,
+            style,
+            &mut sc,
+            Some(Url::parse("http://example.org/style.xsl").unwrap()),
+        )
+        .expect("failed to compile stylesheet");
+        let mut r = MemoryResolver::new();
+        r.insert(
+            "http://example.org/other.xml",
+            "<Other><Item>a</Item><Item>b</Item></Other>",
+        );
+        ev.set_resolver(Rc::new(r));
+
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+
+        assert_eq!(seq.to_string(Some(&f)), "main oneother 2 [a][b]");
+
+        // The document is only loaded once
+        let u = Url::parse("http://example.org/other.xml").unwrap();
+        let other = ev
+            .dynamic_context()
+            .get_document(&u)
+            .expect("document not loaded");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+        assert_eq!(seq.to_string(Some(&f)), "main oneother 2 [a][b]");
+        assert_eq!(ev.dynamic_context().get_document(&u), Some(other))
+    }
+
+    #[test]
+    fn xslt_preloaded_document() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+
+        let mut f = Forest::new();
+        let src = f.grow_tree("<Test/>").expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+        let second = f
+            .grow_tree("<Second><Item>x</Item></Second>")
+            .expect("unable to parse XML");
+
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:apply-templates select='document(\"urn:second\")/child::*'/></xsl:template>
+  <xsl:template match='child::Second'><xsl:sequence select='child::Item'/></xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+
+        let ev = from_document(&mut f, style, &mut sc, None).expect("failed to compile stylesheet");
+        ev.dynamic_context()
+            .add_document(Url::parse("urn:second").unwrap(), second);
+
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+        assert_eq!(seq.to_string(Some(&f)), "x")
+    }
+
+    #[test]
+    fn xslt_named_outputs() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:output indent='yes'/>
+  <xsl:output method='xml'/>
+  <xsl:output name='compact' indent='no'/>
+  <xsl:output name='compact' omit-xml-declaration='yes'/>
+  <xsl:template match='/'>Found the document</xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        let ev = from_document(&mut f, style, &mut sc, None).expect("failed to compile stylesheet");
+        let od = ev.get_output_definition();
+        assert!(od.get_indent());
+        assert_eq!(od.get_method(), Some("xml"));
+        let c = ev
+            .get_named_output_definition(&QualifiedName::new(None, None, String::from("compact")))
+            .expect("no named output definition");
+        assert!(!c.get_indent());
+        assert!(c.get_omit_xml_declaration());
+        assert!(ev
+            .get_named_output_definition(&QualifiedName::new(None, None, String::from("other")))
+            .is_none());
+
+        // Names are compared by namespace URI, not by prefix
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:output xmlns:a='urn:o' name='a:fmt' indent='yes'/>
+  <xsl:output xmlns:b='urn:o' name='b:fmt' method='text'/>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        let ev = from_document(&mut f, style, &mut sc, None).expect("failed to compile stylesheet");
+        let c = ev
+            .get_named_output_definition(&QualifiedName::new(
+                Some(String::from("urn:o")),
+                Some(String::from("c")),
+                String::from("fmt"),
+            ))
+            .expect("no named output definition");
+        assert!(c.get_indent());
+        assert_eq!(c.get_method(), Some("text"));
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:output name='a:fmt' indent='yes'/>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        assert!(from_document(&mut f, style, &mut sc, None).is_err());
+
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:output indent='yes'/>
+  <xsl:output indent='no'/>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        assert!(from_document(&mut f, style, &mut sc, None).is_err())
+    }
+
+    #[test]
+    fn xslt_generate_id() {
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::doc'><r><xsl:apply-templates/></r></xsl:template>
+  <xsl:template match='child::a'><i><xsl:sequence select='generate-id()'/>,<xsl:sequence select='generate-id(.)'/>,<xsl:sequence select='generate-id(())'/></i></xsl:template>
+</xsl:stylesheet>";
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let ev =
+            from_document(&mut f, styledoc, &mut sc, None).expect("failed to compile stylesheet");
+        let out =
+            transform_document(&ev, &mut f, "<doc><a/><a/></doc>").expect("unable to transform");
+        let ids: Vec<Vec<&str>> = out
+            .trim_start_matches("<r><i>")
+            .trim_end_matches("</i></r>")
+            .split("</i><i>")
+            .map(|i| i.split(',').collect())
+            .collect();
+        assert_eq!(ids.len(), 2);
+        // The same for each call on a node, and different for different nodes
+        assert_eq!(ids[0][0], ids[0][1]);
+        assert_ne!(ids[0][0], ids[1][0]);
+        assert_eq!(ids[0][2], "");
+        // Identifiers are XML names that refer back to the node
+        let n = NodeRef::from_id(ids[1][0])
+            .and_then(|r| r.to_node(&f))
+            .expect("unable to find node");
+        assert_eq!(n.to_name(&f).get_localname(), "a");
+        assert!(NodeRef::from_id("d0n1").is_none());
+        assert!(NodeRef::from_id("d0n+1g1").is_none());
+    }
+
+    #[test]
+    fn xslt_prefixes_resolved() {
+        // The stylesheet and the source document use different prefixes for the same namespace
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform' xmlns:s='urn:x'>
+  <xsl:template match='child::s:doc'><r><xsl:apply-templates/></r></xsl:template>
+  <xsl:template match='child::s:item' xmlns:u='urn:y'><i><xsl:sequence select='count(child::u:*)'/></i></xsl:template>
+  <xsl:template match='child::other'>other</xsl:template>
+</xsl:stylesheet>";
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let ev =
+            from_document(&mut f, styledoc, &mut sc, None).expect("failed to compile stylesheet");
+        let out = transform_document(
+            &ev,
+            &mut f,
+            "<t:doc xmlns:t='urn:x' xmlns:v='urn:y'><t:item><v:a/><t:a/></t:item><s:item xmlns:s='urn:z'/></t:doc>",
+        )
+        .expect("unable to transform");
+        assert_eq!(out, "<r><i>1</i></r>")
+    }
+
+    #[test]
+    fn xslt_keys_changed_document() {
+        // Key names are compared as expanded QNames
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform' xmlns:a='urn:k'>
+  <xsl:key name='a:k' match='child::item' use='attribute::ref'/>
+  <xsl:template match='/' xmlns:b='urn:k'><xsl:sequence select='count(key(\"b:k\", \"x\"))'/></xsl:template>
+</xsl:stylesheet>";
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let ev =
+            from_document(&mut f, styledoc, &mut sc, None).expect("failed to compile stylesheet");
+        let src = f
+            .grow_tree("<doc><item ref='x'/><item ref='y'/></doc>")
+            .expect("unable to parse XML");
+        let mut run = |f: &mut Forest| {
+            let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+            let rd = f.plant_tree();
+            let t = ev
+                .find_match(&isrc, f, src, rd, None)
+                .expect("unable to find match");
+            ev.evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, f, src, rd)
+                .expect("evaluation failed")
+                .to_string(Some(f))
+        };
+        assert_eq!(run(&mut f), "1");
+        // The index is rebuilt after the document changes
+        let mut items = f
+            .get_ref(src)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap()
+            .child_iter();
+        items.next(&f);
+        let y = items.next(&f).expect("no second item");
+        let at = f
+            .get_ref_mut(src)
+            .unwrap()
+            .new_attribute(
+                QualifiedName::new(None, None, String::from("ref")),
+                Value::from("x"),
+            )
+            .expect("unable to create attribute");
+        y.add_attribute(&mut f, at)
+            .expect("unable to add attribute");
+        assert_eq!(run(&mut f), "2");
+    }
+
+    #[test]
+    fn xslt_keys_across_documents() {
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:key name='k' match='child::item' use='attribute::ref'/>
+  <xsl:template match='/'><xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::doc'><r><xsl:apply-templates select='child::q'/></r></xsl:template>
+  <xsl:template match='child::q'><xsl:sequence select='key(\"k\", .)'/>|<xsl:sequence select='key(\"k\", ., document(\"other.xml\"))'/>|<xsl:sequence select='key(\"k\", (\"x\", \"y\"), /child::doc/child::item[2])'/>|<xsl:sequence select='id(\"b a\")'/>|<xsl:sequence select='id(\"a\", document(\"other.xml\"))'/>|<xsl:sequence select='generate-id(key(\"k\", ., document(\"other.xml\"))) = generate-id(key(\"k\", .))'/></xsl:template>
+</xsl:stylesheet>";
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let mut ev =
+            from_document(&mut f, styledoc, &mut sc, None).expect("failed to compile stylesheet");
+        let mut m = crate::resolvers::MemoryResolver::new();
+        m.insert(
+            "http://example.org/other.xml",
+            "<doc><item ref='x'>other-x</item><p id='a'/></doc>",
+        );
+        ev.set_baseurl(Url::parse("http://example.org/").unwrap());
+        ev.set_resolver(Rc::new(m));
+        let out = transform_document(
+            &ev,
+            &mut f,
+            "<!DOCTYPE doc [<!ATTLIST p id ID #IMPLIED>]><doc><item ref='x'>main-x</item><item ref='y'>main-y</item><p xml:id='a'/><p id='b'/><q>x</q></doc>",
+        )
+        .expect("unable to transform");
+        // Each document has its own index, and generate-id distinguishes nodes in different documents.
+        // An attribute named id is only an ID if it is declared to be one.
+        assert_eq!(
+            out,
+            "<r><item ref='x'>main-x</item>|<item ref='x'>other-x</item>|<item ref='y'>main-y</item>|<p xml:id='a'></p><p id='b'></p>||false</r>"
+        );
+    }
+
+    #[test]
+    fn xslt_disable_output_escaping() {
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::doc'><r><xsl:text>&lt;a&gt;</xsl:text><xsl:text disable-output-escaping='no'>&amp;</xsl:text><xsl:text disable-output-escaping='yes'>&lt;b/&gt;&amp;nbsp;</xsl:text></r></xsl:template>
+</xsl:stylesheet>";
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let ev =
+            from_document(&mut f, styledoc, &mut sc, None).expect("failed to compile stylesheet");
+        let src = f.grow_tree("<doc/>").expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("no template");
+        let seq = ev
+            .evaluate(Some(vec![isrc]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+        assert_eq!(seq.to_xml(Some(&f)), "<r>&lt;a&gt;&amp;<b/>&nbsp;</r>");
+        // The serializer can be told to escape all text
+        let od = OutputDefinitionBuilder::new()
+            .disable_output_escaping(false)
+            .build()
+            .unwrap();
+        assert_eq!(
+            seq.to_xml_with_options(&od, Some(&f)),
+            "<r>&lt;a&gt;&amp;&lt;b/&gt;&amp;nbsp;</r>"
+        );
+        let bad = f.grow_tree("<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'><xsl:template match='/'><xsl:text disable-output-escaping='maybe'>x</xsl:text></xsl:template></xsl:stylesheet>").unwrap();
+        assert!(from_document(&mut f, bad, &mut sc, None).is_err());
+    }
+
+    #[test]
+    fn xslt_fallback() {
+        let compile = |f: &mut Forest, style: &str| {
+            let styledoc = f.grow_tree(style).expect("unable to parse XML");
+            from_document(
+                f,
+                styledoc,
+                &mut StaticContext::new_with_xslt_builtins(),
+                None,
+            )
+        };
+        let mut f = Forest::new();
+        let ev = compile(
+            &mut f,
+            "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform' version='4.0'>
+  <xsl:template match='child::doc'><r><xsl:frobnicate select='1'><xsl:fallback>a</xsl:fallback><xsl:fallback>b</xsl:fallback></xsl:frobnicate><xsl:if test='true()'>c<xsl:fallback>d</xsl:fallback></xsl:if><xsl:value-of select='1'><xsl:fallback>e</xsl:fallback></xsl:value-of><xsl:apply-templates/></r></xsl:template>
+  <xsl:template match='child::x'><xsl:frobnicate/></xsl:template>
+</xsl:stylesheet>",
+        )
+        .expect("failed to compile stylesheet");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc/>").expect("unable to transform"),
+            "<r>abce</r>"
+        );
+        // An unknown instruction without a fallback is an error when it is evaluated
+        assert!(transform_document(&ev, &mut f, "<doc><x/></doc>").is_err());
+
+        // Not in forwards-compatible mode, an unknown instruction is a static error
+        let unknown =
+            "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform' version='3.0'>
+  <xsl:template match='child::doc'><r><xsl:frobnicate/></r></xsl:template>
+</xsl:stylesheet>";
+        assert!(compile(&mut f, unknown).is_err());
+        // ...unless a literal result element enables forwards-compatible mode
+        let ev = compile(
+            &mut f,
+            unknown
+                .replace("<r>", "<r xsl:version='4.0'>")
+                .replace(
+                    "<xsl:frobnicate/>",
+                    "<xsl:frobnicate><xsl:fallback>f</xsl:fallback></xsl:frobnicate>",
+                )
+                .as_str(),
+        )
+        .expect("failed to compile stylesheet");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc/>").expect("unable to transform"),
+            "<r>f</r>"
+        );
+        // Where it is not used, xsl:fallback is the empty sequence
+        let t = f
+            .grow_tree(
+                "<xsl:fallback xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>d</xsl:fallback>",
+            )
+            .expect("unable to parse XML");
+        let fb = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        assert!(matches!(to_constructor(fb, &f), Ok(Constructor::Empty)));
+    }
+
+    #[test]
+    fn xslt_feature_detection() {
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::doc'><r><xsl:sequence select='concat(element-available(\"xsl:if\"), element-available(\"Q{http://www.w3.org/1999/XSL/Transform}for-each\"), element-available(\"xsl:frobnicate\"), element-available(\"if\"))'/>|<xsl:sequence select='concat(function-available(\"concat\"), function-available(\"fn:generate-id\", 1), function-available(\"ext\"), function-available(\"my:concat\"))'/>|<xsl:sequence select='concat(system-property(\"xsl:version\"), system-property(\"xsl:vendor\"), system-property(\"xsl:is-schema-aware\"), system-property(\"xsl:unknown\"))'/></r></xsl:template>
+</xsl:stylesheet>";
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let ev =
+            from_document(&mut f, styledoc, &mut sc, None).expect("failed to compile stylesheet");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc/>").expect("unable to transform"),
+            "<r>truetruefalsefalse|truetruefalsefalse|3.0xrustno</r>"
+        );
+    }
+
+    #[test]
+    fn xslt_unknown_function() {
+        let compile = |f: &mut Forest, body: &str| {
+            let style = format!("<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform' xmlns:my='urn:my'>
+  <xsl:template match='child::doc'><r>{}</r></xsl:template>
+</xsl:stylesheet>", body);
+            let styledoc = f.grow_tree(style.as_str()).expect("unable to parse XML");
+            from_document(
+                f,
+                styledoc,
+                &mut StaticContext::new_with_xslt_builtins(),
+                None,
+            )
+            .expect("failed to compile stylesheet")
+        };
+        let mut f = Forest::new();
+        // A call to an unknown function that is guarded by function-available() is not evaluated
+        let ev = compile(
+            &mut f,
+            "<xsl:if test='function-available(\"my:frobnicate\")'><xsl:sequence select='my:frobnicate()'/></xsl:if>ok",
+        );
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc/>").expect("unable to transform"),
+            "<r>ok</r>"
+        );
+        // An unguarded call is a dynamic error
+        let ev = compile(&mut f, "<xsl:sequence select='my:frobnicate()'/>");
+        let e = transform_document(&ev, &mut f, "<doc/>").expect_err("call to unknown function");
+        assert!(matches!(e.kind, ErrorKind::StaticBadFunction));
+        assert!(e.message.starts_with("XPST0017"));
+    }
+
+    #[test]
+    fn xslt_typed_parameters() {
+        let compile = |f: &mut Forest, with: &str| {
+            let style = format!("<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::doc'><xsl:iterate select='child::x'><xsl:param name='last' as='xs:integer' select='0'/><xsl:param name='scale' as='xs:double' select='2'/><xsl:param name='none' as='xs:string?'/><xsl:next-iteration><xsl:with-param name='last' select='{}'/></xsl:next-iteration><xsl:on-completion><r><xsl:sequence select='concat($last * $scale, \" \", $last instance of xs:integer, $scale instance of xs:double, count($none))'/></r></xsl:on-completion></xsl:iterate></xsl:template>
+</xsl:stylesheet>", with);
+            let styledoc = f.grow_tree(style.as_str()).expect("unable to parse XML");
+            from_document(
+                f,
+                styledoc,
+                &mut StaticContext::new_with_xslt_builtins(),
+                None,
+            )
+            .expect("failed to compile stylesheet")
+        };
+        let mut f = Forest::new();
+        // The untyped value of the node is cast to xs:integer, and the integer 2 is promoted to xs:double
+        let ev = compile(&mut f, ".");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc><x>1</x><x>21</x></doc>")
+                .expect("unable to transform"),
+            "<r>42 truetrue0</r>"
+        );
+        // A node that is not a valid integer cannot be converted
+        assert!(transform_document(&ev, &mut f, "<doc><x>one</x></doc>").is_err());
+        // Integer arithmetic gives an integer
+        let ev = compile(&mut f, "$last + 1");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc><x/><x/><x/></doc>")
+                .expect("unable to transform"),
+            "<r>6 truetrue0</r>"
+        );
+        let ev = compile(&mut f, "($last + 7) idiv 2 - 1 mod 2");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc><x/><x/><x/></doc>")
+                .expect("unable to transform"),
+            "<r>8 truetrue0</r>"
+        );
+        // A string is not converted to xs:integer
+        let ev = compile(&mut f, "string(.)");
+        match transform_document(&ev, &mut f, "<doc><x>1</x></doc>") {
+            Err(e) => assert!(e
+                .message
+                .starts_with("XTTE0570: value of parameter \"last\"")),
+            Ok(_) => panic!("string parameter value was accepted as xs:integer"),
+        }
+        // Nor can more than one item
+        let ev = compile(&mut f, "(1, 2)");
+        assert!(transform_document(&ev, &mut f, "<doc><x>1</x></doc>").is_err())
+    }
+
+    #[test]
+    fn xslt_union_pattern() {
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/' priority='1'><xsl:apply-templates/></xsl:template>
+  <xsl:template match='child::doc'><r><xsl:apply-templates/></r></xsl:template>
+  <xsl:template match='child::b | child::d'><m><xsl:apply-templates/></m></xsl:template>
+  <xsl:template match='child::a'><o/></xsl:template>
+  <xsl:template match='child::c'><o/></xsl:template>
+  <xsl:template match='child::e | child::*'><u/></xsl:template>
+</xsl:stylesheet>";
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let ev = from_document(
+            &mut f,
+            styledoc,
+            &mut StaticContext::new_with_xslt_builtins(),
+            None,
+        )
+        .expect("failed to compile stylesheet");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc><a/><b>1</b><c/><d>2</d><e/><f/></doc>")
+                .expect("unable to transform"),
+            // Each alternative has its own default priority, so child::* does not take precedence over the other templates
+            "<r><o></o><m>1</m><o></o><m>2</m><u></u><u></u></r>"
+        );
+    }
+
+    #[test]
+    fn xslt_expand_text() {
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform' expand-text='yes'>
+  <xsl:template match='child::doc'><r>Items: {count(child::i)} {{literal}} [{child::i}]<p xsl:expand-text='no'>{child::i}</p><xsl:text>{1 + 1}</xsl:text><q>{'}'}{}</q></r></xsl:template>
+</xsl:stylesheet>";
+        let mut f = Forest::new();
+        let styledoc = f.grow_tree(style).expect("unable to parse XML");
+        let ev = from_document(
+            &mut f,
+            styledoc,
+            &mut StaticContext::new_with_xslt_builtins(),
+            None,
+        )
+        .expect("failed to compile stylesheet");
+        assert_eq!(
+            transform_document(&ev, &mut f, "<doc><i>a</i><i>b</i></doc>")
+                .expect("unable to transform"),
+            "<r>Items: 2 {literal} [a b]<p>{child::i}</p>2<q>}</q></r>"
+        );
+
+        // Unbalanced curly brackets are static errors
+        for t in ["<r>{1 + 1</r>", "<r>a}b</r>"] {
+            let style = format!("<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform' expand-text='yes'><xsl:template match='child::doc'>{}</xsl:template></xsl:stylesheet>", t);
+            let styledoc = f.grow_tree(style.as_str()).expect("unable to parse XML");
+            assert!(from_document(
+                &mut f,
+                styledoc,
+                &mut StaticContext::new_with_xslt_builtins(),
+                None,
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn xslt_parallel() {
+        let style = "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::record'><row><xsl:apply-templates/></row></xsl:template>
+</xsl:stylesheet>";
+        let mut src = String::from("<records>");
+        for i in 0..10 {
+            src.push_str(format!("<record>{}</record> ", i).as_str())
+        }
+        src.push_str("</records>");
+        let expected: String = (0..10).map(|i| format!("<row>{}</row>", i)).collect();
+        assert_eq!(
+            transform_parallel(style, src.as_str(), 3).expect("unable to transform"),
+            expected
+        );
+        assert_eq!(
+            transform_parallel(style, src.as_str(), 1).expect("unable to transform"),
+            expected
+        );
+        assert_eq!(
+            transform_parallel(style, "<records/>", 4).expect("unable to transform"),
+            ""
+        );
+        assert!(transform_parallel("<notxsl/>", src.as_str(), 2).is_err())
+    }
+
+    #[test]
+    fn xslt_iterate() {
+        let transform = |style: &str| {
+            let mut sc = StaticContext::new_with_xslt_builtins();
+            let mut f = Forest::new();
+            let src = f.grow_tree("<Test/>").expect("unable to parse XML");
+            let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+            let style = f.grow_tree(style).expect("unable to parse XML");
+            let ev = from_document(&mut f, style, &mut sc, None)?;
+            let rd = f.plant_tree();
+            let t = ev
+                .find_match(&isrc, &mut f, src, rd, None)
+                .expect("unable to find match");
+            ev.evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+                .map(|seq| seq.to_xml(Some(&f)))
+        };
+        // A running total, carried from one iteration to the next
+        assert_eq!(
+            transform(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:iterate select='1 to 4'><xsl:param name='total' select='0'/><xsl:param name='label'>total</xsl:param><t><xsl:sequence select='$total + .'/></t><xsl:next-iteration><xsl:with-param name='total' select='$total + .'/></xsl:next-iteration><xsl:on-completion><done><xsl:sequence select='concat($label, \" \", $total)'/></done></xsl:on-completion></xsl:iterate></xsl:template>
+</xsl:stylesheet>"
+            )
+            .expect("evaluation failed"),
+            "<t>1</t><t>3</t><t>6</t><t>10</t><done>total 10</done>"
+        );
+        // Stop when the total exceeds 3; the on-completion instruction is not evaluated
+        assert_eq!(
+            transform(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:iterate select='1 to 4'><xsl:param name='total' select='0'/><xsl:choose><xsl:when test='$total + . gt 3'><xsl:break><stop><xsl:sequence select='$total'/></stop></xsl:break></xsl:when><xsl:otherwise><xsl:next-iteration><xsl:with-param name='total' select='$total + .'/></xsl:next-iteration></xsl:otherwise></xsl:choose><xsl:on-completion><done/></xsl:on-completion></xsl:iterate></xsl:template>
+</xsl:stylesheet>"
+            )
+            .expect("evaluation failed"),
+            "<stop>3</stop>"
+        );
+        // XTSE3120: break and next-iteration must be in tail position within iterate
+        for body in [
+            "<xsl:break/>",
+            "<xsl:iterate select='1 to 4'><xsl:break/><t/></xsl:iterate>",
+            "<xsl:iterate select='1 to 4'><xsl:if test='. eq 2'><xsl:break/></xsl:if><t/></xsl:iterate>",
+            "<xsl:iterate select='1 to 4'><xsl:choose><xsl:when test='. eq 2'><xsl:next-iteration/><t/></xsl:when></xsl:choose></xsl:iterate>",
+            "<xsl:iterate select='1 to 4'><t><xsl:break/></t></xsl:iterate>",
+        ] {
+            assert!(transform(
+                format!(
+                    "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'>{}</xsl:template>
+</xsl:stylesheet>",
+                    body
+                )
+                .as_str()
+            )
+            .is_err_and(|e| e.message.starts_with("XTSE3120")))
+        }
+    }
+
+    #[test]
+    fn xslt_merge() {
+        let transform = |style: &str| {
+            let mut sc = StaticContext::new_with_xslt_builtins();
+            let mut f = Forest::new();
+            let src = f
+                .grow_tree("<data><a><r k='1'>a1</r><r k='3'>a3</r><r k='3'>a3</r></a><b><r k='2'>b2</r><r k='3'>b3</r><r k='4'>b4</r></b></data>")
+                .expect("unable to parse XML");
+            let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+            let style = f.grow_tree(style).expect("unable to parse XML");
+            let ev = from_document(&mut f, style, &mut sc, None)?;
+            let rd = f.plant_tree();
+            let t = ev
+                .find_match(&isrc, &mut f, src, rd, None)
+                .expect("unable to find match");
+            ev.evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+                .map(|seq| seq.to_xml(Some(&f)))
+        };
+        assert_eq!(
+            transform(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:merge><xsl:merge-source name='a' select='child::data/child::a/child::r'><xsl:merge-key select='attribute::k'/></xsl:merge-source><xsl:merge-source name='b' select='child::data/child::b/child::r'><xsl:merge-key select='attribute::k'/></xsl:merge-source><xsl:merge-action><g><xsl:sequence select='current-merge-key()'/>:<xsl:sequence select='count(current-merge-group())'/>:<xsl:sequence select='count(current-merge-group(\"b\"))'/>:<xsl:sequence select='string(self::node())'/></g></xsl:merge-action></xsl:merge></xsl:template>
+</xsl:stylesheet>"
+            )
+            .expect("evaluation failed"),
+            "<g>1:1:0:a1</g><g>2:1:1:b2</g><g>3:3:1:a3</g><g>4:1:1:b4</g>"
+        );
+        // Sources in descending order
+        assert_eq!(
+            transform(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:merge><xsl:merge-source select='(4, 2, 1)'><xsl:merge-key select='.' order='descending'/></xsl:merge-source><xsl:merge-source select='(3, 2)'><xsl:merge-key select='.' order='descending'/></xsl:merge-source><xsl:merge-action><g><xsl:sequence select='current-merge-key()'/>:<xsl:sequence select='count(current-merge-group())'/></g></xsl:merge-action></xsl:merge></xsl:template>
+</xsl:stylesheet>"
+            )
+            .expect("evaluation failed"),
+            "<g>4:1</g><g>3:1</g><g>2:2</g><g>1:1</g>"
+        );
+        assert!(transform(
+            "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:merge><xsl:merge-source select='(1, 2)'><xsl:merge-key select='.'/></xsl:merge-source></xsl:merge></xsl:template>
+</xsl:stylesheet>"
+        )
+        .is_err());
+        // A source that is not in the order of the merge keys
+        let e = transform(
+            "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='/'><xsl:merge><xsl:merge-source name='s' select='(1, 3, 2)'><xsl:merge-key select='.'/></xsl:merge-source><xsl:merge-action><g/></xsl:merge-action></xsl:merge></xsl:template>
+</xsl:stylesheet>",
+        )
+        .expect_err("unsorted merge source");
+        assert!(e.message.starts_with("XTDE2220"))
+    }
+
+    #[test]
+    fn xslt_recursion_limit() {
+        let transform = |src: &str, max_depth: Option<usize>| {
+            let mut sc = StaticContext::new_with_xslt_builtins();
+            let mut f = Forest::new();
+            let src = f.grow_tree(src).expect("unable to parse XML");
+            let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+            let style = f
+                .grow_tree(
+                    "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::a'><b><xsl:apply-templates/></b></xsl:template>
+  <xsl:template match='child::loop'><xsl:apply-templates select='self::node()'/></xsl:template>
+</xsl:stylesheet>",
+                )
+                .expect("unable to parse XML");
+            let mut ev =
+                from_document(&mut f, style, &mut sc, None).expect("failed to compile stylesheet");
+            if let Some(d) = max_depth {
+                ev.set_max_depth(d)
+            }
+            let rd = f.plant_tree();
+            let t = ev
+                .find_match(&isrc, &mut f, src, rd, None)
+                .expect("unable to find match");
+            ev.evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+                .map(|seq| seq.to_xml(Some(&f)))
+        };
+        let nested = "<a>".repeat(10) + &"</a>".repeat(10);
+        assert_eq!(
+            transform(nested.as_str(), None).expect("evaluation failed"),
+            "<b>".repeat(10) + &"</b>".repeat(10)
+        );
+        // The template for the document node is evaluated directly, so the depth is that of the elements
+        assert!(transform(nested.as_str(), Some(10)).is_ok());
+        match transform(nested.as_str(), Some(9)) {
+            Err(e) => assert!(matches!(e.kind, ErrorKind::RecursionLimit)),
+            Ok(_) => panic!("recursion limit was not enforced"),
+        }
+        // The default limit allows deep documents. Parsing and serializing a deep document needs a larger stack than a test thread has, but evaluation extends the stack as needed.
+        let deep = std::thread::Builder::new()
+            .stack_size(16 * 1024 * 1024)
+            .spawn(move || {
+                let deep = "<a>".repeat(300) + &"</a>".repeat(300);
+                transform(deep.as_str(), None)
+            })
+            .expect("unable to start thread")
+            .join()
+            .expect("thread panicked");
+        assert_eq!(
+            deep.expect("evaluation failed"),
+            "<b>".repeat(300) + &"</b>".repeat(300)
+        );
+        // Infinite recursion is stopped by a limit, including the default one
+        for d in [Some(50), Some(5000), None] {
+            match transform("<loop/>", d) {
+                Err(e) => assert!(matches!(e.kind, ErrorKind::RecursionLimit)),
+                Ok(_) => panic!("infinite recursion did not fail"),
+            }
+        }
+    }
+
+    #[test]
+    fn xslt_dry_run() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test><Level1>one</Level1><Level1>two</Level1></Test>")
+            .expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::Test'><out><xsl:apply-templates/></out></xsl:template>
+  <xsl:template match='child::Level1'><l><xsl:apply-templates/></l></xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        let ev = from_document(&mut f, style, &mut sc, None).expect("failed to compile stylesheet");
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        let stats = ev
+            .dry_run(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+        let xml = seq.to_xml(Some(&f));
+        assert_eq!(xml, "<out><l>one</l><l>two</l></out>");
+        // Test and two Level1 elements, and the two text nodes. The template for the document node is evaluated directly.
+        assert_eq!(stats.templates_fired, 5);
+        // out, two l elements, and their text
+        assert_eq!(stats.nodes, 5);
+        assert_eq!(stats.items, 0);
+        assert_eq!(stats.output_size, xml.len())
+    }
+
+    #[test]
+    fn xslt_source_map() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test><Level1>one</Level1><Level1>two</Level1></Test>")
+            .expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::Test'><out><xsl:apply-templates/></out></xsl:template>
+  <xsl:template match='child::Level1'><l><xsl:copy-of select='child::text()'/></l></xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        let mut ev =
+            from_document(&mut f, style, &mut sc, None).expect("failed to compile stylesheet");
+        ev.set_source_map(true);
+        let rd = f.plant_tree();
+        let t = ev
+            .find_match(&isrc, &mut f, src, rd, None)
+            .expect("unable to find match");
+        let seq = ev
+            .evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd)
+            .expect("evaluation failed");
+        let test = f
+            .get_ref(src)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let children = |n: Node| {
+            let mut v = vec![];
+            let mut cit = n.child_iter();
+            while let Some(c) = cit.next(&f) {
+                v.push(c)
+            }
+            v
+        };
+        let level1 = children(test);
+        let out = match &*seq[0] {
+            Item::Node(n) => *n,
+            _ => panic!("not a node"),
+        };
+        let o = ev.origin(&out).expect("no origin");
+        assert_eq!(o.source, Some(test));
+        assert_eq!(o.instruction, Instruction::LiteralElement);
+        let template = o.template.expect("no template");
+        assert_eq!(template.pattern.as_deref(), Some("child::Test"));
+        assert_eq!(template.mode, None);
+        let l = children(out);
+        assert_eq!(l.len(), 2);
+        assert_eq!(ev.origin(&l[1]).unwrap().source, Some(level1[1]));
+        assert_eq!(ev.derived_from(&level1[0]), vec![l[0]]);
+        let text = children(l[0])[0];
+        let o = ev.origin(&text).expect("no origin");
+        assert_eq!(o.instruction, Instruction::DeepCopy);
+        assert_eq!(o.source, children(level1[0]).first().copied());
+        // Nodes that are not in the result have no origin
+        assert_eq!(ev.origin(&test), None);
+        let one = children(level1[0])[0];
+        // An attribute set on an element, and a copied text node
+        ev.evaluate(
+            Some(vec![Rc::new(Item::Node(out))]),
+            Some(0),
+            &vec![Constructor::SetAttribute(
+                QualifiedName::new(None, None, String::from("a")),
+                vec![Constructor::Literal(Value::from("b"))],
+            )],
+            &mut f,
+            src,
+            rd,
+        )
+        .expect("evaluation failed");
+        let a = out.attribute_iter(&f).next().expect("no attribute");
+        let o = ev.origin(&a).expect("no origin");
+        assert_eq!(o.instruction, Instruction::SetAttribute);
+        assert_eq!(o.source, Some(out));
+        let seq = ev
+            .evaluate(
+                Some(vec![Rc::new(Item::Node(one))]),
+                Some(0),
+                &vec![Constructor::Copy(vec![], vec![])],
+                &mut f,
+                src,
+                rd,
+            )
+            .expect("evaluation failed");
+        let o = match &*seq[0] {
+            Item::Node(n) => ev.origin(n).expect("no origin"),
+            _ => panic!("not a node"),
+        };
+        assert_eq!(o.instruction, Instruction::Copy);
+        assert_eq!(o.source, Some(one))
+    }
+
+    // Records where the debugger stopped, and steps as instructed
+    #[derive(Default)]
+    struct Stepper {
+        steps: Vec<Step>,
+        stops: Vec<(Option<String>, usize, String)>,
+        entered: usize,
+        param: Option<String>,
+    }
+    impl Debugger for Stepper {
+        fn enter(&mut self, _frame: &Frame) {
+            self.entered += 1
+        }
+        fn stop(&mut self, frame: &Frame) -> Step {
+            self.stops.push((
+                frame.pattern.map(String::from),
+                frame.depth,
+                frame.context.to_string(Some(frame.forest)),
+            ));
+            self.param = frame.variable("p").map(|v| v.to_string(None));
+            if self.steps.is_empty() {
+                Step::Continue
+            } else {
+                self.steps.remove(0)
+            }
+        }
+    }
+
+    #[test]
+    fn xslt_debugger() {
+        let mut sc = StaticContext::new_with_xslt_builtins();
+        let mut f = Forest::new();
+        let src = f
+            .grow_tree("<Test><Level1>one</Level1><Level1>two</Level1></Test>")
+            .expect("unable to parse XML");
+        let isrc = Rc::new(Item::Node(f.get_ref(src).unwrap().get_doc_node()));
+        let style = f
+            .grow_tree(
+                "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::Test'><out><xsl:apply-templates/></out></xsl:template>
+  <xsl:template match='child::Level1'><l><xsl:apply-templates/></l></xsl:template>
+</xsl:stylesheet>",
+            )
+            .expect("unable to parse XML");
+        let mut ev =
+            from_document(&mut f, style, &mut sc, None).expect("failed to compile stylesheet");
+        ev.dynamic_context().set_parameter(
+            String::from("p"),
+            vec![Rc::new(Item::Value(Value::from(1)))],
+        );
+        let mut run = |ev: &mut Evaluator, breakpoint: &str, steps: Vec<Step>| {
+            let d = Rc::new(RefCell::new(Stepper {
+                steps,
+                ..Default::default()
+            }));
+            ev.set_debugger(d.clone());
+            ev.add_breakpoint(breakpoint);
+            let rd = f.plant_tree();
+            let t = ev
+                .find_match(&isrc, &mut f, src, rd, None)
+                .expect("unable to find match");
+            let r = ev.evaluate(Some(vec![Rc::clone(&isrc)]), Some(0), &t, &mut f, src, rd);
+            assert!(ev.remove_breakpoint(breakpoint));
+            (r.is_ok(), d)
+        };
+
+        let (ok, d) = run(&mut ev, "child::Level1", vec![]);
+        assert!(ok);
+        let d = d.borrow();
+        assert_eq!(d.entered, 5);
+        assert_eq!(
+            d.stops,
+            vec![
+                (Some(String::from("child::Level1")), 2, String::from("one")),
+                (Some(String::from("child::Level1")), 2, String::from("two")),
+            ]
+        );
+        assert_eq!(d.param, Some(String::from("1")));
+
+        // Step into the first Level1, then over the text inside it
+        let (ok, d) = run(&mut ev, "child::Test", vec![Step::Into, Step::Over]);
+        assert!(ok);
+        let d = d.borrow();
+        assert_eq!(
+            d.stops.iter().map(|s| s.1).collect::<Vec<_>>(),
+            vec![1, 2, 2]
+        );
+        // Step out of the first Level1, into the second
+        let (ok, d) = run(
+            &mut ev,
+            "child::Level1",
+            vec![Step::Into, Step::Out, Step::Abort],
+        );
+        assert!(!ok);
+        let d = d.borrow();
+        assert_eq!(
+            d.stops.iter().map(|s| s.2.as_str()).collect::<Vec<_>>(),
+            vec!["one", "one", "two"]
+        )
+    }
 }
@@ -0,0 +1,654 @@
+/*! ## Testing stylesheets with XSpec
+
+Runs the scenarios in an [XSpec](https://github.com/xspec/xspec) description document against a stylesheet, and reports the result of each expectation.
+
+A description is an x:description element, whose stylesheet attribute gives the URL of the stylesheet to test. It contains x:scenario elements, which may be nested. The scenario's x:context is the input: either its content, or the document given by its href attribute. A select attribute on the x:context selects the nodes to use from the input. Templates are applied to the context nodes, in the same way as xsl:apply-templates, and the result is checked against each x:expect in the scenario. A nested scenario that does not have an x:context uses the context of its parent.
+
+An x:expect is satisfied when:
+
+* its test attribute is an XPath expression with an effective boolean value of true. The context item is a document node that contains the result.
+* otherwise, its select attribute is an XPath expression whose value is the same as the result.
+* otherwise, its content is the same as the result.
+
+Results are compared by serialising them, ignoring whitespace-only text. Scenarios and expectations that are x:pending, or that have a pending attribute, are not run.
+
+Only template scenarios with x:context are supported; scenarios that use x:call to call a named template or a function fail, since the XSLT engine does not have either.
+
+```rust
+use xrust::forest::Forest;
+use xrust::resolvers::MemoryResolver;
+use xrust::xspec::run;
+use url::Url;
+
+let mut r = MemoryResolver::new();
+r.insert(
+    "http://example.org/greeting.xsl",
+    "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::name'><p>Hello, <xsl:apply-templates/></p></xsl:template>
+</xsl:stylesheet>",
+);
+let mut f = Forest::new();
+let d = f.grow_tree(
+    "<x:description xmlns:x='http://www.jenitennison.com/xslt/xspec' stylesheet='greeting.xsl'>
+  <x:scenario label='a greeting'>
+    <x:context><name>World</name></x:context>
+    <x:expect label='is a paragraph'><p>Hello, World</p></x:expect>
+    <x:expect label='mentions the name' test='contains(child::p, \"World\")'/>
+  </x:scenario>
+</x:description>",
+).expect("unable to parse XML");
+
+let report = run(&mut f, d, Url::parse("http://example.org/test.xspec").ok(), &r)
+    .expect("unable to run tests");
+assert_eq!(report.passed(), 2);
+assert!(report.is_success());
+```
+*/
+
+use crate::evaluate::{Constructor, Evaluator, StaticContext};
+use crate::forest::{Forest, Node, NodeType, TreeIndex};
+use crate::item::{Item, Sequence, SequenceTrait};
+use crate::qname::QualifiedName;
+use crate::resolvers::{resolve_href, Resolver};
+use crate::value::Value;
+use crate::xdmerror::*;
+use crate::xpath::parse;
+use crate::xslt::from_document_with_resolver;
+use std::fmt;
+use std::rc::Rc;
+use url::Url;
+
+/// The XSpec namespace URI.
+pub const XSPEC_NAMESPACE: &str = "http://www.jenitennison.com/xslt/xspec";
+
+/// The outcome of an x:expect.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    /// The labels of the scenario and its ancestors, outermost first.
+    pub scenario: Vec<String>,
+    /// The label of the x:expect.
+    pub label: String,
+    /// Whether the expectation was satisfied. None if it is pending.
+    pub passed: Option<bool>,
+    /// Why the expectation failed, such as the result that was not expected.
+    pub message: Option<String>,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            match self.passed {
+                Some(true) => "PASS",
+                Some(false) => "FAIL",
+                None => "PENDING",
+            },
+            self.scenario.join(" "),
+            self.label
+        )?;
+        match &self.message {
+            Some(m) => write!(f, " ({})", m),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The outcomes of all of the expectations in a description, in document order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.count(Some(true))
+    }
+    pub fn failed(&self) -> usize {
+        self.count(Some(false))
+    }
+    pub fn pending(&self) -> usize {
+        self.count(None)
+    }
+    /// Whether no expectation failed.
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0
+    }
+    fn count(&self, p: Option<bool>) -> usize {
+        self.outcomes.iter().filter(|o| o.passed == p).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for o in &self.outcomes {
+            writeln!(f, "{}", o)?
+        }
+        write!(
+            f,
+            "passed: {} failed: {} pending: {}",
+            self.passed(),
+            self.failed(),
+            self.pending()
+        )
+    }
+}
+
+/// Compile the stylesheet named by the description, and run its scenarios.
+///
+/// The stylesheet, and any x:context documents, are fetched with the resolver. Relative URLs are resolved against the base URL of the description.
+pub fn run(
+    f: &mut Forest,
+    description: TreeIndex,
+    base: Option<Url>,
+    resolver: &dyn Resolver,
+) -> Result<Report, Error> {
+    let d = description_element(f, description)?;
+    let href = attribute(f, d, "stylesheet").ok_or(Error::new(
+        ErrorKind::Unknown,
+        String::from("description does not have a stylesheet attribute"),
+    ))?;
+    let url = resolve_href(base.as_ref(), href.as_str())?;
+    let styledoc = f.grow_tree(resolver.resolve(&url)?.as_str())?;
+    let mut sc = StaticContext::new_with_xslt_builtins();
+    let ev = from_document_with_resolver(f, styledoc, &mut sc, Some(url), resolver)?;
+    run_with_evaluator(f, description, &ev, base, resolver)
+}
+
+/// Run the scenarios in a description with a stylesheet that has already been compiled. The stylesheet attribute of the description is ignored.
+pub fn run_with_evaluator(
+    f: &mut Forest,
+    description: TreeIndex,
+    ev: &Evaluator,
+    base: Option<Url>,
+    resolver: &dyn Resolver,
+) -> Result<Report, Error> {
+    let d = description_element(f, description)?;
+    let mut r = Runner {
+        ev,
+        base,
+        resolver,
+        report: Report::default(),
+    };
+    r.scenarios(f, d, &mut vec![], None, false)?;
+    Ok(r.report)
+}
+
+fn description_element(f: &Forest, t: TreeIndex) -> Result<Node, Error> {
+    f.get_ref(t)
+        .and_then(|d| d.get_doc_node().get_first_element(f))
+        .filter(|e| is_xspec(f, *e, "description"))
+        .ok_or(Error::new(
+            ErrorKind::Unknown,
+            String::from("not an XSpec description"),
+        ))
+}
+
+// The input to a scenario: a tree, and the nodes in it that templates are applied to
+#[derive(Clone)]
+struct Context {
+    tree: TreeIndex,
+    nodes: Sequence,
+}
+
+struct Runner<'a> {
+    ev: &'a Evaluator,
+    base: Option<Url>,
+    resolver: &'a dyn Resolver,
+    report: Report,
+}
+
+impl<'a> Runner<'a> {
+    // Run the scenarios that are children of the node
+    fn scenarios(
+        &mut self,
+        f: &mut Forest,
+        n: Node,
+        labels: &mut Vec<String>,
+        context: Option<Context>,
+        pending: bool,
+    ) -> Result<(), Error> {
+        for c in xspec_children(f, n) {
+            let local = c.to_name(f).get_localname();
+            match local.as_str() {
+                "scenario" => {
+                    labels.push(label(f, c));
+                    let r =
+                        self.scenario(f, c, labels, context.clone(), pending || is_pending(f, c));
+                    labels.pop();
+                    r?
+                }
+                "pending" => self.scenarios(f, c, labels, context.clone(), true)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    fn scenario(
+        &mut self,
+        f: &mut Forest,
+        s: Node,
+        labels: &mut Vec<String>,
+        inherited: Option<Context>,
+        pending: bool,
+    ) -> Result<(), Error> {
+        let children = xspec_children(f, s);
+        let expects: Vec<Node> = children
+            .iter()
+            .filter(|c| c.to_name(f).get_localname() == "expect")
+            .copied()
+            .collect();
+        let outcome = |label: String, passed: Option<bool>, message: Option<String>| Outcome {
+            scenario: labels.clone(),
+            label,
+            passed,
+            message,
+        };
+        if pending {
+            let o: Vec<Outcome> = expects
+                .iter()
+                .map(|e| outcome(label(f, *e), None, None))
+                .collect();
+            self.report.outcomes.extend(o);
+            return self.scenarios(f, s, labels, inherited, pending);
+        }
+        // The trees that are made for the scenario are discarded once it, and the scenarios within it, have been run
+        let trees = f.tree_count();
+        let mut context = inherited;
+        let mut failure = None;
+        for c in &children {
+            match c.to_name(f).get_localname().as_str() {
+                "context" => match self.context(f, *c, context.as_ref()) {
+                    Ok(x) => context = Some(x),
+                    Err(e) => failure = Some(e.message),
+                },
+                "call" => {
+                    failure = Some(String::from(
+                        "x:call is not supported: named templates and functions are not available",
+                    ))
+                }
+                _ => {}
+            }
+        }
+        let result = match (failure, &context) {
+            (Some(m), _) => Err(m),
+            (None, Some(x)) if !expects.is_empty() => self.apply(f, x).map_err(|e| e.message),
+            (None, _) if !expects.is_empty() => {
+                Err(String::from("scenario does not have an x:context"))
+            }
+            _ => Ok(vec![]),
+        };
+        let mut o = vec![];
+        for e in expects {
+            o.push(if is_pending(f, e) {
+                outcome(label(f, e), None, None)
+            } else {
+                match &result {
+                    Ok(r) => match self.expect(f, e, r) {
+                        Ok(None) => outcome(label(f, e), Some(true), None),
+                        Ok(Some(m)) => outcome(label(f, e), Some(false), Some(m)),
+                        Err(err) => outcome(label(f, e), Some(false), Some(err.message)),
+                    },
+                    Err(m) => outcome(label(f, e), Some(false), Some(m.clone())),
+                }
+            })
+        }
+        self.report.outcomes.extend(o);
+        let r = self.scenarios(f, s, labels, context, false);
+        f.truncate(trees);
+        self.ev.forget_trees(trees);
+        r
+    }
+    // The input for a scenario. An x:context that has neither content nor an href selects from the inherited context.
+    fn context(
+        &self,
+        f: &mut Forest,
+        c: Node,
+        inherited: Option<&Context>,
+    ) -> Result<Context, Error> {
+        let content = children(f, c);
+        let empty = content
+            .iter()
+            .all(|n| n.node_type(f) == NodeType::Text && n.to_string(f).trim().is_empty());
+        let (tree, nodes) = match (attribute(f, c, "href"), inherited) {
+            (Some(h), _) => {
+                let url = resolve_href(self.base.as_ref(), h.as_str())?;
+                let t = f.grow_tree(self.resolver.resolve(&url)?.as_str())?;
+                (
+                    t,
+                    vec![Rc::new(Item::Node(f.get_ref(t).unwrap().get_doc_node()))],
+                )
+            }
+            (None, Some(x)) if empty => (x.tree, x.nodes.clone()),
+            (None, _) => {
+                let t = copy_fragment(f, &content)?.tree;
+                let d = f.get_ref(t).unwrap().get_doc_node();
+                (
+                    t,
+                    children(f, d)
+                        .into_iter()
+                        .map(|n| Rc::new(Item::Node(n)))
+                        .collect(),
+                )
+            }
+        };
+        match attribute(f, c, "select") {
+            Some(s) => {
+                let d = f.get_ref(tree).unwrap().get_doc_node();
+                Ok(Context {
+                    tree,
+                    nodes: xpath(f, s.as_str(), d, tree)?,
+                })
+            }
+            None => Ok(Context { tree, nodes }),
+        }
+    }
+    // Apply templates to each context node
+    fn apply(&self, f: &mut Forest, x: &Context) -> Result<Sequence, Error> {
+        let rd = f.plant_tree();
+        let c = vec![Constructor::ApplyTemplates(vec![Constructor::ContextItem])];
+        let mut result = vec![];
+        for i in 0..x.nodes.len() {
+            result.append(&mut self.ev.evaluate(
+                Some(x.nodes.clone()),
+                Some(i),
+                &c,
+                f,
+                x.tree,
+                rd,
+            )?)
+        }
+        Ok(result)
+    }
+    // Check the result against an expectation. Returns a message if it is not satisfied.
+    // The trees that are made to check the expectation are discarded afterwards.
+    fn expect(&self, f: &mut Forest, e: Node, result: &Sequence) -> Result<Option<String>, Error> {
+        let trees = f.tree_count();
+        let r = self.check(f, e, result);
+        f.truncate(trees);
+        r
+    }
+    fn check(&self, f: &mut Forest, e: Node, result: &Sequence) -> Result<Option<String>, Error> {
+        let actual = copy_fragment_items(f, result)?;
+        if let Some(test) = attribute(f, e, "test") {
+            let doc = f.get_ref(actual.tree).unwrap().get_doc_node();
+            let r = xpath(f, test.as_str(), doc, actual.tree)?;
+            return Ok((!r.to_bool()).then(|| format!("\"{}\" is false", test)));
+        }
+        let expected = match attribute(f, e, "select") {
+            Some(s) => {
+                let t = f.plant_tree();
+                let d = f.get_ref(t).unwrap().get_doc_node();
+                let v = xpath(f, s.as_str(), d, t)?;
+                copy_fragment_items(f, &v)?
+            }
+            None => copy_fragment(f, &children(f, e))?,
+        };
+        let (a, x) = (serialise(f, &actual), serialise(f, &expected));
+        Ok((a != x).then(|| format!("expected \"{}\", got \"{}\"", x, a)))
+    }
+}
+
+fn is_xspec(f: &Forest, n: Node, local: &str) -> bool {
+    n.node_type(f) == NodeType::Element
+        && n.to_name(f).get_nsuri_ref() == Some(XSPEC_NAMESPACE)
+        && n.to_name(f).get_localname() == local
+}
+
+fn xspec_children(f: &Forest, n: Node) -> Vec<Node> {
+    children(f, n)
+        .into_iter()
+        .filter(|c| {
+            c.node_type(f) == NodeType::Element
+                && c.to_name(f).get_nsuri_ref() == Some(XSPEC_NAMESPACE)
+        })
+        .collect()
+}
+
+fn children(f: &Forest, n: Node) -> Vec<Node> {
+    let mut v = vec![];
+    let mut cit = n.child_iter();
+    while let Some(c) = cit.next(f) {
+        v.push(c)
+    }
+    v
+}
+
+fn attribute(f: &Forest, n: Node, name: &str) -> Option<String> {
+    n.get_attribute(f, &QualifiedName::new(None, None, String::from(name)))
+        .map(|a| a.to_string(f))
+}
+
+fn label(f: &Forest, n: Node) -> String {
+    attribute(f, n, "label").unwrap_or_default()
+}
+
+fn is_pending(f: &Forest, n: Node) -> bool {
+    attribute(f, n, "pending").is_some()
+}
+
+// Copied nodes. Attributes can't be children of the document node, so they are kept separately.
+struct Fragment {
+    tree: TreeIndex,
+    attributes: Vec<Node>,
+}
+
+// Copy nodes into a new fragment. Whitespace-only text is removed.
+fn copy_fragment(f: &mut Forest, nodes: &[Node]) -> Result<Fragment, Error> {
+    let t = f.plant_fragment();
+    let mut attributes = vec![];
+    for n in nodes {
+        match n.node_type(f) {
+            NodeType::Attribute => attributes.push(n.deep_copy(f, Some(t))?),
+            NodeType::Document => {
+                // The children of a document are copied instead
+                for c in children(f, *n) {
+                    let d = c.deep_copy(f, Some(t))?;
+                    f.get_ref_mut(t).unwrap().push_doc_node(d)?
+                }
+            }
+            _ => {
+                let d = n.deep_copy(f, Some(t))?;
+                f.get_ref_mut(t).unwrap().push_doc_node(d)?
+            }
+        }
+    }
+    f.strip_whitespace_nodes(t, |_, _| true)?;
+    Ok(Fragment {
+        tree: t,
+        attributes,
+    })
+}
+
+// Copy the items of a sequence into a new fragment. Atomic values become text, separated by spaces.
+fn copy_fragment_items(f: &mut Forest, s: &Sequence) -> Result<Fragment, Error> {
+    let mut nodes = vec![];
+    let mut text = String::new();
+    let t = f.plant_tree();
+    for (k, i) in s.iter().enumerate() {
+        match &**i {
+            Item::Node(n) => nodes.push(*n),
+            _ => {
+                if k > 0 && !matches!(&*s[k - 1], Item::Node(_)) {
+                    text.push(' ')
+                }
+                text.push_str(i.to_string(Some(f)).as_str());
+                if s.get(k + 1).is_none_or(|j| matches!(&**j, Item::Node(_))) {
+                    let n = f
+                        .get_ref_mut(t)
+                        .unwrap()
+                        .new_text(Value::from(text.as_str()))?;
+                    nodes.push(n);
+                    text.clear()
+                }
+            }
+        }
+    }
+    copy_fragment(f, &nodes)
+}
+
+// Attributes are serialised before the other nodes, as name="value"
+fn serialise(f: &Forest, x: &Fragment) -> String {
+    x.attributes
+        .iter()
+        .map(|a| format!("{}=\"{}\" ", a.to_name(f).to_string(), a.to_string(f)))
+        .chain(
+            children(f, f.get_ref(x.tree).unwrap().get_doc_node())
+                .iter()
+                .map(|n| n.to_xml(f)),
+        )
+        .collect()
+}
+
+// Evaluate an XPath expression with the given node as the context item
+fn xpath(f: &mut Forest, expr: &str, n: Node, t: TreeIndex) -> Result<Sequence, Error> {
+    let mut c = parse(expr)?;
+    StaticContext::new_with_builtins().static_analysis(&mut c);
+    let rd = f.plant_tree();
+    Evaluator::new().evaluate(Some(vec![Rc::new(Item::Node(n))]), Some(0), &c, f, t, rd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolvers::MemoryResolver;
+
+    fn resolver() -> MemoryResolver {
+        let mut r = MemoryResolver::new();
+        r.insert(
+            "http://example.org/style.xsl",
+            "<xsl:stylesheet xmlns:xsl='http://www.w3.org/1999/XSL/Transform'>
+  <xsl:template match='child::list'><ul><xsl:apply-templates/></ul></xsl:template>
+  <xsl:template match='child::item'><li><xsl:apply-templates/></li></xsl:template>
+</xsl:stylesheet>",
+        );
+        r.insert(
+            "http://example.org/input.xml",
+            "<list><item>a</item></list>",
+        );
+        r
+    }
+
+    #[test]
+    fn scenarios() {
+        let mut f = Forest::new();
+        let d = f
+            .grow_tree(
+                "<x:description xmlns:x='http://www.jenitennison.com/xslt/xspec' stylesheet='style.xsl'>
+  <x:scenario label='list'>
+    <x:context>
+      <list><item>one</item> <item>two</item></list>
+    </x:context>
+    <x:expect label='content'>
+      <ul>
+        <li>one</li>
+        <li>two</li>
+      </ul>
+    </x:expect>
+    <x:expect label='count' test='count(child::ul/child::li) = 2'/>
+    <x:expect label='wrong'><ul/></x:expect>
+    <x:expect label='later' pending='not yet'><ol/></x:expect>
+    <x:scenario label='item'>
+      <x:context select='child::list/child::item[2]'/>
+      <x:expect label='selected'><li>two</li></x:expect>
+    </x:scenario>
+    <x:scenario label='inherited'>
+      <x:expect label='same context' test='count(child::ul) = 1'/>
+    </x:scenario>
+  </x:scenario>
+  <x:scenario label='from a document'>
+    <x:context href='input.xml'/>
+    <x:expect label='document'><ul><li>a</li></ul></x:expect>
+    <x:scenario label='text'>
+      <x:context select='child::list/child::item/child::text()'/>
+      <x:expect label='string value' select='\"a\"'/>
+    </x:scenario>
+  </x:scenario>
+  <x:scenario label='function'>
+    <x:call function='f:double'/>
+    <x:expect label='unsupported' select='2'/>
+  </x:scenario>
+  <x:pending>
+    <x:scenario label='skipped'>
+      <x:context><list/></x:context>
+      <x:expect label='not run'><nothing/></x:expect>
+    </x:scenario>
+  </x:pending>
+</x:description>",
+            )
+            .expect("unable to parse XML");
+        let trees = f.tree_count();
+        let report = run(
+            &mut f,
+            d,
+            Url::parse("http://example.org/test.xspec").ok(),
+            &resolver(),
+        )
+        .expect("unable to run description");
+        // Only the stylesheet is left behind
+        assert_eq!(f.tree_count(), trees + 1);
+        let summary: Vec<(&str, Option<bool>)> = report
+            .outcomes
+            .iter()
+            .map(|o| (o.label.as_str(), o.passed))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("content", Some(true)),
+                ("count", Some(true)),
+                ("wrong", Some(false)),
+                ("later", None),
+                ("selected", Some(true)),
+                ("same context", Some(true)),
+                ("document", Some(true)),
+                ("string value", Some(true)),
+                ("unsupported", Some(false)),
+                ("not run", None),
+            ]
+        );
+        assert_eq!(report.outcomes[4].scenario, vec!["list", "item"]);
+        assert_eq!(
+            report.outcomes[2].message.as_deref(),
+            Some("expected \"<ul></ul>\", got \"<ul><li>one</li><li>two</li></ul>\"")
+        );
+        assert_eq!(
+            (report.passed(), report.failed(), report.pending()),
+            (6, 2, 2)
+        );
+        assert!(!report.is_success());
+        assert!(report
+            .to_string()
+            .ends_with("passed: 6 failed: 2 pending: 2"))
+    }
+
+    #[test]
+    fn attributes() {
+        let mut f = Forest::new();
+        let t = f
+            .grow_tree("<a b='1' c='2'>x</a>")
+            .expect("unable to parse XML");
+        let a = f
+            .get_ref(t)
+            .unwrap()
+            .get_doc_node()
+            .get_first_element(&f)
+            .unwrap();
+        let mut nodes = vec![];
+        let mut ait = a.attribute_iter(&f);
+        while let Some(b) = ait.next() {
+            nodes.push(b)
+        }
+        nodes.sort_by_key(|n| n.to_name(&f).get_localname());
+        nodes.extend(children(&f, a));
+        let x = copy_fragment(&mut f, &nodes).expect("unable to copy nodes");
+        assert_eq!(serialise(&f, &x), "b=\"1\" c=\"2\" x")
+    }
+
+    #[test]
+    fn not_a_description() {
+        let mut f = Forest::new();
+        let d = f.grow_tree("<description/>").expect("unable to parse XML");
+        assert!(run(&mut f, d, None, &resolver()).is_err())
+    }
+}
@@ -6,7 +6,31 @@ OASIS/NIST test cases
 
 use std::convert::TryFrom;
 use std::fs;
-use xrust::parsexml;
+use url::Url;
+use xrust::parsexml::{self, XMLDocument};
+use xrust::resolvers::MemoryResolver;
+use xrust::xdmerror::Error;
+
+// A non-validating processor only reports a well-formedness error in the external DTD subset if it reads the subset.
+// The subset is read when general entities are expanded with a resolver, so these tests expand the document,
+// serving the DTDs from memory using the same URLs as the test documents.
+// They used to pass without reading the subset only because a DOCTYPE with an external identifier was rejected.
+fn expand_external_subset(mut d: XMLDocument, name: &str) -> Result<XMLDocument, Error> {
+    const BASE: &str = "file:///xmlconf/oasis/";
+    let mut r = MemoryResolver::new();
+    for e in fs::read_dir("tests/conformance/xml/xmlconf/oasis").unwrap() {
+        let path = e.unwrap().path();
+        if path.extension().is_some_and(|x| x == "dtd") {
+            r.insert(
+                format!("{}{}", BASE, path.file_name().unwrap().to_str().unwrap()).as_str(),
+                fs::read(&path).unwrap(),
+            );
+        }
+    }
+    let base = Url::parse(format!("{}{}", BASE, name).as_str()).unwrap();
+    d.expand_with_resolver(Some(&base), &r)?;
+    Ok(d)
+}
 
 #[test]
 fn op01fail1() {
@@ -1186,7 +1210,8 @@ fn op09fail1() {
 
     let testxml = parsexml::XMLDocument::try_from(
         fs::read_to_string("tests/conformance/xml/xmlconf/oasis/p09fail1.xml").unwrap(),
-    );
+    )
+    .and_then(|d| expand_external_subset(d, "p09fail1.xml"));
 
     assert!(testxml.is_err());
 }
@@ -1202,7 +1227,8 @@ fn op09fail2() {
 
     let testxml = parsexml::XMLDocument::try_from(
         fs::read_to_string("tests/conformance/xml/xmlconf/oasis/p09fail2.xml").unwrap(),
-    );
+    )
+    .and_then(|d| expand_external_subset(d, "p09fail2.xml"));
 
     assert!(testxml.is_err());
 }
@@ -3265,7 +3291,8 @@ fn op61fail1() {
 
     let testxml = parsexml::XMLDocument::try_from(
         fs::read_to_string("tests/conformance/xml/xmlconf/oasis/p61fail1.xml").unwrap(),
-    );
+    )
+    .and_then(|d| expand_external_subset(d, "p61fail1.xml"));
 
     assert!(testxml.is_err());
 }
@@ -3281,7 +3308,8 @@ fn op62fail1() {
 
     let testxml = parsexml::XMLDocument::try_from(
         fs::read_to_string("tests/conformance/xml/xmlconf/oasis/p62fail1.xml").unwrap(),
-    );
+    )
+    .and_then(|d| expand_external_subset(d, "p62fail1.xml"));
 
     assert!(testxml.is_err());
 }
@@ -3297,7 +3325,8 @@ fn op62fail2() {
 
     let testxml = parsexml::XMLDocument::try_from(
         fs::read_to_string("tests/conformance/xml/xmlconf/oasis/p62fail2.xml").unwrap(),
-    );
+    )
+    .and_then(|d| expand_external_subset(d, "p62fail2.xml"));
 
     assert!(testxml.is_err());
 }
@@ -3313,7 +3342,8 @@ fn op63fail1() {
 
     let testxml = parsexml::XMLDocument::try_from(
         fs::read_to_string("tests/conformance/xml/xmlconf/oasis/p63fail1.xml").unwrap(),
-    );
+    )
+    .and_then(|d| expand_external_subset(d, "p63fail1.xml"));
 
     assert!(testxml.is_err());
 }
@@ -3329,7 +3359,8 @@ fn op63fail2() {
 
     let testxml = parsexml::XMLDocument::try_from(
         fs::read_to_string("tests/conformance/xml/xmlconf/oasis/p63fail2.xml").unwrap(),
-    );
+    )
+    .and_then(|d| expand_external_subset(d, "p63fail2.xml"));
 
     assert!(testxml.is_err());
 }
@@ -3431,7 +3462,6 @@ fn op66fail4() {
 }
 
 #[test]
-#[should_panic(expected = "assertion failed")]
 fn op66fail5() {
     /*
         Test ID:o-p66fail5
@@ -3448,7 +3478,6 @@ fn op66fail5() {
 }
 
 #[test]
-#[should_panic]
 fn op66fail6() {
     /*
         Test ID:o-p66fail6
@@ -6,12 +6,40 @@ James Clark XMLTEST cases - Standalone
     This contains case that are standalone and have references to external general entities .
 */
 
-use std::convert::TryFrom;
 use std::fs;
-use xrust::parsexml;
+use url::Url;
+use xrust::forest::{Forest, TreeIndex};
+use xrust::resolvers::MemoryResolver;
+
+// The external entities are served from memory, using the same URLs as the test documents
+const BASE: &str = "file:///xmlconf/xmltest/valid/ext-sa/";
+
+fn ext_sa_resolver() -> MemoryResolver {
+    let mut r = MemoryResolver::new();
+    for e in fs::read_dir("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa").unwrap() {
+        let path = e.unwrap().path();
+        if path.extension().map_or(false, |x| x == "ent") {
+            r.insert(
+                format!("{}{}", BASE, path.file_name().unwrap().to_str().unwrap()).as_str(),
+                fs::read(&path).unwrap(),
+            );
+        }
+    }
+    r
+}
+
+// The parser does not (yet) accept whitespace after the document element, so the files' trailing newlines are trimmed.
+// The canonical form has no document type declaration, so only the document elements are compared
+fn document_element(f: &Forest, t: TreeIndex) -> String {
+    f.get_ref(t)
+        .unwrap()
+        .get_doc_node()
+        .get_first_element(f)
+        .unwrap()
+        .to_xml(f)
+}
 
 #[test]
-#[ignore]
 fn validextsa001() {
     /*
         Test ID:valid-ext-sa-001
@@ -20,21 +48,29 @@ fn validextsa001() {
         Description:A combination of carriage return line feed in an external entity must be normalized to a single newline.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/001.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/001.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}001.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/001.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa002() {
     /*
         Test ID:valid-ext-sa-002
@@ -43,21 +79,29 @@ fn validextsa002() {
         Description:A carriage return (also CRLF) in an external entity must be normalized to a single newline.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/002.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/002.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}002.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/002.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa003() {
     /*
         Test ID:valid-ext-sa-003
@@ -66,21 +110,29 @@ fn validextsa003() {
         Description:Test demonstrates that the content of an element can be empty. In this case the external entity is an empty file.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/003.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/003.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}003.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/003.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa004() {
     /*
         Test ID:valid-ext-sa-004
@@ -89,21 +141,29 @@ fn validextsa004() {
         Description:A carriage return (also CRLF) in an external entity must be normalized to a single newline.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/004.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/004.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}004.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/004.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa005() {
     /*
         Test ID:valid-ext-sa-005
@@ -112,21 +172,29 @@ fn validextsa005() {
         Description:Test demonstrates the use of optional character and content particles within an element content. The test also show the use of external entity.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/005.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/005.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}005.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/005.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa006() {
     /*
         Test ID:valid-ext-sa-006
@@ -135,21 +203,29 @@ fn validextsa006() {
         Description:Test demonstrates the use of optional character and content particles within mixed element content. The test also shows the use of an external entity and that a carriage control line feed in an external entity must be normalized to a single newline.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/006.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/006.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}006.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/006.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa007() {
     /*
         Test ID:valid-ext-sa-007
@@ -158,21 +234,29 @@ fn validextsa007() {
         Description:Test demonstrates the use of external entity and how replacement text is retrieved and processed.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/007.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/007.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}007.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/007.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa008() {
     /*
         Test ID:valid-ext-sa-008
@@ -181,21 +265,29 @@ fn validextsa008() {
         Description:Test demonstrates the use of external entity and how replacement text is retrieved and processed. Also tests the use of an EncodingDecl of UTF-16.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/008.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/008.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}008.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/008.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa009() {
     /*
         Test ID:valid-ext-sa-009
@@ -204,21 +296,29 @@ fn validextsa009() {
         Description:A carriage return (also CRLF) in an external entity must be normalized to a single newline.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/009.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/009.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}009.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/009.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa011() {
     /*
         Test ID:valid-ext-sa-011
@@ -227,21 +327,29 @@ fn validextsa011() {
         Description:Test demonstrates the use of a public identifier with and external entity. The test also show that a carriage control line feed combination in an external entity must be normalized to a single newline.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/011.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/011.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}011.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/011.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa012() {
     /*
         Test ID:valid-ext-sa-012
@@ -250,21 +358,29 @@ fn validextsa012() {
         Description:Test demonstrates both internal and external entities and that processing of entity references may be required to produce the correct replacement text.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/012.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/012.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}012.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/012.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa013() {
     /*
         Test ID:valid-ext-sa-013
@@ -273,21 +389,29 @@ fn validextsa013() {
         Description:Test demonstrates that whitespace is handled by adding a single whitespace to the normalized value in the attribute list.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/013.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/013.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}013.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/013.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }
 
 #[test]
-#[ignore]
 fn validextsa014() {
     /*
         Test ID:valid-ext-sa-014
@@ -296,15 +420,24 @@ fn validextsa014() {
         Description:Test demonstrates use of characters outside of normal ASCII range.
     */
 
-    let testxml = parsexml::XMLDocument::try_from(
-        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/014.xml").unwrap(),
+    let mut f = Forest::new();
+    let testxml = f.grow_tree_with_resolver(
+        fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/014.xml")
+            .unwrap()
+            .trim_end(),
+        Some(&Url::parse(format!("{}014.xml", BASE).as_str()).unwrap()),
+        &ext_sa_resolver(),
     );
-    let canonicalxml = parsexml::XMLDocument::try_from(
+    let canonicalxml = f.grow_tree(
         fs::read_to_string("tests/conformance/xml/xmlconf/xmltest/valid/ext-sa/out/014.xml")
-            .unwrap(),
+            .unwrap()
+            .trim_end(),
     );
 
     assert!(testxml.is_ok());
     assert!(canonicalxml.is_ok());
-    assert!(testxml.unwrap() == canonicalxml.unwrap());
+    assert_eq!(
+        document_element(&f, testxml.unwrap()),
+        document_element(&f, canonicalxml.unwrap())
+    );
 }